@@ -0,0 +1,61 @@
+/* Functions exported by sxd-xpath when it is built with the `capi`
+ * feature. See the documentation of the `capi` module for how objects
+ * are owned. */
+
+#ifndef SXD_XPATH_H
+#define SXD_XPATH_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+    SXD_OK = 0,
+    SXD_NULL_ARGUMENT = 1,
+    SXD_INVALID_UTF8 = 2,
+    SXD_INVALID_XML = 3,
+    SXD_INVALID_XPATH = 4,
+    SXD_EVALUATION_FAILED = 5,
+    SXD_OUT_OF_RANGE = 6,
+} sxd_status;
+
+typedef enum {
+    SXD_BOOLEAN = 0,
+    SXD_NUMBER = 1,
+    SXD_STRING = 2,
+    SXD_NODESET = 3,
+    SXD_SEQUENCE = 4,
+} sxd_value_type;
+
+typedef struct sxd_document sxd_document;
+typedef struct sxd_xpath sxd_xpath;
+typedef struct sxd_result sxd_result;
+
+const char *sxd_last_error(void);
+
+sxd_status sxd_document_parse(const char *xml, sxd_document **document);
+void sxd_document_free(sxd_document *document);
+
+sxd_status sxd_xpath_compile(const char *xpath, sxd_xpath **compiled);
+sxd_status sxd_xpath_set_namespace(sxd_xpath *xpath, const char *prefix, const char *uri);
+sxd_status sxd_xpath_evaluate(const sxd_xpath *xpath, const sxd_document *document,
+                              sxd_result **result);
+void sxd_xpath_free(sxd_xpath *xpath);
+
+sxd_value_type sxd_result_type(const sxd_result *result);
+bool sxd_result_boolean(const sxd_result *result);
+double sxd_result_number(const sxd_result *result);
+const char *sxd_result_string(const sxd_result *result);
+size_t sxd_result_count(const sxd_result *result);
+sxd_status sxd_result_item_string(const sxd_result *result, size_t index, const char **value);
+sxd_status sxd_result_item_name(const sxd_result *result, size_t index, const char **name);
+void sxd_result_free(sxd_result *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
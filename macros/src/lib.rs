@@ -0,0 +1,163 @@
+//! XPaths that are checked when the program is compiled.
+//!
+//! [`xpath!`][] compiles an XPath written as a string literal, and
+//! fails the build when the XPath has a syntax error, instead of
+//! failing when the program runs.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::{Context, Value};
+//! use sxd_xpath_macros::xpath;
+//!
+//! let package = parser::parse("<root><item/><item/></root>").expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let count = xpath!("count(/root/item)");
+//! let value = count.evaluate(&Context::new(), document.root()).expect("XPath evaluation failed");
+//! assert_eq!(Value::Number(2.0), value);
+//! ```
+//!
+//! The error is reported at the literal:
+//!
+//! ```compile_fail
+//! use sxd_xpath_macros::xpath;
+//!
+//! let items = xpath!("/root/item[@price > ]");
+//! ```
+//!
+//! Only the syntax is checked. Functions, variables and namespace
+//! prefixes are still looked up in the context when the XPath is
+//! evaluated.
+//!
+//! [`xpath!`]: macro.xpath.html
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use sxd_xpath::Factory;
+
+/// Compiles the XPath string literal into an `sxd_xpath::XPath`,
+/// failing the build if it cannot be parsed.
+#[proc_macro]
+pub fn xpath(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+    let literal = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => literal,
+        (Some(token), _) => return compile_error("expected a string literal", token.span()),
+        (None, _) => return compile_error("expected a string literal", Span::call_site()),
+    };
+
+    let written = literal.to_string();
+    let xpath = match unquote(&written) {
+        Some(xpath) => xpath,
+        None => return compile_error("expected a string literal", literal.span()),
+    };
+
+    if let Err(error) = Factory::new().build(&xpath) {
+        let message = format!("invalid XPath: {}", error.render(&xpath));
+        return compile_error(&message, literal.span());
+    }
+
+    format!(
+        "::sxd_xpath::Factory::new().build({}).expect(\"The XPath was checked when compiled\")",
+        written
+    )
+    .parse()
+    .expect("The expansion is valid Rust")
+}
+
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::from(message).into());
+    arguments.set_span(span);
+
+    vec![
+        TokenTree::from(Ident::new("compile_error", span)),
+        TokenTree::from({
+            let mut bang = Punct::new('!', Spacing::Alone);
+            bang.set_span(span);
+            bang
+        }),
+        TokenTree::from(arguments),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// The value of a string literal as it is written in Rust, or nothing
+/// when it is not a string literal.
+fn unquote(written: &str) -> Option<String> {
+    if let Some(raw) = written.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let quoted = &raw[hashes..raw.len().checked_sub(hashes)?];
+        return quoted
+            .strip_prefix('"')?
+            .strip_suffix('"')
+            .map(String::from);
+    }
+
+    let quoted = written.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            '\\' => value.push('\\'),
+            '\'' => value.push('\''),
+            '"' => value.push('"'),
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                value.push(char::from(u8::from_str_radix(&digits, 16).ok()?));
+            }
+            'u' => {
+                chars.next().filter(|&c| c == '{')?;
+                let digits: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let code = u32::from_str_radix(&digits.replace('_', ""), 16).ok()?;
+                value.push(std::char::from_u32(code)?);
+            }
+            // A backslash at the end of a line skips the line break and
+            // the whitespace that starts the next line
+            '\n' | '\r' => {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_strings_are_unescaped() {
+        assert_eq!(Some("a[@b = \"c\"]".into()), unquote(r#""a[@b = \"c\"]""#));
+        assert_eq!(Some("'\t\u{e9}x".into()), unquote(r#""\'\t\u{e9}\x78""#));
+        assert_eq!(Some("a | b".into()), unquote("\"a | \\\n    b\""));
+    }
+
+    #[test]
+    fn raw_strings_are_kept_as_written() {
+        assert_eq!(Some(r"a\b".into()), unquote(r#"r"a\b""#));
+        assert_eq!(Some(r#"a["b"]"#.into()), unquote(r###"r#"a["b"]"#"###));
+    }
+
+    #[test]
+    fn other_literals_are_not_strings() {
+        assert_eq!(None, unquote("42"));
+        assert_eq!(None, unquote("b\"a\""));
+        assert_eq!(None, unquote("'a'"));
+    }
+}
@@ -0,0 +1,25 @@
+use sxd_document::parser;
+use sxd_xpath::{Context, Value};
+use sxd_xpath_macros::xpath;
+
+#[test]
+fn checked_xpaths_evaluate() {
+    let package = parser::parse("<a><b c='1'/><b c='2'/></a>").expect("Unable to parse XML");
+    let doc = package.as_document();
+    let context = Context::new();
+
+    let sum = xpath!("sum(/a/b/@c)");
+    assert_eq!(
+        Value::Number(3.0),
+        sum.evaluate(&context, doc.root())
+            .expect("Unable to evaluate")
+    );
+
+    let second = xpath!(r#"string(/a/b[@c = "2"]/@c)"#);
+    assert_eq!(
+        Value::String("2".into()),
+        second
+            .evaluate(&context, doc.root())
+            .expect("Unable to evaluate")
+    );
+}
@@ -0,0 +1,1271 @@
+//! The syntax tree of a parsed XPath.
+//!
+//! The [`Parser`][] produces an [`Expr`][], which is then compiled
+//! into the [`Expression`][]s that are evaluated. Keeping the tree
+//! around allows a compiled [`XPath`][] to be inspected.
+//!
+//! [`Parser`]: ../parser/struct.Parser.html
+//! [`Expr`]: enum.Expr.html
+//! [`Expression`]: ../expression/trait.Expression.html
+//! [`XPath`]: ../struct.XPath.html
+
+use std::convert::Infallible;
+use std::fmt;
+
+use sxd_document::QName;
+
+use crate::axis::{Axis, AxisLike, PrincipalNodeType};
+use crate::context::Context;
+use crate::expression::{self, SubExpression};
+use crate::format::Formatter;
+use crate::node_test::{self, NameTest, SubNodeTest};
+use crate::{OwnedPrefixedName, Value};
+
+/// An operator that combines two expressions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Addition,
+    Subtraction,
+    Multiplication,
+    Division,
+    Remainder,
+    Union,
+    Range,
+    Is,
+    Precedes,
+    Follows,
+    ValueEqual,
+    ValueNotEqual,
+    ValueLessThan,
+    ValueLessThanOrEqual,
+    ValueGreaterThan,
+    ValueGreaterThanOrEqual,
+}
+
+/// Whether a quantified expression requires some or every item of
+/// its sequence to satisfy the test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quantifier {
+    Some,
+    Every,
+}
+
+/// How many items a sequence type allows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Occurrence {
+    ExactlyOne,
+    /// `?`
+    ZeroOrOne,
+    /// `*`
+    ZeroOrMore,
+    /// `+`
+    OneOrMore,
+}
+
+/// The type of a sequence, such as `xs:string?` or `element(a)*`, as
+/// used by `instance of` and `treat as`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SequenceType {
+    /// The type of every item, or `None` for `empty-sequence()`
+    pub item_type: Option<ItemType>,
+    pub occurrence: Occurrence,
+}
+
+/// The atomic type targeted by `cast as` and `castable as`, such as
+/// `xs:integer?`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SingleType {
+    pub name: OwnedPrefixedName,
+    /// Whether the empty sequence is allowed, written as `?`
+    pub optional: bool,
+}
+
+/// The type of a single item of a sequence.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ItemType {
+    /// `item()`, which matches anything
+    Item,
+    /// A test of the kind of node, such as `node()` or `element(a)`
+    Kind(KindTest),
+    /// An atomic type, such as `xs:string`
+    Atomic(OwnedPrefixedName),
+}
+
+/// The kinds of node that an [`ItemType`][] can test for.
+///
+/// [`ItemType`]: enum.ItemType.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KindTest {
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction(Option<String>),
+    Element(Option<NameTest>),
+    Attribute(Option<NameTest>),
+    Document,
+}
+
+/// The key of a lookup, which selects entries of a map or members of
+/// an array.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LookupKey {
+    /// `?name`, the same as the string key `?("name")`
+    Name(String),
+    /// `?1` or `?(expression)`, where every item is a key
+    Expression(Box<Expr>),
+    /// `?*`, which selects every entry or member
+    Wildcard,
+}
+
+/// The kind of [`Value`][] that an expression gives, as far as it can
+/// be known without evaluating it.
+///
+/// [`Value`]: ../enum.Value.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    Boolean,
+    Number,
+    String,
+    Nodeset,
+    Sequence,
+    /// Depends on the context, such as the value of a variable
+    Unknown,
+}
+
+/// A single expression of an XPath.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expr {
+    /// The context node, the implicit start of a relative path
+    ContextNode,
+    /// The root node of the context node's tree
+    RootNode,
+    /// A string or numeric literal
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::literal"))]
+    Literal(Value<'static>),
+    /// A reference to a variable, such as `$name`
+    Variable(OwnedPrefixedName),
+    /// A call to a function
+    Function {
+        name: OwnedPrefixedName,
+        arguments: Vec<Expr>,
+    },
+    /// A series of location steps applied to a starting point
+    Path {
+        start_point: Box<Expr>,
+        steps: Vec<Step>,
+    },
+    /// A predicate applied to the result of an expression
+    Filter {
+        expression: Box<Expr>,
+        predicate: Box<Expr>,
+    },
+    /// Unary minus
+    Negation(Box<Expr>),
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `if (condition) then consequent else alternative`
+    If {
+        condition: Box<Expr>,
+        consequent: Box<Expr>,
+        alternative: Box<Expr>,
+    },
+    /// `for $variable in sequence return body`, evaluating the body
+    /// once for each item of the sequence
+    For {
+        variable: OwnedPrefixedName,
+        sequence: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `let $variable := value return body`
+    Let {
+        variable: OwnedPrefixedName,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `some $variable in sequence satisfies test` or the equivalent
+    /// with `every`
+    Quantified {
+        quantifier: Quantifier,
+        variable: OwnedPrefixedName,
+        sequence: Box<Expr>,
+        test: Box<Expr>,
+    },
+    /// `expression instance of sequence-type`
+    InstanceOf {
+        expression: Box<Expr>,
+        sequence_type: SequenceType,
+    },
+    /// `expression treat as sequence-type`
+    TreatAs {
+        expression: Box<Expr>,
+        sequence_type: SequenceType,
+    },
+    /// `expression cast as single-type`
+    Cast {
+        expression: Box<Expr>,
+        single_type: SingleType,
+    },
+    /// `expression castable as single-type`
+    Castable {
+        expression: Box<Expr>,
+        single_type: SingleType,
+    },
+    /// A comma-separated list of expressions, such as `(1, 'a', b)`
+    /// or the empty sequence `()`
+    Sequence(Vec<Expr>),
+    /// `map { key: value, ... }`
+    Map(Vec<(Expr, Expr)>),
+    /// `[a, b, c]`, where each expression is one member of the array
+    SquareArray(Vec<Expr>),
+    /// `array { expression }`, where each item of the sequence is one
+    /// member of the array
+    CurlyArray(Box<Expr>),
+    /// `expression?key`, which looks up entries of maps or members of
+    /// arrays
+    Lookup {
+        expression: Box<Expr>,
+        key: LookupKey,
+    },
+    /// `function($parameter, ...) { body }`
+    InlineFunction {
+        parameters: Vec<OwnedPrefixedName>,
+        body: Box<Expr>,
+    },
+    /// A call of a function item, such as `$f(1, 2)`
+    DynamicCall {
+        function: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+}
+
+/// A [location step][step] of a path.
+///
+/// [step]: https://www.w3.org/TR/xpath/#section-Location-Steps
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Step {
+    pub axis: Axis,
+    pub node_test: NodeTest,
+    pub predicates: Vec<Expr>,
+}
+
+/// The test that nodes selected by a step must pass.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeTest {
+    /// Tests the name of nodes of the axis' principal node type
+    Name(NameTest),
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction(Option<String>),
+}
+
+impl Expr {
+    pub fn binary(operator: BinaryOperator, left: Expr, right: Expr) -> Expr {
+        Expr::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Builds the evaluable form of this expression.
+    pub fn compile(&self) -> SubExpression {
+        self.compile_traced(false)
+    }
+
+    /// Builds the evaluable form of this expression. When traced, each
+    /// expression tells the [`Debugger`](../debug/trait.Debugger.html)
+    /// of the evaluation about being evaluated.
+    pub(crate) fn compile_traced(&self, traced: bool) -> SubExpression {
+        let compile = |expr: &Expr| expr.compile_traced(traced);
+
+        let expression: SubExpression = match *self {
+            Expr::ContextNode => Box::new(expression::ContextNode),
+            Expr::RootNode => Box::new(expression::RootNode),
+            Expr::Literal(ref value) => Box::new(expression::Literal::from(value.clone())),
+            Expr::Variable(ref name) => Box::new(expression::Variable { name: name.clone() }),
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => Box::new(expression::Function {
+                name: name.clone(),
+                arguments: arguments.iter().map(compile).collect(),
+            }),
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => compile_path(start_point, steps, traced),
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => expression::Filter::new(compile(expression), compile(predicate)),
+            Expr::Negation(ref expr) => Box::new(expression::Negation {
+                expression: compile(expr),
+            }),
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => {
+                let builder = match operator {
+                    BinaryOperator::Or => expression::Or::new,
+                    BinaryOperator::And => expression::And::new,
+                    BinaryOperator::Equal => expression::Equal::new,
+                    BinaryOperator::NotEqual => expression::NotEqual::new,
+                    BinaryOperator::LessThan => expression::Relational::less_than,
+                    BinaryOperator::LessThanOrEqual => expression::Relational::less_than_or_equal,
+                    BinaryOperator::GreaterThan => expression::Relational::greater_than,
+                    BinaryOperator::GreaterThanOrEqual => {
+                        expression::Relational::greater_than_or_equal
+                    }
+                    BinaryOperator::Addition => expression::Math::addition,
+                    BinaryOperator::Subtraction => expression::Math::subtraction,
+                    BinaryOperator::Multiplication => expression::Math::multiplication,
+                    BinaryOperator::Division => expression::Math::division,
+                    BinaryOperator::Remainder => expression::Math::remainder,
+                    BinaryOperator::Union => expression::Union::new,
+                    BinaryOperator::Range => expression::Range::new,
+                    BinaryOperator::Is => expression::NodeComparison::is,
+                    BinaryOperator::Precedes => expression::NodeComparison::precedes,
+                    BinaryOperator::Follows => expression::NodeComparison::follows,
+                    BinaryOperator::ValueEqual => expression::ValueComparison::equal,
+                    BinaryOperator::ValueNotEqual => expression::ValueComparison::not_equal,
+                    BinaryOperator::ValueLessThan => expression::ValueComparison::less_than,
+                    BinaryOperator::ValueLessThanOrEqual => {
+                        expression::ValueComparison::less_than_or_equal
+                    }
+                    BinaryOperator::ValueGreaterThan => expression::ValueComparison::greater_than,
+                    BinaryOperator::ValueGreaterThanOrEqual => {
+                        expression::ValueComparison::greater_than_or_equal
+                    }
+                };
+                builder(compile(left), compile(right))
+            }
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => Box::new(expression::Conditional {
+                condition: compile(condition),
+                consequent: compile(consequent),
+                alternative: compile(alternative),
+            }),
+            Expr::For {
+                ref variable,
+                ref sequence,
+                ref body,
+            } => Box::new(expression::For {
+                variable: variable.clone(),
+                sequence: compile(sequence),
+                body: compile(body),
+            }),
+            Expr::Let {
+                ref variable,
+                ref value,
+                ref body,
+            } => Box::new(expression::Let {
+                variable: variable.clone(),
+                value: compile(value),
+                body: compile(body),
+            }),
+            Expr::Quantified {
+                quantifier,
+                ref variable,
+                ref sequence,
+                ref test,
+            } => Box::new(expression::Quantified {
+                every: quantifier == Quantifier::Every,
+                variable: variable.clone(),
+                sequence: compile(sequence),
+                test: compile(test),
+            }),
+            Expr::InstanceOf {
+                ref expression,
+                ref sequence_type,
+            } => Box::new(expression::InstanceOf {
+                expression: compile(expression),
+                sequence_type: sequence_type.compile(),
+            }),
+            Expr::TreatAs {
+                ref expression,
+                ref sequence_type,
+            } => Box::new(expression::TreatAs {
+                expression: compile(expression),
+                sequence_type: sequence_type.compile(),
+            }),
+            Expr::Cast {
+                ref expression,
+                ref single_type,
+            } => Box::new(expression::Cast {
+                expression: compile(expression),
+                single_type: single_type.clone(),
+            }),
+            Expr::Castable {
+                ref expression,
+                ref single_type,
+            } => Box::new(expression::Castable {
+                expression: compile(expression),
+                single_type: single_type.clone(),
+            }),
+            Expr::Sequence(ref expressions) => Box::new(expression::Sequence {
+                expressions: expressions.iter().map(compile).collect(),
+            }),
+            Expr::Map(ref entries) => Box::new(expression::MapConstructor {
+                entries: entries
+                    .iter()
+                    .map(|(key, value)| (compile(key), compile(value)))
+                    .collect(),
+            }),
+            Expr::SquareArray(ref members) => Box::new(expression::SquareArray {
+                members: members.iter().map(compile).collect(),
+            }),
+            Expr::CurlyArray(ref expr) => Box::new(expression::CurlyArray {
+                expression: compile(expr),
+            }),
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => Box::new(expression::Lookup {
+                expression: compile(expression),
+                key: key.compile(traced),
+            }),
+            Expr::InlineFunction {
+                ref parameters,
+                ref body,
+            } => Box::new(expression::InlineFunction {
+                parameters: parameters.clone(),
+                body: compile(body).into(),
+            }),
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => Box::new(expression::DynamicCall {
+                function: compile(function),
+                arguments: arguments.iter().map(compile).collect(),
+            }),
+        };
+
+        if traced {
+            Box::new(expression::Traced {
+                expr: self.clone(),
+                expression,
+            })
+        } else {
+            expression
+        }
+    }
+}
+
+/// A name that an expression refers to.
+#[derive(Copy, Clone)]
+enum Name<'a> {
+    Variable(&'a OwnedPrefixedName),
+    Function(&'a OwnedPrefixedName),
+    Type(&'a OwnedPrefixedName),
+    Test(&'a NameTest),
+}
+
+impl<'a> Name<'a> {
+    fn prefix(self) -> Option<&'a str> {
+        match self {
+            Name::Variable(name) | Name::Function(name) | Name::Type(name) => {
+                name.prefix.as_deref()
+            }
+            Name::Test(name) => name.prefix.as_deref().filter(|&prefix| prefix != "*"),
+        }
+    }
+}
+
+impl Expr {
+    /// Checks that the functions, variables, atomic types and namespace
+    /// prefixes that this expression refers to are known to the
+    /// context, apart from the variables that it binds itself.
+    pub fn check_names(&self, context: &Context<'_>) -> Result<(), expression::Error> {
+        self.visit_names(&mut Vec::new(), &mut |name| match name {
+            Name::Variable(name) => {
+                if context.has_variable(resolve(context, name)?) {
+                    Ok(())
+                } else {
+                    Err(expression::Error::UnknownVariable { name: name.clone() })
+                }
+            }
+            Name::Function(name) => {
+                let qname = resolve(context, name)?;
+                if context.has_function(qname) || context.has_atomic_type(qname) {
+                    Ok(())
+                } else {
+                    Err(expression::Error::UnknownFunction { name: name.clone() })
+                }
+            }
+            Name::Type(name) => {
+                if context.has_atomic_type(resolve(context, name)?) {
+                    Ok(())
+                } else {
+                    Err(expression::Error::UnknownType { name: name.clone() })
+                }
+            }
+            // Name tests only use the prefixes registered in the context
+            Name::Test(_) => match name.prefix() {
+                Some(prefix) if context.namespace_for(prefix).is_none() => {
+                    Err(expression::Error::UnknownNamespace {
+                        prefix: prefix.to_owned(),
+                    })
+                }
+                _ => Ok(()),
+            },
+        })
+    }
+
+    /// The variables that this expression refers to, apart from the
+    /// variables that it binds itself, in the order they first appear.
+    pub fn variables(&self) -> Vec<&OwnedPrefixedName> {
+        self.unique_names(|name| match name {
+            Name::Variable(name) => Some(name),
+            _ => None,
+        })
+    }
+
+    /// The names of the functions that this expression calls, in the
+    /// order they first appear.
+    pub fn functions(&self) -> Vec<&OwnedPrefixedName> {
+        self.unique_names(|name| match name {
+            Name::Function(name) => Some(name),
+            _ => None,
+        })
+    }
+
+    /// The namespace prefixes of the names that this expression refers
+    /// to, in the order they first appear.
+    pub fn prefixes(&self) -> Vec<&str> {
+        self.unique_names(Name::prefix)
+    }
+
+    fn unique_names<'a, T, F>(&'a self, select: F) -> Vec<T>
+    where
+        T: PartialEq,
+        F: Fn(Name<'a>) -> Option<T>,
+    {
+        let mut selected = Vec::new();
+        let visited = self.visit_names(&mut Vec::new(), &mut |name| {
+            if let Some(name) = select(name) {
+                if !selected.contains(&name) {
+                    selected.push(name);
+                }
+            }
+            Ok::<_, Infallible>(())
+        });
+        match visited {
+            Ok(()) => selected,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Visits the names that this expression refers to, apart from the
+    /// variables that it binds itself.
+    fn visit_names<'a, E>(
+        &'a self,
+        bound: &mut Vec<&'a OwnedPrefixedName>,
+        visit: &mut dyn FnMut(Name<'a>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match *self {
+            Expr::ContextNode | Expr::RootNode | Expr::Literal(_) => Ok(()),
+            Expr::Variable(ref name) => {
+                if bound.contains(&name) {
+                    Ok(())
+                } else {
+                    visit(Name::Variable(name))
+                }
+            }
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => {
+                visit(Name::Function(name))?;
+                visit_all(arguments, bound, visit)
+            }
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => {
+                start_point.visit_names(bound, visit)?;
+                for step in steps {
+                    if let NodeTest::Name(ref name) = step.node_test {
+                        visit(Name::Test(name))?;
+                    }
+                    visit_all(&step.predicates, bound, visit)?;
+                }
+                Ok(())
+            }
+            Expr::Filter {
+                ref expression,
+                predicate: ref other,
+            }
+            | Expr::Binary {
+                left: ref expression,
+                right: ref other,
+                ..
+            } => {
+                expression.visit_names(bound, visit)?;
+                other.visit_names(bound, visit)
+            }
+            Expr::Negation(ref expression) | Expr::CurlyArray(ref expression) => {
+                expression.visit_names(bound, visit)
+            }
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => {
+                condition.visit_names(bound, visit)?;
+                consequent.visit_names(bound, visit)?;
+                alternative.visit_names(bound, visit)
+            }
+            Expr::For {
+                ref variable,
+                sequence: ref value,
+                ref body,
+            }
+            | Expr::Let {
+                ref variable,
+                ref value,
+                ref body,
+            }
+            | Expr::Quantified {
+                ref variable,
+                sequence: ref value,
+                test: ref body,
+                ..
+            } => {
+                value.visit_names(bound, visit)?;
+                visit_bound(&[variable], body, bound, visit)
+            }
+            Expr::InstanceOf {
+                ref expression,
+                ref sequence_type,
+            }
+            | Expr::TreatAs {
+                ref expression,
+                ref sequence_type,
+            } => {
+                expression.visit_names(bound, visit)?;
+                match sequence_type.item_type {
+                    Some(ItemType::Atomic(ref name)) => visit(Name::Type(name)),
+                    Some(ItemType::Kind(KindTest::Element(Some(ref name))))
+                    | Some(ItemType::Kind(KindTest::Attribute(Some(ref name)))) => {
+                        visit(Name::Test(name))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Expr::Cast {
+                ref expression,
+                ref single_type,
+            }
+            | Expr::Castable {
+                ref expression,
+                ref single_type,
+            } => {
+                expression.visit_names(bound, visit)?;
+                visit(Name::Type(&single_type.name))
+            }
+            Expr::Sequence(ref expressions) | Expr::SquareArray(ref expressions) => {
+                visit_all(expressions, bound, visit)
+            }
+            Expr::Map(ref entries) => {
+                for (key, value) in entries {
+                    key.visit_names(bound, visit)?;
+                    value.visit_names(bound, visit)?;
+                }
+                Ok(())
+            }
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => {
+                expression.visit_names(bound, visit)?;
+                match *key {
+                    LookupKey::Expression(ref key) => key.visit_names(bound, visit),
+                    LookupKey::Name(_) | LookupKey::Wildcard => Ok(()),
+                }
+            }
+            Expr::InlineFunction {
+                ref parameters,
+                ref body,
+            } => {
+                let parameters: Vec<_> = parameters.iter().collect();
+                visit_bound(&parameters, body, bound, visit)
+            }
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => {
+                function.visit_names(bound, visit)?;
+                visit_all(arguments, bound, visit)
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// The kind of value that evaluating this expression gives. Calls
+    /// of the core functions are assumed to call the functions that
+    /// [`Context::new`][] registers.
+    ///
+    /// [`Context::new`]: ../context/struct.Context.html#method.new
+    pub fn return_type(&self) -> ValueType {
+        // Arithmetic on dates and durations gives them instead of numbers
+        let numeric = |operands: &[&Expr]| {
+            let simple = operands.iter().all(|operand| match operand.return_type() {
+                ValueType::Boolean | ValueType::Number | ValueType::String | ValueType::Nodeset => {
+                    true
+                }
+                ValueType::Sequence | ValueType::Unknown => false,
+            });
+            if simple {
+                ValueType::Number
+            } else {
+                ValueType::Unknown
+            }
+        };
+
+        match *self {
+            Expr::ContextNode | Expr::RootNode | Expr::Path { .. } => ValueType::Nodeset,
+            Expr::Literal(Value::Boolean(_)) => ValueType::Boolean,
+            Expr::Literal(Value::Number(_)) => ValueType::Number,
+            Expr::Literal(Value::String(_)) => ValueType::String,
+            Expr::Literal(Value::Nodeset(_)) => ValueType::Nodeset,
+            Expr::Literal(Value::Sequence(_)) => ValueType::Sequence,
+            Expr::Variable(_) => ValueType::Unknown,
+            Expr::Function { ref name, .. } if name.prefix.is_none() => {
+                core_function_type(&name.local_part)
+            }
+            Expr::Function { .. } => ValueType::Unknown,
+            Expr::Filter { ref expression, .. } | Expr::TreatAs { ref expression, .. } => {
+                match expression.return_type() {
+                    t @ ValueType::Nodeset | t @ ValueType::Sequence => t,
+                    _ => ValueType::Unknown,
+                }
+            }
+            Expr::Negation(ref expression) => numeric(&[expression]),
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => match operator {
+                BinaryOperator::Or
+                | BinaryOperator::And
+                | BinaryOperator::Equal
+                | BinaryOperator::NotEqual
+                | BinaryOperator::LessThan
+                | BinaryOperator::LessThanOrEqual
+                | BinaryOperator::GreaterThan
+                | BinaryOperator::GreaterThanOrEqual => ValueType::Boolean,
+                BinaryOperator::Addition
+                | BinaryOperator::Subtraction
+                | BinaryOperator::Multiplication
+                | BinaryOperator::Division
+                | BinaryOperator::Remainder => numeric(&[left, right]),
+                BinaryOperator::Union => ValueType::Nodeset,
+                BinaryOperator::Range => ValueType::Sequence,
+                // These give the empty sequence for an empty operand
+                BinaryOperator::Is
+                | BinaryOperator::Precedes
+                | BinaryOperator::Follows
+                | BinaryOperator::ValueEqual
+                | BinaryOperator::ValueNotEqual
+                | BinaryOperator::ValueLessThan
+                | BinaryOperator::ValueLessThanOrEqual
+                | BinaryOperator::ValueGreaterThan
+                | BinaryOperator::ValueGreaterThanOrEqual => ValueType::Unknown,
+            },
+            Expr::If {
+                ref consequent,
+                ref alternative,
+                ..
+            } => match (consequent.return_type(), alternative.return_type()) {
+                (consequent, alternative) if consequent == alternative => consequent,
+                _ => ValueType::Unknown,
+            },
+            Expr::Let { ref body, .. } => body.return_type(),
+            Expr::Quantified { .. } | Expr::InstanceOf { .. } | Expr::Castable { .. } => {
+                ValueType::Boolean
+            }
+            Expr::For { .. }
+            | Expr::Sequence(_)
+            | Expr::Map(_)
+            | Expr::SquareArray(_)
+            | Expr::CurlyArray(_) => ValueType::Sequence,
+            Expr::Cast { .. }
+            | Expr::Lookup { .. }
+            | Expr::InlineFunction { .. }
+            | Expr::DynamicCall { .. } => ValueType::Unknown,
+        }
+    }
+}
+
+/// The kind of value that the core function with the name gives, when
+/// it is always the same.
+pub(crate) fn core_function_type(name: &str) -> ValueType {
+    match name {
+        "boolean" | "contains" | "ends-with" | "false" | "not" | "starts-with" | "true" => {
+            ValueType::Boolean
+        }
+        "ceiling" | "count" | "floor" | "last" | "number" | "position" | "string-length"
+        | "sum" => ValueType::Number,
+        "concat" | "local-name" | "lower-case" | "name" | "namespace-uri" | "normalize-space"
+        | "string" | "string-join" | "substring" | "translate" | "upper-case" => ValueType::String,
+        "current" | "document" | "id" | "key" => ValueType::Nodeset,
+        _ => ValueType::Unknown,
+    }
+}
+
+fn visit_all<'a, E>(
+    expressions: &'a [Expr],
+    bound: &mut Vec<&'a OwnedPrefixedName>,
+    visit: &mut dyn FnMut(Name<'a>) -> Result<(), E>,
+) -> Result<(), E> {
+    expressions
+        .iter()
+        .try_for_each(|expr| expr.visit_names(bound, visit))
+}
+
+/// Bound variables are only visible in the expression that they are
+/// bound for.
+fn visit_bound<'a, E>(
+    variables: &[&'a OwnedPrefixedName],
+    body: &'a Expr,
+    bound: &mut Vec<&'a OwnedPrefixedName>,
+    visit: &mut dyn FnMut(Name<'a>) -> Result<(), E>,
+) -> Result<(), E> {
+    let outer = bound.len();
+    bound.extend(variables);
+    let visited = body.visit_names(bound, visit);
+    bound.truncate(outer);
+    visited
+}
+
+/// Resolves the prefix of a function, variable or type name the same
+/// way as evaluating does.
+fn resolve<'a>(
+    context: &'a Context<'_>,
+    name: &'a OwnedPrefixedName,
+) -> Result<QName<'a>, expression::Error> {
+    let uri = match name.prefix {
+        None => None,
+        Some(ref prefix) => Some(
+            context
+                .namespace_for(prefix)
+                .or_else(|| expression::predeclared_namespace(prefix))
+                .ok_or_else(|| expression::Error::UnknownNamespace {
+                    prefix: prefix.clone(),
+                })?,
+        ),
+    };
+    Ok(QName::with_namespace_uri(uri, &name.local_part))
+}
+
+impl LookupKey {
+    fn compile(&self, traced: bool) -> expression::LookupKey {
+        match *self {
+            LookupKey::Name(ref name) => expression::LookupKey::Name(name.clone()),
+            LookupKey::Expression(ref expr) => {
+                expression::LookupKey::Expression(expr.compile_traced(traced))
+            }
+            LookupKey::Wildcard => expression::LookupKey::Wildcard,
+        }
+    }
+}
+
+/// Compiles a path. An absolute path is split after the first step
+/// that compares a value to a literal or a variable, so that the nodes
+/// up to that step may be looked up in an index.
+fn compile_path(start_point: &Expr, steps: &[Step], traced: bool) -> SubExpression {
+    let lookup = steps
+        .iter()
+        .enumerate()
+        .find_map(|(i, step)| step.compared_value().map(|(key, value)| (i, key, value)));
+
+    let (i, key, value) = match (start_point, lookup) {
+        // A traced path is not split, so its predicates are evaluated
+        (Expr::RootNode, Some(lookup)) if !traced => lookup,
+        _ => {
+            return expression::Path::new(
+                start_point.compile_traced(traced),
+                steps.iter().map(|step| step.compile(traced)).collect(),
+            )
+        }
+    };
+
+    let (indexed, rest) = steps.split_at(i + 1);
+    let mut path = indexed.to_vec();
+    path[i].predicates.clear();
+    let lookup = Box::new(expression::IndexedLookup {
+        path: Expr::Path {
+            start_point: Box::new(Expr::RootNode),
+            steps: path,
+        },
+        key: key.clone(),
+        value: value.compile_traced(traced),
+        fallback: expression::Path::new(
+            start_point.compile_traced(traced),
+            indexed.iter().map(|step| step.compile(traced)).collect(),
+        ),
+    });
+
+    if rest.is_empty() {
+        lookup
+    } else {
+        expression::Path::new(
+            lookup,
+            rest.iter().map(|step| step.compile(traced)).collect(),
+        )
+    }
+}
+
+impl Step {
+    /// The key and the value compared by the only predicate of the
+    /// step, when the value is a literal or a variable.
+    fn compared_value(&self) -> Option<(&Expr, &Expr)> {
+        let (left, right) = match self.predicates[..] {
+            [Expr::Binary {
+                operator: BinaryOperator::Equal,
+                ref left,
+                ref right,
+            }] => (&**left, &**right),
+            _ => return None,
+        };
+        let is_value = |e: &Expr| matches!(e, Expr::Literal(_) | Expr::Variable(_));
+        match (is_value(left), is_value(right)) {
+            (false, true) => Some((left, right)),
+            (true, false) => Some((right, left)),
+            _ => None,
+        }
+    }
+
+    fn compile(&self, traced: bool) -> expression::Step {
+        let predicates = self
+            .predicates
+            .iter()
+            .map(|predicate| predicate.compile_traced(traced))
+            .collect();
+        expression::Step::new(self.axis, self.node_test.compile(self.axis), predicates)
+    }
+}
+
+impl SequenceType {
+    fn compile(&self) -> expression::SequenceType {
+        let item_type = self.item_type.as_ref().map(|item_type| match *item_type {
+            ItemType::Item => expression::ItemType::Item,
+            ItemType::Kind(ref kind) => expression::ItemType::Kind(kind.compile()),
+            ItemType::Atomic(ref name) => expression::ItemType::Atomic(name.clone()),
+        });
+
+        expression::SequenceType {
+            item_type,
+            occurrence: self.occurrence,
+            description: self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression in the canonical form of a [`Formatter`][].
+    ///
+    /// [`Formatter`]: ../format/struct.Formatter.html
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Formatter::new().format(self))
+    }
+}
+
+impl fmt::Display for SequenceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let item_type = match self.item_type {
+            Some(ref item_type) => item_type,
+            None => return f.write_str("empty-sequence()"),
+        };
+
+        write!(f, "{}", item_type)?;
+        match self.occurrence {
+            Occurrence::ExactlyOne => Ok(()),
+            Occurrence::ZeroOrOne => f.write_str("?"),
+            Occurrence::ZeroOrMore => f.write_str("*"),
+            Occurrence::OneOrMore => f.write_str("+"),
+        }
+    }
+}
+
+impl fmt::Display for ItemType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn name_test(f: &mut fmt::Formatter<'_>, name: &Option<NameTest>) -> fmt::Result {
+            match *name {
+                Some(NameTest {
+                    prefix: Some(ref prefix),
+                    ref local_part,
+                }) => write!(f, "{}:{}", prefix, local_part),
+                Some(NameTest {
+                    prefix: None,
+                    ref local_part,
+                }) => f.write_str(local_part),
+                None => Ok(()),
+            }
+        }
+
+        match *self {
+            ItemType::Item => f.write_str("item()"),
+            ItemType::Atomic(ref name) => match name.prefix {
+                Some(ref prefix) => write!(f, "{}:{}", prefix, name.local_part),
+                None => f.write_str(&name.local_part),
+            },
+            ItemType::Kind(ref kind) => match *kind {
+                KindTest::Node => f.write_str("node()"),
+                KindTest::Text => f.write_str("text()"),
+                KindTest::Comment => f.write_str("comment()"),
+                KindTest::ProcessingInstruction(Some(ref target)) => {
+                    write!(f, "processing-instruction('{}')", target)
+                }
+                KindTest::ProcessingInstruction(None) => f.write_str("processing-instruction()"),
+                KindTest::Element(ref name) => {
+                    f.write_str("element(")?;
+                    name_test(f, name)?;
+                    f.write_str(")")
+                }
+                KindTest::Attribute(ref name) => {
+                    f.write_str("attribute(")?;
+                    name_test(f, name)?;
+                    f.write_str(")")
+                }
+                KindTest::Document => f.write_str("document-node()"),
+            },
+        }
+    }
+}
+
+impl KindTest {
+    fn compile(&self) -> SubNodeTest {
+        fn any_name(name: &Option<NameTest>) -> NodeTest {
+            NodeTest::Name(name.clone().unwrap_or_else(|| NameTest {
+                prefix: None,
+                local_part: "*".to_owned(),
+            }))
+        }
+
+        match *self {
+            KindTest::Node => NodeTest::Node.compile(Axis::Child),
+            KindTest::Text => NodeTest::Text.compile(Axis::Child),
+            KindTest::Comment => NodeTest::Comment.compile(Axis::Child),
+            KindTest::ProcessingInstruction(ref target) => {
+                NodeTest::ProcessingInstruction(target.clone()).compile(Axis::Child)
+            }
+            KindTest::Element(ref name) => any_name(name).compile(Axis::Child),
+            KindTest::Attribute(ref name) => any_name(name).compile(Axis::Attribute),
+            KindTest::Document => Box::new(node_test::Root),
+        }
+    }
+}
+
+impl NodeTest {
+    fn compile(&self, axis: Axis) -> SubNodeTest {
+        match *self {
+            NodeTest::Name(ref name) => match axis.principal_node_type() {
+                PrincipalNodeType::Attribute => Box::new(node_test::Attribute::new(name.clone())),
+                PrincipalNodeType::Element => Box::new(node_test::Element::new(name.clone())),
+                PrincipalNodeType::Namespace => Box::new(node_test::Namespace::new(name.clone())),
+            },
+            NodeTest::Node => Box::new(node_test::Node),
+            NodeTest::Text => Box::new(node_test::Text),
+            NodeTest::Comment => Box::new(node_test::Comment),
+            NodeTest::ProcessingInstruction(ref target) => {
+                Box::new(node_test::ProcessingInstruction::new(target.clone()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Factory;
+
+    use super::*;
+
+    fn name(local_part: &str) -> NodeTest {
+        NodeTest::Name(NameTest {
+            prefix: None,
+            local_part: local_part.to_owned(),
+        })
+    }
+
+    #[test]
+    fn compiled_xpaths_keep_their_syntax_tree() {
+        let xpath = Factory::new()
+            .build("/a[@b] | 1")
+            .expect("Unable to build XPath");
+
+        let predicate = Expr::Path {
+            start_point: Box::new(Expr::ContextNode),
+            steps: vec![Step {
+                axis: Axis::Attribute,
+                node_test: name("b"),
+                predicates: vec![],
+            }],
+        };
+        let path = Expr::Path {
+            start_point: Box::new(Expr::RootNode),
+            steps: vec![Step {
+                axis: Axis::Child,
+                node_test: name("a"),
+                predicates: vec![predicate],
+            }],
+        };
+
+        assert_eq!(
+            xpath.ast(),
+            &Expr::binary(
+                BinaryOperator::Union,
+                path,
+                Expr::Literal(Value::Number(1.0))
+            )
+        );
+    }
+
+    fn check(xpath: &str, context: &Context<'_>) -> Result<(), expression::Error> {
+        Factory::new()
+            .build(xpath)
+            .expect("Unable to build XPath")
+            .ast()
+            .check_names(context)
+    }
+
+    #[test]
+    fn names_known_to_the_context_are_accepted() {
+        let mut context = Context::new();
+        context.set_variable("v", 1.0);
+        context.set_namespace("p", "urn:p");
+
+        assert_eq!(Ok(()), check("count(/p:a[@p:b = $v])", &context));
+        assert_eq!(Ok(()), check("xs:integer('1') cast as xs:string", &context));
+        assert_eq!(Ok(()), check("//p:* instance of element(p:a)*", &context));
+    }
+
+    #[test]
+    fn bound_variables_are_only_known_where_they_are_bound() {
+        let context = Context::new();
+
+        assert_eq!(Ok(()), check("for $x in 1 return $x", &context));
+        assert_eq!(Ok(()), check("function($a, $b) { $a + $b }", &context));
+        assert_eq!(
+            Err(expression::Error::UnknownVariable { name: "x".into() }),
+            check("(for $x in 1 return $x) + $x", &context)
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let context = Context::new();
+
+        assert_eq!(
+            Err(expression::Error::UnknownFunction { name: "f".into() }),
+            check("count(f())", &context)
+        );
+        assert_eq!(
+            Err(expression::Error::UnknownType {
+                name: ("xs", "text").into()
+            }),
+            check("1 castable as xs:text", &context)
+        );
+        assert_eq!(
+            Err(expression::Error::UnknownNamespace {
+                prefix: "p".to_owned()
+            }),
+            check("/a/p:b", &context)
+        );
+    }
+
+    fn return_type(xpath: &str) -> ValueType {
+        Factory::new()
+            .build(xpath)
+            .expect("Unable to build XPath")
+            .ast()
+            .return_type()
+    }
+
+    #[test]
+    fn return_types_of_xpath_1_expressions_are_known() {
+        assert_eq!(ValueType::Nodeset, return_type("//a[1] | /b"));
+        assert_eq!(ValueType::Nodeset, return_type("id('x')/a"));
+        assert_eq!(ValueType::Number, return_type("count(//a) * 2"));
+        assert_eq!(ValueType::Number, return_type("//a div 2"));
+        assert_eq!(ValueType::String, return_type("concat('a', //b)"));
+        assert_eq!(ValueType::Boolean, return_type("not(a) or b = 'c'"));
+        assert_eq!(ValueType::Number, return_type("-'1'"));
+    }
+
+    #[test]
+    fn return_types_that_depend_on_the_context_are_unknown() {
+        assert_eq!(ValueType::Unknown, return_type("$v"));
+        assert_eq!(ValueType::Unknown, return_type("$a + 1"));
+        assert_eq!(ValueType::Unknown, return_type("ns:f()"));
+        assert_eq!(ValueType::Unknown, return_type("if (a) then 1 else 'b'"));
+        assert_eq!(ValueType::Number, return_type("if (a) then 1 else 2"));
+        assert_eq!(ValueType::Sequence, return_type("for $a in b return $a"));
+    }
+
+    fn build(xpath: &str) -> crate::XPath {
+        Factory::new().build(xpath).expect("Unable to build XPath")
+    }
+
+    fn names(names: Vec<&OwnedPrefixedName>) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| match name.prefix() {
+                Some(prefix) => format!("{}:{}", prefix, name.local_part()),
+                None => name.local_part().to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn variables_bound_by_the_expression_are_not_listed() {
+        let xpath = build("for $a in $b return ($a, let $c := $a return $c, $d, $b)");
+        assert_eq!(vec!["b", "d"], names(xpath.ast().variables()));
+
+        let xpath = build("some $x in $x satisfies function($y) { $y + $z }(1)");
+        assert_eq!(vec!["x", "z"], names(xpath.ast().variables()));
+    }
+
+    #[test]
+    fn functions_include_constructor_calls() {
+        let xpath = build("count(//a[p:f(.)]) + xs:integer('1') + count(b)");
+        assert_eq!(
+            vec!["count", "p:f", "xs:integer"],
+            names(xpath.ast().functions())
+        );
+    }
+
+    #[test]
+    fn prefixes_of_all_names_are_listed() {
+        let xpath = build("/a:b/*:c/d:*[$e:v] instance of element(f:g) and g:f() cast as h:t");
+        assert_eq!(vec!["a", "d", "e", "f", "g", "h"], xpath.ast().prefixes());
+    }
+}
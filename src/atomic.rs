@@ -0,0 +1,337 @@
+//! Atomic types that values can be tested against and cast to, such
+//! as `xs:string` and `xs:integer`.
+//!
+//! The types are looked up by name in the [`Context`][], so
+//! additional types can be registered alongside the predefined XML
+//! Schema types.
+//!
+//! [`Context`]: ../context/struct.Context.html
+
+use snafu::{ensure, Snafu};
+
+use crate::context;
+use crate::datetime::{DateTime, DateTimeKind, Duration, DurationKind};
+use crate::{Item, Value};
+
+/// The namespace of the predefined atomic types, which is used for
+/// the `xs` prefix unless the context maps it elsewhere.
+pub const XML_SCHEMA_NAMESPACE_URI: &str = "http://www.w3.org/2001/XMLSchema";
+
+/// Types that can be used as XPath atomic types.
+pub trait Type {
+    /// Tests whether the atomic item is an instance of this type.
+    fn matches(&self, item: &Item<'_>) -> bool;
+
+    /// Converts the atomic item to an instance of this type. Nodes
+    /// are converted to their string value before being cast.
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error>;
+}
+
+/// The errors that may occur while casting a value
+#[derive(Debug, Snafu, Clone, PartialEq, Hash)]
+pub enum Error {
+    #[snafu(display("cannot cast {:?} to {}", value, type_name))]
+    InvalidValue { value: String, type_name: String },
+}
+
+fn invalid(item: &Item<'_>, type_name: &str) -> Error {
+    Error::InvalidValue {
+        value: item.string(),
+        type_name: type_name.to_owned(),
+    }
+}
+
+/// Parses the lexical form of a double, which is stricter than the
+/// `number()` function.
+fn parse_double(s: &str) -> Option<f64> {
+    match s.trim() {
+        "INF" => Some(f64::INFINITY),
+        "-INF" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        s if s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+struct AnyAtomicType;
+
+impl Type for AnyAtomicType {
+    fn matches(&self, item: &Item<'_>) -> bool {
+        item.is_atomic()
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        Ok(item)
+    }
+}
+
+struct StringType;
+
+impl Type for StringType {
+    fn matches(&self, item: &Item<'_>) -> bool {
+        matches!(*item, Item::String(_))
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        Ok(Item::String(item.string()))
+    }
+}
+
+struct BooleanType;
+
+impl Type for BooleanType {
+    fn matches(&self, item: &Item<'_>) -> bool {
+        matches!(*item, Item::Boolean(_))
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        match item {
+            Item::String(ref s) => match s.trim() {
+                "true" | "1" => Ok(Item::Boolean(true)),
+                "false" | "0" => Ok(Item::Boolean(false)),
+                _ => Err(invalid(&item, "xs:boolean")),
+            },
+            item => Ok(Item::Boolean(item.boolean())),
+        }
+    }
+}
+
+struct DoubleType;
+
+impl Type for DoubleType {
+    fn matches(&self, item: &Item<'_>) -> bool {
+        matches!(*item, Item::Number(_))
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        match item {
+            Item::String(ref s) => parse_double(s)
+                .map(Item::Number)
+                .ok_or_else(|| invalid(&item, "xs:double")),
+            item => Ok(Item::Number(item.number())),
+        }
+    }
+}
+
+struct DecimalType;
+
+impl Type for DecimalType {
+    fn matches(&self, item: &Item<'_>) -> bool {
+        matches!(*item, Item::Number(n) if n.is_finite())
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        let n = match item {
+            // Decimals are written without an exponent
+            Item::String(ref s) if s.contains(['e', 'E']) => None,
+            Item::String(ref s) => parse_double(s),
+            ref item => Some(item.number()),
+        };
+
+        match n {
+            Some(n) if n.is_finite() => Ok(Item::Number(n)),
+            _ => Err(invalid(&item, "xs:decimal")),
+        }
+    }
+}
+
+struct IntegerType;
+
+impl Type for IntegerType {
+    // Numbers are doubles, but whole numbers are also integers
+    fn matches(&self, item: &Item<'_>) -> bool {
+        matches!(*item, Item::Number(n) if n.is_finite() && n.fract() == 0.0)
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        let n = match item {
+            Item::String(ref s) => {
+                let s = s.trim();
+                let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+                ensure!(
+                    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()),
+                    InvalidValue {
+                        value: s,
+                        type_name: "xs:integer",
+                    }
+                );
+                s.parse().ok()
+            }
+            ref item => Some(item.number().trunc()),
+        };
+
+        match n {
+            Some(n) if n.is_finite() => Ok(Item::Number(n)),
+            _ => Err(invalid(&item, "xs:integer")),
+        }
+    }
+}
+
+struct DateTimeType(DateTimeKind, &'static str);
+
+impl Type for DateTimeType {
+    fn matches(&self, item: &Item<'_>) -> bool {
+        matches!(*item, Item::DateTime(ref dt) if dt.kind() == self.0)
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        let cast = match item {
+            Item::DateTime(ref dt) => dt.to_kind(self.0),
+            Item::String(ref s) => DateTime::parse(s, self.0),
+            _ => None,
+        };
+        cast.map(Item::DateTime)
+            .ok_or_else(|| invalid(&item, self.1))
+    }
+}
+
+struct DurationType(DurationKind, &'static str);
+
+impl Type for DurationType {
+    // The year-month and day-time durations are also durations
+    fn matches(&self, item: &Item<'_>) -> bool {
+        match *item {
+            Item::Duration(ref d) => self.0 == DurationKind::Duration || d.kind() == self.0,
+            _ => false,
+        }
+    }
+
+    fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, Error> {
+        let cast = match item {
+            Item::Duration(ref d) => Some(d.to_kind(self.0)),
+            Item::String(ref s) => Duration::parse(s, self.0),
+            _ => None,
+        };
+        cast.map(Item::Duration)
+            .ok_or_else(|| invalid(&item, self.1))
+    }
+}
+
+/// Converts a value to its atomic items. Nodes are converted to their
+/// string value and arrays to their members.
+pub(crate) fn atomize_all(value: Value<'_>) -> Vec<Item<'_>> {
+    let mut items = Vec::new();
+    atomize_into(value, &mut items);
+    items
+}
+
+fn atomize_into<'d>(value: Value<'d>, atomized: &mut Vec<Item<'d>>) {
+    for item in value.into_items() {
+        match item {
+            Item::Node(n) => atomized.push(Item::String(n.string_value())),
+            Item::Array(members) => {
+                for member in members {
+                    atomize_into(member, atomized);
+                }
+            }
+            item => atomized.push(item),
+        }
+    }
+}
+
+/// Converts a value to a single atomic item, or `None` for the empty
+/// sequence. Fails when the value has more than one item.
+pub(crate) fn atomize(value: Value<'_>) -> Result<Option<Item<'_>>, ()> {
+    let mut items = atomize_all(value);
+    if items.len() > 1 {
+        return Err(());
+    }
+
+    Ok(items.pop())
+}
+
+/// Registers the atomic types of XML Schema that XPath values map to.
+pub fn register_core_types(context: &mut context::Context<'_>) {
+    let xs = |local_part| (XML_SCHEMA_NAMESPACE_URI, local_part);
+
+    context.set_atomic_type(xs("anyAtomicType"), AnyAtomicType);
+    context.set_atomic_type(xs("string"), StringType);
+    context.set_atomic_type(xs("boolean"), BooleanType);
+    context.set_atomic_type(xs("double"), DoubleType);
+    context.set_atomic_type(xs("decimal"), DecimalType);
+    context.set_atomic_type(xs("integer"), IntegerType);
+    context.set_atomic_type(
+        xs("dateTime"),
+        DateTimeType(DateTimeKind::DateTime, "xs:dateTime"),
+    );
+    context.set_atomic_type(xs("date"), DateTimeType(DateTimeKind::Date, "xs:date"));
+    context.set_atomic_type(xs("time"), DateTimeType(DateTimeKind::Time, "xs:time"));
+    context.set_atomic_type(
+        xs("duration"),
+        DurationType(DurationKind::Duration, "xs:duration"),
+    );
+    context.set_atomic_type(
+        xs("yearMonthDuration"),
+        DurationType(DurationKind::YearMonth, "xs:yearMonthDuration"),
+    );
+    context.set_atomic_type(
+        xs("dayTimeDuration"),
+        DurationType(DurationKind::DayTime, "xs:dayTimeDuration"),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn string(s: &str) -> Item<'static> {
+        Item::String(s.to_owned())
+    }
+
+    #[test]
+    fn strings_are_cast_by_their_lexical_form() {
+        assert_eq!(Ok(Item::Number(42.0)), IntegerType.cast(string(" 42 ")));
+        assert_eq!(Ok(Item::Number(-1.5)), DoubleType.cast(string("-1.5")));
+        assert_eq!(
+            Ok(Item::Number(f64::INFINITY)),
+            DoubleType.cast(string("INF"))
+        );
+        assert_eq!(Ok(Item::Boolean(true)), BooleanType.cast(string("1")));
+        assert_eq!(Ok(Item::Number(1.5)), DecimalType.cast(string("1.5")));
+    }
+
+    #[test]
+    fn invalid_lexical_forms_are_errors() {
+        assert!(IntegerType.cast(string("1.5")).is_err());
+        assert!(DoubleType.cast(string("inf")).is_err());
+        assert!(BooleanType.cast(string("yes")).is_err());
+        assert!(DecimalType.cast(string("1e3")).is_err());
+    }
+
+    #[test]
+    fn numbers_are_truncated_to_integers() {
+        assert_eq!(Ok(Item::Number(-2.0)), IntegerType.cast(Item::Number(-2.7)));
+        assert!(IntegerType.cast(Item::Number(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn temporal_values_are_cast_between_kinds() {
+        let date_time = DateTimeType(DateTimeKind::DateTime, "xs:dateTime")
+            .cast(string("2024-01-31T10:30:00Z"))
+            .unwrap();
+        let date = DateTimeType(DateTimeKind::Date, "xs:date").cast(date_time);
+        assert_eq!("2024-01-31Z", date.unwrap().string());
+
+        let time = string("10:30:00");
+        assert!(DateTimeType(DateTimeKind::Date, "xs:date")
+            .cast(time)
+            .is_err());
+    }
+
+    #[test]
+    fn durations_match_their_supertype() {
+        let day_time = DurationType(DurationKind::DayTime, "xs:dayTimeDuration")
+            .cast(string("PT1H"))
+            .unwrap();
+        assert!(DurationType(DurationKind::Duration, "xs:duration").matches(&day_time));
+        assert!(!DurationType(DurationKind::YearMonth, "xs:yearMonthDuration").matches(&day_time));
+    }
+
+    #[test]
+    fn whole_numbers_are_integers() {
+        assert!(IntegerType.matches(&Item::Number(3.0)));
+        assert!(!IntegerType.matches(&Item::Number(3.5)));
+        assert!(DoubleType.matches(&Item::Number(3.5)));
+        assert!(!StringType.matches(&Item::Number(3.5)));
+    }
+}
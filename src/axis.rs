@@ -1,8 +1,10 @@
 use std::fmt;
+use std::mem;
 
 use crate::context;
 use crate::node_test::NodeTest;
-use crate::nodeset::{self, Node, OrderedNodes};
+use crate::nodeset::{Node, OrderedNodes};
+use crate::tree::{self, TreeNode};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PrincipalNodeType {
@@ -21,6 +23,27 @@ pub trait AxisLike: fmt::Debug {
         node_test: &dyn NodeTest,
     ) -> OrderedNodes<'d>;
 
+    /// Visits the nodes that `select_nodes` would select, in the same
+    /// order, until `f` returns false. Returns whether every node was
+    /// visited. Axes that can find their nodes one at a time should
+    /// stop looking once `f` returns false.
+    fn select_nodes_while<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        node_test: &dyn NodeTest,
+        f: &mut dyn FnMut(Node<'d>) -> bool,
+    ) -> bool {
+        Vec::from(self.select_nodes(context, node_test))
+            .into_iter()
+            .all(f)
+    }
+
+    /// Whether every node selected by this axis comes at or after the
+    /// node it starts from, in document order.
+    fn is_forward(&self) -> bool {
+        false
+    }
+
     /// Describes what node type is naturally selected by this axis.
     fn principal_node_type(&self) -> PrincipalNodeType {
         PrincipalNodeType::Element
@@ -28,6 +51,7 @@ pub trait AxisLike: fmt::Debug {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Axis {
     Ancestor,
     AncestorOrSelf,
@@ -65,79 +89,92 @@ impl<'c, 'd> CompleteNodeTest<'c, 'd> {
     }
 }
 
+impl Axis {
+    /// Visits each node selected by this axis, starting from the given
+    /// node, in the order of the axis.
+    pub fn traverse<'d, N, F>(&self, node: N, mut f: F)
+    where
+        N: TreeNode<'d>,
+        F: FnMut(N),
+    {
+        self.traverse_while(node, |n| {
+            f(n);
+            true
+        });
+    }
+
+    /// Visits each node selected by this axis like
+    /// [`traverse`](#method.traverse), until `f` returns false. The
+    /// nodes after that are not looked for. Returns whether every
+    /// node was visited.
+    pub fn traverse_while<'d, N, F>(&self, node: N, mut f: F) -> bool
+    where
+        N: TreeNode<'d>,
+        F: FnMut(N) -> bool,
+    {
+        use self::Axis::*;
+
+        match *self {
+            Ancestor => tree::each_parent_while(node, f),
+            AncestorOrSelf => tree::node_and_each_parent_while(node, f),
+            Attribute => node.attributes().into_iter().all(f),
+            Namespace => node.namespaces().into_iter().all(f),
+            Child => node.children().into_iter().all(f),
+            Descendant => node
+                .children()
+                .into_iter()
+                .all(|child| tree::preorder_left_to_right_while(child, &mut f)),
+            DescendantOrSelf => tree::preorder_left_to_right_while(node, f),
+            Parent => node.parent().into_iter().all(f),
+            PrecedingSibling => node.preceding_siblings().into_iter().all(f),
+            FollowingSibling => node.following_siblings().into_iter().all(f),
+            Preceding => tree::node_and_each_parent_while(node, |node| {
+                node.preceding_siblings()
+                    .into_iter()
+                    .all(|sibling| tree::postorder_right_to_left_while(sibling, &mut f))
+            }),
+            Following => tree::node_and_each_parent_while(node, |node| {
+                node.following_siblings()
+                    .into_iter()
+                    .all(|sibling| tree::preorder_left_to_right_while(sibling, &mut f))
+            }),
+            SelfAxis => f(node),
+        }
+    }
+}
+
 impl AxisLike for Axis {
     fn select_nodes<'c, 'd>(
         &self,
         context: &context::Evaluation<'c, 'd>,
         node_test: &dyn NodeTest,
     ) -> OrderedNodes<'d> {
-        use self::Axis::*;
+        let mut node_test = CompleteNodeTest::new(context, node_test);
+        self.traverse(context.node, |n| node_test.run(n));
+        node_test.result
+    }
 
+    fn select_nodes_while<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        node_test: &dyn NodeTest,
+        f: &mut dyn FnMut(Node<'d>) -> bool,
+    ) -> bool {
         let mut node_test = CompleteNodeTest::new(context, node_test);
+        self.traverse_while(context.node, |n| {
+            node_test.run(n);
+            let selected = mem::take(&mut node_test.result);
+            Vec::from(selected).into_iter().all(&mut *f)
+        })
+    }
 
+    fn is_forward(&self) -> bool {
+        use self::Axis::*;
         match *self {
-            Ancestor => each_parent(context.node, |n| node_test.run(n)),
-            AncestorOrSelf => node_and_each_parent(context.node, |n| node_test.run(n)),
-            Attribute => {
-                if let Node::Element(ref e) = context.node {
-                    for attr in e.attributes() {
-                        node_test.run(Node::Attribute(attr));
-                    }
-                }
-            }
-            Namespace => {
-                if let Node::Element(ref e) = context.node {
-                    for ns in e.namespaces_in_scope() {
-                        let ns = Node::Namespace(nodeset::Namespace {
-                            parent: *e,
-                            prefix: ns.prefix(),
-                            uri: ns.uri(),
-                        });
-
-                        node_test.run(ns);
-                    }
-                }
-            }
-            Child => {
-                for child in context.node.children() {
-                    node_test.run(child);
-                }
-            }
-            Descendant => {
-                for child in context.node.children() {
-                    preorder_left_to_right(child, |n| node_test.run(n));
-                }
-            }
-            DescendantOrSelf => preorder_left_to_right(context.node, |n| node_test.run(n)),
-            Parent => {
-                if let Some(parent) = context.node.parent() {
-                    node_test.run(parent);
-                }
-            }
-            PrecedingSibling => {
-                for sibling in context.node.preceding_siblings() {
-                    node_test.run(sibling)
-                }
-            }
-            FollowingSibling => {
-                for sibling in context.node.following_siblings() {
-                    node_test.run(sibling)
-                }
-            }
-            Preceding => node_and_each_parent(context.node, |node| {
-                for sibling in node.preceding_siblings() {
-                    postorder_right_to_left(sibling, |n| node_test.run(n));
-                }
-            }),
-            Following => node_and_each_parent(context.node, |node| {
-                for sibling in node.following_siblings() {
-                    preorder_left_to_right(sibling, |n| node_test.run(n));
-                }
-            }),
-            SelfAxis => node_test.run(context.node),
+            Attribute | Namespace | Child | Descendant | DescendantOrSelf | FollowingSibling
+            | Following | SelfAxis => true,
+            Ancestor | AncestorOrSelf | Parent | PrecedingSibling | Preceding => false,
         }
-
-        node_test.result
     }
 
     fn principal_node_type(&self) -> PrincipalNodeType {
@@ -150,60 +187,6 @@ impl AxisLike for Axis {
     }
 }
 
-fn preorder_left_to_right<'d, F>(node: Node<'d>, mut f: F)
-where
-    F: FnMut(Node<'d>),
-{
-    let mut stack = vec![node];
-
-    while let Some(current) = stack.pop() {
-        f(current);
-
-        for child in current.children().into_iter().rev() {
-            stack.push(child);
-        }
-    }
-}
-
-// There's other implementations that only require a single stack; are
-// those applicable? Are they better?
-fn postorder_right_to_left<'d, F>(node: Node<'d>, mut f: F)
-where
-    F: FnMut(Node<'d>),
-{
-    let mut stack = vec![node];
-    let mut stack2 = vec![];
-
-    while let Some(current) = stack.pop() {
-        for child in current.children().into_iter().rev() {
-            stack.push(child);
-        }
-        stack2.push(current);
-    }
-
-    for current in stack2.into_iter().rev() {
-        f(current);
-    }
-}
-
-fn node_and_each_parent<'d, F>(node: Node<'d>, mut f: F)
-where
-    F: FnMut(Node<'d>),
-{
-    f(node);
-    each_parent(node, f);
-}
-
-fn each_parent<'d, F>(mut node: Node<'d>, mut f: F)
-where
-    F: FnMut(Node<'d>),
-{
-    while let Some(parent) = node.parent() {
-        f(parent);
-        node = parent;
-    }
-}
-
 #[cfg(test)]
 mod test {
     use sxd_document::dom;
@@ -0,0 +1,379 @@
+//! Support for evaluating XPaths against trees other than
+//! `sxd_document`.
+//!
+//! A foreign tree is exposed by implementing [`ExternalTree`][], which
+//! describes every node of the tree by a numeric identifier. Nodes of
+//! such a tree are wrapped in [`ExternalNode`][] and can then be used
+//! anywhere a [`Node`][] is accepted, so the same compiled [`XPath`][]
+//! can be evaluated against any backend.
+//!
+//! Adapters for some popular tree crates are available behind cargo
+//! features:
+//!
+//! - `html` — [`html::HtmlTree`](html/struct.HtmlTree.html)
+//! - `minidom` — [`minidom::MinidomTree`](minidom/struct.MinidomTree.html)
+//! - `xmltree` — [`xmltree::XmlTree`](xmltree/struct.XmlTree.html)
+//!
+//! [`ExternalTree`]: trait.ExternalTree.html
+//! [`ExternalNode`]: struct.ExternalNode.html
+//! [`Node`]: ../nodeset/enum.Node.html
+//! [`XPath`]: ../struct.XPath.html
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use sxd_document::QName;
+
+use crate::nodeset::Node;
+use crate::tree::NodeKind;
+
+#[cfg(feature = "html")]
+pub mod html;
+#[cfg(feature = "minidom")]
+pub mod minidom;
+#[cfg(feature = "xmltree")]
+pub mod xmltree;
+
+/// A tree whose nodes are identified by a `usize`.
+///
+/// Identifiers only need to be unique within a single tree. Every
+/// node of the [XPath data model][model], including attributes and
+/// namespaces, must have its own identifier.
+///
+/// [model]: https://www.w3.org/TR/xpath/#data-model
+pub trait ExternalTree {
+    /// The identifier of the root node.
+    fn root(&self) -> usize;
+
+    /// The type of the node.
+    fn kind(&self, id: usize) -> NodeKind;
+
+    /// Returns the [expanded name][] of the node, if any.
+    ///
+    /// [expanded name]: https://www.w3.org/TR/xpath/#dt-expanded-name
+    fn expanded_name(&self, id: usize) -> Option<QName<'_>>;
+
+    /// Returns the parent of the node, if any. The parent of an
+    /// attribute or namespace is the element that owns it.
+    fn parent(&self, id: usize) -> Option<usize>;
+
+    /// Returns the children of the node in document order.
+    fn children(&self, id: usize) -> Vec<usize>;
+
+    /// Returns the attributes of the node, if it is an element.
+    fn attributes(&self, _id: usize) -> Vec<usize> {
+        Vec::new()
+    }
+
+    /// Returns the namespaces in scope for the node, if it is an
+    /// element.
+    fn namespaces(&self, _id: usize) -> Vec<usize> {
+        Vec::new()
+    }
+
+    /// The textual content of a text, comment, attribute,
+    /// processing instruction, or namespace node.
+    fn value(&self, id: usize) -> Option<&str>;
+
+    /// The name of the node, including a prefix that corresponds to
+    /// the namespace, if any.
+    fn prefixed_name(&self, id: usize) -> Option<String> {
+        self.expanded_name(id).map(|n| n.local_part().to_owned())
+    }
+
+    /// Returns the [string value] of the node.
+    ///
+    /// [string value]: https://www.w3.org/TR/xpath/#dt-string-value
+    fn string_value(&self, id: usize) -> String {
+        fn text_descendants<T: ExternalTree + ?Sized>(tree: &T, id: usize, result: &mut String) {
+            for child in tree.children(id) {
+                match tree.kind(child) {
+                    NodeKind::Element => text_descendants(tree, child, result),
+                    NodeKind::Text => result.push_str(tree.value(child).unwrap_or("")),
+                    _ => {}
+                }
+            }
+        }
+
+        match self.kind(id) {
+            NodeKind::Root | NodeKind::Element => {
+                let mut result = String::new();
+                text_descendants(self, id, &mut result);
+                result
+            }
+            _ => self.value(id).unwrap_or("").to_owned(),
+        }
+    }
+
+    /// Whether name tests against the node follow the HTML rules.
+    /// See [`TreeNode::is_html`](../tree/trait.TreeNode.html#method.is_html).
+    fn is_html(&self, _id: usize) -> bool {
+        false
+    }
+}
+
+/// A node belonging to an [`ExternalTree`][].
+///
+/// [`ExternalTree`]: trait.ExternalTree.html
+#[derive(Copy, Clone)]
+pub struct ExternalNode<'d> {
+    tree: &'d dyn ExternalTree,
+    id: usize,
+    /// The position among the children of the parent, when the node
+    /// was found in that list.
+    index: Option<usize>,
+}
+
+impl<'d> ExternalNode<'d> {
+    pub fn new(tree: &'d dyn ExternalTree, id: usize) -> Self {
+        ExternalNode {
+            tree,
+            id,
+            index: None,
+        }
+    }
+
+    /// The tree to which this node belongs.
+    pub fn tree(&self) -> &'d dyn ExternalTree {
+        self.tree
+    }
+
+    /// The identifier of this node within its tree.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.tree.kind(self.id)
+    }
+
+    pub fn expanded_name(&self) -> Option<QName<'d>> {
+        self.tree.expanded_name(self.id)
+    }
+
+    pub fn prefixed_name(&self) -> Option<String> {
+        self.tree.prefixed_name(self.id)
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        self.tree.parent(self.id).map(|id| self.with_id(id))
+    }
+
+    pub fn children(&self) -> Vec<Self> {
+        self.map_siblings(&self.tree.children(self.id), 0)
+    }
+
+    pub fn attributes(&self) -> Vec<Self> {
+        self.map_ids(self.tree.attributes(self.id))
+    }
+
+    pub fn namespaces(&self) -> Vec<Self> {
+        self.map_ids(self.tree.namespaces(self.id))
+    }
+
+    /// Returns the nodes with the same parent that occur before this
+    /// node, nearest first.
+    pub fn preceding_siblings(&self) -> Vec<Self> {
+        let siblings = self.siblings();
+        match self.position_in(&siblings) {
+            Some(pos) => {
+                let mut preceding = self.map_siblings(&siblings[..pos], 0);
+                preceding.reverse();
+                preceding
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the nodes with the same parent that occur after this
+    /// node, nearest first.
+    pub fn following_siblings(&self) -> Vec<Self> {
+        let siblings = self.siblings();
+        match self.position_in(&siblings) {
+            Some(pos) => self.map_siblings(&siblings[pos + 1..], pos + 1),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn string_value(&self) -> String {
+        self.tree.string_value(self.id)
+    }
+
+    pub fn is_html(&self) -> bool {
+        self.tree.is_html(self.id)
+    }
+
+    fn siblings(&self) -> Vec<usize> {
+        match self.kind() {
+            NodeKind::Attribute | NodeKind::Namespace => Vec::new(),
+            _ => self
+                .tree
+                .parent(self.id)
+                .map(|p| self.tree.children(p))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Finds this node among its siblings, without a search when its
+    /// position is already known.
+    fn position_in(&self, siblings: &[usize]) -> Option<usize> {
+        match self.index {
+            Some(index) if siblings.get(index) == Some(&self.id) => Some(index),
+            _ => siblings.iter().position(|&id| id == self.id),
+        }
+    }
+
+    fn with_id(&self, id: usize) -> Self {
+        ExternalNode::new(self.tree, id)
+    }
+
+    /// Wraps consecutive children of one parent, the first of which
+    /// is at `first_index`.
+    fn map_siblings(&self, ids: &[usize], first_index: usize) -> Vec<Self> {
+        ids.iter()
+            .enumerate()
+            .map(|(i, &id)| ExternalNode {
+                index: Some(first_index + i),
+                ..self.with_id(id)
+            })
+            .collect()
+    }
+
+    fn map_ids(&self, ids: Vec<usize>) -> Vec<Self> {
+        ids.into_iter().map(|id| self.with_id(id)).collect()
+    }
+
+    fn tree_address(&self) -> *const u8 {
+        self.tree as *const dyn ExternalTree as *const u8
+    }
+}
+
+impl<'d> PartialEq for ExternalNode<'d> {
+    fn eq(&self, other: &Self) -> bool {
+        self.tree_address() == other.tree_address() && self.id == other.id
+    }
+}
+
+impl<'d> Eq for ExternalNode<'d> {}
+
+impl<'d> Hash for ExternalNode<'d> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.tree_address().hash(state);
+        self.id.hash(state);
+    }
+}
+
+impl<'d> fmt::Debug for ExternalNode<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalNode")
+            .field("id", &self.id)
+            .field("kind", &self.kind())
+            .field("name", &self.expanded_name())
+            .finish()
+    }
+}
+
+impl<'d> From<ExternalNode<'d>> for Node<'d> {
+    fn from(v: ExternalNode<'d>) -> Node<'d> {
+        Node::External(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A root with the children 1 to 4.
+    struct Flat;
+
+    impl ExternalTree for Flat {
+        fn root(&self) -> usize {
+            0
+        }
+
+        fn kind(&self, id: usize) -> NodeKind {
+            if id == 0 {
+                NodeKind::Root
+            } else {
+                NodeKind::Element
+            }
+        }
+
+        fn expanded_name(&self, id: usize) -> Option<QName<'_>> {
+            if id == 0 {
+                None
+            } else {
+                Some(QName::new("item"))
+            }
+        }
+
+        fn parent(&self, id: usize) -> Option<usize> {
+            if id == 0 {
+                None
+            } else {
+                Some(0)
+            }
+        }
+
+        fn children(&self, id: usize) -> Vec<usize> {
+            if id == 0 {
+                vec![1, 2, 3, 4]
+            } else {
+                Vec::new()
+            }
+        }
+
+        fn value(&self, _id: usize) -> Option<&str> {
+            None
+        }
+    }
+
+    fn ids(nodes: Vec<ExternalNode<'_>>) -> Vec<usize> {
+        nodes.iter().map(ExternalNode::id).collect()
+    }
+
+    #[test]
+    fn siblings_are_found_with_and_without_a_known_position() {
+        let tree = Flat;
+        let found = ExternalNode::new(&tree, 0).children()[2];
+        let stale = ExternalNode {
+            index: Some(0),
+            ..found
+        };
+
+        for node in &[found, ExternalNode::new(&tree, 3), stale] {
+            assert_eq!(vec![2, 1], ids(node.preceding_siblings()));
+            assert_eq!(vec![4], ids(node.following_siblings()));
+        }
+    }
+
+    #[test]
+    fn siblings_know_their_positions() {
+        let tree = Flat;
+        let node = ExternalNode::new(&tree, 1);
+        let following = node.following_siblings();
+
+        assert_eq!(Some(1), following[0].index);
+        assert_eq!(vec![3, 2, 1], ids(following[2].preceding_siblings()));
+    }
+
+    #[test]
+    fn external_nodes_have_no_sxd_document() {
+        let tree = Flat;
+        let node = Node::from(ExternalNode::new(&tree, 1));
+
+        assert!(node.owner().document().is_none());
+    }
+
+    #[test]
+    fn external_nodes_are_written_with_their_kind() {
+        let tree = Flat;
+        let node = Node::from(ExternalNode::new(&tree, 1));
+
+        assert_eq!("element", node.kind_name());
+        assert_eq!(
+            r#"[{"type":"element","name":"item","value":""}]"#,
+            crate::json::render_value(&nodeset![node].into())
+        );
+    }
+}
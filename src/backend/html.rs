@@ -0,0 +1,282 @@
+//! Evaluate XPaths against HTML parsed by
+//! [`html5ever`](https://docs.rs/html5ever/).
+//!
+//! Name tests follow the rules browsers use for HTML documents: an
+//! unprefixed name selects elements in the HTML namespace, and element
+//! and attribute names are compared ASCII case-insensitively.
+//!
+//! ```
+//! use sxd_xpath::backend::html::HtmlTree;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let tree = HtmlTree::parse("<p>Hello <A HREF='/world'>world</A>");
+//!
+//! let xpath = Factory::new().build("//p/a/@href").unwrap();
+//! let value = xpath.evaluate(&Context::new(), tree.root()).unwrap();
+//!
+//! assert_eq!(value.string(), "/world");
+//! ```
+
+use ::html5ever::tendril::TendrilSink;
+use ::html5ever::QualName;
+use ::markup5ever_rcdom::{Handle, NodeData, RcDom};
+use sxd_document::QName;
+
+use crate::backend::{ExternalNode, ExternalTree};
+use crate::nodeset::Node;
+use crate::tree::NodeKind;
+use crate::HTML_NAMESPACE_URI;
+
+#[derive(Debug)]
+struct Entry {
+    kind: NodeKind,
+    parent: Option<usize>,
+    local_part: Option<String>,
+    namespace_uri: Option<String>,
+    prefix: Option<String>,
+    value: Option<String>,
+    is_html: bool,
+    children: Vec<usize>,
+    attributes: Vec<usize>,
+}
+
+impl Entry {
+    fn new(kind: NodeKind, parent: Option<usize>) -> Self {
+        Entry {
+            kind,
+            parent,
+            local_part: None,
+            namespace_uri: None,
+            prefix: None,
+            value: None,
+            is_html: false,
+            children: Vec::new(),
+            attributes: Vec::new(),
+        }
+    }
+
+    fn set_name(&mut self, name: &QualName) {
+        self.local_part = Some(name.local.to_string());
+        self.namespace_uri = Some(name.ns.to_string()).filter(|ns| !ns.is_empty());
+        self.prefix = name.prefix.as_ref().map(|p| p.to_string());
+    }
+}
+
+/// A copy of an HTML DOM that XPaths can be evaluated against.
+///
+/// The DOM built by html5ever keeps its text behind `RefCell`s, so
+/// the tree copies the content it needs when it is created. Doctypes
+/// and the contents of `<template>` elements are not included.
+#[derive(Debug)]
+pub struct HtmlTree {
+    entries: Vec<Entry>,
+}
+
+impl HtmlTree {
+    /// Parses an HTML document, recovering from errors the same way a
+    /// browser does.
+    pub fn parse(html: &str) -> Self {
+        let dom = ::html5ever::parse_document(RcDom::default(), Default::default()).one(html);
+        HtmlTree::new(&dom)
+    }
+
+    pub fn new(dom: &RcDom) -> Self {
+        let mut tree = HtmlTree {
+            entries: vec![Entry::new(NodeKind::Root, None)],
+        };
+        tree.add_children(&dom.document, 0);
+        tree
+    }
+
+    /// The root node of the tree.
+    pub fn root(&self) -> Node<'_> {
+        ExternalNode::new(self, 0).into()
+    }
+
+    fn push(&mut self, entry: Entry) -> usize {
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+
+    fn add_children(&mut self, handle: &Handle, parent: usize) {
+        for child in handle.children.borrow().iter() {
+            if let Some(id) = self.add_node(child, parent) {
+                self.entries[parent].children.push(id);
+            }
+        }
+    }
+
+    fn add_node(&mut self, handle: &Handle, parent: usize) -> Option<usize> {
+        let id = match handle.data {
+            NodeData::Document | NodeData::Doctype { .. } => return None,
+            NodeData::Text { ref contents } => {
+                let mut entry = Entry::new(NodeKind::Text, Some(parent));
+                entry.value = Some(contents.borrow().to_string());
+                self.push(entry)
+            }
+            NodeData::Comment { ref contents } => {
+                let mut entry = Entry::new(NodeKind::Comment, Some(parent));
+                entry.value = Some(contents.to_string());
+                self.push(entry)
+            }
+            NodeData::ProcessingInstruction {
+                ref target,
+                ref contents,
+            } => {
+                let mut entry = Entry::new(NodeKind::ProcessingInstruction, Some(parent));
+                entry.local_part = Some(target.to_string());
+                entry.value = Some(contents.to_string());
+                self.push(entry)
+            }
+            NodeData::Element {
+                ref name,
+                ref attrs,
+                ..
+            } => {
+                let is_html = &*name.ns == HTML_NAMESPACE_URI;
+
+                let mut entry = Entry::new(NodeKind::Element, Some(parent));
+                entry.set_name(name);
+                entry.is_html = is_html;
+                let id = self.push(entry);
+
+                for attr in attrs.borrow().iter() {
+                    let mut entry = Entry::new(NodeKind::Attribute, Some(id));
+                    entry.set_name(&attr.name);
+                    entry.value = Some(attr.value.to_string());
+                    entry.is_html = is_html;
+                    let attr = self.push(entry);
+                    self.entries[id].attributes.push(attr);
+                }
+
+                self.add_children(handle, id);
+                id
+            }
+        };
+        Some(id)
+    }
+}
+
+impl ExternalTree for HtmlTree {
+    fn root(&self) -> usize {
+        0
+    }
+
+    fn kind(&self, id: usize) -> NodeKind {
+        self.entries[id].kind
+    }
+
+    fn expanded_name(&self, id: usize) -> Option<QName<'_>> {
+        let entry = &self.entries[id];
+        let local_part = entry.local_part.as_ref()?;
+        Some(QName::with_namespace_uri(
+            entry.namespace_uri.as_deref(),
+            local_part,
+        ))
+    }
+
+    fn parent(&self, id: usize) -> Option<usize> {
+        self.entries[id].parent
+    }
+
+    fn children(&self, id: usize) -> Vec<usize> {
+        self.entries[id].children.clone()
+    }
+
+    fn attributes(&self, id: usize) -> Vec<usize> {
+        self.entries[id].attributes.clone()
+    }
+
+    fn value(&self, id: usize) -> Option<&str> {
+        self.entries[id].value.as_deref()
+    }
+
+    fn prefixed_name(&self, id: usize) -> Option<String> {
+        let entry = &self.entries[id];
+        let local_part = entry.local_part.as_ref()?;
+        match entry.prefix {
+            Some(ref prefix) => Some(format!("{}:{}", prefix, local_part)),
+            None => Some(local_part.clone()),
+        }
+    }
+
+    fn is_html(&self, id: usize) -> bool {
+        self.entries[id].is_html
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Context, Factory, Value};
+
+    use super::*;
+
+    fn evaluate<'d>(tree: &'d HtmlTree, xpath: &str) -> Value<'d> {
+        let mut context = Context::new();
+        context.set_namespace("h", HTML_NAMESPACE_URI);
+        context.set_namespace("svg", "http://www.w3.org/2000/svg");
+
+        let xpath = Factory::new().build(xpath).expect("Unable to build XPath");
+        xpath
+            .evaluate(&context, tree.root())
+            .expect("Unable to evaluate XPath")
+    }
+
+    #[test]
+    fn recovers_from_malformed_markup() {
+        let tree = HtmlTree::parse("<title>T</title><p>one<p>two");
+
+        assert_eq!(evaluate(&tree, "count(/html/body/p)"), Value::Number(2.0));
+        assert_eq!(
+            evaluate(&tree, "string(/html/head/title)"),
+            Value::String("T".into())
+        );
+    }
+
+    #[test]
+    fn unprefixed_names_select_html_elements() {
+        let tree = HtmlTree::parse("<div id=main><span>x</span></div>");
+
+        assert_eq!(evaluate(&tree, "count(//div)"), Value::Number(1.0));
+        assert_eq!(evaluate(&tree, "count(//h:div)"), Value::Number(1.0));
+        assert_eq!(
+            evaluate(&tree, "string(//div/@id)"),
+            Value::String("main".into())
+        );
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let tree = HtmlTree::parse("<DIV Class=a><Span>x</Span></DIV>");
+
+        assert_eq!(evaluate(&tree, "count(//DIV/span)"), Value::Number(1.0));
+        assert_eq!(
+            evaluate(&tree, "string(//div/@CLASS)"),
+            Value::String("a".into())
+        );
+        assert_eq!(
+            evaluate(&tree, "name(//SPAN)"),
+            Value::String("span".into())
+        );
+    }
+
+    #[test]
+    fn foreign_elements_keep_their_namespace() {
+        let tree = HtmlTree::parse("<svg><circle/></svg>");
+
+        assert_eq!(evaluate(&tree, "count(//circle)"), Value::Number(0.0));
+        assert_eq!(evaluate(&tree, "count(//svg:circle)"), Value::Number(1.0));
+        assert_eq!(evaluate(&tree, "count(//svg:CIRCLE)"), Value::Number(0.0));
+    }
+
+    #[test]
+    fn includes_comments_and_text() {
+        let tree = HtmlTree::parse("<p>a<!-- note -->b</p>");
+
+        assert_eq!(evaluate(&tree, "string(//p)"), Value::String("ab".into()));
+        assert_eq!(
+            evaluate(&tree, "string(//p/comment())"),
+            Value::String(" note ".into())
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Evaluate XPaths against [`minidom`](https://docs.rs/minidom/)
+//! elements.
+//!
+//! ```
+//! use sxd_xpath::backend::minidom::MinidomTree;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let stanza: minidom::Element =
+//!     r#"<message xmlns="jabber:client" to="juliet@example.com"><body>hi</body></message>"#
+//!         .parse()
+//!         .unwrap();
+//! let tree = MinidomTree::new(&stanza);
+//!
+//! let mut context = Context::new();
+//! context.set_namespace("c", "jabber:client");
+//!
+//! let xpath = Factory::new().build("/c:message/c:body").unwrap();
+//! let value = xpath.evaluate(&context, tree.root()).unwrap();
+//!
+//! assert_eq!(value.string(), "hi");
+//! ```
+
+use std::collections::BTreeMap;
+
+use ::minidom::{Element, Node as MinidomNode};
+use sxd_document::QName;
+
+use crate::backend::{ExternalNode, ExternalTree};
+use crate::nodeset::Node;
+use crate::tree::NodeKind;
+use crate::XML_NAMESPACE_URI;
+
+const XML_PREFIX: &str = "xml";
+
+type Scope<'d> = BTreeMap<Option<&'d str>, &'d str>;
+
+#[derive(Debug)]
+struct Entry<'d> {
+    kind: NodeKind,
+    parent: Option<usize>,
+    local_part: Option<&'d str>,
+    // minidom does not lend out the namespace of an element
+    namespace_uri: Option<String>,
+    prefix: Option<&'d str>,
+    value: Option<&'d str>,
+    children: Vec<usize>,
+    attributes: Vec<usize>,
+    namespaces: Vec<usize>,
+}
+
+impl<'d> Entry<'d> {
+    fn new(kind: NodeKind, parent: Option<usize>) -> Self {
+        Entry {
+            kind,
+            parent,
+            local_part: None,
+            namespace_uri: None,
+            prefix: None,
+            value: None,
+            children: Vec::new(),
+            attributes: Vec::new(),
+            namespaces: Vec::new(),
+        }
+    }
+}
+
+/// An index over a `minidom::Element`, allowing it to be used as the
+/// document element of an XPath tree.
+///
+/// minidom does not retain comments or processing instructions, so
+/// the tree only contains elements, attributes, namespaces, and text.
+#[derive(Debug)]
+pub struct MinidomTree<'d> {
+    entries: Vec<Entry<'d>>,
+}
+
+impl<'d> MinidomTree<'d> {
+    pub fn new(element: &'d Element) -> Self {
+        let mut tree = MinidomTree {
+            entries: vec![Entry::new(NodeKind::Root, None)],
+        };
+        let mut scope = Scope::new();
+        scope.insert(Some(XML_PREFIX), XML_NAMESPACE_URI);
+        let id = tree.add_element(element, 0, &scope);
+        tree.entries[0].children.push(id);
+        tree
+    }
+
+    /// The root node of the tree, the parent of the document element.
+    pub fn root(&self) -> Node<'_> {
+        ExternalNode::new(self, 0).into()
+    }
+
+    fn push(&mut self, entry: Entry<'d>) -> usize {
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+
+    fn add_element(&mut self, element: &'d Element, parent: usize, scope: &Scope<'d>) -> usize {
+        let mut scope = scope.clone();
+        for (prefix, uri) in element.prefixes.declared_prefixes() {
+            scope.insert(prefix.as_deref(), uri);
+        }
+
+        let namespace_uri = element.ns();
+        let mut entry = Entry::new(NodeKind::Element, Some(parent));
+        entry.local_part = Some(element.name());
+        entry.prefix = prefix_for(&scope, &namespace_uri, true);
+        entry.namespace_uri = Some(namespace_uri).filter(|ns| !ns.is_empty());
+        let id = self.push(entry);
+
+        for ((namespace, name), value) in element.attrs().iter() {
+            let namespace_uri = namespace.as_namespace_name();
+            let mut entry = Entry::new(NodeKind::Attribute, Some(id));
+            entry.local_part = Some(name);
+            entry.prefix = namespace_uri.and_then(|ns| prefix_for(&scope, ns, false));
+            entry.namespace_uri = namespace_uri.map(Into::into);
+            entry.value = Some(value);
+            let attr = self.push(entry);
+            self.entries[id].attributes.push(attr);
+        }
+
+        for (&prefix, &uri) in &scope {
+            if uri.is_empty() {
+                continue;
+            }
+            let mut entry = Entry::new(NodeKind::Namespace, Some(id));
+            entry.local_part = Some(prefix.unwrap_or(""));
+            entry.value = Some(uri);
+            let ns = self.push(entry);
+            self.entries[id].namespaces.push(ns);
+        }
+
+        for child in element.nodes() {
+            let child_id = match child {
+                MinidomNode::Element(e) => self.add_element(e, id, &scope),
+                MinidomNode::Text(t) => {
+                    let mut entry = Entry::new(NodeKind::Text, Some(id));
+                    entry.value = Some(t);
+                    self.push(entry)
+                }
+            };
+            self.entries[id].children.push(child_id);
+        }
+
+        id
+    }
+}
+
+/// Finds a prefix bound to the namespace, preferring the default
+/// namespace when allowed.
+fn prefix_for<'d>(scope: &Scope<'d>, namespace_uri: &str, allow_default: bool) -> Option<&'d str> {
+    if allow_default && scope.get(&None) == Some(&namespace_uri) {
+        return None;
+    }
+
+    scope
+        .iter()
+        .find(|&(prefix, &uri)| prefix.is_some() && uri == namespace_uri)
+        .and_then(|(&prefix, _)| prefix)
+}
+
+impl<'d> ExternalTree for MinidomTree<'d> {
+    fn root(&self) -> usize {
+        0
+    }
+
+    fn kind(&self, id: usize) -> NodeKind {
+        self.entries[id].kind
+    }
+
+    fn expanded_name(&self, id: usize) -> Option<QName<'_>> {
+        let entry = &self.entries[id];
+        let local_part = entry.local_part?;
+        Some(QName::with_namespace_uri(
+            entry.namespace_uri.as_deref(),
+            local_part,
+        ))
+    }
+
+    fn parent(&self, id: usize) -> Option<usize> {
+        self.entries[id].parent
+    }
+
+    fn children(&self, id: usize) -> Vec<usize> {
+        self.entries[id].children.clone()
+    }
+
+    fn attributes(&self, id: usize) -> Vec<usize> {
+        self.entries[id].attributes.clone()
+    }
+
+    fn namespaces(&self, id: usize) -> Vec<usize> {
+        self.entries[id].namespaces.clone()
+    }
+
+    fn value(&self, id: usize) -> Option<&str> {
+        self.entries[id].value
+    }
+
+    fn prefixed_name(&self, id: usize) -> Option<String> {
+        let entry = &self.entries[id];
+        let local_part = entry.local_part?;
+        match entry.prefix {
+            Some(prefix) => Some(format!("{}:{}", prefix, local_part)),
+            None => Some(local_part.to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Context, Factory, Value};
+
+    use super::*;
+
+    fn evaluate<'d>(tree: &'d MinidomTree<'_>, xpath: &str) -> Value<'d> {
+        let mut context = Context::new();
+        context.set_namespace("c", "jabber:client");
+        context.set_namespace("p", "urn:xmpp:ping");
+
+        let xpath = Factory::new().build(xpath).expect("Unable to build XPath");
+        xpath
+            .evaluate(&context, tree.root())
+            .expect("Unable to evaluate XPath")
+    }
+
+    fn parse(xml: &str) -> Element {
+        xml.parse().expect("Unable to parse XML")
+    }
+
+    #[test]
+    fn selects_elements_in_the_default_namespace() {
+        let stanza = parse(
+            r#"<message xmlns="jabber:client" type="chat"><body>one</body><body>two</body></message>"#,
+        );
+        let tree = MinidomTree::new(&stanza);
+
+        assert_eq!(
+            evaluate(&tree, "count(/c:message/c:body)"),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            evaluate(&tree, "string(/c:message/c:body[2])"),
+            Value::String("two".into())
+        );
+        assert_eq!(evaluate(&tree, "count(/message)"), Value::Number(0.0));
+        assert_eq!(evaluate(&tree, "name(/*)"), Value::String("message".into()));
+    }
+
+    #[test]
+    fn selects_attributes() {
+        let stanza = parse(r#"<iq xmlns="jabber:client" id="a1" type="get"/>"#);
+        let tree = MinidomTree::new(&stanza);
+
+        assert_eq!(
+            evaluate(&tree, "string(/c:iq/@type)"),
+            Value::String("get".into())
+        );
+        assert_eq!(evaluate(&tree, "count(/c:iq/@*)"), Value::Number(2.0));
+    }
+
+    #[test]
+    fn selects_prefixed_children() {
+        let stanza =
+            parse(r#"<iq xmlns="jabber:client" xmlns:x="urn:xmpp:ping" type="get"><x:ping/></iq>"#);
+        let tree = MinidomTree::new(&stanza);
+
+        assert_eq!(evaluate(&tree, "count(/c:iq/p:ping)"), Value::Number(1.0));
+        assert_eq!(
+            evaluate(&tree, "name(//p:ping)"),
+            Value::String("x:ping".into())
+        );
+        assert_eq!(
+            evaluate(&tree, "string(//p:ping/namespace::x)"),
+            Value::String("urn:xmpp:ping".into())
+        );
+    }
+
+    #[test]
+    fn navigates_between_siblings() {
+        let stanza = parse(r#"<a xmlns="jabber:client"><b/>text<c/></a>"#);
+        let tree = MinidomTree::new(&stanza);
+
+        assert_eq!(
+            evaluate(&tree, "name(//c:c/preceding-sibling::*)"),
+            Value::String("b".into())
+        );
+        assert_eq!(
+            evaluate(&tree, "string(//c:b/following-sibling::text())"),
+            Value::String("text".into())
+        );
+    }
+}
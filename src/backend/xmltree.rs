@@ -0,0 +1,279 @@
+//! Evaluate XPaths against [`xmltree`](https://docs.rs/xmltree/)
+//! elements.
+//!
+//! ```
+//! use sxd_xpath::backend::xmltree::XmlTree;
+//! use sxd_xpath::{Context, Factory, Value};
+//!
+//! let element = xmltree::Element::parse("<a><b>hello</b></a>".as_bytes()).unwrap();
+//! let tree = XmlTree::new(&element);
+//!
+//! let xpath = Factory::new().build("/a/b").unwrap();
+//! let value = xpath.evaluate(&Context::new(), tree.root()).unwrap();
+//!
+//! assert_eq!(value.string(), "hello");
+//! ```
+
+use ::xmltree::{Element, XMLNode};
+use sxd_document::QName;
+
+use crate::backend::{ExternalNode, ExternalTree};
+use crate::nodeset::Node;
+use crate::tree::NodeKind;
+
+const XMLNS_PREFIX: &str = "xmlns";
+
+#[derive(Debug)]
+struct Entry<'d> {
+    kind: NodeKind,
+    parent: Option<usize>,
+    name: Option<QName<'d>>,
+    prefix: Option<&'d str>,
+    value: Option<&'d str>,
+    children: Vec<usize>,
+    attributes: Vec<usize>,
+    namespaces: Vec<usize>,
+}
+
+impl<'d> Entry<'d> {
+    fn new(kind: NodeKind, parent: Option<usize>) -> Self {
+        Entry {
+            kind,
+            parent,
+            name: None,
+            prefix: None,
+            value: None,
+            children: Vec::new(),
+            attributes: Vec::new(),
+            namespaces: Vec::new(),
+        }
+    }
+}
+
+/// An index over an `xmltree::Element`, allowing it to be used as
+/// the document element of an XPath tree.
+///
+/// The index is built once, up front; the element must not be
+/// modified while the index is alive.
+#[derive(Debug)]
+pub struct XmlTree<'d> {
+    entries: Vec<Entry<'d>>,
+}
+
+impl<'d> XmlTree<'d> {
+    pub fn new(element: &'d Element) -> Self {
+        let mut tree = XmlTree {
+            entries: vec![Entry::new(NodeKind::Root, None)],
+        };
+        let id = tree.add_element(element, 0);
+        tree.entries[0].children.push(id);
+        tree
+    }
+
+    /// The root node of the tree, the parent of the document element.
+    pub fn root(&self) -> Node<'_> {
+        ExternalNode::new(self, 0).into()
+    }
+
+    fn push(&mut self, entry: Entry<'d>) -> usize {
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+
+    fn add_element(&mut self, element: &'d Element, parent: usize) -> usize {
+        let mut entry = Entry::new(NodeKind::Element, Some(parent));
+        entry.name = Some(QName::with_namespace_uri(
+            element.namespace.as_deref(),
+            &element.name,
+        ));
+        entry.prefix = element.prefix.as_deref();
+        let id = self.push(entry);
+
+        // Attribute storage may be unordered; sort for stable results
+        let mut attributes: Vec<_> = element.attributes.iter().collect();
+        attributes.sort();
+        for (name, value) in attributes {
+            let mut entry = Entry::new(NodeKind::Attribute, Some(id));
+            entry.name = Some(QName::new(name));
+            entry.value = Some(value);
+            let attr = self.push(entry);
+            self.entries[id].attributes.push(attr);
+        }
+
+        if let Some(namespaces) = &element.namespaces {
+            for (prefix, uri) in &namespaces.0 {
+                if prefix == XMLNS_PREFIX || uri.is_empty() {
+                    continue;
+                }
+                let mut entry = Entry::new(NodeKind::Namespace, Some(id));
+                entry.name = Some(QName::new(prefix));
+                entry.value = Some(uri);
+                let ns = self.push(entry);
+                self.entries[id].namespaces.push(ns);
+            }
+        }
+
+        for child in &element.children {
+            let child_id = match child {
+                XMLNode::Element(e) => self.add_element(e, id),
+                XMLNode::Text(t) | XMLNode::CData(t) => self.add_leaf(NodeKind::Text, t, id),
+                XMLNode::Comment(c) => self.add_leaf(NodeKind::Comment, c, id),
+                XMLNode::ProcessingInstruction(target, value) => {
+                    let mut entry = Entry::new(NodeKind::ProcessingInstruction, Some(id));
+                    entry.name = Some(QName::new(target));
+                    entry.value = value.as_deref();
+                    self.push(entry)
+                }
+            };
+            self.entries[id].children.push(child_id);
+        }
+
+        id
+    }
+
+    fn add_leaf(&mut self, kind: NodeKind, value: &'d str, parent: usize) -> usize {
+        let mut entry = Entry::new(kind, Some(parent));
+        entry.value = Some(value);
+        self.push(entry)
+    }
+}
+
+impl<'d> ExternalTree for XmlTree<'d> {
+    fn root(&self) -> usize {
+        0
+    }
+
+    fn kind(&self, id: usize) -> NodeKind {
+        self.entries[id].kind
+    }
+
+    fn expanded_name(&self, id: usize) -> Option<QName<'_>> {
+        self.entries[id].name
+    }
+
+    fn parent(&self, id: usize) -> Option<usize> {
+        self.entries[id].parent
+    }
+
+    fn children(&self, id: usize) -> Vec<usize> {
+        self.entries[id].children.clone()
+    }
+
+    fn attributes(&self, id: usize) -> Vec<usize> {
+        self.entries[id].attributes.clone()
+    }
+
+    fn namespaces(&self, id: usize) -> Vec<usize> {
+        self.entries[id].namespaces.clone()
+    }
+
+    fn value(&self, id: usize) -> Option<&str> {
+        self.entries[id].value
+    }
+
+    fn prefixed_name(&self, id: usize) -> Option<String> {
+        let entry = &self.entries[id];
+        let name = entry.name?;
+        match entry.prefix {
+            Some(prefix) => Some(format!("{}:{}", prefix, name.local_part())),
+            None => Some(name.local_part().to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Context, Factory, Value};
+
+    use super::*;
+
+    fn evaluate<'d>(tree: &'d XmlTree<'_>, xpath: &str) -> Value<'d> {
+        let mut context = Context::new();
+        context.set_namespace("ns", "urn:example");
+
+        let xpath = Factory::new().build(xpath).expect("Unable to build XPath");
+        xpath
+            .evaluate(&context, tree.root())
+            .expect("Unable to evaluate XPath")
+    }
+
+    fn parse(xml: &str) -> Element {
+        Element::parse(xml.as_bytes()).expect("Unable to parse XML")
+    }
+
+    #[test]
+    fn selects_elements_and_text() {
+        let element = parse("<root><a>one</a><b/><a>two</a></root>");
+        let tree = XmlTree::new(&element);
+
+        assert_eq!(evaluate(&tree, "count(/root/a)"), Value::Number(2.0));
+        assert_eq!(
+            evaluate(&tree, "string(/root/a[2])"),
+            Value::String("two".into())
+        );
+        assert_eq!(evaluate(&tree, "string(/)"), Value::String("onetwo".into()));
+        assert_eq!(
+            evaluate(&tree, "name(/root/b/preceding-sibling::*)"),
+            Value::String("a".into())
+        );
+    }
+
+    #[test]
+    fn selects_attributes() {
+        let element = parse(r#"<root><a id="1" lang="en"/><a id="2"/></root>"#);
+        let tree = XmlTree::new(&element);
+
+        assert_eq!(
+            evaluate(&tree, "string(//a[@id = 2]/@id)"),
+            Value::String("2".into())
+        );
+        assert_eq!(evaluate(&tree, "count(//@*)"), Value::Number(3.0));
+    }
+
+    #[test]
+    fn selects_namespaced_elements() {
+        let element = parse(r#"<x:root xmlns:x="urn:example"><x:a>1</x:a><a>2</a></x:root>"#);
+        let tree = XmlTree::new(&element);
+
+        assert_eq!(
+            evaluate(&tree, "string(/ns:root/ns:a)"),
+            Value::String("1".into())
+        );
+        assert_eq!(
+            evaluate(&tree, "name(/ns:root)"),
+            Value::String("x:root".into())
+        );
+        assert_eq!(
+            evaluate(&tree, "string(/ns:root/a)"),
+            Value::String("2".into())
+        );
+    }
+
+    #[test]
+    fn selects_comments_and_processing_instructions() {
+        let element = parse("<root><!--note--><?pi data?></root>");
+        let tree = XmlTree::new(&element);
+
+        assert_eq!(
+            evaluate(&tree, "string(//comment())"),
+            Value::String("note".into())
+        );
+        assert_eq!(
+            evaluate(&tree, "string(//processing-instruction('pi'))"),
+            Value::String("data".into())
+        );
+    }
+
+    #[test]
+    fn results_are_in_document_order() {
+        let element = parse("<root><a>1</a><b><a>2</a></b><a>3</a></root>");
+        let tree = XmlTree::new(&element);
+
+        let nodes = match evaluate(&tree, "//a") {
+            Value::Nodeset(ns) => ns.document_order(),
+            other => panic!("Expected a nodeset, got {:?}", other),
+        };
+        let text: Vec<_> = nodes.iter().map(|n| n.string_value()).collect();
+        assert_eq!(text, ["1", "2", "3"]);
+    }
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use sxd_document::parser::parse;
+
+use sxd_xpath::repl::{Outcome, Session};
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let filename = match args.get(1) {
+        Some(filename) if args.len() == 2 => filename,
+        _ => {
+            eprintln!("Usage: {} FILE", args[0]);
+            process::exit(2);
+        }
+    };
+
+    let data = fs::read_to_string(filename).unwrap_or_else(|e| {
+        eprintln!("Can't read {}: {}", filename, e);
+        process::exit(1);
+    });
+    let package = parse(&data).unwrap_or_else(|e| {
+        eprintln!("Unable to parse input XML: {}", e);
+        process::exit(1);
+    });
+
+    let mut session = Session::new(package.as_document());
+    println!("Type an XPath to evaluate it, or :help for the commands.");
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("xpath> ");
+        io::stdout().flush().expect("Unable to write the prompt");
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("Can't read input: {}", e);
+                process::exit(1);
+            }
+            None => break,
+        };
+
+        match session.execute(&line) {
+            Outcome::Output(ref output) if output.is_empty() => {}
+            Outcome::Output(output) => println!("{}", output),
+            Outcome::Quit => break,
+        }
+    }
+}
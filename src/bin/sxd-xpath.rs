@@ -0,0 +1,54 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+use sxd_document::parser::parse;
+
+use sxd_xpath::cli::{Options, Query, USAGE};
+
+fn fail(message: &str) -> ! {
+    eprintln!("sxd-xpath: {}", message);
+    process::exit(1);
+}
+
+fn read_file(filename: &str) -> io::Result<String> {
+    let mut data = String::new();
+    if filename == "-" {
+        io::stdin().read_to_string(&mut data)?;
+    } else {
+        File::open(filename)?.read_to_string(&mut data)?;
+    }
+    Ok(data)
+}
+
+fn main() {
+    let mut options = Options::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("sxd-xpath: {}\n\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if options.files.is_empty() {
+        options.files.push(String::from("-"));
+    }
+
+    let query = Query::new(&options).unwrap_or_else(|e| fail(&e.to_string()));
+
+    for filename in &options.files {
+        let data = read_file(filename)
+            .unwrap_or_else(|e| fail(&format!("can't read {}: {}", filename, e)));
+        let package =
+            parse(&data).unwrap_or_else(|e| fail(&format!("unable to parse {}: {}", filename, e)));
+
+        let results = query
+            .run(package.as_document())
+            .unwrap_or_else(|e| fail(&e.to_string()));
+        for result in results.iter().filter(|r| !r.is_empty()) {
+            println!("{}", result);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Functions for calling the crate from C and C++.
+//!
+//! With the `capi` feature, the crate can be built as a C dynamic
+//! library that exports these functions, which `include/sxd_xpath.h`
+//! declares:
+//!
+//! ```text
+//! cargo rustc --release --features capi --crate-type cdylib
+//! ```
+//!
+//! Every object is created by one function and
+//! released by its own `_free` function, which accepts null. Strings
+//! are NUL-terminated UTF-8, and a string returned by a function
+//! belongs to the object it came from.
+//!
+//! Each function that can fail returns a [`Status`][], and the message
+//! of the latest failure on the calling thread is given by
+//! [`sxd_last_error`][].
+//!
+//! ```c
+//! sxd_document *document;
+//! sxd_xpath *xpath;
+//! sxd_result *result;
+//!
+//! if (sxd_document_parse("<a><b>1</b><b>2</b></a>", &document) != SXD_OK ||
+//!     sxd_xpath_compile("/a/b", &xpath) != SXD_OK ||
+//!     sxd_xpath_evaluate(xpath, document, &result) != SXD_OK) {
+//!     fprintf(stderr, "%s\n", sxd_last_error());
+//! }
+//!
+//! for (size_t i = 0; i < sxd_result_count(result); i++) {
+//!     const char *value;
+//!     sxd_result_item_string(result, i, &value);
+//!     puts(value);
+//! }
+//!
+//! sxd_result_free(result);
+//! sxd_xpath_free(xpath);
+//! sxd_document_free(document);
+//! ```
+//!
+//! A result keeps copies of what it holds, so it may outlive the
+//! document it was evaluated against.
+//!
+//! [`Status`]: enum.Status.html
+//! [`sxd_last_error`]: fn.sxd_last_error.html
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+use sxd_document::{parser, Package};
+
+use crate::{Context, Factory, Item, Value};
+
+/// Whether a function succeeded, and how it failed if not.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    /// A pointer that must not be null was null
+    NullArgument = 1,
+    /// A string was not UTF-8
+    InvalidUtf8 = 2,
+    InvalidXml = 3,
+    InvalidXPath = 4,
+    EvaluationFailed = 5,
+    /// An index was past the last item of a result
+    OutOfRange = 6,
+}
+
+/// What kind of value an XPath evaluated to.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueType {
+    Boolean = 0,
+    Number = 1,
+    String = 2,
+    Nodeset = 3,
+    Sequence = 4,
+}
+
+/// A parsed XML document.
+pub struct Document(Package);
+
+/// A compiled XPath and the namespaces it is evaluated with.
+pub struct XPath {
+    xpath: crate::XPath,
+    namespaces: Vec<(String, String)>,
+}
+
+/// The value of an XPath, copied out of the document.
+pub struct Evaluated {
+    value_type: ValueType,
+    boolean: bool,
+    number: f64,
+    string: CString,
+    /// The name and string value of each node or item
+    items: Vec<(Option<CString>, CString)>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(status: Status, message: String) -> Status {
+    LAST_ERROR.with(|last| *last.borrow_mut() = c_string(message));
+    status
+}
+
+/// C strings cannot hold NUL, so the string is cut short at the first
+/// one.
+fn c_string(mut s: String) -> CString {
+    if let Some(nul) = s.find('\0') {
+        s.truncate(nul);
+    }
+    CString::new(s).expect("NUL was removed")
+}
+
+unsafe fn str_argument<'a>(s: *const c_char) -> Result<&'a str, Status> {
+    if s.is_null() {
+        return Err(fail(
+            Status::NullArgument,
+            "a string argument was null".into(),
+        ));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| fail(Status::InvalidUtf8, e.to_string()))
+}
+
+macro_rules! try_status {
+    ($e:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(status) => return status,
+        }
+    };
+}
+
+macro_rules! non_null {
+    ($($p:expr),+) => {
+        if $($p.is_null())||+ {
+            return fail(Status::NullArgument, "a pointer argument was null".into());
+        }
+    };
+}
+
+/// The message of the latest failure on this thread, or an empty
+/// string. It is valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn sxd_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Parses the XML into a new document.
+///
+/// # Safety
+///
+/// `xml` must be a NUL-terminated string and `document` must point to
+/// writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_document_parse(
+    xml: *const c_char,
+    document: *mut *mut Document,
+) -> Status {
+    non_null!(document);
+    let xml = try_status!(str_argument(xml));
+    match parser::parse(xml) {
+        Ok(package) => {
+            *document = Box::into_raw(Box::new(Document(package)));
+            Status::Ok
+        }
+        Err(e) => fail(Status::InvalidXml, format!("invalid XML: {}", e)),
+    }
+}
+
+/// # Safety
+///
+/// `document` must be null or come from `sxd_document_parse`, and must
+/// not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_document_free(document: *mut Document) {
+    if !document.is_null() {
+        drop(Box::from_raw(document));
+    }
+}
+
+/// Compiles the XPath.
+///
+/// # Safety
+///
+/// `xpath` must be a NUL-terminated string and `compiled` must point
+/// to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_xpath_compile(
+    xpath: *const c_char,
+    compiled: *mut *mut XPath,
+) -> Status {
+    non_null!(compiled);
+    let xpath = try_status!(str_argument(xpath));
+    match Factory::new().build(xpath) {
+        Ok(built) => {
+            *compiled = Box::into_raw(Box::new(XPath {
+                xpath: built,
+                namespaces: Vec::new(),
+            }));
+            Status::Ok
+        }
+        Err(e) => fail(Status::InvalidXPath, e.render(xpath)),
+    }
+}
+
+/// Binds the prefix to the namespace URI for later evaluations of the
+/// XPath.
+///
+/// # Safety
+///
+/// `xpath` must come from `sxd_xpath_compile`, and `prefix` and `uri`
+/// must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_xpath_set_namespace(
+    xpath: *mut XPath,
+    prefix: *const c_char,
+    uri: *const c_char,
+) -> Status {
+    non_null!(xpath);
+    let prefix = try_status!(str_argument(prefix));
+    let uri = try_status!(str_argument(uri));
+    (*xpath).namespaces.push((prefix.into(), uri.into()));
+    Status::Ok
+}
+
+/// Evaluates the XPath against the root of the document.
+///
+/// # Safety
+///
+/// `xpath` and `document` must come from `sxd_xpath_compile` and
+/// `sxd_document_parse`, and `result` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_xpath_evaluate(
+    xpath: *const XPath,
+    document: *const Document,
+    result: *mut *mut Evaluated,
+) -> Status {
+    non_null!(xpath, document, result);
+    let xpath = &*xpath;
+    let document = (*document).0.as_document();
+
+    let mut context = Context::new();
+    for (prefix, uri) in &xpath.namespaces {
+        context.set_namespace(prefix, uri);
+    }
+
+    match xpath.xpath.evaluate(&context, document.root()) {
+        Ok(value) => {
+            *result = Box::into_raw(Box::new(Evaluated::new(&value)));
+            Status::Ok
+        }
+        Err(e) => fail(Status::EvaluationFailed, e.to_string()),
+    }
+}
+
+/// # Safety
+///
+/// `xpath` must be null or come from `sxd_xpath_compile`, and must not
+/// be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_xpath_free(xpath: *mut XPath) {
+    if !xpath.is_null() {
+        drop(Box::from_raw(xpath));
+    }
+}
+
+impl Evaluated {
+    fn new(value: &Value<'_>) -> Self {
+        let value_type = match *value {
+            Value::Boolean(_) => ValueType::Boolean,
+            Value::Number(_) => ValueType::Number,
+            Value::String(_) => ValueType::String,
+            Value::Nodeset(_) => ValueType::Nodeset,
+            Value::Sequence(_) => ValueType::Sequence,
+        };
+        let items = match *value {
+            Value::Nodeset(ref nodes) => nodes
+                .document_order()
+                .into_iter()
+                .map(|node| (node.prefixed_name(), node.string_value()))
+                .collect(),
+            Value::Sequence(ref items) => items
+                .iter()
+                .map(|item| match *item {
+                    Item::Node(node) => (node.prefixed_name(), node.string_value()),
+                    ref item => (None, item.string()),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Evaluated {
+            value_type,
+            boolean: value.boolean(),
+            number: value.number(),
+            string: c_string(value.string()),
+            items: items
+                .into_iter()
+                .map(|(name, value)| (name.map(c_string), c_string(value)))
+                .collect(),
+        }
+    }
+}
+
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate`.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_type(result: *const Evaluated) -> ValueType {
+    (*result).value_type
+}
+
+/// The result converted as by the XPath function `boolean()`.
+///
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate`.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_boolean(result: *const Evaluated) -> bool {
+    (*result).boolean
+}
+
+/// The result converted as by the XPath function `number()`.
+///
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate`.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_number(result: *const Evaluated) -> f64 {
+    (*result).number
+}
+
+/// The result converted as by the XPath function `string()`. The
+/// string belongs to the result.
+///
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate`.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_string(result: *const Evaluated) -> *const c_char {
+    (*result).string.as_ptr()
+}
+
+/// The number of nodes of a nodeset, or of items of a sequence. Other
+/// results have none.
+///
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate`.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_count(result: *const Evaluated) -> usize {
+    (*result).items.len()
+}
+
+/// The string value of a node or item of the result, in document
+/// order for a nodeset. The string belongs to the result.
+///
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate` and `value` must point
+/// to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_item_string(
+    result: *const Evaluated,
+    index: usize,
+    value: *mut *const c_char,
+) -> Status {
+    non_null!(result, value);
+    let (_, string) = try_status!(item(&*result, index));
+    *value = string.as_ptr();
+    Status::Ok
+}
+
+/// The prefixed name of a node of the result, or null for nodes and
+/// items without a name. The string belongs to the result.
+///
+/// # Safety
+///
+/// `result` must come from `sxd_xpath_evaluate` and `name` must point
+/// to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_item_name(
+    result: *const Evaluated,
+    index: usize,
+    name: *mut *const c_char,
+) -> Status {
+    non_null!(result, name);
+    let (item_name, _) = try_status!(item(&*result, index));
+    *name = item_name.as_ref().map_or(ptr::null(), |n| n.as_ptr());
+    Status::Ok
+}
+
+fn item(result: &Evaluated, index: usize) -> Result<&(Option<CString>, CString), Status> {
+    result.items.get(index).ok_or_else(|| {
+        fail(
+            Status::OutOfRange,
+            format!("item {} of a result with {}", index, result.items.len()),
+        )
+    })
+}
+
+/// # Safety
+///
+/// `result` must be null or come from `sxd_xpath_evaluate`, and must
+/// not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sxd_result_free(result: *mut Evaluated) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(sxd_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn string(s: *const c_char) -> String {
+        unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+    }
+
+    fn evaluate(xml: &str, xpath: &str) -> Result<*mut Evaluated, Status> {
+        let xml = CString::new(xml).unwrap();
+        let xpath = CString::new(xpath).unwrap();
+        let mut document = ptr::null_mut();
+        let mut compiled = ptr::null_mut();
+        let mut result = ptr::null_mut();
+
+        unsafe {
+            let status = match sxd_document_parse(xml.as_ptr(), &mut document) {
+                Status::Ok => match sxd_xpath_compile(xpath.as_ptr(), &mut compiled) {
+                    Status::Ok => sxd_xpath_evaluate(compiled, document, &mut result),
+                    status => status,
+                },
+                status => status,
+            };
+            sxd_xpath_free(compiled);
+            sxd_document_free(document);
+
+            match status {
+                Status::Ok => Ok(result),
+                status => Err(status),
+            }
+        }
+    }
+
+    #[test]
+    fn nodesets_are_read_one_node_at_a_time() {
+        let result = evaluate("<a><b>1</b><b>2</b></a>", "/a/b").unwrap();
+
+        unsafe {
+            assert_eq!(ValueType::Nodeset, sxd_result_type(result));
+            assert_eq!(2, sxd_result_count(result));
+            assert_eq!("1", string(sxd_result_string(result)));
+
+            let mut value = ptr::null();
+            let mut name = ptr::null();
+            assert_eq!(Status::Ok, sxd_result_item_string(result, 1, &mut value));
+            assert_eq!(Status::Ok, sxd_result_item_name(result, 1, &mut name));
+            assert_eq!(("b".into(), "2".into()), (string(name), string(value)));
+
+            assert_eq!(
+                Status::OutOfRange,
+                sxd_result_item_string(result, 2, &mut value)
+            );
+            assert_eq!("item 2 of a result with 2", last_error());
+
+            sxd_result_free(result);
+        }
+    }
+
+    #[test]
+    fn values_are_converted_like_xpath() {
+        let result = evaluate("<a/>", "'4'").unwrap();
+
+        unsafe {
+            assert_eq!(ValueType::String, sxd_result_type(result));
+            assert_eq!(4.0, sxd_result_number(result));
+            assert!(sxd_result_boolean(result));
+            assert_eq!(0, sxd_result_count(result));
+            sxd_result_free(result);
+        }
+    }
+
+    #[test]
+    fn namespaces_are_set_on_the_xpath() {
+        let xml = CString::new("<a xmlns='urn:a'/>").unwrap();
+        let xpath = CString::new("count(/x:a)").unwrap();
+        let (prefix, uri) = (CString::new("x").unwrap(), CString::new("urn:a").unwrap());
+
+        unsafe {
+            let mut document = ptr::null_mut();
+            let mut compiled = ptr::null_mut();
+            let mut result = ptr::null_mut();
+            assert_eq!(Status::Ok, sxd_document_parse(xml.as_ptr(), &mut document));
+            assert_eq!(Status::Ok, sxd_xpath_compile(xpath.as_ptr(), &mut compiled));
+            assert_eq!(
+                Status::Ok,
+                sxd_xpath_set_namespace(compiled, prefix.as_ptr(), uri.as_ptr())
+            );
+            assert_eq!(
+                Status::Ok,
+                sxd_xpath_evaluate(compiled, document, &mut result)
+            );
+            sxd_xpath_free(compiled);
+            sxd_document_free(document);
+
+            assert_eq!(1.0, sxd_result_number(result));
+            sxd_result_free(result);
+        }
+    }
+
+    #[test]
+    fn failures_have_a_status_and_a_message() {
+        assert_eq!(Some(Status::InvalidXml), evaluate("<a>", "1").err());
+        assert!(last_error().starts_with("invalid XML"));
+
+        assert_eq!(Some(Status::InvalidXPath), evaluate("<a/>", "1 +").err());
+        assert!(last_error().starts_with("right hand side of expression is missing"));
+
+        assert_eq!(
+            Some(Status::EvaluationFailed),
+            evaluate("<a/>", "$missing").err()
+        );
+
+        let mut document = ptr::null_mut();
+        assert_eq!(Status::NullArgument, unsafe {
+            sxd_document_parse(ptr::null(), &mut document)
+        });
+    }
+}
@@ -0,0 +1,513 @@
+//! [XML Catalogs][catalogs] that map public identifiers, system
+//! identifiers and URIs to other URIs, typically local copies.
+//!
+//! A [`Catalog`][] given to [`Context::set_catalog`][] is consulted by
+//! `resolve-uri()` and `document()`. A URI of the form
+//! `urn:publicid:...` is unwrapped and looked up as a public
+//! identifier; any other URI is looked up in the `uri` entries and
+//! then in the `system` entries.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::catalog::Catalog;
+//! use sxd_xpath::resolver::ResolveError;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let catalog = Catalog::load("file:///etc/xml/catalog", &|uri: &str| -> Result<_, ResolveError> {
+//!     assert_eq!("file:///etc/xml/catalog", uri);
+//!     Ok(parser::parse(
+//!         "<catalog xmlns='urn:oasis:names:tc:entity:xmlns:xml:catalog'>
+//!            <rewriteURI uriStartString='http://example.com/topics/' rewritePrefix='topics/'/>
+//!          </catalog>",
+//!     )?)
+//! })
+//! .expect("Unable to load catalog");
+//!
+//! let mut context = Context::new();
+//! context.set_catalog(catalog);
+//!
+//! let package = parser::parse("<map/>").expect("failed to parse XML");
+//! let document = package.as_document();
+//! let xpath = Factory::new()
+//!     .build("resolve-uri('http://example.com/topics/intro.xml')")
+//!     .expect("Could not compile XPath");
+//! let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//!
+//! assert_eq!("file:///etc/xml/topics/intro.xml", value.string());
+//! ```
+//!
+//! The `delegatePublic`, `delegateSystem` and `delegateURI` entries
+//! are not supported and are ignored, as are elements from other
+//! namespaces.
+//!
+//! [catalogs]: https://www.oasis-open.org/committees/download.php/14809/xml-catalogs.html
+//! [`Catalog`]: struct.Catalog.html
+//! [`Context::set_catalog`]: ../context/struct.Context.html#method.set_catalog
+
+use std::collections::HashSet;
+
+use snafu::Snafu;
+use sxd_document::dom;
+
+use crate::resolver::DocumentResolver;
+use crate::uri;
+use crate::XML_NAMESPACE_URI;
+
+const CATALOG_NAMESPACE_URI: &str = "urn:oasis:names:tc:entity:xmlns:xml:catalog";
+const PUBLIC_ID_URN: &str = "urn:publicid:";
+
+/// The errors that may occur while loading a catalog
+#[derive(Debug, Snafu, Clone, PartialEq, Hash)]
+pub enum Error {
+    #[snafu(display("unable to load the catalog {:?}: {}", uri, what))]
+    NotLoaded { uri: String, what: String },
+    #[snafu(display("{:?} is not a catalog", uri))]
+    NotACatalog { uri: String },
+    #[snafu(display("a {} entry of {:?} has no {} attribute", entry, uri, attribute))]
+    MissingAttribute {
+        uri: String,
+        entry: String,
+        attribute: &'static str,
+    },
+}
+
+/// How an entry matches an identifier.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Match {
+    /// The whole identifier, which is replaced by the target.
+    Exact,
+    /// The start of the identifier, which is replaced by the target.
+    Rewrite,
+    /// The end of the identifier, which is replaced by the target.
+    Suffix,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    how: Match,
+    pattern: String,
+    target: String,
+}
+
+impl Rule {
+    fn matches(&self, id: &str) -> bool {
+        match self.how {
+            Match::Exact => id == self.pattern,
+            Match::Rewrite => id.starts_with(&self.pattern),
+            Match::Suffix => id.ends_with(&self.pattern),
+        }
+    }
+}
+
+/// Finds the target of the identifier. An exact match is preferred,
+/// then the longest start, then the longest end.
+fn lookup(rules: &[Rule], id: &str) -> Option<String> {
+    let best = |how| {
+        rules
+            .iter()
+            .filter(|r| r.how == how && r.matches(id))
+            .max_by_key(|r| r.pattern.len())
+    };
+
+    if let Some(rule) = rules
+        .iter()
+        .find(|r| r.how == Match::Exact && r.matches(id))
+    {
+        return Some(rule.target.clone());
+    }
+    if let Some(rule) = best(Match::Rewrite) {
+        return Some(format!("{}{}", rule.target, &id[rule.pattern.len()..]));
+    }
+    best(Match::Suffix).map(|rule| rule.target.clone())
+}
+
+/// Collapses runs of whitespace in a public identifier.
+fn normalize_public_id(id: &str) -> String {
+    id.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Turns the rest of a `urn:publicid:` URN back into the public
+/// identifier, as described by [RFC 3151].
+///
+/// [RFC 3151]: https://tools.ietf.org/html/rfc3151
+fn unwrap_public_id_urn(urn: &str) -> String {
+    let mut id = String::new();
+    let mut rest = urn;
+    while let Some(c) = rest.chars().next() {
+        let (replacement, len) = match c {
+            '+' => (" ", 1),
+            ':' => ("//", 1),
+            ';' => ("::", 1),
+            '%' => match rest.get(1..3).map(str::to_ascii_uppercase).as_deref() {
+                Some("2B") => ("+", 3),
+                Some("3A") => (":", 3),
+                Some("2F") => ("/", 3),
+                Some("3B") => (";", 3),
+                Some("27") => ("'", 3),
+                Some("3F") => ("?", 3),
+                Some("23") => ("#", 3),
+                Some("25") => ("%", 3),
+                _ => ("%", 1),
+            },
+            _ => {
+                id.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+        id.push_str(replacement);
+        rest = &rest[len..];
+    }
+    normalize_public_id(&id)
+}
+
+/// The entries of a catalog and of the catalogs it refers to with
+/// `nextCatalog`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+    public: Vec<Rule>,
+    system: Vec<Rule>,
+    uri: Vec<Rule>,
+    next: Vec<Catalog>,
+}
+
+impl Catalog {
+    /// A catalog without any entries.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Loads the catalog at the URI with the resolver, followed by the
+    /// catalogs it refers to with `nextCatalog`. A next catalog that
+    /// cannot be loaded is skipped, as is one that was already loaded.
+    pub fn load<R>(uri: &str, resolver: &R) -> Result<Catalog, Error>
+    where
+        R: DocumentResolver + ?Sized,
+    {
+        let mut seen = HashSet::new();
+        Catalog::load_once(uri, resolver, &mut seen)
+    }
+
+    fn load_once<R>(uri: &str, resolver: &R, seen: &mut HashSet<String>) -> Result<Catalog, Error>
+    where
+        R: DocumentResolver + ?Sized,
+    {
+        seen.insert(uri.to_owned());
+        let package = resolver.resolve(uri).map_err(|e| Error::NotLoaded {
+            uri: uri.to_owned(),
+            what: e.to_string(),
+        })?;
+        let doc = package.as_document();
+
+        let root = doc
+            .root()
+            .children()
+            .into_iter()
+            .find_map(|c| c.element())
+            .filter(|e| is_catalog_element(*e, "catalog"))
+            .ok_or_else(|| Error::NotACatalog {
+                uri: uri.to_owned(),
+            })?;
+
+        let mut catalog = Catalog::new();
+        let mut next = Vec::new();
+        catalog.add_entries(root, uri, uri, &mut next)?;
+
+        for next_uri in next {
+            if seen.contains(&next_uri) {
+                continue;
+            }
+            if let Ok(next_catalog) = Catalog::load_once(&next_uri, resolver, seen) {
+                catalog.next.push(next_catalog);
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// Adds the entries that are children of the element, which are
+    /// relative to the base URI.
+    fn add_entries(
+        &mut self,
+        element: dom::Element<'_>,
+        base: &str,
+        catalog_uri: &str,
+        next: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        let absolute = |base: &str, reference: &str| {
+            uri::resolve(base, reference).unwrap_or_else(|| reference.to_owned())
+        };
+
+        for child in element.children().into_iter().filter_map(|c| c.element()) {
+            if child.name().namespace_uri() != Some(CATALOG_NAMESPACE_URI) {
+                continue;
+            }
+            let base = match child.attribute_value((XML_NAMESPACE_URI, "base")) {
+                Some(xml_base) => absolute(base, xml_base),
+                None => base.to_owned(),
+            };
+            let attribute = |attribute| {
+                child
+                    .attribute_value(attribute)
+                    .ok_or_else(|| Error::MissingAttribute {
+                        uri: catalog_uri.to_owned(),
+                        entry: child.name().local_part().to_owned(),
+                        attribute,
+                    })
+            };
+            let rule = |how, pattern: String, target| Rule {
+                how,
+                pattern,
+                target: absolute(&base, target),
+            };
+
+            match child.name().local_part() {
+                "group" => self.add_entries(child, &base, catalog_uri, next)?,
+                "public" => self.public.push(rule(
+                    Match::Exact,
+                    normalize_public_id(attribute("publicId")?),
+                    attribute("uri")?,
+                )),
+                "system" => self.system.push(rule(
+                    Match::Exact,
+                    attribute("systemId")?.to_owned(),
+                    attribute("uri")?,
+                )),
+                "rewriteSystem" => self.system.push(rule(
+                    Match::Rewrite,
+                    attribute("systemIdStartString")?.to_owned(),
+                    attribute("rewritePrefix")?,
+                )),
+                "systemSuffix" => self.system.push(rule(
+                    Match::Suffix,
+                    attribute("systemIdSuffix")?.to_owned(),
+                    attribute("uri")?,
+                )),
+                "uri" => self.uri.push(rule(
+                    Match::Exact,
+                    absolute(&base, attribute("name")?),
+                    attribute("uri")?,
+                )),
+                "rewriteURI" => self.uri.push(rule(
+                    Match::Rewrite,
+                    absolute(&base, attribute("uriStartString")?),
+                    attribute("rewritePrefix")?,
+                )),
+                "uriSuffix" => self.uri.push(rule(
+                    Match::Suffix,
+                    attribute("uriSuffix")?.to_owned(),
+                    attribute("uri")?,
+                )),
+                "nextCatalog" => next.push(absolute(&base, attribute("catalog")?)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The URI that the catalog maps the URI to, if any.
+    pub fn resolve(&self, uri: &str) -> Option<String> {
+        let urn_prefix = uri.get(..PUBLIC_ID_URN.len());
+        if urn_prefix.is_some_and(|p| p.eq_ignore_ascii_case(PUBLIC_ID_URN)) {
+            return self.resolve_public(&unwrap_public_id_urn(&uri[PUBLIC_ID_URN.len()..]));
+        }
+
+        lookup(&self.uri, uri)
+            .or_else(|| lookup(&self.system, uri))
+            .or_else(|| self.next.iter().find_map(|c| c.resolve(uri)))
+    }
+
+    /// The URI that the catalog maps the public identifier to, if any.
+    pub fn resolve_public(&self, public_id: &str) -> Option<String> {
+        let public_id = normalize_public_id(public_id);
+        lookup(&self.public, &public_id)
+            .or_else(|| self.next.iter().find_map(|c| c.resolve_public(&public_id)))
+    }
+}
+
+fn is_catalog_element(element: dom::Element<'_>, name: &str) -> bool {
+    let qname = element.name();
+    qname.namespace_uri() == Some(CATALOG_NAMESPACE_URI) && qname.local_part() == name
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use sxd_document::{parser, Package};
+
+    use super::*;
+    use crate::resolver::ResolveError;
+
+    /// Resolves the URIs to the catalogs, which are wrapped in a
+    /// `catalog` element.
+    fn catalogs(
+        files: &[(&'static str, &'static str)],
+    ) -> impl Fn(&str) -> Result<Package, ResolveError> {
+        let files: HashMap<_, _> = files.iter().cloned().collect();
+        move |uri: &str| {
+            let entries = files.get(uri).ok_or("no such catalog")?;
+            let xml = format!(
+                "<catalog xmlns='{}'>{}</catalog>",
+                CATALOG_NAMESPACE_URI, entries
+            );
+            Ok(parser::parse(&xml)?)
+        }
+    }
+
+    fn load(entries: &'static str) -> Catalog {
+        let files = catalogs(&[("file:///xml/catalog.xml", entries)]);
+        Catalog::load("file:///xml/catalog.xml", &files).expect("Unable to load catalog")
+    }
+
+    #[test]
+    fn uris_are_mapped_relative_to_the_catalog() {
+        let catalog = load("<uri name='http://example.com/a.xml' uri='local/a.xml'/>");
+        assert_eq!(
+            Some("file:///xml/local/a.xml".to_owned()),
+            catalog.resolve("http://example.com/a.xml")
+        );
+        assert_eq!(None, catalog.resolve("http://example.com/b.xml"));
+    }
+
+    #[test]
+    fn the_longest_rewrite_wins() {
+        let catalog = load(
+            "<rewriteURI uriStartString='http://example.com/' rewritePrefix='all/'/>
+             <rewriteURI uriStartString='http://example.com/docs/' rewritePrefix='docs/'/>",
+        );
+        assert_eq!(
+            Some("file:///xml/docs/a/b.xml".to_owned()),
+            catalog.resolve("http://example.com/docs/a/b.xml")
+        );
+        assert_eq!(
+            Some("file:///xml/all/c.xml".to_owned()),
+            catalog.resolve("http://example.com/c.xml")
+        );
+    }
+
+    #[test]
+    fn exact_matches_are_preferred_to_suffixes() {
+        let catalog = load(
+            "<uriSuffix uriSuffix='/a.xml' uri='suffix.xml'/>
+             <uri name='http://example.com/a.xml' uri='exact.xml'/>",
+        );
+        assert_eq!(
+            Some("file:///xml/exact.xml".to_owned()),
+            catalog.resolve("http://example.com/a.xml")
+        );
+        assert_eq!(
+            Some("file:///xml/suffix.xml".to_owned()),
+            catalog.resolve("http://example.org/a.xml")
+        );
+    }
+
+    #[test]
+    fn system_identifiers_are_used_for_uris() {
+        let catalog = load(
+            "<system systemId='http://example.com/dtd/topic.dtd' uri='dtd/topic.dtd'/>
+             <rewriteSystem systemIdStartString='http://example.com/mod/' rewritePrefix='mod/'/>",
+        );
+        assert_eq!(
+            Some("file:///xml/dtd/topic.dtd".to_owned()),
+            catalog.resolve("http://example.com/dtd/topic.dtd")
+        );
+        assert_eq!(
+            Some("file:///xml/mod/x.mod".to_owned()),
+            catalog.resolve("http://example.com/mod/x.mod")
+        );
+    }
+
+    #[test]
+    fn public_identifiers_are_found_through_urns() {
+        let catalog = load("<public publicId='-//OASIS//DTD  DITA Topic//EN' uri='topic.dtd'/>");
+        let expected = Some("file:///xml/topic.dtd".to_owned());
+
+        assert_eq!(
+            expected,
+            catalog.resolve_public("-//OASIS//DTD DITA Topic//EN")
+        );
+        assert_eq!(
+            expected,
+            catalog.resolve("urn:publicid:-:OASIS:DTD+DITA+Topic:EN")
+        );
+    }
+
+    #[test]
+    fn public_identifier_urns_are_unescaped() {
+        assert_eq!(
+            "ISO/IEC 10179:1996//DTD DSSSL Architecture//EN",
+            unwrap_public_id_urn("ISO%2FIEC+10179%3A1996:DTD+DSSSL+Architecture:EN")
+        );
+        assert_eq!("a::b", unwrap_public_id_urn("a;b"));
+    }
+
+    #[test]
+    fn groups_and_xml_base_change_the_base() {
+        let catalog = load(
+            "<group xml:base='http://mirror.example.com/'>
+               <uri name='http://example.com/a.xml' uri='a.xml'/>
+             </group>
+             <uri name='http://example.com/b.xml' uri='b.xml'/>",
+        );
+        assert_eq!(
+            Some("http://mirror.example.com/a.xml".to_owned()),
+            catalog.resolve("http://example.com/a.xml")
+        );
+        assert_eq!(
+            Some("file:///xml/b.xml".to_owned()),
+            catalog.resolve("http://example.com/b.xml")
+        );
+    }
+
+    #[test]
+    fn next_catalogs_are_consulted_afterwards() {
+        let files = catalogs(&[
+            (
+                "file:///xml/catalog.xml",
+                "<nextCatalog catalog='more.xml'/>
+                 <nextCatalog catalog='missing.xml'/>
+                 <nextCatalog catalog='catalog.xml'/>
+                 <uri name='http://example.com/a.xml' uri='first.xml'/>",
+            ),
+            (
+                "file:///xml/more.xml",
+                "<uri name='http://example.com/a.xml' uri='second.xml'/>
+                 <uri name='http://example.com/b.xml' uri='second.xml'/>",
+            ),
+        ]);
+        let catalog = Catalog::load("file:///xml/catalog.xml", &files).expect("Unable to load");
+
+        assert_eq!(
+            Some("file:///xml/first.xml".to_owned()),
+            catalog.resolve("http://example.com/a.xml")
+        );
+        assert_eq!(
+            Some("file:///xml/second.xml".to_owned()),
+            catalog.resolve("http://example.com/b.xml")
+        );
+    }
+
+    #[test]
+    fn other_documents_are_not_catalogs() {
+        let resolver = |_: &str| -> Result<_, ResolveError> { Ok(parser::parse("<catalog/>")?) };
+        assert_eq!(
+            Err(Error::NotACatalog {
+                uri: "file:///a.xml".to_owned()
+            }),
+            Catalog::load("file:///a.xml", &resolver)
+        );
+    }
+
+    #[test]
+    fn entries_need_their_attributes() {
+        let files = catalogs(&[("file:///xml/catalog.xml", "<uri name='a.xml'/>")]);
+        assert_eq!(
+            Err(Error::MissingAttribute {
+                uri: "file:///xml/catalog.xml".to_owned(),
+                entry: "uri".to_owned(),
+                attribute: "uri",
+            }),
+            Catalog::load("file:///xml/catalog.xml", &files)
+        );
+    }
+}
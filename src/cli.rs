@@ -0,0 +1,432 @@
+//! The parts of the `sxd-xpath` program that do not read files.
+//!
+//! The program evaluates one or more XPaths against each of the XML
+//! files it is given and writes the results as text, XML or JSON:
+//!
+//! ```text
+//! sxd-xpath -n a=urn:atom -e 'count(//a:entry)' -e '//a:entry/@id' feed.xml
+//! ```
+//!
+//! [`Options::parse`][] reads the arguments of the program and
+//! [`Query`][] evaluates the XPaths against one document.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::cli::{Options, Query};
+//!
+//! let arguments = ["-e", "//item/@id", "--format", "json", "shop.xml"];
+//! let options = Options::parse(arguments.iter().map(|a| a.to_string()))
+//!     .expect("Invalid arguments");
+//! assert_eq!(vec!["shop.xml"], options.files);
+//!
+//! let package = parser::parse("<shop><item id='a'/><item id='b'/></shop>")
+//!     .expect("failed to parse XML");
+//! let query = Query::new(&options).expect("Invalid XPath");
+//! let results = query.run(package.as_document()).expect("XPath evaluation failed");
+//!
+//! assert_eq!(
+//!     vec![r#"[{"type":"attribute","name":"id","value":"a"},{"type":"attribute","name":"id","value":"b"}]"#],
+//!     results
+//! );
+//! ```
+//!
+//! [`Options::parse`]: struct.Options.html#method.parse
+//! [`Query`]: struct.Query.html
+
+use std::str::FromStr;
+
+use snafu::Snafu;
+use sxd_document::dom;
+
+use crate::json;
+use crate::nodeset::Node;
+use crate::{Context, Factory, Item, Value, XPath};
+
+/// How the program is used, as shown by `--help`.
+pub const USAGE: &str = "\
+Usage: sxd-xpath [options] -e XPATH [FILE...]
+
+Evaluates each XPath against each file, or against standard input when
+no file is given or the file is `-`.
+
+Options:
+    -e, --expression XPATH     an XPath to evaluate; may be repeated
+    -n, --namespace PREFIX=URI binds a namespace prefix; may be repeated
+    -f, --format FORMAT        writes results as text, xml or json
+                               (default text)
+    -h, --help                 shows this help";
+
+/// A failure to understand the arguments or to evaluate an XPath.
+#[derive(Debug, Snafu, Clone, PartialEq)]
+pub enum Error {
+    #[snafu(display("unknown option {}", option))]
+    UnknownOption { option: String },
+    #[snafu(display("option {} needs a value", option))]
+    MissingValue { option: String },
+    #[snafu(display("unknown format {:?}; expected text, xml or json", format))]
+    UnknownFormat { format: String },
+    #[snafu(display("namespace binding {:?} should be PREFIX=URI", binding))]
+    MalformedNamespace { binding: String },
+    #[snafu(display("no XPath was given; use -e XPATH"))]
+    NoExpressions,
+    #[snafu(display("invalid XPath: {}", rendered))]
+    InvalidXPath { rendered: String },
+    #[snafu(display("unable to evaluate {}: {}", xpath, source))]
+    Evaluation {
+        xpath: String,
+        source: crate::ExecutionError,
+    },
+}
+
+/// How results are written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The string value of each node or item, one on each line
+    #[default]
+    Text,
+    /// Nodes written as XML, and the string value of anything else
+    Xml,
+    /// One JSON value for each XPath
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self, Error> {
+        match format {
+            "text" => Ok(Format::Text),
+            "xml" => Ok(Format::Xml),
+            "json" => Ok(Format::Json),
+            _ => UnknownFormat { format }.fail(),
+        }
+    }
+}
+
+/// The arguments of the program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    pub expressions: Vec<String>,
+    /// Prefixes and the namespace URIs they are bound to
+    pub namespaces: Vec<(String, String)>,
+    pub format: Format,
+    pub files: Vec<String>,
+    /// Only the usage should be shown
+    pub help: bool,
+}
+
+impl Options {
+    /// Reads the arguments, not including the name of the program.
+    /// Values may follow their option as the next argument or, for
+    /// long options, after an `=`. Every argument after `--` is a file.
+    pub fn parse<I>(arguments: I) -> Result<Options, Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options::default();
+        let mut arguments = arguments.into_iter();
+
+        while let Some(argument) = arguments.next() {
+            if argument == "--" {
+                options.files.extend(arguments.by_ref());
+                break;
+            }
+            if argument == "-" || !argument.starts_with('-') {
+                options.files.push(argument);
+                continue;
+            }
+
+            let (option, inline) = match argument.find('=') {
+                Some(i) if argument.starts_with("--") => {
+                    (&argument[..i], Some(argument[i + 1..].to_owned()))
+                }
+                _ => (&argument[..], None),
+            };
+
+            if option == "-h" || option == "--help" {
+                options.help = true;
+                continue;
+            }
+
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| arguments.next())
+                    .ok_or_else(|| Error::MissingValue {
+                        option: option.to_owned(),
+                    })
+            };
+
+            match option {
+                "-e" | "--expression" => options.expressions.push(value()?),
+                "-n" | "--namespace" => {
+                    let binding = value()?;
+                    match binding.find('=') {
+                        Some(i) if i > 0 => options
+                            .namespaces
+                            .push((binding[..i].to_owned(), binding[i + 1..].to_owned())),
+                        _ => return MalformedNamespace { binding }.fail(),
+                    }
+                }
+                "-f" | "--format" => options.format = value()?.parse()?,
+                _ => return UnknownOption { option }.fail(),
+            }
+        }
+
+        if options.expressions.is_empty() && !options.help {
+            return NoExpressions.fail();
+        }
+        Ok(options)
+    }
+}
+
+/// The XPaths of the options, compiled once to be evaluated against
+/// each document.
+pub struct Query {
+    xpaths: Vec<(String, XPath)>,
+    namespaces: Vec<(String, String)>,
+    format: Format,
+}
+
+impl Query {
+    pub fn new(options: &Options) -> Result<Query, Error> {
+        let factory = Factory::new();
+        let xpaths = options
+            .expressions
+            .iter()
+            .map(|xpath| match factory.build(xpath) {
+                Ok(compiled) => Ok((xpath.clone(), compiled)),
+                Err(error) => InvalidXPath {
+                    rendered: error.render(xpath),
+                }
+                .fail(),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Query {
+            xpaths,
+            namespaces: options.namespaces.clone(),
+            format: options.format,
+        })
+    }
+
+    /// The result of each XPath against the root of the document, in
+    /// the order the XPaths were given. A result is empty when there
+    /// is nothing to write.
+    pub fn run(&self, document: dom::Document<'_>) -> Result<Vec<String>, Error> {
+        let mut context = Context::new();
+        for (prefix, uri) in &self.namespaces {
+            context.set_namespace(prefix, uri);
+        }
+
+        self.xpaths
+            .iter()
+            .map(|(xpath, compiled)| {
+                let value = compiled
+                    .evaluate(&context, document.root())
+                    .map_err(|source| Error::Evaluation {
+                        xpath: xpath.clone(),
+                        source,
+                    })?;
+                Ok(write(self.format, &value))
+            })
+            .collect()
+    }
+}
+
+/// Writes the value in the format.
+pub fn write(format: Format, value: &Value<'_>) -> String {
+    match format {
+        Format::Json => json::render_value(value),
+        Format::Text | Format::Xml => {
+            let write_node: fn(Node<'_>) -> String = if format == Format::Xml {
+                |node: Node<'_>| node.to_xml()
+            } else {
+                |node: Node<'_>| node.string_value()
+            };
+            let lines: Vec<_> = match *value {
+                Value::Nodeset(ref nodes) => {
+                    nodes.document_order().into_iter().map(write_node).collect()
+                }
+                Value::Sequence(ref items) => items
+                    .iter()
+                    .map(|item| match *item {
+                        Item::Node(node) => write_node(node),
+                        ref item => item.string(),
+                    })
+                    .collect(),
+                ref value => vec![value.string()],
+            };
+            lines.join("\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+
+    fn options(arguments: &[&str]) -> Result<Options, Error> {
+        Options::parse(arguments.iter().map(|a| a.to_string()))
+    }
+
+    fn run(arguments: &[&str], xml: &str) -> Vec<String> {
+        let options = options(arguments).expect("Invalid arguments");
+        let package = parser::parse(xml).expect("failed to parse XML");
+        let query = Query::new(&options).expect("Invalid XPath");
+        query
+            .run(package.as_document())
+            .expect("XPath evaluation failed")
+    }
+
+    #[test]
+    fn options_are_read_in_either_form() {
+        let parsed = options(&[
+            "-e",
+            "a",
+            "--expression=b",
+            "-n",
+            "x=urn:x",
+            "--format",
+            "xml",
+            "one.xml",
+            "--",
+            "-two.xml",
+        ]);
+
+        assert_eq!(
+            Ok(Options {
+                expressions: vec!["a".into(), "b".into()],
+                namespaces: vec![("x".into(), "urn:x".into())],
+                format: Format::Xml,
+                files: vec!["one.xml".into(), "-two.xml".into()],
+                help: false,
+            }),
+            parsed
+        );
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        assert_eq!(Err(Error::NoExpressions), options(&["a.xml"]));
+        assert_eq!(
+            Err(Error::MissingValue {
+                option: "-e".into()
+            }),
+            options(&["-e"])
+        );
+        assert_eq!(
+            Err(Error::UnknownFormat {
+                format: "csv".into()
+            }),
+            options(&["-e", "a", "-f", "csv"])
+        );
+        assert_eq!(
+            Err(Error::MalformedNamespace {
+                binding: "urn:x".into()
+            }),
+            options(&["-e", "a", "-n", "urn:x"])
+        );
+        assert_eq!(
+            Err(Error::UnknownOption {
+                option: "--nope".into()
+            }),
+            options(&["-e", "a", "--nope=1"])
+        );
+        assert_eq!(Ok(true), options(&["--help"]).map(|o| o.help));
+    }
+
+    #[test]
+    fn each_xpath_gives_one_result() {
+        let xml = "<shop xmlns='urn:shop'><item>a &amp; b</item><item>c</item></shop>";
+        assert_eq!(
+            vec!["2", "a & b\nc", ""],
+            run(
+                &[
+                    "-n",
+                    "s=urn:shop",
+                    "-e",
+                    "count(//s:item)",
+                    "-e",
+                    "//s:item",
+                    "-e",
+                    "//item"
+                ],
+                xml
+            )
+        );
+    }
+
+    #[test]
+    fn nodes_are_written_as_xml() {
+        let xml = "<p:a xmlns:p='urn:p' id='1'><b>x &lt; y</b><!--c--></p:a>";
+        assert_eq!(
+            vec![
+                r#"<b xmlns:p="urn:p">x &lt; y</b>"#,
+                "id=\"1\"",
+                "<!--c-->",
+                r#"<p:a xmlns:p="urn:p" id="1"><b>x &lt; y</b><!--c--></p:a>"#,
+                "3",
+            ],
+            run(
+                &[
+                    "-f",
+                    "xml",
+                    "-n",
+                    "p=urn:p",
+                    "-e",
+                    "/p:a/b",
+                    "-e",
+                    "/p:a/@id",
+                    "-e",
+                    "//comment()",
+                    "-e",
+                    "/p:a",
+                    "-e",
+                    "1 + 2"
+                ],
+                xml
+            )
+        );
+    }
+
+    #[test]
+    fn values_are_written_as_json() {
+        assert_eq!(
+            vec![
+                r#"[{"type":"element","name":"a","value":"x"},{"type":"text","value":"x"}]"#,
+                r#"[1,"two",true]"#,
+                r#""Infinity""#,
+            ],
+            run(
+                &[
+                    "-f",
+                    "json",
+                    "-e",
+                    "/a | /a/text()",
+                    "-e",
+                    "(1, 'two', true())",
+                    "-e",
+                    "1 div 0"
+                ],
+                "<a>x</a>"
+            )
+        );
+    }
+
+    #[test]
+    fn failures_name_the_xpath() {
+        let invalid = Query::new(&options(&["-e", "1 +"]).expect("Invalid arguments"));
+        assert!(matches!(invalid, Err(Error::InvalidXPath { .. })));
+
+        let package = parser::parse("<a/>").expect("failed to parse XML");
+        let query =
+            Query::new(&options(&["-e", "$v"]).expect("Invalid arguments")).expect("Invalid XPath");
+        match query.run(package.as_document()) {
+            Err(Error::Evaluation { xpath, .. }) => assert_eq!("$v", xpath),
+            other => panic!("expected an evaluation error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Collations, which decide how strings are compared by the functions
+//! that accept a collation URI, such as `compare`.
+//!
+//! Collations are looked up by URI in the [`Context`][], so
+//! locale-aware collations can be registered alongside the predefined
+//! Unicode codepoint and ASCII case-insensitive collations. The
+//! context's default collation is used by the comparison operators
+//! and by functions called without a collation argument.
+//!
+//! [`Context`]: ../context/struct.Context.html
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::context;
+
+/// The URI of the Unicode codepoint collation, which compares strings
+/// character by character.
+pub const CODEPOINT_COLLATION_URI: &str =
+    "http://www.w3.org/2005/xpath-functions/collation/codepoint";
+
+/// The URI of the collation that compares strings as if their ASCII
+/// letters were lower case.
+pub const HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI: &str =
+    "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive";
+
+/// Types that can be used as collations.
+///
+/// Only `compare` must be implemented. The other methods are derived
+/// from it by trying each part of the string, so collations that can
+/// match substrings directly should override them.
+pub trait Collation {
+    /// Decides the order of two strings.
+    fn compare(&self, left: &str, right: &str) -> Ordering;
+
+    /// Whether the strings are equal under this collation.
+    fn equal(&self, left: &str, right: &str) -> bool {
+        self.compare(left, right) == Ordering::Equal
+    }
+
+    /// Finds the first part of `haystack` that is equal to `needle`,
+    /// returning its byte range.
+    fn find(&self, haystack: &str, needle: &str) -> Option<Range<usize>> {
+        let boundaries = char_boundaries(haystack);
+        boundaries.iter().find_map(|&start| {
+            boundaries
+                .iter()
+                .filter(|&&end| end >= start)
+                .find(|&&end| self.equal(&haystack[start..end], needle))
+                .map(|&end| start..end)
+        })
+    }
+
+    /// Whether some prefix of `haystack` is equal to `needle`.
+    fn starts_with(&self, haystack: &str, needle: &str) -> bool {
+        char_boundaries(haystack)
+            .into_iter()
+            .any(|end| self.equal(&haystack[..end], needle))
+    }
+
+    /// Whether some suffix of `haystack` is equal to `needle`.
+    fn ends_with(&self, haystack: &str, needle: &str) -> bool {
+        char_boundaries(haystack)
+            .into_iter()
+            .any(|start| self.equal(&haystack[start..], needle))
+    }
+}
+
+/// The byte offsets between the characters of the string, including
+/// both ends.
+fn char_boundaries(s: &str) -> Vec<usize> {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .collect()
+}
+
+/// Compares strings by the Unicode codepoints of their characters.
+#[derive(Debug, Copy, Clone)]
+pub struct Codepoint;
+
+impl Collation for Codepoint {
+    fn compare(&self, left: &str, right: &str) -> Ordering {
+        left.cmp(right)
+    }
+
+    fn equal(&self, left: &str, right: &str) -> bool {
+        left == right
+    }
+
+    fn find(&self, haystack: &str, needle: &str) -> Option<Range<usize>> {
+        haystack.find(needle).map(|i| i..i + needle.len())
+    }
+
+    fn starts_with(&self, haystack: &str, needle: &str) -> bool {
+        haystack.starts_with(needle)
+    }
+
+    fn ends_with(&self, haystack: &str, needle: &str) -> bool {
+        haystack.ends_with(needle)
+    }
+}
+
+/// Compares strings by codepoint after converting ASCII letters to
+/// lower case. Other characters keep their case.
+#[derive(Debug, Copy, Clone)]
+pub struct HtmlAsciiCaseInsensitive;
+
+impl Collation for HtmlAsciiCaseInsensitive {
+    fn compare(&self, left: &str, right: &str) -> Ordering {
+        let left = left.bytes().map(|b| b.to_ascii_lowercase());
+        let right = right.bytes().map(|b| b.to_ascii_lowercase());
+        left.cmp(right)
+    }
+
+    fn equal(&self, left: &str, right: &str) -> bool {
+        left.eq_ignore_ascii_case(right)
+    }
+
+    // Changing the case of ASCII letters keeps every byte offset
+    fn find(&self, haystack: &str, needle: &str) -> Option<Range<usize>> {
+        let haystack = haystack.to_ascii_lowercase();
+        let needle = needle.to_ascii_lowercase();
+        haystack.find(&needle).map(|i| i..i + needle.len())
+    }
+
+    fn starts_with(&self, haystack: &str, needle: &str) -> bool {
+        haystack
+            .get(..needle.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(needle))
+    }
+
+    fn ends_with(&self, haystack: &str, needle: &str) -> bool {
+        haystack
+            .len()
+            .checked_sub(needle.len())
+            .and_then(|start| haystack.get(start..))
+            .is_some_and(|suffix| suffix.eq_ignore_ascii_case(needle))
+    }
+}
+
+/// Registers the collations that every context provides.
+pub fn register_core_collations(context: &mut context::Context<'_>) {
+    context.set_collation(CODEPOINT_COLLATION_URI, Codepoint);
+    context.set_collation(
+        HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI,
+        HtmlAsciiCaseInsensitive,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codepoint_collation_orders_by_character() {
+        assert_eq!(Ordering::Less, Codepoint.compare("B", "a"));
+        assert_eq!(Ordering::Greater, Codepoint.compare("\u{e9}", "f"));
+        assert_eq!(Ordering::Equal, Codepoint.compare("a", "a"));
+    }
+
+    #[test]
+    fn case_insensitive_collation_ignores_only_ascii_case() {
+        let c = HtmlAsciiCaseInsensitive;
+        assert_eq!(Ordering::Less, c.compare("apple", "BANANA"));
+        assert!(c.equal("Hello", "hELLO"));
+        assert!(!c.equal("\u{c9}", "\u{e9}"));
+
+        assert_eq!(Some(2..5), c.find("a-BcD-e", "bcd"));
+        assert!(c.starts_with("Straße", "STR"));
+        assert!(!c.ends_with("Straße", "SSE"));
+        assert!(c.ends_with("HTML", "ml"));
+    }
+
+    #[test]
+    fn derived_methods_try_each_part_of_the_string() {
+        // Treats runs of spaces as a single space
+        struct SpaceInsensitive;
+
+        impl Collation for SpaceInsensitive {
+            fn compare(&self, left: &str, right: &str) -> Ordering {
+                fn words(s: &str) -> Vec<&str> {
+                    s.split(' ').filter(|w| !w.is_empty()).collect()
+                }
+                words(left).cmp(&words(right))
+            }
+        }
+
+        let c = SpaceInsensitive;
+        assert_eq!(Some(1..8), c.find("xa   b c ", "a b c"));
+        assert!(c.starts_with("  a  b", "a b"));
+        assert!(c.ends_with("x a   b", " a b"));
+        assert_eq!(None, c.find("abc", "d"));
+    }
+}
@@ -0,0 +1,335 @@
+//! The general comparisons `=`, `!=`, `<`, `<=`, `>` and `>=`, for
+//! values that were computed outside of an expression.
+//!
+//! These are the functions the operators themselves use. A nodeset is
+//! compared existentially: the comparison is true when it is true for
+//! the string value of at least one of its nodes. So `//price > 10` is
+//! true if any price is over 10, and `//price != 10` is true if any
+//! price is not 10; they are not the opposite of `//price <= 10` and
+//! `//price = 10`. Sequences are compared item by item in the same
+//! way, as in XPath 2.0.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::{compare, evaluate_xpath, Value};
+//!
+//! let package = parser::parse("<r><price>5</price><price>20</price></r>").expect("failed to parse XML");
+//! let document = package.as_document();
+//! let prices = evaluate_xpath(&document, "//price").expect("XPath evaluation failed");
+//!
+//! assert!(compare::greater_than(&prices, &Value::Number(10.0)));
+//! assert!(compare::less_than(&prices, &Value::Number(10.0)));
+//! assert!(compare::equal(&prices, &Value::String("20".into())));
+//! assert!(compare::not_equal(&prices, &Value::String("20".into())));
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::collation::Collation;
+use crate::conv;
+use crate::datetime;
+use crate::expression::temporal_operands;
+use crate::nodeset::Nodeset;
+use crate::Value;
+
+/// `left = right`, comparing strings by codepoint.
+pub fn equal(left: &Value<'_>, right: &Value<'_>) -> bool {
+    equality(left, right, true, None)
+}
+
+/// `left != right`, comparing strings by codepoint.
+pub fn not_equal(left: &Value<'_>, right: &Value<'_>) -> bool {
+    equality(left, right, false, None)
+}
+
+/// `left = right`, comparing strings with the collation, as when it is
+/// the default collation of the context.
+pub fn equal_with(left: &Value<'_>, right: &Value<'_>, collation: &dyn Collation) -> bool {
+    equality(left, right, true, Some(collation))
+}
+
+/// `left != right`, comparing strings with the collation.
+pub fn not_equal_with(left: &Value<'_>, right: &Value<'_>, collation: &dyn Collation) -> bool {
+    equality(left, right, false, Some(collation))
+}
+
+/// `left < right`
+pub fn less_than(left: &Value<'_>, right: &Value<'_>) -> bool {
+    relation(left, right, |l, r| l < r)
+}
+
+/// `left <= right`
+pub fn less_than_or_equal(left: &Value<'_>, right: &Value<'_>) -> bool {
+    relation(left, right, |l, r| l <= r)
+}
+
+/// `left > right`
+pub fn greater_than(left: &Value<'_>, right: &Value<'_>) -> bool {
+    relation(left, right, |l, r| l > r)
+}
+
+/// `left >= right`
+pub fn greater_than_or_equal(left: &Value<'_>, right: &Value<'_>) -> bool {
+    relation(left, right, |l, r| l >= r)
+}
+
+/// Compares every pair of items when either value is a sequence, as
+/// the general comparisons of XPath 2.0 do.
+fn compare_items<'d, F>(left: &Value<'d>, right: &Value<'d>, compare: F) -> Option<bool>
+where
+    F: Fn(&Value<'d>, &Value<'d>) -> bool,
+{
+    // Items such as dates are sequences of one item, which are left to
+    // the caller
+    let is_single_item = |v: &Value<'_>| match *v {
+        Value::Sequence(ref items) => items.len() == 1,
+        _ => true,
+    };
+    if is_single_item(left) && is_single_item(right) {
+        return None;
+    }
+
+    match (left, right) {
+        (Value::Sequence(_), _) | (_, Value::Sequence(_)) => {
+            let right: Vec<Value<'_>> = right.items().into_iter().map(Value::from).collect();
+            let v = left
+                .items()
+                .into_iter()
+                .map(Value::from)
+                .any(|l| right.iter().any(|r| compare(&l, r)));
+            Some(v)
+        }
+        _ => None,
+    }
+}
+
+fn compare_temporal(left: &Value<'_>, right: &Value<'_>) -> Option<Option<Ordering>> {
+    temporal_operands(left, right).map(|(l, r)| datetime::compare(&l, &r))
+}
+
+/// `=` when `equal` is true and `!=` otherwise. Strings are compared
+/// by codepoint unless a collation is given.
+pub(crate) fn equality(
+    left_val: &Value<'_>,
+    right_val: &Value<'_>,
+    equal: bool,
+    collation: Option<&dyn Collation>,
+) -> bool {
+    fn str_vals(nodes: &Nodeset<'_>) -> HashSet<String> {
+        nodes.iter().map(|n| n.string_value()).collect()
+    }
+
+    fn num_vals(nodes: &Nodeset<'_>) -> Vec<f64> {
+        // f64 isn't hashable...
+        nodes
+            .iter()
+            .map(|n| conv::string_to_number(&n.string_value()))
+            .collect()
+    }
+
+    fn strings(value: &Value<'_>) -> Option<Vec<String>> {
+        match *value {
+            Value::Nodeset(ref nodes) => Some(nodes.iter().map(|n| n.string_value()).collect()),
+            Value::String(ref s) => Some(vec![s.clone()]),
+            _ => None,
+        }
+    }
+
+    if let Some(v) = compare_items(left_val, right_val, |l, r| equality(l, r, equal, collation)) {
+        return v;
+    }
+    if let Some(ordering) = compare_temporal(left_val, right_val) {
+        return (ordering == Some(Ordering::Equal)) == equal;
+    }
+
+    if let Some(collation) = collation {
+        // Only comparisons between strings use the collation
+        if let (Some(left), Some(right)) = (strings(left_val), strings(right_val)) {
+            return left
+                .iter()
+                .any(|l| right.iter().any(|r| collation.equal(l, r) == equal));
+        }
+    }
+
+    match (left_val, right_val) {
+        (Value::Nodeset(left_nodes), Value::Nodeset(right_nodes)) => {
+            let left_strings = str_vals(left_nodes);
+            let right_strings = str_vals(right_nodes);
+            if equal {
+                !left_strings.is_disjoint(&right_strings)
+            } else {
+                // Some pair differs unless both hold the same single string
+                let same = left_strings.len() == 1 && left_strings == right_strings;
+                !left_strings.is_empty() && !right_strings.is_empty() && !same
+            }
+        }
+        (&Value::Nodeset(ref nodes), &Value::Number(val))
+        | (&Value::Number(val), &Value::Nodeset(ref nodes)) => {
+            num_vals(nodes).iter().any(|&n| (n == val) == equal)
+        }
+        (&Value::Nodeset(ref nodes), &Value::String(ref val))
+        | (&Value::String(ref val), &Value::Nodeset(ref nodes)) => {
+            if equal {
+                str_vals(nodes).contains(val)
+            } else {
+                str_vals(nodes).iter().any(|s| s != val)
+            }
+        }
+        (&Value::Boolean(_), _) | (_, &Value::Boolean(_)) => {
+            (left_val.boolean() == right_val.boolean()) == equal
+        }
+        (&Value::Number(_), _) | (_, &Value::Number(_)) => {
+            (left_val.number() == right_val.number()) == equal
+        }
+        _ => (left_val.string() == right_val.string()) == equal,
+    }
+}
+
+/// `<`, `<=`, `>` or `>=`, depending on the operation. Both operands
+/// are compared as numbers.
+pub(crate) fn relation(
+    left_val: &Value<'_>,
+    right_val: &Value<'_>,
+    op: fn(f64, f64) -> bool,
+) -> bool {
+    fn num_vals(nodes: &Nodeset<'_>) -> Vec<f64> {
+        nodes
+            .iter()
+            .map(|n| conv::string_to_number(&n.string_value()))
+            .collect()
+    }
+
+    let relate = |l: &Value<'_>, r: &Value<'_>| match compare_temporal(l, r) {
+        // The ordering is compared as a number with zero
+        Some(ordering) => ordering.is_some_and(|o| op(f64::from(o as i8), 0.0)),
+        None => match (l, r) {
+            (&Value::Boolean(_), _) | (_, &Value::Boolean(_)) => op(
+                conv::boolean_to_number(l.boolean()),
+                conv::boolean_to_number(r.boolean()),
+            ),
+            (Value::Nodeset(left_nodes), Value::Nodeset(right_nodes)) => {
+                let right = num_vals(right_nodes);
+                num_vals(left_nodes)
+                    .into_iter()
+                    .any(|l| right.iter().any(|&r| op(l, r)))
+            }
+            (Value::Nodeset(nodes), _) => {
+                let r = r.number();
+                num_vals(nodes).into_iter().any(|l| op(l, r))
+            }
+            (_, Value::Nodeset(nodes)) => {
+                let l = l.number();
+                num_vals(nodes).into_iter().any(|r| op(l, r))
+            }
+            _ => op(l.number(), r.number()),
+        },
+    };
+
+    compare_items(left_val, right_val, relate).unwrap_or_else(|| relate(left_val, right_val))
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::dom::Document;
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::collation::HtmlAsciiCaseInsensitive;
+    use crate::{evaluate_xpath, Item};
+
+    fn nodes<'d>(doc: &'d Document<'d>, xpath: &str) -> Value<'d> {
+        evaluate_xpath(doc, xpath).expect("XPath evaluation failed")
+    }
+
+    fn number(n: f64) -> Value<'static> {
+        Value::Number(n)
+    }
+
+    fn string(s: &str) -> Value<'static> {
+        Value::String(s.to_owned())
+    }
+
+    #[test]
+    fn nodesets_are_compared_existentially() {
+        let package =
+            parser::parse("<r><a>1</a><a>2</a><b>2</b></r>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let a = nodes(&doc, "//a");
+        let b = nodes(&doc, "//b");
+
+        assert!(equal(&a, &number(1.0)));
+        assert!(equal(&a, &number(2.0)));
+        assert!(not_equal(&a, &number(1.0)));
+        assert!(!not_equal(&b, &number(2.0)));
+
+        assert!(equal(&a, &b));
+        assert!(not_equal(&a, &b));
+        assert!(!not_equal(&b, &b));
+
+        assert!(less_than(&a, &number(2.0)));
+        assert!(greater_than(&a, &number(1.0)));
+        assert!(!greater_than(&a, &number(2.0)));
+        assert!(less_than(&a, &b));
+        assert!(!greater_than(&a, &b));
+        assert!(less_than_or_equal(&number(2.0), &a));
+    }
+
+    #[test]
+    fn empty_nodesets_are_never_equal_or_unequal() {
+        let package = parser::parse("<r/>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let none = nodes(&doc, "//missing");
+
+        assert!(!equal(&none, &string("")));
+        assert!(!not_equal(&none, &string("")));
+        assert!(!less_than(&none, &number(1.0)));
+        assert!(!greater_than_or_equal(&none, &number(1.0)));
+    }
+
+    #[test]
+    fn nodesets_compared_with_booleans_use_their_boolean_value() {
+        let package = parser::parse("<r><a>0</a></r>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let a = nodes(&doc, "//a");
+
+        assert!(equal(&a, &Value::Boolean(true)));
+        assert!(greater_than(&a, &Value::Boolean(false)));
+        assert!(!greater_than(&a, &Value::Boolean(true)));
+    }
+
+    #[test]
+    fn atomic_values_are_converted_before_comparing() {
+        assert!(equal(&Value::Boolean(true), &string("x")));
+        assert!(equal(&number(1.0), &string(" 1 ")));
+        assert!(not_equal(&number(f64::NAN), &number(f64::NAN)));
+        assert!(!equal(&number(f64::NAN), &number(f64::NAN)));
+        assert!(less_than(&string("1"), &string("2")));
+        assert!(!less_than(&string("a"), &string("b")));
+    }
+
+    #[test]
+    fn sequences_are_compared_item_by_item() {
+        let items = Value::Sequence(vec![Item::Number(1.0), Item::Number(3.0)]);
+
+        assert!(equal(&items, &number(3.0)));
+        assert!(not_equal(&items, &number(3.0)));
+        assert!(greater_than(&items, &number(2.0)));
+        assert!(!greater_than(&items, &number(3.0)));
+    }
+
+    #[test]
+    fn strings_can_be_compared_with_a_collation() {
+        assert!(equal_with(
+            &string("ABC"),
+            &string("abc"),
+            &HtmlAsciiCaseInsensitive
+        ));
+        assert!(!not_equal_with(
+            &string("ABC"),
+            &string("abc"),
+            &HtmlAsciiCaseInsensitive
+        ));
+        assert!(!equal(&string("ABC"), &string("abc")));
+    }
+}
@@ -0,0 +1,443 @@
+//! Suggests what can be written at a point of an XPath.
+//!
+//! [`completions`][] looks at the name being written at the cursor of
+//! an XPath that is still being written, and suggests the axes,
+//! functions and variables that can be written there. When a document
+//! is given, it also suggests the names of the elements and attributes
+//! that the step being written could select.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::completion::{self, Kind};
+//! use sxd_xpath::Context;
+//!
+//! let package = parser::parse("<shop><item price='1'/><owner/></shop>")
+//!     .expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let xpath = "/shop/it";
+//! let context = Context::new();
+//! let completions = completion::completions(xpath, xpath.len(), &context, Some(&document));
+//!
+//! assert_eq!(1, completions.len());
+//! assert_eq!("item", completions[0].text);
+//! assert_eq!(Kind::Element, completions[0].kind);
+//! assert_eq!(6..8, completions[0].span.range());
+//! ```
+//!
+//! [`completions`]: fn.completions.html
+
+use std::ops::Range;
+use sxd_document::dom::Document;
+use sxd_document::XmlChar;
+
+use crate::nodeset::Node;
+use crate::token::Token;
+use crate::tokenizer::{self, Span, Tokenizer};
+use crate::{Context, Factory, Item, Value};
+
+/// What a completion names.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    /// An axis, written with the `::` that follows it
+    Axis,
+    Function,
+    /// A variable, written with its `$`
+    Variable,
+    Element,
+    Attribute,
+}
+
+/// Something that can be written at the cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub text: String,
+    pub kind: Kind,
+    /// The part of the XPath that the text replaces, which is the name
+    /// being written at the cursor
+    pub span: Span,
+}
+
+/// The completions of the name being written at the cursor, which is an
+/// offset in bytes into the XPath. The completions start with what has
+/// been written of the name before the cursor, and are sorted by kind
+/// and then by text.
+///
+/// Names in a namespace are only suggested when the context has a
+/// prefix for the namespace. Element and attribute names are found by
+/// evaluating the path that the step being written is part of, with a
+/// relative path starting at the root of the document.
+pub fn completions<'d>(
+    xpath: &str,
+    cursor: usize,
+    context: &Context<'d>,
+    document: Option<&Document<'d>>,
+) -> Vec<Completion> {
+    if cursor > xpath.len() || !xpath.is_char_boundary(cursor) {
+        return Vec::new();
+    }
+
+    let is_name_char = |c: char| c.is_name_char() || c == ':';
+    let start = xpath[..cursor]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !is_name_char(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = xpath[cursor..]
+        .find(|c| !is_name_char(c))
+        .map_or(xpath.len(), |i| cursor + i);
+    let written = &xpath[start..cursor];
+
+    let mut candidates = Vec::new();
+
+    if let Some(separator) = written.find("::") {
+        let axis = &written[..separator];
+        if let Some(document) = document {
+            let kind = if axis == "attribute" {
+                Kind::Attribute
+            } else {
+                Kind::Element
+            };
+            let step = format!("{}::*", axis);
+            candidates.extend(names(xpath, start, &step, kind, context, document));
+        }
+        return matching(candidates, xpath, start + separator + 2, cursor, end);
+    }
+
+    match xpath[..start].chars().next_back() {
+        Some('$') => {
+            if let Some(tokens) = tokens(&xpath[..start - 1]) {
+                candidates.extend(variables(context, &tokens));
+            }
+            return matching(candidates, xpath, start - 1, cursor, end);
+        }
+        Some('@') => {
+            if let Some(document) = document {
+                let attributes = names(xpath, start - 1, "@*", Kind::Attribute, context, document);
+                candidates.extend(attributes);
+            }
+            return matching(candidates, xpath, start, cursor, end);
+        }
+        _ => {}
+    }
+
+    let tokens = match tokens(&xpath[..start]) {
+        Some(tokens) => tokens,
+        None => return Vec::new(),
+    };
+
+    let (steps, functions) = match tokens.last().map(|(token, _)| token) {
+        Some(Token::Arrow) => (false, true),
+        Some(Token::Slash) | Some(Token::DoubleSlash) => (true, false),
+        Some(token) if !token.precedes_expression() && !token.is_operator() => (false, false),
+        _ => (true, true),
+    };
+
+    if steps {
+        let axes = tokenizer::axis_names().map(|axis| (Kind::Axis, format!("{}::", axis)));
+        candidates.extend(axes);
+        if let Some(document) = document {
+            candidates.extend(names(xpath, start, "*", Kind::Element, context, document));
+        }
+    }
+    if functions {
+        let names = context.function_names().filter_map(|name| {
+            written_name(context, name.namespace_uri.as_deref(), &name.local_part)
+        });
+        candidates.extend(names.map(|name| (Kind::Function, name)));
+    }
+
+    matching(candidates, xpath, start, cursor, end)
+}
+
+/// The tokens of the XPath, or nothing if it ends in, or has, text that
+/// cannot be made into a token, such as an unterminated string.
+fn tokens(xpath: &str) -> Option<Vec<(Token, Span)>> {
+    Tokenizer::new(xpath)
+        .with_spans()
+        .map(|(token, span)| token.ok().map(|token| (token, span)))
+        .collect()
+}
+
+/// The candidates that start with what has been written from `start`
+/// to the cursor, replacing the text up to `end`.
+fn matching(
+    mut candidates: Vec<(Kind, String)>,
+    xpath: &str,
+    start: usize,
+    cursor: usize,
+    end: usize,
+) -> Vec<Completion> {
+    let written = &xpath[start..cursor];
+    candidates.retain(|(_, text)| text.starts_with(written));
+    candidates.sort();
+    candidates.dedup();
+
+    let span = Span {
+        offset: start,
+        length: end - start,
+    };
+    candidates
+        .into_iter()
+        .map(|(kind, text)| Completion { text, kind, span })
+        .collect()
+}
+
+/// The variables that the context sets, and that the XPath binds with
+/// `for`, `let`, `some` and `every` before the cursor.
+fn variables(context: &Context<'_>, tokens: &[(Token, Span)]) -> Vec<(Kind, String)> {
+    let set = context
+        .variable_names()
+        .filter_map(|name| written_name(context, name.namespace_uri.as_deref(), &name.local_part));
+
+    let bound = tokens
+        .windows(2)
+        .filter_map(|pair| match (&pair[0].0, &pair[1].0) {
+            (Token::Variable(name), Token::In) | (Token::Variable(name), Token::Assign) => {
+                Some(match name.prefix {
+                    Some(ref prefix) => format!("{}:{}", prefix, name.local_part),
+                    None => name.local_part.clone(),
+                })
+            }
+            _ => None,
+        });
+
+    set.chain(bound)
+        .map(|name| (Kind::Variable, format!("${}", name)))
+        .collect()
+}
+
+/// The brackets that the step being written is in.
+struct Frame {
+    /// Where the path being written in the brackets starts
+    path_start: usize,
+    /// For a predicate, the part of the XPath that it filters
+    filtered: Option<Range<usize>>,
+}
+
+/// The names of the nodes that could be selected by writing `step` in
+/// place of the step that starts at `step_start`.
+fn names<'d>(
+    xpath: &str,
+    step_start: usize,
+    step: &str,
+    kind: Kind,
+    context: &Context<'d>,
+    document: &Document<'d>,
+) -> Vec<(Kind, String)> {
+    let tokens = match tokens(&xpath[..step_start]) {
+        Some(tokens) => tokens,
+        None => return Vec::new(),
+    };
+
+    let mut frames = vec![Frame {
+        path_start: 0,
+        filtered: None,
+    }];
+    let mut previous = None;
+    for (token, span) in &tokens {
+        let path_start = frames.last().map_or(0, |frame| frame.path_start);
+        match *token {
+            Token::LeftBracket if previous.is_some_and(ends_operand) => frames.push(Frame {
+                path_start: span.range().end,
+                filtered: Some(path_start..span.offset),
+            }),
+            Token::LeftParen | Token::LeftBracket | Token::LeftBrace => frames.push(Frame {
+                path_start: span.range().end,
+                filtered: None,
+            }),
+            Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                frames.pop();
+                if frames.is_empty() {
+                    return Vec::new();
+                }
+            }
+            Token::Slash | Token::DoubleSlash => {}
+            ref token if token.precedes_expression() || token.is_operator() => {
+                if let Some(frame) = frames.last_mut() {
+                    frame.path_start = span.range().end;
+                }
+            }
+            _ => {}
+        }
+        previous = Some(token);
+    }
+
+    let mut nodes = vec![Node::from(document.root())];
+    for frame in &frames {
+        if let Some(ref filtered) = frame.filtered {
+            nodes = select(&xpath[filtered.clone()], &nodes, context);
+        }
+    }
+
+    let path_start = frames.last().map_or(0, |frame| frame.path_start);
+    let path = format!("{}{}", &xpath[path_start..step_start], step);
+    select(&path, &nodes, context)
+        .into_iter()
+        .filter_map(|node| node.expanded_name())
+        .filter_map(|name| written_name(context, name.namespace_uri(), name.local_part()))
+        .map(|name| (kind, name))
+        .collect()
+}
+
+/// Whether a `[` after the token is a predicate rather than an array.
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        *token,
+        Token::NameTest(_)
+            | Token::NodeTest(_)
+            | Token::CurrentNode
+            | Token::ParentNode
+            | Token::Literal(_)
+            | Token::Number(_)
+            | Token::Variable(_)
+            | Token::RightParen
+            | Token::RightBracket
+            | Token::RightBrace
+    )
+}
+
+/// The nodes that the XPath selects from each of the nodes.
+fn select<'d>(xpath: &str, nodes: &[Node<'d>], context: &Context<'d>) -> Vec<Node<'d>> {
+    let built = match Factory::new().build(xpath) {
+        Ok(built) => built,
+        Err(_) => return Vec::new(),
+    };
+
+    nodes
+        .iter()
+        .flat_map(|&node| match built.evaluate(context, node) {
+            Ok(Value::Nodeset(nodes)) => nodes.document_order(),
+            Ok(Value::Sequence(items)) => items
+                .into_iter()
+                .filter_map(|item| match item {
+                    Item::Node(node) => Some(node),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// How the name is written with the prefixes of the context, if it can
+/// be.
+fn written_name(
+    context: &Context<'_>,
+    namespace_uri: Option<&str>,
+    local_part: &str,
+) -> Option<String> {
+    match namespace_uri {
+        None => Some(local_part.into()),
+        Some(uri) => context
+            .prefix_for(uri)
+            .map(|prefix| format!("{}:{}", prefix, local_part)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::{parser, Package};
+
+    use super::*;
+
+    fn package() -> Package {
+        parser::parse(
+            "<shop xmlns:x='urn:x'>\
+             <item price='1' x:code='a'><name/><note/></item>\
+             <owner since='2000'/><x:extra/>\
+             </shop>",
+        )
+        .expect("failed to parse XML")
+    }
+
+    fn texts(xpath: &str, context: &Context<'_>, document: Option<&Document<'_>>) -> Vec<String> {
+        let cursor = xpath.find('|').expect("The cursor is marked");
+        let xpath = xpath.replacen('|', "", 1);
+        completions(&xpath, cursor, context, document)
+            .into_iter()
+            .map(|completion| completion.text)
+            .collect()
+    }
+
+    #[test]
+    fn element_names_are_those_the_step_could_select() {
+        let package = package();
+        let document = package.as_document();
+        let context = Context::new();
+
+        assert_eq!(
+            vec!["namespace::", "name", "note"],
+            texts("/shop/item/n|", &context, Some(&document))
+        );
+        assert_eq!(
+            vec!["item"],
+            texts("count(/shop/i|", &context, Some(&document))
+        );
+        assert_eq!(vec!["shop"], texts("sh|", &context, Some(&document)));
+        assert_eq!(vec!["note"], texts("//no|te", &context, Some(&document)));
+    }
+
+    #[test]
+    fn attribute_names_are_those_of_the_selected_elements() {
+        let package = package();
+        let document = package.as_document();
+        let mut context = Context::new();
+
+        assert_eq!(
+            vec!["price", "since"],
+            texts("/shop/*/@|", &context, Some(&document))
+        );
+        assert_eq!(
+            vec!["price"],
+            texts("/shop/item[@p|", &context, Some(&document))
+        );
+        assert_eq!(
+            vec!["since"],
+            texts("/shop/owner/attribute::|", &context, Some(&document))
+        );
+
+        context.set_namespace("y", "urn:x");
+        assert_eq!(
+            vec!["price", "y:code"],
+            texts("//item[name][@|", &context, Some(&document))
+        );
+    }
+
+    #[test]
+    fn axes_and_functions_start_expressions() {
+        let context = Context::new();
+
+        assert_eq!(vec!["floor"], texts("1 + fl|", &context, None));
+        assert_eq!(vec!["child::"], texts("(ch|", &context, None));
+        assert_eq!(
+            vec!["compare", "concat", "contains", "count"],
+            texts("(co|", &context, None)
+        );
+        assert_eq!(vec!["child::"], texts("a/c|", &context, None));
+        assert_eq!(vec!["count"], texts("a => cou|", &context, None));
+        assert!(texts("a c|", &context, None).is_empty());
+    }
+
+    #[test]
+    fn variables_are_those_set_and_those_bound() {
+        let mut context = Context::new();
+        context.set_variable("limit", 1.0);
+
+        assert_eq!(
+            vec!["$item", "$limit"],
+            texts("for $item in a return $|", &context, None)
+        );
+        assert_eq!(vec!["$limit"], texts("$l|imit", &context, None));
+    }
+
+    #[test]
+    fn completions_replace_the_whole_name_at_the_cursor() {
+        let context = Context::new();
+        let found = completions("count(co + 1)", 8, &context, None);
+
+        assert_eq!(6..8, found[0].span.range());
+        assert!(found.iter().all(|c| c.text.starts_with("co")));
+        assert!(completions("'co", 3, &context, None).is_empty());
+    }
+}
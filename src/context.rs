@@ -1,21 +1,38 @@
 //! Support for the various types of contexts before and during XPath
 //! evaluation.
 
-use sxd_document::QName;
+use sxd_document::{dom, QName};
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::iter;
+use std::rc::Rc;
 
+use crate::atomic;
+use crate::catalog::Catalog;
+use crate::collation;
+use crate::debug::Trace;
+use crate::fragment::Fragments;
 use crate::function;
+use crate::index::Index;
+use crate::key::{self, Key};
 use crate::nodeset::{Node, OrderedNodes};
-use crate::{OwnedQName, Value};
+use crate::pool::DocumentPool;
+use crate::resolver::{AsyncDocumentResolver, DocumentResolver};
+use crate::{Item, OwnedQName, Value, XPath};
 
 /// A mapping of names to XPath functions.
 type Functions = HashMap<OwnedQName, Box<dyn function::Function + 'static>>;
 /// A mapping of names to XPath variables.
 type Variables<'d> = HashMap<OwnedQName, Value<'d>>;
+/// A mapping of names to atomic types.
+type AtomicTypes = HashMap<OwnedQName, Box<dyn atomic::Type + 'static>>;
+/// A mapping of URIs to collations.
+type Collations = HashMap<String, Box<dyn collation::Collation + 'static>>;
 /// A mapping of namespace prefixes to namespace URIs.
 type Namespaces = HashMap<String, String>;
+/// A mapping of names to the keys used by `key()`.
+type Keys = HashMap<OwnedQName, Key>;
 
 /// Contains the context in which XPath expressions are executed. The
 /// context contains functions, variables, and namespace mappings.
@@ -77,7 +94,19 @@ type Namespaces = HashMap<String, String>;
 pub struct Context<'d> {
     functions: Functions,
     variables: Variables<'d>,
+    atomic_types: AtomicTypes,
+    collations: Collations,
     namespaces: Namespaces,
+    keys: Keys,
+    indexes: Vec<&'d Index<'d>>,
+    id_attributes: Vec<OwnedQName>,
+    base_uri: Option<String>,
+    default_collation: Option<String>,
+    fragments: Option<&'d Fragments>,
+    pool: Option<&'d DocumentPool>,
+    resolver: Option<Box<dyn DocumentResolver>>,
+    async_resolver: Option<Box<dyn AsyncDocumentResolver>>,
+    catalog: Option<Catalog>,
 }
 
 impl<'d> Context<'d> {
@@ -88,13 +117,30 @@ impl<'d> Context<'d> {
         context
     }
 
-    /// No functions, variables or namespaces will be defined.
+    /// No functions, variables or namespaces will be defined. The
+    /// atomic types of XML Schema and the codepoint collation are
+    /// still registered.
     pub fn without_core_functions() -> Self {
-        Context {
+        let mut context = Context {
             functions: Default::default(),
             variables: Default::default(),
+            atomic_types: Default::default(),
+            collations: Default::default(),
             namespaces: Default::default(),
-        }
+            keys: Default::default(),
+            indexes: Vec::new(),
+            id_attributes: Vec::new(),
+            base_uri: None,
+            default_collation: None,
+            fragments: None,
+            pool: None,
+            resolver: None,
+            async_resolver: None,
+            catalog: None,
+        };
+        atomic::register_core_types(&mut context);
+        collation::register_core_collations(&mut context);
+        context
     }
 
     /// Register a function within the context
@@ -115,10 +161,163 @@ impl<'d> Context<'d> {
         self.variables.insert(name.into(), value.into());
     }
 
+    /// Register an atomic type within the context
+    pub fn set_atomic_type<N, T>(&mut self, name: N, atomic_type: T)
+    where
+        N: Into<OwnedQName>,
+        T: atomic::Type + 'static,
+    {
+        self.atomic_types.insert(name.into(), Box::new(atomic_type));
+    }
+
+    /// Register a collation within the context
+    pub fn set_collation<C>(&mut self, uri: &str, collation: C)
+    where
+        C: collation::Collation + 'static,
+    {
+        self.collations.insert(uri.into(), Box::new(collation));
+    }
+
+    /// Sets the collation used by the comparison operators and by
+    /// functions called without a collation argument. Strings are
+    /// compared by codepoint unless a registered collation is set.
+    pub fn set_default_collation(&mut self, uri: &str) {
+        self.default_collation = Some(uri.into());
+    }
+
     /// Register a namespace prefix within the context
     pub fn set_namespace(&mut self, prefix: &str, uri: &str) {
         self.namespaces.insert(prefix.into(), uri.into());
     }
+
+    /// Looks up the namespace URI registered for the given prefix
+    pub fn namespace_for(&self, prefix: &str) -> Option<&str> {
+        self.namespaces.get(prefix).map(String::as_str)
+    }
+
+    /// A prefix registered for the namespace URI, the first in
+    /// alphabetical order when there are several
+    pub(crate) fn prefix_for(&self, uri: &str) -> Option<&str> {
+        self.namespaces
+            .iter()
+            .filter(|&(_, u)| u == uri)
+            .map(|(prefix, _)| prefix.as_str())
+            .min()
+    }
+
+    /// The names of the registered functions
+    pub(crate) fn function_names(&self) -> impl Iterator<Item = &OwnedQName> {
+        self.functions.keys()
+    }
+
+    /// The names of the variables that are set
+    pub(crate) fn variable_names(&self) -> impl Iterator<Item = &OwnedQName> {
+        self.variables.keys()
+    }
+
+    /// Whether a function is registered with the name
+    pub(crate) fn has_function(&self, name: QName<'_>) -> bool {
+        self.functions.contains_key(&name.into())
+    }
+
+    /// Whether a variable is set with the name
+    pub(crate) fn has_variable(&self, name: QName<'_>) -> bool {
+        self.variables.contains_key(&name.into())
+    }
+
+    /// Whether an atomic type is registered with the name
+    pub(crate) fn has_atomic_type(&self, name: QName<'_>) -> bool {
+        self.atomic_types.contains_key(&name.into())
+    }
+
+    /// Declares a key for the `key()` function. The nodes that the
+    /// pattern matches, at any depth when it is a relative path, are
+    /// indexed by each value of `uses`, which is evaluated with the
+    /// node as the context node. See the [`key`](../key/index.html)
+    /// module.
+    pub fn set_key<N>(&mut self, name: N, pattern: &XPath, uses: &XPath)
+    where
+        N: Into<OwnedQName>,
+    {
+        self.keys
+            .insert(name.into(), Key::new(pattern.ast(), uses.ast()));
+    }
+
+    /// Attaches an index that paths selecting nodes by their value of
+    /// its key may be answered from. See the
+    /// [`index`](../index/index.html) module.
+    pub fn add_index(&mut self, index: &'d Index<'d>) {
+        self.indexes.push(index);
+    }
+
+    /// Makes attributes with the name IDs for the `id()` function, in
+    /// addition to `xml:id`. The parser does not report which
+    /// attributes a DTD declares as IDs, so they are declared here.
+    pub fn add_id_attribute<N>(&mut self, name: N)
+    where
+        N: Into<OwnedQName>,
+    {
+        self.id_attributes.push(name.into());
+    }
+
+    /// Sets the static base URI, against which `resolve-uri` resolves
+    /// and `xml:base` attributes are resolved. It is also the base URI
+    /// of the document.
+    pub fn set_base_uri(&mut self, uri: &str) {
+        self.base_uri = Some(uri.into());
+    }
+
+    /// Provides the storage for the result tree fragments that
+    /// functions create, such as the tokens of `str:tokenize`, and for
+    /// the documents loaded by `document()`. Functions that create
+    /// nodes fail when none was provided.
+    pub fn set_fragments(&mut self, fragments: &'d Fragments) {
+        self.fragments = Some(fragments);
+    }
+
+    /// Keeps the documents loaded by `document()` in the pool instead
+    /// of with the fragments, so that they can be shared with other
+    /// contexts.
+    pub fn set_document_pool(&mut self, pool: &'d DocumentPool) {
+        self.pool = Some(pool);
+    }
+
+    /// Sets how `document()` loads the documents it refers to.
+    pub fn set_document_resolver<R>(&mut self, resolver: R)
+    where
+        R: DocumentResolver + 'static,
+    {
+        self.resolver = Some(Box::new(resolver));
+    }
+
+    /// Sets how `document()` loads the documents it refers to when
+    /// evaluating with [`XPath::evaluate_async`][]. It is used instead
+    /// of the resolver given to
+    /// [`set_document_resolver`](#method.set_document_resolver).
+    ///
+    /// [`XPath::evaluate_async`]: ../struct.XPath.html#method.evaluate_async
+    pub fn set_async_document_resolver<R>(&mut self, resolver: R)
+    where
+        R: AsyncDocumentResolver + 'static,
+    {
+        self.async_resolver = Some(Box::new(resolver));
+    }
+
+    pub(crate) fn async_document_resolver(&self) -> Option<&dyn AsyncDocumentResolver> {
+        self.async_resolver.as_deref()
+    }
+
+    /// Where the documents loaded by `document()` are kept.
+    pub(crate) fn documents(&self) -> Option<&'d DocumentPool> {
+        self.pool
+            .or_else(|| self.fragments.map(Fragments::documents))
+    }
+
+    /// Sets the catalog that `resolve-uri` and `document()` consult
+    /// to map the URIs they resolve.
+    pub fn set_catalog(&mut self, catalog: Catalog) {
+        self.catalog = Some(catalog);
+    }
 }
 
 impl<'d> Default for Context<'d> {
@@ -141,13 +340,42 @@ impl<'d> Default for Context<'d> {
 pub struct Evaluation<'c, 'd> {
     /// The context node
     pub node: Node<'d>,
+    /// The context item, when it is an atomic value instead of a node
+    pub item: Option<&'c Item<'d>>,
     /// The context position
     pub position: usize,
     /// The context size
     pub size: usize,
+    current: Node<'d>,
     functions: &'c Functions,
     variables: &'c Variables<'d>,
+    bindings: Option<&'c Binding<'c, 'd>>,
+    atomic_types: &'c AtomicTypes,
+    collations: &'c Collations,
     namespaces: &'c Namespaces,
+    keys: &'c Keys,
+    indexes: &'c [&'d Index<'d>],
+    id_attributes: &'c [OwnedQName],
+    key_indexes: Option<&'c key::Indexes<'d>>,
+    base_uri: Option<&'c str>,
+    default_collation: Option<&'c dyn collation::Collation>,
+    fragments: Option<&'d Fragments>,
+    pool: Option<&'d DocumentPool>,
+    resolver: Option<&'c dyn DocumentResolver>,
+    pending: Option<&'c RefCell<Vec<String>>>,
+    catalog: Option<&'c Catalog>,
+    tracer: Option<&'c (dyn Trace + 'c)>,
+}
+
+/// A variable bound while evaluating an expression, such as the
+/// variable of a `for` expression. It shadows variables of the same
+/// name from the [`Context`][] and from enclosing bindings.
+///
+/// [`Context`]: struct.Context.html
+pub struct Binding<'b, 'd> {
+    name: OwnedQName,
+    value: Value<'d>,
+    outer: Option<&'b Binding<'b, 'd>>,
 }
 
 impl<'c, 'd> Evaluation<'c, 'd> {
@@ -155,14 +383,66 @@ impl<'c, 'd> Evaluation<'c, 'd> {
     pub fn new(context: &'c Context<'d>, node: Node<'d>) -> Evaluation<'c, 'd> {
         Evaluation {
             node,
+            item: None,
+            current: node,
             functions: &context.functions,
             variables: &context.variables,
+            bindings: None,
+            atomic_types: &context.atomic_types,
+            collations: &context.collations,
             namespaces: &context.namespaces,
+            keys: &context.keys,
+            indexes: &context.indexes,
+            id_attributes: &context.id_attributes,
+            key_indexes: None,
+            base_uri: context.base_uri.as_deref(),
+            default_collation: context
+                .default_collation
+                .as_ref()
+                .and_then(|uri| context.collations.get(uri))
+                .map(AsRef::as_ref),
+            fragments: context.fragments,
+            pool: context.pool,
+            resolver: context.resolver.as_deref(),
+            pending: None,
+            catalog: context.catalog.as_ref(),
+            tracer: None,
             position: 1,
             size: 1,
         }
     }
 
+    /// Instead of loading documents that have not been loaded yet,
+    /// `document()` adds their URIs to `pending` and fails.
+    pub(crate) fn awaiting_documents(self, pending: &'c RefCell<Vec<String>>) -> Self {
+        Evaluation {
+            pending: Some(pending),
+            ..self
+        }
+    }
+
+    /// Keeps the indexes that `key()` builds, so that they are only
+    /// built once for each document.
+    pub(crate) fn with_key_indexes(self, key_indexes: &'c key::Indexes<'d>) -> Self {
+        Evaluation {
+            key_indexes: Some(key_indexes),
+            ..self
+        }
+    }
+
+    /// Tells the debugger about each traced expression that is
+    /// evaluated.
+    pub(crate) fn traced_by(self, tracer: &'c (dyn Trace + 'c)) -> Self {
+        Evaluation {
+            tracer: Some(tracer),
+            ..self
+        }
+    }
+
+    pub(crate) fn tracer(&self) -> Option<&'c (dyn Trace + 'c)> {
+        self.tracer
+    }
+
     /// Creates a new context node using the provided node
     pub fn new_context_for<N>(&self, node: N) -> Evaluation<'c, 'd>
     where
@@ -170,10 +450,99 @@ impl<'c, 'd> Evaluation<'c, 'd> {
     {
         Evaluation {
             node: node.into(),
+            item: None,
+            ..*self
+        }
+    }
+
+    /// Creates a new context for an item of a sequence. Atomic items
+    /// keep the current context node.
+    pub fn new_context_for_item<'i>(
+        &self,
+        item: &'i Item<'d>,
+        position: usize,
+        size: usize,
+    ) -> Evaluation<'i, 'd>
+    where
+        'c: 'i,
+    {
+        let (node, item) = match *item {
+            Item::Node(node) => (node, None),
+            ref atomic => (self.node, Some(atomic)),
+        };
+
+        Evaluation {
+            node,
+            item,
+            position,
+            size,
+            current: self.current,
+            functions: self.functions,
+            variables: self.variables,
+            bindings: self.bindings,
+            atomic_types: self.atomic_types,
+            collations: self.collations,
+            namespaces: self.namespaces,
+            keys: self.keys,
+            indexes: self.indexes,
+            id_attributes: self.id_attributes,
+            key_indexes: self.key_indexes,
+            base_uri: self.base_uri,
+            fragments: self.fragments,
+            pool: self.pool,
+            resolver: self.resolver,
+            pending: self.pending,
+            catalog: self.catalog,
+            default_collation: self.default_collation,
+            tracer: self.tracer,
+        }
+    }
+
+    /// The node the whole expression is evaluated for, which stays the
+    /// same inside predicates and paths, as with `current()` in XSLT.
+    pub fn current_node(&self) -> Node<'d> {
+        self.current
+    }
+
+    /// Prepares a binding of the variable that can be used to create a
+    /// new context with [`new_context_with`](#method.new_context_with).
+    pub fn bind<N>(&self, name: N, value: Value<'d>) -> Binding<'c, 'd>
+    where
+        N: Into<OwnedQName>,
+    {
+        Binding {
+            name: name.into(),
+            value,
+            outer: self.bindings,
+        }
+    }
+
+    /// Creates a new context in which the bound variable is visible
+    pub fn new_context_with<'b>(&self, binding: &'b Binding<'b, 'd>) -> Evaluation<'b, 'd>
+    where
+        'c: 'b,
+    {
+        Evaluation {
+            bindings: Some(binding),
             ..*self
         }
     }
 
+    /// The variables bound while evaluating, such as those of `for`
+    /// expressions, from the outermost to the innermost binding.
+    pub(crate) fn bound_variables(&self) -> Vec<(OwnedQName, Value<'d>)> {
+        let mut bound = Vec::new();
+
+        let mut binding = self.bindings;
+        while let Some(b) = binding {
+            bound.push((b.name.clone(), b.value.clone()));
+            binding = b.outer;
+        }
+
+        bound.reverse();
+        bound
+    }
+
     /// Looks up the function with the given name
     pub fn function_for_name(&self, name: QName<'_>) -> Option<&'c dyn function::Function> {
         // FIXME: remove allocation
@@ -181,10 +550,36 @@ impl<'c, 'd> Evaluation<'c, 'd> {
         self.functions.get(&name).map(AsRef::as_ref)
     }
 
+    /// Looks up the atomic type with the given name
+    pub fn atomic_type_for_name(&self, name: QName<'_>) -> Option<&'c dyn atomic::Type> {
+        // FIXME: remove allocation
+        let name = name.into();
+        self.atomic_types.get(&name).map(AsRef::as_ref)
+    }
+
+    /// Looks up the collation with the given URI
+    pub fn collation_for(&self, uri: &str) -> Option<&'c dyn collation::Collation> {
+        self.collations.get(uri).map(AsRef::as_ref)
+    }
+
+    /// The default collation, if one was set
+    pub fn default_collation(&self) -> Option<&'c dyn collation::Collation> {
+        self.default_collation
+    }
+
     /// Looks up the value of the variable
     pub fn value_of(&self, name: QName<'_>) -> Option<&Value<'d>> {
         // FIXME: remove allocation
         let name = name.into();
+
+        let mut binding = self.bindings;
+        while let Some(b) = binding {
+            if b.name == name {
+                return Some(&b.value);
+            }
+            binding = b.outer;
+        }
+
         self.variables.get(&name)
     }
 
@@ -193,6 +588,95 @@ impl<'c, 'd> Evaluation<'c, 'd> {
         self.namespaces.get(prefix).map(String::as_str)
     }
 
+    /// The static base URI, if one was set
+    pub fn base_uri(&self) -> Option<&'c str> {
+        self.base_uri
+    }
+
+    /// The catalog, if one was set
+    pub fn catalog(&self) -> Option<&'c Catalog> {
+        self.catalog
+    }
+
+    /// Creates an empty result tree fragment, if the context was
+    /// given somewhere to keep it. New nodes are appended to the
+    /// returned element.
+    pub fn new_fragment(&self) -> Option<dom::Element<'d>> {
+        self.fragments.map(Fragments::create)
+    }
+
+    /// Loads the document at the URI with the resolver of the context.
+    /// A document that was already loaded into the same document pool,
+    /// or fragment storage when there is no pool, is returned again.
+    pub fn load_document(&self, uri: &str) -> Result<dom::Document<'d>, function::Error> {
+        let documents = self
+            .pool
+            .or_else(|| self.fragments.map(Fragments::documents))
+            .ok_or(function::Error::NoFragments)?;
+        documents.load(uri, || {
+            if let Some(pending) = self.pending {
+                let mut pending = pending.borrow_mut();
+                if !pending.iter().any(|p| p == uri) {
+                    pending.push(uri.to_owned());
+                }
+                return Err(function::Error::DocumentPending {
+                    uri: uri.to_owned(),
+                });
+            }
+            let resolver = self.resolver.ok_or(function::Error::NoDocumentResolver)?;
+            resolver
+                .resolve(uri)
+                .map_err(|e| function::Error::DocumentNotLoaded {
+                    uri: uri.to_owned(),
+                    what: e.to_string(),
+                })
+        })
+    }
+
+    /// Whether attributes with the name are IDs, other than `xml:id`
+    pub(crate) fn is_id_attribute(&self, name: QName<'_>) -> bool {
+        self.id_attributes.iter().any(|id| {
+            id.namespace_uri.as_deref() == name.namespace_uri()
+                && id.local_part == name.local_part()
+        })
+    }
+
+    /// The indexes attached to the context
+    pub(crate) fn indexes(&self) -> &'c [&'d Index<'d>] {
+        self.indexes
+    }
+
+    /// The index of the named key for the document of the context
+    /// node. The name may have a prefix.
+    pub(crate) fn key_index(&self, name: &str) -> Result<Rc<Index<'d>>, function::Error> {
+        let unknown = || function::Error::UnknownKey {
+            name: name.to_owned(),
+        };
+        let qname = match name.find(':') {
+            Some(colon) => {
+                let uri = self.namespace_for(&name[..colon]).ok_or_else(unknown)?;
+                OwnedQName::from((uri, &name[colon + 1..]))
+            }
+            None => OwnedQName::from(name),
+        };
+        let key = self.keys.get(&qname).ok_or_else(unknown)?;
+
+        let built;
+        let indexes = match self.key_indexes {
+            Some(indexes) => indexes,
+            None => {
+                built = key::Indexes::default();
+                &built
+            }
+        };
+        indexes
+            .get(&qname, key, self)
+            .map_err(|e| function::Error::KeyEvaluation {
+                name: name.to_owned(),
+                source: Box::new(e),
+            })
+    }
+
     /// Yields a new `Evaluation` context for each node in the nodeset.
     pub fn new_contexts_for(self, nodes: OrderedNodes<'d>) -> EvaluationNodesetIter<'c, 'd> {
         let sz = nodes.size();
@@ -217,6 +701,7 @@ impl<'c, 'd> Iterator for EvaluationNodesetIter<'c, 'd> {
     fn next(&mut self) -> Option<Evaluation<'c, 'd>> {
         self.nodes.next().map(|(idx, node)| Evaluation {
             node,
+            item: None,
             position: idx + 1,
             size: self.size,
             ..self.parent
@@ -0,0 +1,168 @@
+//! The conversions between booleans, numbers and strings that the
+//! `boolean()`, `number()` and `string()` functions perform.
+//!
+//! [`Value::boolean`][], [`Value::number`][] and [`Value::string`][]
+//! are built from these, so extension functions that work on plain
+//! Rust values can convert them exactly as XPath does:
+//!
+//! ```
+//! use sxd_xpath::conv;
+//!
+//! assert_eq!("0", conv::number_to_string(-0.0));
+//! assert_eq!("-Infinity", conv::number_to_string(-1.0 / 0.0));
+//! assert!(conv::string_to_number("Infinity").is_nan());
+//! assert_eq!(12.5, conv::string_to_number(" 12.5\n"));
+//! assert!(!conv::number_to_boolean(0.0 / 0.0));
+//! ```
+//!
+//! [`Value::boolean`]: ../enum.Value.html#method.boolean
+//! [`Value::number`]: ../enum.Value.html#method.number
+//! [`Value::string`]: ../enum.Value.html#method.string
+
+use std::f64;
+
+/// `true` is 1 and `false` is 0.
+pub fn boolean_to_number(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// `true` or `false`.
+pub fn boolean_to_string(b: bool) -> &'static str {
+    if b {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+/// A number is true unless it is zero, of either sign, or NaN.
+pub fn number_to_boolean(n: f64) -> bool {
+    n != 0.0 && !n.is_nan()
+}
+
+/// Writes the number without an exponent. Both zeros are written as
+/// `0`, and the infinities as `Infinity` and `-Infinity`.
+pub fn number_to_string(n: f64) -> String {
+    if n.is_infinite() {
+        if n.signum() < 0.0 {
+            "-Infinity".to_owned()
+        } else {
+            "Infinity".to_owned()
+        }
+    } else if n == 0.0 {
+        // Rust 1.53 and later write negative zero as `-0`
+        "0".to_owned()
+    } else {
+        n.to_string()
+    }
+}
+
+/// A string is true unless it is empty.
+pub fn string_to_boolean(s: &str) -> bool {
+    !s.is_empty()
+}
+
+/// Reads a number surrounded by optional whitespace: an optional
+/// minus sign, digits with an optional decimal point and an optional
+/// exponent. Anything else, including `Infinity`, `NaN` and a leading
+/// plus sign, is NaN.
+pub fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(is_xml_space);
+    if is_number(s) {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+fn is_xml_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+fn is_number(s: &str) -> bool {
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let mantissa_ok = match mantissa.find('.') {
+        Some(i) => {
+            let (whole, fraction) = (&mantissa[..i], &mantissa[i + 1..]);
+            (!whole.is_empty() || !fraction.is_empty()) && digits(whole) && digits(fraction)
+        }
+        None => !mantissa.is_empty() && digits(mantissa),
+    };
+    let exponent_ok = match exponent {
+        Some(e) => {
+            let e = e.strip_prefix(['-', '+']).unwrap_or(e);
+            !e.is_empty() && digits(e)
+        }
+        None => true,
+    };
+
+    mantissa_ok && exponent_ok
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn booleans_convert_to_one_and_zero() {
+        assert_eq!(1.0, boolean_to_number(true));
+        assert_eq!(0.0, boolean_to_number(false));
+        assert_eq!("true", boolean_to_string(true));
+        assert_eq!("false", boolean_to_string(false));
+    }
+
+    #[test]
+    fn zero_and_nan_are_false() {
+        assert!(number_to_boolean(-2.5));
+        assert!(number_to_boolean(f64::INFINITY));
+        assert!(!number_to_boolean(0.0));
+        assert!(!number_to_boolean(-0.0));
+        assert!(!number_to_boolean(f64::NAN));
+    }
+
+    #[test]
+    fn only_the_empty_string_is_false() {
+        assert!(string_to_boolean("false"));
+        assert!(string_to_boolean(" "));
+        assert!(!string_to_boolean(""));
+    }
+
+    #[test]
+    fn numbers_are_written_without_exponents() {
+        assert_eq!("1", number_to_string(1.0));
+        assert_eq!("-0.5", number_to_string(-0.5));
+        assert_eq!("0", number_to_string(-0.0));
+        assert_eq!("NaN", number_to_string(f64::NAN));
+        assert_eq!("Infinity", number_to_string(f64::INFINITY));
+        assert_eq!("-Infinity", number_to_string(f64::NEG_INFINITY));
+        assert_eq!("100000000000000000000", number_to_string(1e20));
+    }
+
+    #[test]
+    fn numbers_are_read_with_surrounding_whitespace() {
+        assert_eq!(42.0, string_to_number("42"));
+        assert_eq!(-0.5, string_to_number(" -.5\t"));
+        assert_eq!(3.0, string_to_number("3."));
+        assert_eq!(1500.0, string_to_number("1.5e3"));
+        assert!(string_to_number("-0").is_sign_negative());
+    }
+
+    #[test]
+    fn other_strings_are_nan() {
+        for s in &[
+            "", " ", ".", "-", "+1", "1 2", "Infinity", "inf", "NaN", "0x10", "1e", "e1", "1.2.3",
+        ] {
+            assert!(string_to_number(s).is_nan(), "{:?} is a number", s);
+        }
+    }
+}
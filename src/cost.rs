@@ -0,0 +1,459 @@
+//! Rough estimates of how expensive an XPath is to evaluate.
+//!
+//! The cost of an XPath is the number of nodes its steps are expected
+//! to visit, based on the shape of a typical document. Statistics
+//! taken from a real document make the estimate closer, but it is only
+//! meant to tell cheap XPaths from ones that search the whole document
+//! many times over.
+//!
+//! ```
+//! use sxd_xpath::cost::Statistics;
+//! use sxd_xpath::Factory;
+//!
+//! let factory = Factory::new();
+//! let statistics = Statistics::default();
+//!
+//! let direct = factory.build("/shop/item[@id = 'a']").expect("Could not compile XPath");
+//! let nested = factory.build("//item[//other/@id = @id]").expect("Could not compile XPath");
+//!
+//! let cost = nested.cost(&statistics);
+//! assert!(cost.score > 1000.0 * direct.cost(&statistics).score);
+//! assert_eq!(Some("descendant-or-self::node() at depth 1"), cost.dominant.as_deref());
+//! ```
+
+use crate::ast::{BinaryOperator, Expr, LookupKey, NodeTest, Step};
+use crate::axis::Axis;
+use crate::nodeset::Node;
+use crate::tree::{NodeKind, TreeNode};
+use crate::Value;
+
+/// The shape of the documents that an XPath is evaluated against.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Statistics {
+    /// The number of nodes, not counting attributes and namespaces
+    pub nodes: f64,
+    /// The average number of children of the nodes that have any
+    pub children: f64,
+    /// The average number of attributes of an element
+    pub attributes: f64,
+    /// The depth of the deepest node, where the root is at depth 0
+    pub depth: f64,
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Statistics {
+            nodes: 10_000.0,
+            children: 5.0,
+            attributes: 2.0,
+            depth: 6.0,
+        }
+    }
+}
+
+impl Statistics {
+    /// Measures the tree below the node.
+    pub fn collect<'d, N>(node: N) -> Statistics
+    where
+        N: Into<Node<'d>>,
+    {
+        let mut nodes = 0;
+        let mut parents = 0;
+        let mut elements = 0;
+        let mut attributes = 0;
+        let mut depth = 0;
+
+        let mut pending = vec![(node.into(), 0)];
+        while let Some((node, level)) = pending.pop() {
+            nodes += 1;
+            depth = depth.max(level);
+            if node.kind() == NodeKind::Element {
+                elements += 1;
+                attributes += TreeNode::attributes(&node).len();
+            }
+
+            let children = node.children();
+            if !children.is_empty() {
+                parents += 1;
+            }
+            pending.extend(children.into_iter().map(|child| (child, level + 1)));
+        }
+
+        Statistics {
+            nodes: nodes as f64,
+            children: ((nodes - 1) as f64 / parents.max(1) as f64).max(1.0),
+            attributes: attributes as f64 / elements.max(1) as f64,
+            depth: depth as f64,
+        }
+    }
+
+    /// The nodes below a node at the depth, including itself.
+    fn subtree(&self, depth: f64) -> f64 {
+        (self.nodes / self.children.powf(depth)).max(1.0)
+    }
+}
+
+/// The estimated cost of an XPath.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cost {
+    /// The number of nodes the steps are expected to visit
+    pub score: f64,
+    /// The step that visits the most nodes and how deeply it is nested
+    /// in predicates, such as `descendant-or-self::node() at depth 2`
+    pub dominant: Option<String>,
+}
+
+/// Estimates the cost of evaluating the expression once.
+pub fn estimate(expr: &Expr, statistics: &Statistics) -> Cost {
+    let mut estimator = Estimator {
+        statistics,
+        score: 0.0,
+        dominant: None,
+        depth: 0,
+    };
+    estimator.expr(expr, 1.0, 0.0);
+
+    Cost {
+        score: estimator.score,
+        dominant: estimator.dominant.map(|(_, description)| description),
+    }
+}
+
+/// The estimated result of an expression: how many nodes or items it
+/// gives, and how deep those nodes are.
+#[derive(Debug, Copy, Clone)]
+struct Estimate {
+    size: f64,
+    level: f64,
+}
+
+impl Estimate {
+    fn one(level: f64) -> Estimate {
+        Estimate { size: 1.0, level }
+    }
+}
+
+struct Estimator<'s> {
+    statistics: &'s Statistics,
+    score: f64,
+    dominant: Option<(f64, String)>,
+    /// How deeply the current expression is nested in predicates
+    depth: usize,
+}
+
+impl<'s> Estimator<'s> {
+    /// Estimates the expression, which is evaluated the given number of
+    /// times with a context node at the level.
+    fn expr(&mut self, expr: &Expr, evaluations: f64, level: f64) -> Estimate {
+        match *expr {
+            Expr::ContextNode | Expr::Literal(_) | Expr::Variable(_) => Estimate::one(level),
+            Expr::RootNode => Estimate::one(0.0),
+            Expr::Function { ref arguments, .. } => {
+                self.all(arguments, evaluations, level);
+                Estimate::one(level)
+            }
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => {
+                let mut current = self.expr(start_point, evaluations, level);
+                for step in steps {
+                    current = self.step(step, evaluations, current);
+                }
+                current
+            }
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => {
+                let selected = self.expr(expression, evaluations, level);
+                self.predicates(Some(&**predicate), evaluations, selected, 1.0)
+            }
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => {
+                let l = self.expr(left, evaluations, level);
+                let r = self.expr(right, evaluations, level);
+                match (operator, &**left, &**right) {
+                    (BinaryOperator::Union, _, _) => Estimate {
+                        size: (l.size + r.size).min(self.statistics.nodes),
+                        level: l.level.max(r.level),
+                    },
+                    (
+                        BinaryOperator::Range,
+                        &Expr::Literal(Value::Number(first)),
+                        &Expr::Literal(Value::Number(last)),
+                    ) => Estimate {
+                        size: (last - first + 1.0).max(0.0),
+                        level,
+                    },
+                    _ => Estimate::one(level),
+                }
+            }
+            Expr::For {
+                ref sequence,
+                ref body,
+                ..
+            } => {
+                let items = self.expr(sequence, evaluations, level);
+                let each = self.expr(body, evaluations * items.size, level);
+                Estimate {
+                    size: items.size * each.size,
+                    level: each.level,
+                }
+            }
+            Expr::Quantified {
+                ref sequence,
+                ref test,
+                ..
+            } => {
+                let items = self.expr(sequence, evaluations, level);
+                self.expr(test, evaluations * items.size, level);
+                Estimate::one(level)
+            }
+            Expr::Let {
+                ref value,
+                ref body,
+                ..
+            } => {
+                self.expr(value, evaluations, level);
+                self.expr(body, evaluations, level)
+            }
+            // Both branches are counted, as either may be taken
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => {
+                self.expr(condition, evaluations, level);
+                let consequent = self.expr(consequent, evaluations, level);
+                let alternative = self.expr(alternative, evaluations, level);
+                if consequent.size >= alternative.size {
+                    consequent
+                } else {
+                    alternative
+                }
+            }
+            Expr::Negation(ref expr)
+            | Expr::InstanceOf {
+                expression: ref expr,
+                ..
+            }
+            | Expr::Cast {
+                expression: ref expr,
+                ..
+            }
+            | Expr::Castable {
+                expression: ref expr,
+                ..
+            } => {
+                self.expr(expr, evaluations, level);
+                Estimate::one(level)
+            }
+            Expr::TreatAs {
+                expression: ref expr,
+                ..
+            }
+            | Expr::CurlyArray(ref expr) => self.expr(expr, evaluations, level),
+            Expr::Sequence(ref expressions) | Expr::SquareArray(ref expressions) => {
+                let size = self.all(expressions, evaluations, level);
+                Estimate { size, level }
+            }
+            Expr::Map(ref entries) => {
+                for (key, value) in entries {
+                    self.expr(key, evaluations, level);
+                    self.expr(value, evaluations, level);
+                }
+                Estimate::one(level)
+            }
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => {
+                self.expr(expression, evaluations, level);
+                if let LookupKey::Expression(ref key) = *key {
+                    self.expr(key, evaluations, level);
+                }
+                Estimate::one(level)
+            }
+            // How often the function is called is unknown, so it is
+            // counted as being called once
+            Expr::InlineFunction { ref body, .. } => {
+                self.expr(body, evaluations, level);
+                Estimate::one(level)
+            }
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => {
+                self.expr(function, evaluations, level);
+                self.all(arguments, evaluations, level);
+                Estimate::one(level)
+            }
+        }
+    }
+
+    /// Estimates each expression, giving their total size.
+    fn all(&mut self, expressions: &[Expr], evaluations: f64, level: f64) -> f64 {
+        expressions
+            .iter()
+            .map(|expr| self.expr(expr, evaluations, level).size)
+            .sum()
+    }
+
+    fn step(&mut self, step: &Step, evaluations: f64, context: Estimate) -> Estimate {
+        let statistics = self.statistics;
+        let (visited, level) = match step.axis {
+            Axis::Child => (statistics.children, context.level + 1.0),
+            Axis::Attribute => (statistics.attributes, context.level + 1.0),
+            Axis::Namespace => (1.0, context.level + 1.0),
+            Axis::SelfAxis => (1.0, context.level),
+            Axis::Parent => (1.0, (context.level - 1.0).max(0.0)),
+            Axis::Ancestor | Axis::AncestorOrSelf => (context.level + 1.0, context.level / 2.0),
+            Axis::PrecedingSibling | Axis::FollowingSibling => {
+                (statistics.children / 2.0, context.level)
+            }
+            Axis::Preceding | Axis::Following => (statistics.nodes / 2.0, context.level),
+            Axis::Descendant | Axis::DescendantOrSelf => (
+                statistics.subtree(context.level),
+                (context.level + statistics.depth) / 2.0,
+            ),
+        };
+
+        // Steps down the tree from different nodes rarely visit the
+        // same nodes, so together they visit at most the whole document
+        let visited = match step.axis {
+            Axis::Child | Axis::Descendant | Axis::DescendantOrSelf => {
+                (context.size * visited).min(statistics.nodes)
+            }
+            Axis::Attribute => {
+                (context.size * visited).min(statistics.nodes * statistics.attributes)
+            }
+            _ => context.size * visited,
+        };
+        let cost = evaluations * visited;
+        self.score += cost;
+        if self.dominant.as_ref().is_none_or(|&(most, _)| cost > most) {
+            let bare = Step {
+                predicates: Vec::new(),
+                ..step.clone()
+            };
+            let text = Expr::Path {
+                start_point: Box::new(Expr::ContextNode),
+                steps: vec![bare],
+            };
+            self.dominant = Some((cost, format!("{} at depth {}", text, self.depth)));
+        }
+
+        let selectivity = match step.node_test {
+            NodeTest::Name(ref name) if name.local_part == "*" => 0.8,
+            NodeTest::Name(_) => 0.2,
+            NodeTest::Node => 1.0,
+            NodeTest::Text => 0.3,
+            NodeTest::Comment | NodeTest::ProcessingInstruction(_) => 0.05,
+        };
+        let selected = Estimate {
+            size: (visited * selectivity).min(statistics.nodes),
+            level,
+        };
+        self.predicates(&step.predicates, evaluations, selected, context.size)
+    }
+
+    /// Each predicate is evaluated once for each node that the ones
+    /// before it keep. A number keeps one node for each context node
+    /// that the nodes were selected from; anything else is assumed to
+    /// keep half of them.
+    fn predicates<'a, I>(
+        &mut self,
+        predicates: I,
+        evaluations: f64,
+        selected: Estimate,
+        contexts: f64,
+    ) -> Estimate
+    where
+        I: IntoIterator<Item = &'a Expr>,
+    {
+        let mut kept = selected;
+        for predicate in predicates {
+            self.depth += 1;
+            self.expr(predicate, evaluations * kept.size, kept.level);
+            self.depth -= 1;
+
+            kept.size = match *predicate {
+                Expr::Literal(Value::Number(_)) => kept.size.min(contexts),
+                _ => kept.size / 2.0,
+            };
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::Factory;
+
+    fn cost(xpath: &str) -> Cost {
+        Factory::new()
+            .build(xpath)
+            .expect("Unable to build XPath")
+            .cost(&Statistics::default())
+    }
+
+    #[test]
+    fn searching_the_document_costs_more_than_direct_paths() {
+        assert!(cost("//a").score > 10.0 * cost("/a/b/c").score);
+        assert!(cost("/a/b/c").score > cost("/a").score);
+        assert!(cost("/a/following::b").score > cost("/a/following-sibling::b").score);
+    }
+
+    #[test]
+    fn predicates_multiply_the_cost_of_their_steps() {
+        let once = cost("count(//b)");
+        let nested = cost("//a[count(//b) > 1]");
+
+        assert!(nested.score > 100.0 * once.score);
+        assert_eq!(
+            Some("descendant-or-self::node() at depth 1"),
+            nested.dominant.as_deref()
+        );
+    }
+
+    #[test]
+    fn loops_multiply_the_cost_of_their_body() {
+        let body = cost("//a").score;
+        assert!(cost("for $i in 1 to 100 return //a").score >= 100.0 * body);
+    }
+
+    #[test]
+    fn expressions_without_steps_cost_nothing() {
+        assert_eq!(
+            Cost {
+                score: 0.0,
+                dominant: None
+            },
+            cost("1 + $a")
+        );
+    }
+
+    #[test]
+    fn statistics_measure_the_document() {
+        let package =
+            parser::parse("<a x='1' y='2'><b/><b><c z='3'/></b>text</a>").expect("Unable to parse");
+        let statistics = Statistics::collect(package.as_document().root());
+
+        assert_eq!(
+            Statistics {
+                nodes: 6.0,
+                children: 5.0 / 3.0,
+                attributes: 3.0 / 4.0,
+                depth: 3.0,
+            },
+            statistics
+        );
+    }
+}
@@ -0,0 +1,1613 @@
+//! Dates, times and durations, as defined by XML Schema.
+//!
+//! Values are created by casting strings, for example with
+//! `xs:date('2024-01-31')` or `'PT1H' cast as xs:dayTimeDuration`.
+//! They can be compared with each other, and durations can be added
+//! to and subtracted from dates and times.
+
+use snafu::{ensure, Snafu};
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::Item;
+
+pub(crate) const NANOS_PER_SECOND: i128 = 1_000_000_000;
+const NANOS_PER_MINUTE: i128 = 60 * NANOS_PER_SECOND;
+const NANOS_PER_DAY: i128 = 24 * 60 * NANOS_PER_MINUTE;
+
+/// Which of `xs:dateTime`, `xs:date` and `xs:time` a
+/// [`DateTime`](struct.DateTime.html) is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DateTimeKind {
+    DateTime,
+    Date,
+    Time,
+}
+
+/// A point in time: an `xs:dateTime`, an `xs:date` or an `xs:time`.
+///
+/// Values are compared as instants. A value without a timezone is
+/// treated as being in UTC.
+#[derive(Debug, Copy, Clone)]
+pub struct DateTime {
+    kind: DateTimeKind,
+    year: i64,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    nanosecond: u32,
+    /// The offset from UTC in minutes
+    timezone: Option<i16>,
+}
+
+/// Which of `xs:duration`, `xs:yearMonthDuration` and
+/// `xs:dayTimeDuration` a [`Duration`](struct.Duration.html) is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DurationKind {
+    Duration,
+    YearMonth,
+    DayTime,
+}
+
+/// A length of time, made of a number of months and a number of
+/// nanoseconds. Both parts have the same sign.
+#[derive(Debug, Copy, Clone)]
+pub struct Duration {
+    kind: DurationKind,
+    months: i64,
+    nanoseconds: i128,
+}
+
+pub(crate) fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The number of days from 1970-01-01 in the proleptic Gregorian
+// calendar, following http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Reads the lexical forms of the temporal types.
+struct Scanner<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(s: &'a str) -> Self {
+        Scanner {
+            s: s.trim().as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.s.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn consume(&mut self, expected: u8) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: u8) -> Option<()> {
+        if self.consume(expected) {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn digit_run(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        &self.s[start..self.pos]
+    }
+
+    fn number(&mut self) -> Option<i64> {
+        let digits = self.digit_run();
+        if digits.is_empty() || digits.len() > 18 {
+            return None;
+        }
+        Some(digits.iter().fold(0, |n, d| n * 10 + i64::from(d - b'0')))
+    }
+
+    fn two_digits(&mut self) -> Option<u8> {
+        let start = self.pos;
+        let n = self.number()?;
+        if self.pos - start == 2 {
+            Some(n as u8)
+        } else {
+            None
+        }
+    }
+
+    /// The digits after a decimal point, as nanoseconds. Digits past
+    /// the ninth are ignored.
+    fn fraction(&mut self) -> Option<u32> {
+        let digits = self.digit_run();
+        if digits.is_empty() {
+            return None;
+        }
+        Some(
+            (0..9)
+                .map(|i| digits.get(i).map_or(0, |d| u32::from(d - b'0')))
+                .fold(0, |n, d| n * 10 + d),
+        )
+    }
+
+    fn date(&mut self) -> Option<(i64, u8, u8)> {
+        let negative = self.consume(b'-');
+        let start = self.pos;
+        let year = self.number()?;
+        if self.pos - start < 4 {
+            return None;
+        }
+        self.expect(b'-')?;
+        let month = self.two_digits()?;
+        self.expect(b'-')?;
+        let day = self.two_digits()?;
+
+        let year = if negative { -year } else { year };
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some((year, month, day))
+    }
+
+    fn time(&mut self) -> Option<(u8, u8, u8, u32)> {
+        let hour = self.two_digits()?;
+        self.expect(b':')?;
+        let minute = self.two_digits()?;
+        self.expect(b':')?;
+        let second = self.two_digits()?;
+        let nanosecond = if self.consume(b'.') {
+            self.fraction()?
+        } else {
+            0
+        };
+
+        let end_of_day = hour == 24 && minute == 0 && second == 0 && nanosecond == 0;
+        if (hour > 23 && !end_of_day) || minute > 59 || second > 59 {
+            return None;
+        }
+        Some((hour, minute, second, nanosecond))
+    }
+
+    fn timezone(&mut self) -> Option<Option<i16>> {
+        let sign = match self.next() {
+            None => return Some(None),
+            Some(b'Z') => return Some(Some(0)),
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            Some(_) => return None,
+        };
+        let hours = self.two_digits()?;
+        self.expect(b':')?;
+        let minutes = self.two_digits()?;
+
+        if hours > 14 || minutes > 59 || (hours == 14 && minutes > 0) {
+            return None;
+        }
+        Some(Some(sign * (i16::from(hours) * 60 + i16::from(minutes))))
+    }
+}
+
+impl DateTime {
+    /// Parses the lexical form of the kind of value, such as
+    /// `2024-01-31T12:00:00Z` for an `xs:dateTime`.
+    pub fn parse(s: &str, kind: DateTimeKind) -> Option<DateTime> {
+        let mut scanner = Scanner::new(s);
+
+        let (year, month, day) = match kind {
+            DateTimeKind::Time => (1972, 12, 31),
+            _ => scanner.date()?,
+        };
+        if kind == DateTimeKind::DateTime {
+            scanner.expect(b'T')?;
+        }
+        let (hour, minute, second, nanosecond) = match kind {
+            DateTimeKind::Date => (0, 0, 0, 0),
+            _ => scanner.time()?,
+        };
+        let timezone = scanner.timezone()?;
+        if !scanner.is_done() {
+            return None;
+        }
+
+        let value = DateTime {
+            kind,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            nanosecond,
+            timezone,
+        };
+
+        // 24:00:00 is the first moment of the next day
+        if hour == 24 {
+            let midnight = DateTime { hour: 0, ..value };
+            return match kind {
+                DateTimeKind::Time => Some(midnight),
+                _ => midnight.add(&Duration::day_time(NANOS_PER_DAY)),
+            };
+        }
+        Some(value)
+    }
+
+    pub(crate) fn from_instant(
+        kind: DateTimeKind,
+        instant: i128,
+        timezone: Option<i16>,
+    ) -> DateTime {
+        let local = instant + i128::from(timezone.unwrap_or(0)) * NANOS_PER_MINUTE;
+        let days = local.div_euclid(NANOS_PER_DAY);
+        let time = local.rem_euclid(NANOS_PER_DAY);
+        let (year, month, day) = civil_from_days(days as i64);
+        let seconds = time / NANOS_PER_SECOND;
+
+        DateTime {
+            kind,
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            nanosecond: (time % NANOS_PER_SECOND) as u32,
+            timezone,
+        }
+    }
+
+    /// The number of nanoseconds since 1970-01-01T00:00:00Z.
+    pub(crate) fn instant(&self) -> i128 {
+        let days = i128::from(days_from_civil(self.year, self.month, self.day));
+        let minutes = i128::from(self.hour) * 60 + i128::from(self.minute)
+            - i128::from(self.timezone.unwrap_or(0));
+        days * NANOS_PER_DAY
+            + minutes * NANOS_PER_MINUTE
+            + i128::from(self.second) * NANOS_PER_SECOND
+            + i128::from(self.nanosecond)
+    }
+
+    pub fn kind(&self) -> DateTimeKind {
+        self.kind
+    }
+
+    pub fn year(&self) -> i64 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    /// The seconds, including any fraction.
+    pub fn second(&self) -> f64 {
+        f64::from(self.second) + f64::from(self.nanosecond) / 1e9
+    }
+
+    /// The offset from UTC in minutes, if the value has a timezone.
+    pub fn timezone(&self) -> Option<i16> {
+        self.timezone
+    }
+
+    /// Converts the value to another kind, as casting does. A date
+    /// becomes a dateTime at midnight; a dateTime keeps only its date
+    /// or its time. Times cannot be converted.
+    pub fn to_kind(&self, kind: DateTimeKind) -> Option<DateTime> {
+        use self::DateTimeKind::{Date, Time};
+
+        let midnight = DateTime {
+            kind,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            nanosecond: 0,
+            ..*self
+        };
+        match (self.kind, kind) {
+            (from, to) if from == to => Some(*self),
+            (DateTimeKind::DateTime, Date) | (Date, DateTimeKind::DateTime) => Some(midnight),
+            (DateTimeKind::DateTime, Time) => Some(DateTime {
+                kind,
+                year: 1972,
+                month: 12,
+                day: 31,
+                ..*self
+            }),
+            _ => None,
+        }
+    }
+
+    /// Adds the duration. Months are added first, moving the day back
+    /// to the end of the month if needed. Times wrap around midnight
+    /// and may only have days, hours, minutes and seconds added.
+    pub fn add(&self, duration: &Duration) -> Option<DateTime> {
+        if self.kind == DateTimeKind::Time {
+            if duration.months != 0 {
+                return None;
+            }
+            let instant = self.instant() + duration.nanoseconds;
+            return Some(DateTime {
+                year: 1972,
+                month: 12,
+                day: 31,
+                ..DateTime::from_instant(self.kind, instant, self.timezone)
+            });
+        }
+
+        let months = self.year * 12 + i64::from(self.month) - 1 + duration.months;
+        let year = months.div_euclid(12);
+        let month = months.rem_euclid(12) as u8 + 1;
+        let shifted = DateTime {
+            year,
+            month,
+            day: self.day.min(days_in_month(year, month)),
+            ..*self
+        };
+
+        let result = DateTime::from_instant(
+            DateTimeKind::DateTime,
+            shifted.instant() + duration.nanoseconds,
+            self.timezone,
+        );
+        result.to_kind(self.kind)
+    }
+
+    /// The time between two values of the same kind.
+    pub fn subtract(&self, other: &DateTime) -> Option<Duration> {
+        if self.kind != other.kind {
+            return None;
+        }
+        Some(Duration::day_time(self.instant() - other.instant()))
+    }
+}
+
+impl PartialEq for DateTime {
+    fn eq(&self, other: &DateTime) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+// Only values of the same kind can be compared
+impl PartialOrd for DateTime {
+    fn partial_cmp(&self, other: &DateTime) -> Option<Ordering> {
+        if self.kind != other.kind {
+            return None;
+        }
+        Some(self.instant().cmp(&other.instant()))
+    }
+}
+
+fn write_seconds(f: &mut fmt::Formatter<'_>, seconds: u8, nanoseconds: u32) -> fmt::Result {
+    write!(f, "{:02}", seconds)?;
+    if nanoseconds > 0 {
+        let fraction = format!("{:09}", nanoseconds);
+        write!(f, ".{}", fraction.trim_end_matches('0'))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.kind != DateTimeKind::Time {
+            if self.year < 0 {
+                f.write_str("-")?;
+            }
+            write!(
+                f,
+                "{:04}-{:02}-{:02}",
+                self.year.abs(),
+                self.month,
+                self.day
+            )?;
+        }
+        if self.kind == DateTimeKind::DateTime {
+            f.write_str("T")?;
+        }
+        if self.kind != DateTimeKind::Date {
+            write!(f, "{:02}:{:02}:", self.hour, self.minute)?;
+            write_seconds(f, self.second, self.nanosecond)?;
+        }
+
+        match self.timezone {
+            None => Ok(()),
+            Some(0) => f.write_str("Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            }
+        }
+    }
+}
+
+impl Duration {
+    /// An `xs:yearMonthDuration` of the number of months.
+    pub fn year_month(months: i64) -> Duration {
+        Duration {
+            kind: DurationKind::YearMonth,
+            months,
+            nanoseconds: 0,
+        }
+    }
+
+    /// An `xs:dayTimeDuration` of the number of nanoseconds.
+    pub fn day_time(nanoseconds: i128) -> Duration {
+        Duration {
+            kind: DurationKind::DayTime,
+            months: 0,
+            nanoseconds,
+        }
+    }
+
+    /// Parses the lexical form of the kind of duration, such as
+    /// `P1Y2M` or `-PT1.5S`.
+    pub fn parse(s: &str, kind: DurationKind) -> Option<Duration> {
+        let mut scanner = Scanner::new(s);
+        let negative = scanner.consume(b'-');
+        scanner.expect(b'P')?;
+
+        let mut months = 0i64;
+        let mut nanoseconds = 0i128;
+        // Each designator may appear once, in the order of this list
+        let mut next_designator = 0;
+        let mut in_time = false;
+        let mut needs_component = true;
+
+        while !scanner.is_done() {
+            if scanner.consume(b'T') {
+                if in_time || needs_component && next_designator > 0 {
+                    return None;
+                }
+                in_time = true;
+                needs_component = true;
+                continue;
+            }
+
+            let whole = scanner.number()?;
+            let fraction = if scanner.consume(b'.') {
+                Some(scanner.fraction()?)
+            } else {
+                None
+            };
+            let designator = match (in_time, scanner.next()?) {
+                (false, b'Y') => 0,
+                (false, b'M') => 1,
+                (false, b'D') => 2,
+                (true, b'H') => 3,
+                (true, b'M') => 4,
+                (true, b'S') => 5,
+                _ => return None,
+            };
+
+            let allowed = match kind {
+                DurationKind::Duration => true,
+                DurationKind::YearMonth => designator < 2,
+                DurationKind::DayTime => designator >= 2,
+            };
+            if !allowed || designator < next_designator || (fraction.is_some() && designator != 5) {
+                return None;
+            }
+            next_designator = designator + 1;
+            needs_component = false;
+
+            let whole = i128::from(whole);
+            match designator {
+                0 => months = months.checked_add(whole as i64 * 12)?,
+                1 => months = months.checked_add(whole as i64)?,
+                2 => nanoseconds += whole * NANOS_PER_DAY,
+                3 => nanoseconds += whole * 60 * NANOS_PER_MINUTE,
+                4 => nanoseconds += whole * NANOS_PER_MINUTE,
+                _ => {
+                    nanoseconds += whole * NANOS_PER_SECOND + i128::from(fraction.unwrap_or(0));
+                }
+            }
+        }
+
+        if needs_component {
+            return None;
+        }
+
+        let (months, nanoseconds) = if negative {
+            (-months, -nanoseconds)
+        } else {
+            (months, nanoseconds)
+        };
+        Some(Duration {
+            kind,
+            months,
+            nanoseconds,
+        })
+    }
+
+    /// An `xs:duration` of months and nanoseconds. Both parts must
+    /// have the same sign.
+    pub fn new(months: i64, nanoseconds: i128) -> Option<Duration> {
+        if (months < 0 && nanoseconds > 0) || (months > 0 && nanoseconds < 0) {
+            return None;
+        }
+        Some(Duration {
+            kind: DurationKind::Duration,
+            months,
+            nanoseconds,
+        })
+    }
+
+    pub fn kind(&self) -> DurationKind {
+        self.kind
+    }
+
+    /// The years and months of the duration, as a number of months.
+    pub fn months(&self) -> i64 {
+        self.months
+    }
+
+    /// The days, hours, minutes and seconds of the duration, in
+    /// seconds.
+    pub fn seconds(&self) -> f64 {
+        self.nanoseconds as f64 / 1e9
+    }
+
+    /// The days, hours, minutes and seconds of the duration, in
+    /// nanoseconds.
+    pub fn nanoseconds(&self) -> i128 {
+        self.nanoseconds
+    }
+
+    /// Converts the duration to another kind, as casting does,
+    /// dropping the parts the new kind does not have.
+    pub fn to_kind(&self, kind: DurationKind) -> Duration {
+        match kind {
+            DurationKind::Duration => Duration { kind, ..*self },
+            DurationKind::YearMonth => Duration::year_month(self.months),
+            DurationKind::DayTime => Duration::day_time(self.nanoseconds),
+        }
+    }
+
+    pub fn negate(&self) -> Duration {
+        Duration {
+            months: -self.months,
+            nanoseconds: -self.nanoseconds,
+            ..*self
+        }
+    }
+
+    /// Adds two durations of the same kind. Durations that have both
+    /// months and seconds cannot be added.
+    pub fn add(&self, other: &Duration) -> Option<Duration> {
+        match (self.kind, other.kind) {
+            (DurationKind::YearMonth, DurationKind::YearMonth) => {
+                Some(Duration::year_month(self.months.checked_add(other.months)?))
+            }
+            (DurationKind::DayTime, DurationKind::DayTime) => {
+                Some(Duration::day_time(self.nanoseconds + other.nanoseconds))
+            }
+            _ => None,
+        }
+    }
+
+    /// Scales the duration. Year and month durations are rounded to
+    /// whole months.
+    pub fn multiply(&self, factor: f64) -> Option<Duration> {
+        if !factor.is_finite() {
+            return None;
+        }
+        match self.kind {
+            DurationKind::YearMonth => {
+                let months = (self.months as f64 * factor).round();
+                Some(Duration::year_month(months as i64))
+            }
+            DurationKind::DayTime => {
+                let nanoseconds = (self.nanoseconds as f64 * factor).round();
+                Some(Duration::day_time(nanoseconds as i128))
+            }
+            DurationKind::Duration => None,
+        }
+    }
+
+    /// The ratio of two durations of the same kind.
+    pub fn divide(&self, other: &Duration) -> Option<f64> {
+        match (self.kind, other.kind) {
+            (DurationKind::YearMonth, DurationKind::YearMonth) if other.months != 0 => {
+                Some(self.months as f64 / other.months as f64)
+            }
+            (DurationKind::DayTime, DurationKind::DayTime) if other.nanoseconds != 0 => {
+                Some(self.nanoseconds as f64 / other.nanoseconds as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Durations of any kind are equal when they have the same parts
+impl PartialEq for Duration {
+    fn eq(&self, other: &Duration) -> bool {
+        self.months == other.months && self.nanoseconds == other.nanoseconds
+    }
+}
+
+// Only durations that are both months or both seconds are ordered
+impl PartialOrd for Duration {
+    fn partial_cmp(&self, other: &Duration) -> Option<Ordering> {
+        if self == other {
+            Some(Ordering::Equal)
+        } else if self.months == 0 && other.months == 0 {
+            Some(self.nanoseconds.cmp(&other.nanoseconds))
+        } else if self.nanoseconds == 0 && other.nanoseconds == 0 {
+            Some(self.months.cmp(&other.months))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.months == 0 && self.nanoseconds == 0 {
+            return match self.kind {
+                DurationKind::YearMonth => f.write_str("P0M"),
+                _ => f.write_str("PT0S"),
+            };
+        }
+
+        if self.months < 0 || self.nanoseconds < 0 {
+            f.write_str("-")?;
+        }
+        f.write_str("P")?;
+
+        let months = self.months.abs();
+        if months >= 12 {
+            write!(f, "{}Y", months / 12)?;
+        }
+        if months % 12 != 0 {
+            write!(f, "{}M", months % 12)?;
+        }
+
+        let nanoseconds = self.nanoseconds.abs();
+        let days = nanoseconds / NANOS_PER_DAY;
+        if days > 0 {
+            write!(f, "{}D", days)?;
+        }
+
+        let time = nanoseconds % NANOS_PER_DAY;
+        if time > 0 {
+            f.write_str("T")?;
+            let seconds = time / NANOS_PER_SECOND;
+            if seconds >= 3600 {
+                write!(f, "{}H", seconds / 3600)?;
+            }
+            if seconds / 60 % 60 != 0 {
+                write!(f, "{}M", seconds / 60 % 60)?;
+            }
+            let nanoseconds = (time % NANOS_PER_SECOND) as u32;
+            if seconds % 60 != 0 || nanoseconds != 0 {
+                write!(f, "{}", seconds % 60)?;
+                if nanoseconds > 0 {
+                    let fraction = format!("{:09}", nanoseconds);
+                    write!(f, ".{}", fraction.trim_end_matches('0'))?;
+                }
+                f.write_str("S")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The errors that may occur while formatting a date or time
+#[derive(Debug, Snafu, Clone, PartialEq, Hash)]
+pub enum FormatError {
+    #[snafu(display("invalid picture string {:?}", picture))]
+    InvalidPicture { picture: String },
+    #[snafu(display("an {} has no [{}] component", type_name, component))]
+    ComponentNotAvailable {
+        component: char,
+        type_name: &'static str,
+    },
+}
+
+pub(crate) const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+pub(crate) const DAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// The width modifier of a variable marker, such as `,3-3`.
+#[derive(Debug, Default, Copy, Clone)]
+struct Width {
+    min: Option<usize>,
+    max: Option<usize>,
+    /// Only one width was written, such as `,2`
+    single: bool,
+}
+
+impl Width {
+    fn parse(s: &str) -> Option<Width> {
+        fn bound(s: &str) -> Option<Option<usize>> {
+            match s {
+                "*" => Some(None),
+                s => s.parse().ok().filter(|&n| n > 0).map(Some),
+            }
+        }
+
+        let (min, max, single) = match s.find('-') {
+            Some(i) => (bound(&s[..i])?, bound(&s[i + 1..])?, false),
+            None => (bound(s)?, None, true),
+        };
+        match (min, max) {
+            (Some(min), Some(max)) if max < min => None,
+            _ => Some(Width { min, max, single }),
+        }
+    }
+
+    fn apply(&self, mut s: String, padding: char) -> String {
+        if let Some(max) = self.max {
+            s = s.chars().take(max).collect();
+        }
+        while s.chars().count() < self.min.unwrap_or(0) {
+            s.push(padding);
+        }
+        s
+    }
+}
+
+fn ordinal_suffix(n: i64) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+fn roman(mut n: i64) -> Option<String> {
+    const NUMERALS: [(i64, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+
+    if !(1..4000).contains(&n) {
+        return None;
+    }
+    let mut s = String::new();
+    for &(value, numeral) in &NUMERALS {
+        while n >= value {
+            s.push_str(numeral);
+            n -= value;
+        }
+    }
+    Some(s)
+}
+
+fn words(n: i64, ordinal: bool) -> String {
+    const ONES: [&str; 20] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+
+    fn cardinal(n: i64) -> String {
+        match n {
+            0..=19 => ONES[n as usize].to_owned(),
+            20..=99 if n % 10 == 0 => TENS[n as usize / 10].to_owned(),
+            20..=99 => format!("{}-{}", TENS[n as usize / 10], ONES[n as usize % 10]),
+            100..=999 if n % 100 == 0 => format!("{} hundred", ONES[n as usize / 100]),
+            100..=999 => format!(
+                "{} hundred and {}",
+                ONES[n as usize / 100],
+                cardinal(n % 100)
+            ),
+            _ if n % 1000 == 0 => format!("{} thousand", cardinal(n / 1000)),
+            _ => format!("{} thousand {}", cardinal(n / 1000), cardinal(n % 1000)),
+        }
+    }
+
+    let s = cardinal(n.abs());
+    if !ordinal {
+        return s;
+    }
+
+    // Only the last word changes
+    let split = s.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (start, last) = s.split_at(split);
+    let last = match last {
+        "one" => "first".to_owned(),
+        "two" => "second".to_owned(),
+        "three" => "third".to_owned(),
+        "five" => "fifth".to_owned(),
+        "eight" => "eighth".to_owned(),
+        "nine" => "ninth".to_owned(),
+        "twelve" => "twelfth".to_owned(),
+        w if w.ends_with('y') => format!("{}ieth", &w[..w.len() - 1]),
+        w => format!("{}th", w),
+    };
+    format!("{}{}", start, last)
+}
+
+/// Changes the case of a name or of words to match the presentation
+/// modifier: `N` for upper case, `n` for lower case and `Nn` for
+/// title case.
+fn with_case(s: &str, token: &str) -> Option<String> {
+    match token {
+        "N" | "W" => Some(s.to_uppercase()),
+        "n" | "w" => Some(s.to_lowercase()),
+        "Nn" | "Ww" => Some(
+            s.split(' ')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+        ),
+        _ => None,
+    }
+}
+
+fn is_digit_pattern(token: &str) -> bool {
+    token.chars().any(|c| c.is_ascii_digit())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '#' || !c.is_alphanumeric())
+}
+
+/// A variable marker of a picture string, such as `[MNn,3-3]`.
+struct Marker<'a> {
+    component: char,
+    token: &'a str,
+    ordinal: bool,
+    width: Option<Width>,
+}
+
+impl<'a> Marker<'a> {
+    fn parse(marker: &'a str) -> Option<Marker<'a>> {
+        let mut chars = marker.chars();
+        let component = chars.next()?;
+        let rest = chars.as_str();
+
+        let (token, width) = match rest.rfind(',') {
+            Some(i) => (&rest[..i], Some(Width::parse(&rest[i + 1..])?)),
+            None => (rest, None),
+        };
+        // A trailing `o` asks for an ordinal, `t` for traditional numbering
+        let (token, ordinal) = match token.char_indices().last() {
+            Some((i, 'o')) if i > 0 => (&token[..i], true),
+            Some((i, 't')) if i > 0 => (&token[..i], false),
+            _ => (token, false),
+        };
+
+        Some(Marker {
+            component,
+            token,
+            ordinal,
+            width,
+        })
+    }
+
+    fn token_or<'t>(&self, default: &'t str) -> &'t str
+    where
+        'a: 't,
+    {
+        if self.token.is_empty() {
+            default
+        } else {
+            self.token
+        }
+    }
+
+    fn width(&self) -> Width {
+        self.width.unwrap_or_default()
+    }
+
+    fn number(&self, n: i64, default: &str) -> String {
+        self.number_with_token(n, self.token_or(default), default)
+    }
+
+    fn number_with_token(&self, n: i64, token: &str, default: &str) -> String {
+        if is_digit_pattern(token) {
+            let digits = token.chars().filter(char::is_ascii_digit).count();
+            let min = self.width.as_ref().and_then(|w| w.min).unwrap_or(digits);
+
+            let mut s = format!("{:0width$}", n.abs(), width = min);
+            if n < 0 {
+                s.insert(0, '-');
+            }
+            if self.ordinal {
+                s.push_str(ordinal_suffix(n.abs()));
+            }
+            return s;
+        }
+
+        let formatted = match token {
+            "I" => roman(n).map(|s| s.to_uppercase()),
+            "i" => roman(n),
+            "W" | "w" | "Ww" => with_case(&words(n, self.ordinal), token),
+            _ => None,
+        };
+        // Unsupported presentations fall back to the default
+        formatted.unwrap_or_else(|| self.number_with_token(n, default, "1"))
+    }
+
+    fn name_or_number(&self, names: &[&str], n: i64, default: &str) -> String {
+        let token = self.token_or(default);
+        match token {
+            "N" | "n" | "Nn" => {
+                let name = with_case(names[n as usize - 1], token).expect("name token");
+                self.width().apply(name, ' ')
+            }
+            _ => self.number_with_token(n, token, "1"),
+        }
+    }
+}
+
+impl DateTime {
+    fn type_name(&self) -> &'static str {
+        match self.kind {
+            DateTimeKind::DateTime => "xs:dateTime",
+            DateTimeKind::Date => "xs:date",
+            DateTimeKind::Time => "xs:time",
+        }
+    }
+
+    fn days(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    /// The day of the week, from 1 for Monday to 7 for Sunday.
+    pub(crate) fn weekday(&self) -> i64 {
+        // 1970-01-01 was a Thursday
+        (self.days() + 3).rem_euclid(7) + 1
+    }
+
+    pub(crate) fn day_of_year(&self) -> i64 {
+        self.days() - days_from_civil(self.year, 1, 1) + 1
+    }
+
+    /// The ISO 8601 week number, where the first week of the year is
+    /// the one with its first Thursday.
+    pub(crate) fn week_of_year(&self) -> i64 {
+        fn weeks_in_year(year: i64) -> i64 {
+            let january_first = (days_from_civil(year, 1, 1) + 3).rem_euclid(7) + 1;
+            if january_first == 4 || (january_first == 3 && is_leap_year(year)) {
+                53
+            } else {
+                52
+            }
+        }
+
+        let week = (self.day_of_year() - self.weekday() + 10) / 7;
+        if week < 1 {
+            weeks_in_year(self.year - 1)
+        } else if week > weeks_in_year(self.year) {
+            1
+        } else {
+            week
+        }
+    }
+
+    /// The week of the month, where the first week of the month is the
+    /// one with its first Thursday. Earlier days are in the last week
+    /// of the previous month.
+    fn week_of_month(&self) -> i64 {
+        let first = DateTime { day: 1, ..*self };
+        let first_monday = match first.weekday() {
+            // The first week starts in the previous month
+            weekday @ 1..=4 => 2 - weekday,
+            weekday => 9 - weekday,
+        };
+
+        let day = i64::from(self.day);
+        if day >= first_monday {
+            (day - first_monday) / 7 + 1
+        } else {
+            let last_of_previous = days_from_civil(self.year, self.month, 1) - 1;
+            let (year, month, day) = civil_from_days(last_of_previous);
+            DateTime {
+                year,
+                month,
+                day,
+                ..*self
+            }
+            .week_of_month()
+        }
+    }
+
+    fn timezone_string(&self, marker: &Marker<'_>, out: &mut String) {
+        let token = marker.token_or("01:01");
+        let offset = match self.timezone {
+            Some(offset) => offset,
+            // Military time zones use J for local time
+            None if token == "Z" => return out.push('J'),
+            None => return,
+        };
+
+        if token == "Z" {
+            let hours = offset / 60;
+            let letter = match hours {
+                0 => Some('Z'),
+                1..=9 => Some((b'A' + hours as u8 - 1) as char),
+                10..=12 => Some((b'K' + hours as u8 - 10) as char),
+                -12..=-1 => Some((b'N' + (-hours) as u8 - 1) as char),
+                _ => None,
+            };
+            if let (Some(letter), 0) = (letter, offset % 60) {
+                return out.push(letter);
+            }
+        }
+
+        if marker.component == 'z' {
+            out.push_str("GMT");
+        }
+        out.push(if offset < 0 { '-' } else { '+' });
+        let (hours, minutes) = (offset.abs() / 60, offset.abs() % 60);
+
+        let digits: Vec<_> = token.chars().filter(char::is_ascii_digit).collect();
+        let separator = token.chars().find(|c| !c.is_ascii_digit());
+        match separator {
+            Some(separator) if token != "Z" => {
+                let hour_digits = token.find(separator).unwrap_or(2);
+                out.push_str(&format!(
+                    "{:0width$}{}{:02}",
+                    hours,
+                    separator,
+                    minutes,
+                    width = hour_digits
+                ));
+            }
+            _ if digits.len() > 2 => out.push_str(&format!("{:02}{:02}", hours, minutes)),
+            _ => {
+                out.push_str(&format!("{:0width$}", hours, width = digits.len().max(1)));
+                if minutes != 0 {
+                    out.push_str(&format!(":{:02}", minutes));
+                }
+            }
+        }
+    }
+
+    fn format_marker(&self, marker: &Marker<'_>, out: &mut String) -> Result<(), FormatError> {
+        let has_date = self.kind != DateTimeKind::Time;
+        let has_time = self.kind != DateTimeKind::Date;
+        let available = match marker.component {
+            'Y' | 'M' | 'D' | 'd' | 'F' | 'W' | 'w' | 'E' => has_date,
+            'H' | 'h' | 'P' | 'm' | 's' | 'f' => has_time,
+            _ => true,
+        };
+        ensure!(
+            available,
+            ComponentNotAvailable {
+                component: marker.component,
+                type_name: self.type_name(),
+            }
+        );
+
+        let formatted = match marker.component {
+            'Y' => {
+                let mut year = marker.number(self.year.abs(), "1");
+                let two_digits = is_digit_pattern(marker.token)
+                    && marker.token.chars().filter(char::is_ascii_digit).count() == 2;
+                let max = match marker.width {
+                    // A single width such as `[Y,2]` is also the
+                    // largest a year may be
+                    Some(ref width) if width.single => width.min,
+                    Some(ref width) => width.max,
+                    None if two_digits => Some(2),
+                    None => None,
+                };
+                // Years keep their last digits
+                if let Some(max) = max {
+                    let len = year.chars().count();
+                    if len > max && year.chars().all(|c| c.is_ascii_digit()) {
+                        year = year[len - max..].to_owned();
+                    }
+                }
+                year
+            }
+            'M' => marker.name_or_number(&MONTH_NAMES, i64::from(self.month), "1"),
+            'D' => marker.number(i64::from(self.day), "1"),
+            'd' => marker.number(self.day_of_year(), "1"),
+            'F' => marker.name_or_number(&DAY_NAMES, self.weekday(), "n"),
+            'W' => marker.number(self.week_of_year(), "1"),
+            'w' => marker.number(self.week_of_month(), "1"),
+            'H' => marker.number(i64::from(self.hour), "1"),
+            'h' => {
+                let hour = match self.hour % 12 {
+                    0 => 12,
+                    hour => hour,
+                };
+                marker.number(i64::from(hour), "1")
+            }
+            'P' => {
+                let period = if self.hour < 12 { "am" } else { "pm" };
+                let token = marker.token_or("n");
+                let period = with_case(period, token).unwrap_or_else(|| period.to_owned());
+                marker.width().apply(period, ' ')
+            }
+            'm' => marker.number(i64::from(self.minute), "01"),
+            's' => marker.number(i64::from(self.second), "01"),
+            'f' => {
+                let token = marker.token_or("1");
+                let digits = token.chars().filter(char::is_ascii_digit).count().max(1);
+                let width = marker.width();
+                let (min, max) = match marker.width {
+                    Some(_) => (width.min.unwrap_or(1), width.max.unwrap_or(9)),
+                    None if digits > 1 => (digits, digits),
+                    None => (1, 9),
+                };
+
+                let fraction = format!("{:09}", self.nanosecond);
+                let mut fraction = fraction[..max.min(9)].trim_end_matches('0').to_owned();
+                while fraction.len() < min {
+                    fraction.push('0');
+                }
+                fraction
+            }
+            'Z' | 'z' => {
+                let mut timezone = String::new();
+                self.timezone_string(marker, &mut timezone);
+                timezone
+            }
+            'E' => if self.year > 0 { "AD" } else { "BC" }.to_owned(),
+            'C' => "ISO".to_owned(),
+            _ => return InvalidPicture { picture: "" }.fail(),
+        };
+
+        out.push_str(&formatted);
+        Ok(())
+    }
+
+    /// Formats the value as described by the picture string, as the
+    /// `format-date`, `format-time` and `format-dateTime` functions
+    /// do. Components are written in English and the Gregorian
+    /// calendar.
+    ///
+    /// ```
+    /// use sxd_xpath::datetime::{DateTime, DateTimeKind};
+    ///
+    /// let date = DateTime::parse("2024-01-05", DateTimeKind::Date).unwrap();
+    /// let formatted = date.format("[FNn], [MNn] [D1o] [Y]").unwrap();
+    /// assert_eq!("Friday, January 5th 2024", formatted);
+    /// ```
+    pub fn format(&self, picture: &str) -> Result<String, FormatError> {
+        let invalid = || FormatError::InvalidPicture {
+            picture: picture.to_owned(),
+        };
+
+        let mut formatted = String::new();
+        let mut chars = picture.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '[' if chars.peek() == Some(&'[') => {
+                    chars.next();
+                    formatted.push('[');
+                }
+                '[' => {
+                    let mut marker = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) if c.is_whitespace() => {}
+                            Some(c) => marker.push(c),
+                            None => return Err(invalid()),
+                        }
+                    }
+                    let marker = Marker::parse(&marker).ok_or_else(invalid)?;
+                    self.format_marker(&marker, &mut formatted)
+                        .map_err(|e| match e {
+                            FormatError::InvalidPicture { .. } => invalid(),
+                            e => e,
+                        })?;
+                }
+                ']' if chars.peek() == Some(&']') => {
+                    chars.next();
+                    formatted.push(']');
+                }
+                ']' => return Err(invalid()),
+                c => formatted.push(c),
+            }
+        }
+
+        Ok(formatted)
+    }
+}
+
+/// Whether the item is a date, time or duration.
+pub(crate) fn is_temporal(item: &Item<'_>) -> bool {
+    matches!(*item, Item::DateTime(_) | Item::Duration(_))
+}
+
+/// Compares two items when either is a date, time or duration. A
+/// string or node compared with one is first cast to its type.
+pub(crate) fn compare(left: &Item<'_>, right: &Item<'_>) -> Option<Ordering> {
+    fn untyped(item: &Item<'_>) -> Option<String> {
+        match *item {
+            Item::Node(n) => Some(n.string_value()),
+            Item::String(ref s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    match (left, right) {
+        (Item::DateTime(l), Item::DateTime(r)) => l.partial_cmp(r),
+        (Item::Duration(l), Item::Duration(r)) => l.partial_cmp(r),
+        (Item::DateTime(l), r) => {
+            DateTime::parse(&untyped(r)?, l.kind).and_then(|r| l.partial_cmp(&r))
+        }
+        (l, Item::DateTime(r)) => {
+            DateTime::parse(&untyped(l)?, r.kind).and_then(|l| l.partial_cmp(r))
+        }
+        (Item::Duration(l), r) => {
+            Duration::parse(&untyped(r)?, l.kind).and_then(|r| l.partial_cmp(&r))
+        }
+        (l, Item::Duration(r)) => {
+            Duration::parse(&untyped(l)?, r.kind).and_then(|l| l.partial_cmp(r))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn add<'d>(left: &Item<'d>, right: &Item<'d>) -> Option<Item<'d>> {
+    match (left, right) {
+        (Item::DateTime(dt), Item::Duration(d)) | (Item::Duration(d), Item::DateTime(dt)) => {
+            dt.add(d).map(Item::DateTime)
+        }
+        (Item::Duration(l), Item::Duration(r)) => l.add(r).map(Item::Duration),
+        _ => None,
+    }
+}
+
+pub(crate) fn subtract<'d>(left: &Item<'d>, right: &Item<'d>) -> Option<Item<'d>> {
+    match (left, right) {
+        (Item::DateTime(l), Item::DateTime(r)) => l.subtract(r).map(Item::Duration),
+        (Item::DateTime(l), Item::Duration(r)) => l.add(&r.negate()).map(Item::DateTime),
+        (Item::Duration(l), Item::Duration(r)) => l.add(&r.negate()).map(Item::Duration),
+        _ => None,
+    }
+}
+
+pub(crate) fn multiply<'d>(left: &Item<'d>, right: &Item<'d>) -> Option<Item<'d>> {
+    match (left, right) {
+        (Item::Duration(d), Item::Number(n)) | (Item::Number(n), Item::Duration(d)) => {
+            d.multiply(*n).map(Item::Duration)
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn divide<'d>(left: &Item<'d>, right: &Item<'d>) -> Option<Item<'d>> {
+    match (left, right) {
+        (Item::Duration(d), Item::Number(n)) => d.multiply(1.0 / n).map(Item::Duration),
+        (Item::Duration(l), Item::Duration(r)) => l.divide(r).map(Item::Number),
+        _ => None,
+    }
+}
+
+pub(crate) fn remainder<'d>(_left: &Item<'d>, _right: &Item<'d>) -> Option<Item<'d>> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date_time(s: &str) -> DateTime {
+        DateTime::parse(s, DateTimeKind::DateTime).expect("invalid dateTime")
+    }
+
+    fn duration(s: &str) -> Duration {
+        Duration::parse(s, DurationKind::Duration).expect("invalid duration")
+    }
+
+    #[test]
+    fn date_times_round_trip_through_their_lexical_form() {
+        for s in &[
+            "2024-02-29T23:59:59.125Z",
+            "-0044-03-15T12:00:00+05:30",
+            "1999-12-31T00:00:00",
+        ] {
+            assert_eq!(*s, date_time(s).to_string());
+        }
+        let time = DateTime::parse("08:30:00-02:00", DateTimeKind::Time).unwrap();
+        assert_eq!("08:30:00-02:00", time.to_string());
+    }
+
+    #[test]
+    fn invalid_dates_are_rejected() {
+        assert!(DateTime::parse("2023-02-29", DateTimeKind::Date).is_none());
+        assert!(DateTime::parse("2023-13-01", DateTimeKind::Date).is_none());
+        assert!(DateTime::parse("23-01-01", DateTimeKind::Date).is_none());
+        assert!(DateTime::parse("2023-01-01T25:00:00", DateTimeKind::DateTime).is_none());
+        assert!(DateTime::parse("2023-01-01+15:00", DateTimeKind::Date).is_none());
+    }
+
+    #[test]
+    fn end_of_day_is_the_start_of_the_next_day() {
+        assert_eq!(
+            "2024-01-01T00:00:00",
+            date_time("2023-12-31T24:00:00").to_string()
+        );
+    }
+
+    #[test]
+    fn date_times_are_compared_as_instants() {
+        assert_eq!(
+            date_time("2024-01-01T12:00:00+01:00"),
+            date_time("2024-01-01T11:00:00Z")
+        );
+        assert!(date_time("2024-01-01T00:00:00Z") > date_time("2023-12-31T23:00:00-00:30"));
+    }
+
+    #[test]
+    fn durations_are_written_canonically() {
+        assert_eq!("P1Y2M", duration("P14M").to_string());
+        assert_eq!("-P1DT1H0.5S", duration("-PT25H0.5S").to_string());
+        assert_eq!("PT0S", duration("P0D").to_string());
+        assert_eq!(
+            "P0M",
+            Duration::parse("P0Y", DurationKind::YearMonth)
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for s in &["P", "PT", "P1DT", "P1M1Y", "PT1.5M", "1D", "P-1D"] {
+            assert!(
+                Duration::parse(s, DurationKind::Duration).is_none(),
+                "{}",
+                s
+            );
+        }
+        assert!(Duration::parse("P1D", DurationKind::YearMonth).is_none());
+        assert!(Duration::parse("P1Y", DurationKind::DayTime).is_none());
+    }
+
+    #[test]
+    fn adding_months_stays_within_the_month() {
+        let one_month = Duration::year_month(1);
+        assert_eq!(
+            date_time("2024-02-29T00:00:00"),
+            date_time("2024-01-31T00:00:00").add(&one_month).unwrap()
+        );
+    }
+
+    #[test]
+    fn adding_time_to_a_date_keeps_only_the_date() {
+        let date = DateTime::parse("2024-12-31", DateTimeKind::Date).unwrap();
+        let result = date.add(&Duration::day_time(36 * 3600 * NANOS_PER_SECOND));
+        assert_eq!("2025-01-01", result.unwrap().to_string());
+    }
+
+    #[test]
+    fn times_wrap_around_midnight() {
+        let time = DateTime::parse("23:00:00", DateTimeKind::Time).unwrap();
+        let result = time.add(&Duration::day_time(2 * 3600 * NANOS_PER_SECOND));
+        assert_eq!("01:00:00", result.unwrap().to_string());
+    }
+
+    #[test]
+    fn subtracting_date_times_gives_the_time_between() {
+        let between = date_time("2024-03-01T00:00:00").subtract(&date_time("2024-02-28T12:00:00"));
+        assert_eq!("P1DT12H", between.unwrap().to_string());
+    }
+
+    #[test]
+    fn durations_of_mixed_parts_are_not_ordered() {
+        assert_eq!(None, duration("P1M").partial_cmp(&duration("P30D")));
+        assert!(duration("PT1H") < duration("PT61M"));
+        assert_eq!(duration("P12M"), duration("P1Y"));
+    }
+
+    fn format(s: &str, kind: DateTimeKind, picture: &str) -> Result<String, FormatError> {
+        DateTime::parse(s, kind)
+            .expect("invalid value")
+            .format(picture)
+    }
+
+    fn format_date(s: &str, picture: &str) -> String {
+        format(s, DateTimeKind::Date, picture).expect("could not format")
+    }
+
+    #[test]
+    fn dates_are_formatted_with_numbers_and_names() {
+        assert_eq!(
+            "2024-03-09",
+            format_date("2024-03-09", "[Y0001]-[M01]-[D01]")
+        );
+        assert_eq!("9 March 24", format_date("2024-03-09", "[D] [MNn] [Y01]"));
+        assert_eq!("24", format_date("2024-03-09", "[Y,2]"));
+        assert_eq!("024", format_date("2024-03-09", "[Y,3]"));
+        assert_eq!("2024", format_date("2024-03-09", "[Y,2-*]"));
+        assert_eq!("0024", format_date("0024-03-09", "[Y,4]"));
+        assert_eq!("SATURDAY", format_date("2024-03-09", "[FN]"));
+        assert_eq!("Sat", format_date("2024-03-09", "[FNn,3-3]"));
+        assert_eq!("69", format_date("2024-03-09", "[d]"));
+    }
+
+    #[test]
+    fn days_can_be_ordinals_words_and_numerals() {
+        assert_eq!("1st 2nd 3rd 11th 22nd", {
+            ["01", "02", "03", "11", "22"]
+                .iter()
+                .map(|d| format_date(&format!("2024-01-{}", d), "[D1o]"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        assert_eq!("Twenty-first", format_date("2024-01-21", "[DWwo]"));
+        assert_eq!(
+            "two thousand twenty-four",
+            format_date("2024-01-21", "[Yw]")
+        );
+        assert_eq!("MMXXIV", format_date("2024-01-21", "[YI]"));
+    }
+
+    #[test]
+    fn weeks_start_with_the_first_thursday() {
+        // 2021-01-01 was a Friday, so it falls in the last week of 2020
+        assert_eq!("53", format_date("2021-01-01", "[W]"));
+        assert_eq!("1", format_date("2021-01-04", "[W]"));
+        assert_eq!("1", format_date("2024-12-30", "[W]"));
+        // 2024-05-01 was a Wednesday, so it starts the first week of May
+        assert_eq!("1", format_date("2024-05-01", "[w]"));
+        // 2024-06-01 was a Saturday, so it ends the last week of May
+        assert_eq!("5", format_date("2024-06-01", "[w]"));
+    }
+
+    #[test]
+    fn times_are_formatted_with_hours_and_fractions() {
+        let time = |picture| format("13:05:09.250-05:00", DateTimeKind::Time, picture);
+        assert_eq!(Ok("1:05:09 pm".to_owned()), time("[h]:[m]:[s] [P]"));
+        assert_eq!(Ok("13.05.09,25".to_owned()), time("[H01].[m].[s],[f]"));
+        assert_eq!(Ok("250".to_owned()), time("[f001]"));
+        assert_eq!(Ok("-05:00".to_owned()), time("[Z]"));
+        assert_eq!(Ok("GMT-5".to_owned()), time("[z0]"));
+        assert_eq!(Ok("R".to_owned()), time("[ZZ]"));
+    }
+
+    #[test]
+    fn brackets_are_escaped_by_doubling() {
+        assert_eq!("[2024]", format_date("2024-01-01", "[[[Y]]]"));
+    }
+
+    #[test]
+    fn invalid_pictures_are_errors() {
+        for picture in &["[Y", "Y]", "[]", "[Q]", "[D,0]"] {
+            assert_eq!(
+                Err(FormatError::InvalidPicture {
+                    picture: (*picture).to_owned()
+                }),
+                format("2024-01-01", DateTimeKind::Date, picture)
+            );
+        }
+    }
+
+    #[test]
+    fn components_must_be_available_for_the_kind() {
+        assert_eq!(
+            Err(FormatError::ComponentNotAvailable {
+                component: 'H',
+                type_name: "xs:date",
+            }),
+            format("2024-01-01", DateTimeKind::Date, "[H]")
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Watches an XPath being evaluated.
+//!
+//! [`XPath::evaluate_debug`][] evaluates an XPath like
+//! [`XPath::evaluate`][], and tells a [`Debugger`][] about each
+//! expression before and after it is evaluated, along with the context
+//! node, position and size it is evaluated with. This shows, for
+//! example, what a predicate gives for each of the nodes it filters.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::ast::Expr;
+//! use sxd_xpath::context::Evaluation;
+//! use sxd_xpath::debug::Debugger;
+//! use sxd_xpath::expression::Error;
+//! use sxd_xpath::{Context, Factory, Value};
+//!
+//! /// Records the value of each comparison with where it was made.
+//! struct Comparisons(Vec<(usize, usize, bool)>);
+//!
+//! impl Debugger for Comparisons {
+//!     fn after(&mut self, expr: &Expr, context: &Evaluation<'_, '_>, result: Result<&Value<'_>, &Error>) {
+//!         if let (Expr::Binary { .. }, Ok(value)) = (expr, result) {
+//!             self.0.push((context.position, context.size, value.boolean()));
+//!         }
+//!     }
+//! }
+//!
+//! let package = parser::parse("<shop><item price='5'/><item price='20'/></shop>")
+//!     .expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let xpath = Factory::new().build("//item[@price > 10]").expect("Could not compile XPath");
+//! let mut comparisons = Comparisons(Vec::new());
+//! xpath
+//!     .evaluate_debug(&Context::new(), document.root(), &mut comparisons)
+//!     .expect("XPath evaluation failed");
+//!
+//! assert_eq!(vec![(1, 2, false), (2, 2, true)], comparisons.0);
+//! ```
+//!
+//! [`XPath::evaluate_debug`]: ../struct.XPath.html#method.evaluate_debug
+//! [`XPath::evaluate`]: ../struct.XPath.html#method.evaluate
+//! [`Debugger`]: trait.Debugger.html
+
+use std::cell::RefCell;
+
+use crate::ast::Expr;
+use crate::context::Evaluation;
+use crate::expression::Error;
+use crate::Value;
+
+/// Is told about each expression of an XPath as it is evaluated. An
+/// expression is evaluated once for each context it is evaluated with,
+/// such as a predicate for each node it filters. Each method does
+/// nothing by default.
+pub trait Debugger {
+    /// Called before the expression is evaluated.
+    fn before(&mut self, expr: &Expr, context: &Evaluation<'_, '_>) {
+        let _ = (expr, context);
+    }
+
+    /// Called after the expression is evaluated, with its value or the
+    /// error that it failed with.
+    fn after(
+        &mut self,
+        expr: &Expr,
+        context: &Evaluation<'_, '_>,
+        result: Result<&Value<'_>, &Error>,
+    ) {
+        let _ = (expr, context, result);
+    }
+}
+
+/// A debugger that can be shared by the contexts of an evaluation.
+pub(crate) trait Trace {
+    fn before(&self, expr: &Expr, context: &Evaluation<'_, '_>);
+
+    fn after(&self, expr: &Expr, context: &Evaluation<'_, '_>, result: Result<&Value<'_>, &Error>);
+}
+
+pub(crate) struct Tracer<'a, D: ?Sized>(pub(crate) RefCell<&'a mut D>);
+
+impl<D> Trace for Tracer<'_, D>
+where
+    D: Debugger + ?Sized,
+{
+    fn before(&self, expr: &Expr, context: &Evaluation<'_, '_>) {
+        self.0.borrow_mut().before(expr, context);
+    }
+
+    fn after(&self, expr: &Expr, context: &Evaluation<'_, '_>, result: Result<&Value<'_>, &Error>) {
+        self.0.borrow_mut().after(expr, context, result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::nodeset::Node;
+    use crate::{Context, Factory};
+
+    /// Records each expression with the node it is evaluated for.
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Debugger for Log {
+        fn before(&mut self, expr: &Expr, context: &Evaluation<'_, '_>) {
+            self.0.push(format!("> {} at {}", expr, name(context.node)));
+        }
+
+        fn after(
+            &mut self,
+            expr: &Expr,
+            context: &Evaluation<'_, '_>,
+            result: Result<&Value<'_>, &Error>,
+        ) {
+            let result = match result {
+                Ok(value) => value.string(),
+                Err(_) => String::from("error"),
+            };
+            self.0
+                .push(format!("< {} at {} = {}", expr, name(context.node), result));
+        }
+    }
+
+    fn name(node: Node<'_>) -> String {
+        node.prefixed_name().unwrap_or_else(|| String::from("/"))
+    }
+
+    #[test]
+    fn each_expression_is_reported_before_and_after_it_is_evaluated() {
+        let package = parser::parse("<a/>").expect("failed to parse XML");
+        let document = package.as_document();
+
+        let xpath = Factory::new()
+            .build("1 + $n")
+            .expect("Unable to build XPath");
+        let mut context = Context::new();
+        context.set_variable("n", 2.0);
+        let mut log = Log::default();
+        let value = xpath.evaluate_debug(&context, document.root(), &mut log);
+
+        assert_eq!(Ok(Value::Number(3.0)), value);
+        assert_eq!(
+            vec![
+                "> 1 + $n at /",
+                "> 1 at /",
+                "< 1 at / = 1",
+                "> $n at /",
+                "< $n at / = 2",
+                "< 1 + $n at / = 3",
+            ],
+            log.0
+        );
+    }
+
+    #[test]
+    fn predicates_are_reported_for_each_node() {
+        let package = parser::parse("<a><b/><c/></a>").expect("failed to parse XML");
+        let document = package.as_document();
+
+        let xpath = Factory::new()
+            .build("/a/*[self::c]")
+            .expect("Unable to build XPath");
+        let mut log = Log::default();
+        xpath
+            .evaluate_debug(&Context::new(), document.root(), &mut log)
+            .expect("Unable to evaluate XPath");
+
+        let predicates: Vec<_> = log.0.iter().filter(|l| l.starts_with("< self")).collect();
+        assert_eq!(vec!["< self::c at b = ", "< self::c at c = "], predicates);
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let package = parser::parse("<a/>").expect("failed to parse XML");
+        let document = package.as_document();
+
+        let xpath = Factory::new()
+            .build("$missing")
+            .expect("Unable to build XPath");
+        let mut log = Log::default();
+        let value = xpath.evaluate_debug(&Context::new(), document.root(), &mut log);
+
+        assert!(value.is_err());
+        assert_eq!(vec!["> $missing at /", "< $missing at / = error"], log.0);
+    }
+}
@@ -0,0 +1,383 @@
+//! Draws syntax trees as graphs.
+//!
+//! [`render`][] writes a syntax tree in the DOT language of Graphviz,
+//! with a box for each expression and step, and an arrow from each
+//! node to each of its parts that is labelled with what the part is
+//! for. This shows how an XPath was parsed, such as which operator a
+//! predicate belongs to.
+//!
+//! ```
+//! use sxd_xpath::Factory;
+//!
+//! let xpath = Factory::new().build("a[@b = 1]").expect("Could not compile XPath");
+//! let dot = xpath.to_dot();
+//!
+//! assert!(dot.starts_with("digraph xpath {"));
+//! assert!(dot.contains(r#"[label="attribute::b"]"#));
+//! ```
+//!
+//! The output can be drawn with `dot -Tsvg`.
+//!
+//! [`render`]: fn.render.html
+
+use crate::ast::{Expr, LookupKey, Quantifier, Step};
+use crate::format;
+use crate::OwnedPrefixedName;
+
+/// Writes the syntax tree as a DOT graph.
+pub fn render(expr: &Expr) -> String {
+    let mut graph = Graph {
+        out: String::from("digraph xpath {\n    node [shape=box];\n"),
+        nodes: 0,
+    };
+    graph.expr(expr);
+    graph.out.push_str("}\n");
+    graph.out
+}
+
+struct Graph {
+    out: String,
+    nodes: usize,
+}
+
+impl Graph {
+    /// Writes the node and its parts, giving the id of the node.
+    fn expr(&mut self, expr: &Expr) -> usize {
+        match *expr {
+            Expr::ContextNode => self.node("."),
+            Expr::RootNode => self.node("/"),
+            Expr::Literal(ref value) => {
+                let mut label = String::new();
+                format::write_literal(&mut label, value);
+                self.node(&label)
+            }
+            Expr::Variable(ref name) => self.node(&variable(name)),
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => {
+                let mut label = String::new();
+                format::write_name(&mut label, name);
+                label.push_str("()");
+
+                let id = self.node(&label);
+                self.numbered(id, "argument", arguments);
+                id
+            }
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => {
+                let id = self.node("path");
+                self.part(id, "start", start_point);
+                for (i, step) in steps.iter().enumerate() {
+                    let step = self.step(step);
+                    self.edge(id, step, &format!("step {}", i + 1));
+                }
+                id
+            }
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => {
+                let id = self.node("filter");
+                self.part(id, "nodes", expression);
+                self.part(id, "predicate", predicate);
+                id
+            }
+            Expr::Negation(ref expression) => {
+                let id = self.node("-");
+                self.part(id, "operand", expression);
+                id
+            }
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => {
+                let id = self.node(format::binary_operator(operator).0);
+                self.part(id, "left", left);
+                self.part(id, "right", right);
+                id
+            }
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => {
+                let id = self.node("if");
+                self.part(id, "condition", condition);
+                self.part(id, "then", consequent);
+                self.part(id, "else", alternative);
+                id
+            }
+            Expr::For {
+                ref variable,
+                ref sequence,
+                ref body,
+            } => {
+                let id = self.node(&format!("for {}", self::variable(variable)));
+                self.part(id, "in", sequence);
+                self.part(id, "return", body);
+                id
+            }
+            Expr::Let {
+                ref variable,
+                ref value,
+                ref body,
+            } => {
+                let id = self.node(&format!("let {}", self::variable(variable)));
+                self.part(id, ":=", value);
+                self.part(id, "return", body);
+                id
+            }
+            Expr::Quantified {
+                quantifier,
+                ref variable,
+                ref sequence,
+                ref test,
+            } => {
+                let quantifier = match quantifier {
+                    Quantifier::Some => "some",
+                    Quantifier::Every => "every",
+                };
+                let id = self.node(&format!("{} {}", quantifier, self::variable(variable)));
+                self.part(id, "in", sequence);
+                self.part(id, "satisfies", test);
+                id
+            }
+            Expr::InstanceOf {
+                ref expression,
+                ref sequence_type,
+            } => {
+                let id = self.node(&format!("instance of {}", sequence_type));
+                self.part(id, "operand", expression);
+                id
+            }
+            Expr::TreatAs {
+                ref expression,
+                ref sequence_type,
+            } => {
+                let id = self.node(&format!("treat as {}", sequence_type));
+                self.part(id, "operand", expression);
+                id
+            }
+            Expr::Cast {
+                ref expression,
+                ref single_type,
+            }
+            | Expr::Castable {
+                ref expression,
+                ref single_type,
+            } => {
+                let mut label = String::from(match *expr {
+                    Expr::Cast { .. } => "cast as ",
+                    _ => "castable as ",
+                });
+                format::write_name(&mut label, &single_type.name);
+                if single_type.optional {
+                    label.push('?');
+                }
+
+                let id = self.node(&label);
+                self.part(id, "operand", expression);
+                id
+            }
+            Expr::Sequence(ref expressions) => {
+                let id = self.node("sequence");
+                self.numbered(id, "item", expressions);
+                id
+            }
+            Expr::Map(ref entries) => {
+                let id = self.node("map");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    self.part(id, &format!("key {}", i + 1), key);
+                    self.part(id, &format!("value {}", i + 1), value);
+                }
+                id
+            }
+            Expr::SquareArray(ref members) => {
+                let id = self.node("array [ ]");
+                self.numbered(id, "member", members);
+                id
+            }
+            Expr::CurlyArray(ref members) => {
+                let id = self.node("array { }");
+                self.part(id, "members", members);
+                id
+            }
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => {
+                let label = match *key {
+                    LookupKey::Name(ref name) => format!("?{}", name),
+                    LookupKey::Wildcard => String::from("?*"),
+                    LookupKey::Expression(_) => String::from("?( )"),
+                };
+
+                let id = self.node(&label);
+                self.part(id, "in", expression);
+                if let LookupKey::Expression(ref key) = *key {
+                    self.part(id, "key", key);
+                }
+                id
+            }
+            Expr::InlineFunction {
+                ref parameters,
+                ref body,
+            } => {
+                let parameters: Vec<_> = parameters.iter().map(variable).collect();
+                let id = self.node(&format!("function({})", parameters.join(", ")));
+                self.part(id, "body", body);
+                id
+            }
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => {
+                let id = self.node("call");
+                self.part(id, "function", function);
+                self.numbered(id, "argument", arguments);
+                id
+            }
+        }
+    }
+
+    fn step(&mut self, step: &Step) -> usize {
+        let mut label = String::from(format::axis_name(step.axis));
+        label.push_str("::");
+        format::write_node_test(&mut label, &step.node_test);
+
+        let id = self.node(&label);
+        self.numbered(id, "predicate", &step.predicates);
+        id
+    }
+
+    fn part(&mut self, parent: usize, label: &str, expr: &Expr) {
+        let child = self.expr(expr);
+        self.edge(parent, child, label);
+    }
+
+    fn numbered(&mut self, parent: usize, label: &str, expressions: &[Expr]) {
+        for (i, expr) in expressions.iter().enumerate() {
+            self.part(parent, &format!("{} {}", label, i + 1), expr);
+        }
+    }
+
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        self.out
+            .push_str(&format!("    n{} [label=\"{}\"];\n", id, escape(label)));
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str) {
+        self.out.push_str(&format!(
+            "    n{} -> n{} [label=\"{}\"];\n",
+            from,
+            to,
+            escape(label)
+        ));
+    }
+}
+
+fn variable(name: &OwnedPrefixedName) -> String {
+    let mut label = String::from("$");
+    format::write_name(&mut label, name);
+    label
+}
+
+/// Labels are quoted, so quotes and backslashes in them are escaped.
+fn escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Factory;
+
+    fn render_xpath(xpath: &str) -> String {
+        let built = Factory::new().build(xpath).expect("Unable to build XPath");
+        render(built.ast())
+    }
+
+    #[test]
+    fn nodes_are_linked_to_their_parts() {
+        assert_eq!(
+            "digraph xpath {\n    node [shape=box];\n    \
+             n0 [label=\"+\"];\n    \
+             n1 [label=\"$a\"];\n    \
+             n0 -> n1 [label=\"left\"];\n    \
+             n2 [label=\"count()\"];\n    \
+             n3 [label=\"1\"];\n    \
+             n2 -> n3 [label=\"argument 1\"];\n    \
+             n0 -> n2 [label=\"right\"];\n\
+             }\n",
+            render_xpath("$a + count(1)")
+        );
+    }
+
+    #[test]
+    fn steps_are_nodes_of_their_path() {
+        let dot = render_xpath("/a[1]");
+
+        assert!(dot.contains("n0 [label=\"path\"]"));
+        assert!(dot.contains("n1 [label=\"/\"]"));
+        assert!(dot.contains("n0 -> n1 [label=\"start\"]"));
+        assert!(dot.contains("n2 [label=\"child::a\"]"));
+        assert!(dot.contains("n2 -> n3 [label=\"predicate 1\"]"));
+        assert!(dot.contains("n0 -> n2 [label=\"step 1\"]"));
+    }
+
+    #[test]
+    fn quotes_in_labels_are_escaped() {
+        let dot = render_xpath("concat('say \"hi\"', \"it's\")");
+
+        assert!(dot.contains(r#"[label="'say \"hi\"'"]"#));
+        assert!(dot.contains(r#"[label="\"it's\""]"#));
+    }
+
+    #[test]
+    fn every_kind_of_expression_has_a_label() {
+        let dot = render_xpath(
+            "for $i in (1, 2) return let $j := $i return \
+             (some $k in map { 1 : 2 }?* satisfies -$k instance of xs:integer, \
+             if (.) then [1]?(1) else array { } treat as item()*, \
+             function($f) { $f cast as xs:string? }($i))",
+        );
+
+        let labels = [
+            "for $i",
+            "let $j",
+            "some $k",
+            "map",
+            "?*",
+            "instance of xs:integer",
+            "if",
+            "array [ ]",
+            "?( )",
+            "array { }",
+            "treat as item()*",
+            "call",
+            "function($f)",
+            "cast as xs:string?",
+        ];
+        for label in labels.iter() {
+            assert!(dot.contains(&format!("[label=\"{}\"]", label)), "{}", label);
+        }
+    }
+}
@@ -1,15 +1,26 @@
-use snafu::{OptionExt, ResultExt, Snafu};
+// Expression constructors return boxed trait objects for the parser
+#![allow(clippy::new_ret_no_self)]
+
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::fmt;
+use std::rc::Rc;
 use sxd_document::QName;
 
+use crate::ast::{self, Occurrence, SingleType};
+use crate::atomic;
 use crate::axis::{Axis, AxisLike};
+use crate::compare;
 use crate::context;
+use crate::datetime::{self, DateTimeKind};
 use crate::function;
-use crate::node_test::NodeTest;
-use crate::nodeset::{Nodeset, OrderedNodes};
+use crate::map::{self, Map};
+use crate::node_test::{NodeTest, SubNodeTest};
+use crate::nodeset::{Node, Nodeset, OrderedNodes};
+use crate::tree;
 use crate::Value::{Boolean, Number};
-use crate::{LiteralValue, OwnedPrefixedName, Value};
+use crate::{Item, LiteralValue, OwnedPrefixedName, OwnedQName, Value};
 
 #[derive(Debug, Snafu, Clone, PartialEq, Hash)]
 #[cfg_attr(test, snafu(visibility(pub(crate))))]
@@ -24,13 +35,47 @@ pub enum Error {
     UnknownNamespace { prefix: String },
     #[snafu(display("error while evaluating function: {}", source))]
     FunctionEvaluation { source: function::Error },
+    /// expression did not evaluate to a single node
+    NotASingleNode,
+    /// expression did not evaluate to a single item
+    NotASingleItem,
+    #[snafu(display("cannot compare a {} with a {}", left, right))]
+    IncomparableValues {
+        left: &'static str,
+        right: &'static str,
+    },
+    #[snafu(display("unknown type {:?}", name))]
+    UnknownType { name: OwnedPrefixedName },
+    #[snafu(display("value does not match the sequence type {}", sequence_type))]
+    TypeMismatch { sequence_type: String },
+    #[snafu(display("error while casting: {}", source))]
+    CastFailed { source: atomic::Error },
+    #[snafu(display("range bound {} is not an integer", value))]
+    RangeBoundNotAnInteger { value: String },
+    /// map key did not evaluate to a single atomic value
+    InvalidMapKey,
+    #[snafu(display("map has more than one entry with the key {:?}", key))]
+    DuplicateMapKey { key: String },
+    /// lookup applied to an item that is not a map or an array
+    NotAMapOrArray,
+    #[snafu(display(
+        "array position {:?} is not valid for an array of size {}",
+        position,
+        size
+    ))]
+    InvalidArrayPosition { position: String, size: usize },
+    /// dynamic call of a value that is not a single function
+    NotAFunction,
+    #[snafu(display("cannot apply {} to a {} and a {}", operation, left, right))]
+    InvalidOperands {
+        operation: &'static str,
+        left: &'static str,
+        right: &'static str,
+    },
 }
 
 fn value_into_nodeset(v: Value<'_>) -> Result<Nodeset<'_>, Error> {
-    match v {
-        Value::Nodeset(ns) => Ok(ns),
-        _ => Err(Error::NotANodeset),
-    }
+    v.into_nodeset().map_err(|_| Error::NotANodeset)
 }
 
 // In these cases, we use document order. From the spec:
@@ -40,14 +85,68 @@ fn value_into_nodeset(v: Value<'_>) -> Result<Nodeset<'_>, Error> {
 // > the child axis
 //
 fn value_into_ordered_nodes(v: Value<'_>) -> Result<OrderedNodes<'_>, Error> {
-    match v {
-        Value::Nodeset(ns) => Ok(ns.document_order().into()),
-        _ => Err(Error::NotANodeset),
+    value_into_nodeset(v).map(|ns| ns.document_order().into())
+}
+
+/// The values as single items, when either is a date, time or
+/// duration. Those items are only found in sequences.
+pub(crate) fn temporal_operands<'d>(
+    left: &Value<'d>,
+    right: &Value<'d>,
+) -> Option<(Item<'d>, Item<'d>)> {
+    let is_temporal = |v: &Value<'_>| match *v {
+        Value::Sequence(ref items) => items.iter().any(datetime::is_temporal),
+        _ => false,
+    };
+    if !is_temporal(left) && !is_temporal(right) {
+        return None;
+    }
+
+    let (mut left, mut right) = (left.items(), right.items());
+    if left.len() != 1 || right.len() != 1 {
+        return None;
     }
+    Some((left.pop()?, right.pop()?))
 }
 
 pub trait Expression: fmt::Debug {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error>;
+
+    /// Evaluates the expression when only the first `limit` nodes of
+    /// the result, in document order, will be used. Expressions that
+    /// can stop early may leave out any other nodes; the rest return
+    /// the whole value.
+    fn evaluate_limited<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        limit: usize,
+    ) -> Result<Value<'d>, Error> {
+        let _ = limit;
+        self.evaluate(context)
+    }
+
+    /// Evaluates the expression for the first node of its result in
+    /// document order, stopping as soon as that node is known where
+    /// `evaluate_limited` can. Values that hold no nodes give `None`.
+    fn evaluate_first<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<Option<Node<'d>>, Error> {
+        match self.evaluate_limited(context, 1)? {
+            Value::Nodeset(nodes) => Ok(nodes.document_order_first()),
+            value => Ok(value.iter().next()),
+        }
+    }
+
+    /// Evaluates the boolean value of the expression. Expressions
+    /// that select nodes only need to find one of them, and `and` and
+    /// `or` stop once their value is known.
+    fn evaluate_boolean<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<bool, Error> {
+        self.evaluate(context).map(|v| v.boolean())
+    }
 }
 
 impl<T: ?Sized> Expression for Box<T>
@@ -57,6 +156,28 @@ where
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         (**self).evaluate(context)
     }
+
+    fn evaluate_limited<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        limit: usize,
+    ) -> Result<Value<'d>, Error> {
+        (**self).evaluate_limited(context, limit)
+    }
+
+    fn evaluate_first<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<Option<Node<'d>>, Error> {
+        (**self).evaluate_first(context)
+    }
+
+    fn evaluate_boolean<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<bool, Error> {
+        (**self).evaluate_boolean(context)
+    }
 }
 
 pub type SubExpression = Box<dyn Expression + 'static>;
@@ -65,7 +186,7 @@ macro_rules! binary_constructor(
     ($t:ident) => (
         impl $t {
             pub fn new(left: SubExpression, right: SubExpression) -> SubExpression {
-                Box::new($t{left: left, right: right})
+                Box::new($t{left, right})
             }
         }
     );
@@ -81,97 +202,165 @@ binary_constructor!(And);
 
 impl Expression for And {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        let left = self.left.evaluate(context)?.boolean();
-        let v = left && self.right.evaluate(context)?.boolean();
-        Ok(Boolean(v))
+        self.evaluate_boolean(context).map(Boolean)
+    }
+
+    fn evaluate_boolean<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<bool, Error> {
+        Ok(self.left.evaluate_boolean(context)? && self.right.evaluate_boolean(context)?)
     }
 }
 
-#[allow(missing_copy_implementations)]
+/// Converts the value of the expression to an atomic type.
 #[derive(Debug)]
-pub struct ContextNode;
+pub struct Cast {
+    pub expression: SubExpression,
+    pub single_type: SingleType,
+}
 
-impl Expression for ContextNode {
+impl Expression for Cast {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        Ok(Value::Nodeset(nodeset![context.node]))
+        let atomic_type = resolve_atomic_type(context, &self.single_type.name)?;
+        let value = self.expression.evaluate(context)?;
+
+        match atomic::atomize(value).map_err(|_| Error::NotASingleItem)? {
+            Some(item) => atomic_type.cast(item).map(Value::from).context(CastFailed),
+            None if self.single_type.optional => Ok(Value::Sequence(Vec::new())),
+            None => NotASingleItem.fail(),
+        }
     }
 }
 
+/// Tests whether the value of the expression can be converted to an
+/// atomic type.
 #[derive(Debug)]
-pub struct Equal {
-    pub left: SubExpression,
-    pub right: SubExpression,
+pub struct Castable {
+    pub expression: SubExpression,
+    pub single_type: SingleType,
 }
 
-binary_constructor!(Equal);
+impl Expression for Castable {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let atomic_type = resolve_atomic_type(context, &self.single_type.name)?;
+        let value = self.expression.evaluate(context)?;
 
-impl Equal {
-    fn boolean_evaluate<'c, 'd>(
-        &self,
-        context: &context::Evaluation<'c, 'd>,
-    ) -> Result<bool, Error> {
-        let left_val = self.left.evaluate(context)?;
-        let right_val = self.right.evaluate(context)?;
+        let castable = match atomic::atomize(value) {
+            Ok(Some(item)) => atomic_type.cast(item).is_ok(),
+            Ok(None) => self.single_type.optional,
+            Err(()) => false,
+        };
+        Ok(Boolean(castable))
+    }
+}
 
-        fn str_vals(nodes: &Nodeset<'_>) -> HashSet<String> {
-            nodes.iter().map(|n| n.string_value()).collect()
+/// Evaluates one of two expressions, depending on the boolean value
+/// of the condition.
+#[derive(Debug)]
+pub struct Conditional {
+    pub condition: SubExpression,
+    pub consequent: SubExpression,
+    pub alternative: SubExpression,
+}
+
+impl Expression for Conditional {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        if self.condition.evaluate(context)?.boolean() {
+            self.consequent.evaluate(context)
+        } else {
+            self.alternative.evaluate(context)
         }
+    }
+}
 
-        fn num_vals(nodes: &Nodeset<'_>) -> Vec<f64> {
-            // f64 isn't hashable...
-            nodes
-                .iter()
-                .map(|n| Value::String(n.string_value()).number())
-                .collect()
+#[allow(missing_copy_implementations)]
+#[derive(Debug)]
+pub struct ContextNode;
+
+impl Expression for ContextNode {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        match context.item {
+            Some(item) => Ok(item.clone().into()),
+            None => Ok(Value::Nodeset(nodeset![context.node])),
         }
+    }
+}
 
-        let v = match (&left_val, &right_val) {
-            (&Value::Nodeset(ref left_nodes), &Value::Nodeset(ref right_nodes)) => {
-                let left_strings = str_vals(left_nodes);
-                let right_strings = str_vals(right_nodes);
-                !left_strings.is_disjoint(&right_strings)
-            }
-            (&Value::Nodeset(ref nodes), &Number(val))
-            | (&Number(val), &Value::Nodeset(ref nodes)) => {
-                let numbers = num_vals(nodes);
-                numbers.iter().any(|n| *n == val)
-            }
-            (&Value::Nodeset(ref nodes), &Value::String(ref val))
-            | (&Value::String(ref val), &Value::Nodeset(ref nodes)) => {
-                let strings = str_vals(nodes);
-                strings.contains(val)
-            }
-            (&Boolean(_), _) | (_, &Boolean(_)) => left_val.boolean() == right_val.boolean(),
-            (&Number(_), _) | (_, &Number(_)) => left_val.number() == right_val.number(),
-            _ => left_val.string() == right_val.string(),
+/// Builds an array with one member for each item of the sequence.
+#[derive(Debug)]
+pub struct CurlyArray {
+    pub expression: SubExpression,
+}
+
+impl Expression for CurlyArray {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let items = self.expression.evaluate(context)?.into_items();
+        let members = items.into_iter().map(Value::from).collect();
+        Ok(Value::Sequence(vec![Item::Array(members)]))
+    }
+}
+
+/// Calls the function item that the expression evaluates to.
+#[derive(Debug)]
+pub struct DynamicCall {
+    pub function: SubExpression,
+    pub arguments: Vec<SubExpression>,
+}
+
+impl Expression for DynamicCall {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let function = match self.function.evaluate(context)?.into_items().as_mut_slice() {
+            [Item::Function(function)] => function.clone(),
+            _ => return NotAFunction.fail(),
         };
 
-        Ok(v)
+        let args = self
+            .arguments
+            .iter()
+            .map(|arg| arg.evaluate(context))
+            .collect::<Result<_, _>>()?;
+        function.call(context, args).context(FunctionEvaluation)
     }
 }
 
+#[derive(Debug)]
+pub struct Equal {
+    pub left: SubExpression,
+    pub right: SubExpression,
+}
+
+binary_constructor!(Equal);
+
 impl Expression for Equal {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        self.boolean_evaluate(context).map(Boolean)
+        let left_val = self.left.evaluate(context)?;
+        let right_val = self.right.evaluate(context)?;
+        let collation = context.default_collation();
+        Ok(Boolean(compare::equality(
+            &left_val, &right_val, true, collation,
+        )))
     }
 }
 
+/// Like `Equal`, a nodeset is unequal to a value when any of its nodes
+/// is, so this is not the negation of `Equal`.
 #[derive(Debug)]
 pub struct NotEqual {
-    equal: Equal,
+    pub left: SubExpression,
+    pub right: SubExpression,
 }
 
-impl NotEqual {
-    pub fn new(left: SubExpression, right: SubExpression) -> SubExpression {
-        Box::new(NotEqual {
-            equal: Equal { left, right },
-        })
-    }
-}
+binary_constructor!(NotEqual);
 
 impl Expression for NotEqual {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        self.equal.boolean_evaluate(context).map(|v| Boolean(!v))
+        let left_val = self.left.evaluate(context)?;
+        let right_val = self.right.evaluate(context)?;
+        let collation = context.default_collation();
+        Ok(Boolean(compare::equality(
+            &left_val, &right_val, false, collation,
+        )))
     }
 }
 
@@ -184,17 +373,86 @@ pub struct Function {
 impl Expression for Function {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         let name = resolve_prefixed_name(context, &self.name)?;
-        context
-            .function_for_name(name)
-            .context(UnknownFunction { name: &self.name })
-            .and_then(|fun| {
-                let args = self
-                    .arguments
-                    .iter()
-                    .map(|arg| arg.evaluate(context))
-                    .collect::<Result<_, _>>()?;
-                fun.evaluate(context, args).context(FunctionEvaluation)
-            })
+        let evaluate_arguments = || -> Result<Vec<_>, Error> {
+            self.arguments
+                .iter()
+                .map(|arg| arg.evaluate(context))
+                .collect()
+        };
+
+        if let Some(fun) = context.function_for_name(name) {
+            let args = evaluate_arguments()?;
+            return fun.evaluate(context, args).context(FunctionEvaluation);
+        }
+
+        // Each atomic type has a constructor function that casts its
+        // argument, such as `xs:date('2024-01-31')`
+        let atomic_type = context
+            .atomic_type_for_name(name)
+            .context(UnknownFunction { name: &self.name })?;
+        let mut args = function::Args(evaluate_arguments()?);
+        args.exactly(1).context(FunctionEvaluation)?;
+        let value = args.pop_value().context(FunctionEvaluation)?;
+
+        match atomic::atomize(value).map_err(|_| Error::NotASingleItem)? {
+            Some(item) => atomic_type.cast(item).map(Value::from).context(CastFailed),
+            None => Ok(Value::Sequence(Vec::new())),
+        }
+    }
+}
+
+/// Creates a function item. Variables bound where the function is
+/// created remain visible to its body.
+#[derive(Debug)]
+pub struct InlineFunction {
+    pub parameters: Vec<OwnedPrefixedName>,
+    pub body: Rc<dyn Expression>,
+}
+
+impl Expression for InlineFunction {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|name| resolve_prefixed_name(context, name).map(OwnedQName::from))
+            .collect::<Result<_, _>>()?;
+
+        let function =
+            function::FunctionItem::new(parameters, self.body.clone(), context.bound_variables());
+        Ok(Value::Sequence(vec![Item::Function(function)]))
+    }
+}
+
+/// Tests whether the value of the expression matches the sequence
+/// type.
+#[derive(Debug)]
+pub struct InstanceOf {
+    pub expression: SubExpression,
+    pub sequence_type: SequenceType,
+}
+
+impl Expression for InstanceOf {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let value = self.expression.evaluate(context)?;
+        self.sequence_type.matches(context, &value).map(Boolean)
+    }
+}
+
+/// Evaluates the body with the variable bound to the value.
+#[derive(Debug)]
+pub struct Let {
+    pub variable: OwnedPrefixedName,
+    pub value: SubExpression,
+    pub body: SubExpression,
+}
+
+impl Expression for Let {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let name = OwnedQName::from(resolve_prefixed_name(context, &self.variable)?);
+        let value = self.value.evaluate(context)?;
+
+        let binding = context.bind(name, value);
+        self.body.evaluate(&context.new_context_with(&binding))
     }
 }
 
@@ -215,10 +473,118 @@ impl Expression for Literal {
     }
 }
 
+/// Selects entries of maps, or members of arrays, by their keys.
+#[derive(Debug)]
+pub struct Lookup {
+    pub expression: SubExpression,
+    pub key: LookupKey,
+}
+
+#[derive(Debug)]
+pub enum LookupKey {
+    Name(String),
+    Expression(SubExpression),
+    Wildcard,
+}
+
+impl Lookup {
+    fn select_from_array<'d>(
+        members: Vec<Value<'d>>,
+        keys: Option<&[Item<'d>]>,
+        result: &mut Vec<Item<'d>>,
+    ) -> Result<(), Error> {
+        let keys = match keys {
+            Some(keys) => keys,
+            None => {
+                result.extend(members.into_iter().flat_map(Value::into_items));
+                return Ok(());
+            }
+        };
+
+        for key in keys {
+            let member = match *key {
+                Item::Number(position) => map::array_member(&members, position),
+                _ => None,
+            };
+            let member = member.context(InvalidArrayPosition {
+                position: key.string(),
+                size: members.len(),
+            })?;
+            result.extend(member.clone().into_items());
+        }
+        Ok(())
+    }
+
+    fn select_from_map<'d>(map: Map<'d>, keys: Option<&[Item<'d>]>, result: &mut Vec<Item<'d>>) {
+        match keys {
+            Some(keys) => {
+                for value in keys.iter().filter_map(|key| map.get(key)) {
+                    result.extend(value.clone().into_items());
+                }
+            }
+            None => result.extend(map.values().cloned().flat_map(Value::into_items)),
+        }
+    }
+}
+
+impl Expression for Lookup {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let items = self.expression.evaluate(context)?.into_items();
+
+        let keys = match self.key {
+            LookupKey::Name(ref name) => Some(vec![Item::String(name.clone())]),
+            LookupKey::Expression(ref expression) => {
+                Some(atomic::atomize_all(expression.evaluate(context)?))
+            }
+            LookupKey::Wildcard => None,
+        };
+        let keys = keys.as_deref();
+
+        let mut result = Vec::new();
+        for item in items {
+            match item {
+                Item::Map(map) => Lookup::select_from_map(map, keys, &mut result),
+                Item::Array(members) => Lookup::select_from_array(members, keys, &mut result)?,
+                _ => return NotAMapOrArray.fail(),
+            }
+        }
+
+        Ok(Value::Sequence(result))
+    }
+}
+
+/// Builds a map from the key and value of each entry. Each key must
+/// be a single atomic value and appear only once.
+#[derive(Debug)]
+pub struct MapConstructor {
+    pub entries: Vec<(SubExpression, SubExpression)>,
+}
+
+impl Expression for MapConstructor {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let mut map = Map::new();
+
+        for (key, value) in &self.entries {
+            let key = atomic::atomize(key.evaluate(context)?)
+                .ok()
+                .flatten()
+                .filter(Item::is_atomic)
+                .context(InvalidMapKey)?;
+            ensure!(
+                !map.contains_key(&key),
+                DuplicateMapKey { key: key.string() }
+            );
+            map.insert(key, value.evaluate(context)?);
+        }
+
+        Ok(Value::Sequence(vec![Item::Map(map)]))
+    }
+}
+
 pub struct Math {
     left: SubExpression,
     right: SubExpression,
-    operation: fn(f64, f64) -> f64,
+    arithmetic: Arithmetic,
 }
 
 fn add(a: f64, b: f64) -> f64 {
@@ -237,45 +603,89 @@ fn modulus(a: f64, b: f64) -> f64 {
     a % b
 }
 
+/// One of the arithmetic operators, applied to values that have
+/// already been evaluated.
+#[derive(Copy, Clone)]
+pub(crate) struct Arithmetic {
+    operation: fn(f64, f64) -> f64,
+    /// Used instead when either operand is a date, time or duration
+    temporal_operation: for<'d> fn(&Item<'d>, &Item<'d>) -> Option<Item<'d>>,
+    name: &'static str,
+}
+
+impl Arithmetic {
+    pub(crate) const ADDITION: Arithmetic = Arithmetic {
+        operation: add,
+        temporal_operation: datetime::add,
+        name: "+",
+    };
+
+    pub(crate) const SUBTRACTION: Arithmetic = Arithmetic {
+        operation: subtract,
+        temporal_operation: datetime::subtract,
+        name: "-",
+    };
+
+    pub(crate) const MULTIPLICATION: Arithmetic = Arithmetic {
+        operation: multiply,
+        temporal_operation: datetime::multiply,
+        name: "*",
+    };
+
+    pub(crate) const DIVISION: Arithmetic = Arithmetic {
+        operation: divide,
+        temporal_operation: datetime::divide,
+        name: "div",
+    };
+
+    pub(crate) const REMAINDER: Arithmetic = Arithmetic {
+        operation: modulus,
+        temporal_operation: datetime::remainder,
+        name: "mod",
+    };
+
+    pub(crate) fn apply<'d>(self, left: &Value<'d>, right: &Value<'d>) -> Result<Value<'d>, Error> {
+        if let Some((l, r)) = temporal_operands(left, right) {
+            return (self.temporal_operation)(&l, &r)
+                .map(Value::from)
+                .context(InvalidOperands {
+                    operation: self.name,
+                    left: ValueComparison::type_name(&l),
+                    right: ValueComparison::type_name(&r),
+                });
+        }
+
+        Ok(Number((self.operation)(left.number(), right.number())))
+    }
+}
+
 impl Math {
-    pub fn addition(left: SubExpression, right: SubExpression) -> SubExpression {
+    fn with(left: SubExpression, right: SubExpression, arithmetic: Arithmetic) -> SubExpression {
         Box::new(Math {
             left,
             right,
-            operation: add,
+            arithmetic,
         })
     }
 
+    pub fn addition(left: SubExpression, right: SubExpression) -> SubExpression {
+        Math::with(left, right, Arithmetic::ADDITION)
+    }
+
     pub fn subtraction(left: SubExpression, right: SubExpression) -> SubExpression {
-        Box::new(Math {
-            left,
-            right,
-            operation: subtract,
-        })
+        Math::with(left, right, Arithmetic::SUBTRACTION)
     }
 
     pub fn multiplication(left: SubExpression, right: SubExpression) -> SubExpression {
-        Box::new(Math {
-            left,
-            right,
-            operation: multiply,
-        })
+        Math::with(left, right, Arithmetic::MULTIPLICATION)
     }
 
     pub fn division(left: SubExpression, right: SubExpression) -> SubExpression {
-        Box::new(Math {
-            left,
-            right,
-            operation: divide,
-        })
+        Math::with(left, right, Arithmetic::DIVISION)
     }
 
     pub fn remainder(left: SubExpression, right: SubExpression) -> SubExpression {
-        Box::new(Math {
-            left,
-            right,
-            operation: modulus,
-        })
+        Math::with(left, right, Arithmetic::REMAINDER)
     }
 }
 
@@ -283,8 +693,7 @@ impl Expression for Math {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         let left = self.left.evaluate(context)?;
         let right = self.right.evaluate(context)?;
-        let op = self.operation;
-        Ok(Number(op(left.number(), right.number())))
+        self.arithmetic.apply(&left, &right)
     }
 }
 
@@ -299,84 +708,457 @@ impl fmt::Debug for Math {
 }
 
 #[derive(Debug)]
-pub struct Negation {
-    pub expression: SubExpression,
+pub struct Negation {
+    pub expression: SubExpression,
+}
+
+impl Expression for Negation {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let value = self.expression.evaluate(context)?;
+        match value.items()[..] {
+            [Item::Duration(ref d)] => Ok(Item::Duration(d.negate()).into()),
+            _ => Ok(Number(-value.number())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Or {
+    left: SubExpression,
+    right: SubExpression,
+}
+
+binary_constructor!(Or);
+
+impl Expression for Or {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        self.evaluate_boolean(context).map(Boolean)
+    }
+
+    fn evaluate_boolean<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<bool, Error> {
+        Ok(self.left.evaluate_boolean(context)? || self.right.evaluate_boolean(context)?)
+    }
+}
+
+/// Tells the debugger of the evaluation, if there is one, about
+/// evaluating the expression.
+#[derive(Debug)]
+pub struct Traced {
+    pub expr: ast::Expr,
+    pub expression: SubExpression,
+}
+
+impl Expression for Traced {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let tracer = match context.tracer() {
+            Some(tracer) => tracer,
+            None => return self.expression.evaluate(context),
+        };
+
+        tracer.before(&self.expr, context);
+        let result = self.expression.evaluate(context);
+        tracer.after(&self.expr, context, result.as_ref());
+        result
+    }
+}
+
+#[derive(Debug)]
+pub struct Path {
+    start_point: SubExpression,
+    steps: Vec<Step>,
+}
+
+impl Path {
+    pub fn new(start_point: SubExpression, steps: Vec<Step>) -> SubExpression {
+        Box::new(Path { start_point, steps })
+    }
+}
+
+impl Path {
+    fn evaluate_steps<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        mut result: Nodeset<'d>,
+    ) -> Result<Nodeset<'d>, Error> {
+        for step in &self.steps {
+            result = step.evaluate(context, result)?;
+        }
+        Ok(result)
+    }
+}
+
+impl Expression for Path {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let result = self.start_point.evaluate(context)?;
+        let result = value_into_nodeset(result)?;
+
+        self.evaluate_steps(context, result).map(Value::Nodeset)
+    }
+
+    fn evaluate_limited<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        limit: usize,
+    ) -> Result<Value<'d>, Error> {
+        let result = self.start_point.evaluate(context)?;
+        let result = value_into_nodeset(result)?;
+
+        if !Search::can_order(&self.steps, &result) {
+            return self.evaluate_steps(context, result).map(Value::Nodeset);
+        }
+        let found = Search::new(&self.steps, limit).run(context, result)?;
+        Ok(Value::Nodeset(Nodeset::from_document_order(found)))
+    }
+
+    fn evaluate_first<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<Option<Node<'d>>, Error> {
+        let result = self.start_point.evaluate(context)?;
+        let result = value_into_nodeset(result)?;
+
+        if !Search::can_order(&self.steps, &result) {
+            let result = self.evaluate_steps(context, result)?;
+            return Ok(result.document_order_first());
+        }
+        let found = Search::new(&self.steps, 1).run(context, result)?;
+        Ok(found.into_iter().next())
+    }
+
+    fn evaluate_boolean<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<bool, Error> {
+        let result = self.start_point.evaluate(context)?;
+        let result = value_into_nodeset(result)?;
+
+        let found = Search::any(&self.steps).run(context, result)?;
+        Ok(!found.is_empty())
+    }
+}
+
+/// Follows the steps of a path from one node at a time, looking for
+/// the first nodes the path selects in document order. Nothing that
+/// can only lead to nodes after those already found is looked at:
+/// when the remaining steps have forward axes, a node after the last
+/// node found is skipped, along with the nodes after it.
+///
+/// For the boolean value of a path, any one node will do, so the
+/// search stops at the first node it finds and never orders them.
+struct Search<'s, 'd> {
+    steps: &'s [Step],
+    /// Whether the first nodes in document order are wanted, rather
+    /// than any node
+    ordered: bool,
+    /// Whether the steps from each position on only select nodes at
+    /// or after the nodes they start from
+    forward: Vec<bool>,
+    limit: usize,
+    order: tree::LazyDocOrder<Node<'d>>,
+    /// The nodes each step has already started from
+    visited: Vec<HashSet<Node<'d>>>,
+    /// The first nodes found so far, in document order
+    found: Vec<Node<'d>>,
+}
+
+impl<'s, 'd> Search<'s, 'd> {
+    fn new(steps: &'s [Step], limit: usize) -> Self {
+        let mut forward = vec![true; steps.len() + 1];
+        for (i, step) in steps.iter().enumerate().rev() {
+            forward[i] = forward[i + 1] && step.axis.is_forward();
+        }
+
+        Search {
+            steps,
+            ordered: true,
+            forward,
+            limit,
+            order: tree::LazyDocOrder::new(),
+            visited: vec![HashSet::new(); steps.len()],
+            found: Vec::new(),
+        }
+    }
+
+    fn any(steps: &'s [Step]) -> Self {
+        Search {
+            ordered: false,
+            ..Search::new(steps, 1)
+        }
+    }
+
+    /// Whether the nodes can be compared without numbering the whole
+    /// document. Namespace nodes, and nodes in different trees, are
+    /// ordered as `DocOrder` orders them, so they are left to it.
+    fn can_order(steps: &[Step], starting_nodes: &Nodeset<'d>) -> bool {
+        steps.iter().all(|step| step.axis != Axis::Namespace)
+            && tree::LazyDocOrder::can_order(starting_nodes)
+    }
+
+    fn run<'c>(
+        mut self,
+        context: &context::Evaluation<'c, 'd>,
+        starting_nodes: Nodeset<'d>,
+    ) -> Result<Vec<Node<'d>>, Error> {
+        if self.limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut starting_nodes: Vec<_> = starting_nodes.into_iter().collect();
+        if self.ordered {
+            let order = &mut self.order;
+            starting_nodes.sort_by(|&l, &r| order.compare(l, r));
+        }
+
+        for node in starting_nodes {
+            if self.visit(context, 0, node)? || self.is_done() {
+                break;
+            }
+        }
+        Ok(self.found)
+    }
+
+    /// Follows the steps from the one at `level` on, starting from the
+    /// node. Returns whether the node was skipped because everything
+    /// it leads to comes after the nodes already found.
+    fn visit<'c>(
+        &mut self,
+        context: &context::Evaluation<'c, 'd>,
+        level: usize,
+        node: Node<'d>,
+    ) -> Result<bool, Error> {
+        if self.is_after_found(level, node) {
+            return Ok(true);
+        }
+
+        let steps = self.steps;
+        let step = match steps.get(level) {
+            Some(step) => step,
+            None => {
+                self.add(node);
+                return Ok(false);
+            }
+        };
+        // The starting nodes are already unique
+        if level > 0 && !self.visited[level].insert(node) {
+            return Ok(false);
+        }
+
+        // A forward axis selects its nodes in document order, so the
+        // ones after a skipped node would be skipped too
+        let in_order = step.axis.is_forward();
+        step.select_while(context, node, |selected| {
+            let skipped = self.visit(context, level + 1, selected)?;
+            Ok(!(self.is_done() || in_order && skipped))
+        })?;
+        Ok(false)
+    }
+
+    fn is_done(&self) -> bool {
+        !self.ordered && !self.found.is_empty()
+    }
+
+    fn is_after_found(&mut self, level: usize, node: Node<'d>) -> bool {
+        if !self.ordered || !self.forward[level] || self.found.len() < self.limit {
+            return false;
+        }
+        match self.found.last() {
+            Some(&last) => self.order.compare(node, last) == Ordering::Greater,
+            None => false,
+        }
+    }
+
+    fn add(&mut self, node: Node<'d>) {
+        if !self.ordered {
+            self.found.push(node);
+            return;
+        }
+
+        let Search {
+            ref mut found,
+            ref mut order,
+            limit,
+            ..
+        } = *self;
+
+        if let Err(i) = found.binary_search_by(|&f| order.compare(f, node)) {
+            found.insert(i, node);
+            found.truncate(limit);
+        }
+    }
+}
+
+/// Selects the nodes of an absolute path whose value of a key equals
+/// a value. When an index attached to the context was built for the
+/// path and key, the nodes are looked up in it instead.
+#[derive(Debug)]
+pub struct IndexedLookup {
+    /// The path without the comparison
+    pub path: ast::Expr,
+    pub key: ast::Expr,
+    pub value: SubExpression,
+    /// The path with the comparison
+    pub fallback: SubExpression,
 }
 
-impl Expression for Negation {
+impl Expression for IndexedLookup {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        self.expression
-            .evaluate(context)
-            .map(|r| Number(-r.number()))
+        let root = tree::Document::root(&context.node.owner());
+        let index = context
+            .indexes()
+            .iter()
+            .find(|index| index.answers(&self.path, &self.key, root));
+        let index = match index {
+            Some(index) if context.default_collation().is_none() => index,
+            _ => return self.fallback.evaluate(context),
+        };
+
+        let values = match self.value.evaluate(context)? {
+            Value::String(s) => vec![s],
+            Value::Nodeset(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+            _ => return self.fallback.evaluate(context),
+        };
+        let mut nodes = Nodeset::new();
+        for value in values {
+            for &node in index.get(&value) {
+                nodes.add(node);
+            }
+        }
+        Ok(Value::Nodeset(nodes))
     }
 }
 
 #[derive(Debug)]
-pub struct Or {
-    left: SubExpression,
-    right: SubExpression,
+pub struct Filter {
+    node_selector: SubExpression,
+    predicate: Predicate,
 }
 
-binary_constructor!(Or);
+impl Filter {
+    pub fn new(node_selector: SubExpression, predicate: SubExpression) -> SubExpression {
+        let predicate = Predicate {
+            expression: predicate,
+        };
+        Box::new(Filter {
+            node_selector,
+            predicate,
+        })
+    }
+}
 
-impl Expression for Or {
+impl Expression for Filter {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        let left = self.left.evaluate(context)?.boolean();
-        let v = left || self.right.evaluate(context)?.boolean();
-        Ok(Boolean(v))
+        match self.node_selector.evaluate(context)? {
+            Value::Sequence(items) => self
+                .predicate
+                .select_items(context, items)
+                .map(Value::Sequence),
+            value => value_into_ordered_nodes(value)
+                .and_then(|nodes| self.predicate.select(context, nodes))
+                .map(|nodes| Value::Nodeset(nodes.into())),
+        }
     }
 }
 
+/// Evaluates the body once for each item of the sequence, with the
+/// variable bound to that item, and concatenates the results.
 #[derive(Debug)]
-pub struct Path {
-    start_point: SubExpression,
-    steps: Vec<Step>,
+pub struct For {
+    pub variable: OwnedPrefixedName,
+    pub sequence: SubExpression,
+    pub body: SubExpression,
 }
 
-impl Path {
-    pub fn new(start_point: SubExpression, steps: Vec<Step>) -> SubExpression {
-        Box::new(Path { start_point, steps })
+impl Expression for For {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let name = OwnedQName::from(resolve_prefixed_name(context, &self.variable)?);
+        let items = self.sequence.evaluate(context)?.into_items();
+
+        let mut result = Vec::new();
+        for item in items {
+            let binding = context.bind(name.clone(), item.into());
+            let context = context.new_context_with(&binding);
+            result.extend(self.body.evaluate(&context)?.into_items());
+        }
+
+        Ok(Value::Sequence(result))
     }
 }
 
-impl Expression for Path {
+/// Tests whether some, or every, item of the sequence satisfies the
+/// test when bound to the variable.
+#[derive(Debug)]
+pub struct Quantified {
+    pub every: bool,
+    pub variable: OwnedPrefixedName,
+    pub sequence: SubExpression,
+    pub test: SubExpression,
+}
+
+impl Expression for Quantified {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        let result = self.start_point.evaluate(context)?;
-        let mut result = value_into_nodeset(result)?;
+        let name = OwnedQName::from(resolve_prefixed_name(context, &self.variable)?);
+        let items = self.sequence.evaluate(context)?.into_items();
 
-        for step in &self.steps {
-            result = step.evaluate(context, result)?;
+        for item in items {
+            let binding = context.bind(name.clone(), item.into());
+            let context = context.new_context_with(&binding);
+            let satisfied = self.test.evaluate(&context)?.boolean();
+
+            // Stop at the first item that decides the answer
+            if satisfied != self.every {
+                return Ok(Value::Boolean(satisfied));
+            }
         }
 
-        Ok(Value::Nodeset(result))
+        Ok(Value::Boolean(self.every))
     }
 }
 
+/// Produces the sequence of integers from the left bound to the right
+/// bound, inclusive.
 #[derive(Debug)]
-pub struct Filter {
-    node_selector: SubExpression,
-    predicate: Predicate,
+pub struct Range {
+    pub left: SubExpression,
+    pub right: SubExpression,
 }
 
-impl Filter {
-    pub fn new(node_selector: SubExpression, predicate: SubExpression) -> SubExpression {
-        let predicate = Predicate {
-            expression: predicate,
-        };
-        Box::new(Filter {
-            node_selector,
-            predicate,
-        })
+binary_constructor!(Range);
+
+impl Range {
+    fn bound(value: Value<'_>) -> Result<Option<f64>, Error> {
+        if value.items().is_empty() {
+            return Ok(None);
+        }
+
+        let n = value.number();
+        ensure!(
+            n.is_finite() && n.fract() == 0.0,
+            RangeBoundNotAnInteger {
+                value: value.string()
+            }
+        );
+        Ok(Some(n))
     }
 }
 
-impl Expression for Filter {
+impl Expression for Range {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        self.node_selector
-            .evaluate(context)
-            .and_then(value_into_ordered_nodes)
-            .and_then(|nodes| self.predicate.select(context, nodes))
-            .map(|nodes| Value::Nodeset(nodes.into()))
+        let start = Range::bound(self.left.evaluate(context)?)?;
+        let end = Range::bound(self.right.evaluate(context)?)?;
+
+        let mut items = Vec::new();
+        if let (Some(start), Some(end)) = (start, end) {
+            let mut n = start;
+            while n <= end {
+                items.push(Item::Number(n));
+                n += 1.0;
+            }
+        }
+
+        Ok(Value::Sequence(items))
     }
 }
 
@@ -437,8 +1219,11 @@ impl Expression for Relational {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         let left_val = self.left.evaluate(context)?;
         let right_val = self.right.evaluate(context)?;
-        let op = self.operation;
-        Ok(Boolean(op(left_val.number(), right_val.number())))
+        Ok(Boolean(compare::relation(
+            &left_val,
+            &right_val,
+            self.operation,
+        )))
     }
 }
 
@@ -452,13 +1237,191 @@ impl fmt::Debug for Relational {
     }
 }
 
+/// Compares the identity (`is`) or the document order (`<<`, `>>`) of
+/// two nodes.
+#[derive(Debug)]
+pub struct NodeComparison {
+    pub left: SubExpression,
+    pub right: SubExpression,
+    pub ordering: Ordering,
+}
+
+impl NodeComparison {
+    pub fn is(left: SubExpression, right: SubExpression) -> SubExpression {
+        Box::new(NodeComparison {
+            left,
+            right,
+            ordering: Ordering::Equal,
+        })
+    }
+
+    pub fn precedes(left: SubExpression, right: SubExpression) -> SubExpression {
+        Box::new(NodeComparison {
+            left,
+            right,
+            ordering: Ordering::Less,
+        })
+    }
+
+    pub fn follows(left: SubExpression, right: SubExpression) -> SubExpression {
+        Box::new(NodeComparison {
+            left,
+            right,
+            ordering: Ordering::Greater,
+        })
+    }
+
+    fn single_node(value: Value<'_>) -> Result<Option<Node<'_>>, Error> {
+        let nodes = value_into_nodeset(value)?;
+        ensure!(nodes.size() <= 1, NotASingleNode);
+        Ok(nodes.into_iter().next())
+    }
+}
+
+impl Expression for NodeComparison {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let left = NodeComparison::single_node(self.left.evaluate(context)?)?;
+        let right = NodeComparison::single_node(self.right.evaluate(context)?)?;
+
+        match (left, right) {
+            (Some(left), Some(right)) => {
+                // Detached nodes share a position, so identity is
+                // checked directly
+                let v = match self.ordering {
+                    Ordering::Equal => left == right,
+                    ordering => left.compare_document_order(&right) == ordering,
+                };
+                Ok(Boolean(v))
+            }
+            // Comparing with the empty sequence gives the empty sequence
+            _ => Ok(Value::Sequence(Vec::new())),
+        }
+    }
+}
+
+/// Compares two single items, as the value comparisons of XPath 2.0
+/// do. Unlike the general comparisons, sequences of more than one
+/// item are an error and items are never converted to another type.
+pub struct ValueComparison {
+    pub left: SubExpression,
+    pub right: SubExpression,
+    pub operation: fn(Option<Ordering>) -> bool,
+}
+
+fn value_equal(o: Option<Ordering>) -> bool {
+    o == Some(Ordering::Equal)
+}
+fn value_not_equal(o: Option<Ordering>) -> bool {
+    o != Some(Ordering::Equal)
+}
+fn value_less_than(o: Option<Ordering>) -> bool {
+    o == Some(Ordering::Less)
+}
+fn value_less_than_or_equal(o: Option<Ordering>) -> bool {
+    matches!(o, Some(Ordering::Less) | Some(Ordering::Equal))
+}
+fn value_greater_than(o: Option<Ordering>) -> bool {
+    o == Some(Ordering::Greater)
+}
+fn value_greater_than_or_equal(o: Option<Ordering>) -> bool {
+    matches!(o, Some(Ordering::Greater) | Some(Ordering::Equal))
+}
+
+macro_rules! value_comparison_constructor(
+    ($name:ident, $operation:ident) => (
+        pub fn $name(left: SubExpression, right: SubExpression) -> SubExpression {
+            Box::new(ValueComparison {
+                left,
+                right,
+                operation: $operation,
+            })
+        }
+    );
+);
+
+impl ValueComparison {
+    value_comparison_constructor!(equal, value_equal);
+    value_comparison_constructor!(not_equal, value_not_equal);
+    value_comparison_constructor!(less_than, value_less_than);
+    value_comparison_constructor!(less_than_or_equal, value_less_than_or_equal);
+    value_comparison_constructor!(greater_than, value_greater_than);
+    value_comparison_constructor!(greater_than_or_equal, value_greater_than_or_equal);
+
+    /// Nodes are compared by their string values
+    fn single_item(value: Value<'_>) -> Result<Option<Item<'_>>, Error> {
+        atomic::atomize(value).map_err(|_| Error::NotASingleItem)
+    }
+
+    fn type_name(item: &Item<'_>) -> &'static str {
+        match *item {
+            Item::Node(_) => "node",
+            Item::Boolean(_) => "boolean",
+            Item::Number(_) => "number",
+            Item::String(_) => "string",
+            Item::DateTime(ref dt) => match dt.kind() {
+                DateTimeKind::DateTime => "dateTime",
+                DateTimeKind::Date => "date",
+                DateTimeKind::Time => "time",
+            },
+            Item::Duration(_) => "duration",
+            Item::Map(_) => "map",
+            Item::Array(_) => "array",
+            Item::Function(_) => "function",
+        }
+    }
+}
+
+impl Expression for ValueComparison {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let left = ValueComparison::single_item(self.left.evaluate(context)?)?;
+        let right = ValueComparison::single_item(self.right.evaluate(context)?)?;
+
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            // Comparing with the empty sequence gives the empty sequence
+            _ => return Ok(Value::Sequence(Vec::new())),
+        };
+
+        let ordering = match (&left, &right) {
+            (Item::Boolean(l), Item::Boolean(r)) => Some(l.cmp(r)),
+            (Item::Number(l), Item::Number(r)) => l.partial_cmp(r),
+            (Item::String(l), Item::String(r)) => match context.default_collation() {
+                Some(collation) => Some(collation.compare(l, r)),
+                None => Some(l.cmp(r)),
+            },
+            (Item::DateTime(l), Item::DateTime(r)) if l.kind() == r.kind() => l.partial_cmp(r),
+            (Item::Duration(l), Item::Duration(r)) => l.partial_cmp(r),
+            (l, r) => {
+                return IncomparableValues {
+                    left: ValueComparison::type_name(l),
+                    right: ValueComparison::type_name(r),
+                }
+                .fail()
+            }
+        };
+
+        Ok(Boolean((self.operation)(ordering)))
+    }
+}
+
+impl fmt::Debug for ValueComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ValueComparison {{ left: {:?}, right: {:?} }}",
+            self.left, self.right
+        )
+    }
+}
+
 #[allow(missing_copy_implementations)]
 #[derive(Debug)]
 pub struct RootNode;
 
 impl Expression for RootNode {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        Ok(Value::Nodeset(nodeset![context.node.document().root()]))
+        let root = tree::Document::root(&context.node.owner());
+        Ok(Value::Nodeset(nodeset![root]))
     }
 }
 
@@ -483,6 +1446,25 @@ impl Predicate {
             .collect()
     }
 
+    /// Selects items of a sequence, keeping their order.
+    fn select_items<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        items: Vec<Item<'d>>,
+    ) -> Result<Vec<Item<'d>>, Error> {
+        let size = items.len();
+        let mut selected = Vec::new();
+
+        for (i, item) in items.iter().enumerate() {
+            let item_context = context.new_context_for_item(item, i + 1, size);
+            if self.matches(&item_context)? {
+                selected.push(item.clone());
+            }
+        }
+
+        Ok(selected)
+    }
+
     fn matches(&self, context: &context::Evaluation<'_, '_>) -> Result<bool, Error> {
         let value = self.expression.evaluate(context)?;
 
@@ -551,6 +1533,104 @@ where
 
         Ok(unique)
     }
+
+    /// Visits the nodes this step selects from one starting node, in
+    /// the order of the axis, until `f` returns false. Returns whether
+    /// every node was visited. Without predicates, the axis stops
+    /// looking for nodes once `f` returns false; predicates need to
+    /// know every node first, for `position()` and `last()`.
+    fn select_while<'c, 'd, F>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        node: Node<'d>,
+        mut f: F,
+    ) -> Result<bool, Error>
+    where
+        F: FnMut(Node<'d>) -> Result<bool, Error>,
+    {
+        let child_context = context.new_context_for(node);
+
+        if self.predicates.is_empty() {
+            let mut result = Ok(());
+            let finished = self.axis.select_nodes_while(
+                &child_context,
+                &self.node_test,
+                &mut |n| match f(n) {
+                    Ok(more) => more,
+                    Err(e) => {
+                        result = Err(e);
+                        false
+                    }
+                },
+            );
+            return result.map(|()| finished);
+        }
+
+        let mut nodes = self.axis.select_nodes(&child_context, &self.node_test);
+        for predicate in &self.predicates {
+            nodes = predicate.select(context, nodes)?;
+        }
+        for node in Vec::from(nodes) {
+            if !f(node)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Concatenates the items of each expression, as in `(1, 'a', b)`.
+#[derive(Debug)]
+pub struct Sequence {
+    pub expressions: Vec<SubExpression>,
+}
+
+impl Expression for Sequence {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let mut items = Vec::new();
+        for expression in &self.expressions {
+            items.extend(expression.evaluate(context)?.into_items());
+        }
+        Ok(Value::Sequence(items))
+    }
+}
+
+/// Builds an array with one member for each expression.
+#[derive(Debug)]
+pub struct SquareArray {
+    pub members: Vec<SubExpression>,
+}
+
+impl Expression for SquareArray {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let members = self
+            .members
+            .iter()
+            .map(|member| member.evaluate(context))
+            .collect::<Result<_, _>>()?;
+        Ok(Value::Sequence(vec![Item::Array(members)]))
+    }
+}
+
+/// Returns the value of the expression unchanged, after asserting that
+/// it matches the sequence type.
+#[derive(Debug)]
+pub struct TreatAs {
+    pub expression: SubExpression,
+    pub sequence_type: SequenceType,
+}
+
+impl Expression for TreatAs {
+    fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
+        let value = self.expression.evaluate(context)?;
+        ensure!(
+            self.sequence_type.matches(context, &value)?,
+            TypeMismatch {
+                sequence_type: self.sequence_type.description.clone(),
+            }
+        );
+        Ok(value)
+    }
 }
 
 #[derive(Debug)]
@@ -571,6 +1651,43 @@ impl Expression for Union {
         left_nodes.extend(right_nodes);
         Ok(Value::Nodeset(left_nodes))
     }
+
+    fn evaluate_limited<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        limit: usize,
+    ) -> Result<Value<'d>, Error> {
+        // The first nodes of the union are among the first nodes of
+        // each side
+        let as_nodes = |e: &SubExpression| {
+            e.evaluate_limited(context, limit)
+                .and_then(value_into_nodeset)
+        };
+
+        let mut left_nodes = as_nodes(&self.left)?;
+        let right_nodes = as_nodes(&self.right)?;
+
+        left_nodes.extend(right_nodes);
+        Ok(Value::Nodeset(left_nodes))
+    }
+
+    fn evaluate_boolean<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+    ) -> Result<bool, Error> {
+        self.evaluate_first(context).map(|n| n.is_some())
+    }
+}
+
+/// The prefixes that may be used without registering them, as in
+/// XPath 3.1. A namespace registered in the context takes precedence.
+pub(crate) fn predeclared_namespace(prefix: &str) -> Option<&'static str> {
+    match prefix {
+        "xs" => Some(atomic::XML_SCHEMA_NAMESPACE_URI),
+        "map" => Some(function::MAP_NAMESPACE_URI),
+        "array" => Some(function::ARRAY_NAMESPACE_URI),
+        _ => None,
+    }
 }
 
 fn resolve_prefixed_name<'a>(
@@ -578,9 +1695,12 @@ fn resolve_prefixed_name<'a>(
     name: &'a OwnedPrefixedName,
 ) -> Result<QName<'a>, Error> {
     // What about a "default" namespace?
-    let ns_uri = match name.prefix.as_ref().map(|p| &**p) {
+    let ns_uri = match name.prefix.as_deref() {
         None => None,
-        Some(prefix) => match context.namespace_for(prefix) {
+        Some(prefix) => match context
+            .namespace_for(prefix)
+            .or_else(|| predeclared_namespace(prefix))
+        {
             None => return UnknownNamespace { prefix }.fail(),
             Some(uri) => Some(uri),
         },
@@ -589,6 +1709,88 @@ fn resolve_prefixed_name<'a>(
     Ok(QName::with_namespace_uri(ns_uri, name.local_part.as_str()))
 }
 
+/// The evaluable form of an [`ast::SequenceType`][].
+///
+/// [`ast::SequenceType`]: ../ast/struct.SequenceType.html
+#[derive(Debug)]
+pub struct SequenceType {
+    pub item_type: Option<ItemType>,
+    pub occurrence: Occurrence,
+    /// The sequence type as written, for error messages
+    pub description: String,
+}
+
+#[derive(Debug)]
+pub enum ItemType {
+    Item,
+    Kind(SubNodeTest),
+    Atomic(OwnedPrefixedName),
+}
+
+impl SequenceType {
+    fn matches<'d>(
+        &self,
+        context: &context::Evaluation<'_, 'd>,
+        value: &Value<'d>,
+    ) -> Result<bool, Error> {
+        let items = value.items();
+
+        let item_type = match self.item_type {
+            Some(ref item_type) => item_type,
+            None => return Ok(items.is_empty()),
+        };
+
+        let count_matches = match self.occurrence {
+            Occurrence::ExactlyOne => items.len() == 1,
+            Occurrence::ZeroOrOne => items.len() <= 1,
+            Occurrence::ZeroOrMore => true,
+            Occurrence::OneOrMore => !items.is_empty(),
+        };
+        if !count_matches {
+            return Ok(false);
+        }
+
+        for item in &items {
+            if !item_type.matches(context, item)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl ItemType {
+    fn matches<'d>(
+        &self,
+        context: &context::Evaluation<'_, 'd>,
+        item: &Item<'d>,
+    ) -> Result<bool, Error> {
+        match (self, item) {
+            (ItemType::Item, _) => Ok(true),
+            (ItemType::Kind(test), Item::Node(node)) => {
+                let mut result = OrderedNodes::new();
+                test.test(&context.new_context_for(*node), &mut result);
+                Ok(result.size() > 0)
+            }
+            (ItemType::Kind(_), _) => Ok(false),
+            (ItemType::Atomic(_), Item::Node(_)) => Ok(false),
+            (ItemType::Atomic(name), item) => {
+                resolve_atomic_type(context, name).map(|t| t.matches(item))
+            }
+        }
+    }
+}
+
+fn resolve_atomic_type<'c>(
+    context: &context::Evaluation<'c, '_>,
+    name: &OwnedPrefixedName,
+) -> Result<&'c dyn atomic::Type, Error> {
+    let qname = resolve_prefixed_name(context, name)?;
+    context
+        .atomic_type_for_name(qname)
+        .context(UnknownType { name })
+}
+
 #[derive(Debug)]
 pub struct Variable {
     pub name: OwnedPrefixedName,
@@ -717,6 +1919,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn expression_equal_compares_number_value_of_nodeset_to_number() {
         let package = Package::new();
         let mut setup = Setup::new(&package);
@@ -834,6 +2037,32 @@ mod test {
         assert_eq!(res, Ok(Boolean(false)));
     }
 
+    #[test]
+    fn expression_equal_compares_strings_with_the_default_collation() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        setup
+            .context
+            .set_default_collation(crate::collation::HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI);
+
+        let a_str = Box::new(Literal {
+            value: Value::String("hello".to_owned()),
+        });
+        let b_str = Box::new(Literal {
+            value: Value::String("HeLLo".to_owned()),
+        });
+
+        let expr = Equal {
+            left: a_str,
+            right: b_str,
+        };
+
+        let context = setup.context();
+        let res = expr.evaluate(&context);
+
+        assert_eq!(res, Ok(Boolean(true)));
+    }
+
     #[test]
     fn expression_not_equal_negates_equality() {
         let package = Package::new();
@@ -0,0 +1,131 @@
+//! The [EXSLT common][common] functions.
+//!
+//! [common]: http://exslt.org/exsl/index.html
+
+use super::COMMON_NAMESPACE_URI as NS;
+use crate::context::{self, Context};
+use crate::function::{Args, Error, Function};
+use crate::nodeset::Nodeset;
+use crate::{Item, Value};
+
+/// Returns the nodes of its argument as a nodeset. Other values are
+/// converted to a text node in a new result tree fragment, as are the
+/// atomic items of a sequence.
+struct NodeSet;
+
+impl Function for NodeSet {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+
+        let items = match args.pop_value()? {
+            Value::Nodeset(nodes) => return Ok(Value::Nodeset(nodes)),
+            Value::Sequence(items) => items,
+            atomic => vec![Item::String(atomic.string())],
+        };
+
+        let mut fragment = None;
+        let mut nodes = Nodeset::new();
+        for item in items {
+            match item {
+                Item::Node(node) => nodes.add(node),
+                atomic => {
+                    let fragment = match fragment {
+                        Some(fragment) => fragment,
+                        None => *fragment.insert(context.new_fragment().ok_or(Error::NoFragments)?),
+                    };
+                    let text = fragment.document().create_text(&atomic.string());
+                    fragment.append_child(text);
+                    nodes.add(text);
+                }
+            }
+        }
+        Ok(Value::Nodeset(nodes))
+    }
+}
+
+/// Registers the common functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "node-set"), NodeSet);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use super::*;
+    use crate::fragment::Fragments;
+    use crate::nodeset::Node;
+
+    #[test]
+    fn nodesets_are_returned_unchanged() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let nodes = nodeset![doc.root()];
+        let result = NodeSet.evaluate(&evaluation, vec![Value::Nodeset(nodes.clone())]);
+        assert_eq!(Ok(Value::Nodeset(nodes)), result);
+    }
+
+    #[test]
+    fn other_values_become_text_nodes() {
+        let fragments = Fragments::new();
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        context.set_fragments(&fragments);
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let nodes = match NodeSet.evaluate(&evaluation, vec![Value::Number(12.5)]) {
+            Ok(Value::Nodeset(nodes)) => nodes,
+            other => panic!("expected a nodeset, was {:?}", other),
+        };
+        let texts: Vec<_> = nodes.iter().map(Node::text).collect();
+        assert_eq!(1, texts.len());
+        assert_eq!(Some("12.5"), texts[0].map(|t| t.text()));
+    }
+
+    #[test]
+    fn sequences_keep_their_nodes() {
+        let fragments = Fragments::new();
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        context.set_fragments(&fragments);
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let root = Node::from(doc.root());
+        let sequence = Value::Sequence(vec![Item::Node(root), "a".into(), "b".into()]);
+        let nodes = match NodeSet.evaluate(&evaluation, vec![sequence]) {
+            Ok(Value::Nodeset(nodes)) => nodes,
+            other => panic!("expected a nodeset, was {:?}", other),
+        };
+
+        assert_eq!(3, nodes.size());
+        assert!(nodes.contains(root));
+        let fragment = nodes
+            .iter()
+            .filter_map(|n| n.text())
+            .map(|t| t.parent())
+            .collect::<Vec<_>>();
+        assert_eq!(2, fragment.len());
+        assert_eq!(fragment[0], fragment[1]);
+    }
+
+    #[test]
+    fn text_nodes_cannot_be_created_without_fragment_storage() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let result = NodeSet.evaluate(&evaluation, vec!["a".into()]);
+        assert_eq!(Err(Error::NoFragments), result);
+    }
+}
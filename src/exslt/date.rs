@@ -0,0 +1,539 @@
+//! The [EXSLT dates and times][date] functions.
+//!
+//! Functions that take an optional date use the current date and time
+//! when it is omitted. Dates that cannot be parsed give `NaN` from the
+//! functions that return numbers and the empty string from the others.
+//!
+//! [date]: http://exslt.org/date/index.html
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::DATES_AND_TIMES_NAMESPACE_URI as NS;
+use crate::context::{self, Context};
+use crate::datetime::{
+    is_leap_year, DateTime, DateTimeKind, Duration, DurationKind, DAY_NAMES, MONTH_NAMES,
+    NANOS_PER_SECOND,
+};
+use crate::function::{Args, Error, Function};
+use crate::Value;
+
+/// The current date and time in UTC, to the second.
+fn now() -> DateTime {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| i128::from(d.as_secs()));
+    DateTime::from_instant(
+        DateTimeKind::DateTime,
+        since_epoch * NANOS_PER_SECOND,
+        Some(0),
+    )
+}
+
+/// Parses the lexical forms a function accepts.
+type Parse = fn(&str) -> Option<DateTime>;
+
+fn date_or_date_time(s: &str) -> Option<DateTime> {
+    DateTime::parse(s, DateTimeKind::DateTime).or_else(|| DateTime::parse(s, DateTimeKind::Date))
+}
+
+fn time_or_date_time(s: &str) -> Option<DateTime> {
+    DateTime::parse(s, DateTimeKind::DateTime).or_else(|| DateTime::parse(s, DateTimeKind::Time))
+}
+
+/// Also accepts the `xs:gYearMonth` and `xs:gYear` forms, such as
+/// `2024-03` and `2024`, which are treated as the first day of the
+/// month or year.
+fn with_partial_dates(s: &str) -> Option<DateTime> {
+    let timezone_start = if s.ends_with('Z') {
+        s.len() - 1
+    } else {
+        match s.len().checked_sub(6) {
+            Some(i) if s[i..].starts_with(['+', '-']) && s[i..].find(':') == Some(3) => i,
+            _ => s.len(),
+        }
+    };
+    let (date, timezone) = s.split_at(timezone_start);
+
+    date_or_date_time(s)
+        .or_else(|| DateTime::parse(&format!("{}-01{}", date, timezone), DateTimeKind::Date))
+        .or_else(|| DateTime::parse(&format!("{}-01-01{}", date, timezone), DateTimeKind::Date))
+}
+
+/// Removes the optional date argument, parsing it with `parse`.
+fn pop_date(args: &mut Args<'_>, parse: Parse) -> Result<Option<DateTime>, Error> {
+    args.at_most(1)?;
+    if args.len() == 1 {
+        Ok(parse(&args.pop_string()?))
+    } else {
+        Ok(Some(now()))
+    }
+}
+
+fn empty_string<'d>() -> Value<'d> {
+    Value::String(String::new())
+}
+
+/// A number taken from the date, such as its year.
+struct NumberComponent(Parse, fn(&DateTime) -> f64);
+
+impl Function for NumberComponent {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        let n = pop_date(&mut args, self.0)?.map_or(f64::NAN, |d| self.1(&d));
+        Ok(Value::Number(n))
+    }
+}
+
+/// A name taken from the date, such as the name of its month.
+struct NameComponent(fn(&DateTime) -> &'static str);
+
+impl Function for NameComponent {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        Ok(match pop_date(&mut args, with_partial_dates)? {
+            Some(d) => Value::String(self.0(&d).to_owned()),
+            None => empty_string(),
+        })
+    }
+}
+
+fn year(d: &DateTime) -> f64 {
+    d.year() as f64
+}
+
+fn month_in_year(d: &DateTime) -> f64 {
+    f64::from(d.month())
+}
+
+fn week_in_year(d: &DateTime) -> f64 {
+    d.week_of_year() as f64
+}
+
+// Weeks start on Monday, so the first week of the month may be short
+fn week_in_month(d: &DateTime) -> f64 {
+    let day = i64::from(d.day());
+    let first_weekday = (d.weekday() - day).rem_euclid(7);
+    ((day + first_weekday - 1) / 7 + 1) as f64
+}
+
+fn day_in_year(d: &DateTime) -> f64 {
+    d.day_of_year() as f64
+}
+
+fn day_in_month(d: &DateTime) -> f64 {
+    f64::from(d.day())
+}
+
+fn day_of_week_in_month(d: &DateTime) -> f64 {
+    f64::from((d.day() - 1) / 7 + 1)
+}
+
+// Sunday is the first day of the week
+fn day_in_week(d: &DateTime) -> f64 {
+    (d.weekday() % 7 + 1) as f64
+}
+
+fn hour_in_day(d: &DateTime) -> f64 {
+    f64::from(d.hour())
+}
+
+fn minute_in_hour(d: &DateTime) -> f64 {
+    f64::from(d.minute())
+}
+
+fn month_name(d: &DateTime) -> &'static str {
+    MONTH_NAMES[usize::from(d.month()) - 1]
+}
+
+fn month_abbreviation(d: &DateTime) -> &'static str {
+    &month_name(d)[..3]
+}
+
+fn day_name(d: &DateTime) -> &'static str {
+    DAY_NAMES[d.weekday() as usize - 1]
+}
+
+fn day_abbreviation(d: &DateTime) -> &'static str {
+    &day_name(d)[..3]
+}
+
+/// The date or time part of a date and time.
+struct Part(DateTimeKind, Parse);
+
+impl Function for Part {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        let part = pop_date(&mut args, self.1)?.and_then(|d| d.to_kind(self.0));
+        Ok(part.map_or_else(empty_string, |d| Value::String(d.to_string())))
+    }
+}
+
+struct DateTimeFn;
+
+impl Function for DateTimeFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(0)?;
+        Ok(Value::String(now().to_string()))
+    }
+}
+
+struct LeapYear;
+
+impl Function for LeapYear {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        Ok(match pop_date(&mut args, with_partial_dates)? {
+            Some(d) => Value::Boolean(is_leap_year(d.year())),
+            None => Value::Number(f64::NAN),
+        })
+    }
+}
+
+/// Adds durations of any kind. The result is `None` when the months
+/// and the seconds of the sum have different signs.
+fn sum_durations<I>(durations: I) -> Option<Duration>
+where
+    I: IntoIterator<Item = Duration>,
+{
+    let (mut months, mut nanoseconds) = (0i64, 0i128);
+    for d in durations {
+        months = months.checked_add(d.months())?;
+        nanoseconds = nanoseconds.checked_add(d.nanoseconds())?;
+    }
+    Duration::new(months, nanoseconds)
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    Duration::parse(s, DurationKind::Duration)
+}
+
+struct Add;
+
+impl Function for Add {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let duration = parse_duration(&args.pop_string()?);
+        let date = date_or_date_time(&args.pop_string()?);
+
+        let sum = match (date, duration) {
+            (Some(date), Some(duration)) => date.add(&duration),
+            _ => None,
+        };
+        Ok(sum.map_or_else(empty_string, |d| Value::String(d.to_string())))
+    }
+}
+
+struct AddDuration;
+
+impl Function for AddDuration {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(2)?;
+        let durations: Option<Vec<_>> = args
+            .0
+            .into_iter()
+            .map(|v| parse_duration(&v.into_string()))
+            .collect();
+
+        let sum = durations.and_then(sum_durations);
+        Ok(sum.map_or_else(empty_string, |d| Value::String(d.to_string())))
+    }
+}
+
+struct Sum;
+
+impl Function for Sum {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+        let durations: Option<Vec<_>> = nodes
+            .iter()
+            .map(|n| parse_duration(&n.string_value()))
+            .collect();
+
+        let sum = durations.and_then(sum_durations);
+        Ok(sum.map_or_else(empty_string, |d| Value::String(d.to_string())))
+    }
+}
+
+struct Difference;
+
+impl Function for Difference {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let as_date_time =
+            |s: String| with_partial_dates(&s).and_then(|d| d.to_kind(DateTimeKind::DateTime));
+        let end = as_date_time(args.pop_string()?);
+        let start = as_date_time(args.pop_string()?);
+
+        let difference = match (start, end) {
+            (Some(start), Some(end)) => end.subtract(&start),
+            _ => None,
+        };
+        Ok(difference.map_or_else(empty_string, |d| Value::String(d.to_string())))
+    }
+}
+
+struct DurationFn;
+
+impl Function for DurationFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+        let seconds = if args.len() == 1 {
+            args.pop_number()?
+        } else {
+            now().instant() as f64 / 1e9
+        };
+
+        if !seconds.is_finite() {
+            return Ok(empty_string());
+        }
+        let duration = Duration::day_time((seconds * 1e9).round() as i128);
+        Ok(Value::String(duration.to_string()))
+    }
+}
+
+struct Seconds;
+
+impl Function for Seconds {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+        let arg = if args.len() == 1 {
+            args.pop_string()?
+        } else {
+            now().to_string()
+        };
+
+        // The length of a month varies, so only durations without
+        // years and months have a number of seconds
+        let seconds = match parse_duration(&arg) {
+            Some(d) if d.months() == 0 => d.seconds(),
+            Some(_) => f64::NAN,
+            None => with_partial_dates(&arg)
+                .and_then(|d| d.to_kind(DateTimeKind::DateTime))
+                .map_or(f64::NAN, |d| d.instant() as f64 / 1e9),
+        };
+        Ok(Value::Number(seconds))
+    }
+}
+
+/// Registers the dates and times functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "year"), NumberComponent(with_partial_dates, year));
+    context.set_function(
+        (NS, "month-in-year"),
+        NumberComponent(with_partial_dates, month_in_year),
+    );
+    context.set_function(
+        (NS, "week-in-year"),
+        NumberComponent(date_or_date_time, week_in_year),
+    );
+    context.set_function(
+        (NS, "week-in-month"),
+        NumberComponent(date_or_date_time, week_in_month),
+    );
+    context.set_function(
+        (NS, "day-in-year"),
+        NumberComponent(date_or_date_time, day_in_year),
+    );
+    context.set_function(
+        (NS, "day-in-month"),
+        NumberComponent(date_or_date_time, day_in_month),
+    );
+    context.set_function(
+        (NS, "day-of-week-in-month"),
+        NumberComponent(date_or_date_time, day_of_week_in_month),
+    );
+    context.set_function(
+        (NS, "day-in-week"),
+        NumberComponent(date_or_date_time, day_in_week),
+    );
+    context.set_function(
+        (NS, "hour-in-day"),
+        NumberComponent(time_or_date_time, hour_in_day),
+    );
+    context.set_function(
+        (NS, "minute-in-hour"),
+        NumberComponent(time_or_date_time, minute_in_hour),
+    );
+    context.set_function(
+        (NS, "second-in-minute"),
+        NumberComponent(time_or_date_time, DateTime::second),
+    );
+    context.set_function((NS, "month-name"), NameComponent(month_name));
+    context.set_function(
+        (NS, "month-abbreviation"),
+        NameComponent(month_abbreviation),
+    );
+    context.set_function((NS, "day-name"), NameComponent(day_name));
+    context.set_function((NS, "day-abbreviation"), NameComponent(day_abbreviation));
+    context.set_function((NS, "date-time"), DateTimeFn);
+    context.set_function((NS, "date"), Part(DateTimeKind::Date, date_or_date_time));
+    context.set_function((NS, "time"), Part(DateTimeKind::Time, time_or_date_time));
+    context.set_function((NS, "leap-year"), LeapYear);
+    context.set_function((NS, "add"), Add);
+    context.set_function((NS, "add-duration"), AddDuration);
+    context.set_function((NS, "sum"), Sum);
+    context.set_function((NS, "difference"), Difference);
+    context.set_function((NS, "duration"), DurationFn);
+    context.set_function((NS, "seconds"), Seconds);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use super::*;
+
+    fn evaluate(name: &str, args: Vec<Value<'_>>) -> Value<'static> {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        let function = evaluation
+            .function_for_name(crate::QName::with_namespace_uri(Some(NS), name))
+            .expect("function is registered");
+        let result = function.evaluate(&evaluation, args).expect("evaluates");
+        match result {
+            Value::String(s) => Value::String(s),
+            Value::Number(n) => Value::Number(n),
+            Value::Boolean(b) => Value::Boolean(b),
+            other => panic!("unexpected value {:?}", other),
+        }
+    }
+
+    fn string(s: &str) -> Value<'static> {
+        Value::String(s.to_owned())
+    }
+
+    #[test]
+    fn components_are_taken_from_dates_and_times() {
+        let at = || vec![string("2024-03-09T18:30:15Z")];
+        assert_eq!(Value::Number(2024.0), evaluate("year", at()));
+        assert_eq!(Value::Number(3.0), evaluate("month-in-year", at()));
+        assert_eq!(Value::Number(69.0), evaluate("day-in-year", at()));
+        assert_eq!(Value::Number(7.0), evaluate("day-in-week", at()));
+        assert_eq!(Value::Number(2.0), evaluate("day-of-week-in-month", at()));
+        assert_eq!(Value::Number(10.0), evaluate("week-in-year", at()));
+        assert_eq!(Value::Number(15.0), evaluate("second-in-minute", at()));
+        assert_eq!(string("Mar"), evaluate("month-abbreviation", at()));
+        assert_eq!(string("Saturday"), evaluate("day-name", at()));
+    }
+
+    #[test]
+    fn years_and_months_may_be_given_alone() {
+        assert_eq!(
+            Value::Number(2001.0),
+            evaluate("year", vec![string("2001")])
+        );
+        assert_eq!(
+            Value::Number(6.0),
+            evaluate("month-in-year", vec![string("2001-06Z")])
+        );
+        assert_eq!(
+            Value::Boolean(true),
+            evaluate("leap-year", vec![string("2000")])
+        );
+    }
+
+    #[test]
+    fn invalid_dates_give_nan_or_the_empty_string() {
+        match evaluate("year", vec![string("yesterday")]) {
+            Value::Number(n) => assert!(n.is_nan()),
+            other => panic!("expected NaN, was {:?}", other),
+        }
+        assert_eq!(string(""), evaluate("day-name", vec![string("yesterday")]));
+        assert_eq!(string(""), evaluate("date", vec![string("10:00:00")]));
+    }
+
+    #[test]
+    fn durations_are_added_and_subtracted() {
+        assert_eq!(
+            string("2024-02-29"),
+            evaluate("add", vec![string("2024-01-31"), string("P1M")])
+        );
+        assert_eq!(
+            string("P1Y1DT1H"),
+            evaluate("add-duration", vec![string("P1Y"), string("P1DT1H")])
+        );
+        assert_eq!(
+            string(""),
+            evaluate("add-duration", vec![string("P1Y"), string("-P1D")])
+        );
+        assert_eq!(
+            string("P1DT12H"),
+            evaluate(
+                "difference",
+                vec![string("2024-02-28T12:00:00Z"), string("2024-03-01")]
+            )
+        );
+    }
+
+    #[test]
+    fn seconds_convert_to_and_from_durations() {
+        assert_eq!(
+            string("P1DT1H1S"),
+            evaluate("duration", vec![Value::Number(90001.0)])
+        );
+        assert_eq!(
+            Value::Number(90.0),
+            evaluate("seconds", vec![string("PT1M30S")])
+        );
+        assert_eq!(
+            Value::Number(86400.0),
+            evaluate("seconds", vec![string("1970-01-02")])
+        );
+    }
+}
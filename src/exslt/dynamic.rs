@@ -0,0 +1,83 @@
+//! The [EXSLT dynamic][dynamic] functions.
+//!
+//! [dynamic]: http://exslt.org/dyn/index.html
+
+use super::DYNAMIC_NAMESPACE_URI as NS;
+use crate::context::{self, Context};
+use crate::function::{Args, Error, Function};
+use crate::nodeset::Nodeset;
+use crate::{Factory, Value};
+
+/// Evaluates its argument as an expression, with the same context
+/// node, position, variables and namespaces as the call. A string that
+/// is not an expression gives an empty nodeset.
+struct Evaluate;
+
+impl Function for Evaluate {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let xpath = args.pop_string()?;
+
+        match Factory::new().build(&xpath) {
+            Ok(xpath) => xpath.expression.evaluate(context).map_err(|e| Error::Body {
+                source: Box::new(e),
+            }),
+            Err(_) => Ok(Value::Nodeset(Nodeset::new())),
+        }
+    }
+}
+
+/// Registers the dynamic functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "evaluate"), Evaluate);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use super::*;
+
+    #[test]
+    fn expressions_are_evaluated_in_the_current_context() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        top.set_attribute_value("size", "3");
+        doc.root().append_child(top);
+
+        let mut context = Context::new();
+        context.set_variable("extra", 4.0);
+        let evaluation = context::Evaluation::new(&context, top.into());
+
+        let result = Evaluate.evaluate(&evaluation, vec!["@size + $extra".into()]);
+        assert_eq!(Ok(Value::Number(7.0)), result);
+    }
+
+    #[test]
+    fn invalid_expressions_give_an_empty_nodeset() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = Context::new();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let result = Evaluate.evaluate(&evaluation, vec!["1 +".into()]);
+        assert_eq!(Ok(Value::Nodeset(nodeset![])), result);
+    }
+
+    #[test]
+    fn errors_while_evaluating_are_reported() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = Context::new();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let result = Evaluate.evaluate(&evaluation, vec!["$missing".into()]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! The [EXSLT math][math] functions.
+//!
+//! The functions over nodesets use the number value of each node's
+//! string value, so a node that is not a number makes the result
+//! `NaN`.
+//!
+//! [math]: http://exslt.org/math/index.html
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use super::MATH_NAMESPACE_URI as NS;
+use crate::context::{self, Context};
+use crate::function::{Args, Error, Function};
+use crate::nodeset::{Node, Nodeset};
+use crate::{conv, Value};
+
+fn node_numbers<'d>(nodes: &Nodeset<'d>) -> Vec<(Node<'d>, f64)> {
+    nodes
+        .iter()
+        .map(|n| (n, conv::string_to_number(&n.string_value())))
+        .collect()
+}
+
+/// The smallest or largest number of a nodeset, as `math:min` and
+/// `math:max` return.
+struct Extremum(fn(f64, f64) -> f64);
+
+impl Function for Extremum {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+        Ok(Value::Number(extremum(&node_numbers(&nodes), self.0)))
+    }
+}
+
+fn extremum(numbers: &[(Node<'_>, f64)], pick: fn(f64, f64) -> f64) -> f64 {
+    let mut numbers = numbers.iter().map(|&(_, n)| n);
+    let first = match numbers.next() {
+        Some(n) => n,
+        None => return f64::NAN,
+    };
+    // Unlike `f64::min`, any NaN makes the result NaN
+    numbers.fold(first, |a, b| {
+        if a.is_nan() || b.is_nan() {
+            f64::NAN
+        } else {
+            pick(a, b)
+        }
+    })
+}
+
+/// The nodes with the smallest or largest number, as `math:lowest`
+/// and `math:highest` return.
+struct Extremes(fn(f64, f64) -> f64);
+
+impl Function for Extremes {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+        let numbers = node_numbers(&nodes);
+
+        let target = extremum(&numbers, self.0);
+        let extremes = numbers
+            .into_iter()
+            .filter(|&(_, n)| n == target)
+            .map(|(node, _)| node)
+            .collect();
+        Ok(Value::Nodeset(extremes))
+    }
+}
+
+struct Unary(fn(f64) -> f64);
+
+impl Function for Unary {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let x = args.pop_number()?;
+        Ok(Value::Number(self.0(x)))
+    }
+}
+
+struct Binary(fn(f64, f64) -> f64);
+
+impl Function for Binary {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let y = args.pop_number()?;
+        let x = args.pop_number()?;
+        Ok(Value::Number(self.0(x, y)))
+    }
+}
+
+struct Constant;
+
+impl Function for Constant {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        const CONSTANTS: [(&str, &str); 7] = [
+            ("PI", "3.1415926535897932384626433832795028841971693993751"),
+            ("E", "2.71828182845904523536028747135266249775724709369996"),
+            (
+                "SQRRT2",
+                "1.41421356237309504880168872420969807856967187537694",
+            ),
+            (
+                "LN2",
+                "0.69314718055994530941723212145817656807550013436025",
+            ),
+            (
+                "LN10",
+                "2.30258509299404568401799145468436420760110148862877",
+            ),
+            (
+                "LOG2E",
+                "1.4426950408889634073599246810018921374266459541529",
+            ),
+            (
+                "SQRT1_2",
+                "0.70710678118654752440084436210484903928483593768847",
+            ),
+        ];
+
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let precision = args.pop_number()?;
+        let name = args.pop_string()?;
+
+        // The precision is the number of characters of the constant
+        // that are kept
+        let value = match CONSTANTS.iter().find(|&&(n, _)| n == name) {
+            Some(&(_, digits)) if precision >= 1.0 => {
+                let kept = (precision as usize).min(digits.len());
+                conv::string_to_number(&digits[..kept])
+            }
+            _ => f64::NAN,
+        };
+        Ok(Value::Number(value))
+    }
+}
+
+struct Random;
+
+impl Function for Random {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(0)?;
+        // Each `RandomState` is seeded randomly
+        let bits = RandomState::new().build_hasher().finish();
+        Ok(Value::Number((bits >> 11) as f64 / (1u64 << 53) as f64))
+    }
+}
+
+/// Registers the math functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "min"), Extremum(f64::min));
+    context.set_function((NS, "max"), Extremum(f64::max));
+    context.set_function((NS, "lowest"), Extremes(f64::min));
+    context.set_function((NS, "highest"), Extremes(f64::max));
+    context.set_function((NS, "abs"), Unary(f64::abs));
+    context.set_function((NS, "sqrt"), Unary(f64::sqrt));
+    context.set_function((NS, "log"), Unary(f64::ln));
+    context.set_function((NS, "exp"), Unary(f64::exp));
+    context.set_function((NS, "sin"), Unary(f64::sin));
+    context.set_function((NS, "cos"), Unary(f64::cos));
+    context.set_function((NS, "tan"), Unary(f64::tan));
+    context.set_function((NS, "asin"), Unary(f64::asin));
+    context.set_function((NS, "acos"), Unary(f64::acos));
+    context.set_function((NS, "atan"), Unary(f64::atan));
+    context.set_function((NS, "power"), Binary(f64::powf));
+    context.set_function((NS, "atan2"), Binary(f64::atan2));
+    context.set_function((NS, "constant"), Constant);
+    context.set_function((NS, "random"), Random);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use super::*;
+
+    fn evaluate<'d>(node: Node<'d>, name: &str, args: Vec<Value<'d>>) -> Value<'d> {
+        let mut context = Context::without_core_functions();
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, node);
+        let function = evaluation
+            .function_for_name(crate::QName::with_namespace_uri(Some(NS), name))
+            .expect("function is registered");
+        function.evaluate(&evaluation, args).expect("evaluates")
+    }
+
+    #[test]
+    fn extremes_are_found_by_number_value() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let values: Vec<_> = ["3", "7", "7", "-1"]
+            .iter()
+            .map(|&v| doc.create_text(v))
+            .collect();
+        let nodes: Nodeset<'_> = values.iter().map(|&t| Node::from(t)).collect();
+        let root = doc.root().into();
+
+        let max = evaluate(root, "max", vec![Value::Nodeset(nodes.clone())]);
+        assert_eq!(Value::Number(7.0), max);
+        let min = evaluate(root, "min", vec![Value::Nodeset(nodes.clone())]);
+        assert_eq!(Value::Number(-1.0), min);
+
+        let highest = evaluate(root, "highest", vec![Value::Nodeset(nodes)]);
+        let expected: Nodeset<'_> = values[1..3].iter().map(|&t| Node::from(t)).collect();
+        assert_eq!(Value::Nodeset(expected), highest);
+    }
+
+    #[test]
+    fn a_node_that_is_not_a_number_makes_the_extremes_nan() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let nodes = nodeset![doc.create_text("3"), doc.create_text("many")];
+        let root = doc.root().into();
+
+        match evaluate(root, "max", vec![Value::Nodeset(nodes.clone())]) {
+            Value::Number(n) => assert!(n.is_nan()),
+            other => panic!("expected NaN, was {:?}", other),
+        }
+        let highest = evaluate(root, "highest", vec![Value::Nodeset(nodes)]);
+        assert_eq!(Value::Nodeset(nodeset![]), highest);
+    }
+
+    #[test]
+    fn arithmetic_functions_apply_to_numbers() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let root = doc.root().into();
+
+        let power = evaluate(root, "power", vec![Value::Number(2.0), Value::Number(10.0)]);
+        assert_eq!(Value::Number(1024.0), power);
+        let sqrt = evaluate(root, "sqrt", vec![Value::Number(2.25)]);
+        assert_eq!(Value::Number(1.5), sqrt);
+    }
+
+    #[test]
+    fn constants_keep_the_requested_number_of_characters() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let root = doc.root().into();
+
+        let ln10 = evaluate(root, "constant", vec!["LN10".into(), Value::Number(4.0)]);
+        assert_eq!(Value::Number(2.30), ln10);
+        match evaluate(root, "constant", vec!["TAU".into(), Value::Number(4.0)]) {
+            Value::Number(n) => assert!(n.is_nan()),
+            other => panic!("expected NaN, was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn random_numbers_are_between_zero_and_one() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        match evaluate(doc.root().into(), "random", vec![]) {
+            Value::Number(n) => assert!((0.0..1.0).contains(&n)),
+            other => panic!("expected a number, was {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! The [EXSLT][] extension functions, which many expressions written
+//! for XSLT 1.0 processors rely on. They are only available with the
+//! `exslt` feature.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::{exslt, Context, Factory};
+//!
+//! let package = parser::parse("<event at='2024-03-09T18:30:00Z'/>").unwrap();
+//! let doc = package.as_document();
+//!
+//! let mut context = Context::new();
+//! exslt::register_exslt_functions(&mut context);
+//!
+//! let xpath = Factory::new().build("date:day-name(/event/@at)").unwrap();
+//! let value = xpath.evaluate(&context, doc.root()).unwrap();
+//!
+//! assert_eq!("Saturday", value.string());
+//! ```
+//!
+//! [EXSLT]: http://exslt.org/
+
+use crate::context::{self, Context};
+use crate::function::Error;
+use crate::nodeset::Nodeset;
+use crate::Value;
+
+mod common;
+mod date;
+mod dynamic;
+mod math;
+#[cfg(feature = "regex")]
+mod regexp;
+mod sets;
+mod strings;
+
+/// The namespace of the `exsl:` functions.
+pub const COMMON_NAMESPACE_URI: &str = "http://exslt.org/common";
+/// The namespace of the `date:` functions.
+pub const DATES_AND_TIMES_NAMESPACE_URI: &str = "http://exslt.org/dates-and-times";
+/// The namespace of the `dyn:` functions.
+pub const DYNAMIC_NAMESPACE_URI: &str = "http://exslt.org/dynamic";
+/// The namespace of the `math:` functions.
+pub const MATH_NAMESPACE_URI: &str = "http://exslt.org/math";
+/// The namespace of the `regexp:` functions.
+pub const REGULAR_EXPRESSIONS_NAMESPACE_URI: &str = "http://exslt.org/regular-expressions";
+/// The namespace of the `set:` functions.
+pub const SETS_NAMESPACE_URI: &str = "http://exslt.org/sets";
+/// The namespace of the `str:` functions.
+pub const STRINGS_NAMESPACE_URI: &str = "http://exslt.org/strings";
+
+/// Binds the conventional prefix of a library, unless the context
+/// already maps it elsewhere.
+fn bind_prefix(context: &mut Context<'_>, prefix: &str, uri: &str) {
+    if context.namespace_for(prefix).is_none() {
+        context.set_namespace(prefix, uri);
+    }
+}
+
+/// Creates an element with the given name for each text, in order,
+/// in a new result tree fragment.
+fn text_elements<'d, I>(
+    context: &context::Evaluation<'_, 'd>,
+    name: &str,
+    texts: I,
+) -> Result<Value<'d>, Error>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let fragment = context.new_fragment().ok_or(Error::NoFragments)?;
+    let doc = fragment.document();
+    let nodes: Nodeset<'d> = texts
+        .into_iter()
+        .map(|text| {
+            let element = doc.create_element(name);
+            element.append_child(doc.create_text(text.as_ref()));
+            fragment.append_child(element);
+            element.into()
+        })
+        .collect();
+    Ok(Value::Nodeset(nodes))
+}
+
+/// Registers every EXSLT function library along with its conventional
+/// prefix, such as `date`. The `regexp:` functions also need the
+/// `regex` feature.
+pub fn register_exslt_functions(context: &mut Context<'_>) {
+    register_common_functions(context);
+    register_date_functions(context);
+    register_dynamic_functions(context);
+    register_math_functions(context);
+    #[cfg(feature = "regex")]
+    register_regexp_functions(context);
+    register_sets_functions(context);
+    register_strings_functions(context);
+}
+
+/// Registers the `exsl:` functions, such as `exsl:node-set`.
+pub fn register_common_functions(context: &mut Context<'_>) {
+    common::register_functions(context);
+    bind_prefix(context, "exsl", COMMON_NAMESPACE_URI);
+}
+
+/// Registers the `date:` functions, such as `date:add`.
+pub fn register_date_functions(context: &mut Context<'_>) {
+    date::register_functions(context);
+    bind_prefix(context, "date", DATES_AND_TIMES_NAMESPACE_URI);
+}
+
+/// Registers the `dyn:` functions, such as `dyn:evaluate`.
+pub fn register_dynamic_functions(context: &mut Context<'_>) {
+    dynamic::register_functions(context);
+    bind_prefix(context, "dyn", DYNAMIC_NAMESPACE_URI);
+}
+
+/// Registers the `math:` functions, such as `math:max`.
+pub fn register_math_functions(context: &mut Context<'_>) {
+    math::register_functions(context);
+    bind_prefix(context, "math", MATH_NAMESPACE_URI);
+}
+
+/// Registers the `regexp:` functions, such as `regexp:test`.
+#[cfg(feature = "regex")]
+pub fn register_regexp_functions(context: &mut Context<'_>) {
+    regexp::register_functions(context);
+    bind_prefix(context, "regexp", REGULAR_EXPRESSIONS_NAMESPACE_URI);
+}
+
+/// Registers the `set:` functions, such as `set:difference`.
+pub fn register_sets_functions(context: &mut Context<'_>) {
+    sets::register_functions(context);
+    bind_prefix(context, "set", SETS_NAMESPACE_URI);
+}
+
+/// Registers the `str:` functions, such as `str:tokenize`.
+pub fn register_strings_functions(context: &mut Context<'_>) {
+    strings::register_functions(context);
+    bind_prefix(context, "str", STRINGS_NAMESPACE_URI);
+}
@@ -0,0 +1,251 @@
+//! The [EXSLT regular expressions][regexp] functions, for expressions
+//! written before the XPath 2.0 functions such as `matches`.
+//!
+//! Patterns use the syntax of the `regex` crate, which is close to the
+//! JavaScript syntax that EXSLT describes. The flags are `g`, to act
+//! on every match instead of the first, and `i`, to ignore case.
+//! `regexp:match` returns `match` elements, which are created in a
+//! result tree fragment.
+//!
+//! [regexp]: http://exslt.org/regexp/index.html
+
+use regex::{NoExpand, Regex, RegexBuilder};
+
+use super::{text_elements, REGULAR_EXPRESSIONS_NAMESPACE_URI as NS};
+use crate::context::{self, Context};
+use crate::function::{Args, Error, Function};
+use crate::Value;
+
+#[derive(Debug, Default)]
+struct Flags {
+    global: bool,
+    case_insensitive: bool,
+}
+
+impl Flags {
+    fn parse(flags: &str) -> Result<Flags, Error> {
+        let mut parsed = Flags::default();
+        for flag in flags.chars() {
+            match flag {
+                'g' => parsed.global = true,
+                'i' => parsed.case_insensitive = true,
+                _ => {
+                    return Err(Error::InvalidRegexFlags {
+                        flags: flags.to_owned(),
+                    })
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn compile(pattern: &str, flags: &Flags) -> Result<Regex, Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(flags.case_insensitive)
+        .build()
+        .map_err(|e| Error::InvalidRegex {
+            pattern: pattern.to_owned(),
+            what: e.to_string(),
+        })
+}
+
+fn pop_flags(args: &mut Args<'_>, arity: usize) -> Result<Flags, Error> {
+    if args.len() == arity {
+        Flags::parse(&args.pop_string()?)
+    } else {
+        Ok(Flags::default())
+    }
+}
+
+struct Test;
+
+impl Function for Test {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+        let flags = pop_flags(&mut args, 3)?;
+        let pattern = args.pop_string()?;
+        let input = args.pop_string()?;
+
+        let regex = compile(&pattern, &flags)?;
+        Ok(Value::Boolean(regex.is_match(&input)))
+    }
+}
+
+/// Without the `g` flag, returns the first match followed by each of
+/// its groups, where a group that did not take part is empty. With it,
+/// returns every match.
+struct Match;
+
+impl Function for Match {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+        let flags = pop_flags(&mut args, 3)?;
+        let pattern = args.pop_string()?;
+        let input = args.pop_string()?;
+
+        let regex = compile(&pattern, &flags)?;
+        let matches: Vec<_> = if flags.global {
+            regex.find_iter(&input).map(|m| m.as_str()).collect()
+        } else {
+            match regex.captures(&input) {
+                Some(captures) => captures
+                    .iter()
+                    .map(|group| group.map_or("", |m| m.as_str()))
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+        text_elements(context, "match", matches)
+    }
+}
+
+/// Replaces the first match, or every match with the `g` flag. The
+/// replacement is used as it is written.
+struct Replace;
+
+impl Function for Replace {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(4)?;
+        let replacement = args.pop_string()?;
+        let flags = Flags::parse(&args.pop_string()?)?;
+        let pattern = args.pop_string()?;
+        let input = args.pop_string()?;
+
+        let regex = compile(&pattern, &flags)?;
+        let limit = if flags.global { 0 } else { 1 };
+        let replaced = regex.replacen(&input, limit, NoExpand(&replacement));
+        Ok(Value::String(replaced.into_owned()))
+    }
+}
+
+/// Registers the regular expressions functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "test"), Test);
+    context.set_function((NS, "match"), Match);
+    context.set_function((NS, "replace"), Replace);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use super::*;
+    use crate::fragment::Fragments;
+
+    fn string(s: &str) -> Value<'static> {
+        Value::String(s.to_owned())
+    }
+
+    fn evaluate(name: &str, args: Vec<Value<'_>>) -> Result<Value<'static>, Error> {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        let function = evaluation
+            .function_for_name(crate::QName::with_namespace_uri(Some(NS), name))
+            .expect("function is registered");
+        function
+            .evaluate(&evaluation, args)
+            .map(|value| match value {
+                Value::String(s) => Value::String(s),
+                Value::Boolean(b) => Value::Boolean(b),
+                other => panic!("unexpected value {:?}", other),
+            })
+    }
+
+    fn evaluate_matches(args: Vec<Value<'_>>) -> Vec<String> {
+        let fragments = Fragments::new();
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        context.set_fragments(&fragments);
+
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        match Match.evaluate(&evaluation, args) {
+            Ok(Value::Nodeset(nodes)) => nodes
+                .document_order()
+                .iter()
+                .map(|n| n.string_value())
+                .collect(),
+            other => panic!("expected matches, was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_honors_the_case_insensitive_flag() {
+        let test =
+            |flags: &str| evaluate("test", vec![string("Hello"), string("^h"), string(flags)]);
+        assert_eq!(Ok(Value::Boolean(false)), test(""));
+        assert_eq!(Ok(Value::Boolean(true)), test("i"));
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        let result = evaluate("test", vec![string("a"), string("a"), string("gx")]);
+        assert_eq!(
+            Err(Error::InvalidRegexFlags {
+                flags: "gx".to_owned()
+            }),
+            result
+        );
+    }
+
+    #[test]
+    fn match_returns_the_first_match_and_its_groups() {
+        let matches = evaluate_matches(vec![
+            string("http://example.com:8080/"),
+            string(r"(\w+)://([^/:]+)(:\d+)?(#.*)?"),
+        ]);
+        assert_eq!(
+            vec![
+                "http://example.com:8080",
+                "http",
+                "example.com",
+                ":8080",
+                ""
+            ],
+            matches
+        );
+    }
+
+    #[test]
+    fn match_returns_every_match_with_the_global_flag() {
+        let matches = evaluate_matches(vec![string("a1b22c333"), string(r"\d+"), string("g")]);
+        assert_eq!(vec!["1", "22", "333"], matches);
+
+        let matches = evaluate_matches(vec![string("abc"), string(r"\d+"), string("g")]);
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn replace_replaces_the_first_match_unless_global() {
+        let replace = |flags: &str| {
+            evaluate(
+                "replace",
+                vec![string("a-b-c"), string("-"), string(flags), string("$0")],
+            )
+        };
+        assert_eq!(Ok(string("a$0b-c")), replace(""));
+        assert_eq!(Ok(string("a$0b$0c")), replace("g"));
+    }
+}
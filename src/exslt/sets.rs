@@ -0,0 +1,251 @@
+//! The [EXSLT sets][sets] functions.
+//!
+//! [sets]: http://exslt.org/set/index.html
+
+use std::collections::HashSet;
+
+use super::SETS_NAMESPACE_URI as NS;
+use crate::context::{self, Context};
+use crate::function::{Args, Error, Function};
+use crate::nodeset::Nodeset;
+use crate::Value;
+
+/// Removes the two nodeset arguments, in the order they were given.
+fn pop_two_nodesets<'d>(args: &mut Args<'d>) -> Result<(Nodeset<'d>, Nodeset<'d>), Error> {
+    args.exactly(2)?;
+    let second = args.pop_nodeset()?;
+    let first = args.pop_nodeset()?;
+    Ok((first, second))
+}
+
+struct Difference;
+
+impl Function for Difference {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        let (first, second) = pop_two_nodesets(&mut args)?;
+        let difference = first.iter().filter(|&n| !second.contains(n)).collect();
+        Ok(Value::Nodeset(difference))
+    }
+}
+
+struct Intersection;
+
+impl Function for Intersection {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        let (first, second) = pop_two_nodesets(&mut args)?;
+        let intersection = first.iter().filter(|&n| second.contains(n)).collect();
+        Ok(Value::Nodeset(intersection))
+    }
+}
+
+struct HasSameNode;
+
+impl Function for HasSameNode {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        let (first, second) = pop_two_nodesets(&mut args)?;
+        Ok(Value::Boolean(first.iter().any(|n| second.contains(n))))
+    }
+}
+
+/// The nodes of the first set whose string value differs from that of
+/// every node before them in document order.
+struct Distinct;
+
+impl Function for Distinct {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+
+        let mut seen = HashSet::new();
+        let distinct = nodes
+            .document_order()
+            .into_iter()
+            .filter(|n| seen.insert(n.string_value()))
+            .collect();
+        Ok(Value::Nodeset(distinct))
+    }
+}
+
+/// Which side of the first node of the second set `set:leading` and
+/// `set:trailing` keep.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Side {
+    Leading,
+    Trailing,
+}
+
+/// The nodes of the first set that come before or after the first node
+/// of the second set. The whole first set is returned when the second
+/// is empty, and nothing when the first set does not contain that node.
+struct Partition(Side);
+
+impl Function for Partition {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        let (first, second) = pop_two_nodesets(&mut args)?;
+
+        let pivot = match second.document_order_first() {
+            Some(pivot) => pivot,
+            None => return Ok(Value::Nodeset(first)),
+        };
+
+        let ordered = first.document_order();
+        let kept = match ordered.iter().position(|&n| n == pivot) {
+            Some(i) if self.0 == Side::Leading => ordered[..i].iter().cloned().collect(),
+            Some(i) => ordered[i + 1..].iter().cloned().collect(),
+            None => Nodeset::new(),
+        };
+        Ok(Value::Nodeset(kept))
+    }
+}
+
+/// Registers the sets functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "difference"), Difference);
+    context.set_function((NS, "intersection"), Intersection);
+    context.set_function((NS, "distinct"), Distinct);
+    context.set_function((NS, "has-same-node"), HasSameNode);
+    context.set_function((NS, "leading"), Partition(Side::Leading));
+    context.set_function((NS, "trailing"), Partition(Side::Trailing));
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::dom;
+    use sxd_document::Package;
+
+    use super::*;
+    use crate::nodeset::Node;
+
+    fn evaluate<'d>(node: Node<'d>, name: &str, args: Vec<Value<'d>>) -> Value<'d> {
+        let mut context = Context::without_core_functions();
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, node);
+        let function = evaluation
+            .function_for_name(crate::QName::with_namespace_uri(Some(NS), name))
+            .expect("function is registered");
+        function.evaluate(&evaluation, args).expect("evaluates")
+    }
+
+    /// Appends elements named `a` to `e` to the root, in that order.
+    fn children<'d>(doc: &dom::Document<'d>) -> Vec<dom::Element<'d>> {
+        let top = doc.create_element("top");
+        doc.root().append_child(top);
+        ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|&name| {
+                let child = doc.create_element(name);
+                top.append_child(child);
+                child
+            })
+            .collect()
+    }
+
+    #[test]
+    fn difference_and_intersection_compare_node_identity() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = children(&doc);
+        let root = doc.root().into();
+
+        let first = || Value::Nodeset(nodeset![e[0], e[1], e[2]]);
+        let second = || Value::Nodeset(nodeset![e[1], e[3]]);
+
+        let difference = evaluate(root, "difference", vec![first(), second()]);
+        assert_eq!(Value::Nodeset(nodeset![e[0], e[2]]), difference);
+
+        let intersection = evaluate(root, "intersection", vec![first(), second()]);
+        assert_eq!(Value::Nodeset(nodeset![e[1]]), intersection);
+
+        let shared = evaluate(root, "has-same-node", vec![first(), second()]);
+        assert_eq!(Value::Boolean(true), shared);
+        let shared = evaluate(
+            root,
+            "has-same-node",
+            vec![first(), Value::Nodeset(nodeset![e[4]])],
+        );
+        assert_eq!(Value::Boolean(false), shared);
+    }
+
+    #[test]
+    fn distinct_keeps_the_first_node_of_each_string_value() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        doc.root().append_child(top);
+        let texts: Vec<_> = ["x", "y", "x"]
+            .iter()
+            .map(|&v| {
+                let text = doc.create_text(v);
+                top.append_child(text);
+                text
+            })
+            .collect();
+
+        let nodes = texts.iter().map(|&t| Node::from(t)).collect();
+        let distinct = evaluate(doc.root().into(), "distinct", vec![Value::Nodeset(nodes)]);
+        assert_eq!(Value::Nodeset(nodeset![texts[0], texts[1]]), distinct);
+    }
+
+    #[test]
+    fn leading_and_trailing_split_around_the_first_node_of_the_second_set() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = children(&doc);
+        let root = doc.root().into();
+
+        let first = || Value::Nodeset(nodeset![e[0], e[1], e[2], e[3]]);
+        let second = || Value::Nodeset(nodeset![e[3], e[2]]);
+
+        let leading = evaluate(root, "leading", vec![first(), second()]);
+        assert_eq!(Value::Nodeset(nodeset![e[0], e[1]]), leading);
+
+        let trailing = evaluate(root, "trailing", vec![first(), second()]);
+        assert_eq!(Value::Nodeset(nodeset![e[3]]), trailing);
+    }
+
+    #[test]
+    fn leading_and_trailing_handle_an_empty_or_foreign_second_set() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = children(&doc);
+        let root = doc.root().into();
+
+        let first = || Value::Nodeset(nodeset![e[0], e[1]]);
+
+        let leading = evaluate(root, "leading", vec![first(), Value::Nodeset(nodeset![])]);
+        assert_eq!(first(), leading);
+
+        let trailing = evaluate(
+            root,
+            "trailing",
+            vec![first(), Value::Nodeset(nodeset![e[4]])],
+        );
+        assert_eq!(Value::Nodeset(nodeset![]), trailing);
+    }
+}
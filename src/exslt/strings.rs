@@ -0,0 +1,364 @@
+//! The [EXSLT strings][strings] functions.
+//!
+//! `str:tokenize` and `str:split` return `token` elements, which are
+//! created in a result tree fragment. The context must be given
+//! storage for them with [`Context::set_fragments`][].
+//!
+//! [strings]: http://exslt.org/str/index.html
+//! [`Context::set_fragments`]: ../../context/struct.Context.html#method.set_fragments
+
+use super::{text_elements, STRINGS_NAMESPACE_URI as NS};
+use crate::context::{self, Context};
+use crate::function::{Args, Error, Function};
+use crate::Value;
+
+/// Creates a `token` element for each token, in order.
+fn tokens<'d, I>(context: &context::Evaluation<'_, 'd>, tokens: I) -> Result<Value<'d>, Error>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    text_elements(context, "token", tokens)
+}
+
+/// Splits a string into its characters, as both tokenizing functions
+/// do when given an empty separator.
+fn characters(s: &str) -> Vec<&str> {
+    s.char_indices()
+        .map(|(i, c)| &s[i..i + c.len_utf8()])
+        .collect()
+}
+
+struct Tokenize;
+
+impl Function for Tokenize {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+        let delimiters = if args.len() == 2 {
+            args.pop_string()?
+        } else {
+            " \t\n\r".to_owned()
+        };
+        let s = args.pop_string()?;
+
+        if delimiters.is_empty() {
+            tokens(context, characters(&s))
+        } else {
+            let parts = s
+                .split(|c| delimiters.contains(c))
+                .filter(|part| !part.is_empty());
+            tokens(context, parts)
+        }
+    }
+}
+
+struct Split;
+
+impl Function for Split {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+        let pattern = if args.len() == 2 {
+            args.pop_string()?
+        } else {
+            " ".to_owned()
+        };
+        let s = args.pop_string()?;
+
+        if pattern.is_empty() {
+            tokens(context, characters(&s))
+        } else {
+            let parts = s.split(pattern.as_str()).filter(|part| !part.is_empty());
+            tokens(context, parts)
+        }
+    }
+}
+
+/// The string values of a nodeset, in document order, or the string
+/// value of any other argument.
+fn pop_strings(args: &mut Args<'_>) -> Result<Vec<String>, Error> {
+    Ok(match args.pop_value()? {
+        Value::Nodeset(nodes) => nodes
+            .document_order()
+            .iter()
+            .map(|n| n.string_value())
+            .collect(),
+        other => vec![other.string()],
+    })
+}
+
+/// Replaces each search string with the replacement at the same
+/// position, or with nothing when there are fewer replacements. Where
+/// several search strings match, the longest is replaced, and the text
+/// that replaces it is not searched again.
+struct Replace;
+
+impl Function for Replace {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(3)?;
+        let replacements = pop_strings(&mut args)?;
+        let searches = pop_strings(&mut args)?;
+        let s = args.pop_string()?;
+
+        let mut result = String::with_capacity(s.len());
+        let mut rest = s.as_str();
+        while let Some(c) = rest.chars().next() {
+            let longest = searches
+                .iter()
+                .enumerate()
+                .filter(|&(_, search)| !search.is_empty() && rest.starts_with(search.as_str()))
+                .max_by_key(|&(i, search)| (search.len(), std::cmp::Reverse(i)));
+
+            match longest {
+                Some((i, search)) => {
+                    result.push_str(replacements.get(i).map_or("", String::as_str));
+                    rest = &rest[search.len()..];
+                }
+                None => {
+                    result.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+
+        Ok(Value::String(result))
+    }
+}
+
+/// Repeats the characters of `padding`, cut to `length` characters.
+fn pad(padding: &str, length: usize) -> String {
+    padding.chars().cycle().take(length).collect()
+}
+
+struct Padding;
+
+impl Function for Padding {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+        let padding = if args.len() == 2 {
+            args.pop_string()?
+        } else {
+            " ".to_owned()
+        };
+        let length = args.pop_number()?;
+
+        let length = if length.is_nan() || length < 0.0 {
+            0
+        } else {
+            length.round() as usize
+        };
+        Ok(Value::String(pad(&padding, length)))
+    }
+}
+
+/// Places the string over the padding, keeping the length of the
+/// padding. A string that is longer than the padding is cut short.
+struct Align;
+
+impl Function for Align {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+        let alignment = if args.len() == 3 {
+            args.pop_string()?
+        } else {
+            "left".to_owned()
+        };
+        let padding: Vec<_> = args.pop_string()?.chars().collect();
+        let s: Vec<_> = args.pop_string()?.chars().collect();
+
+        let width = padding.len();
+        let s = &s[..s.len().min(width)];
+        let start = match alignment.as_str() {
+            "right" => width - s.len(),
+            "center" => (width - s.len()) / 2,
+            _ => 0,
+        };
+
+        let aligned = padding[..start]
+            .iter()
+            .chain(s)
+            .chain(&padding[start + s.len()..])
+            .collect();
+        Ok(Value::String(aligned))
+    }
+}
+
+/// Registers the strings functions in their namespace.
+pub(super) fn register_functions(context: &mut Context<'_>) {
+    context.set_function((NS, "tokenize"), Tokenize);
+    context.set_function((NS, "split"), Split);
+    context.set_function((NS, "replace"), Replace);
+    context.set_function((NS, "padding"), Padding);
+    context.set_function((NS, "align"), Align);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use super::*;
+    use crate::fragment::Fragments;
+    use crate::nodeset::{Node, Nodeset};
+
+    fn string(s: &str) -> Value<'static> {
+        Value::String(s.to_owned())
+    }
+
+    /// Evaluates a function that returns a string.
+    fn evaluate(name: &str, args: Vec<Value<'_>>) -> String {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        let function = evaluation
+            .function_for_name(crate::QName::with_namespace_uri(Some(NS), name))
+            .expect("function is registered");
+        match function.evaluate(&evaluation, args) {
+            Ok(Value::String(s)) => s,
+            other => panic!("expected a string, was {:?}", other),
+        }
+    }
+
+    /// Evaluates a function that returns tokens, giving their text.
+    fn evaluate_tokens(name: &str, args: Vec<Value<'_>>) -> Vec<String> {
+        let fragments = Fragments::new();
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        context.set_fragments(&fragments);
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        let function = evaluation
+            .function_for_name(crate::QName::with_namespace_uri(Some(NS), name))
+            .expect("function is registered");
+        match function.evaluate(&evaluation, args) {
+            Ok(Value::Nodeset(nodes)) => nodes
+                .document_order()
+                .iter()
+                .map(|n| {
+                    assert_eq!(Some("token".into()), n.expanded_name());
+                    n.string_value()
+                })
+                .collect(),
+            other => panic!("expected tokens, was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tokenize_splits_at_any_delimiter_character() {
+        let tokens = evaluate_tokens("tokenize", vec![string("2024-03-09T18:30"), string("-T:")]);
+        assert_eq!(vec!["2024", "03", "09", "18", "30"], tokens);
+
+        let tokens = evaluate_tokens("tokenize", vec![string("  a\tb\n")]);
+        assert_eq!(vec!["a", "b"], tokens);
+
+        let tokens = evaluate_tokens("tokenize", vec![string("héé"), string("")]);
+        assert_eq!(vec!["h", "é", "é"], tokens);
+    }
+
+    #[test]
+    fn split_splits_at_the_whole_pattern() {
+        let tokens = evaluate_tokens("split", vec![string("a, simple, list"), string(", ")]);
+        assert_eq!(vec!["a", "simple", "list"], tokens);
+
+        let tokens = evaluate_tokens("split", vec![string("a b")]);
+        assert_eq!(vec!["a", "b"], tokens);
+    }
+
+    #[test]
+    fn tokens_cannot_be_created_without_fragment_storage() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        register_functions(&mut context);
+
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        let result = Tokenize.evaluate(&evaluation, vec![string("a b")]);
+        assert_eq!(Err(Error::NoFragments), result);
+    }
+
+    #[test]
+    fn replace_prefers_the_longest_search_string() {
+        let replaced = evaluate(
+            "replace",
+            vec![string("a cat and a catalog"), string("cat"), string("dog")],
+        );
+        assert_eq!("a dog and a dogalog", replaced);
+
+        let package = Package::new();
+        let doc = package.as_document();
+        let texts: Vec<_> = ["ab", "a", "b"]
+            .iter()
+            .map(|&s| doc.create_text(s))
+            .collect();
+        let top = doc.create_element("top");
+        doc.root().append_child(top);
+        for &text in &texts {
+            top.append_child(text);
+        }
+        let searches: Nodeset<'_> = texts.iter().map(|&t| Node::from(t)).collect();
+
+        let replaced = evaluate(
+            "replace",
+            vec![string("aabbc"), Value::Nodeset(searches), string("X")],
+        );
+        assert_eq!("Xc", replaced);
+    }
+
+    #[test]
+    fn padding_repeats_the_characters() {
+        assert_eq!(
+            "-=-=-",
+            evaluate("padding", vec![Value::Number(5.0), string("-=")])
+        );
+        assert_eq!("   ", evaluate("padding", vec![Value::Number(3.0)]));
+        assert_eq!("", evaluate("padding", vec![Value::Number(-1.0)]));
+    }
+
+    #[test]
+    fn align_places_the_string_over_the_padding() {
+        let align = |alignment: &str| {
+            evaluate(
+                "align",
+                vec![string("abc"), string("--------"), string(alignment)],
+            )
+        };
+        assert_eq!("abc-----", align("left"));
+        assert_eq!("-----abc", align("right"));
+        assert_eq!("--abc---", align("center"));
+
+        let cut = evaluate("align", vec![string("abcdef"), string("---")]);
+        assert_eq!("abc", cut);
+    }
+}
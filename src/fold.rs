@@ -0,0 +1,364 @@
+//! Rewrites syntax trees one node at a time.
+//!
+//! A [`Fold`][] takes an [`Expr`][] apart and puts it back together,
+//! calling one of its methods for every expression, step and name on
+//! the way. Each method does nothing by default, so an implementation
+//! only overrides the parts it changes. Overriding methods can call the
+//! free function of the same name, such as [`fold_expr`][], to rewrite
+//! the children the default way.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::ast::Expr;
+//! use sxd_xpath::fold::{self, Fold};
+//! use sxd_xpath::node_test::NameTest;
+//! use sxd_xpath::{Context, Factory, Value, XPath};
+//!
+//! /// Moves names from the `old` prefix to `new` and replaces `$limit`.
+//! struct Migrate;
+//!
+//! impl Fold for Migrate {
+//!     fn fold_expr(&mut self, expr: Expr) -> Expr {
+//!         match expr {
+//!             Expr::Variable(ref name) if name.local_part() == "limit" => {
+//!                 Expr::Literal(Value::Number(2.0))
+//!             }
+//!             expr => fold::fold_expr(self, expr),
+//!         }
+//!     }
+//!
+//!     fn fold_name_test(&mut self, name: NameTest) -> NameTest {
+//!         match name.prefix.as_deref() {
+//!             Some("old") => NameTest { prefix: Some("new".into()), ..name },
+//!             _ => name,
+//!         }
+//!     }
+//! }
+//!
+//! let package = parser::parse("<a xmlns='urn:new'><b/><b/><b/></a>").expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let stored = Factory::new()
+//!     .build("count(//old:b[position() <= $limit])")
+//!     .expect("Could not compile XPath");
+//! let migrated: XPath = Migrate.fold_expr(stored.ast().clone()).into();
+//!
+//! let mut context = Context::new();
+//! context.set_namespace("new", "urn:new");
+//! let value = migrated.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//! assert_eq!(2.0, value.number());
+//! ```
+//!
+//! [`Fold`]: trait.Fold.html
+//! [`Expr`]: ../ast/enum.Expr.html
+//! [`fold_expr`]: fn.fold_expr.html
+
+use crate::ast::{Expr, ItemType, KindTest, LookupKey, NodeTest, SequenceType, SingleType, Step};
+use crate::node_test::NameTest;
+use crate::OwnedPrefixedName;
+
+/// Rewrites the parts of a syntax tree.
+pub trait Fold {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_step(&mut self, step: Step) -> Step {
+        fold_step(self, step)
+    }
+
+    /// Rewrites the name of a function, variable or atomic type.
+    fn fold_name(&mut self, name: OwnedPrefixedName) -> OwnedPrefixedName {
+        name
+    }
+
+    /// Rewrites the name that a step, `element()` or `attribute()`
+    /// tests nodes for.
+    fn fold_name_test(&mut self, name: NameTest) -> NameTest {
+        name
+    }
+}
+
+/// Rewrites each part of the expression with the fold.
+pub fn fold_expr<F>(folder: &mut F, expr: Expr) -> Expr
+where
+    F: Fold + ?Sized,
+{
+    let mut boxed = |expr: Box<Expr>| Box::new(folder.fold_expr(*expr));
+
+    match expr {
+        Expr::ContextNode | Expr::RootNode | Expr::Literal(_) => expr,
+        Expr::Variable(name) => Expr::Variable(folder.fold_name(name)),
+        Expr::Function { name, arguments } => Expr::Function {
+            name: folder.fold_name(name),
+            arguments: fold_all(folder, arguments),
+        },
+        Expr::Path { start_point, steps } => Expr::Path {
+            start_point: Box::new(folder.fold_expr(*start_point)),
+            steps: steps
+                .into_iter()
+                .map(|step| folder.fold_step(step))
+                .collect(),
+        },
+        Expr::Filter {
+            expression,
+            predicate,
+        } => Expr::Filter {
+            expression: boxed(expression),
+            predicate: boxed(predicate),
+        },
+        Expr::Negation(expression) => Expr::Negation(boxed(expression)),
+        Expr::Binary {
+            operator,
+            left,
+            right,
+        } => Expr::Binary {
+            operator,
+            left: boxed(left),
+            right: boxed(right),
+        },
+        Expr::If {
+            condition,
+            consequent,
+            alternative,
+        } => Expr::If {
+            condition: boxed(condition),
+            consequent: boxed(consequent),
+            alternative: boxed(alternative),
+        },
+        Expr::For {
+            variable,
+            sequence,
+            body,
+        } => Expr::For {
+            variable: folder.fold_name(variable),
+            sequence: Box::new(folder.fold_expr(*sequence)),
+            body: Box::new(folder.fold_expr(*body)),
+        },
+        Expr::Let {
+            variable,
+            value,
+            body,
+        } => Expr::Let {
+            variable: folder.fold_name(variable),
+            value: Box::new(folder.fold_expr(*value)),
+            body: Box::new(folder.fold_expr(*body)),
+        },
+        Expr::Quantified {
+            quantifier,
+            variable,
+            sequence,
+            test,
+        } => Expr::Quantified {
+            quantifier,
+            variable: folder.fold_name(variable),
+            sequence: Box::new(folder.fold_expr(*sequence)),
+            test: Box::new(folder.fold_expr(*test)),
+        },
+        Expr::InstanceOf {
+            expression,
+            sequence_type,
+        } => Expr::InstanceOf {
+            expression: Box::new(folder.fold_expr(*expression)),
+            sequence_type: fold_sequence_type(folder, sequence_type),
+        },
+        Expr::TreatAs {
+            expression,
+            sequence_type,
+        } => Expr::TreatAs {
+            expression: Box::new(folder.fold_expr(*expression)),
+            sequence_type: fold_sequence_type(folder, sequence_type),
+        },
+        Expr::Cast {
+            expression,
+            single_type,
+        } => Expr::Cast {
+            expression: Box::new(folder.fold_expr(*expression)),
+            single_type: fold_single_type(folder, single_type),
+        },
+        Expr::Castable {
+            expression,
+            single_type,
+        } => Expr::Castable {
+            expression: Box::new(folder.fold_expr(*expression)),
+            single_type: fold_single_type(folder, single_type),
+        },
+        Expr::Sequence(expressions) => Expr::Sequence(fold_all(folder, expressions)),
+        Expr::Map(entries) => Expr::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (folder.fold_expr(key), folder.fold_expr(value)))
+                .collect(),
+        ),
+        Expr::SquareArray(members) => Expr::SquareArray(fold_all(folder, members)),
+        Expr::CurlyArray(expression) => Expr::CurlyArray(boxed(expression)),
+        Expr::Lookup { expression, key } => Expr::Lookup {
+            expression: Box::new(folder.fold_expr(*expression)),
+            key: match key {
+                LookupKey::Expression(key) => {
+                    LookupKey::Expression(Box::new(folder.fold_expr(*key)))
+                }
+                key => key,
+            },
+        },
+        Expr::InlineFunction { parameters, body } => Expr::InlineFunction {
+            parameters: parameters
+                .into_iter()
+                .map(|name| folder.fold_name(name))
+                .collect(),
+            body: Box::new(folder.fold_expr(*body)),
+        },
+        Expr::DynamicCall {
+            function,
+            arguments,
+        } => Expr::DynamicCall {
+            function: Box::new(folder.fold_expr(*function)),
+            arguments: fold_all(folder, arguments),
+        },
+    }
+}
+
+/// Rewrites the node test and predicates of the step with the fold.
+pub fn fold_step<F>(folder: &mut F, step: Step) -> Step
+where
+    F: Fold + ?Sized,
+{
+    let node_test = match step.node_test {
+        NodeTest::Name(name) => NodeTest::Name(folder.fold_name_test(name)),
+        node_test => node_test,
+    };
+
+    Step {
+        axis: step.axis,
+        node_test,
+        predicates: fold_all(folder, step.predicates),
+    }
+}
+
+fn fold_all<F>(folder: &mut F, expressions: Vec<Expr>) -> Vec<Expr>
+where
+    F: Fold + ?Sized,
+{
+    expressions
+        .into_iter()
+        .map(|expr| folder.fold_expr(expr))
+        .collect()
+}
+
+fn fold_sequence_type<F>(folder: &mut F, sequence_type: SequenceType) -> SequenceType
+where
+    F: Fold + ?Sized,
+{
+    let item_type = sequence_type.item_type.map(|item_type| match item_type {
+        ItemType::Atomic(name) => ItemType::Atomic(folder.fold_name(name)),
+        ItemType::Kind(KindTest::Element(Some(name))) => {
+            ItemType::Kind(KindTest::Element(Some(folder.fold_name_test(name))))
+        }
+        ItemType::Kind(KindTest::Attribute(Some(name))) => {
+            ItemType::Kind(KindTest::Attribute(Some(folder.fold_name_test(name))))
+        }
+        item_type => item_type,
+    });
+
+    SequenceType {
+        item_type,
+        occurrence: sequence_type.occurrence,
+    }
+}
+
+fn fold_single_type<F>(folder: &mut F, single_type: SingleType) -> SingleType
+where
+    F: Fold + ?Sized,
+{
+    SingleType {
+        name: folder.fold_name(single_type.name),
+        optional: single_type.optional,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Factory, Value};
+
+    fn build(xpath: &str) -> Expr {
+        Factory::new()
+            .build(xpath)
+            .expect("Unable to build XPath")
+            .ast()
+            .clone()
+    }
+
+    struct Identity;
+
+    impl Fold for Identity {}
+
+    #[test]
+    fn default_fold_keeps_the_tree() {
+        let xpaths = [
+            "//a[@b = $c]/text() | f:g(1, 'x')",
+            "for $i in (1 to 3) return let $j := $i return $j instance of xs:integer+",
+            "some $x in a satisfies $x castable as xs:double?",
+            "map { 'k' : [1, 2] }?k?1",
+            "function($p) { $p }(.)",
+            "(a treat as element(p:b)*) | a/attribute::p:*",
+        ];
+        for xpath in xpaths.iter() {
+            let ast = build(xpath);
+            assert_eq!(ast, Identity.fold_expr(ast.clone()), "{}", xpath);
+        }
+    }
+
+    struct Prefixes;
+
+    impl Fold for Prefixes {
+        fn fold_name(&mut self, name: OwnedPrefixedName) -> OwnedPrefixedName {
+            match name.prefix() {
+                Some("old") => OwnedPrefixedName::from(("new", name.local_part())),
+                _ => name,
+            }
+        }
+
+        fn fold_name_test(&mut self, name: NameTest) -> NameTest {
+            match name.prefix.as_deref() {
+                Some("old") => NameTest {
+                    prefix: Some("new".into()),
+                    ..name
+                },
+                _ => name,
+            }
+        }
+    }
+
+    #[test]
+    fn names_are_rewritten_everywhere() {
+        let ast = build(
+            "old:f($old:v)/old:a[. instance of element(old:b)] | (for $old:i in 1 return $old:i cast as old:t)",
+        );
+        let rewritten = Prefixes.fold_expr(ast);
+
+        assert_eq!(vec!["new"], rewritten.prefixes());
+    }
+
+    struct Inline<'a>(&'a str, Value<'static>);
+
+    impl Fold for Inline<'_> {
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            match expr {
+                Expr::Variable(ref name) if name.local_part() == self.0 => {
+                    Expr::Literal(self.1.clone())
+                }
+                expr => fold_expr(self, expr),
+            }
+        }
+    }
+
+    #[test]
+    fn expressions_are_replaced_before_their_children() {
+        let ast = build("$limit * 2 > count(a[$limit])");
+        let rewritten = Inline("limit", Value::Number(3.0)).fold_expr(ast);
+
+        assert_eq!(build("3 * 2 > count(a[3])"), rewritten);
+        assert!(rewritten.variables().is_empty());
+    }
+}
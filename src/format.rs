@@ -0,0 +1,680 @@
+//! Writes syntax trees back out as XPaths.
+//!
+//! A [`Formatter`][] gives every expression with the same syntax tree
+//! the same text, with single spaces around operators, no parentheses
+//! that are not needed and every step written out in full. This makes
+//! stored XPaths easy to compare.
+//!
+//! ```
+//! use sxd_xpath::format::Formatter;
+//!
+//! let mut formatter = Formatter::new();
+//! let canonical = formatter.format_xpath("//item[ ( @price>10 ) ]").expect("Could not compile XPath");
+//! assert_eq!(
+//!     "/descendant-or-self::node()/child::item[attribute::price > 10]",
+//!     canonical
+//! );
+//!
+//! formatter.set_abbreviate(true);
+//! let abbreviated = formatter.format_xpath(&canonical).expect("Could not compile XPath");
+//! assert_eq!("//item[@price > 10]", abbreviated);
+//! ```
+//!
+//! Parsing the text gives the same syntax tree again. Strings that
+//! contain both kinds of quote cannot be written as a single literal
+//! and are written as a call to `concat()` instead.
+//!
+//! [`Formatter`]: struct.Formatter.html
+
+use crate::ast::{BinaryOperator, Expr, LookupKey, NodeTest, Occurrence, Quantifier, Step};
+use crate::axis::Axis;
+use crate::node_test::NameTest;
+use crate::{Factory, OwnedPrefixedName, ParserError, Value};
+
+// How tightly each kind of expression binds, loosest first. An
+// expression is put in parentheses where only tighter ones can appear.
+const SEQUENCE: u8 = 0;
+const SINGLE: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const RELATIONAL: u8 = 5;
+const RANGE: u8 = 6;
+const ADDITIVE: u8 = 7;
+const MULTIPLICATIVE: u8 = 8;
+const INSTANCE_OF: u8 = 9;
+const TREAT: u8 = 10;
+const CASTABLE: u8 = 11;
+const CAST: u8 = 12;
+const UNARY: u8 = 13;
+const UNION: u8 = 14;
+const PATH: u8 = 15;
+const POSTFIX: u8 = 16;
+const PRIMARY: u8 = 17;
+
+/// Writes syntax trees as XPaths in a canonical form.
+#[derive(Debug, Clone, Default)]
+pub struct Formatter {
+    abbreviate: bool,
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        Formatter::default()
+    }
+
+    /// Whether steps are written with the abbreviated syntax, such as
+    /// `@a` for `attribute::a` and `..` for `parent::node()`.
+    pub fn set_abbreviate(&mut self, abbreviate: bool) {
+        self.abbreviate = abbreviate;
+    }
+
+    pub fn format(&self, expr: &Expr) -> String {
+        let mut out = String::new();
+        self.write(&mut out, expr, SEQUENCE);
+        out
+    }
+
+    /// Parses the XPath and formats its syntax tree.
+    pub fn format_xpath(&self, xpath: &str) -> Result<String, ParserError> {
+        let built = Factory::new().build(xpath)?;
+        Ok(self.format(built.ast()))
+    }
+
+    fn write(&self, out: &mut String, expr: &Expr, level: u8) {
+        let nested = precedence(expr) < level;
+        if nested {
+            out.push('(');
+        }
+
+        match *expr {
+            Expr::ContextNode => out.push('.'),
+            Expr::RootNode => out.push('/'),
+            Expr::Literal(ref value) => write_literal(out, value),
+            Expr::Variable(ref name) => {
+                out.push('$');
+                write_name(out, name);
+            }
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => {
+                write_name(out, name);
+                self.write_arguments(out, arguments);
+            }
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => self.write_path(out, start_point, steps),
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => {
+                self.write(out, expression, POSTFIX);
+                self.write_predicate(out, predicate);
+            }
+            Expr::Negation(ref expr) => {
+                out.push('-');
+                self.write(out, expr, UNARY);
+            }
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => {
+                let (symbol, level) = binary_operator(operator);
+                let (left_level, right_level) = match operator {
+                    // Ranges do not chain in either direction
+                    BinaryOperator::Range => (ADDITIVE, ADDITIVE),
+                    _ => (level, level + 1),
+                };
+                self.write(out, left, left_level);
+                out.push(' ');
+                out.push_str(symbol);
+                out.push(' ');
+                self.write(out, right, right_level);
+            }
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => {
+                out.push_str("if (");
+                self.write(out, condition, SEQUENCE);
+                out.push_str(") then ");
+                self.write(out, consequent, SINGLE);
+                out.push_str(" else ");
+                self.write(out, alternative, SINGLE);
+            }
+            Expr::For { .. } => {
+                out.push_str("for ");
+                let body = self.write_bindings(out, expr, " in ", |expr| match *expr {
+                    Expr::For {
+                        ref variable,
+                        ref sequence,
+                        ref body,
+                    } => Some((variable, &**sequence, &**body)),
+                    _ => None,
+                });
+                out.push_str(" return ");
+                self.write(out, body, SINGLE);
+            }
+            Expr::Let { .. } => {
+                out.push_str("let ");
+                let body = self.write_bindings(out, expr, " := ", |expr| match *expr {
+                    Expr::Let {
+                        ref variable,
+                        ref value,
+                        ref body,
+                    } => Some((variable, &**value, &**body)),
+                    _ => None,
+                });
+                out.push_str(" return ");
+                self.write(out, body, SINGLE);
+            }
+            Expr::Quantified { quantifier, .. } => {
+                out.push_str(match quantifier {
+                    Quantifier::Some => "some ",
+                    Quantifier::Every => "every ",
+                });
+                let test = self.write_bindings(out, expr, " in ", |expr| match *expr {
+                    Expr::Quantified {
+                        quantifier: q,
+                        ref variable,
+                        ref sequence,
+                        ref test,
+                    } if q == quantifier => Some((variable, &**sequence, &**test)),
+                    _ => None,
+                });
+                out.push_str(" satisfies ");
+                self.write(out, test, SINGLE);
+            }
+            Expr::InstanceOf {
+                ref expression,
+                ref sequence_type,
+            } => {
+                self.write(out, expression, TREAT);
+                out.push_str(" instance of ");
+                out.push_str(&sequence_type.to_string());
+            }
+            Expr::TreatAs {
+                ref expression,
+                ref sequence_type,
+            } => {
+                self.write(out, expression, CASTABLE);
+                out.push_str(" treat as ");
+                out.push_str(&sequence_type.to_string());
+            }
+            Expr::Castable {
+                ref expression,
+                ref single_type,
+            } => {
+                self.write(out, expression, CAST);
+                out.push_str(" castable as ");
+                write_name(out, &single_type.name);
+                if single_type.optional {
+                    out.push('?');
+                }
+            }
+            Expr::Cast {
+                ref expression,
+                ref single_type,
+            } => {
+                self.write(out, expression, UNARY);
+                out.push_str(" cast as ");
+                write_name(out, &single_type.name);
+                if single_type.optional {
+                    out.push('?');
+                }
+            }
+            Expr::Sequence(ref expressions) => {
+                if expressions.is_empty() {
+                    out.push_str("()");
+                }
+                self.write_list(out, expressions);
+            }
+            Expr::Map(ref entries) => {
+                out.push_str("map {");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    out.push_str(if i == 0 { " " } else { ", " });
+                    self.write(out, key, SINGLE);
+                    out.push_str(" : ");
+                    self.write(out, value, SINGLE);
+                }
+                out.push_str(" }");
+            }
+            Expr::SquareArray(ref members) => {
+                out.push('[');
+                self.write_list(out, members);
+                out.push(']');
+            }
+            Expr::CurlyArray(ref expr) => {
+                out.push_str("array ");
+                self.write_block(out, expr);
+            }
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => {
+                // A lookup without an expression applies to the context item
+                if **expression != Expr::ContextNode {
+                    self.write(out, expression, POSTFIX);
+                }
+                out.push('?');
+                match *key {
+                    LookupKey::Name(ref name) => out.push_str(name),
+                    LookupKey::Wildcard => out.push('*'),
+                    LookupKey::Expression(ref key) => match **key {
+                        Expr::Literal(Value::Number(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                            write_literal(out, &Value::Number(n))
+                        }
+                        ref key => {
+                            out.push('(');
+                            self.write(out, key, SEQUENCE);
+                            out.push(')');
+                        }
+                    },
+                }
+            }
+            Expr::InlineFunction {
+                ref parameters,
+                ref body,
+            } => {
+                out.push_str("function(");
+                for (i, parameter) in parameters.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push('$');
+                    write_name(out, parameter);
+                }
+                out.push_str(") ");
+                self.write_block(out, body);
+            }
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => {
+                self.write(out, function, POSTFIX);
+                self.write_arguments(out, arguments);
+            }
+        }
+
+        if nested {
+            out.push(')');
+        }
+    }
+
+    fn write_list(&self, out: &mut String, expressions: &[Expr]) {
+        for (i, expr) in expressions.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            self.write(out, expr, SINGLE);
+        }
+    }
+
+    fn write_arguments(&self, out: &mut String, arguments: &[Expr]) {
+        out.push('(');
+        self.write_list(out, arguments);
+        out.push(')');
+    }
+
+    fn write_predicate(&self, out: &mut String, predicate: &Expr) {
+        out.push('[');
+        self.write(out, predicate, SEQUENCE);
+        out.push(']');
+    }
+
+    /// Writes the body of an array or function, where nothing is the
+    /// empty sequence.
+    fn write_block(&self, out: &mut String, expr: &Expr) {
+        match *expr {
+            Expr::Sequence(ref expressions) if expressions.is_empty() => out.push_str("{ }"),
+            _ => {
+                out.push_str("{ ");
+                self.write(out, expr, SEQUENCE);
+                out.push_str(" }");
+            }
+        }
+    }
+
+    /// Writes the bindings of nested expressions of the same kind as
+    /// one list, as the parser nests a list of bindings, and gives the
+    /// innermost body.
+    fn write_bindings<'a, F>(
+        &self,
+        out: &mut String,
+        mut expr: &'a Expr,
+        separator: &str,
+        binding: F,
+    ) -> &'a Expr
+    where
+        F: Fn(&'a Expr) -> Option<(&'a OwnedPrefixedName, &'a Expr, &'a Expr)>,
+    {
+        let mut first = true;
+        while let Some((variable, value, body)) = binding(expr) {
+            if !first {
+                out.push_str(", ");
+            }
+            out.push('$');
+            write_name(out, variable);
+            out.push_str(separator);
+            self.write(out, value, SINGLE);
+
+            first = false;
+            expr = body;
+        }
+        expr
+    }
+
+    fn write_path(&self, out: &mut String, start_point: &Expr, steps: &[Step]) {
+        let mut separator = match *start_point {
+            Expr::RootNode => "/",
+            Expr::ContextNode => "",
+            ref start_point => {
+                self.write(out, start_point, POSTFIX);
+                "/"
+            }
+        };
+
+        for (i, step) in steps.iter().enumerate() {
+            let between_steps = !separator.is_empty() && i + 1 < steps.len();
+            if self.abbreviate && between_steps && is_any_descendant(step) {
+                separator = "//";
+                continue;
+            }
+
+            out.push_str(separator);
+            self.write_step(out, step);
+            separator = "/";
+        }
+    }
+
+    fn write_step(&self, out: &mut String, step: &Step) {
+        let bare = step.predicates.is_empty() && step.node_test == NodeTest::Node;
+        match step.axis {
+            Axis::SelfAxis if self.abbreviate && bare => out.push('.'),
+            Axis::Parent if self.abbreviate && bare => out.push_str(".."),
+            Axis::Child if self.abbreviate => write_node_test(out, &step.node_test),
+            Axis::Attribute if self.abbreviate => {
+                out.push('@');
+                write_node_test(out, &step.node_test);
+            }
+            axis => {
+                out.push_str(axis_name(axis));
+                out.push_str("::");
+                write_node_test(out, &step.node_test);
+            }
+        }
+
+        for predicate in &step.predicates {
+            self.write_predicate(out, predicate);
+        }
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match *expr {
+        Expr::Sequence(ref expressions) if !expressions.is_empty() => SEQUENCE,
+        // The tokenizer reads a name after an occurrence indicator as a
+        // name test, so a keyword such as `and` cannot follow one
+        Expr::InstanceOf {
+            ref sequence_type, ..
+        }
+        | Expr::TreatAs {
+            ref sequence_type, ..
+        } if sequence_type.occurrence != Occurrence::ExactlyOne => SEQUENCE,
+        Expr::Cast {
+            ref single_type, ..
+        }
+        | Expr::Castable {
+            ref single_type, ..
+        } if single_type.optional => SEQUENCE,
+        // A lone `/` is followed by parentheses, as `/ * 2` would read
+        // `*` as a name
+        Expr::RootNode
+        | Expr::If { .. }
+        | Expr::For { .. }
+        | Expr::Let { .. }
+        | Expr::Quantified { .. } => SINGLE,
+        Expr::Binary { operator, .. } => binary_operator(operator).1,
+        Expr::InstanceOf { .. } => INSTANCE_OF,
+        Expr::TreatAs { .. } => TREAT,
+        Expr::Castable { .. } => CASTABLE,
+        Expr::Cast { .. } => CAST,
+        Expr::Negation(_) => UNARY,
+        Expr::Literal(Value::Number(n)) if n.is_sign_negative() => UNARY,
+        Expr::ContextNode | Expr::Path { .. } => PATH,
+        Expr::Lookup { ref expression, .. } if **expression == Expr::ContextNode => PRIMARY,
+        Expr::Filter { .. } | Expr::Lookup { .. } | Expr::DynamicCall { .. } => POSTFIX,
+        Expr::Literal(_)
+        | Expr::Variable(_)
+        | Expr::Function { .. }
+        | Expr::Sequence(_)
+        | Expr::Map(_)
+        | Expr::SquareArray(_)
+        | Expr::CurlyArray(_)
+        | Expr::InlineFunction { .. } => PRIMARY,
+    }
+}
+
+pub(crate) fn binary_operator(operator: BinaryOperator) -> (&'static str, u8) {
+    use crate::ast::BinaryOperator::*;
+
+    match operator {
+        Or => ("or", OR),
+        And => ("and", AND),
+        Equal => ("=", EQUALITY),
+        NotEqual => ("!=", EQUALITY),
+        ValueEqual => ("eq", EQUALITY),
+        ValueNotEqual => ("ne", EQUALITY),
+        LessThan => ("<", RELATIONAL),
+        LessThanOrEqual => ("<=", RELATIONAL),
+        GreaterThan => (">", RELATIONAL),
+        GreaterThanOrEqual => (">=", RELATIONAL),
+        Is => ("is", RELATIONAL),
+        Precedes => ("<<", RELATIONAL),
+        Follows => (">>", RELATIONAL),
+        ValueLessThan => ("lt", RELATIONAL),
+        ValueLessThanOrEqual => ("le", RELATIONAL),
+        ValueGreaterThan => ("gt", RELATIONAL),
+        ValueGreaterThanOrEqual => ("ge", RELATIONAL),
+        Range => ("to", RANGE),
+        Addition => ("+", ADDITIVE),
+        Subtraction => ("-", ADDITIVE),
+        Multiplication => ("*", MULTIPLICATIVE),
+        Division => ("div", MULTIPLICATIVE),
+        Remainder => ("mod", MULTIPLICATIVE),
+        Union => ("|", UNION),
+    }
+}
+
+pub(crate) fn axis_name(axis: Axis) -> &'static str {
+    match axis {
+        Axis::Ancestor => "ancestor",
+        Axis::AncestorOrSelf => "ancestor-or-self",
+        Axis::Attribute => "attribute",
+        Axis::Namespace => "namespace",
+        Axis::Child => "child",
+        Axis::Descendant => "descendant",
+        Axis::DescendantOrSelf => "descendant-or-self",
+        Axis::Parent => "parent",
+        Axis::PrecedingSibling => "preceding-sibling",
+        Axis::FollowingSibling => "following-sibling",
+        Axis::Preceding => "preceding",
+        Axis::Following => "following",
+        Axis::SelfAxis => "self",
+    }
+}
+
+/// Whether the step is the `descendant-or-self::node()` that `//`
+/// stands for.
+fn is_any_descendant(step: &Step) -> bool {
+    step.axis == Axis::DescendantOrSelf
+        && step.node_test == NodeTest::Node
+        && step.predicates.is_empty()
+}
+
+pub(crate) fn write_node_test(out: &mut String, node_test: &NodeTest) {
+    match *node_test {
+        NodeTest::Name(NameTest {
+            ref prefix,
+            ref local_part,
+        }) => {
+            if let Some(ref prefix) = *prefix {
+                out.push_str(prefix);
+                out.push(':');
+            }
+            out.push_str(local_part);
+        }
+        NodeTest::Node => out.push_str("node()"),
+        NodeTest::Text => out.push_str("text()"),
+        NodeTest::Comment => out.push_str("comment()"),
+        NodeTest::ProcessingInstruction(ref target) => {
+            out.push_str("processing-instruction(");
+            if let Some(ref target) = *target {
+                write_string(out, target);
+            }
+            out.push(')');
+        }
+    }
+}
+
+pub(crate) fn write_name(out: &mut String, name: &OwnedPrefixedName) {
+    if let Some(prefix) = name.prefix() {
+        out.push_str(prefix);
+        out.push(':');
+    }
+    out.push_str(name.local_part());
+}
+
+/// Writes the literals that the parser produces. Other values, which
+/// only appear in trees that are built by hand, are written as their
+/// string value.
+pub(crate) fn write_literal(out: &mut String, value: &Value<'_>) {
+    match *value {
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::Boolean(b) => out.push_str(if b { "true()" } else { "false()" }),
+        Value::String(ref s) => write_string(out, s),
+        ref value => write_string(out, &value.string()),
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    if !s.contains('\'') {
+        out.push('\'');
+        out.push_str(s);
+        out.push('\'');
+    } else if !s.contains('"') {
+        out.push('"');
+        out.push_str(s);
+        out.push('"');
+    } else {
+        out.push_str("concat(");
+        for (i, part) in s.split('\'').enumerate() {
+            if i > 0 {
+                out.push_str(", \"'\", ");
+            }
+            write_string(out, part);
+        }
+        out.push(')');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format(xpath: &str) -> String {
+        Formatter::new()
+            .format_xpath(xpath)
+            .expect("Unable to format XPath")
+    }
+
+    fn abbreviated(xpath: &str) -> String {
+        let mut formatter = Formatter::new();
+        formatter.set_abbreviate(true);
+        formatter
+            .format_xpath(xpath)
+            .expect("Unable to format XPath")
+    }
+
+    #[test]
+    fn formatted_xpaths_parse_to_the_same_tree() {
+        let xpaths = [
+            "/a/b[@c = 'd'][2]//e | ../f/text() | self::node()/g",
+            "(a | b)/c | (/)[1], -(1 + 2) * 3 - 4 div (5 mod 6)",
+            "1 to 2 + 3, (1 to 2) = 3, a < b = (c < d)",
+            "not(a and (b or c)) or (d or e) and f",
+            "for $a in 1 to 3, $b in $a return let $c := $b * 2 return $c",
+            "some $x in a satisfies every $y in $x satisfies $y != 'z'",
+            "if (a, b) then (1, 2) else if (c) then () else 3",
+            "(a instance of element(p:q)*) and (b treat as xs:string?) castable as xs:int?",
+            "if (a) then (b instance of item()+) else (c cast as xs:int?)",
+            "-a cast as xs:double, (a + b) cast as xs:string",
+            "map { 'a' : [1, (2, 3)], b : array { c } }?a?1?(1 + 1)?*",
+            "function($f, $g) { $f($g) }(function() { }, ?a)",
+            "$f(1)[2]?x, f(1)[. > 2], (a/b)/c, ./a, a/./b, a//b",
+            "processing-instruction('p') | namespace::x | p:*",
+            "\"it's\" = 'say \"hi\"' or concat('a', \"'\") = '\"'",
+        ];
+
+        let factory = Factory::new();
+        for xpath in xpaths.iter() {
+            let original = factory
+                .build(xpath)
+                .unwrap_or_else(|e| panic!("{} does not parse: {}", xpath, e));
+            for formatted in &[format(xpath), abbreviated(xpath)] {
+                let reparsed = factory
+                    .build(formatted)
+                    .unwrap_or_else(|e| panic!("{} does not parse: {}", formatted, e));
+                assert_eq!(original.ast(), reparsed.ast(), "{}", formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn whitespace_and_parentheses_are_made_consistent() {
+        assert_eq!("1 + 2 * 3", format("(1)+(2*3)"));
+        assert_eq!("(1 + 2) * 3", format("( 1+2 )*3"));
+        assert_eq!("f(1, 'a')", format("f( 1,'a' )"));
+        assert_eq!(
+            "for $a in 1, $b in 2 return $a",
+            format("for $a in 1 return for $b in 2 return $a")
+        );
+    }
+
+    #[test]
+    fn steps_are_written_in_full() {
+        assert_eq!(
+            "/descendant-or-self::node()/child::a/attribute::b | parent::node()",
+            format("//a/@b | ..")
+        );
+        assert_eq!(".", format("."));
+    }
+
+    #[test]
+    fn steps_can_be_abbreviated() {
+        assert_eq!(
+            "//a/@b | ../c//d[.] | descendant-or-self::node()/e | a/descendant-or-self::node()",
+            abbreviated("//a/@b | ../c//d[.] | descendant-or-self::node()/child::e | a/descendant-or-self::node()")
+        );
+    }
+
+    #[test]
+    fn strings_use_a_quote_they_do_not_contain() {
+        let string =
+            |value: &str| Formatter::new().format(&Expr::Literal(Value::String(value.into())));
+
+        assert_eq!("'a\"b'", string("a\"b"));
+        assert_eq!("\"a'b\"", string("a'b"));
+        assert_eq!("concat('a\"', \"'\", 'b')", string("a\"'b"));
+    }
+
+    #[test]
+    fn expressions_display_in_canonical_form() {
+        let xpath = Factory::new().build("@a*2").expect("Unable to build XPath");
+        assert_eq!("attribute::a * 2", xpath.ast().to_string());
+    }
+}
@@ -0,0 +1,112 @@
+//! Result tree fragments: small documents that functions create while
+//! an expression is evaluated, such as the `token` elements returned
+//! by `str:tokenize`.
+//!
+//! The nodes of a fragment must live as long as the nodes of the
+//! document being queried, so the fragments are kept in storage that
+//! the caller provides to the [`Context`][]. A custom function creates
+//! a fragment with [`Evaluation::new_fragment`][] and may return any
+//! of the nodes it adds to it:
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::fragment::Fragments;
+//! use sxd_xpath::nodeset::Nodeset;
+//! use sxd_xpath::{context, function, Context, Factory, Value};
+//!
+//! struct Greet;
+//! impl function::Function for Greet {
+//!     fn evaluate<'c, 'd>(&self,
+//!                         context: &context::Evaluation<'c, 'd>,
+//!                         args: Vec<Value<'d>>)
+//!                         -> Result<Value<'d>, function::Error>
+//!     {
+//!         let mut args = function::Args(args);
+//!         args.exactly(1)?;
+//!         let name = args.pop_string()?;
+//!
+//!         let fragment = context.new_fragment().ok_or(function::Error::NoFragments)?;
+//!         let greeting = fragment.document().create_element("greeting");
+//!         greeting.set_attribute_value("to", &name);
+//!         fragment.append_child(greeting);
+//!
+//!         let mut nodes = Nodeset::new();
+//!         nodes.add(greeting);
+//!         Ok(Value::Nodeset(nodes))
+//!     }
+//! }
+//!
+//! let fragments = Fragments::new();
+//! let package = parser::parse("<guest>Ada</guest>").expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let mut context = Context::new();
+//! context.set_fragments(&fragments);
+//! context.set_function("greet", Greet);
+//!
+//! let xpath = Factory::new().build("greet(/guest)/@to").expect("Could not compile XPath");
+//! let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//!
+//! assert_eq!("Ada", value.string());
+//! ```
+//!
+//! A document can only have one element as a child of its root, so
+//! the nodes of a fragment are the children of a `fragment` element
+//! instead of the root.
+//!
+//! [`Context`]: ../context/struct.Context.html
+//! [`Evaluation::new_fragment`]: ../context/struct.Evaluation.html#method.new_fragment
+
+use sxd_document::{dom, Package};
+use typed_arena::Arena;
+
+use crate::pool::DocumentPool;
+
+/// Owns the result tree fragments created during evaluation, and the
+/// documents loaded by `document()` when no [`DocumentPool`][] was
+/// given. They are freed when this is dropped.
+///
+/// [`DocumentPool`]: ../pool/struct.DocumentPool.html
+#[derive(Default)]
+pub struct Fragments {
+    packages: Arena<Package>,
+    documents: DocumentPool,
+}
+
+impl Fragments {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The documents loaded into this storage.
+    pub(crate) fn documents(&self) -> &DocumentPool {
+        &self.documents
+    }
+
+    /// Creates a new, empty fragment, returning the element that its
+    /// nodes are added to.
+    pub(crate) fn create(&self) -> dom::Element<'_> {
+        let doc = self.packages.alloc(Package::new()).as_document();
+        let fragment = doc.create_element("fragment");
+        doc.root().append_child(fragment);
+        fragment
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fragments_are_separate_documents() {
+        let fragments = Fragments::new();
+        let one = fragments.create();
+        let two = fragments.create();
+
+        one.append_child(one.document().create_element("a"));
+
+        assert_eq!(1, one.children().len());
+        assert!(two.children().is_empty());
+        assert_ne!(one.document(), two.document());
+    }
+}
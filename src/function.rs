@@ -1,16 +1,32 @@
 //! Support for registering and creating XPath functions.
 
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 use std::borrow::ToOwned;
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::iter;
 use std::ops::Index;
+use std::rc::Rc;
+use std::{fmt, iter, mem, vec};
 use sxd_document::XmlChar;
 
+use crate::atomic;
+use crate::collation::{self, Collation};
 use crate::context;
-use crate::nodeset::Nodeset;
-use crate::{str_to_num, Value};
+use crate::datetime::{self, DateTime, DateTimeKind};
+use crate::expression::{self, Expression};
+use crate::map::{self, Map};
+use crate::nodeset::{Node, Nodeset};
+use crate::tree::{self, NodeKind, TreeNode};
+use crate::uri;
+use crate::{conv, Item, OwnedQName, Value, XML_NAMESPACE_URI};
+
+/// The namespace of the `map:` functions, which is used for the `map`
+/// prefix unless the context maps it elsewhere.
+pub const MAP_NAMESPACE_URI: &str = "http://www.w3.org/2005/xpath-functions/map";
+/// The namespace of the `array:` functions, which is used for the
+/// `array` prefix unless the context maps it elsewhere.
+pub const ARRAY_NAMESPACE_URI: &str = "http://www.w3.org/2005/xpath-functions/array";
 
 /// Types that can be used as XPath functions.
 pub trait Function {
@@ -30,6 +46,7 @@ pub enum ArgumentType {
     Number,
     String,
     Nodeset,
+    Sequence,
 }
 
 impl<'a> From<&'a Value<'a>> for ArgumentType {
@@ -39,6 +56,7 @@ impl<'a> From<&'a Value<'a>> for ArgumentType {
             Value::Number(..) => ArgumentType::Number,
             Value::String(..) => ArgumentType::String,
             Value::Nodeset(..) => ArgumentType::Nodeset,
+            Value::Sequence(..) => ArgumentType::Sequence,
         }
     }
 }
@@ -54,8 +72,75 @@ pub enum Error {
     ArgumentMissing,
     #[snafu(display("argument was expected to be a nodeset but was a {:?}", actual))]
     ArgumentNotANodeset { actual: ArgumentType },
+    #[snafu(display("argument was expected to be a map but was a {:?}", actual))]
+    ArgumentNotAMap { actual: ArgumentType },
+    #[snafu(display("argument was expected to be an array but was a {:?}", actual))]
+    ArgumentNotAnArray { actual: ArgumentType },
+    #[snafu(display("argument was expected to be an atomic value but was a {:?}", actual))]
+    ArgumentNotAtomic { actual: ArgumentType },
+    #[snafu(display("argument was expected to be a function but was a {:?}", actual))]
+    ArgumentNotAFunction { actual: ArgumentType },
+    #[snafu(display(
+        "array position {} is not valid for an array of size {}",
+        position,
+        size
+    ))]
+    InvalidArrayPosition { position: String, size: usize },
+    #[snafu(display("cannot compare the items {:?} and {:?}", left, right))]
+    IncomparableItems { left: String, right: String },
+    #[snafu(display("function items cannot be compared"))]
+    FunctionsNotComparable,
+    #[snafu(display("invalid regular expression {:?}: {}", pattern, what))]
+    InvalidRegex { pattern: String, what: String },
+    #[snafu(display("invalid regular expression flags {:?}", flags))]
+    InvalidRegexFlags { flags: String },
+    #[snafu(display("regular expression {:?} matches the empty string", pattern))]
+    RegexMatchesEmptyString { pattern: String },
+    #[snafu(display("invalid replacement string {:?}", replacement))]
+    InvalidReplacement { replacement: String },
+    #[snafu(display("argument was expected to be an {} but was a {:?}", expected, actual))]
+    ArgumentNotADateTime {
+        expected: &'static str,
+        actual: ArgumentType,
+    },
+    #[snafu(display("no collation is registered for {:?}", uri))]
+    UnknownCollation { uri: String },
+    #[snafu(display("unsupported Unicode normalization form {:?}", form))]
+    UnsupportedNormalizationForm { form: String },
+    #[snafu(display("no base URI was set to resolve against"))]
+    NoBaseUri,
+    #[snafu(display("the base URI {:?} is not absolute", uri))]
+    InvalidBaseUri { uri: String },
+    #[snafu(display("no storage for result tree fragments was provided"))]
+    NoFragments,
+    #[snafu(display("no document resolver was set to load documents"))]
+    NoDocumentResolver,
+    #[snafu(display("could not load the document {:?}: {}", uri, what))]
+    DocumentNotLoaded { uri: String, what: String },
+    #[snafu(display("the document {:?} has not been loaded yet", uri))]
+    DocumentPending { uri: String },
+    #[snafu(display("no key named {:?} was declared", name))]
+    UnknownKey { name: String },
+    #[snafu(display("error while evaluating the key {:?}: {}", name, source))]
+    KeyEvaluation {
+        name: String,
+        source: Box<expression::Error>,
+    },
+    #[snafu(display("could not format the date: {}", source))]
+    Format { source: datetime::FormatError },
+    #[snafu(display("invalid JSON at offset {}: {}", offset, what))]
+    InvalidJson { offset: usize, what: &'static str },
+    #[snafu(display("the key {:?} appears more than once in the JSON", key))]
+    DuplicateJsonKey { key: String },
+    #[snafu(display("invalid value {:?} for the option {:?}", value, option))]
+    InvalidOption { option: &'static str, value: String },
     #[snafu(display("could not evaluate function: {}", what))]
     Other { what: String },
+    #[snafu(display("error while evaluating function body: {}", source))]
+    Body {
+        #[snafu(source(from(expression::Error, Box::new)))]
+        source: Box<expression::Error>,
+    },
 }
 
 impl Error {
@@ -66,6 +151,80 @@ impl Error {
     }
 }
 
+/// A function that is itself a value, such as one created by an
+/// inline function expression. It may be stored in variables,
+/// sequences, maps and arrays, and called with
+/// [`call`](#method.call).
+#[derive(Clone)]
+pub struct FunctionItem<'d> {
+    parameters: Rc<[OwnedQName]>,
+    body: Rc<dyn Expression>,
+    /// The variables that were bound where the function was created
+    closure: Vec<(OwnedQName, Value<'d>)>,
+}
+
+impl<'d> FunctionItem<'d> {
+    pub(crate) fn new(
+        parameters: Rc<[OwnedQName]>,
+        body: Rc<dyn Expression>,
+        closure: Vec<(OwnedQName, Value<'d>)>,
+    ) -> Self {
+        FunctionItem {
+            parameters,
+            body,
+            closure,
+        }
+    }
+
+    /// The number of arguments the function accepts.
+    pub fn arity(&self) -> usize {
+        self.parameters.len()
+    }
+
+    /// Evaluates the body of the function with each parameter bound
+    /// to the matching argument.
+    pub fn call<'c>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(self.arity())?;
+
+        let mut bindings = self.closure.clone();
+        bindings.extend(self.parameters.iter().cloned().zip(args.0));
+
+        evaluate_with_bindings(context, bindings.into_iter(), &*self.body).context(Body)
+    }
+}
+
+fn evaluate_with_bindings<'c, 'd>(
+    context: &context::Evaluation<'c, 'd>,
+    mut bindings: vec::IntoIter<(OwnedQName, Value<'d>)>,
+    body: &dyn Expression,
+) -> Result<Value<'d>, expression::Error> {
+    match bindings.next() {
+        Some((name, value)) => {
+            let binding = context.bind(name, value);
+            evaluate_with_bindings(&context.new_context_with(&binding), bindings, body)
+        }
+        None => body.evaluate(context),
+    }
+}
+
+impl<'d> fmt::Debug for FunctionItem<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FunctionItem {{ arity: {} }}", self.arity())
+    }
+}
+
+// Function items have no value to compare, only their identity
+impl<'d> PartialEq for FunctionItem<'d> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.parameters, &other.parameters) && self.closure == other.closure
+    }
+}
+
 /// Provides common utility functions for dealing with function
 /// argument lists.
 pub struct Args<'d>(pub Vec<Value<'d>>);
@@ -121,6 +280,11 @@ impl<'d> Args<'d> {
         self.0.into_iter().map(Value::into_string).collect()
     }
 
+    /// Removes the **last** argument without converting it.
+    pub fn pop_value(&mut self) -> Result<Value<'d>, Error> {
+        self.0.pop().ok_or(Error::ArgumentMissing)
+    }
+
     /// Removes the **last** argument and ensures it is a boolean. If
     /// the argument is not a boolean, it is converted to one.
     pub fn pop_boolean(&mut self) -> Result<bool, Error> {
@@ -142,26 +306,107 @@ impl<'d> Args<'d> {
         Ok(v.into_string())
     }
 
+    /// Removes the **last** argument and converts it to a string, or
+    /// returns `None` if it is the empty sequence.
+    fn pop_optional_string(&mut self) -> Result<Option<String>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        Ok(v.into_items().first().map(Item::string))
+    }
+
+    /// Removes the **last** argument and converts it to a number, or
+    /// returns `None` if it is the empty sequence.
+    fn pop_optional_number(&mut self) -> Result<Option<f64>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        Ok(v.into_items().first().map(Item::number))
+    }
+
+    /// Removes the collation argument, when there are more than
+    /// `arity` arguments, and looks up the collation it names.
+    /// Otherwise the default collation of the context is used.
+    fn pop_collation<'c>(
+        &mut self,
+        context: &context::Evaluation<'c, 'd>,
+        arity: usize,
+    ) -> Result<&'c dyn Collation, Error> {
+        if self.len() <= arity {
+            return Ok(context.default_collation().unwrap_or(&collation::Codepoint));
+        }
+
+        let uri = self.pop_string()?;
+        context
+            .collation_for(&uri)
+            .ok_or(Error::UnknownCollation { uri })
+    }
+
     /// Removes the **last** argument and ensures it is a nodeset. If
     /// the argument is not a nodeset, a type mismatch error is
     /// returned.
     pub fn pop_nodeset(&mut self) -> Result<Nodeset<'d>, Error> {
         let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
-        match v {
-            Value::Nodeset(v) => Ok(v),
-            a => Err(Error::not_a_nodeset(&a)),
+        v.into_nodeset().map_err(|a| Error::not_a_nodeset(&a))
+    }
+
+    /// Removes the **last** argument and returns its items. An atomic
+    /// value is treated as a sequence of one item.
+    pub fn pop_sequence(&mut self) -> Result<Vec<Item<'d>>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        Ok(v.into_items())
+    }
+
+    /// Removes the **last** argument and ensures it is a single map.
+    pub fn pop_map(&mut self) -> Result<Map<'d>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        let actual = ArgumentType::from(&v);
+        match v.into_items()[..] {
+            [Item::Map(ref mut map)] => Ok(mem::take(map)),
+            _ => Err(Error::ArgumentNotAMap { actual }),
+        }
+    }
+
+    /// Removes the **last** argument and ensures it is a single
+    /// array, returning its members.
+    pub fn pop_array(&mut self) -> Result<Vec<Value<'d>>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        let actual = ArgumentType::from(&v);
+        match v.into_items()[..] {
+            [Item::Array(ref mut members)] => Ok(mem::take(members)),
+            _ => Err(Error::ArgumentNotAnArray { actual }),
+        }
+    }
+
+    /// Removes the **last** argument and ensures it is a single
+    /// function item.
+    pub fn pop_function(&mut self) -> Result<FunctionItem<'d>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        let actual = ArgumentType::from(&v);
+        match v.into_items()[..] {
+            [Item::Function(ref function)] => Ok(function.clone()),
+            _ => Err(Error::ArgumentNotAFunction { actual }),
         }
     }
 
+    /// Removes the **last** argument and ensures it is a single atomic
+    /// value. A node is converted to its string value.
+    pub fn pop_atomic(&mut self) -> Result<Item<'d>, Error> {
+        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        let actual = ArgumentType::from(&v);
+        atomic::atomize(v)
+            .ok()
+            .flatten()
+            .filter(Item::is_atomic)
+            .ok_or(Error::ArgumentNotAtomic { actual })
+    }
+
     /// Removes the **last** argument. If no argument is present, the
-    /// context node is returned as a nodeset.
+    /// context item is returned.
     fn pop_value_or_context_node<'c>(
         &mut self,
         context: &context::Evaluation<'c, 'd>,
     ) -> Value<'d> {
-        self.0
-            .pop()
-            .unwrap_or_else(|| Value::Nodeset(nodeset![context.node]))
+        self.0.pop().unwrap_or_else(|| match context.item {
+            Some(item) => item.clone().into(),
+            None => Value::Nodeset(nodeset![context.node]),
+        })
     }
 
     /// Removes the **last** argument if it is a string. If no
@@ -175,7 +420,10 @@ impl<'d> Args<'d> {
         self.0
             .pop()
             .map(Value::into_string)
-            .unwrap_or_else(|| context.node.string_value())
+            .unwrap_or_else(|| match context.item {
+                Some(item) => item.string(),
+                None => context.node.string_value(),
+            })
     }
 
     /// Removes the **last** argument if it is a nodeset. If no
@@ -187,9 +435,11 @@ impl<'d> Args<'d> {
         context: &context::Evaluation<'c, 'd>,
     ) -> Result<Nodeset<'d>, Error> {
         match self.0.pop() {
-            Some(Value::Nodeset(ns)) => Ok(ns),
-            Some(arg) => Err(Error::not_a_nodeset(&arg)),
-            None => Ok(nodeset![context.node]),
+            Some(arg) => arg.into_nodeset().map_err(|a| Error::not_a_nodeset(&a)),
+            None => match context.item {
+                Some(item) => Err(Error::not_a_nodeset(&item.clone().into())),
+                None => Ok(nodeset![context.node]),
+            },
         }
     }
 }
@@ -240,8 +490,11 @@ impl Function for Count {
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
         args.exactly(1)?;
-        let arg = args.pop_nodeset()?;
-        Ok(Value::Number(arg.size() as f64))
+        let size = match args.pop_value()? {
+            Value::Nodeset(ns) => ns.size(),
+            other => other.into_items().len(),
+        };
+        Ok(Value::Number(size as f64))
     }
 }
 
@@ -334,55 +587,65 @@ impl Function for Concat {
     }
 }
 
-struct TwoStringPredicate(fn(&str, &str) -> bool);
+struct TwoStringPredicate(fn(&dyn Collation, &str, &str) -> bool);
 
 impl Function for TwoStringPredicate {
     fn evaluate<'c, 'd>(
         &self,
-        _context: &context::Evaluation<'c, 'd>,
+        context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
-        let args = Args(args);
-        args.exactly(2)?;
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+        let collation = args.pop_collation(context, 2)?;
         let args = args.into_strings();
-        let v = self.0(&args[0], &args[1]);
+        let v = self.0(collation, &args[0], &args[1]);
         Ok(Value::Boolean(v))
     }
 }
 
 fn starts_with() -> TwoStringPredicate {
-    fn imp(a: &str, b: &str) -> bool {
-        str::starts_with(a, b)
-    };
+    fn imp(c: &dyn Collation, a: &str, b: &str) -> bool {
+        c.starts_with(a, b)
+    }
     TwoStringPredicate(imp)
 }
 fn contains() -> TwoStringPredicate {
-    fn imp(a: &str, b: &str) -> bool {
-        str::contains(a, b)
-    };
+    fn imp(c: &dyn Collation, a: &str, b: &str) -> bool {
+        c.find(a, b).is_some()
+    }
+    TwoStringPredicate(imp)
+}
+fn ends_with() -> TwoStringPredicate {
+    fn imp(c: &dyn Collation, a: &str, b: &str) -> bool {
+        c.ends_with(a, b)
+    }
     TwoStringPredicate(imp)
 }
 
-struct SubstringCommon(for<'s> fn(&'s str, &'s str) -> &'s str);
+struct SubstringCommon(for<'s> fn(&dyn Collation, &'s str, &'s str) -> &'s str);
 
 impl Function for SubstringCommon {
     fn evaluate<'c, 'd>(
         &self,
-        _context: &context::Evaluation<'c, 'd>,
+        context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
-        let args = Args(args);
-        args.exactly(2)?;
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+        let collation = args.pop_collation(context, 2)?;
         let args = args.into_strings();
-        let s = self.0(&args[0], &args[1]);
+        let s = self.0(collation, &args[0], &args[1]);
         Ok(Value::String(s.to_owned()))
     }
 }
 
 fn substring_before() -> SubstringCommon {
-    fn inner<'a>(haystack: &'a str, needle: &'a str) -> &'a str {
-        match haystack.find(needle) {
-            Some(pos) => &haystack[..pos],
+    fn inner<'a>(c: &dyn Collation, haystack: &'a str, needle: &'a str) -> &'a str {
+        match c.find(haystack, needle) {
+            Some(found) => &haystack[..found.start],
             None => "",
         }
     }
@@ -390,248 +653,2142 @@ fn substring_before() -> SubstringCommon {
 }
 
 fn substring_after() -> SubstringCommon {
-    fn inner<'a>(haystack: &'a str, needle: &'a str) -> &'a str {
-        match haystack.find(needle) {
-            Some(pos) => &haystack[pos + needle.len()..],
+    fn inner<'a>(c: &dyn Collation, haystack: &'a str, needle: &'a str) -> &'a str {
+        match c.find(haystack, needle) {
+            Some(found) => &haystack[found.end..],
             None => "",
         }
     }
     SubstringCommon(inner)
 }
 
-struct Substring;
+struct StringConvert(fn(&str) -> String);
 
-impl Function for Substring {
+impl Function for StringConvert {
     fn evaluate<'c, 'd>(
         &self,
         _context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_least(2)?;
-        args.at_most(3)?;
-
-        let len = if args.len() == 3 {
-            let len = args.pop_number()?;
-            round_ties_to_positive_infinity(len)
-        } else {
-            ::std::f64::INFINITY
-        };
-
-        let start = args.pop_number()?;
-        let start = round_ties_to_positive_infinity(start);
+        args.exactly(1)?;
         let s = args.pop_string()?;
-
-        let chars = s.chars().enumerate();
-        let selected_chars = chars
-            .filter_map(|(p, s)| {
-                let p = (p + 1) as f64; // 1-based indexing
-                if p >= start && p < start + len {
-                    Some(s)
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        Ok(Value::String(selected_chars))
+        Ok(Value::String(self.0(&s)))
     }
 }
 
-struct StringLength;
-
-impl Function for StringLength {
-    fn evaluate<'c, 'd>(
-        &self,
-        context: &context::Evaluation<'c, 'd>,
-        args: Vec<Value<'d>>,
-    ) -> Result<Value<'d>, Error> {
-        let mut args = Args(args);
-        args.at_most(1)?;
-        let arg = args.pop_string_value_or_context_node(context);
-        Ok(Value::Number(arg.chars().count() as f64))
-    }
+fn lower_case() -> StringConvert {
+    StringConvert(str::to_lowercase)
+}
+fn upper_case() -> StringConvert {
+    StringConvert(str::to_uppercase)
 }
 
-struct NormalizeSpace;
+struct Compare;
 
-impl Function for NormalizeSpace {
+impl Function for Compare {
     fn evaluate<'c, 'd>(
         &self,
         context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
-        let arg = args.pop_string_value_or_context_node(context);
-        // TODO: research itertools or another pure-iterator solution
-        let s: Vec<_> = arg
-            .split(XmlChar::is_space_char)
-            .filter(|s| !s.is_empty())
-            .collect();
-        let s = s.join(" ");
-        Ok(Value::String(s))
+        args.at_least(2)?;
+        args.at_most(3)?;
+
+        let collation = args.pop_collation(context, 2)?;
+        let right = args.pop_optional_string()?;
+        let left = args.pop_optional_string()?;
+
+        let ordering = match (left, right) {
+            (Some(left), Some(right)) => collation.compare(&left, &right),
+            _ => return Ok(Value::Sequence(Vec::new())),
+        };
+        let n = match ordering {
+            Ordering::Less => -1.0,
+            Ordering::Equal => 0.0,
+            Ordering::Greater => 1.0,
+        };
+        Ok(Value::Number(n))
     }
 }
 
-struct Translate;
+#[cfg(feature = "unicode-normalization")]
+struct NormalizeUnicode;
 
-impl Function for Translate {
+#[cfg(feature = "unicode-normalization")]
+impl Function for NormalizeUnicode {
     fn evaluate<'c, 'd>(
         &self,
         _context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
+        use unicode_normalization::UnicodeNormalization;
+
         let mut args = Args(args);
-        args.exactly(3)?;
+        args.at_least(1)?;
+        args.at_most(2)?;
 
-        let to = args.pop_string()?;
-        let from = args.pop_string()?;
+        let form = if args.len() == 2 {
+            args.pop_string()?.trim().to_uppercase()
+        } else {
+            "NFC".to_owned()
+        };
         let s = args.pop_string()?;
 
-        let mut replacements = HashMap::new();
-        let pairs = from
-            .chars()
-            .zip(to.chars().map(Some).chain(iter::repeat(None)));
-        for (from, to) in pairs {
-            if let Entry::Vacant(entry) = replacements.entry(from) {
-                entry.insert(to);
+        let normalized = match &*form {
+            "NFC" => s.nfc().collect(),
+            "NFD" => s.nfd().collect(),
+            "NFKC" => s.nfkc().collect(),
+            "NFKD" => s.nfkd().collect(),
+            "" => s,
+            _ => return Err(Error::UnsupportedNormalizationForm { form }),
+        };
+        Ok(Value::String(normalized))
+    }
+}
+
+/// Escapes each UTF-8 byte of the characters that are not kept as
+/// `%HH`.
+fn percent_encode(s: &str, keep: fn(char) -> bool) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.chars() {
+        if keep(c) {
+            encoded.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for b in c.encode_utf8(&mut bytes).bytes() {
+                encoded.push_str(&format!("%{:02X}", b));
             }
         }
+    }
+    encoded
+}
 
-        let s = s
-            .chars()
-            .filter_map(|c| replacements.get(&c).cloned().unwrap_or_else(|| Some(c)))
-            .collect();
-
-        Ok(Value::String(s))
+fn encode_for_uri() -> StringConvert {
+    fn imp(s: &str) -> String {
+        percent_encode(s, |c| {
+            c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '~'
+        })
+    }
+    StringConvert(imp)
+}
+fn iri_to_uri() -> StringConvert {
+    fn imp(s: &str) -> String {
+        percent_encode(s, |c| c.is_ascii_graphic() && !"<>\"{}|\\^`".contains(c))
+    }
+    StringConvert(imp)
+}
+fn escape_html_uri() -> StringConvert {
+    fn imp(s: &str) -> String {
+        percent_encode(s, |c| (' '..='~').contains(&c))
     }
+    StringConvert(imp)
 }
 
-struct BooleanFn;
+/// The base URI of a node, from the `xml:base` attributes of the
+/// element and its ancestors. The document has the static base URI.
+fn base_uri_of(node: Node<'_>, static_base: Option<&str>) -> Option<String> {
+    let parent_base = || match node.parent() {
+        Some(parent) => base_uri_of(parent, static_base),
+        None => static_base.map(ToOwned::to_owned),
+    };
 
-impl Function for BooleanFn {
-    fn evaluate<'c, 'd>(
-        &self,
-        _context: &context::Evaluation<'c, 'd>,
-        args: Vec<Value<'d>>,
-    ) -> Result<Value<'d>, Error> {
-        let args = Args(args);
-        args.exactly(1)?;
-        Ok(Value::Boolean(args[0].boolean()))
+    match node.kind() {
+        NodeKind::Element => {}
+        NodeKind::Namespace => return None,
+        _ => return parent_base(),
+    }
+
+    let xml_base = node
+        .attributes()
+        .into_iter()
+        .find(|a| {
+            a.expanded_name().is_some_and(|name| {
+                name.namespace_uri() == Some(XML_NAMESPACE_URI) && name.local_part() == "base"
+            })
+        })
+        .map(|a| a.string_value());
+
+    match (xml_base, parent_base()) {
+        (Some(base), Some(parent)) => uri::resolve(&parent, &base).or(Some(base)),
+        (Some(base), None) => Some(base),
+        (None, parent) => parent,
     }
 }
 
-struct Not;
+struct BaseUri;
 
-impl Function for Not {
+impl Function for BaseUri {
     fn evaluate<'c, 'd>(
         &self,
-        _context: &context::Evaluation<'c, 'd>,
+        context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.exactly(1)?;
-        let arg = args.pop_boolean()?;
-        Ok(Value::Boolean(!arg))
+        args.at_most(1)?;
+        let arg = args.pop_nodeset_or_context_node(context)?;
+        let base = arg
+            .document_order_first()
+            .and_then(|n| base_uri_of(n, context.base_uri()));
+        Ok(base
+            .map(Value::String)
+            .unwrap_or_else(|| Value::Sequence(Vec::new())))
     }
 }
 
-struct BooleanLiteral(bool);
+/// The URI that the catalog of the context maps the URI to, or the
+/// URI itself.
+fn through_catalog(context: &context::Evaluation<'_, '_>, uri: String) -> String {
+    context
+        .catalog()
+        .and_then(|catalog| catalog.resolve(&uri))
+        .unwrap_or(uri)
+}
 
-impl Function for BooleanLiteral {
+struct ResolveUri;
+
+impl Function for ResolveUri {
     fn evaluate<'c, 'd>(
         &self,
-        _context: &context::Evaluation<'c, 'd>,
+        context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
-        let args = Args(args);
-        args.exactly(0)?;
-        Ok(Value::Boolean(self.0))
-    }
-}
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
 
-fn true_fn() -> BooleanLiteral {
-    BooleanLiteral(true)
-}
-fn false_fn() -> BooleanLiteral {
-    BooleanLiteral(false)
+        let base = if args.len() == 2 {
+            Some(args.pop_string()?)
+        } else {
+            None
+        };
+        let relative = match args.pop_sequence()?.first() {
+            Some(item) => item.string(),
+            None => return Ok(Value::Sequence(Vec::new())),
+        };
+
+        if uri::is_absolute(&relative) {
+            return Ok(Value::String(through_catalog(context, relative)));
+        }
+        let base = base
+            .as_deref()
+            .or_else(|| context.base_uri())
+            .ok_or(Error::NoBaseUri)?;
+        let resolved = uri::resolve(base, &relative).ok_or_else(|| Error::InvalidBaseUri {
+            uri: base.to_owned(),
+        })?;
+        Ok(Value::String(through_catalog(context, resolved)))
+    }
 }
 
-struct NumberFn;
+/// Loads the documents that its argument refers to, returning their
+/// root nodes. A relative URI is resolved against the base URI of the
+/// node that holds it, or of the first node of the second argument.
+struct DocumentFn;
 
-impl Function for NumberFn {
+impl Function for DocumentFn {
     fn evaluate<'c, 'd>(
         &self,
         context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
-        let arg = args.pop_value_or_context_node(context);
-        Ok(Value::Number(arg.number()))
+        args.at_least(1)?;
+        args.at_most(2)?;
+
+        let base_node = if args.len() == 2 {
+            args.pop_nodeset()?.document_order_first()
+        } else {
+            None
+        };
+
+        let mut documents = Nodeset::new();
+        for item in args.pop_sequence()? {
+            let (reference, holder) = match item {
+                Item::Node(node) => (node.string_value(), Some(node)),
+                atomic => (atomic.string(), None),
+            };
+            let reference = reference.split('#').next().unwrap_or_default();
+
+            let base = match base_node.or(holder) {
+                Some(node) => base_uri_of(node, context.base_uri()),
+                None => context.base_uri().map(ToOwned::to_owned),
+            };
+            let uri = base
+                .and_then(|base| uri::resolve(&base, reference))
+                .unwrap_or_else(|| reference.to_owned());
+            let uri = through_catalog(context, uri);
+
+            documents.add(context.load_document(&uri)?.root());
+        }
+        Ok(Value::Nodeset(documents))
     }
 }
 
-struct Sum;
+/// Finds the elements of the context node's document with any of the
+/// IDs in its argument, which are separated by whitespace. The ID of an
+/// element is the value of its `xml:id` attribute or of an attribute
+/// declared with [`Context::add_id_attribute`][]. When IDs are
+/// repeated, the first element is found.
+///
+/// [`Context::add_id_attribute`]: ../context/struct.Context.html#method.add_id_attribute
+struct Id;
 
-impl Function for Sum {
+impl Function for Id {
     fn evaluate<'c, 'd>(
         &self,
-        _context: &context::Evaluation<'c, 'd>,
+        context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
         args.exactly(1)?;
-        let arg = args.pop_nodeset()?;
-        let r = arg
+        let values = match args.pop_value()? {
+            Value::Nodeset(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+            Value::Sequence(items) => items.iter().map(Item::string).collect(),
+            value => vec![value.string()],
+        };
+        let mut ids: HashMap<String, bool> = values
             .iter()
-            .map(|n| str_to_num(&n.string_value()))
-            .fold(0.0, |acc, i| acc + i);
-        Ok(Value::Number(r))
+            .flat_map(|v| v.split_whitespace())
+            .map(|id| (id.to_owned(), false))
+            .collect();
+
+        let is_id = |name: sxd_document::QName<'_>| {
+            (name.namespace_uri() == Some(XML_NAMESPACE_URI) && name.local_part() == "id")
+                || context.is_id_attribute(name)
+        };
+
+        let mut elements = Nodeset::new();
+        let root = tree::Document::root(&context.node.owner());
+        tree::preorder_left_to_right(root, |node: Node<'d>| {
+            if node.kind() != NodeKind::Element {
+                return;
+            }
+            for attribute in node.attributes() {
+                if !attribute.expanded_name().is_some_and(is_id) {
+                    continue;
+                }
+                if let Some(found) = ids.get_mut(attribute.string_value().trim()) {
+                    if !*found {
+                        *found = true;
+                        elements.add(node);
+                    }
+                }
+            }
+        });
+        Ok(Value::Nodeset(elements))
     }
 }
 
-struct NumberConvert(fn(f64) -> f64);
+/// Finds the nodes of the context node's document that have any of
+/// the values of its second argument for the key named by the first.
+struct KeyFn;
 
-impl Function for NumberConvert {
+impl Function for KeyFn {
     fn evaluate<'c, 'd>(
         &self,
-        _context: &context::Evaluation<'c, 'd>,
+        context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.exactly(1)?;
-        let arg = args.pop_number()?;
+        args.exactly(2)?;
+        let values = args.pop_sequence()?;
+        let name = args.pop_string()?;
+
+        let index = context.key_index(&name)?;
+        let mut nodes = Nodeset::new();
+        for item in values {
+            let value = match item {
+                Item::Node(node) => node.string_value(),
+                atomic => atomic.string(),
+            };
+            for &node in index.get(&value) {
+                nodes.add(node);
+            }
+        }
+        Ok(Value::Nodeset(nodes))
+    }
+}
+
+/// The node the whole expression is evaluated for, even inside
+/// predicates, as with `current()` in XSLT.
+struct Current;
+
+impl Function for Current {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(0)?;
+        Ok(Value::Nodeset(nodeset![context.current_node()]))
+    }
+}
+
+struct StringJoin;
+
+impl Function for StringJoin {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+
+        let separator = if args.len() == 2 {
+            args.pop_string()?
+        } else {
+            String::new()
+        };
+        let items = args.pop_sequence()?;
+
+        let strings: Vec<_> = items.iter().map(Item::string).collect();
+        Ok(Value::String(strings.join(&separator)))
+    }
+}
+
+struct Substring;
+
+impl Function for Substring {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+
+        let len = if args.len() == 3 {
+            let len = args.pop_number()?;
+            round_ties_to_positive_infinity(len)
+        } else {
+            f64::INFINITY
+        };
+
+        let start = args.pop_number()?;
+        let start = round_ties_to_positive_infinity(start);
+        let s = args.pop_string()?;
+
+        let chars = s.chars().enumerate();
+        let selected_chars = chars
+            .filter_map(|(p, s)| {
+                let p = (p + 1) as f64; // 1-based indexing
+                if p >= start && p < start + len {
+                    Some(s)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Ok(Value::String(selected_chars))
+    }
+}
+
+struct StringLength;
+
+impl Function for StringLength {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+        let arg = args.pop_string_value_or_context_node(context);
+        Ok(Value::Number(arg.chars().count() as f64))
+    }
+}
+
+struct NormalizeSpace;
+
+impl Function for NormalizeSpace {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+        let arg = args.pop_string_value_or_context_node(context);
+        // TODO: research itertools or another pure-iterator solution
+        let s: Vec<_> = arg
+            .split(XmlChar::is_space_char)
+            .filter(|s| !s.is_empty())
+            .collect();
+        let s = s.join(" ");
+        Ok(Value::String(s))
+    }
+}
+
+struct Translate;
+
+impl Function for Translate {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(3)?;
+
+        let to = args.pop_string()?;
+        let from = args.pop_string()?;
+        let s = args.pop_string()?;
+
+        let mut replacements = HashMap::new();
+        let pairs = from
+            .chars()
+            .zip(to.chars().map(Some).chain(iter::repeat(None)));
+        for (from, to) in pairs {
+            if let Entry::Vacant(entry) = replacements.entry(from) {
+                entry.insert(to);
+            }
+        }
+
+        let s = s
+            .chars()
+            .filter_map(|c| replacements.get(&c).cloned().unwrap_or(Some(c)))
+            .collect();
+
+        Ok(Value::String(s))
+    }
+}
+
+struct BooleanFn;
+
+impl Function for BooleanFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(1)?;
+        Ok(Value::Boolean(args[0].boolean()))
+    }
+}
+
+struct Not;
+
+impl Function for Not {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let arg = args.pop_boolean()?;
+        Ok(Value::Boolean(!arg))
+    }
+}
+
+struct BooleanLiteral(bool);
+
+impl Function for BooleanLiteral {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(0)?;
+        Ok(Value::Boolean(self.0))
+    }
+}
+
+fn true_fn() -> BooleanLiteral {
+    BooleanLiteral(true)
+}
+fn false_fn() -> BooleanLiteral {
+    BooleanLiteral(false)
+}
+
+struct NumberFn;
+
+impl Function for NumberFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+        let arg = args.pop_value_or_context_node(context);
+        Ok(Value::Number(arg.number()))
+    }
+}
+
+struct Sum;
+
+impl Function for Sum {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let r = match args.pop_value()? {
+            Value::Nodeset(ns) => ns
+                .iter()
+                .map(|n| conv::string_to_number(&n.string_value()))
+                .sum(),
+            other => other.into_items().iter().map(Item::number).sum(),
+        };
+        Ok(Value::Number(r))
+    }
+}
+
+struct DistinctValues;
+
+impl Function for DistinctValues {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+        let collation = args.pop_collation(context, 1)?;
+        let items = atomic::atomize_all(args.pop_value()?);
+
+        let same = |l: &Item<'_>, r: &Item<'_>| match (l, r) {
+            (Item::String(l), Item::String(r)) => collation.equal(l, r),
+            (l, r) => map::same_key(l, r),
+        };
+
+        let mut distinct: Vec<Item<'d>> = Vec::new();
+        for item in items {
+            if !distinct.iter().any(|d| same(d, &item)) {
+                distinct.push(item);
+            }
+        }
+        Ok(Value::Sequence(distinct))
+    }
+}
+
+struct IndexOf;
+
+impl Function for IndexOf {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(3)?;
+        let collation = args.pop_collation(context, 2)?;
+        let search = args.pop_atomic()?;
+        let items = atomic::atomize_all(args.pop_value()?);
+
+        // Items of different types are never equal
+        let equal = |item: &Item<'_>| match (item, &search) {
+            (Item::Boolean(l), Item::Boolean(r)) => l == r,
+            (Item::Number(l), Item::Number(r)) => l == r,
+            (Item::String(l), Item::String(r)) => collation.equal(l, r),
+            _ => false,
+        };
+
+        let positions = items
+            .iter()
+            .enumerate()
+            .filter(|&(_, item)| equal(item))
+            .map(|(i, _)| Item::Number((i + 1) as f64))
+            .collect();
+        Ok(Value::Sequence(positions))
+    }
+}
+
+struct SequenceIsEmpty(bool);
+
+impl Function for SequenceIsEmpty {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let items = args.pop_sequence()?;
+        Ok(Value::Boolean(items.is_empty() == self.0))
+    }
+}
+
+fn exists() -> SequenceIsEmpty {
+    SequenceIsEmpty(false)
+}
+fn empty() -> SequenceIsEmpty {
+    SequenceIsEmpty(true)
+}
+
+struct NumberConvert(fn(f64) -> f64);
+
+impl Function for NumberConvert {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let arg = args.pop_number()?;
         Ok(Value::Number(self.0(arg)))
     }
-}
+}
+
+fn floor() -> NumberConvert {
+    NumberConvert(f64::floor)
+}
+fn ceiling() -> NumberConvert {
+    NumberConvert(f64::ceil)
+}
+
+// https://stackoverflow.com/a/28124775/155423
+fn round_ties_to_positive_infinity(x: f64) -> f64 {
+    let y = x.floor();
+    if x == y {
+        x
+    } else {
+        let z = (2.0 * x - y).floor();
+        // Should use copysign
+        if x.is_sign_positive() ^ z.is_sign_positive() {
+            -z
+        } else {
+            z
+        }
+    }
+}
+
+fn round() -> NumberConvert {
+    NumberConvert(round_ties_to_positive_infinity)
+}
+
+/// The absolute value of a number, or the empty sequence for an
+/// empty argument.
+struct Abs;
+
+impl Function for Abs {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+
+        match args.pop_optional_number()? {
+            Some(x) => Ok(Value::Number(x.abs())),
+            None => Ok(Value::Sequence(vec![])),
+        }
+    }
+}
+
+struct RoundHalfToEven;
+
+impl Function for RoundHalfToEven {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+
+        let precision = if args.len() == 2 {
+            args.pop_number()?
+        } else {
+            0.0
+        };
+        // Far more digits than a double has, in either direction
+        let precision = precision.trunc().clamp(-1000.0, 1000.0) as i32;
+
+        match args.pop_optional_number()? {
+            Some(x) => Ok(Value::Number(round_half_to_even(x, precision))),
+            None => Ok(Value::Sequence(vec![])),
+        }
+    }
+}
+
+/// Rounds the decimal number that the double is written as, keeping
+/// `precision` digits after the decimal point. A tie goes to the
+/// neighbour whose last digit is even. Rounding the decimal digits
+/// rather than a scaled double keeps `1.245` a tie.
+fn round_half_to_even(x: f64, precision: i32) -> f64 {
+    if !x.is_finite() || x == 0.0 {
+        return x;
+    }
+
+    // The shortest digits that read back as the same double
+    let written = format!("{:e}", x.abs());
+    let (mantissa, exponent) = written.split_at(written.find('e').expect("Exponent is written"));
+    let exponent: i32 = exponent[1..].parse().expect("Exponent is an integer");
+    let digits: Vec<u8> = mantissa.bytes().filter(u8::is_ascii_digit).collect();
+
+    // The digits before the decimal point are followed by `precision`
+    // more, which may start or end outside of the written digits
+    let keep = exponent + 1 + precision;
+    if keep >= digits.len() as i32 {
+        return x;
+    }
+
+    let (kept, next) = if keep < 0 {
+        (0, b'0')
+    } else {
+        let keep = keep as usize;
+        let kept = digits[..keep]
+            .iter()
+            .fold(0u64, |n, &d| n * 10 + u64::from(d - b'0'));
+        (kept, digits[keep])
+    };
+    let after_tie = keep >= 0 && digits[keep as usize + 1..].iter().any(|&d| d != b'0');
+
+    let round_up = next > b'5' || (next == b'5' && (after_tie || kept % 2 == 1));
+    let rounded = if round_up { kept + 1 } else { kept };
+
+    let sign = if x < 0.0 { "-" } else { "" };
+    format!("{}{}e{}", sign, rounded, -precision)
+        .parse()
+        .expect("Rounded number is valid")
+}
+
+/// Atomizes a value for numeric use. Nodes are untyped, so their
+/// string values are converted to numbers.
+fn atomize_untyped(value: Value<'_>) -> Vec<Item<'_>> {
+    let mut items = Vec::new();
+    for item in value.into_items() {
+        match item {
+            Item::Node(n) => items.push(Item::Number(conv::string_to_number(&n.string_value()))),
+            item => items.extend(atomic::atomize_all(item.into())),
+        }
+    }
+    items
+}
+
+struct Extremum(Ordering);
+
+impl Function for Extremum {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(1)?;
+        args.at_most(2)?;
+        let collation = args.pop_collation(context, 1)?;
+        let items = atomize_untyped(args.pop_value()?);
+
+        let mut items = items.into_iter();
+        let mut extremum = match items.next() {
+            Some(item) => item,
+            None => return Ok(Value::Sequence(vec![])),
+        };
+
+        for item in items {
+            let ordering = match (&item, &extremum) {
+                (Item::Boolean(l), Item::Boolean(r)) => Some(l.cmp(r)),
+                (Item::Number(l), Item::Number(r)) => l.partial_cmp(r),
+                (Item::String(l), Item::String(r)) => Some(collation.compare(l, r)),
+                (l, r) => {
+                    return Err(Error::IncomparableItems {
+                        left: r.string(),
+                        right: l.string(),
+                    })
+                }
+            };
+
+            match ordering {
+                Some(o) if o == self.0 => extremum = item,
+                Some(_) => {}
+                // Any NaN makes the result NaN
+                None => extremum = Item::Number(f64::NAN),
+            }
+        }
+
+        Ok(extremum.into())
+    }
+}
+
+fn min() -> Extremum {
+    Extremum(Ordering::Less)
+}
+fn max() -> Extremum {
+    Extremum(Ordering::Greater)
+}
+
+struct Avg;
+
+impl Function for Avg {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let items = atomize_untyped(args.pop_value()?);
+
+        if items.is_empty() {
+            return Ok(Value::Sequence(vec![]));
+        }
+
+        let sum: f64 = items.iter().map(Item::number).sum();
+        Ok(Value::Number(sum / items.len() as f64))
+    }
+}
+
+/// Compares two items as `deep-equal` does. Atomic values are never
+/// converted to another type, nodes are compared by their contents
+/// and maps and arrays by their entries.
+fn deep_equal_items(left: &Item<'_>, right: &Item<'_>) -> Result<bool, Error> {
+    match (left, right) {
+        (Item::Node(l), Item::Node(r)) => Ok(deep_equal_nodes(*l, *r)),
+        (Item::Map(l), Item::Map(r)) => {
+            if l.len() != r.len() {
+                return Ok(false);
+            }
+            for (key, l) in l.iter() {
+                match r.get(key) {
+                    Some(r) if deep_equal_values(l, r)? => {}
+                    _ => return Ok(false),
+                }
+            }
+            Ok(true)
+        }
+        (Item::Array(l), Item::Array(r)) => {
+            if l.len() != r.len() {
+                return Ok(false);
+            }
+            for (l, r) in l.iter().zip(r) {
+                if !deep_equal_values(l, r)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        (Item::Function(_), _) | (_, Item::Function(_)) => Err(Error::FunctionsNotComparable),
+        (l, r) => Ok(map::same_key(l, r)),
+    }
+}
+
+fn deep_equal_values(left: &Value<'_>, right: &Value<'_>) -> Result<bool, Error> {
+    let left = left.clone().into_items();
+    let right = right.clone().into_items();
+
+    if left.len() != right.len() {
+        return Ok(false);
+    }
+    for (l, r) in left.iter().zip(&right) {
+        if !deep_equal_items(l, r)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn deep_equal_nodes(left: Node<'_>, right: Node<'_>) -> bool {
+    if left.kind() != right.kind() {
+        return false;
+    }
+
+    match left.kind() {
+        NodeKind::Root => deep_equal_children(left, right),
+        NodeKind::Element => {
+            left.expanded_name() == right.expanded_name()
+                && deep_equal_attributes(left, right)
+                && deep_equal_children(left, right)
+        }
+        NodeKind::Attribute | NodeKind::Namespace | NodeKind::ProcessingInstruction => {
+            left.expanded_name() == right.expanded_name()
+                && left.string_value() == right.string_value()
+        }
+        NodeKind::Text | NodeKind::Comment => left.string_value() == right.string_value(),
+    }
+}
+
+// The order of the attributes does not matter
+fn deep_equal_attributes(left: Node<'_>, right: Node<'_>) -> bool {
+    let left = left.attributes();
+    let right = right.attributes();
+
+    left.len() == right.len()
+        && left.iter().all(|l| {
+            right.iter().any(|r| {
+                l.expanded_name() == r.expanded_name() && l.string_value() == r.string_value()
+            })
+        })
+}
+
+// Comments and processing instructions are not compared
+fn deep_equal_children(left: Node<'_>, right: Node<'_>) -> bool {
+    fn compared_children(node: Node<'_>) -> Vec<Node<'_>> {
+        let mut children = node.children();
+        children.retain(|c| {
+            !matches!(
+                c.kind(),
+                NodeKind::Comment | NodeKind::ProcessingInstruction
+            )
+        });
+        children
+    }
+
+    let left = compared_children(left);
+    let right = compared_children(right);
+
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(&right)
+            .all(|(l, r)| deep_equal_nodes(*l, *r))
+}
+
+struct DeepEqual;
+
+impl Function for DeepEqual {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let right = args.pop_value()?;
+        let left = args.pop_value()?;
+        Ok(Value::Boolean(deep_equal_values(&left, &right)?))
+    }
+}
+
+struct FormatDateTime(DateTimeKind, &'static str);
+
+impl Function for FormatDateTime {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_least(2)?;
+        args.at_most(5)?;
+        // Only English and the ISO calendar are supported, so the
+        // language, calendar and place are ignored
+        while args.len() > 2 {
+            args.pop_value()?;
+        }
+        let picture = args.pop_string()?;
+        let value = args.pop_value()?;
+
+        let actual = ArgumentType::from(&value);
+        let not_a_date_time = || Error::ArgumentNotADateTime {
+            expected: self.1,
+            actual,
+        };
+        let value = match atomic::atomize(value).map_err(|_| not_a_date_time())? {
+            None => return Ok(Value::Sequence(Vec::new())),
+            Some(Item::DateTime(d)) if d.kind() == self.0 => d,
+            // Untyped values are parsed as the expected type
+            Some(Item::String(s)) => DateTime::parse(&s, self.0).ok_or_else(not_a_date_time)?,
+            Some(_) => return Err(not_a_date_time()),
+        };
+
+        let formatted = value.format(&picture).context(Format)?;
+        Ok(Value::String(formatted))
+    }
+}
+
+fn format_date() -> FormatDateTime {
+    FormatDateTime(DateTimeKind::Date, "xs:date")
+}
+fn format_time() -> FormatDateTime {
+    FormatDateTime(DateTimeKind::Time, "xs:time")
+}
+fn format_date_time() -> FormatDateTime {
+    FormatDateTime(DateTimeKind::DateTime, "xs:dateTime")
+}
+
+struct MapGet;
+
+impl Function for MapGet {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let key = args.pop_atomic()?;
+        let map = args.pop_map()?;
+        Ok(map
+            .get(&key)
+            .cloned()
+            .unwrap_or_else(|| Value::Sequence(Vec::new())))
+    }
+}
+
+struct MapContains;
+
+impl Function for MapContains {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let key = args.pop_atomic()?;
+        let map = args.pop_map()?;
+        Ok(Value::Boolean(map.contains_key(&key)))
+    }
+}
+
+struct MapKeys;
+
+impl Function for MapKeys {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let map = args.pop_map()?;
+        Ok(Value::Sequence(map.keys().cloned().collect()))
+    }
+}
+
+struct MapSize;
+
+impl Function for MapSize {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let map = args.pop_map()?;
+        Ok(Value::Number(map.len() as f64))
+    }
+}
+
+struct ArrayGet;
+
+impl Function for ArrayGet {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let position = args.pop_number()?;
+        let members = args.pop_array()?;
+        map::array_member(&members, position)
+            .cloned()
+            .ok_or_else(|| Error::InvalidArrayPosition {
+                position: Value::Number(position).into_string(),
+                size: members.len(),
+            })
+    }
+}
+
+struct ArraySize;
+
+impl Function for ArraySize {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let members = args.pop_array()?;
+        Ok(Value::Number(members.len() as f64))
+    }
+}
+
+struct ForEach;
+
+impl Function for ForEach {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let function = args.pop_function()?;
+        let items = args.pop_sequence()?;
+
+        let mut result = Vec::new();
+        for item in items {
+            result.extend(function.call(context, vec![item.into()])?.into_items());
+        }
+        Ok(Value::Sequence(result))
+    }
+}
+
+struct Filter;
+
+impl Function for Filter {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let function = args.pop_function()?;
+        let items = args.pop_sequence()?;
+
+        let mut result = Vec::new();
+        for item in items {
+            if function.call(context, vec![item.clone().into()])?.boolean() {
+                result.push(item);
+            }
+        }
+        Ok(Value::Sequence(result))
+    }
+}
+
+/// Combines the items of a sequence with an accumulated value, from
+/// the first item or from the last.
+struct Fold {
+    from_left: bool,
+}
+
+impl Function for Fold {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(3)?;
+        let function = args.pop_function()?;
+        let mut accumulated = args.pop_value()?;
+        let mut items = args.pop_sequence()?;
+
+        if self.from_left {
+            for item in items {
+                accumulated = function.call(context, vec![accumulated, item.into()])?;
+            }
+        } else {
+            items.reverse();
+            for item in items {
+                accumulated = function.call(context, vec![item.into(), accumulated])?;
+            }
+        }
+        Ok(accumulated)
+    }
+}
+
+fn fold_left() -> Fold {
+    Fold { from_left: true }
+}
+fn fold_right() -> Fold {
+    Fold { from_left: false }
+}
+
+/// The regular expression functions. Patterns use the syntax of XML
+/// Schema as extended by XPath, which is translated to the syntax of
+/// the `regex` crate.
+#[cfg(feature = "regex")]
+mod pattern {
+    use regex::{Captures, Regex, RegexBuilder};
+    use sxd_document::XmlChar;
+
+    use super::{Args, Error, Function};
+    use crate::context;
+    use crate::{Item, Value};
+
+    // XML Schema's `\s` and `\w` differ from those of the `regex` crate
+    const SPACE: &str = r"\t\n\r ";
+    const NOT_WORD: &str = r"\p{P}\p{Z}\p{C}";
+    const NAME_START: &str = r"_:A-Za-z\xC0-\xD6\xD8-\xF6\xF8-\x{2FF}\x{370}-\x{37D}\x{37F}-\x{1FFF}\x{200C}-\x{200D}\x{2070}-\x{218F}\x{2C00}-\x{2FEF}\x{3001}-\x{D7FF}\x{F900}-\x{FDCF}\x{FDF0}-\x{FFFD}\x{10000}-\x{EFFFF}";
+    const NAME_REST: &str = r"\-.0-9\xB7\x{300}-\x{36F}\x{203F}-\x{2040}";
+
+    #[derive(Debug, Default)]
+    struct Flags {
+        dot_all: bool,
+        multi_line: bool,
+        case_insensitive: bool,
+        ignore_whitespace: bool,
+        literal: bool,
+    }
+
+    impl Flags {
+        fn parse(flags: &str) -> Result<Flags, Error> {
+            let mut parsed = Flags::default();
+            for flag in flags.chars() {
+                match flag {
+                    's' => parsed.dot_all = true,
+                    'm' => parsed.multi_line = true,
+                    'i' => parsed.case_insensitive = true,
+                    'x' => parsed.ignore_whitespace = true,
+                    'q' => parsed.literal = true,
+                    _ => {
+                        return Err(Error::InvalidRegexFlags {
+                            flags: flags.to_owned(),
+                        })
+                    }
+                }
+            }
+            Ok(parsed)
+        }
+    }
+
+    fn translate(pattern: &str, flags: &Flags) -> Result<String, String> {
+        if flags.literal {
+            return Ok(regex::escape(pattern));
+        }
+
+        let mut translated = String::with_capacity(pattern.len());
+        let mut class_depth = 0;
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let escaped = chars.next().ok_or("the pattern ends with a backslash")?;
+                    match escaped {
+                        's' => translated.push_str(&format!("[{}]", SPACE)),
+                        'S' => translated.push_str(&format!("[^{}]", SPACE)),
+                        'w' => translated.push_str(&format!("[^{}]", NOT_WORD)),
+                        'W' => translated.push_str(&format!("[{}]", NOT_WORD)),
+                        'i' => translated.push_str(&format!("[{}]", NAME_START)),
+                        'I' => translated.push_str(&format!("[^{}]", NAME_START)),
+                        'c' => translated.push_str(&format!("[{}{}]", NAME_START, NAME_REST)),
+                        'C' => translated.push_str(&format!("[^{}{}]", NAME_START, NAME_REST)),
+                        'd' | 'D' | 'p' | 'P' | 'n' | 'r' | 't' | '\\' | '|' | '.' | '?' | '*'
+                        | '+' | '(' | ')' | '{' | '}' | '-' | '[' | ']' | '^' | '$' => {
+                            translated.push('\\');
+                            translated.push(escaped);
+                        }
+                        '1'..='9' => return Err("back-references are not supported".into()),
+                        _ => return Err(format!("unknown escape \\{}", escaped)),
+                    }
+                }
+                '[' => {
+                    class_depth += 1;
+                    translated.push('[');
+                    if chars.peek() == Some(&'^') {
+                        translated.push(chars.next().expect("peeked"));
+                    }
+                }
+                ']' if class_depth > 0 => {
+                    class_depth -= 1;
+                    translated.push(']');
+                }
+                // Class subtraction is written `[a-z-[aeiou]]`
+                '-' if class_depth > 0 && chars.peek() == Some(&'[') => {
+                    translated.push_str("--");
+                }
+                // These are set operators when doubled inside a class
+                '&' | '~' if class_depth > 0 => {
+                    translated.push('\\');
+                    translated.push(c);
+                }
+                '.' if class_depth == 0 && !flags.dot_all => translated.push_str(r"[^\n\r]"),
+                '\t' | '\n' | '\r' | ' ' if class_depth == 0 && flags.ignore_whitespace => {}
+                c => translated.push(c),
+            }
+        }
+
+        Ok(translated)
+    }
+
+    fn compile(pattern: &str, flags: &Flags) -> Result<Regex, Error> {
+        let invalid = |what| Error::InvalidRegex {
+            pattern: pattern.to_owned(),
+            what,
+        };
+
+        let translated = translate(pattern, flags).map_err(invalid)?;
+        RegexBuilder::new(&translated)
+            .case_insensitive(flags.case_insensitive)
+            .multi_line(flags.multi_line)
+            .dot_matches_new_line(flags.dot_all)
+            .build()
+            .map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Compiles a pattern that is used to find the parts of a string,
+    /// which may not match the empty string.
+    fn compile_nonempty(pattern: &str, flags: &Flags) -> Result<Regex, Error> {
+        let regex = compile(pattern, flags)?;
+        if regex.is_match("") {
+            return Err(Error::RegexMatchesEmptyString {
+                pattern: pattern.to_owned(),
+            });
+        }
+        Ok(regex)
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Replacement {
+        Literal(String),
+        Group(usize),
+    }
+
+    fn parse_replacement(replacement: &str, groups: usize) -> Result<Vec<Replacement>, Error> {
+        let invalid = || Error::InvalidReplacement {
+            replacement: replacement.to_owned(),
+        };
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = replacement.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped @ '\\') | Some(escaped @ '$') => literal.push(escaped),
+                    _ => return Err(invalid()),
+                },
+                '$' => {
+                    let first = chars
+                        .next()
+                        .and_then(|d| d.to_digit(10))
+                        .ok_or_else(invalid)?;
+                    let mut group = first as usize;
+                    // Later digits are only used while they name a group
+                    while let Some(digit) = chars.peek().and_then(|d| d.to_digit(10)) {
+                        let longer = group * 10 + digit as usize;
+                        if longer > groups {
+                            break;
+                        }
+                        group = longer;
+                        chars.next();
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Replacement::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Replacement::Group(group));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Replacement::Literal(literal));
+        }
+        Ok(parts)
+    }
+
+    fn pop_flags(args: &mut Args<'_>, arity: usize) -> Result<Flags, Error> {
+        if args.len() == arity {
+            Flags::parse(&args.pop_string()?)
+        } else {
+            Ok(Flags::default())
+        }
+    }
+
+    pub(super) struct Matches;
+
+    impl Function for Matches {
+        fn evaluate<'c, 'd>(
+            &self,
+            _context: &context::Evaluation<'c, 'd>,
+            args: Vec<Value<'d>>,
+        ) -> Result<Value<'d>, Error> {
+            let mut args = Args(args);
+            args.at_least(2)?;
+            args.at_most(3)?;
+
+            let flags = pop_flags(&mut args, 3)?;
+            let pattern = args.pop_string()?;
+            let input = args.pop_string()?;
+
+            let regex = compile(&pattern, &flags)?;
+            Ok(Value::Boolean(regex.is_match(&input)))
+        }
+    }
+
+    pub(super) struct Replace;
+
+    impl Function for Replace {
+        fn evaluate<'c, 'd>(
+            &self,
+            _context: &context::Evaluation<'c, 'd>,
+            args: Vec<Value<'d>>,
+        ) -> Result<Value<'d>, Error> {
+            let mut args = Args(args);
+            args.at_least(3)?;
+            args.at_most(4)?;
+
+            let flags = pop_flags(&mut args, 4)?;
+            let replacement = args.pop_string()?;
+            let pattern = args.pop_string()?;
+            let input = args.pop_string()?;
+
+            let regex = compile_nonempty(&pattern, &flags)?;
+            let parts = if flags.literal {
+                vec![Replacement::Literal(replacement)]
+            } else {
+                parse_replacement(&replacement, regex.captures_len() - 1)?
+            };
+
+            let replaced = regex.replace_all(&input, |captures: &Captures<'_>| {
+                let mut replaced = String::new();
+                for part in &parts {
+                    match *part {
+                        Replacement::Literal(ref s) => replaced.push_str(s),
+                        Replacement::Group(n) => {
+                            if let Some(group) = captures.get(n) {
+                                replaced.push_str(group.as_str());
+                            }
+                        }
+                    }
+                }
+                replaced
+            });
+
+            Ok(Value::String(replaced.into_owned()))
+        }
+    }
+
+    pub(super) struct Tokenize;
+
+    impl Function for Tokenize {
+        fn evaluate<'c, 'd>(
+            &self,
+            _context: &context::Evaluation<'c, 'd>,
+            args: Vec<Value<'d>>,
+        ) -> Result<Value<'d>, Error> {
+            let mut args = Args(args);
+            args.at_least(1)?;
+            args.at_most(3)?;
+
+            let flags = pop_flags(&mut args, 3)?;
+            let pattern = if args.len() == 2 {
+                Some(args.pop_string()?)
+            } else {
+                None
+            };
+            let input = args.pop_string()?;
+
+            // Without a pattern, the input is split at whitespace
+            let (input, pattern) = match pattern {
+                Some(pattern) => (input, pattern),
+                None => {
+                    let words: Vec<_> = input
+                        .split(XmlChar::is_space_char)
+                        .filter(|s| !s.is_empty())
+                        .collect();
+                    (words.join(" "), " ".to_owned())
+                }
+            };
+
+            if input.is_empty() {
+                return Ok(Value::Sequence(vec![]));
+            }
+
+            let regex = compile_nonempty(&pattern, &flags)?;
+            let tokens = regex
+                .split(&input)
+                .map(|token| Item::String(token.to_owned()))
+                .collect();
+            Ok(Value::Sequence(tokens))
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn translated(pattern: &str, flags: &str) -> Result<String, String> {
+            translate(pattern, &Flags::parse(flags).unwrap())
+        }
+
+        #[test]
+        fn unknown_flags_are_rejected() {
+            assert_eq!(
+                Err(Error::InvalidRegexFlags {
+                    flags: "sg".to_owned()
+                }),
+                Flags::parse("sg").map(|_| ())
+            );
+        }
+
+        #[test]
+        fn dot_does_not_match_line_endings_unless_dot_all() {
+            assert_eq!(Ok(r"a[^\n\r]".to_owned()), translated("a.", ""));
+            assert_eq!(Ok("a.".to_owned()), translated("a.", "s"));
+            assert_eq!(Ok("[.]".to_owned()), translated("[.]", ""));
+        }
+
+        #[test]
+        fn whitespace_is_removed_outside_classes_with_the_x_flag() {
+            assert_eq!(Ok("ab[ c]".to_owned()), translated("a b[ c]", "x"));
+        }
+
+        #[test]
+        fn class_subtraction_is_translated() {
+            assert_eq!(
+                Ok("[a-z--[aeiou]]".to_owned()),
+                translated("[a-z-[aeiou]]", "")
+            );
+        }
+
+        #[test]
+        fn literal_patterns_are_escaped() {
+            assert_eq!(Ok(r"a\.b\*".to_owned()), translated("a.b*", "q"));
+        }
+
+        #[test]
+        fn back_references_are_rejected() {
+            assert!(translated(r"(a)\1", "").is_err());
+            assert!(translated(r"\x41", "").is_err());
+        }
+
+        #[test]
+        fn replacement_groups_use_as_many_digits_as_name_a_group() {
+            assert_eq!(
+                Ok(vec![
+                    Replacement::Group(1),
+                    Replacement::Literal("2-$".to_owned())
+                ]),
+                parse_replacement(r"$12-\$", 1)
+            );
+            assert_eq!(
+                Ok(vec![Replacement::Group(12)]),
+                parse_replacement("$12", 12)
+            );
+        }
+
+        #[test]
+        fn invalid_replacements_are_rejected() {
+            assert!(parse_replacement("$x", 1).is_err());
+            assert!(parse_replacement(r"\n", 1).is_err());
+        }
+    }
+}
+
+/// `parse-json` and `json-to-xml`, which read JSON text.
+mod json {
+    use std::collections::HashMap;
+    use std::f64;
+
+    use sxd_document::dom;
+
+    use super::{Args, Error, Function};
+    use crate::context;
+    use crate::map::Map;
+    use crate::nodeset::Nodeset;
+    use crate::{Item, Value};
+
+    /// The namespace of the elements written by `json-to-xml`.
+    const FUNCTIONS_NAMESPACE_URI: &str = "http://www.w3.org/2005/xpath-functions";
+
+    /// What to do with a key that appears more than once in an object.
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    enum Duplicates {
+        Reject,
+        UseFirst,
+        UseLast,
+        Retain,
+    }
+
+    impl Duplicates {
+        /// Reads the `duplicates` option, when an options map was
+        /// given, allowing the policies that the function supports.
+        fn pop(
+            args: &mut Args<'_>,
+            default: Duplicates,
+            allowed: &[Duplicates],
+        ) -> Result<Duplicates, Error> {
+            if args.len() < 2 {
+                return Ok(default);
+            }
+            let options = args.pop_map()?;
+            let value = match options.get(&Item::String("duplicates".into())) {
+                Some(value) => value.string(),
+                None => return Ok(default),
+            };
+            let duplicates = match &*value {
+                "reject" => Some(Duplicates::Reject),
+                "use-first" => Some(Duplicates::UseFirst),
+                "use-last" => Some(Duplicates::UseLast),
+                "retain" => Some(Duplicates::Retain),
+                _ => None,
+            };
+            duplicates
+                .filter(|d| allowed.contains(d))
+                .ok_or(Error::InvalidOption {
+                    option: "duplicates",
+                    value,
+                })
+        }
+    }
+
+    /// A JSON value. Numbers keep their text, which `json-to-xml`
+    /// writes as it was.
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Boolean(bool),
+        Number(String),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        /// Null is the empty sequence, arrays are arrays and objects
+        /// are maps keyed by strings.
+        fn into_value<'d>(self) -> Value<'d> {
+            match self {
+                Json::Null => Value::Sequence(Vec::new()),
+                Json::Boolean(b) => Value::Boolean(b),
+                Json::Number(n) => Value::Number(n.parse().unwrap_or(f64::NAN)),
+                Json::String(s) => Value::String(s),
+                Json::Array(members) => {
+                    Item::Array(members.into_iter().map(Json::into_value).collect()).into()
+                }
+                Json::Object(members) => {
+                    let mut map = Map::new();
+                    for (key, value) in members {
+                        map.insert(Item::String(key), value.into_value());
+                    }
+                    Item::Map(map).into()
+                }
+            }
+        }
+
+        /// Creates the element that `json-to-xml` gives for the value,
+        /// with a `key` attribute when it is a member of an object.
+        fn element<'d>(&self, document: dom::Document<'d>, key: Option<&str>) -> dom::Element<'d> {
+            let name = match *self {
+                Json::Null => "null",
+                Json::Boolean(_) => "boolean",
+                Json::Number(_) => "number",
+                Json::String(_) => "string",
+                Json::Array(_) => "array",
+                Json::Object(_) => "map",
+            };
+            let element = document.create_element((FUNCTIONS_NAMESPACE_URI, name));
+            if let Some(key) = key {
+                element.set_attribute_value("key", key);
+            }
+
+            let text = |text: &str| {
+                if !text.is_empty() {
+                    element.append_child(document.create_text(text));
+                }
+            };
+            match *self {
+                Json::Null => {}
+                Json::Boolean(b) => text(if b { "true" } else { "false" }),
+                Json::Number(ref n) => text(n),
+                Json::String(ref s) => text(s),
+                Json::Array(ref members) => {
+                    for member in members {
+                        element.append_child(member.element(document, None));
+                    }
+                }
+                Json::Object(ref members) => {
+                    for (key, value) in members {
+                        element.append_child(value.element(document, Some(key)));
+                    }
+                }
+            }
+            element
+        }
+    }
 
-fn floor() -> NumberConvert {
-    NumberConvert(f64::floor)
-}
-fn ceiling() -> NumberConvert {
-    NumberConvert(f64::ceil)
-}
+    /// Reads JSON text as [RFC 7159][] describes it. Characters that
+    /// XML does not allow, including unpaired surrogates, are replaced
+    /// with U+FFFD.
+    ///
+    /// [RFC 7159]: https://tools.ietf.org/html/rfc7159
+    struct Parser<'a> {
+        text: &'a str,
+        position: usize,
+        duplicates: Duplicates,
+    }
 
-// https://stackoverflow.com/a/28124775/155423
-fn round_ties_to_positive_infinity(x: f64) -> f64 {
-    let y = x.floor();
-    if x == y {
-        x
-    } else {
-        let z = (2.0 * x - y).floor();
-        // Should use copysign
-        if x.is_sign_positive() ^ z.is_sign_positive() {
-            -z
-        } else {
-            z
+    impl<'a> Parser<'a> {
+        fn parse(text: &'a str, duplicates: Duplicates) -> Result<Json, Error> {
+            let mut parser = Parser {
+                text,
+                position: 0,
+                duplicates,
+            };
+            let value = parser.value()?;
+            parser.whitespace();
+            if parser.position < text.len() {
+                return Err(parser.error("unexpected text after the value"));
+            }
+            Ok(value)
+        }
+
+        fn rest(&self) -> &'a str {
+            &self.text[self.position..]
+        }
+
+        fn error(&self, what: &'static str) -> Error {
+            Error::InvalidJson {
+                offset: self.position,
+                what,
+            }
+        }
+
+        fn whitespace(&mut self) {
+            let rest = self.rest();
+            let trimmed = rest.trim_start_matches(&[' ', '\t', '\n', '\r'][..]);
+            self.position += rest.len() - trimmed.len();
+        }
+
+        fn consume(&mut self, token: &str) -> bool {
+            let found = self.rest().starts_with(token);
+            if found {
+                self.position += token.len();
+            }
+            found
+        }
+
+        fn expect(&mut self, token: &str, what: &'static str) -> Result<(), Error> {
+            self.whitespace();
+            if self.consume(token) {
+                Ok(())
+            } else {
+                Err(self.error(what))
+            }
+        }
+
+        fn value(&mut self) -> Result<Json, Error> {
+            self.whitespace();
+            match self.rest().chars().next() {
+                Some('{') => self.object(),
+                Some('[') => self.array(),
+                Some('"') => self.string().map(Json::String),
+                Some('-') | Some('0'..='9') => self.number(),
+                None => Err(self.error("expected a value but the text ended")),
+                _ if self.consume("true") => Ok(Json::Boolean(true)),
+                _ if self.consume("false") => Ok(Json::Boolean(false)),
+                _ if self.consume("null") => Ok(Json::Null),
+                _ => Err(self.error("expected a value")),
+            }
+        }
+
+        fn object(&mut self) -> Result<Json, Error> {
+            self.position += 1;
+            let mut members: Vec<(String, Json)> = Vec::new();
+            let mut seen = HashMap::new();
+
+            self.whitespace();
+            if self.consume("}") {
+                return Ok(Json::Object(members));
+            }
+            loop {
+                self.whitespace();
+                if !self.rest().starts_with('"') {
+                    return Err(self.error("expected a key"));
+                }
+                let key = self.string()?;
+                self.expect(":", "expected ':' after a key")?;
+                let value = self.value()?;
+
+                match seen.get(&key) {
+                    None => {
+                        seen.insert(key.clone(), members.len());
+                        members.push((key, value));
+                    }
+                    Some(&i) => match self.duplicates {
+                        Duplicates::Reject => return Err(Error::DuplicateJsonKey { key }),
+                        Duplicates::UseFirst => {}
+                        Duplicates::UseLast => members[i].1 = value,
+                        Duplicates::Retain => members.push((key, value)),
+                    },
+                }
+
+                self.whitespace();
+                if self.consume("}") {
+                    return Ok(Json::Object(members));
+                }
+                self.expect(",", "expected ',' or '}' in an object")?;
+            }
+        }
+
+        fn array(&mut self) -> Result<Json, Error> {
+            self.position += 1;
+            let mut members = Vec::new();
+
+            self.whitespace();
+            if self.consume("]") {
+                return Ok(Json::Array(members));
+            }
+            loop {
+                members.push(self.value()?);
+                self.whitespace();
+                if self.consume("]") {
+                    return Ok(Json::Array(members));
+                }
+                self.expect(",", "expected ',' or ']' in an array")?;
+            }
+        }
+
+        fn string(&mut self) -> Result<String, Error> {
+            self.position += 1;
+            let mut s = String::new();
+            loop {
+                let rest = self.rest();
+                let end = rest
+                    .find(|c| c == '"' || c == '\\' || c < ' ')
+                    .ok_or_else(|| self.error("the string is not closed"))?;
+                s.push_str(&rest[..end]);
+                self.position += end;
+
+                match rest[end..].chars().next() {
+                    Some('"') => {
+                        self.position += 1;
+                        return Ok(s);
+                    }
+                    Some('\\') => {
+                        self.position += 1;
+                        s.push(self.escape()?);
+                    }
+                    _ => return Err(self.error("control characters must be escaped")),
+                }
+            }
+        }
+
+        /// Reads what follows a backslash in a string.
+        fn escape(&mut self) -> Result<char, Error> {
+            let c = match self.rest().chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("the string is not closed")),
+            };
+            self.position += c.len_utf8();
+
+            let escaped = match c {
+                '"' | '\\' | '/' => c,
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => self.code_point()?,
+                _ => return Err(self.error("invalid escape")),
+            };
+
+            let allowed = matches!(escaped, '\t' | '\n' | '\r')
+                || (escaped >= ' ' && escaped != '\u{fffe}' && escaped != '\u{ffff}');
+            Ok(if allowed { escaped } else { '\u{fffd}' })
+        }
+
+        /// Reads the hex digits of a `\u` escape, and of a second one
+        /// when the two are a surrogate pair.
+        fn code_point(&mut self) -> Result<char, Error> {
+            let unit = self.hex()?;
+            if !(0xD800..0xDC00).contains(&unit) {
+                return Ok(std::char::from_u32(unit).unwrap_or('\u{fffd}'));
+            }
+
+            let high = self.position;
+            if self.consume("\\u") {
+                let low = self.hex()?;
+                if (0xDC00..0xE000).contains(&low) {
+                    let c = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+                    return Ok(std::char::from_u32(c).unwrap_or('\u{fffd}'));
+                }
+                // The next escape is read again on its own
+                self.position = high;
+            }
+            Ok('\u{fffd}')
+        }
+
+        fn hex(&mut self) -> Result<u32, Error> {
+            let digits = self
+                .rest()
+                .get(..4)
+                .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))
+                .ok_or_else(|| self.error("expected four hex digits"))?;
+            self.position += 4;
+            Ok(u32::from_str_radix(digits, 16).expect("the digits were checked"))
+        }
+
+        fn number(&mut self) -> Result<Json, Error> {
+            let start = self.position;
+            self.consume("-");
+            if !self.consume("0") && self.digits() == 0 {
+                return Err(self.error("expected a digit"));
+            }
+            if self.consume(".") && self.digits() == 0 {
+                return Err(self.error("expected a digit after the decimal point"));
+            }
+            if self.consume("e") || self.consume("E") {
+                if !self.consume("+") {
+                    self.consume("-");
+                }
+                if self.digits() == 0 {
+                    return Err(self.error("expected a digit in the exponent"));
+                }
+            }
+            Ok(Json::Number(self.text[start..self.position].to_owned()))
+        }
+
+        fn digits(&mut self) -> usize {
+            let rest = self.rest();
+            let count = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            self.position += count;
+            count
         }
     }
-}
 
-fn round() -> NumberConvert {
-    NumberConvert(round_ties_to_positive_infinity)
+    /// `parse-json($json-text[, $options])`, which gives the JSON as
+    /// maps, arrays and atomic values.
+    pub(super) struct ParseJson;
+
+    impl Function for ParseJson {
+        fn evaluate<'c, 'd>(
+            &self,
+            _context: &context::Evaluation<'c, 'd>,
+            args: Vec<Value<'d>>,
+        ) -> Result<Value<'d>, Error> {
+            let mut args = Args(args);
+            args.at_least(1)?;
+            args.at_most(2)?;
+            let duplicates = Duplicates::pop(
+                &mut args,
+                Duplicates::UseFirst,
+                &[
+                    Duplicates::Reject,
+                    Duplicates::UseFirst,
+                    Duplicates::UseLast,
+                ],
+            )?;
+            match args.pop_optional_string()? {
+                Some(text) => Ok(Parser::parse(&text, duplicates)?.into_value()),
+                None => Ok(Value::Sequence(Vec::new())),
+            }
+        }
+    }
+
+    /// `json-to-xml($json-text[, $options])`, which gives the JSON as
+    /// elements in the functions namespace, in a new result tree
+    /// fragment. The outermost element is returned.
+    pub(super) struct JsonToXml;
+
+    impl Function for JsonToXml {
+        fn evaluate<'c, 'd>(
+            &self,
+            context: &context::Evaluation<'c, 'd>,
+            args: Vec<Value<'d>>,
+        ) -> Result<Value<'d>, Error> {
+            let mut args = Args(args);
+            args.at_least(1)?;
+            args.at_most(2)?;
+            let duplicates = Duplicates::pop(
+                &mut args,
+                Duplicates::Retain,
+                &[Duplicates::Reject, Duplicates::Retain],
+            )?;
+            let json = match args.pop_optional_string()? {
+                Some(text) => Parser::parse(&text, duplicates)?,
+                None => return Ok(Value::Sequence(Vec::new())),
+            };
+
+            let fragment = context.new_fragment().ok_or(Error::NoFragments)?;
+            let element = json.element(fragment.document(), None);
+            element.set_default_namespace_uri(Some(FUNCTIONS_NAMESPACE_URI));
+            fragment.append_child(element);
+
+            let mut nodes = Nodeset::new();
+            nodes.add(element);
+            Ok(Value::Nodeset(nodes))
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn parse(text: &str) -> Result<Json, Error> {
+            Parser::parse(text, Duplicates::UseFirst)
+        }
+
+        #[test]
+        fn values_of_every_type_are_read() {
+            assert_eq!(
+                Ok(Json::Object(vec![
+                    (
+                        "a".into(),
+                        Json::Array(vec![Json::Null, Json::Boolean(true)])
+                    ),
+                    ("b".into(), Json::Number("-1.5e+3".into())),
+                    ("c".into(), Json::String("x".into())),
+                ])),
+                parse(" { \"a\" : [null, true], \"b\": -1.5e+3, \"c\": \"x\" } ")
+            );
+        }
+
+        #[test]
+        fn escapes_are_replaced() {
+            assert_eq!(
+                Ok(Json::String("\"/\n\u{e9}\u{1f600}".into())),
+                parse(r#""\"\/\n\u00e9\ud83d\ude00""#)
+            );
+        }
+
+        #[test]
+        fn characters_that_xml_does_not_allow_are_replaced() {
+            assert_eq!(
+                Ok(Json::String("\u{fffd}a\u{fffd}".into())),
+                parse(r#""\ud800a\u0001""#)
+            );
+        }
+
+        #[test]
+        fn malformed_json_is_reported_where_it_goes_wrong() {
+            assert_eq!(
+                Err(Error::InvalidJson {
+                    offset: 4,
+                    what: "expected a value",
+                }),
+                parse("[1, ]")
+            );
+            assert!(parse("01").is_err());
+            assert!(parse("1.").is_err());
+            assert!(parse("\"a").is_err());
+            assert!(parse("{'a': 1}").is_err());
+            assert!(parse("[1] 2").is_err());
+        }
+
+        #[test]
+        fn duplicate_keys_follow_the_policy() {
+            let text = r#"{"a": 1, "a": 2}"#;
+            let member = |n: &str| ("a".to_owned(), Json::Number(n.into()));
+
+            assert_eq!(
+                Ok(Json::Object(vec![member("1")])),
+                Parser::parse(text, Duplicates::UseFirst)
+            );
+            assert_eq!(
+                Ok(Json::Object(vec![member("2")])),
+                Parser::parse(text, Duplicates::UseLast)
+            );
+            assert_eq!(
+                Ok(Json::Object(vec![member("1"), member("2")])),
+                Parser::parse(text, Duplicates::Retain)
+            );
+            assert_eq!(
+                Err(Error::DuplicateJsonKey { key: "a".into() }),
+                Parser::parse(text, Duplicates::Reject)
+            );
+        }
+    }
 }
 
 /// Adds the [XPath 1.0 core function library][corelib].
@@ -648,12 +2805,28 @@ pub fn register_core_functions(context: &mut context::Context<'_>) {
     context.set_function("concat", Concat);
     context.set_function("starts-with", starts_with());
     context.set_function("contains", contains());
+    context.set_function("ends-with", ends_with());
     context.set_function("substring-before", substring_before());
     context.set_function("substring-after", substring_after());
     context.set_function("substring", Substring);
     context.set_function("string-length", StringLength);
     context.set_function("normalize-space", NormalizeSpace);
     context.set_function("translate", Translate);
+    context.set_function("lower-case", lower_case());
+    context.set_function("upper-case", upper_case());
+    context.set_function("string-join", StringJoin);
+    context.set_function("compare", Compare);
+    #[cfg(feature = "unicode-normalization")]
+    context.set_function("normalize-unicode", NormalizeUnicode);
+    context.set_function("encode-for-uri", encode_for_uri());
+    context.set_function("iri-to-uri", iri_to_uri());
+    context.set_function("escape-html-uri", escape_html_uri());
+    context.set_function("base-uri", BaseUri);
+    context.set_function("resolve-uri", ResolveUri);
+    context.set_function("current", Current);
+    context.set_function("document", DocumentFn);
+    context.set_function("id", Id);
+    context.set_function("key", KeyFn);
     context.set_function("boolean", BooleanFn);
     context.set_function("not", Not);
     context.set_function("true", true_fn());
@@ -663,23 +2836,67 @@ pub fn register_core_functions(context: &mut context::Context<'_>) {
     context.set_function("floor", floor());
     context.set_function("ceiling", ceiling());
     context.set_function("round", round());
+    context.set_function("abs", Abs);
+    context.set_function("round-half-to-even", RoundHalfToEven);
+    context.set_function("min", min());
+    context.set_function("max", max());
+    context.set_function("avg", Avg);
+    context.set_function("distinct-values", DistinctValues);
+    context.set_function("index-of", IndexOf);
+    context.set_function("exists", exists());
+    context.set_function("empty", empty());
+    context.set_function("deep-equal", DeepEqual);
+    context.set_function("format-date", format_date());
+    context.set_function("format-time", format_time());
+    context.set_function("format-dateTime", format_date_time());
+    context.set_function("for-each", ForEach);
+    context.set_function("filter", Filter);
+    context.set_function("fold-left", fold_left());
+    context.set_function("fold-right", fold_right());
+    context.set_function("parse-json", json::ParseJson);
+    context.set_function("json-to-xml", json::JsonToXml);
+    #[cfg(feature = "regex")]
+    {
+        context.set_function("matches", pattern::Matches);
+        context.set_function("replace", pattern::Replace);
+        context.set_function("tokenize", pattern::Tokenize);
+    }
+    context.set_function((MAP_NAMESPACE_URI, "get"), MapGet);
+    context.set_function((MAP_NAMESPACE_URI, "contains"), MapContains);
+    context.set_function((MAP_NAMESPACE_URI, "keys"), MapKeys);
+    context.set_function((MAP_NAMESPACE_URI, "size"), MapSize);
+    context.set_function((ARRAY_NAMESPACE_URI, "get"), ArrayGet);
+    context.set_function((ARRAY_NAMESPACE_URI, "size"), ArraySize);
 }
 
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::rc::Rc;
     use std::{f64, fmt};
 
-    use sxd_document::Package;
+    use sxd_document::{parser, Package};
 
+    use crate::catalog::Catalog;
+    use crate::collation::Collation;
     use crate::context;
+    use crate::datetime::{DateTime, DateTimeKind};
+    use crate::fragment::Fragments;
+    use crate::map::Map;
     use crate::nodeset::Node;
-    use crate::{LiteralValue, Value};
+    use crate::resolver::ResolveError;
+    use crate::{Item, LiteralValue, Value};
 
     use super::{
-        ceiling, contains, floor, round, starts_with, substring_after, substring_before, BooleanFn,
-        Concat, Count, Error, Function, Last, LocalName, Name, NamespaceUri, NormalizeSpace,
-        NumberFn, Position, StringFn, StringLength, Substring, Sum, Translate,
+        ceiling, contains, empty, encode_for_uri, ends_with, escape_html_uri, exists, floor,
+        format_date, format_time, iri_to_uri, lower_case, max, min, round, starts_with,
+        substring_after, substring_before, upper_case, Abs, ArgumentType, ArrayGet, Avg, BaseUri,
+        BooleanFn, Compare, Concat, Count, Current, DeepEqual, DistinctValues, DocumentFn, Error,
+        ForEach, Function, Id, IndexOf, KeyFn, Last, LocalName, MapContains, MapKeys, Name,
+        NamespaceUri, NormalizeSpace, NumberFn, Position, ResolveUri, RoundHalfToEven, StringFn,
+        StringJoin, StringLength, Substring, Sum, Translate,
     };
 
     /// Converts each argument into a `Value` and packs them into a
@@ -725,109 +2942,500 @@ mod test {
         let doc = package.as_document();
         let setup = Setup::new();
 
-        rf(setup.evaluate(doc.root(), f, args))
+        rf(setup.evaluate(doc.root(), f, args))
+    }
+
+    #[test]
+    fn last_returns_context_size() {
+        evaluate_literal(Last, args![], |r| {
+            assert_eq!(Ok(Value::Number(1.0)), r);
+        });
+    }
+
+    #[test]
+    fn position_returns_context_position() {
+        evaluate_literal(Position, args![], |r| {
+            assert_eq!(Ok(Value::Number(1.0)), r);
+        });
+    }
+
+    #[test]
+    fn count_counts_nodes_in_nodeset() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let r = setup.evaluate(doc.root(), Count, args![nodeset![doc.root()]]);
+
+        assert_eq!(Ok(Value::Number(1.0)), r);
+    }
+
+    #[test]
+    fn local_name_gets_name_of_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element(("uri", "wow"));
+        doc.root().append_child(e);
+
+        let r = setup.evaluate(doc.root(), LocalName, args![nodeset![e]]);
+
+        assert_eq!(Ok(Value::String("wow".to_owned())), r);
+    }
+
+    #[test]
+    fn local_name_is_empty_for_empty_nodeset() {
+        evaluate_literal(LocalName, args![nodeset![]], |r| {
+            assert_eq!(Ok(Value::String("".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn namespace_uri_gets_uri_of_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element(("uri", "wow"));
+        doc.root().append_child(e);
+
+        let r = setup.evaluate(doc.root(), NamespaceUri, args![nodeset![e]]);
+
+        assert_eq!(Ok(Value::String("uri".to_owned())), r);
+    }
+
+    #[test]
+    fn name_uses_declared_prefix() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element(("uri", "wow"));
+        e.register_prefix("prefix", "uri");
+        doc.root().append_child(e);
+
+        let r = setup.evaluate(doc.root(), Name, args![nodeset![e]]);
+
+        assert_eq!(Ok(Value::String("prefix:wow".to_owned())), r);
+    }
+
+    #[test]
+    fn string_converts_to_string() {
+        evaluate_literal(StringFn, args![true], |r| {
+            assert_eq!(Ok(Value::String("true".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn concat_combines_strings() {
+        evaluate_literal(Concat, args!["hello", " ", "world"], |r| {
+            assert_eq!(Ok(Value::String("hello world".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn starts_with_checks_prefixes() {
+        evaluate_literal(starts_with(), args!["hello", "he"], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
+    #[test]
+    fn contains_looks_for_a_needle() {
+        evaluate_literal(contains(), args!["astronomer", "ono"], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
+    #[test]
+    fn ends_with_checks_suffixes() {
+        evaluate_literal(ends_with(), args!["hello", "lo"], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
+    #[test]
+    fn case_functions_convert_all_characters() {
+        evaluate_literal(upper_case(), args!["straße"], |r| {
+            assert_eq!(Ok(Value::String("STRASSE".to_owned())), r);
+        });
+        evaluate_literal(lower_case(), args!["ÀB"], |r| {
+            assert_eq!(Ok(Value::String("àb".to_owned())), r);
+        });
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalize_unicode_composes_by_default() {
+        evaluate_literal(super::NormalizeUnicode, args!["e\u{301}"], |r| {
+            assert_eq!(Ok(Value::String("\u{e9}".to_owned())), r);
+        });
+        evaluate_literal(super::NormalizeUnicode, args!["\u{e9}", " nfd "], |r| {
+            assert_eq!(Ok(Value::String("e\u{301}".to_owned())), r);
+        });
+        evaluate_literal(super::NormalizeUnicode, args!["\u{fb01}", "NFKC"], |r| {
+            assert_eq!(Ok(Value::String("fi".to_owned())), r);
+        });
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalize_unicode_rejects_unknown_forms() {
+        evaluate_literal(
+            super::NormalizeUnicode,
+            args!["a", "FULLY-NORMALIZED"],
+            |r| {
+                assert_eq!(
+                    Err(Error::UnsupportedNormalizationForm {
+                        form: "FULLY-NORMALIZED".to_owned()
+                    }),
+                    r
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn compare_orders_strings_by_codepoint() {
+        evaluate_literal(Compare, args!["apple", "banana"], |r| {
+            assert_eq!(Ok(Value::Number(-1.0)), r);
+        });
+        evaluate_literal(Compare, args!["a", "B"], |r| {
+            assert_eq!(Ok(Value::Number(1.0)), r);
+        });
+        evaluate_literal(Compare, args![Vec::<Item<'_>>::new(), "a"], |r| {
+            assert_eq!(Ok(Value::Sequence(Vec::new())), r);
+        });
+    }
+
+    #[test]
+    fn compare_uses_the_named_collation() {
+        struct Reversed;
+
+        impl Collation for Reversed {
+            fn compare(&self, left: &str, right: &str) -> Ordering {
+                right.cmp(left)
+            }
+        }
+
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup.context.set_collation("urn:reversed", Reversed);
+
+        let r = setup.evaluate(doc.root(), Compare, args!["a", "b", "urn:reversed"]);
+        assert_eq!(Ok(Value::Number(1.0)), r);
+
+        let r = setup.evaluate(doc.root(), Compare, args!["a", "b", "urn:unknown"]);
+        assert_eq!(
+            Err(Error::UnknownCollation {
+                uri: "urn:unknown".to_owned()
+            }),
+            r
+        );
     }
 
+    const CASE_INSENSITIVE: &str =
+        "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive";
+
     #[test]
-    fn last_returns_context_size() {
-        evaluate_literal(Last, args![], |r| {
-            assert_eq!(Ok(Value::Number(1.0)), r);
+    fn string_functions_match_with_the_named_collation() {
+        evaluate_literal(
+            contains(),
+            args!["Hello World", "WORLD", CASE_INSENSITIVE],
+            |r| {
+                assert_eq!(Ok(Value::Boolean(true)), r);
+            },
+        );
+        evaluate_literal(starts_with(), args!["Hello", "hE", CASE_INSENSITIVE], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
         });
+        evaluate_literal(
+            substring_after(),
+            args!["Key=Value", "KEY=", CASE_INSENSITIVE],
+            |r| {
+                assert_eq!(Ok(Value::String("Value".to_owned())), r);
+            },
+        );
     }
 
     #[test]
-    fn position_returns_context_position() {
-        evaluate_literal(Position, args![], |r| {
-            assert_eq!(Ok(Value::Number(1.0)), r);
+    fn sequence_functions_compare_strings_with_the_named_collation() {
+        let items = vec![Item::from("a"), Item::from("B"), Item::from("A")];
+        evaluate_literal(IndexOf, args![items.clone(), "a", CASE_INSENSITIVE], |r| {
+            assert_eq!(
+                Ok(Value::Sequence(vec![Item::Number(1.0), Item::Number(3.0)])),
+                r
+            );
+        });
+        evaluate_literal(
+            DistinctValues,
+            args![items.clone(), CASE_INSENSITIVE],
+            |r| {
+                assert_eq!(
+                    Ok(Value::Sequence(vec![Item::from("a"), Item::from("B")])),
+                    r
+                );
+            },
+        );
+        evaluate_literal(max(), args![items, CASE_INSENSITIVE], |r| {
+            assert_eq!(Ok(Value::String("B".to_owned())), r);
         });
     }
 
     #[test]
-    fn count_counts_nodes_in_nodeset() {
+    fn functions_use_the_default_collation_without_an_argument() {
         let package = Package::new();
         let doc = package.as_document();
-        let setup = Setup::new();
+        let mut setup = Setup::new();
+        setup.context.set_default_collation(CASE_INSENSITIVE);
 
-        let r = setup.evaluate(doc.root(), Count, args![nodeset![doc.root()]]);
+        let r = setup.evaluate(doc.root(), ends_with(), args!["README.md", ".MD"]);
+        assert_eq!(Ok(Value::Boolean(true)), r);
 
-        assert_eq!(Ok(Value::Number(1.0)), r);
+        let r = setup.evaluate(doc.root(), Compare, args!["abc", "ABC"]);
+        assert_eq!(Ok(Value::Number(0.0)), r);
     }
 
     #[test]
-    fn local_name_gets_name_of_element() {
+    fn encode_for_uri_escapes_reserved_characters() {
+        evaluate_literal(encode_for_uri(), args!["100% a/b~c é"], |r| {
+            assert_eq!(Ok(Value::String("100%25%20a%2Fb~c%20%C3%A9".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn iri_to_uri_escapes_only_characters_invalid_in_uris() {
+        let iri = "http://example.com/~b\u{e9}b\u{e9}?a=<1> b#top";
+        evaluate_literal(iri_to_uri(), args![iri], |r| {
+            assert_eq!(
+                Ok(Value::String(
+                    "http://example.com/~b%C3%A9b%C3%A9?a=%3C1%3E%20b#top".to_owned()
+                )),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn escape_html_uri_escapes_only_non_printable_ascii() {
+        let uri = "javascript:if (a < 1) go('\u{e9}')";
+        evaluate_literal(escape_html_uri(), args![uri], |r| {
+            assert_eq!(
+                Ok(Value::String(
+                    "javascript:if (a < 1) go('%C3%A9')".to_owned()
+                )),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn base_uri_resolves_each_xml_base_against_the_parent() {
         let package = Package::new();
         let doc = package.as_document();
-        let setup = Setup::new();
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_base_uri("http://example.com/docs/index.xml");
+
+        let xml_base = (super::XML_NAMESPACE_URI, "base");
+        let outer = doc.create_element("outer");
+        outer.set_attribute_value(xml_base, "guide/");
+        let inner = doc.create_element("inner");
+        inner.set_attribute_value(xml_base, "../api/intro.html");
+        let text = doc.create_text("text");
+        doc.root().append_child(outer);
+        outer.append_child(inner);
+        inner.append_child(text);
+
+        let r = setup.evaluate(doc.root(), BaseUri, args![nodeset![text]]);
+        assert_eq!(
+            Ok(Value::String(
+                "http://example.com/docs/api/intro.html".to_owned()
+            )),
+            r
+        );
 
-        let e = doc.create_element(("uri", "wow"));
-        doc.root().append_child(e);
+        let r = setup.evaluate(doc.root(), BaseUri, args![]);
+        assert_eq!(
+            Ok(Value::String(
+                "http://example.com/docs/index.xml".to_owned()
+            )),
+            r
+        );
+    }
 
-        let r = setup.evaluate(doc.root(), LocalName, args![nodeset![e]]);
+    #[test]
+    fn base_uri_is_empty_without_any_base() {
+        evaluate_literal(BaseUri, args![], |r| {
+            assert_eq!(Ok(Value::Sequence(Vec::new())), r);
+        });
+    }
 
-        assert_eq!(Ok(Value::String("wow".to_owned())), r);
+    #[test]
+    fn resolve_uri_resolves_against_the_given_base() {
+        evaluate_literal(ResolveUri, args!["../c", "http://example.com/a/b/"], |r| {
+            assert_eq!(Ok(Value::String("http://example.com/a/c".to_owned())), r);
+        });
+        evaluate_literal(ResolveUri, args!["urn:x", "http://example.com/"], |r| {
+            assert_eq!(Ok(Value::String("urn:x".to_owned())), r);
+        });
     }
 
     #[test]
-    fn local_name_is_empty_for_empty_nodeset() {
-        evaluate_literal(LocalName, args![nodeset![]], |r| {
-            assert_eq!(Ok(Value::String("".to_owned())), r);
+    fn resolve_uri_needs_an_absolute_base() {
+        evaluate_literal(ResolveUri, args!["c"], |r| {
+            assert_eq!(Err(Error::NoBaseUri), r);
+        });
+        evaluate_literal(ResolveUri, args!["c", "a/b"], |r| {
+            assert_eq!(
+                Err(Error::InvalidBaseUri {
+                    uri: "a/b".to_owned()
+                }),
+                r
+            );
         });
     }
 
+    fn parse_any(uri: &str) -> Result<Package, ResolveError> {
+        Ok(parser::parse(&format!("<loaded>{}</loaded>", uri))?)
+    }
+
     #[test]
-    fn namespace_uri_gets_uri_of_element() {
+    fn document_loads_each_uri_against_the_base_of_its_node() {
+        let fragments = Fragments::new();
         let package = Package::new();
         let doc = package.as_document();
-        let setup = Setup::new();
-
-        let e = doc.create_element(("uri", "wow"));
-        doc.root().append_child(e);
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        setup.context.set_base_uri("http://example.com/maps/");
+        setup.context.set_document_resolver(parse_any);
+
+        let link = doc.create_element("link");
+        link.set_attribute_value((super::XML_NAMESPACE_URI, "base"), "topics/");
+        let href = link.set_attribute_value("href", "a.xml#section");
+        doc.root().append_child(link);
+
+        let loaded = |r: Result<Value<'_>, Error>| match r {
+            Ok(Value::Nodeset(nodes)) => nodes.iter().map(|n| n.string_value()).collect::<Vec<_>>(),
+            other => panic!("expected documents, was {:?}", other),
+        };
 
-        let r = setup.evaluate(doc.root(), NamespaceUri, args![nodeset![e]]);
+        let r = setup.evaluate(doc.root(), DocumentFn, args![nodeset![href]]);
+        assert_eq!(vec!["http://example.com/maps/topics/a.xml"], loaded(r));
 
-        assert_eq!(Ok(Value::String("uri".to_owned())), r);
+        let r = setup.evaluate(doc.root(), DocumentFn, args!["b.xml"]);
+        assert_eq!(vec!["http://example.com/maps/b.xml"], loaded(r));
     }
 
     #[test]
-    fn name_uses_declared_prefix() {
+    fn document_loads_each_uri_once() {
+        let fragments = Fragments::new();
         let package = Package::new();
         let doc = package.as_document();
-        let setup = Setup::new();
-
-        let e = doc.create_element(("uri", "wow"));
-        e.register_prefix("prefix", "uri");
-        doc.root().append_child(e);
+        let loads = Rc::new(Cell::new(0));
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        setup.context.set_document_resolver({
+            let loads = Rc::clone(&loads);
+            move |uri: &str| {
+                loads.set(loads.get() + 1);
+                parse_any(uri)
+            }
+        });
 
-        let r = setup.evaluate(doc.root(), Name, args![nodeset![e]]);
+        let first = setup.evaluate(doc.root(), DocumentFn, args!["urn:a"]);
+        let second = setup.evaluate(doc.root(), DocumentFn, args!["urn:a"]);
+        assert!(first.is_ok());
+        assert_eq!(first, second);
+        assert_eq!(1, loads.get());
+    }
 
-        assert_eq!(Ok(Value::String("prefix:wow".to_owned())), r);
+    fn catalog() -> Catalog {
+        Catalog::load(
+            "file:///xml/catalog.xml",
+            &|_: &str| -> Result<_, ResolveError> {
+                Ok(parser::parse(
+                    "<catalog xmlns='urn:oasis:names:tc:entity:xmlns:xml:catalog'>
+                   <rewriteURI uriStartString='http://example.com/' rewritePrefix='local/'/>
+                 </catalog>",
+                )?)
+            },
+        )
+        .expect("Unable to load catalog")
     }
 
     #[test]
-    fn string_converts_to_string() {
-        evaluate_literal(StringFn, args![true], |r| {
-            assert_eq!(Ok(Value::String("true".to_owned())), r);
-        });
+    fn resolve_uri_maps_the_uri_through_the_catalog() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup.context.set_catalog(catalog());
+
+        let r = setup.evaluate(
+            doc.root(),
+            ResolveUri,
+            args!["b/c", "http://example.com/a/"],
+        );
+        assert_eq!(Ok(Value::String("file:///xml/local/a/b/c".to_owned())), r);
+        let r = setup.evaluate(doc.root(), ResolveUri, args!["http://example.org/"]);
+        assert_eq!(Ok(Value::String("http://example.org/".to_owned())), r);
     }
 
     #[test]
-    fn concat_combines_strings() {
-        evaluate_literal(Concat, args!["hello", " ", "world"], |r| {
-            assert_eq!(Ok(Value::String("hello world".to_owned())), r);
-        });
+    fn document_loads_the_uri_from_the_catalog() {
+        let fragments = Fragments::new();
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        setup.context.set_catalog(catalog());
+        setup.context.set_document_resolver(parse_any);
+
+        let r = setup.evaluate(doc.root(), DocumentFn, args!["http://example.com/a.xml"]);
+        let loaded = match r {
+            Ok(Value::Nodeset(nodes)) => nodes.iter().map(|n| n.string_value()).collect::<Vec<_>>(),
+            other => panic!("expected documents, was {:?}", other),
+        };
+        assert_eq!(vec!["file:///xml/local/a.xml"], loaded);
     }
 
     #[test]
-    fn starts_with_checks_prefixes() {
-        evaluate_literal(starts_with(), args!["hello", "he"], |r| {
-            assert_eq!(Ok(Value::Boolean(true)), r);
-        });
+    fn document_reports_documents_that_cannot_be_loaded() {
+        let fragments = Fragments::new();
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+
+        let r = setup.evaluate(doc.root(), DocumentFn, args!["urn:a"]);
+        assert_eq!(Err(Error::NoDocumentResolver), r);
+
+        setup
+            .context
+            .set_document_resolver(|_: &str| -> Result<Package, ResolveError> {
+                Err("not found".into())
+            });
+        let r = setup.evaluate(doc.root(), DocumentFn, args!["urn:a"]);
+        assert_eq!(
+            Err(Error::DocumentNotLoaded {
+                uri: "urn:a".to_owned(),
+                what: "not found".to_owned()
+            }),
+            r
+        );
     }
 
     #[test]
-    fn contains_looks_for_a_needle() {
-        evaluate_literal(contains(), args!["astronomer", "ono"], |r| {
-            assert_eq!(Ok(Value::Boolean(true)), r);
+    fn string_join_separates_each_item() {
+        let items = vec![Item::Number(1.0), Item::String("a".to_owned())];
+        evaluate_literal(StringJoin, args![items.clone(), ", "], |r| {
+            assert_eq!(Ok(Value::String("1, a".to_owned())), r);
+        });
+        evaluate_literal(StringJoin, args![items], |r| {
+            assert_eq!(Ok(Value::String("1a".to_owned())), r);
         });
     }
 
@@ -995,6 +3603,50 @@ mod test {
         assert_eq!(Ok(Value::Number(66.7)), r);
     }
 
+    #[test]
+    fn distinct_values_keeps_the_first_of_each_value() {
+        let items = vec![
+            Item::Number(1.0),
+            Item::String("1".to_owned()),
+            Item::Number(f64::NAN),
+            Item::Number(1.0),
+            Item::Number(f64::NAN),
+        ];
+        evaluate_literal(DistinctValues, args![items], |r| {
+            let r = r.expect("distinct-values failed").into_items();
+            assert_eq!(3, r.len());
+            assert_eq!(Item::String("1".to_owned()), r[1]);
+        });
+    }
+
+    #[test]
+    fn index_of_finds_every_equal_item() {
+        let items = vec![
+            Item::String("a".to_owned()),
+            Item::Number(1.0),
+            Item::String("a".to_owned()),
+        ];
+        evaluate_literal(IndexOf, args![items, "a"], |r| {
+            assert_eq!(
+                Ok(Value::Sequence(vec![Item::Number(1.0), Item::Number(3.0)])),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn exists_and_empty_check_for_items() {
+        evaluate_literal(exists(), args![Vec::<Item<'_>>::new()], |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
+        });
+        evaluate_literal(empty(), args![Vec::<Item<'_>>::new()], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+        evaluate_literal(exists(), args![""], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
     /// By default, NaN != NaN and -0.0 == 0.0. We don't want either
     /// of those to be true.
     struct PedanticNumber(f64);
@@ -1031,7 +3683,7 @@ mod test {
     fn assert_number(expected: f64, actual: Result<Value<'_>, Error>) {
         match actual {
             Ok(Value::Number(n)) => assert_eq!(PedanticNumber(n), PedanticNumber(expected)),
-            _ => assert!(false, "{:?} did not evaluate correctly", actual),
+            _ => panic!("{:?} did not evaluate correctly", actual),
         }
     }
 
@@ -1088,4 +3740,365 @@ mod test {
     fn round_pos_zero_point_five_to_pos_one() {
         evaluate_literal(round(), args![0.5], |r| assert_number(1.0, r));
     }
+
+    #[test]
+    fn abs_removes_the_sign() {
+        evaluate_literal(Abs, args![-2.5], |r| assert_number(2.5, r));
+    }
+
+    #[test]
+    fn round_half_to_even_rounds_ties_to_even() {
+        evaluate_literal(RoundHalfToEven, args![2.5], |r| assert_number(2.0, r));
+        evaluate_literal(RoundHalfToEven, args![3.5], |r| assert_number(4.0, r));
+        evaluate_literal(RoundHalfToEven, args![-0.5], |r| assert_number(-0.0, r));
+    }
+
+    #[test]
+    fn round_half_to_even_uses_the_precision() {
+        evaluate_literal(RoundHalfToEven, args![1.125, 2.0], |r| {
+            assert_number(1.12, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![350.0, -2.0], |r| {
+            assert_number(400.0, r)
+        });
+    }
+
+    #[test]
+    fn round_half_to_even_rounds_the_decimal_digits() {
+        evaluate_literal(RoundHalfToEven, args![1.245, 2.0], |r| {
+            assert_number(1.24, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![2.665, 2.0], |r| {
+            assert_number(2.66, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![2.675, 2.0], |r| {
+            assert_number(2.68, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![2.6651, 2.0], |r| {
+            assert_number(2.67, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![-0.05, 1.0], |r| {
+            assert_number(-0.0, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![0.15, 1.0], |r| assert_number(0.2, r));
+        evaluate_literal(RoundHalfToEven, args![12345.0, -10.0], |r| {
+            assert_number(0.0, r)
+        });
+    }
+
+    #[test]
+    fn round_half_to_even_keeps_numbers_with_fewer_digits_than_the_precision() {
+        evaluate_literal(RoundHalfToEven, args![1.5, 400.0], |r| {
+            assert_number(1.5, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![1e300, 20.0], |r| {
+            assert_number(1e300, r)
+        });
+        evaluate_literal(RoundHalfToEven, args![1.5, -400.0], |r| {
+            assert_number(0.0, r)
+        });
+    }
+
+    #[test]
+    fn abs_and_round_half_to_even_keep_the_empty_sequence() {
+        let empty: Vec<Item<'_>> = vec![];
+        evaluate_literal(Abs, args![empty.clone()], |r| {
+            assert_eq!(Ok(Value::Sequence(vec![])), r)
+        });
+        evaluate_literal(RoundHalfToEven, args![empty.clone()], |r| {
+            assert_eq!(Ok(Value::Sequence(vec![])), r)
+        });
+        evaluate_literal(RoundHalfToEven, args![empty, 2.0], |r| {
+            assert_eq!(Ok(Value::Sequence(vec![])), r)
+        });
+    }
+
+    #[test]
+    fn min_and_max_compare_numbers_and_strings() {
+        let numbers = vec![Item::Number(3.0), Item::Number(-1.0), Item::Number(2.0)];
+        evaluate_literal(min(), args![numbers.clone()], |r| assert_number(-1.0, r));
+        evaluate_literal(max(), args![numbers], |r| assert_number(3.0, r));
+
+        let strings = vec![Item::from("pear"), Item::from("apple")];
+        evaluate_literal(max(), args![strings], |r| {
+            assert_eq!(Ok(Value::String("pear".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn min_is_nan_if_any_number_is_nan() {
+        let numbers = vec![Item::Number(1.0), Item::Number(f64::NAN), Item::Number(0.0)];
+        evaluate_literal(min(), args![numbers], |r| assert_number(f64::NAN, r));
+    }
+
+    #[test]
+    fn max_fails_with_mixed_types() {
+        let mixed = vec![Item::Number(1.0), Item::from("a")];
+        evaluate_literal(max(), args![mixed], |r| {
+            assert_eq!(
+                Err(Error::IncomparableItems {
+                    left: "1".to_owned(),
+                    right: "a".to_owned()
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn aggregates_of_the_empty_sequence_are_empty() {
+        for f in [min(), max()] {
+            evaluate_literal(f, args![Vec::<Item<'_>>::new()], |r| {
+                assert_eq!(Ok(Value::Sequence(vec![])), r);
+            });
+        }
+        evaluate_literal(Avg, args![Vec::<Item<'_>>::new()], |r| {
+            assert_eq!(Ok(Value::Sequence(vec![])), r);
+        });
+    }
+
+    #[test]
+    fn avg_divides_the_sum_by_the_count() {
+        let numbers = vec![Item::Number(1.0), Item::Number(2.0), Item::Number(6.0)];
+        evaluate_literal(Avg, args![numbers], |r| assert_number(3.0, r));
+    }
+
+    #[test]
+    fn deep_equal_compares_items_without_conversion() {
+        let left = vec![Item::Number(f64::NAN), Item::from("1")];
+        let right = vec![Item::Number(f64::NAN), Item::from("1")];
+        evaluate_literal(DeepEqual, args![left, right], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+
+        evaluate_literal(DeepEqual, args![1.0, "1"], |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
+        });
+    }
+
+    #[test]
+    fn deep_equal_compares_maps_and_arrays_by_entry() {
+        let mut reordered = Map::new();
+        reordered.insert(2.0, "two");
+        reordered.insert("b", 1.0);
+
+        let left = Item::Array(vec![sample_map(), Value::Number(1.0)]);
+        let right = Item::Array(vec![Item::Map(reordered).into(), Value::Number(1.0)]);
+        evaluate_literal(DeepEqual, args![left.clone(), right], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+
+        let shorter = Item::Array(vec![sample_map()]);
+        evaluate_literal(DeepEqual, args![left, shorter], |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
+        });
+    }
+
+    #[test]
+    fn format_date_parses_untyped_arguments_as_dates() {
+        evaluate_literal(format_date(), args!["2024-03-09", "[D01]/[M01]/[Y]"], |r| {
+            assert_eq!(Ok(Value::String("09/03/2024".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn format_date_of_the_empty_sequence_is_empty() {
+        evaluate_literal(format_date(), args![Vec::<Item<'_>>::new(), "[Y]"], |r| {
+            assert_eq!(Ok(Value::Sequence(Vec::new())), r);
+        });
+    }
+
+    #[test]
+    fn format_time_rejects_dates() {
+        let date = DateTime::parse("2024-03-09", DateTimeKind::Date).unwrap();
+        evaluate_literal(format_time(), args![Item::DateTime(date), "[H]"], |r| {
+            assert_eq!(
+                Err(Error::ArgumentNotADateTime {
+                    expected: "xs:time",
+                    actual: ArgumentType::Sequence,
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn format_time_ignores_language_calendar_and_place() {
+        evaluate_literal(
+            format_time(),
+            args!["13:05:00", "[h]:[m] [P]", "en", "AD", "US"],
+            |r| {
+                assert_eq!(Ok(Value::String("1:05 pm".to_owned())), r);
+            },
+        );
+    }
+
+    fn sample_map() -> LiteralValue {
+        let mut map = Map::new();
+        map.insert("b", 1.0);
+        map.insert(2.0, "two");
+        Item::Map(map).into()
+    }
+
+    #[test]
+    fn map_keys_are_in_insertion_order() {
+        evaluate_literal(MapKeys, args![sample_map()], |r| {
+            assert_eq!(
+                Ok(Value::Sequence(vec![Item::from("b"), Item::Number(2.0)])),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn map_contains_does_not_convert_keys() {
+        evaluate_literal(MapContains, args![sample_map(), "2"], |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
+        });
+    }
+
+    #[test]
+    fn map_functions_require_a_map() {
+        evaluate_literal(MapKeys, args!["b"], |r| {
+            assert_eq!(
+                Err(Error::ArgumentNotAMap {
+                    actual: ArgumentType::String
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn array_get_rejects_positions_outside_of_the_array() {
+        let array: LiteralValue = Item::Array(vec![Value::Number(1.0)]).into();
+        evaluate_literal(ArrayGet, args![array, 2.0], |r| {
+            assert_eq!(
+                Err(Error::InvalidArrayPosition {
+                    position: "2".to_owned(),
+                    size: 1
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn higher_order_functions_require_a_function() {
+        evaluate_literal(ForEach, args![1.0, "upper-case"], |r| {
+            assert_eq!(
+                Err(Error::ArgumentNotAFunction {
+                    actual: ArgumentType::String
+                }),
+                r
+            );
+        });
+    }
+
+    fn key_setup<'d>() -> Setup<'d> {
+        let factory = crate::Factory::new();
+        let mut setup = Setup::new();
+        setup.context.set_namespace("lib", "urn:library");
+        setup.context.set_key(
+            ("urn:library", "by-author"),
+            &factory.build("book").expect("Invalid pattern"),
+            &factory.build("@author").expect("Invalid expression"),
+        );
+        setup
+    }
+
+    #[test]
+    fn key_finds_the_nodes_with_any_of_the_values() {
+        let package = parser::parse(
+            "<books><book author='a'>1</book><book author='b'>2</book><book author='a'>3</book></books>",
+        )
+        .expect("Unable to parse");
+        let doc = package.as_document();
+        let setup = key_setup();
+
+        let r = setup.evaluate(doc.root(), KeyFn, args!["lib:by-author", "a"]);
+        let nodes = match r {
+            Ok(Value::Nodeset(nodes)) => nodes.document_order(),
+            other => panic!("expected nodes, was {:?}", other),
+        };
+        let texts: Vec<_> = nodes.iter().map(|n| n.string_value()).collect();
+        assert_eq!(vec!["1", "3"], texts);
+
+        let values = Value::Sequence(vec!["b".into(), "c".into()]);
+        let r = setup.evaluate(doc.root(), KeyFn, vec!["lib:by-author".into(), values]);
+        match r {
+            Ok(Value::Nodeset(nodes)) => assert_eq!(1, nodes.size()),
+            other => panic!("expected nodes, was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn key_needs_a_declared_key() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = key_setup();
+
+        for name in &["by-author", "other:by-author"] {
+            let r = setup.evaluate(doc.root(), KeyFn, args![*name, "a"]);
+            assert_eq!(
+                Err(Error::UnknownKey {
+                    name: (*name).to_owned()
+                }),
+                r
+            );
+        }
+    }
+
+    fn ids<'d>(r: Result<Value<'d>, Error>) -> Vec<String> {
+        match r {
+            Ok(Value::Nodeset(nodes)) => nodes
+                .document_order()
+                .iter()
+                .map(|n| n.string_value())
+                .collect(),
+            other => panic!("expected elements, was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn id_finds_elements_by_xml_id() {
+        let package = parser::parse(
+            "<doc><p xml:id='a'>1</p><p xml:id=' b '>2</p><p id='c'>3</p><p xml:id='a'>4</p></doc>",
+        )
+        .expect("Unable to parse");
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let r = setup.evaluate(doc.root(), Id, args!["b a\tc"]);
+        assert_eq!(vec!["1", "2"], ids(r));
+    }
+
+    #[test]
+    fn id_uses_the_declared_id_attributes() {
+        let package =
+            parser::parse("<doc><p id='a'>1</p><p ref='a'>2</p></doc>").expect("Unable to parse");
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup.context.add_id_attribute("id");
+
+        let p = doc.root().children()[0].element().unwrap().children()[1];
+        let refs = p.element().unwrap().attribute("ref").unwrap();
+        let r = setup.evaluate(doc.root(), Id, args![nodeset![refs]]);
+        assert_eq!(vec!["1"], ids(r));
+    }
+
+    #[test]
+    fn current_is_kept_for_inner_contexts() {
+        let package = parser::parse("<a><b/></a>").expect("Unable to parse");
+        let doc = package.as_document();
+        let setup = Setup::new();
+        let a = doc.root().children()[0];
+        let b = a.element().unwrap().children()[0];
+
+        let evaluation = context::Evaluation::new(&setup.context, a.into());
+        let inner = evaluation.new_context_for(b);
+        let r = Current.evaluate(&inner, vec![]);
+
+        assert_eq!(Ok(Value::Nodeset(nodeset![a])), r);
+    }
 }
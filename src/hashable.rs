@@ -0,0 +1,287 @@
+//! Values that can be used as the keys of a `HashMap` or `HashSet`.
+//!
+//! A [`Value`][] only implements `PartialEq`, as XPath's `NaN` is not
+//! equal to itself. [`HashableValue`][] wraps a value and defines an
+//! equality that is reflexive, so evaluated values can be used to
+//! memoize or deduplicate results:
+//!
+//! ```
+//! use std::collections::HashSet;
+//!
+//! use sxd_xpath::hashable::HashableValue;
+//! use sxd_xpath::Value;
+//!
+//! let mut seen = HashSet::new();
+//! assert!(seen.insert(HashableValue::from(Value::Number(f64::NAN))));
+//! assert!(!seen.insert(HashableValue::from(Value::Number(f64::NAN))));
+//! assert!(seen.insert(HashableValue::from(Value::String("NaN".into()))));
+//! ```
+//!
+//! Two values are the same when they are the same kind of value and
+//! hold the same things:
+//!
+//! - Every `NaN` is the same as every other `NaN`. Other numbers are
+//!   the same only when their bits are, so `0` and `-0` differ, as
+//!   `1 div 0` and `1 div -0` do.
+//! - Nodesets are the same when they hold the same nodes, and
+//!   sequences when their items are the same and in the same order.
+//!   A nodeset is never the same as a sequence.
+//! - Dates, times and durations are the same when they are written
+//!   the same, so the same instant in two timezones differs.
+//! - Maps are the same when they have the same keys with the same
+//!   values, in any order. Functions are compared as `==` compares
+//!   them.
+//!
+//! [`Value`]: ../enum.Value.html
+//! [`HashableValue`]: struct.HashableValue.html
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+use crate::{Item, Value};
+
+/// A value with an equality and a hash that agree with each other.
+/// See the [module documentation](index.html).
+#[derive(Debug, Clone)]
+pub struct HashableValue<'d>(Value<'d>);
+
+impl<'d> HashableValue<'d> {
+    pub fn new(value: Value<'d>) -> Self {
+        HashableValue(value)
+    }
+
+    pub fn value(&self) -> &Value<'d> {
+        &self.0
+    }
+
+    pub fn into_value(self) -> Value<'d> {
+        self.0
+    }
+}
+
+impl<'d> From<Value<'d>> for HashableValue<'d> {
+    fn from(value: Value<'d>) -> Self {
+        HashableValue(value)
+    }
+}
+
+impl<'d> From<HashableValue<'d>> for Value<'d> {
+    fn from(value: HashableValue<'d>) -> Self {
+        value.0
+    }
+}
+
+impl<'d> PartialEq for HashableValue<'d> {
+    fn eq(&self, other: &Self) -> bool {
+        same_value(&self.0, &other.0)
+    }
+}
+
+impl<'d> Eq for HashableValue<'d> {}
+
+impl<'d> Hash for HashableValue<'d> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, state)
+    }
+}
+
+fn same_number(left: f64, right: f64) -> bool {
+    (left.is_nan() && right.is_nan()) || left.to_bits() == right.to_bits()
+}
+
+fn hash_number<H: Hasher>(n: f64, state: &mut H) {
+    let n = if n.is_nan() { f64::NAN } else { n };
+    n.to_bits().hash(state)
+}
+
+fn same_value(left: &Value<'_>, right: &Value<'_>) -> bool {
+    match (left, right) {
+        (Value::Boolean(l), Value::Boolean(r)) => l == r,
+        (Value::Number(l), Value::Number(r)) => same_number(*l, *r),
+        (Value::String(l), Value::String(r)) => l == r,
+        (Value::Nodeset(l), Value::Nodeset(r)) => l == r,
+        (Value::Sequence(l), Value::Sequence(r)) => same_values(l, r, same_item),
+        _ => false,
+    }
+}
+
+fn same_values<T, F>(left: &[T], right: &[T], same: F) -> bool
+where
+    F: Fn(&T, &T) -> bool,
+{
+    left.len() == right.len() && left.iter().zip(right).all(|(l, r)| same(l, r))
+}
+
+fn same_item(left: &Item<'_>, right: &Item<'_>) -> bool {
+    match (left, right) {
+        (Item::Node(l), Item::Node(r)) => l == r,
+        (Item::Boolean(l), Item::Boolean(r)) => l == r,
+        (Item::Number(l), Item::Number(r)) => same_number(*l, *r),
+        (Item::String(l), Item::String(r)) => l == r,
+        (Item::DateTime(l), Item::DateTime(r)) => {
+            l.kind() == r.kind() && l.to_string() == r.to_string()
+        }
+        (Item::Duration(l), Item::Duration(r)) => l.to_string() == r.to_string(),
+        (Item::Map(l), Item::Map(r)) => {
+            l.len() == r.len()
+                && l.iter()
+                    .all(|(k, v)| r.get(k).is_some_and(|rv| same_value(v, rv)))
+        }
+        (Item::Array(l), Item::Array(r)) => same_values(l, r, same_value),
+        (Item::Function(l), Item::Function(r)) => l == r,
+        _ => false,
+    }
+}
+
+/// Combines hashes without regard to their order, for sets and maps.
+fn hash_unordered<I, H>(hashes: I, state: &mut H)
+where
+    I: Iterator<Item = u64>,
+    H: Hasher,
+{
+    hashes.fold(0u64, u64::wrapping_add).hash(state)
+}
+
+fn hash_of<F>(f: F) -> u64
+where
+    F: FnOnce(&mut DefaultHasher),
+{
+    let mut hasher = DefaultHasher::new();
+    f(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_value<H: Hasher>(value: &Value<'_>, state: &mut H) {
+    mem::discriminant(value).hash(state);
+    match *value {
+        Value::Boolean(b) => b.hash(state),
+        Value::Number(n) => hash_number(n, state),
+        Value::String(ref s) => s.hash(state),
+        Value::Nodeset(ref nodes) => {
+            hash_unordered(nodes.iter().map(|n| hash_of(|h| n.hash(h))), state)
+        }
+        Value::Sequence(ref items) => {
+            items.len().hash(state);
+            for item in items {
+                hash_item(item, state);
+            }
+        }
+    }
+}
+
+fn hash_item<H: Hasher>(item: &Item<'_>, state: &mut H) {
+    mem::discriminant(item).hash(state);
+    match *item {
+        Item::Node(n) => n.hash(state),
+        Item::Boolean(b) => b.hash(state),
+        Item::Number(n) => hash_number(n, state),
+        Item::String(ref s) => s.hash(state),
+        Item::DateTime(ref dt) => dt.to_string().hash(state),
+        Item::Duration(ref d) => d.to_string().hash(state),
+        // Keys are looked up as maps look them up, where `0` and `-0`
+        // are the same key, so only the values are hashed
+        Item::Map(ref map) => {
+            hash_unordered(map.values().map(|v| hash_of(|h| hash_value(v, h))), state)
+        }
+        Item::Array(ref members) => {
+            members.len().hash(state);
+            for member in members {
+                hash_value(member, state);
+            }
+        }
+        Item::Function(ref f) => f.arity().hash(state),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::evaluate_xpath;
+    use crate::map::Map;
+
+    fn hashable(value: Value<'_>) -> HashableValue<'_> {
+        HashableValue::from(value)
+    }
+
+    fn hash(value: &HashableValue<'_>) -> u64 {
+        hash_of(|h| value.hash(h))
+    }
+
+    fn assert_same(left: Value<'_>, right: Value<'_>) {
+        let (left, right) = (hashable(left), hashable(right));
+        assert_eq!(left, right);
+        assert_eq!(hash(&left), hash(&right));
+    }
+
+    #[test]
+    fn nan_is_the_same_as_itself() {
+        assert_same(Value::Number(f64::NAN), Value::Number(-f64::NAN));
+        assert_same(
+            Value::Sequence(vec![Item::Number(f64::NAN)]),
+            Value::Sequence(vec![Item::Number(-f64::NAN)]),
+        );
+    }
+
+    #[test]
+    fn zero_and_negative_zero_differ() {
+        assert_ne!(hashable(Value::Number(0.0)), hashable(Value::Number(-0.0)));
+    }
+
+    #[test]
+    fn values_of_different_kinds_differ() {
+        assert_ne!(
+            hashable(Value::Number(1.0)),
+            hashable(Value::String("1".into()))
+        );
+        assert_ne!(
+            hashable(Value::Boolean(true)),
+            hashable(Value::Sequence(vec![Item::Boolean(true)]))
+        );
+    }
+
+    #[test]
+    fn nodesets_are_the_same_in_any_order() {
+        let package = parser::parse("<a><b/><c/></a>").expect("failed to parse XML");
+        let doc = package.as_document();
+
+        let forwards = evaluate_xpath(&doc, "/a/b | /a/c").expect("XPath evaluation failed");
+        let backwards = evaluate_xpath(&doc, "/a/c | /a/b").expect("XPath evaluation failed");
+        let other = evaluate_xpath(&doc, "/a/b").expect("XPath evaluation failed");
+
+        assert_same(forwards.clone(), backwards);
+        assert_ne!(hashable(forwards), hashable(other));
+    }
+
+    #[test]
+    fn maps_are_the_same_in_any_order() {
+        let mut forwards = Map::new();
+        forwards.insert("a", Value::Number(1.0));
+        forwards.insert("b", Value::Number(f64::NAN));
+        let mut backwards = Map::new();
+        backwards.insert("b", Value::Number(f64::NAN));
+        backwards.insert("a", Value::Number(1.0));
+
+        assert_same(
+            Value::Sequence(vec![Item::Map(forwards)]),
+            Value::Sequence(vec![Item::Map(backwards)]),
+        );
+    }
+
+    #[test]
+    // Nodes are hashed by identity, not by their contents
+    #[allow(clippy::mutable_key_type)]
+    fn values_can_be_used_as_keys() {
+        let mut cache = HashMap::new();
+        cache.insert(hashable(Value::Number(f64::NAN)), "nan");
+        cache.insert(hashable(Value::String("x".into())), "x");
+
+        assert_eq!(Some(&"nan"), cache.get(&hashable(Value::Number(f64::NAN))));
+        assert_eq!(Some(&"x"), cache.get(&hashable(Value::String("x".into()))));
+        assert_eq!(None, cache.get(&hashable(Value::Boolean(false))));
+    }
+}
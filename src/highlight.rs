@@ -0,0 +1,355 @@
+//! Sorts the text of an XPath into categories for highlighting.
+//!
+//! [`classify`][] splits an XPath into tokens and gives each the
+//! [`Category`][] of what it is written as, such as an axis, the name
+//! of a function or an operator. It works on XPaths that are still
+//! being written: text that cannot be made into a token is an error,
+//! and the rest is still classified.
+//!
+//! ```
+//! use sxd_xpath::highlight::{self, Category};
+//!
+//! let xpath = "count(child::item[@price > $limit])";
+//! let categories: Vec<_> = highlight::classify(xpath)
+//!     .into_iter()
+//!     .map(|highlight| (&xpath[highlight.span.range()], highlight.category))
+//!     .collect();
+//!
+//! assert_eq!(("count", Category::Function), categories[0]);
+//! assert_eq!(("child::", Category::Axis), categories[2]);
+//! assert_eq!(("item", Category::NodeTest), categories[3]);
+//! assert_eq!(("$limit", Category::Variable), categories[8]);
+//! ```
+//!
+//! [`classify`]: fn.classify.html
+//! [`Category`]: enum.Category.html
+
+use crate::token::Token;
+use crate::tokenizer::{Span, Tokenizer};
+
+/// What a part of an XPath is written as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// An axis, including `@`
+    Axis,
+    /// The name or kind that a step tests nodes for, including `.`
+    /// and `..`
+    NodeTest,
+    /// The name of a called function
+    Function,
+    /// A type that values are tested for or cast to, and its
+    /// occurrence indicator
+    Type,
+    Operator,
+    /// A word that begins or separates the parts of an expression, such
+    /// as `if` or `return`
+    Keyword,
+    /// A string or number, including the key of a lookup
+    Literal,
+    Variable,
+    /// Brackets, commas and the separators of maps and bindings
+    Punctuation,
+    Comment,
+    /// Text that cannot be made into a token
+    Error,
+}
+
+/// A part of an XPath and what it is written as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    pub span: Span,
+    pub category: Category,
+}
+
+/// The parts of the XPath in the order they are written. Whitespace is
+/// left out.
+pub fn classify(xpath: &str) -> Vec<Highlight> {
+    let mut highlights = Vec::new();
+    let mut position = Position::Expression;
+    let mut end = 0;
+
+    for (token, span) in Tokenizer::new(xpath).with_spans() {
+        comments(xpath, end, span.offset, &mut highlights);
+        end = span.range().end;
+
+        let category = match token {
+            Ok(token) => position.classify(&token),
+            Err(_) => Category::Error,
+        };
+        highlights.push(Highlight { span, category });
+    }
+    comments(xpath, end, xpath.len(), &mut highlights);
+
+    highlights
+}
+
+/// The tokenizer skips comments, so any text between two tokens that is
+/// not whitespace is made of comments.
+fn comments(xpath: &str, start: usize, end: usize, highlights: &mut Vec<Highlight>) {
+    let between = &xpath[start..end];
+    let comments = between.trim();
+    if !comments.is_empty() {
+        highlights.push(Highlight {
+            span: Span {
+                offset: start + between.len() - between.trim_start().len(),
+                length: comments.len(),
+            },
+            category: Category::Comment,
+        });
+    }
+}
+
+/// Where a token is written, for the tokens that the tokenizer gives
+/// the same token wherever they are written.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Position {
+    Expression,
+    /// After `instance of`, `treat as`, `cast as` or `castable as`
+    TypeName,
+    /// Inside the parentheses of a type such as `element()`
+    TypeArguments,
+    /// Where an occurrence indicator may be written
+    AfterType,
+    /// After the `?` of a lookup
+    LookupKey,
+}
+
+impl Position {
+    fn classify(&mut self, token: &Token) -> Category {
+        let (position, category) = match (*self, token) {
+            (Position::TypeName, Token::NameTest(_)) | (Position::TypeName, Token::NodeTest(_)) => {
+                (Position::AfterType, Category::Type)
+            }
+            (Position::TypeName, Token::Function(_)) => (Position::TypeArguments, Category::Type),
+            (Position::TypeArguments, Token::RightParen) => {
+                (Position::AfterType, Category::Punctuation)
+            }
+            (Position::TypeArguments, Token::LeftParen) => {
+                (Position::TypeArguments, Category::Punctuation)
+            }
+            (Position::TypeArguments, _) => (Position::TypeArguments, Category::NodeTest),
+            (Position::AfterType, Token::QuestionMark)
+            | (Position::AfterType, Token::Multiply)
+            | (Position::AfterType, Token::PlusSign) => (Position::Expression, Category::Type),
+            (Position::LookupKey, Token::NameTest(name)) if name.local_part == "*" => {
+                (Position::Expression, Category::Operator)
+            }
+            (Position::LookupKey, Token::NameTest(_)) => (Position::Expression, Category::Literal),
+            (_, token) => {
+                let position = match *token {
+                    Token::InstanceOf | Token::TreatAs | Token::CastAs | Token::CastableAs => {
+                        Position::TypeName
+                    }
+                    Token::Lookup => Position::LookupKey,
+                    _ => Position::Expression,
+                };
+                (position, category(token))
+            }
+        };
+
+        *self = position;
+        category
+    }
+}
+
+/// The category of the token wherever it is written.
+fn category(token: &Token) -> Category {
+    match *token {
+        Token::Axis(_) | Token::AtSign => Category::Axis,
+        Token::NameTest(_) | Token::NodeTest(_) | Token::CurrentNode | Token::ParentNode => {
+            Category::NodeTest
+        }
+        Token::Function(_) => Category::Function,
+        Token::Variable(_) => Category::Variable,
+        Token::Literal(_) | Token::Number(_) => Category::Literal,
+        Token::Array
+        | Token::CastAs
+        | Token::CastableAs
+        | Token::Else
+        | Token::Every
+        | Token::For
+        | Token::If
+        | Token::In
+        | Token::InlineFunction
+        | Token::InstanceOf
+        | Token::Let
+        | Token::Map
+        | Token::Return
+        | Token::Satisfies
+        | Token::Some
+        | Token::Then
+        | Token::TreatAs => Category::Keyword,
+        Token::Assign
+        | Token::Colon
+        | Token::Comma
+        | Token::LeftBrace
+        | Token::LeftBracket
+        | Token::LeftParen
+        | Token::RightBrace
+        | Token::RightBracket
+        | Token::RightParen => Category::Punctuation,
+        Token::And
+        | Token::Arrow
+        | Token::Divide
+        | Token::DoubleSlash
+        | Token::Equal
+        | Token::Follows
+        | Token::GreaterThan
+        | Token::GreaterThanOrEqual
+        | Token::Is
+        | Token::LessThan
+        | Token::LessThanOrEqual
+        | Token::Lookup
+        | Token::MinusSign
+        | Token::Multiply
+        | Token::NotEqual
+        | Token::Or
+        | Token::Pipe
+        | Token::PlusSign
+        | Token::Precedes
+        | Token::QuestionMark
+        | Token::Remainder
+        | Token::Slash
+        | Token::To
+        | Token::ValueEqual
+        | Token::ValueGreaterThan
+        | Token::ValueGreaterThanOrEqual
+        | Token::ValueLessThan
+        | Token::ValueLessThanOrEqual
+        | Token::ValueNotEqual => Category::Operator,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Category::*;
+    use super::*;
+
+    fn classified(xpath: &str) -> Vec<(&str, Category)> {
+        classify(xpath)
+            .into_iter()
+            .map(|highlight| (&xpath[highlight.span.range()], highlight.category))
+            .collect()
+    }
+
+    #[test]
+    fn steps_are_split_into_axes_and_node_tests() {
+        assert_eq!(
+            vec![
+                ("/", Operator),
+                ("descendant::", Axis),
+                ("a", NodeTest),
+                ("/", Operator),
+                ("@", Axis),
+                ("b", NodeTest),
+                ("//", Operator),
+                ("text()", NodeTest),
+                ("/", Operator),
+                ("..", NodeTest),
+            ],
+            classified("/descendant::a/@b//text()/..")
+        );
+    }
+
+    #[test]
+    fn function_names_are_told_apart_from_node_tests() {
+        assert_eq!(
+            vec![
+                ("div", NodeTest),
+                ("div", Operator),
+                ("div", Function),
+                ("(", Punctuation),
+                ("'x'", Literal),
+                (",", Punctuation),
+                ("2", Literal),
+                (")", Punctuation),
+            ],
+            classified("div div div('x', 2)")
+        );
+    }
+
+    #[test]
+    fn names_in_types_are_types() {
+        assert_eq!(
+            vec![
+                ("$a", Variable),
+                ("instance of", Keyword),
+                ("element", Type),
+                ("(", Punctuation),
+                ("p:b", NodeTest),
+                (")", Punctuation),
+                ("and", Operator),
+                ("$a", Variable),
+                ("treat as", Keyword),
+                ("item", Type),
+                ("(", Punctuation),
+                (")", Punctuation),
+                ("*", Type),
+                (",", Punctuation),
+                ("$a", Variable),
+                ("cast as", Keyword),
+                ("xs:string", Type),
+                ("?", Type),
+                ("=", Operator),
+                ("count", Function),
+            ],
+            classified(
+                "$a instance of element(p:b) and $a treat as item()*, \
+                 $a cast as xs:string? = count()"
+            )[..20]
+        );
+    }
+
+    #[test]
+    fn lookup_keys_are_literals() {
+        assert_eq!(
+            vec![
+                ("$m", Variable),
+                ("?", Operator),
+                ("key", Literal),
+                ("?", Operator),
+                ("*", Operator),
+                ("/", Operator),
+                ("key", NodeTest),
+            ],
+            classified("$m?key?*/key")
+        );
+    }
+
+    #[test]
+    fn keywords_are_told_apart_from_operators() {
+        let categories: Vec<_> =
+            classified("for $i in 1 to 2 return if ($i) then map { 1 : $i } else ()")
+                .into_iter()
+                .filter(|&(_, category)| category == Keyword || category == Operator)
+                .collect();
+
+        assert_eq!(
+            vec![
+                ("for", Keyword),
+                ("in", Keyword),
+                ("to", Operator),
+                ("return", Keyword),
+                ("if", Keyword),
+                ("then", Keyword),
+                ("map", Keyword),
+                ("else", Keyword),
+            ],
+            categories
+        );
+    }
+
+    #[test]
+    fn comments_and_text_that_is_not_a_token_are_classified() {
+        assert_eq!(
+            vec![
+                ("a", NodeTest),
+                ("(: one :) (: two :)", Comment),
+                ("+", Operator),
+                ("\u{ac}", Error),
+                ("(: open", Error),
+            ],
+            classified("a (: one :) (: two :) + \u{ac} (: open")
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Indexes of a document's nodes by the values of an expression.
+//!
+//! An [`Index`][] is built in advance for one document and attached to
+//! a [`Context`][] with [`Context::add_index`][]. Evaluating a path
+//! whose step has a single predicate comparing the index's key to a
+//! literal or a variable, such as `//item[@id = $id]`, then looks the
+//! value up in the index instead of testing every node. The path up to
+//! that step must be the index's pattern: `//item` for a pattern of
+//! `item`, or `/shop/item` for a pattern of `/shop/item`.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::index::Index;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let package = parser::parse("<shop><item id='a'>Apple</item><item id='b'>Pear</item></shop>")
+//!     .expect("failed to parse XML");
+//! let document = package.as_document();
+//! let factory = Factory::new();
+//!
+//! let index = Index::build(
+//!     &Context::new(),
+//!     document.root(),
+//!     &factory.build("item").expect("Could not compile pattern"),
+//!     &factory.build("@id").expect("Could not compile XPath"),
+//! )
+//! .expect("Could not build index");
+//!
+//! let mut context = Context::new();
+//! context.add_index(&index);
+//! context.set_variable("id", "b");
+//!
+//! let xpath = factory.build("string(//item[@id = $id])").expect("Could not compile XPath");
+//! let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//!
+//! assert_eq!("Pear", value.string());
+//! ```
+//!
+//! The index is only used when the key gives nodes or strings, when the
+//! compared value is a string or nodes, and when no default collation
+//! is set, as the result is then the same as comparing each node. It
+//! is not updated when the document changes.
+//!
+//! [`Index`]: struct.Index.html
+//! [`Context`]: ../context/struct.Context.html
+//! [`Context::add_index`]: ../context/struct.Context.html#method.add_index
+
+use std::collections::HashMap;
+
+use crate::ast::{self, BinaryOperator, Expr};
+use crate::axis::Axis;
+use crate::context::{self, Context};
+use crate::expression;
+use crate::nodeset::Node;
+use crate::tree;
+use crate::{ExecutionError, Value, XPath};
+
+/// The nodes of one document by each value of a key.
+#[derive(Debug, Clone)]
+pub struct Index<'d> {
+    pattern: Expr,
+    key: Expr,
+    root: Node<'d>,
+    entries: HashMap<String, Vec<Node<'d>>>,
+    by_string: bool,
+}
+
+impl<'d> Index<'d> {
+    /// Indexes the nodes of the node's document that the pattern
+    /// matches, at any depth when it is a relative path, by each value
+    /// of `key`, which is evaluated with the node as the context node.
+    pub fn build<N>(
+        context: &Context<'d>,
+        node: N,
+        pattern: &XPath,
+        key: &XPath,
+    ) -> Result<Index<'d>, ExecutionError>
+    where
+        N: Into<Node<'d>>,
+    {
+        let evaluation = context::Evaluation::new(context, node.into());
+        Index::build_with(&evaluation, &matching_nodes(pattern.ast()), key.ast())
+            .map_err(ExecutionError)
+    }
+
+    /// Builds the index for the document of the context node. The
+    /// pattern selects the nodes from any node of the document.
+    pub(crate) fn build_with(
+        context: &context::Evaluation<'_, 'd>,
+        pattern: &Expr,
+        key: &Expr,
+    ) -> Result<Index<'d>, expression::Error> {
+        let root = tree::Document::root(&context.node.owner());
+        let context = context.new_context_for(root);
+
+        let matched = match pattern.compile().evaluate(&context)? {
+            Value::Nodeset(nodes) => nodes.document_order(),
+            _ => return Err(expression::Error::NotANodeset),
+        };
+
+        let uses = key.compile();
+        let mut entries: HashMap<_, Vec<_>> = HashMap::new();
+        let mut by_string = true;
+        for node in matched {
+            let values = match uses.evaluate(&context.new_context_for(node))? {
+                Value::Nodeset(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+                Value::String(s) => vec![s],
+                value => {
+                    by_string = false;
+                    value.items().iter().map(|item| item.string()).collect()
+                }
+            };
+            for value in values {
+                let nodes = entries.entry(value).or_default();
+                if nodes.last() != Some(&node) {
+                    nodes.push(node);
+                }
+            }
+        }
+
+        Ok(Index {
+            pattern: pattern.clone(),
+            key: key.clone(),
+            root,
+            entries,
+            by_string,
+        })
+    }
+
+    /// The nodes with the value, in document order.
+    pub fn get(&self, value: &str) -> &[Node<'d>] {
+        self.entries.get(value).map_or(&[], Vec::as_slice)
+    }
+
+    /// The number of distinct values.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no node has a value.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the nodes the path selects from the root, whose value of
+    /// `key` equals a string, can be looked up in this index.
+    pub(crate) fn answers(&self, path: &Expr, key: &Expr, root: Node<'d>) -> bool {
+        self.by_string && self.root == root && self.pattern == *path && self.key == *key
+    }
+}
+
+/// The expression that selects, from any node of a document, the
+/// nodes of the document that the pattern matches. Relative paths may
+/// match at any depth, so they start from every node of the document.
+pub(crate) fn matching_nodes(pattern: &Expr) -> Expr {
+    match *pattern {
+        Expr::Binary {
+            operator: BinaryOperator::Union,
+            ref left,
+            ref right,
+        } => Expr::binary(
+            BinaryOperator::Union,
+            matching_nodes(left),
+            matching_nodes(right),
+        ),
+        Expr::Path {
+            ref start_point,
+            ref steps,
+        } if **start_point == Expr::ContextNode => {
+            let anywhere = ast::Step {
+                axis: Axis::DescendantOrSelf,
+                node_test: ast::NodeTest::Node,
+                predicates: Vec::new(),
+            };
+            Expr::Path {
+                start_point: Box::new(Expr::RootNode),
+                steps: Some(anywhere)
+                    .into_iter()
+                    .chain(steps.iter().cloned())
+                    .collect(),
+            }
+        }
+        ref other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::Factory;
+
+    fn build<'d>(doc: sxd_document::dom::Document<'d>, pattern: &str, key: &str) -> Index<'d> {
+        let factory = Factory::new();
+        let pattern = factory.build(pattern).expect("Invalid pattern");
+        let key = factory.build(key).expect("Invalid expression");
+        Index::build(&Context::new(), doc.root(), &pattern, &key).expect("Unable to index")
+    }
+
+    fn texts(nodes: &[Node<'_>]) -> Vec<String> {
+        nodes.iter().map(|n| n.string_value()).collect()
+    }
+
+    #[test]
+    fn relative_patterns_match_at_any_depth() {
+        let package = parser::parse("<a><b id='1'>x<b id='2'>y</b></b><c id='1'>z</c></a>")
+            .expect("Unable to parse");
+        let index = build(package.as_document(), "b | a/c", "@id");
+
+        assert_eq!(vec!["xy", "z"], texts(index.get("1")));
+        assert_eq!(vec!["y"], texts(index.get("2")));
+        assert!(index.get("3").is_empty());
+        assert_eq!(2, index.len());
+    }
+
+    #[test]
+    fn each_value_of_the_key_is_indexed() {
+        let package =
+            parser::parse("<a><b><t>x</t><t>y</t><t>x</t></b></a>").expect("Unable to parse");
+        let index = build(package.as_document(), "b", "t");
+
+        assert_eq!(vec!["xyx"], texts(index.get("x")));
+        assert_eq!(vec!["xyx"], texts(index.get("y")));
+    }
+
+    #[test]
+    fn only_paths_to_the_pattern_are_answered() {
+        let package = parser::parse("<a><b id='1'/></a>").expect("Unable to parse");
+        let doc = package.as_document();
+        let index = build(doc, "b", "@id");
+
+        let ast = |xpath| {
+            Factory::new()
+                .build(xpath)
+                .expect("Invalid XPath")
+                .ast()
+                .clone()
+        };
+        assert!(index.answers(&ast("//b"), &ast("@id"), doc.root().into()));
+        assert!(!index.answers(&ast("/a/b"), &ast("@id"), doc.root().into()));
+        assert!(!index.answers(&ast("//b"), &ast("@name"), doc.root().into()));
+
+        let numbers = build(doc, "b", "number(@id)");
+        assert!(!numbers.answers(&ast("//b"), &ast("number(@id)"), doc.root().into()));
+    }
+}
@@ -0,0 +1,863 @@
+//! Writes syntax trees and values as JSON.
+//!
+//! [`render`][] gives each node of a syntax tree as a JSON object,
+//! which has a `type` such as `"path"` or `"binary"` and one member for
+//! each of its parts, so that programs in other languages can examine
+//! XPaths compiled by this crate.
+//!
+//! ```
+//! use sxd_xpath::Factory;
+//!
+//! let xpath = Factory::new().build("$limit > 2").expect("Could not compile XPath");
+//!
+//! assert_eq!(
+//!     r#"{"type":"binary","operator":">","left":{"type":"variable","name":{"prefix":null,"local_part":"limit"}},"right":{"type":"literal","value":2}}"#,
+//!     xpath.to_json()
+//! );
+//! ```
+//!
+//! The members of each type of node are:
+//!
+//! | type | members |
+//! |------|---------|
+//! | `context-node`, `root-node` | |
+//! | `literal` | `value`, a string, number or boolean |
+//! | `variable` | `name` |
+//! | `function` | `name`, `arguments` |
+//! | `path` | `start_point`, `steps` |
+//! | `filter` | `expression`, `predicate` |
+//! | `negation` | `expression` |
+//! | `binary` | `operator`, `left`, `right` |
+//! | `if` | `condition`, `consequent`, `alternative` |
+//! | `for` | `variable`, `sequence`, `body` |
+//! | `let` | `variable`, `value`, `body` |
+//! | `quantified` | `quantifier`, `variable`, `sequence`, `test` |
+//! | `instance-of`, `treat-as` | `expression`, `sequence_type` |
+//! | `cast`, `castable` | `expression`, `single_type` |
+//! | `sequence` | `items` |
+//! | `map` | `entries`, each with a `key` and a `value` |
+//! | `square-array` | `members` |
+//! | `curly-array` | `expression` |
+//! | `lookup` | `expression`, `key` |
+//! | `inline-function` | `parameters`, `body` |
+//! | `dynamic-call` | `function`, `arguments` |
+//!
+//! Names have a `prefix`, which may be null, and a `local_part`. Steps
+//! have an `axis`, a `node_test` and `predicates`. Operators, axes and
+//! quantifiers are spelled as they are in XPath. Numbers that JSON
+//! cannot hold, such as `NaN`, are written as strings.
+//!
+//! The names of types and members do not change between versions,
+//! although new ones may be added.
+//!
+//! [`render_value`][] writes the value that an XPath evaluates to in
+//! the same way.
+//!
+//! [`render_nodes`][] instead maps the XML of a nodeset onto plain JSON,
+//! for programs that expect JSON rather than XML: elements become
+//! objects, attributes become members and repeated children become
+//! arrays, as chosen by a [`Mapping`][].
+//!
+//! [`render`]: fn.render.html
+//! [`render_value`]: fn.render_value.html
+//! [`render_nodes`]: fn.render_nodes.html
+//! [`Mapping`]: struct.Mapping.html
+
+use sxd_document::dom;
+
+use crate::ast::{Expr, LookupKey, NodeTest, Occurrence, Quantifier, SingleType, Step};
+use crate::format;
+use crate::node_test::NameTest;
+use crate::nodeset::{Node, Nodeset};
+use crate::{Item, OwnedPrefixedName, Value};
+
+/// Writes the syntax tree as JSON.
+pub fn render(expr: &Expr) -> String {
+    let mut writer = Writer { out: String::new() };
+    writer.expr(expr);
+    writer.out
+}
+
+/// Writes the value of an XPath as JSON.
+///
+/// Booleans, strings and finite numbers are written as themselves and
+/// other numbers as strings. Nodesets and sequences are arrays, in
+/// document order for nodesets. A node is an object with its `type`,
+/// its `name` when it has one and its string `value`. Maps are objects
+/// keyed by the string value of each key and arrays are arrays. Dates,
+/// times and durations are strings, and functions are null.
+///
+/// ```
+/// use sxd_document::parser;
+/// use sxd_xpath::{evaluate_xpath, json};
+///
+/// let package = parser::parse("<item id='a'>Apple</item>").expect("failed to parse XML");
+/// let document = package.as_document();
+///
+/// let value = evaluate_xpath(&document, "(/item, count(//item))").expect("XPath evaluation failed");
+/// assert_eq!(
+///     r#"[{"type":"element","name":"item","value":"Apple"},1]"#,
+///     json::render_value(&value)
+/// );
+/// ```
+pub fn render_value(value: &Value<'_>) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+/// When the children of an element are written as arrays.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Arrays {
+    /// Children are arrays when there is more than one with the same
+    /// name, and are written as themselves otherwise.
+    #[default]
+    Repeated,
+    /// Children are always arrays, so that the shape of the JSON does
+    /// not depend on how many there are.
+    Always,
+}
+
+/// How [`render_nodes`](fn.render_nodes.html) maps XML onto JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    attribute_prefix: String,
+    text_key: String,
+    arrays: Arrays,
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        Mapping {
+            attribute_prefix: "@".into(),
+            text_key: "#text".into(),
+            arrays: Arrays::default(),
+        }
+    }
+}
+
+impl Mapping {
+    pub fn new() -> Self {
+        Mapping::default()
+    }
+
+    /// Sets what comes before the name of an attribute in its key, to
+    /// keep attributes apart from children. The default is `@`.
+    pub fn set_attribute_prefix(&mut self, prefix: &str) {
+        self.attribute_prefix = prefix.into();
+    }
+
+    /// Sets the key of the text of an element that also has attributes
+    /// or children. The default is `#text`.
+    pub fn set_text_key(&mut self, key: &str) {
+        self.text_key = key.into();
+    }
+
+    /// Chooses when children are arrays. The default is
+    /// [`Arrays::Repeated`](enum.Arrays.html#variant.Repeated).
+    pub fn set_arrays(&mut self, arrays: Arrays) {
+        self.arrays = arrays;
+    }
+}
+
+/// Maps the nodes of the nodeset, in document order, onto an array of
+/// plain JSON.
+///
+/// An element is an object with its name as the only key. What it
+/// holds is its text when it has no attributes or children, and an
+/// object otherwise, with a member for each attribute, one for each
+/// name of its children and one for its text. Text that is only
+/// whitespace is left out of such an object, as are comments and
+/// processing instructions. An attribute is an object with its key as
+/// the only key, and the document is an object like an element's.
+/// Other nodes are their string value. All values are strings, as XML
+/// does not say which text is a number.
+///
+/// ```
+/// use sxd_document::parser;
+/// use sxd_xpath::{evaluate_xpath, json, Value};
+///
+/// let package = parser::parse("<order id='7'><item>Apple</item><item>Pear</item><note/></order>")
+///     .expect("failed to parse XML");
+/// let document = package.as_document();
+///
+/// if let Value::Nodeset(nodes) = evaluate_xpath(&document, "/order").expect("XPath evaluation failed") {
+///     assert_eq!(
+///         r#"[{"order":{"@id":"7","item":["Apple","Pear"],"note":""}}]"#,
+///         json::render_nodes(&nodes, &json::Mapping::new())
+///     );
+/// }
+/// ```
+pub fn render_nodes(nodes: &Nodeset<'_>, mapping: &Mapping) -> String {
+    let mut out = String::new();
+    write_list(&mut out, nodes.document_order(), |out, node| {
+        mapping.node(out, node)
+    });
+    out
+}
+
+struct Writer {
+    out: String,
+}
+
+impl Writer {
+    fn expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::ContextNode => self.open("context-node"),
+            Expr::RootNode => self.open("root-node"),
+            Expr::Literal(ref value) => {
+                self.open("literal");
+                self.key("value");
+                self.value(value);
+            }
+            Expr::Variable(ref name) => {
+                self.open("variable");
+                self.key("name");
+                self.name(name);
+            }
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => {
+                self.open("function");
+                self.key("name");
+                self.name(name);
+                self.key("arguments");
+                self.exprs(arguments);
+            }
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => {
+                self.open("path");
+                self.key("start_point");
+                self.expr(start_point);
+                self.key("steps");
+                self.list(steps, Writer::step);
+            }
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => {
+                self.open("filter");
+                self.key("expression");
+                self.expr(expression);
+                self.key("predicate");
+                self.expr(predicate);
+            }
+            Expr::Negation(ref expression) => {
+                self.open("negation");
+                self.key("expression");
+                self.expr(expression);
+            }
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => {
+                self.open("binary");
+                self.key("operator");
+                self.string(format::binary_operator(operator).0);
+                self.key("left");
+                self.expr(left);
+                self.key("right");
+                self.expr(right);
+            }
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => {
+                self.open("if");
+                self.key("condition");
+                self.expr(condition);
+                self.key("consequent");
+                self.expr(consequent);
+                self.key("alternative");
+                self.expr(alternative);
+            }
+            Expr::For {
+                ref variable,
+                ref sequence,
+                ref body,
+            } => {
+                self.open("for");
+                self.key("variable");
+                self.name(variable);
+                self.key("sequence");
+                self.expr(sequence);
+                self.key("body");
+                self.expr(body);
+            }
+            Expr::Let {
+                ref variable,
+                ref value,
+                ref body,
+            } => {
+                self.open("let");
+                self.key("variable");
+                self.name(variable);
+                self.key("value");
+                self.expr(value);
+                self.key("body");
+                self.expr(body);
+            }
+            Expr::Quantified {
+                quantifier,
+                ref variable,
+                ref sequence,
+                ref test,
+            } => {
+                self.open("quantified");
+                self.key("quantifier");
+                self.string(match quantifier {
+                    Quantifier::Some => "some",
+                    Quantifier::Every => "every",
+                });
+                self.key("variable");
+                self.name(variable);
+                self.key("sequence");
+                self.expr(sequence);
+                self.key("test");
+                self.expr(test);
+            }
+            Expr::InstanceOf {
+                ref expression,
+                ref sequence_type,
+            }
+            | Expr::TreatAs {
+                ref expression,
+                ref sequence_type,
+            } => {
+                self.open(match *expr {
+                    Expr::InstanceOf { .. } => "instance-of",
+                    _ => "treat-as",
+                });
+                self.key("expression");
+                self.expr(expression);
+
+                self.key("sequence_type");
+                self.out.push('{');
+                self.out.push_str("\"item_type\":");
+                match sequence_type.item_type {
+                    Some(ref item_type) => self.string(&item_type.to_string()),
+                    None => self.out.push_str("null"),
+                }
+                self.key("occurrence");
+                self.string(match sequence_type.occurrence {
+                    Occurrence::ExactlyOne => "exactly-one",
+                    Occurrence::ZeroOrOne => "zero-or-one",
+                    Occurrence::ZeroOrMore => "zero-or-more",
+                    Occurrence::OneOrMore => "one-or-more",
+                });
+                self.out.push('}');
+            }
+            Expr::Cast {
+                ref expression,
+                ref single_type,
+            }
+            | Expr::Castable {
+                ref expression,
+                ref single_type,
+            } => {
+                self.open(match *expr {
+                    Expr::Cast { .. } => "cast",
+                    _ => "castable",
+                });
+                self.key("expression");
+                self.expr(expression);
+                self.key("single_type");
+                self.single_type(single_type);
+            }
+            Expr::Sequence(ref items) => {
+                self.open("sequence");
+                self.key("items");
+                self.exprs(items);
+            }
+            Expr::Map(ref entries) => {
+                self.open("map");
+                self.key("entries");
+                self.list(entries, |writer, (key, value)| {
+                    writer.out.push_str("{\"key\":");
+                    writer.expr(key);
+                    writer.key("value");
+                    writer.expr(value);
+                    writer.out.push('}');
+                });
+            }
+            Expr::SquareArray(ref members) => {
+                self.open("square-array");
+                self.key("members");
+                self.exprs(members);
+            }
+            Expr::CurlyArray(ref expression) => {
+                self.open("curly-array");
+                self.key("expression");
+                self.expr(expression);
+            }
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => {
+                self.open("lookup");
+                self.key("expression");
+                self.expr(expression);
+
+                self.key("key");
+                match *key {
+                    LookupKey::Name(ref name) => {
+                        self.open("name");
+                        self.key("name");
+                        self.string(name);
+                    }
+                    LookupKey::Wildcard => self.open("wildcard"),
+                    LookupKey::Expression(ref key) => {
+                        self.open("expression");
+                        self.key("expression");
+                        self.expr(key);
+                    }
+                }
+                self.out.push('}');
+            }
+            Expr::InlineFunction {
+                ref parameters,
+                ref body,
+            } => {
+                self.open("inline-function");
+                self.key("parameters");
+                self.list(parameters, Writer::name);
+                self.key("body");
+                self.expr(body);
+            }
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => {
+                self.open("dynamic-call");
+                self.key("function");
+                self.expr(function);
+                self.key("arguments");
+                self.exprs(arguments);
+            }
+        }
+        self.out.push('}');
+    }
+
+    fn step(&mut self, step: &Step) {
+        self.out.push_str("{\"axis\":");
+        self.string(format::axis_name(step.axis));
+
+        self.key("node_test");
+        match step.node_test {
+            NodeTest::Name(NameTest {
+                ref prefix,
+                ref local_part,
+            }) => {
+                self.open("name");
+                self.key("prefix");
+                self.optional_string(prefix.as_deref());
+                self.key("local_part");
+                self.string(local_part);
+            }
+            NodeTest::Node => self.open("node"),
+            NodeTest::Text => self.open("text"),
+            NodeTest::Comment => self.open("comment"),
+            NodeTest::ProcessingInstruction(ref target) => {
+                self.open("processing-instruction");
+                self.key("target");
+                self.optional_string(target.as_deref());
+            }
+        }
+        self.out.push('}');
+
+        self.key("predicates");
+        self.exprs(&step.predicates);
+        self.out.push('}');
+    }
+
+    fn single_type(&mut self, single_type: &SingleType) {
+        self.out.push_str("{\"name\":");
+        self.name(&single_type.name);
+        self.key("optional");
+        self.out.push_str(if single_type.optional {
+            "true"
+        } else {
+            "false"
+        });
+        self.out.push('}');
+    }
+
+    fn name(&mut self, name: &OwnedPrefixedName) {
+        self.out.push_str("{\"prefix\":");
+        self.optional_string(name.prefix());
+        self.key("local_part");
+        self.string(name.local_part());
+        self.out.push('}');
+    }
+
+    /// Writes the literals that the parser produces. Other values,
+    /// which only appear in trees that are built by hand, are written
+    /// as their string value.
+    fn value(&mut self, value: &Value<'_>) {
+        match *value {
+            Value::Number(n) if n.is_finite() => self.out.push_str(&n.to_string()),
+            Value::Number(n) => self.string(&n.to_string()),
+            Value::Boolean(b) => self.out.push_str(if b { "true" } else { "false" }),
+            Value::String(ref s) => self.string(s),
+            ref value => self.string(&value.string()),
+        }
+    }
+
+    /// Starts the object of a node, leaving it open for its members.
+    fn open(&mut self, kind: &str) {
+        self.out.push_str("{\"type\":");
+        self.string(kind);
+    }
+
+    fn key(&mut self, key: &str) {
+        self.out.push(',');
+        self.string(key);
+        self.out.push(':');
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        self.list(exprs, Writer::expr);
+    }
+
+    fn list<T, F>(&mut self, items: &[T], mut write: F)
+    where
+        F: FnMut(&mut Writer, &T),
+    {
+        self.out.push('[');
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            write(self, item);
+        }
+        self.out.push(']');
+    }
+
+    fn optional_string(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => self.string(s),
+            None => self.out.push_str("null"),
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        write_string(&mut self.out, s);
+    }
+}
+
+fn write_value(out: &mut String, value: &Value<'_>) {
+    match *value {
+        Value::Nodeset(ref nodes) => write_list(out, nodes.document_order(), |out, node| {
+            write_node(out, node)
+        }),
+        Value::Sequence(ref items) => write_list(out, items, write_item),
+        Value::Number(n) if n.is_finite() => out.push_str(&n.to_string()),
+        Value::Boolean(b) => out.push_str(if b { "true" } else { "false" }),
+        ref value => write_string(out, &value.string()),
+    }
+}
+
+fn write_item(out: &mut String, item: &Item<'_>) {
+    match *item {
+        Item::Node(node) => write_node(out, node),
+        Item::Map(ref map) => {
+            out.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, &key.string());
+                out.push(':');
+                write_value(out, value);
+            }
+            out.push('}');
+        }
+        Item::Array(ref members) => write_list(out, members, write_value),
+        Item::Function(_) => out.push_str("null"),
+        Item::DateTime(_) | Item::Duration(_) => write_string(out, &item.string()),
+        ref atomic => write_value(out, &Value::from(atomic.clone())),
+    }
+}
+
+/// Writes a node as an object with its type, its name when it has one
+/// and its string value.
+fn write_node(out: &mut String, node: Node<'_>) {
+    out.push_str("{\"type\":");
+    write_string(out, node.kind_name());
+    if let Some(name) = node.prefixed_name() {
+        out.push_str(",\"name\":");
+        write_string(out, &name);
+    }
+    out.push_str(",\"value\":");
+    write_string(out, &node.string_value());
+    out.push('}');
+}
+
+impl Mapping {
+    fn node(&self, out: &mut String, node: Node<'_>) {
+        match node {
+            Node::Root(root) => {
+                let elements = root.children().into_iter().filter_map(|c| c.element());
+                self.object(out, &[], elements.collect(), "");
+            }
+            Node::Element(element) => self.object(out, &[], vec![element], ""),
+            Node::Attribute(attribute) => self.object(out, &[attribute], Vec::new(), ""),
+            _ => write_string(out, &node.string_value()),
+        }
+    }
+
+    /// Writes what an element holds.
+    fn element(&self, out: &mut String, element: dom::Element<'_>) {
+        let attributes = element.attributes();
+        let mut children = Vec::new();
+        let mut text = String::new();
+        for child in element.children() {
+            match child {
+                dom::ChildOfElement::Element(e) => children.push(e),
+                dom::ChildOfElement::Text(t) => text.push_str(t.text()),
+                _ => {}
+            }
+        }
+
+        if attributes.is_empty() && children.is_empty() {
+            write_string(out, &text);
+        } else {
+            let text = if text.trim().is_empty() { "" } else { &text };
+            self.object(out, &attributes, children, text);
+        }
+    }
+
+    /// Writes an object with the attributes, the children grouped by
+    /// name in the order each name first appears, and the text unless
+    /// it is empty.
+    fn object(
+        &self,
+        out: &mut String,
+        attributes: &[dom::Attribute<'_>],
+        children: Vec<dom::Element<'_>>,
+        text: &str,
+    ) {
+        let mut groups: Vec<(String, Vec<dom::Element<'_>>)> = Vec::new();
+        for child in children {
+            let name = Node::Element(child).prefixed_name().unwrap_or_default();
+            match groups.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, group)) => group.push(child),
+                None => groups.push((name, vec![child])),
+            }
+        }
+
+        out.push('{');
+        let mut first = true;
+        for &attribute in attributes {
+            let name = Node::Attribute(attribute)
+                .prefixed_name()
+                .unwrap_or_default();
+            member(
+                out,
+                &mut first,
+                &format!("{}{}", self.attribute_prefix, name),
+            );
+            write_string(out, attribute.value());
+        }
+        for (name, group) in groups {
+            member(out, &mut first, &name);
+            if group.len() > 1 || self.arrays == Arrays::Always {
+                write_list(out, group, |out, e| self.element(out, e));
+            } else {
+                self.element(out, group[0]);
+            }
+        }
+        if !text.is_empty() {
+            member(out, &mut first, &self.text_key);
+            write_string(out, text);
+        }
+        out.push('}');
+    }
+}
+
+/// Writes the key of a member of an object, after a comma unless it is
+/// the first.
+fn member(out: &mut String, first: &mut bool, key: &str) {
+    if !*first {
+        out.push(',');
+    }
+    *first = false;
+    write_string(out, key);
+    out.push(':');
+}
+
+fn write_list<I, F>(out: &mut String, items: I, mut write: F)
+where
+    I: IntoIterator,
+    F: FnMut(&mut String, I::Item),
+{
+    out.push('[');
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write(out, item);
+    }
+    out.push(']');
+}
+
+/// Writes the string as a quoted JSON string.
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Factory;
+
+    fn render_xpath(xpath: &str) -> String {
+        let built = Factory::new().build(xpath).expect("Unable to build XPath");
+        render(built.ast())
+    }
+
+    #[test]
+    fn paths_have_their_steps() {
+        assert_eq!(
+            concat!(
+                r#"{"type":"path","start_point":{"type":"root-node"},"steps":["#,
+                r#"{"axis":"child","node_test":{"type":"name","prefix":"p","local_part":"a"},"#,
+                r#""predicates":[{"type":"literal","value":1}]},"#,
+                r#"{"axis":"attribute","node_test":{"type":"node"},"predicates":[]}]}"#
+            ),
+            render_xpath("/p:a[1]/@node()")
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(
+            r#"{"type":"literal","value":"a \"b\" \\ c"}"#,
+            render_xpath(r#"'a "b" \ c'"#)
+        );
+
+        let mut writer = Writer { out: String::new() };
+        writer.string("\n\u{1}");
+        assert_eq!(r#""\n\u0001""#, writer.out);
+    }
+
+    #[test]
+    fn numbers_that_json_cannot_hold_are_strings() {
+        let expr = Expr::Literal(Value::Number(f64::INFINITY));
+        assert_eq!(r#"{"type":"literal","value":"inf"}"#, render(&expr));
+    }
+
+    #[test]
+    fn types_are_written_with_their_parts() {
+        assert_eq!(
+            concat!(
+                r#"{"type":"instance-of","expression":{"type":"cast","#,
+                r#""expression":{"type":"variable","name":{"prefix":null,"local_part":"v"}},"#,
+                r#""single_type":{"name":{"prefix":"xs","local_part":"double"},"optional":true}},"#,
+                r#""sequence_type":{"item_type":"element(a)","occurrence":"zero-or-more"}}"#
+            ),
+            render_xpath("($v cast as xs:double?) instance of element(a)*")
+        );
+    }
+
+    #[test]
+    fn every_kind_of_expression_has_a_type() {
+        let json = render_xpath(
+            "for $i in (1, 2) return let $j := -$i return \
+             (every $k in map { 1 : 2 }?* satisfies $k treat as item(), \
+             if (.) then [1]?(1) else array { }?a, \
+             function($f) { $f castable as xs:string }($i)[1] | a)",
+        );
+
+        let types = [
+            "for",
+            "let",
+            "negation",
+            "quantified",
+            "map",
+            "lookup",
+            "wildcard",
+            "treat-as",
+            "if",
+            "context-node",
+            "square-array",
+            "expression",
+            "curly-array",
+            "name",
+            "dynamic-call",
+            "inline-function",
+            "castable",
+            "filter",
+            "binary",
+            "sequence",
+        ];
+        for kind in types.iter() {
+            assert!(
+                json.contains(&format!("{{\"type\":\"{}\"", kind)),
+                "{}",
+                kind
+            );
+        }
+    }
+
+    fn render_xml(xml: &str, xpath: &str, mapping: &Mapping) -> String {
+        let package = sxd_document::parser::parse(xml).expect("failed to parse XML");
+        let document = package.as_document();
+
+        match crate::evaluate_xpath(&document, xpath).expect("XPath evaluation failed") {
+            Value::Nodeset(nodes) => render_nodes(&nodes, mapping),
+            other => panic!("expected a nodeset, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn elements_are_objects_of_their_attributes_children_and_text() {
+        let xml = "<a x='1'>\n  <b>one</b>\n  <c y='2'>two<!--c--></c>\n  <b>three</b>\n</a>";
+        assert_eq!(
+            r##"[{"a":{"@x":"1","b":["one","three"],"c":{"@y":"2","#text":"two"}}}]"##,
+            render_xml(xml, "/", &Mapping::new())
+        );
+    }
+
+    #[test]
+    fn the_mapping_is_configurable() {
+        let mut mapping = Mapping::new();
+        mapping.set_attribute_prefix("_");
+        mapping.set_text_key("value");
+        mapping.set_arrays(Arrays::Always);
+
+        assert_eq!(
+            r#"[{"r":[{"a":[{"_x":"1","value":"t"}]}]}]"#,
+            render_xml("<r><a x='1'>t</a></r>", "/r", &mapping)
+        );
+    }
+
+    #[test]
+    fn other_nodes_are_their_own_values() {
+        assert_eq!(
+            r#"[{"@p:x":"1"},"t",{"b":""}]"#,
+            render_xml(
+                "<a xmlns:p='urn:p' p:x='1'>t<b/></a>",
+                "/a/@* | /a/text() | /a/b",
+                &Mapping::new()
+            )
+        );
+    }
+}
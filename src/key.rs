@@ -0,0 +1,128 @@
+//! Keys for the `key()` function, like the `xsl:key` declarations of
+//! XSLT.
+//!
+//! A key is declared on the [`Context`][] with a name, a pattern that
+//! matches the nodes to index and an expression that gives the values
+//! each node is indexed by. The first call of `key()` for a document
+//! indexes the whole document, and later calls during the same
+//! evaluation look values up in that index.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let package = parser::parse(
+//!     "<shop>
+//!        <customer id='c1'>Ada</customer>
+//!        <customer id='c2'>Grace</customer>
+//!        <order customer='c2'/>
+//!      </shop>",
+//! )
+//! .expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let factory = Factory::new();
+//! let mut context = Context::new();
+//! context.set_key(
+//!     "customer",
+//!     &factory.build("customer").expect("Could not compile pattern"),
+//!     &factory.build("@id").expect("Could not compile XPath"),
+//! );
+//!
+//! let xpath = factory
+//!     .build("string(key('customer', //order/@customer))")
+//!     .expect("Could not compile XPath");
+//! let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//!
+//! assert_eq!("Grace", value.string());
+//! ```
+//!
+//! [`Context`]: ../context/struct.Context.html
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::context;
+use crate::expression;
+use crate::index::{self, Index};
+use crate::nodeset::Node;
+use crate::tree;
+use crate::OwnedQName;
+
+/// A declared key.
+#[derive(Debug)]
+pub(crate) struct Key {
+    pattern: Expr,
+    uses: Expr,
+}
+
+impl Key {
+    pub(crate) fn new(pattern: &Expr, uses: &Expr) -> Key {
+        Key {
+            pattern: index::matching_nodes(pattern),
+            uses: uses.clone(),
+        }
+    }
+}
+
+/// The indexes built while evaluating an expression, by key name and
+/// document.
+#[derive(Default)]
+pub(crate) struct Indexes<'d>(RefCell<HashMap<(OwnedQName, Node<'d>), Rc<Index<'d>>>>);
+
+impl<'d> Indexes<'d> {
+    /// The index of the key for the document of the context node,
+    /// building it the first time.
+    pub(crate) fn get(
+        &self,
+        name: &OwnedQName,
+        key: &Key,
+        context: &context::Evaluation<'_, 'd>,
+    ) -> Result<Rc<Index<'d>>, expression::Error> {
+        let root = tree::Document::root(&context.node.owner());
+        let id = (name.clone(), root);
+        if let Some(index) = self.0.borrow().get(&id) {
+            return Ok(Rc::clone(index));
+        }
+
+        let index = Rc::new(Index::build_with(context, &key.pattern, &key.uses)?);
+        self.0.borrow_mut().insert(id, Rc::clone(&index));
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::{Context, Factory};
+
+    #[test]
+    fn indexes_are_built_once_per_document() {
+        let package = parser::parse("<a><b id='1'/></a>").expect("Unable to parse");
+        let doc = package.as_document();
+        let context = Context::new();
+        let b = doc.root().children()[0].element().unwrap().children()[0];
+        let evaluation = context::Evaluation::new(&context, b.into());
+
+        let factory = Factory::new();
+        let pattern = factory.build("b").expect("Invalid pattern");
+        let uses = factory.build("@id").expect("Invalid expression");
+        let key = Key::new(pattern.ast(), uses.ast());
+        let name = OwnedQName::from("k");
+
+        let indexes = Indexes::default();
+        let first = indexes
+            .get(&name, &key, &evaluation)
+            .expect("Unable to index");
+        let second = indexes
+            .get(&name, &key, &evaluation.new_context_for(doc.root()))
+            .expect("Unable to index");
+
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(1, first.get("1").len());
+    }
+}
@@ -65,6 +65,10 @@
 //! See [`Context`][] for details on how to customize the
 //! evaluation of the XPath.
 //!
+//! XPaths that are written in the program can instead be compiled
+//! with the `xpath!` macro of the `sxd-xpath-macros` crate, which
+//! fails the build when an XPath has a syntax error.
+//!
 //! [evaluate_xpath]: fn.evaluate_xpath.html
 //! [`Value`]: enum.Value.html
 //! [`Factory`]: struct.Factory.html
@@ -83,9 +87,9 @@
 //! defined prefixes, some XPath behavior may be confusing:
 //!
 //! 1. The `name` method will not include a prefix, even if the
-//! element or attribute has a namespace.
+//!    element or attribute has a namespace.
 //! 2. The `namespace` axis will not include namespaces without
-//! prefixes.
+//!    prefixes.
 //!
 //! #### Document order
 //!
@@ -93,43 +97,110 @@
 //! nodes to the document, some XPath behavior may be confusing:
 //!
 //! 1. These nodes have no [*document order*]. If you create a
-//! variable containing these nodes and apply a predicate to them,
-//! these nodes will appear after any nodes that are present in the
-//! document, but the relative order of the nodes is undefined.
+//!    variable containing these nodes and apply a predicate to them,
+//!    these nodes will appear after any nodes that are present in the
+//!    document, but the relative order of the nodes is undefined.
 //!
 //! [*document order*]: https://www.w3.org/TR/xpath/#dt-document-order
 
 use snafu::{ResultExt, Snafu};
 use std::borrow::ToOwned;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::iter;
 use std::string;
 use sxd_document::dom::Document;
 use sxd_document::{PrefixedName, QName};
 
+use crate::function::ArgumentType;
 use crate::parser::Parser;
-use crate::tokenizer::{TokenDeabbreviator, Tokenizer};
+use crate::token::Token;
+use crate::tokenizer::{Deabbreviator, Span, TokenResult, TokenTransformer, Tokenizer};
 
 pub use crate::context::Context;
 
 #[macro_use]
 pub mod macros;
+pub mod ast;
+pub mod atomic;
 pub mod axis;
+pub mod backend;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod catalog;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod collation;
+pub mod compare;
+pub mod completion;
 pub mod context;
+pub mod conv;
+pub mod cost;
+pub mod datetime;
+pub mod debug;
+pub mod dot;
 pub mod expression;
+#[cfg(feature = "exslt")]
+pub mod exslt;
+pub mod fold;
+pub mod format;
+pub mod fragment;
 pub mod function;
+pub mod hashable;
+pub mod highlight;
+pub mod index;
+pub mod json;
+pub mod key;
+pub mod lint;
+pub mod map;
 pub mod node_test;
 pub mod nodeset;
+pub mod normalize;
+pub mod owned;
 pub mod parser;
+pub mod pool;
+#[cfg(feature = "stream")]
+pub mod projection;
+#[cfg(feature = "repl")]
+pub mod repl;
+pub mod resolver;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod spanned;
+pub mod stream;
 pub mod token;
 pub mod tokenizer;
+pub mod tree;
+pub mod uri;
+pub mod xml;
 
 // These belong in the the document
 
+/// The namespace of the `xml` prefix, which is always bound.
+pub(crate) const XML_NAMESPACE_URI: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// The namespace of HTML elements.
+pub(crate) const HTML_NAMESPACE_URI: &str = "http://www.w3.org/1999/xhtml";
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OwnedPrefixedName {
     prefix: Option<String>,
     local_part: String,
 }
 
+impl OwnedPrefixedName {
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    pub fn local_part(&self) -> &str {
+        &self.local_part
+    }
+}
+
 impl<'a> From<&'a str> for OwnedPrefixedName {
     fn from(local_part: &'a str) -> Self {
         OwnedPrefixedName {
@@ -203,6 +274,24 @@ type LiteralValue = Value<'static>;
 
 /// The primary types of values that an XPath expression accepts
 /// as an argument or returns as a result.
+///
+/// A value that is known to be of one type can be converted to it with
+/// `TryFrom`, which fails with a [`ConversionError`][] otherwise:
+///
+/// ```
+/// use std::convert::TryFrom;
+///
+/// use sxd_document::parser;
+/// use sxd_xpath::evaluate_xpath;
+///
+/// let package = parser::parse("<a><b/><b/></a>").expect("failed to parse XML");
+/// let document = package.as_document();
+///
+/// let count = f64::try_from(evaluate_xpath(&document, "count(//b)").expect("XPath evaluation failed"));
+/// assert_eq!(Ok(2.0), count);
+/// ```
+///
+/// [`ConversionError`]: enum.ConversionError.html
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value<'d> {
     /// A true or false value
@@ -213,10 +302,133 @@ pub enum Value<'d> {
     String(string::String),
     /// A collection of unique nodes
     Nodeset(nodeset::Nodeset<'d>),
+    /// An ordered list of nodes and atomic values, which may contain
+    /// duplicates
+    Sequence(Vec<Item<'d>>),
+}
+
+/// A single member of a [`Value::Sequence`][].
+///
+/// [`Value::Sequence`]: enum.Value.html#variant.Sequence
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item<'d> {
+    Node(nodeset::Node<'d>),
+    Boolean(bool),
+    Number(f64),
+    String(string::String),
+    /// An `xs:dateTime`, `xs:date` or `xs:time`
+    DateTime(datetime::DateTime),
+    /// An `xs:duration` or one of its subtypes
+    Duration(datetime::Duration),
+    /// A map from atomic keys to values
+    Map(map::Map<'d>),
+    /// An ordered list of values, each of which is one member
+    Array(Vec<Value<'d>>),
+    /// A function that can be called with arguments
+    Function(function::FunctionItem<'d>),
+}
+
+impl<'d> Item<'d> {
+    /// Whether the item is a node rather than an atomic value.
+    pub fn is_node(&self) -> bool {
+        matches!(*self, Item::Node(_))
+    }
+
+    /// Whether the item is an atomic value, such as a string or a
+    /// date, rather than a node, map, array or function.
+    pub fn is_atomic(&self) -> bool {
+        matches!(
+            *self,
+            Item::Boolean(_)
+                | Item::Number(_)
+                | Item::String(_)
+                | Item::DateTime(_)
+                | Item::Duration(_)
+        )
+    }
+
+    // Maps, arrays and functions have no string or numeric value of
+    // their own
+    pub fn boolean(&self) -> bool {
+        match *self {
+            Item::Node(_) | Item::Map(_) | Item::Array(_) | Item::Function(_) => true,
+            Item::DateTime(_) | Item::Duration(_) => true,
+            ref atomic => Value::from(atomic.clone()).boolean(),
+        }
+    }
+
+    pub fn number(&self) -> f64 {
+        match *self {
+            Item::Map(_) | Item::Array(_) | Item::Function(_) => f64::NAN,
+            Item::DateTime(_) | Item::Duration(_) => f64::NAN,
+            ref item => Value::from(item.clone()).number(),
+        }
+    }
+
+    pub fn string(&self) -> string::String {
+        match *self {
+            Item::Node(n) => n.string_value(),
+            Item::String(ref s) => s.clone(),
+            Item::DateTime(ref dt) => dt.to_string(),
+            Item::Duration(ref d) => d.to_string(),
+            Item::Map(_) | Item::Array(_) | Item::Function(_) => string::String::new(),
+            ref atomic => Value::from(atomic.clone()).string(),
+        }
+    }
+}
+
+impl<'d> From<Item<'d>> for Value<'d> {
+    fn from(item: Item<'d>) -> Value<'d> {
+        match item {
+            Item::Node(n) => Value::Nodeset(nodeset![n]),
+            Item::Boolean(b) => Value::Boolean(b),
+            Item::Number(n) => Value::Number(n),
+            Item::String(s) => Value::String(s),
+            structured => Value::Sequence(vec![structured]),
+        }
+    }
+}
+
+impl<'d> From<bool> for Item<'d> {
+    fn from(other: bool) -> Item<'d> {
+        Item::Boolean(other)
+    }
+}
+
+impl<'d> From<f64> for Item<'d> {
+    fn from(other: f64) -> Item<'d> {
+        Item::Number(other)
+    }
+}
+
+impl<'a, 'd> From<&'a str> for Item<'d> {
+    fn from(other: &'a str) -> Item<'d> {
+        Item::String(other.to_owned())
+    }
+}
+
+impl<'d> From<datetime::DateTime> for Item<'d> {
+    fn from(other: datetime::DateTime) -> Item<'d> {
+        Item::DateTime(other)
+    }
+}
+
+impl<'d> From<datetime::Duration> for Item<'d> {
+    fn from(other: datetime::Duration) -> Item<'d> {
+        Item::Duration(other)
+    }
+}
+
+impl<'d> From<map::Map<'d>> for Item<'d> {
+    fn from(other: map::Map<'d>) -> Item<'d> {
+        Item::Map(other)
+    }
 }
 
-fn str_to_num(s: &str) -> f64 {
-    s.trim().parse().unwrap_or(::std::f64::NAN)
+impl<'d> From<nodeset::Node<'d>> for Item<'d> {
+    fn from(node: nodeset::Node<'d>) -> Item<'d> {
+        Item::Node(node)
+    }
 }
 
 impl<'d> Value<'d> {
@@ -224,9 +436,16 @@ impl<'d> Value<'d> {
         use crate::Value::*;
         match *self {
             Boolean(val) => val,
-            Number(n) => n != 0.0 && !n.is_nan(),
-            String(ref s) => !s.is_empty(),
+            Number(n) => conv::number_to_boolean(n),
+            String(ref s) => conv::string_to_boolean(s),
             Nodeset(ref nodeset) => nodeset.size() > 0,
+            // A single atomic value is tested by its own rules, as in
+            // XPath 2.0; any other non-empty sequence is true
+            Sequence(ref items) => match items[..] {
+                [] => false,
+                [ref item] => item.boolean(),
+                _ => true,
+            },
         }
     }
 
@@ -237,16 +456,11 @@ impl<'d> Value<'d> {
     pub fn number(&self) -> f64 {
         use crate::Value::*;
         match *self {
-            Boolean(val) => {
-                if val {
-                    1.0
-                } else {
-                    0.0
-                }
-            }
+            Boolean(val) => conv::boolean_to_number(val),
             Number(val) => val,
-            String(ref s) => str_to_num(s),
-            Nodeset(..) => str_to_num(&self.string()),
+            String(ref s) => conv::string_to_number(s),
+            Nodeset(..) => conv::string_to_number(&self.string()),
+            Sequence(ref items) => items.first().map_or(f64::NAN, Item::number),
         }
     }
 
@@ -257,23 +471,89 @@ impl<'d> Value<'d> {
     pub fn string(&self) -> string::String {
         use crate::Value::*;
         match *self {
-            Boolean(v) => v.to_string(),
-            Number(n) => {
-                if n.is_infinite() {
-                    if n.signum() < 0.0 {
-                        "-Infinity".to_owned()
-                    } else {
-                        "Infinity".to_owned()
-                    }
-                } else {
-                    n.to_string()
-                }
-            }
+            Boolean(v) => conv::boolean_to_string(v).to_owned(),
+            Number(n) => conv::number_to_string(n),
             String(ref val) => val.clone(),
             Nodeset(ref ns) => match ns.document_order_first() {
                 Some(n) => n.string_value(),
                 None => "".to_owned(),
             },
+            Sequence(ref items) => items.first().map(Item::string).unwrap_or_default(),
+        }
+    }
+
+    /// `self + other`, as the `+` operator computes it. Both values
+    /// are converted to numbers, so a value that is not a number gives
+    /// NaN, which every later operation keeps. Dates, times and
+    /// durations are added as in XPath 2.0, which fails for operands
+    /// such as two dates.
+    ///
+    /// ```
+    /// use sxd_xpath::Value;
+    ///
+    /// let sum = Value::Number(1.5).add(&Value::String(" 2 ".into()));
+    /// assert_eq!(Ok(Value::Number(3.5)), sum);
+    ///
+    /// let sum = Value::Number(1.0).add(&Value::String("two".into()));
+    /// assert!(sum.expect("numbers can always be added").number().is_nan());
+    /// ```
+    pub fn add(&self, other: &Value<'d>) -> Result<Value<'d>, ExecutionError> {
+        expression::Arithmetic::ADDITION
+            .apply(self, other)
+            .map_err(ExecutionError)
+    }
+
+    /// `self - other`, as the `-` operator computes it. See
+    /// [`add`](#method.add).
+    pub fn subtract(&self, other: &Value<'d>) -> Result<Value<'d>, ExecutionError> {
+        expression::Arithmetic::SUBTRACTION
+            .apply(self, other)
+            .map_err(ExecutionError)
+    }
+
+    /// `self * other`, as the `*` operator computes it. See
+    /// [`add`](#method.add).
+    pub fn multiply(&self, other: &Value<'d>) -> Result<Value<'d>, ExecutionError> {
+        expression::Arithmetic::MULTIPLICATION
+            .apply(self, other)
+            .map_err(ExecutionError)
+    }
+
+    /// `self div other`, as the `div` operator computes it. Dividing by
+    /// zero gives an infinity, or NaN for zero divided by zero. See
+    /// [`add`](#method.add).
+    pub fn divide(&self, other: &Value<'d>) -> Result<Value<'d>, ExecutionError> {
+        expression::Arithmetic::DIVISION
+            .apply(self, other)
+            .map_err(ExecutionError)
+    }
+
+    /// `self mod other`, as the `mod` operator computes it. The result
+    /// has the sign of `self`, like the `%` operator of Rust. See
+    /// [`add`](#method.add).
+    pub fn remainder(&self, other: &Value<'d>) -> Result<Value<'d>, ExecutionError> {
+        expression::Arithmetic::REMAINDER
+            .apply(self, other)
+            .map_err(ExecutionError)
+    }
+
+    /// Writes the same text as [`string`](#method.string). The string
+    /// value of a node is written without building a string of it
+    /// first, so large text nodes are not copied.
+    pub fn write_string<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        use crate::Value::*;
+        match *self {
+            String(ref val) => out.write_all(val.as_bytes()),
+            Nodeset(ref ns) => match ns.document_order_first() {
+                Some(n) => n.write_string_value(out),
+                None => Ok(()),
+            },
+            Sequence(ref items) => match items.first() {
+                Some(Item::Node(n)) => n.write_string_value(out),
+                Some(item) => out.write_all(item.string().as_bytes()),
+                None => Ok(()),
+            },
+            Boolean(_) | Number(_) => out.write_all(self.string().as_bytes()),
         }
     }
 
@@ -284,6 +564,63 @@ impl<'d> Value<'d> {
             other => other.string(),
         }
     }
+
+    /// The nodes of the value, in document order. Booleans, numbers
+    /// and strings have no nodes; the atomic items of a sequence are
+    /// skipped and its nodes are kept in the sequence's order.
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::evaluate_xpath;
+    ///
+    /// let package = parser::parse("<root><a>1</a><a>2</a></root>").expect("failed to parse the XML");
+    /// let document = package.as_document();
+    ///
+    /// let value = evaluate_xpath(&document, "/root/a").expect("XPath evaluation failed");
+    /// let text: Vec<_> = value.iter().map(|node| node.string_value()).collect();
+    /// assert_eq!(vec!["1", "2"], text);
+    /// ```
+    pub fn iter(&self) -> Nodes<'d> {
+        self.clone().into_iter()
+    }
+
+    /// The members of the value as a sequence. The nodes of a nodeset
+    /// are returned in document order.
+    pub fn items(&self) -> Vec<Item<'d>> {
+        self.clone().into_items()
+    }
+
+    pub fn into_items(self) -> Vec<Item<'d>> {
+        match self {
+            Value::Boolean(v) => vec![Item::Boolean(v)],
+            Value::Number(v) => vec![Item::Number(v)],
+            Value::String(v) => vec![Item::String(v)],
+            Value::Nodeset(ns) => ns.document_order().into_iter().map(Item::Node).collect(),
+            Value::Sequence(items) => items,
+        }
+    }
+
+    /// Converts a nodeset, or a sequence that only contains nodes, to
+    /// a nodeset. Any other value is returned unchanged.
+    pub(crate) fn into_nodeset(self) -> Result<nodeset::Nodeset<'d>, Self> {
+        match self {
+            Value::Nodeset(ns) => Ok(ns),
+            Value::Sequence(items) => {
+                if items.iter().all(Item::is_node) {
+                    Ok(items
+                        .into_iter()
+                        .filter_map(|item| match item {
+                            Item::Node(n) => Some(n),
+                            _ => None,
+                        })
+                        .collect())
+                } else {
+                    Err(Value::Sequence(items))
+                }
+            }
+            other => Err(other),
+        }
+    }
 }
 
 macro_rules! from_impl {
@@ -305,6 +642,22 @@ impl<'a, 'd> From<&'a str> for Value<'d> {
     }
 }
 from_impl!(nodeset::Nodeset<'d>, Value::Nodeset);
+from_impl!(Vec<Item<'d>>, Value::Sequence);
+
+impl<'d> From<Vec<nodeset::Node<'d>>> for Value<'d> {
+    fn from(other: Vec<nodeset::Node<'d>>) -> Value<'d> {
+        Value::Nodeset(other.into())
+    }
+}
+
+impl<'d> iter::FromIterator<nodeset::Node<'d>> for Value<'d> {
+    fn from_iter<I>(iterator: I) -> Value<'d>
+    where
+        I: IntoIterator<Item = nodeset::Node<'d>>,
+    {
+        Value::Nodeset(iterator.into_iter().collect())
+    }
+}
 
 macro_rules! partial_eq_impl {
     ($raw:ty, $variant:pat => $b:expr) => {
@@ -334,13 +687,346 @@ partial_eq_impl!(String, Value::String(ref v) => v);
 partial_eq_impl!(&'d str, Value::String(ref v) => v);
 partial_eq_impl!(nodeset::Nodeset<'d>, Value::Nodeset(ref v) => v);
 
+/// Converts a value that is a single item of the type, either on its
+/// own or as the only item of a sequence.
+macro_rules! try_from_impl {
+    ($raw:ty, $variant:ident) => {
+        impl<'d> TryFrom<Value<'d>> for $raw {
+            type Error = ConversionError;
+
+            fn try_from(value: Value<'d>) -> Result<$raw, ConversionError> {
+                let actual = ArgumentType::from(&value);
+                let mut items = value.into_items();
+                match (items.pop(), items.is_empty()) {
+                    (Some(Item::$variant(v)), true) => Ok(v),
+                    _ => UnexpectedType {
+                        expected: ArgumentType::$variant,
+                        actual,
+                    }
+                    .fail(),
+                }
+            }
+        }
+    };
+}
+
+try_from_impl!(bool, Boolean);
+try_from_impl!(f64, Number);
+try_from_impl!(String, String);
+
+/// Converts a nodeset, or a sequence that only contains nodes.
+impl<'d> TryFrom<Value<'d>> for nodeset::Nodeset<'d> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value<'d>) -> Result<nodeset::Nodeset<'d>, ConversionError> {
+        value
+            .into_nodeset()
+            .map_err(|other| ConversionError::UnexpectedType {
+                expected: ArgumentType::Nodeset,
+                actual: ArgumentType::from(&other),
+            })
+    }
+}
+
+/// Converts a nodeset, or a sequence that only contains nodes, and
+/// sorts the nodes into document order.
+impl<'d> TryFrom<Value<'d>> for nodeset::OrderedNodeset<'d> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value<'d>) -> Result<nodeset::OrderedNodeset<'d>, ConversionError> {
+        nodeset::Nodeset::try_from(value).map(Into::into)
+    }
+}
+
+/// Iterates over the nodes of a value. See
+/// [`Value::iter`](enum.Value.html#method.iter).
+#[derive(Debug, Clone)]
+pub struct Nodes<'d> {
+    iter: std::vec::IntoIter<nodeset::Node<'d>>,
+}
+
+impl<'d> Iterator for Nodes<'d> {
+    type Item = nodeset::Node<'d>;
+
+    fn next(&mut self) -> Option<nodeset::Node<'d>> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'d> DoubleEndedIterator for Nodes<'d> {
+    fn next_back(&mut self) -> Option<nodeset::Node<'d>> {
+        self.iter.next_back()
+    }
+}
+
+impl<'d> ExactSizeIterator for Nodes<'d> {}
+
+impl<'d> IntoIterator for Value<'d> {
+    type Item = nodeset::Node<'d>;
+    type IntoIter = Nodes<'d>;
+
+    fn into_iter(self) -> Nodes<'d> {
+        let nodes = match self {
+            Value::Nodeset(ns) => ns.document_order(),
+            Value::Sequence(items) => items
+                .into_iter()
+                .filter_map(|item| match item {
+                    Item::Node(n) => Some(n),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Nodes {
+            iter: nodes.into_iter(),
+        }
+    }
+}
+
+impl<'d> IntoIterator for &Value<'d> {
+    type Item = nodeset::Node<'d>;
+    type IntoIter = Nodes<'d>;
+
+    fn into_iter(self) -> Nodes<'d> {
+        self.iter()
+    }
+}
+
+/// Types that the value of an XPath can be converted to, for
+/// [`XPath::evaluate_as`](struct.XPath.html#method.evaluate_as).
+///
+/// Booleans, numbers and strings are converted as the `boolean()`,
+/// `number()` and `string()` functions convert them, which cannot fail.
+/// A nodeset, or an ordered nodeset, is only converted from a value
+/// that holds nodes.
+pub trait FromValue<'d>: Sized {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError>;
+}
+
+impl<'d> FromValue<'d> for bool {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        Ok(value.into_boolean())
+    }
+}
+
+impl<'d> FromValue<'d> for f64 {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        Ok(value.into_number())
+    }
+}
+
+impl<'d> FromValue<'d> for String {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        Ok(value.into_string())
+    }
+}
+
+impl<'d> FromValue<'d> for nodeset::Nodeset<'d> {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        nodeset::Nodeset::try_from(value)
+    }
+}
+
+impl<'d> FromValue<'d> for nodeset::OrderedNodeset<'d> {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        nodeset::OrderedNodeset::try_from(value)
+    }
+}
+
+/// The items of the value, as [`Value::into_items`](enum.Value.html#method.into_items)
+/// gives them.
+impl<'d> FromValue<'d> for Vec<Item<'d>> {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        Ok(value.into_items())
+    }
+}
+
+impl<'d> FromValue<'d> for Value<'d> {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        Ok(value)
+    }
+}
+
+impl<'d> FromValue<'d> for owned::OwnedValue {
+    fn from_value(value: Value<'d>) -> Result<Self, ConversionError> {
+        Ok(value.into())
+    }
+}
+
+/// Which part of the result [`XPath::evaluate_with_options`][] returns,
+/// for showing the nodes a page at a time.
+///
+/// A nodeset is returned in [document order] from the first node
+/// after the skipped ones, and a sequence from the first item after
+/// the skipped ones. Other values are returned unchanged.
+///
+/// [`XPath::evaluate_with_options`]: struct.XPath.html#method.evaluate_with_options
+/// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EvaluationOptions {
+    max_results: Option<usize>,
+    skip: usize,
+}
+
+impl EvaluationOptions {
+    pub fn new() -> Self {
+        EvaluationOptions::default()
+    }
+
+    /// At most this many nodes or items are returned. The default is
+    /// `None`, for all of them.
+    pub fn set_max_results(&mut self, max_results: Option<usize>) {
+        self.max_results = max_results;
+    }
+
+    /// This many nodes or items are left out before the first one that
+    /// is returned. The default is 0.
+    pub fn set_skip(&mut self, skip: usize) {
+        self.skip = skip;
+    }
+
+    fn page<'d>(&self, value: Value<'d>) -> Value<'d> {
+        let max_results = self.max_results.unwrap_or(usize::MAX);
+        match value {
+            Value::Nodeset(nodes) if self.skip > 0 || nodes.size() > max_results => {
+                let page = nodes
+                    .document_order_iter()
+                    .skip(self.skip)
+                    .take(max_results)
+                    .collect();
+                Value::Nodeset(nodeset::Nodeset::from_document_order(page))
+            }
+            Value::Sequence(items) => Value::Sequence(
+                items
+                    .into_iter()
+                    .skip(self.skip)
+                    .take(max_results)
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+}
+
 /// A compiled XPath. Construct via [`Factory`][].
 ///
 /// [`Factory`]: struct.Factory.html
 #[derive(Debug)]
-pub struct XPath(Box<dyn expression::Expression + 'static>);
+pub struct XPath {
+    ast: ast::Expr,
+    expression: Box<dyn expression::Expression + 'static>,
+    spans: Option<spanned::SpanTree>,
+}
 
 impl XPath {
+    fn new(ast: ast::Expr) -> XPath {
+        let expression = ast.compile();
+        XPath {
+            ast,
+            expression,
+            spans: None,
+        }
+    }
+
+    fn parsed(ast: ast::Expr, spans: spanned::SpanTree) -> XPath {
+        XPath {
+            spans: Some(spans),
+            ..XPath::new(ast)
+        }
+    }
+
+    /// The syntax tree of this XPath.
+    pub fn ast(&self) -> &ast::Expr {
+        &self.ast
+    }
+
+    /// The syntax tree of this XPath with where each node was written.
+    /// See the [`spanned`](spanned/index.html) module for how. There
+    /// are no spans when the XPath was made from a syntax tree rather
+    /// than parsed.
+    pub fn spanned(&self) -> Option<spanned::Spanned<'_>> {
+        self.spans.as_ref().map(|spans| spans.spanned(&self.ast))
+    }
+
+    /// The kind of value that evaluating this XPath gives, such as a
+    /// nodeset or a number, when it can be known without evaluating it.
+    ///
+    /// ```
+    /// use sxd_xpath::ast::ValueType;
+    /// use sxd_xpath::Factory;
+    ///
+    /// let factory = Factory::new();
+    /// let nodes = factory.build("//item[@price > 10]").expect("Could not compile XPath");
+    /// let total = factory.build("sum(//item/@price)").expect("Could not compile XPath");
+    ///
+    /// assert_eq!(ValueType::Nodeset, nodes.return_type());
+    /// assert_eq!(ValueType::Number, total.return_type());
+    /// ```
+    pub fn return_type(&self) -> ast::ValueType {
+        self.ast.return_type()
+    }
+
+    /// Estimates how expensive this XPath is to evaluate against
+    /// documents with the statistics. See the [`cost`](cost/index.html)
+    /// module for how.
+    pub fn cost(&self, statistics: &cost::Statistics) -> cost::Cost {
+        cost::estimate(&self.ast, statistics)
+    }
+
+    /// The syntax tree of this XPath as a graph in the DOT language,
+    /// as described in the [`dot`](dot/index.html) module.
+    pub fn to_dot(&self) -> String {
+        dot::render(&self.ast)
+    }
+
+    /// The syntax tree of this XPath as JSON, as described in the
+    /// [`json`](json/index.html) module.
+    pub fn to_json(&self) -> String {
+        json::render(&self.ast)
+    }
+
+    /// Whether the XPaths have the same syntax tree once normalized, as
+    /// described in the [`normalize`](normalize/index.html) module.
+    pub fn is_equivalent(&self, other: &XPath) -> bool {
+        normalize::equivalent(&self.ast, &other.ast)
+    }
+
+    /// The variables that this XPath refers to, in the order they first
+    /// appear. Variables bound inside the XPath, such as by `for` or
+    /// `let`, are not included.
+    ///
+    /// ```
+    /// use sxd_xpath::{Factory, OwnedPrefixedName};
+    ///
+    /// let factory = Factory::new();
+    /// let xpath = factory
+    ///     .build("for $i in $items return $i * $rate")
+    ///     .expect("Could not compile XPath");
+    ///
+    /// let items = OwnedPrefixedName::from("items");
+    /// let rate = OwnedPrefixedName::from("rate");
+    /// assert_eq!(vec![&items, &rate], xpath.variables());
+    /// ```
+    pub fn variables(&self) -> Vec<&OwnedPrefixedName> {
+        self.ast.variables()
+    }
+
+    /// The names of the functions that this XPath calls, in the order
+    /// they first appear.
+    pub fn functions(&self) -> Vec<&OwnedPrefixedName> {
+        self.ast.functions()
+    }
+
+    /// The namespace prefixes that this XPath uses, in the order they
+    /// first appear.
+    pub fn prefixes(&self) -> Vec<&str> {
+        self.ast.prefixes()
+    }
+
     /// Evaluate this expression in the given context.
     ///
     /// # Examples
@@ -369,33 +1055,494 @@ impl XPath {
     where
         N: Into<nodeset::Node<'d>>,
     {
-        let context = context::Evaluation::new(context, node.into());
-        self.0.evaluate(&context).map_err(ExecutionError)
-    }
-}
-
-/// The primary entrypoint to convert an XPath represented as a string
-/// to a structure that can be evaluated.
-pub struct Factory {
-    parser: Parser,
-}
-
-impl Factory {
-    pub fn new() -> Factory {
-        Factory {
-            parser: Parser::new(),
-        }
+        let key_indexes = key::Indexes::default();
+        let context = context::Evaluation::new(context, node.into()).with_key_indexes(&key_indexes);
+        self.expression.evaluate(&context).map_err(ExecutionError)
     }
 
-    /// Compiles the given string into an XPath structure.
-    pub fn build(&self, xpath: &str) -> Result<XPath, ParserError> {
-        let tokenizer = Tokenizer::new(xpath);
-        let deabbreviator = TokenDeabbreviator::new(tokenizer);
-
-        self.parser
-            .parse(deabbreviator)
-            .map(XPath)
-            .map_err(Into::into)
+    /// Evaluate this expression like [`evaluate`](#method.evaluate),
+    /// returning only the nodes or items that the options ask for.
+    ///
+    /// With a maximum number of results, a location path follows its
+    /// steps from one node at a time and stops looking for nodes once
+    /// it has found enough of them, as long as the axes of its steps
+    /// only select nodes after the nodes they start from, such as
+    /// `child` or `descendant`. Nodes that would come later, and the
+    /// predicates of the steps that lead to them, are then not
+    /// evaluated.
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{Context, EvaluationOptions, Factory, Value};
+    ///
+    /// let package = parser::parse("<list><item>a</item><item>b</item><item>c</item></list>")
+    ///     .expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let xpath = Factory::new().build("//item").expect("Could not compile XPath");
+    /// let mut options = EvaluationOptions::new();
+    /// options.set_skip(1);
+    /// options.set_max_results(Some(1));
+    ///
+    /// let page = xpath
+    ///     .evaluate_with_options(&Context::new(), document.root(), &options)
+    ///     .expect("XPath evaluation failed");
+    /// assert_eq!("b", page.string());
+    /// ```
+    pub fn evaluate_with_options<'d, N>(
+        &self,
+        context: &Context<'d>,
+        node: N,
+        options: &EvaluationOptions,
+    ) -> Result<Value<'d>, ExecutionError>
+    where
+        N: Into<nodeset::Node<'d>>,
+    {
+        let key_indexes = key::Indexes::default();
+        let context = context::Evaluation::new(context, node.into()).with_key_indexes(&key_indexes);
+        let value = match options.max_results {
+            Some(max_results) => {
+                let limit = options.skip.saturating_add(max_results);
+                self.expression.evaluate_limited(&context, limit)
+            }
+            None => self.expression.evaluate(&context),
+        };
+        value.map(|v| options.page(v)).map_err(ExecutionError)
+    }
+
+    /// Evaluate this expression like [`evaluate`](#method.evaluate),
+    /// returning only the first node of the result in document order.
+    ///
+    /// Location paths stop looking for nodes once that node is known,
+    /// under the same conditions as a maximum number of results given
+    /// to [`evaluate_with_options`](#method.evaluate_with_options).
+    /// This makes checking whether a node exists cheaper on large
+    /// documents. A value without nodes gives `None`.
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{Context, Factory};
+    ///
+    /// let package = parser::parse("<list><item>a</item><item>b</item></list>")
+    ///     .expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let xpath = Factory::new().build("//item").expect("Could not compile XPath");
+    /// let first = xpath
+    ///     .evaluate_first(&Context::new(), document.root())
+    ///     .expect("XPath evaluation failed");
+    /// assert_eq!(Some("a".to_owned()), first.map(|n| n.string_value()));
+    /// ```
+    pub fn evaluate_first<'d, N>(
+        &self,
+        context: &Context<'d>,
+        node: N,
+    ) -> Result<Option<nodeset::Node<'d>>, ExecutionError>
+    where
+        N: Into<nodeset::Node<'d>>,
+    {
+        let key_indexes = key::Indexes::default();
+        let context = context::Evaluation::new(context, node.into()).with_key_indexes(&key_indexes);
+        self.expression
+            .evaluate_first(&context)
+            .map_err(ExecutionError)
+    }
+
+    /// Evaluate this expression like [`evaluate`](#method.evaluate),
+    /// returning only its boolean value, as the `boolean()` function
+    /// would.
+    ///
+    /// The nodes of a location path or union are not collected: the
+    /// value is known once one node is found, as with
+    /// [`evaluate_first`](#method.evaluate_first). `and` and `or` do
+    /// not evaluate their right side when the left side decides the
+    /// value. This suits checking documents for nodes that should or
+    /// should not be there.
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{Context, Factory};
+    ///
+    /// let package = parser::parse("<log><error/><error fatal='yes'/></log>")
+    ///     .expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let xpath = Factory::new().build("//error[@fatal]").expect("Could not compile XPath");
+    /// let failed = xpath
+    ///     .evaluate_boolean(&Context::new(), document.root())
+    ///     .expect("XPath evaluation failed");
+    /// assert!(failed);
+    /// ```
+    pub fn evaluate_boolean<'d, N>(
+        &self,
+        context: &Context<'d>,
+        node: N,
+    ) -> Result<bool, ExecutionError>
+    where
+        N: Into<nodeset::Node<'d>>,
+    {
+        let key_indexes = key::Indexes::default();
+        let context = context::Evaluation::new(context, node.into()).with_key_indexes(&key_indexes);
+        self.expression
+            .evaluate_boolean(&context)
+            .map_err(ExecutionError)
+    }
+
+    /// Evaluate this expression like [`evaluate`](#method.evaluate)
+    /// and convert the value to the type that is asked for, as
+    /// described by [`FromValue`](trait.FromValue.html).
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{Context, Factory};
+    ///
+    /// let package = parser::parse("<price>12.50</price>").expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let xpath = Factory::new().build("/price").expect("Could not compile XPath");
+    /// let price: f64 = xpath
+    ///     .evaluate_as(&Context::new(), document.root())
+    ///     .expect("XPath evaluation failed");
+    /// assert_eq!(12.5, price);
+    /// ```
+    pub fn evaluate_as<'d, T, N>(&self, context: &Context<'d>, node: N) -> Result<T, Error>
+    where
+        T: FromValue<'d>,
+        N: Into<nodeset::Node<'d>>,
+    {
+        let value = self.evaluate(context, node).context(Executing)?;
+        T::from_value(value).context(Converting)
+    }
+
+    /// Evaluate this expression like [`evaluate`](#method.evaluate),
+    /// telling the debugger about each expression as it is evaluated.
+    /// See the [`debug`](debug/index.html) module for how.
+    pub fn evaluate_debug<'d, N, D>(
+        &self,
+        context: &Context<'d>,
+        node: N,
+        debugger: &mut D,
+    ) -> Result<Value<'d>, ExecutionError>
+    where
+        N: Into<nodeset::Node<'d>>,
+        D: debug::Debugger + ?Sized,
+    {
+        let expression = self.ast.compile_traced(true);
+        let tracer = debug::Tracer(RefCell::new(debugger));
+        let key_indexes = key::Indexes::default();
+        let context = context::Evaluation::new(context, node.into())
+            .with_key_indexes(&key_indexes)
+            .traced_by(&tracer);
+        expression.evaluate(&context).map_err(ExecutionError)
+    }
+
+    /// Evaluate this expression in the given context, loading the
+    /// documents that `document()` refers to with the resolver given
+    /// to [`Context::set_async_document_resolver`][].
+    ///
+    /// Evaluation itself does not wait: when `document()` refers to
+    /// documents that have not been loaded, they are awaited and the
+    /// expression is evaluated again. A document is only loaded once
+    /// for the same storage, so this ends when every document the
+    /// expression refers to has been loaded. Without an asynchronous
+    /// resolver, this is the same as [`evaluate`](#method.evaluate).
+    ///
+    /// The context is borrowed across the awaits and is not `Sync`, so
+    /// the future is not `Send`. With tokio, run it on a `LocalSet`.
+    ///
+    /// [`Context::set_async_document_resolver`]: context/struct.Context.html#method.set_async_document_resolver
+    pub async fn evaluate_async<'d, N>(
+        &self,
+        context: &Context<'d>,
+        node: N,
+    ) -> Result<Value<'d>, ExecutionError>
+    where
+        N: Into<nodeset::Node<'d>>,
+    {
+        let node = node.into();
+        let (resolver, documents) = match (context.async_document_resolver(), context.documents()) {
+            (Some(resolver), Some(documents)) => (resolver, documents),
+            _ => return self.evaluate(context, node),
+        };
+
+        let pending = RefCell::new(Vec::new());
+        let key_indexes = key::Indexes::default();
+        loop {
+            let evaluation = context::Evaluation::new(context, node)
+                .awaiting_documents(&pending)
+                .with_key_indexes(&key_indexes);
+            let result = self.expression.evaluate(&evaluation);
+
+            let uris = pending.take();
+            if uris.is_empty() {
+                return result.map_err(ExecutionError);
+            }
+            for uri in uris {
+                let package = resolver.resolve(&uri).await.map_err(|e| {
+                    ExecutionError(expression::Error::FunctionEvaluation {
+                        source: function::Error::DocumentNotLoaded {
+                            uri: uri.clone(),
+                            what: e.to_string(),
+                        },
+                    })
+                })?;
+                documents.insert(&uri, package);
+            }
+        }
+    }
+}
+
+/// Compiles a syntax tree, such as one rewritten by a
+/// [`Fold`](fold/trait.Fold.html), into an XPath.
+impl From<ast::Expr> for XPath {
+    fn from(ast: ast::Expr) -> XPath {
+        XPath::new(ast)
+    }
+}
+
+/// Writes the XPath in the canonical form of a
+/// [`Formatter`](format/struct.Formatter.html), which compiles to the
+/// same XPath again.
+///
+/// ```
+/// use sxd_xpath::Factory;
+///
+/// let factory = Factory::new();
+/// let xpath = factory.build("//item[@price>10]").expect("Could not compile XPath");
+/// let text = xpath.to_string();
+///
+/// assert_eq!("/descendant-or-self::node()/child::item[attribute::price > 10]", text);
+/// let again = factory.build(&text).expect("Could not compile XPath");
+/// assert_eq!(xpath.ast(), again.ast());
+/// ```
+impl fmt::Display for XPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.ast.fmt(f)
+    }
+}
+
+/// The primary entrypoint to convert an XPath represented as a string
+/// to a structure that can be evaluated.
+pub struct Factory {
+    parser: Parser,
+}
+
+impl Factory {
+    pub fn new() -> Factory {
+        Factory {
+            parser: Parser::new(),
+        }
+    }
+
+    /// Compiles the given string into an XPath structure.
+    pub fn build(&self, xpath: &str) -> Result<XPath, ParserError> {
+        self.build_with_pipeline(xpath, &mut [])
+    }
+
+    /// Compiles the XPath like [`build`](#method.build), after passing
+    /// its tokens through each of the transformers in turn. The
+    /// abbreviations of XPath are expanded after the transformers, so
+    /// they may produce abbreviations.
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::node_test::NameTest;
+    /// use sxd_xpath::token::Token;
+    /// use sxd_xpath::tokenizer::TokenTransformer;
+    /// use sxd_xpath::{Context, Factory, Value};
+    ///
+    /// /// Expands the `items` shorthand into `//item`.
+    /// struct Items;
+    ///
+    /// impl TokenTransformer for Items {
+    ///     fn transform(&mut self, token: Token) -> Vec<Token> {
+    ///         match token {
+    ///             Token::NameTest(ref name) if name.prefix.is_none() && name.local_part == "items" => {
+    ///                 let item = NameTest { prefix: None, local_part: "item".into() };
+    ///                 vec![Token::DoubleSlash, Token::NameTest(item)]
+    ///             }
+    ///             token => vec![token],
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let package = parser::parse("<root><item/><item/></root>").expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let xpath = Factory::new()
+    ///     .build_with_pipeline("count(items)", &mut [&mut Items])
+    ///     .expect("Could not compile XPath");
+    /// let value = xpath.evaluate(&Context::new(), document.root()).expect("XPath evaluation failed");
+    /// assert_eq!(Value::Number(2.0), value);
+    /// ```
+    pub fn build_with_pipeline(
+        &self,
+        xpath: &str,
+        transformers: &mut [&mut dyn TokenTransformer],
+    ) -> Result<XPath, ParserError> {
+        let mut tokenizer = Tokenizer::new(xpath);
+        let mut spans = Vec::new();
+        let tokens = tokenizer.with_spans();
+        let parsed = self.parser.parse_spanned(transform(
+            tokens,
+            |token| run_pipeline(transformers, token),
+            &mut spans,
+        ));
+
+        parsed
+            .map(|(ast, tokens)| {
+                XPath::parsed(ast, spanned::SpanTree::new(&tokens, &spans, xpath.len()))
+            })
+            .map_err(|(source, expected)| ParserError {
+                source,
+                span: tokenizer.span(),
+                expected,
+            })
+    }
+
+    /// Compiles the XPath like [`build`](#method.build), but also fails
+    /// when it refers to a function, variable, atomic type or namespace
+    /// prefix that the context does not have, which would otherwise
+    /// only fail when evaluated. The XPath can still be evaluated with
+    /// any context.
+    ///
+    /// ```
+    /// use sxd_xpath::{Context, Factory};
+    ///
+    /// let factory = Factory::new();
+    /// let mut context = Context::new();
+    /// context.set_variable("limit", 10.0);
+    ///
+    /// assert!(factory.build_checked("count(//item) > $limit", &context).is_ok());
+    ///
+    /// let error = factory
+    ///     .build_checked("count(//item) > $limt", &context)
+    ///     .expect_err("XPath should refer to an unknown variable");
+    /// assert_eq!(16, error.span().offset);
+    /// ```
+    pub fn build_checked(&self, xpath: &str, context: &Context<'_>) -> Result<XPath, ParserError> {
+        let built = self.build(xpath)?;
+
+        match built.ast().check_names(context) {
+            Ok(()) => Ok(built),
+            Err(source) => Err(ParserError {
+                span: span_of_name(xpath, &source),
+                source: parser::Error::UnknownName { source },
+                expected: Vec::new(),
+            }),
+        }
+    }
+
+    /// Compiles the XPath like [`build`](#method.build) and checks it
+    /// for constructs that are often mistakes or slow. See the
+    /// [`lint`](lint/index.html) module for the constructs.
+    pub fn lint(&self, xpath: &str) -> Result<Vec<lint::Lint>, ParserError> {
+        let built = self.build(xpath)?;
+        Ok(lint::lint(&self.parser, xpath, built.ast()))
+    }
+
+    /// Compiles as much of the XPath as possible, for tools that check
+    /// XPaths while they are being written. Text that cannot be made
+    /// into a token is skipped, brackets that are left open are closed,
+    /// and the tokens where the XPath cannot be parsed are removed until
+    /// the rest can be.
+    ///
+    /// Each error of the tokenizer is returned, and the first error of
+    /// the parser, as the later ones are often caused by it. There is
+    /// no XPath when no tokens are left.
+    ///
+    /// ```
+    /// use sxd_xpath::Factory;
+    ///
+    /// let (xpath, errors) = Factory::new().build_recovering("count(/shop/item[@price > ])");
+    ///
+    /// assert!(xpath.is_some());
+    /// assert_eq!(1, errors.len());
+    /// assert_eq!(26, errors[0].span().offset);
+    /// ```
+    pub fn build_recovering(&self, xpath: &str) -> (Option<XPath>, Vec<ParserError>) {
+        let mut errors = Vec::new();
+        let mut tokens = Vec::new();
+
+        let mut tokenizer = Tokenizer::new(xpath);
+        for (token, span) in tokenizer.with_spans() {
+            match token {
+                Ok(token) => tokens.push((token, span)),
+                Err(source) => errors.push(ParserError {
+                    source: parser::Error::Tokenizer { source },
+                    span,
+                    expected: Vec::new(),
+                }),
+            }
+        }
+        let end = tokenizer.span();
+
+        let mut first_error = true;
+        while !tokens.is_empty() {
+            // Once the first error is found, brackets that are still
+            // open are closed, as they are while the XPath is written
+            let closers = if first_error {
+                Vec::new()
+            } else {
+                unclosed_brackets(tokens.iter().map(|(token, _)| token))
+            };
+
+            let mut pulled = 0;
+            let source = iter::from_fn(|| {
+                let token = tokens
+                    .iter()
+                    .cloned()
+                    .chain(closers.iter().map(|token| (token.clone(), end)))
+                    .nth(pulled);
+                pulled += 1;
+                token.map(|(token, span)| (Ok(token), span))
+            });
+
+            let mut spans = Vec::new();
+            let (source, expected) = match self.parser.parse_spanned(transform(
+                source,
+                |token| run_pipeline(&mut [], token),
+                &mut spans,
+            )) {
+                Ok((ast, tokens)) => {
+                    let spans = spanned::SpanTree::new(&tokens, &spans, xpath.len());
+                    errors.sort_by_key(|e| e.span.offset);
+                    return (Some(XPath::parsed(ast, spans)), errors);
+                }
+                Err(failure) => failure,
+            };
+
+            // The parser stops at the token it could not use, or at the
+            // end when it needed more
+            let at_token = pulled <= tokens.len();
+            if first_error {
+                let span = if at_token { tokens[pulled - 1].1 } else { end };
+                errors.push(ParserError {
+                    source,
+                    span,
+                    expected,
+                });
+                first_error = false;
+            } else if at_token {
+                tokens.remove(pulled - 1);
+            } else {
+                tokens.pop();
+            }
+        }
+
+        if errors.is_empty() {
+            let (source, expected) = self
+                .parser
+                .parse_expecting(iter::empty())
+                .expect_err("Nothing should not be an XPath");
+            errors.push(ParserError {
+                source,
+                span: end,
+                expected,
+            });
+        }
+        errors.sort_by_key(|e| e.span.offset);
+        (None, errors)
     }
 }
 
@@ -405,14 +1552,224 @@ impl Default for Factory {
     }
 }
 
+/// Transforms each token, recording the span of each token given. The
+/// tokens that a token is transformed into have its span.
+fn transform<'a, I, F>(
+    tokens: I,
+    mut transform_token: F,
+    spans: &'a mut Vec<Span>,
+) -> impl Iterator<Item = TokenResult> + 'a
+where
+    I: Iterator<Item = (TokenResult, Span)> + 'a,
+    F: FnMut(Token) -> Vec<Token> + 'a,
+{
+    tokens
+        .flat_map(move |(token, span)| {
+            let tokens = match token {
+                Ok(token) => transform_token(token).into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            tokens.into_iter().map(move |token| (token, span))
+        })
+        .map(move |(token, span)| {
+            spans.push(span);
+            token
+        })
+}
+
+/// Passes the token through the transformers and expands the
+/// abbreviations of the result.
+fn run_pipeline(transformers: &mut [&mut dyn TokenTransformer], token: Token) -> Vec<Token> {
+    let mut tokens = vec![token];
+    for transformer in transformers.iter_mut() {
+        tokens = tokens
+            .into_iter()
+            .flat_map(|token| transformer.transform(token))
+            .collect();
+    }
+    tokens
+        .into_iter()
+        .flat_map(|token| Deabbreviator.transform(token))
+        .collect()
+}
+
+/// The span of the first token with the unknown name, or of the whole
+/// XPath if there is none.
+fn span_of_name(xpath: &str, error: &expression::Error) -> Span {
+    use crate::expression::Error::{
+        UnknownFunction, UnknownNamespace, UnknownType, UnknownVariable,
+    };
+
+    let is_name = |token: &Token| match (error, token) {
+        (UnknownFunction { name }, Token::Function(n))
+        | (UnknownVariable { name }, Token::Variable(n)) => name == n,
+        (UnknownType { name }, Token::NameTest(n)) => {
+            name.prefix == n.prefix && name.local_part == n.local_part
+        }
+        (UnknownNamespace { prefix }, Token::Function(n))
+        | (UnknownNamespace { prefix }, Token::Variable(n)) => n.prefix.as_ref() == Some(prefix),
+        (UnknownNamespace { prefix }, Token::NameTest(n)) => n.prefix.as_ref() == Some(prefix),
+        _ => false,
+    };
+
+    Tokenizer::new(xpath)
+        .with_spans()
+        .find(|(token, _)| token.as_ref().is_ok_and(is_name))
+        .map_or(
+            Span {
+                offset: 0,
+                length: xpath.len(),
+            },
+            |(_, span)| span,
+        )
+}
+
+/// The tokens that close the brackets left open, innermost first.
+fn unclosed_brackets<'a, I>(tokens: I) -> Vec<Token>
+where
+    I: IntoIterator<Item = &'a Token>,
+{
+    let mut closers = Vec::new();
+    for token in tokens {
+        match *token {
+            Token::LeftParen => closers.push(Token::RightParen),
+            Token::LeftBracket => closers.push(Token::RightBracket),
+            Token::LeftBrace => closers.push(Token::RightBrace),
+            ref token if closers.last() == Some(token) => {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+    closers.reverse();
+    closers
+}
+
 /// Errors that may occur when parsing an XPath
-#[derive(Debug, Snafu, Clone, PartialEq)]
-pub struct ParserError(parser::Error);
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParserError {
+    source: parser::Error,
+    span: Span,
+    expected: Vec<parser::Expected>,
+}
+
+impl ParserError {
+    /// What was wrong with the XPath.
+    pub fn kind(&self) -> &parser::Error {
+        &self.source
+    }
+
+    /// The part of the XPath where the error was found: the token that
+    /// could not be used, the text that could not be made into a token,
+    /// or the empty span at the end when the XPath ended too soon.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The tokens that could have been used at the span, such as to
+    /// suggest how the XPath could continue. This is empty when the
+    /// error is in text that could not be made into a token.
+    ///
+    /// ```
+    /// use sxd_xpath::parser::Expected;
+    /// use sxd_xpath::token::Token;
+    /// use sxd_xpath::Factory;
+    ///
+    /// let error = Factory::new().build("count(a b)").expect_err("XPath should not parse");
+    ///
+    /// assert!(error.expected().contains(&Expected::Token(Token::Comma)));
+    /// assert!(error.expected().contains(&Expected::Token(Token::RightParen)));
+    /// ```
+    pub fn expected(&self) -> &[parser::Expected] {
+        &self.expected
+    }
+
+    /// Describes the error for people, showing the line of the XPath
+    /// that has the error with its span marked underneath.
+    ///
+    /// ```
+    /// use sxd_xpath::Factory;
+    ///
+    /// let xpath = "/shop/item[@price > ]";
+    /// let error = Factory::new().build(xpath).expect_err("XPath should not parse");
+    ///
+    /// let expected = [
+    ///     "right hand side of expression is missing at line 1, column 21",
+    ///     "/shop/item[@price > ]",
+    ///     "                    ^",
+    /// ];
+    /// assert_eq!(expected.join("\n"), error.render(xpath));
+    /// ```
+    ///
+    /// The XPath should be the one that was built.
+    pub fn render(&self, xpath: &str) -> String {
+        let mut offset = self.span.offset.min(xpath.len());
+        while !xpath.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line_start = xpath[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = xpath[offset..]
+            .find('\n')
+            .map_or(xpath.len(), |i| offset + i);
+        let line = xpath[line_start..line_end].trim_end_matches('\r');
+        let before = &xpath[line_start..offset];
+
+        let line_number = xpath[..line_start].matches('\n').count() + 1;
+        let column = before.chars().count() + 1;
+
+        // Tabs are kept so that the marks line up with the line
+        let padding: String = before
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let end = (offset + self.span.length).min(line_start + line.len());
+        let marks = xpath
+            .get(offset..end)
+            .map_or(0, |marked| marked.chars().count())
+            .max(1);
+
+        format!(
+            "{} at line {}, column {}\n{}\n{}{}",
+            self.source,
+            line_number,
+            column,
+            line,
+            padding,
+            "^".repeat(marks)
+        )
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.source, self.span.offset)
+    }
+}
+
+impl std::error::Error for ParserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
 
 /// Errors that may occur when executing an XPath
 #[derive(Debug, Snafu, Clone, PartialEq)]
 pub struct ExecutionError(expression::Error);
 
+/// The error when a [`Value`][] is converted with `TryFrom` to a type
+/// that it does not hold.
+///
+/// [`Value`]: enum.Value.html
+#[derive(Debug, Snafu, Clone, PartialEq)]
+pub enum ConversionError {
+    #[snafu(display("expected a {:?} but the value was a {:?}", expected, actual))]
+    UnexpectedType {
+        expected: ArgumentType,
+        actual: ArgumentType,
+    },
+}
+
 /// The failure modes of executing an XPath.
 #[derive(Debug, Snafu, Clone, PartialEq)]
 pub enum Error {
@@ -422,6 +1779,9 @@ pub enum Error {
     /// The XPath could not be executed
     #[snafu(display("Unable to execute XPath: {}", source))]
     Executing { source: ExecutionError },
+    /// The value of the XPath was not of the type asked for
+    #[snafu(display("Unable to convert the value of the XPath: {}", source))]
+    Converting { source: ConversionError },
 }
 
 /// Easily evaluate an XPath expression
@@ -457,13 +1817,43 @@ pub fn evaluate_xpath<'d>(document: &'d Document<'d>, xpath: &str) -> Result<Val
         .context(Executing)
 }
 
+/// Evaluate an XPath expression like [`evaluate_xpath`][] and convert
+/// the value to the type that is asked for, as described by
+/// [`FromValue`][].
+///
+/// ```
+/// use sxd_document::parser;
+/// use sxd_xpath::evaluate_xpath_as;
+///
+/// let package = parser::parse("<root><a>1</a><b>2</b></root>").expect("failed to parse the XML");
+/// let document = package.as_document();
+///
+/// assert_eq!(Ok("1".to_owned()), evaluate_xpath_as::<String>(&document, "/root/*"));
+/// assert_eq!(Ok(true), evaluate_xpath_as(&document, "/root/b"));
+/// ```
+///
+/// [`evaluate_xpath`]: fn.evaluate_xpath.html
+/// [`FromValue`]: trait.FromValue.html
+pub fn evaluate_xpath_as<'d, T>(document: &'d Document<'d>, xpath: &str) -> Result<T, Error>
+where
+    T: FromValue<'d>,
+{
+    let value = evaluate_xpath(document, xpath)?;
+    T::from_value(value).context(Converting)
+}
+
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
+    use std::future::{self, Future};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::task::{self, Poll, Wake, Waker};
 
     use sxd_document::{self, dom, Package};
 
     use super::*;
+    use crate::resolver::{AsyncDocumentResolver, ResolveFuture};
 
     #[test]
     fn number_of_string_is_ieee_754_number() {
@@ -489,6 +1879,28 @@ mod test {
         assert!(v.number().is_nan());
     }
 
+    #[test]
+    fn sequence_of_one_atomic_value_has_its_boolean_value() {
+        let v = Value::Sequence(vec![Item::Number(0.0)]);
+        assert!(!v.boolean());
+
+        let v = Value::Sequence(vec![Item::Number(0.0), Item::Number(0.0)]);
+        assert!(v.boolean());
+
+        assert!(!Value::Sequence(vec![]).boolean());
+    }
+
+    #[test]
+    fn sequence_is_converted_by_its_first_item() {
+        let v = Value::Sequence(vec![Item::String("1.5".to_owned()), Item::Boolean(true)]);
+        assert_eq!(1.5, v.number());
+        assert_eq!("1.5", v.string());
+
+        let v = Value::Sequence(vec![]);
+        assert!(v.number().is_nan());
+        assert_eq!("", v.string());
+    }
+
     #[test]
     fn number_of_boolean_true_is_1() {
         let v = Value::Boolean(true);
@@ -529,7 +1941,7 @@ mod test {
 
     #[test]
     fn string_of_nan_is_nan() {
-        let v = Value::Number(::std::f64::NAN);
+        let v = Value::Number(f64::NAN);
         assert_eq!("NaN", v.string());
     }
 
@@ -547,13 +1959,13 @@ mod test {
 
     #[test]
     fn string_of_positive_infinity_is_infinity() {
-        let v = Value::Number(::std::f64::INFINITY);
+        let v = Value::Number(f64::INFINITY);
         assert_eq!("Infinity", v.string());
     }
 
     #[test]
     fn string_of_negative_infinity_is_minus_infinity() {
-        let v = Value::Number(::std::f64::NEG_INFINITY);
+        let v = Value::Number(f64::NEG_INFINITY);
         assert_eq!("-Infinity", v.string());
     }
 
@@ -569,6 +1981,185 @@ mod test {
         assert_eq!("1.2", v.string());
     }
 
+    #[test]
+    fn values_convert_to_the_type_they_hold() {
+        let package = sxd_document::parser::parse("<a><b/>1</a>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let value = |xpath| evaluate_xpath(&doc, xpath).expect("XPath evaluation failed");
+
+        assert_eq!(Ok(true), bool::try_from(value("true()")));
+        assert_eq!(Ok(3.0), f64::try_from(value("1 + 2")));
+        assert_eq!(Ok(3.0), f64::try_from(value("(3)")));
+        assert_eq!(Ok("x".to_owned()), String::try_from(value("'x'")));
+        assert_eq!(
+            Ok(2),
+            nodeset::Nodeset::try_from(value("(/a/b, /a)")).map(|n| n.size())
+        );
+    }
+
+    #[test]
+    fn values_of_another_type_do_not_convert() {
+        let package = sxd_document::parser::parse("<a>1</a>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let value = |xpath| evaluate_xpath(&doc, xpath).expect("XPath evaluation failed");
+
+        assert_eq!(
+            Err(ConversionError::UnexpectedType {
+                expected: ArgumentType::Number,
+                actual: ArgumentType::Nodeset,
+            }),
+            f64::try_from(value("/a"))
+        );
+        assert!(f64::try_from(value("(1, 2)")).is_err());
+        assert!(bool::try_from(value("()")).is_err());
+        assert!(String::try_from(value("1")).is_err());
+        assert!(nodeset::Nodeset::try_from(value("(/a, 1)")).is_err());
+    }
+
+    #[test]
+    fn nodeset_values_can_be_built_from_nodes() {
+        let package = sxd_document::parser::parse("<a><b/><c/></a>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let value = evaluate_xpath(&doc, "/a/*").expect("XPath evaluation failed");
+
+        let collected: Value<'_> = value
+            .iter()
+            .filter(|n| n.string_value().is_empty())
+            .collect();
+        assert_eq!(value, collected);
+        assert_eq!(value, Value::from(value.iter().rev().collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn iterating_a_value_gives_its_nodes_in_document_order() {
+        let package = sxd_document::parser::parse("<a><b>1</b><b>2</b><c>3</c></a>")
+            .expect("failed to parse XML");
+        let doc = package.as_document();
+
+        let value = evaluate_xpath(&doc, "//c | //b").expect("XPath evaluation failed");
+        let text: Vec<_> = (&value).into_iter().map(|n| n.string_value()).collect();
+        assert_eq!(vec!["1", "2", "3"], text);
+
+        let mut count = 0;
+        for _node in value {
+            count += 1;
+        }
+        assert_eq!(3, count);
+    }
+
+    #[test]
+    fn iterating_a_sequence_skips_its_atomic_values() {
+        let package = sxd_document::parser::parse("<a><b>1</b></a>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let b = evaluate_xpath(&doc, "//b")
+            .expect("XPath evaluation failed")
+            .iter()
+            .next()
+            .expect("no node found");
+
+        let value = Value::Sequence(vec![Item::Number(1.0), Item::Node(b), Item::Boolean(true)]);
+        assert_eq!(vec![b], value.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iterating_an_atomic_value_gives_no_nodes() {
+        assert_eq!(0, Value::Number(1.0).iter().count());
+        assert_eq!(0, Value::String("a".to_owned()).into_iter().count());
+    }
+
+    #[test]
+    fn arithmetic_converts_values_to_numbers() {
+        let package =
+            sxd_document::parser::parse("<a><b>4</b><b>9</b></a>").expect("failed to parse XML");
+        let doc = package.as_document();
+        let b = evaluate_xpath(&doc, "//b").expect("XPath evaluation failed");
+
+        assert_eq!(Ok(Value::Number(5.0)), b.add(&Value::Boolean(true)));
+        assert_eq!(
+            Ok(Value::Number(-1.0)),
+            Value::Number(1.0).subtract(&Value::String("2".into()))
+        );
+        assert_eq!(Ok(Value::Number(8.0)), b.multiply(&Value::Number(2.0)));
+        assert_eq!(
+            Ok(Value::Number(-1.0)),
+            Value::Number(-7.0).remainder(&Value::Number(2.0))
+        );
+        assert_eq!(
+            Ok(Value::Number(f64::NEG_INFINITY)),
+            Value::Number(-1.0).divide(&Value::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn arithmetic_propagates_nan() {
+        let nan = Value::Number(1.0)
+            .add(&Value::String("x".into()))
+            .and_then(|v| v.multiply(&Value::Number(0.0)))
+            .expect("numbers can always be multiplied");
+        assert!(nan.number().is_nan());
+        assert!(Value::Number(0.0)
+            .divide(&Value::Number(0.0))
+            .expect("numbers can always be divided")
+            .number()
+            .is_nan());
+    }
+
+    #[test]
+    fn arithmetic_on_dates_follows_xpath_2() {
+        let date = datetime::DateTime::parse("2024-01-31", datetime::DateTimeKind::Date);
+        let day = datetime::Duration::parse("P1D", datetime::DurationKind::DayTime);
+        let date = Value::from(Item::DateTime(date.expect("not a date")));
+        let day = Value::from(Item::Duration(day.expect("not a duration")));
+
+        assert_eq!(
+            "2024-02-01",
+            date.add(&day).expect("dates can be moved").string()
+        );
+        assert!(date.add(&date).is_err());
+    }
+
+    #[test]
+    fn values_are_evaluated_as_the_type_asked_for() {
+        let package =
+            sxd_document::parser::parse("<a><b>2</b><b>x</b></a>").expect("failed to parse XML");
+        let doc = package.as_document();
+
+        assert_eq!(Ok(2.0), evaluate_xpath_as(&doc, "//b"));
+        assert_eq!(Ok("2".to_owned()), evaluate_xpath_as(&doc, "//b"));
+        assert_eq!(Ok(false), evaluate_xpath_as(&doc, "//c"));
+        assert_eq!(
+            Ok(2),
+            evaluate_xpath_as::<nodeset::Nodeset<'_>>(&doc, "//b").map(|n| n.size())
+        );
+        assert_eq!(
+            Err(Error::Converting {
+                source: ConversionError::UnexpectedType {
+                    expected: ArgumentType::Nodeset,
+                    actual: ArgumentType::Number,
+                }
+            }),
+            evaluate_xpath_as::<nodeset::Nodeset<'_>>(&doc, "count(//b)")
+        );
+        assert!(matches!(
+            evaluate_xpath_as::<f64>(&doc, "$missing"),
+            Err(Error::Executing { .. })
+        ));
+    }
+
+    #[test]
+    fn written_string_is_the_same_as_the_string() {
+        let package = sxd_document::parser::parse("<a>one<b>two</b><!--c-->three</a>")
+            .expect("failed to parse XML");
+        let doc = package.as_document();
+
+        for xpath in &["/a", "(/a/b, 1)", "/a/missing", "'x'", "1 div 0", "true()"] {
+            let value = evaluate_xpath(&doc, xpath).expect("XPath evaluation failed");
+            let mut written = Vec::new();
+            value.write_string(&mut written).expect("Unable to write");
+            assert_eq!(value.string().into_bytes(), written, "{}", xpath);
+        }
+    }
+
     #[test]
     fn string_of_nodeset_is_string_value_of_first_node_in_document_order() {
         let package = Package::new();
@@ -600,17 +2191,222 @@ mod test {
         });
     }
 
+    #[test]
+    fn displayed_xpaths_compile_to_the_same_xpath() {
+        let factory = Factory::new();
+        let xpaths = [
+            "count(//a[@b != \"it's\"]) div 2",
+            "for $i in 1 to 3 return $i * -$i",
+            "(a | b)[last()]/text()",
+        ];
+        for xpath in xpaths.iter() {
+            let built = factory.build(xpath).expect("Unable to build XPath");
+            let again = factory
+                .build(&built.to_string())
+                .expect("Unable to build displayed XPath");
+            assert_eq!(built.ast(), again.ast(), "{}", xpath);
+        }
+
+        let built = XPath::from(ast::Expr::Literal(Value::String("'\"".into())));
+        assert_eq!("concat('', \"'\", '\"')", built.to_string());
+    }
+
+    struct Rename<'a>(&'a str, &'a str);
+
+    impl TokenTransformer for Rename<'_> {
+        fn transform(&mut self, token: Token) -> Vec<Token> {
+            match token {
+                Token::Variable(ref name) if name.local_part() == self.0 => {
+                    vec![Token::Variable(self.1.into())]
+                }
+                token => vec![token],
+            }
+        }
+    }
+
+    struct Remove(Token);
+
+    impl TokenTransformer for Remove {
+        fn transform(&mut self, token: Token) -> Vec<Token> {
+            if token == self.0 {
+                Vec::new()
+            } else {
+                vec![token]
+            }
+        }
+    }
+
+    #[test]
+    fn pipelines_transform_tokens_in_order_before_abbreviations() {
+        let factory = Factory::new();
+
+        let built = factory
+            .build_with_pipeline(
+                "$a + $b",
+                &mut [&mut Rename("a", "b"), &mut Rename("b", "c")],
+            )
+            .expect("Unable to build XPath");
+        assert_eq!(factory.build("$c + $c").unwrap().ast(), built.ast());
+
+        let built = factory
+            .build_with_pipeline("count(/a//b)", &mut [&mut Remove(Token::Slash)])
+            .expect("Unable to build XPath");
+        assert_eq!(factory.build("count(a//b)").unwrap().ast(), built.ast());
+    }
+
+    #[test]
+    fn transformed_tokens_have_the_span_of_the_token_they_replace() {
+        let xpath = "$a + 1";
+        let built = Factory::new()
+            .build_with_pipeline(xpath, &mut [&mut Rename("a", "longer")])
+            .expect("Unable to build XPath");
+        let spanned = built.spanned().expect("Parsed XPaths have spans");
+
+        assert_eq!("$a", &xpath[spanned.children[0].span.range()]);
+    }
+
     #[test]
     fn xpath_evaluation_parsing_error() {
         with_document("<root><child>content</child></root>", |doc| {
             let result = evaluate_xpath(&doc, "/root/child/");
 
-            let expected_error = crate::parser::TrailingSlash
-                .fail()
-                .map_err(ParserError::from)
-                .context(Parsing);
-            assert_eq!(expected_error, result);
+            match result {
+                Err(Error::Parsing { source }) => {
+                    assert_eq!(&crate::parser::Error::TrailingSlash, source.kind());
+                    assert_eq!(12, source.span().offset);
+                }
+                other => panic!("expected a parsing error, was {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn parser_errors_locate_the_offending_token() {
+        let factory = Factory::new();
+        let span = |xpath| {
+            let error = factory.build(xpath).expect_err("XPath should not parse");
+            &xpath[error.span().range()]
+        };
+
+        assert_eq!("]", span("/a[1 = ]"));
+        assert_eq!("b", span("count(a) b"));
+        assert_eq!("'x)", span("concat('a', 'x)"));
+        assert_eq!("", span("1 + "));
+
+        let error = factory.build("a[]").expect_err("XPath should not parse");
+        assert_eq!(&crate::parser::Error::EmptyPredicate, error.kind());
+        assert_eq!("empty predicate at byte 2", error.to_string());
+    }
+
+    #[test]
+    fn rendered_parser_errors_mark_the_span_on_its_line() {
+        let xpath = "/a\r\n\t[@é = 'x\n]";
+        let error = Factory::new()
+            .build(xpath)
+            .expect_err("XPath should not parse");
+
+        let expected = [
+            "right hand side of expression is missing at line 2, column 8",
+            "\t[@é = 'x",
+            "\t      ^^",
+        ];
+        assert_eq!(expected.join("\n"), error.render(xpath));
+    }
+
+    #[test]
+    fn rendered_parser_errors_mark_the_end_when_the_xpath_ends_too_soon() {
+        let xpath = "1 +";
+        let error = Factory::new()
+            .build(xpath)
+            .expect_err("XPath should not parse");
+
+        let expected = [
+            "right hand side of expression is missing at line 1, column 4",
+            "1 +",
+            "   ^",
+        ];
+        assert_eq!(expected.join("\n"), error.render(xpath));
+    }
+
+    fn recover(xpath: &str) -> (Option<string::String>, Vec<(parser::Error, usize)>) {
+        let (built, errors) = Factory::new().build_recovering(xpath);
+        let value = built.map(|built| {
+            let mut value = None;
+            with_document("<a><b>1</b><b>2</b></a>", |doc| {
+                let evaluated = built.evaluate(&Context::new(), doc.root());
+                value = Some(evaluated.expect("Unable to evaluate XPath").string());
+            });
+            value.expect("XPath was not evaluated")
         });
+        let errors = errors
+            .into_iter()
+            .map(|e| (e.kind().clone(), e.span().offset))
+            .collect();
+        (value, errors)
+    }
+
+    #[test]
+    fn recovering_builds_of_valid_xpaths_have_no_errors() {
+        assert_eq!((Some("3".to_owned()), vec![]), recover("1 + 2"));
+    }
+
+    #[test]
+    fn recovering_builds_skip_text_that_is_not_a_token() {
+        let (value, errors) = recover("1 + ! 2 ~");
+
+        assert_eq!(Some("3".to_owned()), value);
+        let unable = parser::Error::Tokenizer {
+            source: tokenizer::Error::UnableToCreateToken,
+        };
+        assert_eq!(vec![(unable.clone(), 4), (unable, 8)], errors);
+    }
+
+    #[test]
+    fn recovering_builds_remove_tokens_that_cannot_be_parsed() {
+        let (value, errors) = recover("count(/a/b[ = ])");
+        assert_eq!(Some("2".to_owned()), value);
+        assert_eq!(vec![(parser::Error::EmptyPredicate, 12)], errors);
+
+        let (value, errors) = recover("string(/a/b[2] ]");
+        assert_eq!(Some("2".to_owned()), value);
+        let unexpected = parser::Error::UnexpectedToken {
+            token: Token::RightBracket,
+        };
+        assert_eq!(vec![(unexpected, 15)], errors);
+    }
+
+    #[test]
+    fn recovering_builds_close_open_brackets() {
+        let (value, errors) = recover("sum(/a/b[. > 1");
+
+        assert_eq!(Some("2".to_owned()), value);
+        assert_eq!(vec![(parser::Error::RanOutOfInput, 14)], errors);
+    }
+
+    #[test]
+    fn recovering_builds_without_tokens_have_no_xpath() {
+        assert_eq!((None, vec![(parser::Error::NoXPath, 6)]), recover("(: :) "));
+
+        let (value, errors) = recover("]");
+        assert_eq!(None, value);
+        assert_eq!(1, errors.len());
+    }
+
+    #[test]
+    fn checked_builds_locate_the_unknown_name() {
+        let factory = Factory::new();
+        let context = Context::new();
+        let span = |xpath| {
+            let error = factory
+                .build_checked(xpath, &context)
+                .expect_err("XPath should refer to an unknown name");
+            &xpath[error.span().range()]
+        };
+
+        assert_eq!("$y", span("for $x in 1 return $y"));
+        assert_eq!("p:f", span("count(/a) + p:f()"));
+        assert_eq!("x:b", span("/a/x:b"));
+        assert_eq!("xs:text", span("'1' cast as xs:text"));
     }
 
     #[test]
@@ -625,4 +2421,102 @@ mod test {
             assert_eq!(expected_error, result);
         });
     }
+
+    /// Runs the future on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Ignore;
+        impl Wake for Ignore {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let waker = Waker::from(Arc::new(Ignore));
+        let mut cx = task::Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+                return value;
+            }
+        }
+    }
+
+    /// Waits once before giving a document that links to the next
+    /// number, counting the documents it was asked for.
+    struct Linked(Rc<RefCell<Vec<String>>>);
+
+    impl AsyncDocumentResolver for Linked {
+        fn resolve<'a>(&'a self, uri: &'a str) -> ResolveFuture<'a> {
+            Box::pin(async move {
+                self.0.borrow_mut().push(uri.to_owned());
+                let mut waited = false;
+                future::poll_fn(|_| {
+                    if waited {
+                        Poll::Ready(())
+                    } else {
+                        waited = true;
+                        Poll::Pending
+                    }
+                })
+                .await;
+
+                let n: u32 = uri.trim_start_matches("urn:").parse()?;
+                let xml = format!("<doc n='{}' next='urn:{}'/>", n, n + 1);
+                Ok(sxd_document::parser::parse(&xml)?)
+            })
+        }
+    }
+
+    #[test]
+    fn evaluate_async_awaits_the_documents() {
+        with_document("<root/>", |doc| {
+            let fragments = fragment::Fragments::new();
+            let mut context = Context::new();
+            context.set_fragments(&fragments);
+            let loads = Rc::new(RefCell::new(Vec::new()));
+            context.set_async_document_resolver(Linked(Rc::clone(&loads)));
+
+            let xpath = Factory::new()
+                .build("document(document('urn:1')/doc/@next)/doc/@n + document('urn:1')/doc/@n")
+                .expect("Could not compile");
+            let result = block_on(xpath.evaluate_async(&context, doc.root()));
+
+            assert_eq!(Ok(Value::Number(3.0)), result);
+            assert_eq!(vec!["urn:1", "urn:2"], *loads.borrow());
+        });
+    }
+
+    #[test]
+    fn evaluate_async_reports_documents_that_cannot_be_loaded() {
+        with_document("<root/>", |doc| {
+            let fragments = fragment::Fragments::new();
+            let mut context = Context::new();
+            context.set_fragments(&fragments);
+            context.set_async_document_resolver(Linked(Default::default()));
+
+            let xpath = Factory::new()
+                .build("document('urn:x')")
+                .expect("Could not compile");
+            let result = block_on(xpath.evaluate_async(&context, doc.root()));
+
+            let expected = ExecutionError(expression::Error::FunctionEvaluation {
+                source: function::Error::DocumentNotLoaded {
+                    uri: "urn:x".to_owned(),
+                    what: "invalid digit found in string".to_owned(),
+                },
+            });
+            assert_eq!(Err(expected), result);
+        });
+    }
+
+    #[test]
+    fn evaluate_async_without_a_resolver_evaluates_directly() {
+        with_document("<root><child>content</child></root>", |doc| {
+            let context = Context::new();
+            let xpath = Factory::new()
+                .build("/root/child")
+                .expect("Could not compile");
+
+            let result = block_on(xpath.evaluate_async(&context, doc.root()));
+            assert_eq!(xpath.evaluate(&context, doc.root()), result);
+        });
+    }
 }
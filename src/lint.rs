@@ -0,0 +1,398 @@
+//! Checks XPaths for constructs that are often mistakes or slow.
+//!
+//! [`Factory::lint`][] compiles an XPath and reports each suspicious
+//! construct as a [`Lint`][], whose [`LintCode`][] can be used to
+//! filter or count the findings.
+//!
+//! ```
+//! use sxd_xpath::lint::LintCode;
+//! use sxd_xpath::Factory;
+//!
+//! let lints = Factory::new()
+//!     .lint("//item[@price > (10)]")
+//!     .expect("Could not compile XPath");
+//!
+//! let codes: Vec<_> = lints.iter().map(|lint| lint.code.as_str()).collect();
+//! assert_eq!(vec!["descendant-from-root", "redundant-parentheses"], codes);
+//! ```
+//!
+//! The constructs are found without a document, so a lint may point
+//! at something that is intended, such as `//` on a small document.
+//!
+//! [`Factory::lint`]: ../struct.Factory.html#method.lint
+//! [`Lint`]: struct.Lint.html
+//! [`LintCode`]: enum.LintCode.html
+
+use std::fmt;
+
+use crate::ast::{self, BinaryOperator, Expr, LookupKey, ValueType};
+use crate::parser::Parser;
+use crate::token::Token;
+use crate::tokenizer::{Span, TokenDeabbreviator, Tokenizer};
+
+/// The kinds of construct that are reported.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LintCode {
+    /// A path that starts with `//`, which visits every node of the
+    /// document
+    DescendantFromRoot,
+    /// A predicate that does not depend on the node it tests, so it
+    /// keeps all of the nodes or none of them
+    ConstantPredicate,
+    /// `=` or `!=` between two nodesets, which is true when any pair of
+    /// their nodes compare that way
+    NodesetComparison,
+    /// Parentheses that do not change the meaning of the XPath
+    RedundantParentheses,
+}
+
+impl LintCode {
+    /// The name of the code, which does not change between versions.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LintCode::DescendantFromRoot => "descendant-from-root",
+            LintCode::ConstantPredicate => "constant-predicate",
+            LintCode::NodesetComparison => "nodeset-comparison",
+            LintCode::RedundantParentheses => "redundant-parentheses",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            LintCode::DescendantFromRoot => "path searches the entire document",
+            LintCode::ConstantPredicate => "predicate does not depend on the node",
+            LintCode::NodesetComparison => "comparison is true if any pair of nodes matches",
+            LintCode::RedundantParentheses => "parentheses are not needed",
+        }
+    }
+}
+
+impl fmt::Display for LintCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A suspicious construct of an XPath.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub code: LintCode,
+    /// Where the construct is, when it can be located
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.code.description())?;
+        if let Some(span) = self.span {
+            write!(f, " at byte {}", span.offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// The lints of the XPath, which has been parsed into the expression.
+/// Lints that can be located come first, in the order they appear.
+pub(crate) fn lint(parser: &Parser, xpath: &str, expr: &Expr) -> Vec<Lint> {
+    let tokens: Vec<_> = Tokenizer::new(xpath)
+        .with_spans()
+        .filter_map(|(token, span)| token.ok().map(|token| (token, span)))
+        .collect();
+
+    let mut lints = Vec::new();
+    lint_descendants_from_root(&tokens, &mut lints);
+    lint_parentheses(parser, &tokens, expr, &mut lints);
+    lints.sort_by_key(|lint| lint.span.map(|span| span.offset));
+
+    lint_expr(expr, &mut lints);
+    lints
+}
+
+/// `//` starts a path when nothing that it could continue comes before.
+fn lint_descendants_from_root(tokens: &[(Token, Span)], lints: &mut Vec<Lint>) {
+    let mut previous: Option<&Token> = None;
+    for (token, span) in tokens {
+        let starts_path = previous.is_none_or(|p| p.is_operator() || p.precedes_expression());
+        if *token == Token::DoubleSlash && starts_path {
+            lints.push(Lint {
+                code: LintCode::DescendantFromRoot,
+                span: Some(*span),
+            });
+        }
+        previous = Some(token);
+    }
+}
+
+/// Parentheses are redundant when the XPath without them has the same
+/// syntax tree. Parentheses that belong to a function call or a type
+/// cannot be removed without breaking the XPath.
+fn lint_parentheses(parser: &Parser, tokens: &[(Token, Span)], expr: &Expr, lints: &mut Vec<Lint>) {
+    let mut open = Vec::new();
+    for (close, (token, _)) in tokens.iter().enumerate() {
+        match *token {
+            Token::LeftParen => open.push(close),
+            Token::RightParen => {
+                let start = match open.pop() {
+                    Some(start) => start,
+                    None => continue,
+                };
+                if close == start + 1 {
+                    continue;
+                }
+
+                let without = tokens
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| i != start && i != close)
+                    .map(|(_, (token, _))| Ok(token.clone()));
+                let parsed = parser.parse(TokenDeabbreviator::new(without));
+
+                if parsed.as_ref() == Ok(expr) {
+                    let offset = tokens[start].1.offset;
+                    let end = tokens[close].1.range().end;
+                    lints.push(Lint {
+                        code: LintCode::RedundantParentheses,
+                        span: Some(Span {
+                            offset,
+                            length: end - offset,
+                        }),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn lint_expr(expr: &Expr, lints: &mut Vec<Lint>) {
+    match *expr {
+        Expr::ContextNode | Expr::RootNode | Expr::Literal(_) | Expr::Variable(_) => {}
+        Expr::Function { ref arguments, .. } => {
+            for argument in arguments {
+                lint_expr(argument, lints);
+            }
+        }
+        Expr::Path {
+            ref start_point,
+            ref steps,
+        } => {
+            lint_expr(start_point, lints);
+            for predicate in steps.iter().flat_map(|s| &s.predicates) {
+                lint_predicate(predicate, lints);
+            }
+        }
+        Expr::Filter {
+            ref expression,
+            ref predicate,
+        } => {
+            lint_expr(expression, lints);
+            lint_predicate(predicate, lints);
+        }
+        Expr::Negation(ref expr)
+        | Expr::InstanceOf {
+            expression: ref expr,
+            ..
+        }
+        | Expr::TreatAs {
+            expression: ref expr,
+            ..
+        }
+        | Expr::Cast {
+            expression: ref expr,
+            ..
+        }
+        | Expr::Castable {
+            expression: ref expr,
+            ..
+        }
+        | Expr::CurlyArray(ref expr)
+        | Expr::InlineFunction { body: ref expr, .. } => lint_expr(expr, lints),
+        Expr::If {
+            ref condition,
+            ref consequent,
+            ref alternative,
+        } => {
+            lint_expr(condition, lints);
+            lint_expr(consequent, lints);
+            lint_expr(alternative, lints);
+        }
+        Expr::For {
+            sequence: ref bound,
+            ref body,
+            ..
+        }
+        | Expr::Let {
+            value: ref bound,
+            ref body,
+            ..
+        }
+        | Expr::Quantified {
+            sequence: ref bound,
+            test: ref body,
+            ..
+        } => {
+            lint_expr(bound, lints);
+            lint_expr(body, lints);
+        }
+        Expr::Sequence(ref expressions) | Expr::SquareArray(ref expressions) => {
+            for expr in expressions {
+                lint_expr(expr, lints);
+            }
+        }
+        Expr::DynamicCall {
+            ref function,
+            ref arguments,
+        } => {
+            lint_expr(function, lints);
+            for argument in arguments {
+                lint_expr(argument, lints);
+            }
+        }
+        Expr::Map(ref entries) => {
+            for (key, value) in entries {
+                lint_expr(key, lints);
+                lint_expr(value, lints);
+            }
+        }
+        Expr::Lookup {
+            ref expression,
+            ref key,
+        } => {
+            lint_expr(expression, lints);
+            if let LookupKey::Expression(ref key) = *key {
+                lint_expr(key, lints);
+            }
+        }
+        Expr::Binary {
+            operator,
+            ref left,
+            ref right,
+        } => {
+            let compares = matches!(operator, BinaryOperator::Equal | BinaryOperator::NotEqual);
+            if compares
+                && left.return_type() == ValueType::Nodeset
+                && right.return_type() == ValueType::Nodeset
+            {
+                lints.push(Lint {
+                    code: LintCode::NodesetComparison,
+                    span: None,
+                });
+            }
+            lint_expr(left, lints);
+            lint_expr(right, lints);
+        }
+    }
+}
+
+/// Numeric predicates select a position, so only predicates that are
+/// always true or always false are reported.
+fn lint_predicate(predicate: &Expr, lints: &mut Vec<Lint>) {
+    let truth = matches!(
+        predicate.return_type(),
+        ValueType::Boolean | ValueType::String
+    );
+    if truth && is_constant(predicate) {
+        lints.push(Lint {
+            code: LintCode::ConstantPredicate,
+            span: None,
+        });
+    }
+    lint_expr(predicate, lints);
+}
+
+/// Whether the expression only combines literals with core functions
+/// and operators. Functions without arguments use the context, apart
+/// from `true()` and `false()`.
+fn is_constant(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Literal(_) => true,
+        Expr::Function {
+            ref name,
+            ref arguments,
+        } => {
+            let core = name.prefix.is_none()
+                && ast::core_function_type(&name.local_part) != ValueType::Unknown;
+            let uses_context =
+                arguments.is_empty() && !matches!(&*name.local_part, "true" | "false");
+            core && !uses_context && arguments.iter().all(is_constant)
+        }
+        Expr::Negation(ref expr) => is_constant(expr),
+        Expr::Binary {
+            ref left,
+            ref right,
+            ..
+        } => is_constant(left) && is_constant(right),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Factory;
+
+    fn codes(xpath: &str) -> Vec<&'static str> {
+        Factory::new()
+            .lint(xpath)
+            .expect("Unable to build XPath")
+            .iter()
+            .map(|lint| lint.code.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn paths_starting_with_descendants_of_the_root() {
+        assert_eq!(
+            vec!["descendant-from-root"; 3],
+            codes("//a | count(//b) + //c")
+        );
+        assert!(codes("a//b | $v//c | (a)//b").is_empty());
+    }
+
+    #[test]
+    fn predicates_that_do_not_depend_on_the_node() {
+        assert_eq!(vec!["constant-predicate"], codes("a[true()]"));
+        assert_eq!(vec!["constant-predicate"], codes("a['y']"));
+        assert_eq!(vec!["constant-predicate"], codes("$v[not(1 = 2)]"));
+        assert!(codes("a[1][last()][@b][$v][string()][. = 'x']").is_empty());
+    }
+
+    #[test]
+    fn equality_of_two_nodesets() {
+        assert_eq!(vec!["nodeset-comparison"], codes("a[b = c]"));
+        assert_eq!(vec!["nodeset-comparison"], codes("a != b"));
+        assert!(codes("a = 'x' or a < b or $v = b").is_empty());
+    }
+
+    #[test]
+    fn parentheses_that_do_not_change_the_meaning() {
+        assert_eq!(vec!["redundant-parentheses"], codes("1 + (2 * 3)"));
+        assert_eq!(vec!["redundant-parentheses"; 2], codes("((a))"));
+        assert!(codes("(1 + 2) * 3").is_empty());
+        assert!(codes("(a)[1] | count(()) | f((1, 2)) | a[not(b)]").is_empty());
+    }
+
+    #[test]
+    fn lints_are_located_when_possible() {
+        let lints = Factory::new()
+            .lint("a[1 = 1] | (//b)")
+            .expect("Unable to build XPath");
+
+        let located: Vec<_> = lints
+            .iter()
+            .map(|lint| (lint.code, lint.span.map(|span| span.range())))
+            .collect();
+        assert_eq!(
+            vec![
+                (LintCode::RedundantParentheses, Some(11..16)),
+                (LintCode::DescendantFromRoot, Some(12..14)),
+                (LintCode::ConstantPredicate, None),
+            ],
+            located
+        );
+        assert_eq!(
+            "redundant-parentheses: parentheses are not needed at byte 11",
+            lints[0].to_string()
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Maps and arrays, the structured items of XPath 3.1.
+//!
+//! A map associates atomic keys with values and an array is an
+//! ordered list of values. Both are single [`Item`][]s, so they may be
+//! stored in a sequence or in another map or array.
+//!
+//! [`Item`]: ../enum.Item.html
+
+use crate::{Item, Value};
+
+/// A map from atomic keys to values, as built by `map { ... }`.
+/// Entries are kept in the order they were added.
+#[derive(Debug, Clone, Default)]
+pub struct Map<'d> {
+    entries: Vec<(Item<'d>, Value<'d>)>,
+}
+
+/// Whether two keys are the same, as defined by XPath 3.1. Keys are
+/// never converted to another type, but `NaN` is the same as itself.
+pub(crate) fn same_key(left: &Item<'_>, right: &Item<'_>) -> bool {
+    match (left, right) {
+        (Item::Boolean(l), Item::Boolean(r)) => l == r,
+        (Item::Number(l), Item::Number(r)) => l == r || (l.is_nan() && r.is_nan()),
+        (Item::String(l), Item::String(r)) => l == r,
+        (Item::DateTime(l), Item::DateTime(r)) => l == r,
+        (Item::Duration(l), Item::Duration(r)) => l == r,
+        _ => false,
+    }
+}
+
+impl<'d> Map<'d> {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds an entry, returning the value it replaced.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<Value<'d>>
+    where
+        K: Into<Item<'d>>,
+        V: Into<Value<'d>>,
+    {
+        let key = key.into();
+        let value = value.into();
+
+        match self.entries.iter_mut().find(|(k, _)| same_key(k, &key)) {
+            Some(entry) => Some(std::mem::replace(&mut entry.1, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, key: &Item<'_>) -> Option<&Value<'d>> {
+        self.entries
+            .iter()
+            .find(|(k, _)| same_key(k, key))
+            .map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &Item<'_>) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Item<'d>> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value<'d>> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Item<'d>, &Value<'d>)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+// The order of the entries does not matter
+impl<'d> PartialEq for Map<'d> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+/// Looks up the member of an array at the one-based position.
+pub(crate) fn array_member<'a, 'd>(
+    members: &'a [Value<'d>],
+    position: f64,
+) -> Option<&'a Value<'d>> {
+    if position.fract() != 0.0 || position < 1.0 {
+        return None;
+    }
+    members.get(position as usize - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn string(s: &str) -> Item<'static> {
+        Item::String(s.to_owned())
+    }
+
+    #[test]
+    fn inserting_a_key_again_replaces_its_value() {
+        let mut map = Map::new();
+        assert_eq!(None, map.insert(string("a"), 1.0));
+        assert_eq!(Some(Value::Number(1.0)), map.insert(string("a"), 2.0));
+
+        assert_eq!(1, map.len());
+        assert_eq!(Some(&Value::Number(2.0)), map.get(&string("a")));
+    }
+
+    #[test]
+    fn keys_of_different_types_are_distinct() {
+        let mut map = Map::new();
+        map.insert(string("1"), "string");
+        map.insert(Item::Number(1.0), "number");
+
+        assert_eq!(2, map.len());
+        assert_eq!(Some(&Value::from("number")), map.get(&Item::Number(1.0)));
+    }
+
+    #[test]
+    fn nan_is_the_same_key_as_itself() {
+        let mut map = Map::new();
+        map.insert(Item::Number(f64::NAN), true);
+
+        assert!(map.contains_key(&Item::Number(f64::NAN)));
+    }
+
+    #[test]
+    fn maps_are_equal_regardless_of_order() {
+        let mut left = Map::new();
+        left.insert(string("a"), 1.0);
+        left.insert(string("b"), 2.0);
+
+        let mut right = Map::new();
+        right.insert(string("b"), 2.0);
+        right.insert(string("a"), 1.0);
+
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn array_members_are_found_by_whole_positions() {
+        let members = vec![Value::Number(10.0), Value::Number(20.0)];
+
+        assert_eq!(Some(&Value::Number(20.0)), array_member(&members, 2.0));
+        assert_eq!(None, array_member(&members, 0.0));
+        assert_eq!(None, array_member(&members, 1.5));
+        assert_eq!(None, array_member(&members, 3.0));
+    }
+}
@@ -3,7 +3,9 @@ use std::fmt;
 use sxd_document::QName;
 
 use crate::context;
-use crate::nodeset::{self, OrderedNodes};
+use crate::nodeset::OrderedNodes;
+use crate::tree::{NodeKind, TreeNode};
+use crate::HTML_NAMESPACE_URI;
 
 pub trait NodeTest: fmt::Debug {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>);
@@ -20,25 +22,67 @@ where
 
 pub type SubNodeTest = Box<dyn NodeTest + 'static>;
 
+/// A test of the name of a node. A local part of `*` matches any
+/// local part, and a prefix of `*` matches any namespace.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NameTest {
     pub prefix: Option<String>,
     pub local_part: String,
 }
 
 impl NameTest {
-    fn matches(&self, context: &context::Evaluation<'_, '_>, node_name: QName<'_>) -> bool {
+    fn matches(
+        &self,
+        context: &context::Evaluation<'_, '_>,
+        node_name: QName<'_>,
+        kind: NodeKind,
+    ) -> bool {
         let is_wildcard = self.local_part == "*";
+        let is_html = context.node.is_html();
 
-        let test_uri = self.prefix.as_ref().map(|p| {
+        let local_part_matches = if is_html {
+            self.local_part.eq_ignore_ascii_case(node_name.local_part())
+        } else {
+            self.local_part == node_name.local_part()
+        };
+
+        // `*:local` matches the local part in any namespace
+        if self.prefix.as_deref() == Some("*") {
+            return local_part_matches;
+        }
+
+        let test_uri = match self.prefix {
             // TODO: Error for undefined prefix
-            context.namespace_for(p).expect("No namespace for prefix")
-        });
+            Some(ref p) => Some(context.namespace_for(p).expect("No namespace for prefix")),
+            // Unprefixed names select HTML elements, as browsers do
+            None if is_html && kind == NodeKind::Element => Some(HTML_NAMESPACE_URI),
+            None => None,
+        };
 
         match (is_wildcard, test_uri) {
             (true, None) => true,
             (true, Some(..)) => test_uri == node_name.namespace_uri(),
-            _ => test_uri == node_name.namespace_uri() && self.local_part == node_name.local_part(),
+            _ => test_uri == node_name.namespace_uri() && local_part_matches,
+        }
+    }
+}
+
+/// Adds the context node to the result when it is of the given kind
+/// and its name matches the name test.
+fn test_named<'c, 'd>(
+    name_test: &NameTest,
+    kind: NodeKind,
+    context: &context::Evaluation<'c, 'd>,
+    result: &mut OrderedNodes<'d>,
+) {
+    if context.node.kind() != kind {
+        return;
+    }
+
+    if let Some(name) = context.node.expanded_name() {
+        if name_test.matches(context, name, kind) {
+            result.add(context.node);
         }
     }
 }
@@ -56,11 +100,7 @@ impl Attribute {
 
 impl NodeTest for Attribute {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
-        if let nodeset::Node::Attribute(ref a) = context.node {
-            if self.name_test.matches(context, a.name()) {
-                result.add(context.node);
-            }
-        }
+        test_named(&self.name_test, NodeKind::Attribute, context, result)
     }
 }
 
@@ -77,11 +117,7 @@ impl Namespace {
 
 impl NodeTest for Namespace {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
-        if let nodeset::Node::Namespace(ref ns) = context.node {
-            if self.name_test.matches(context, QName::new(ns.prefix())) {
-                result.add(context.node);
-            }
-        }
+        test_named(&self.name_test, NodeKind::Namespace, context, result)
     }
 }
 
@@ -98,11 +134,7 @@ impl Element {
 
 impl NodeTest for Element {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
-        if let nodeset::Node::Element(ref e) = context.node {
-            if self.name_test.matches(context, e.name()) {
-                result.add(context.node);
-            }
-        }
+        test_named(&self.name_test, NodeKind::Element, context, result)
     }
 }
 
@@ -122,7 +154,7 @@ pub struct Text;
 
 impl NodeTest for Text {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
-        if let nodeset::Node::Text(_) = context.node {
+        if context.node.kind() == NodeKind::Text {
             result.add(context.node);
         }
     }
@@ -134,7 +166,19 @@ pub struct Comment;
 
 impl NodeTest for Comment {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
-        if let nodeset::Node::Comment(_) = context.node {
+        if context.node.kind() == NodeKind::Comment {
+            result.add(context.node);
+        }
+    }
+}
+
+#[allow(missing_copy_implementations)]
+#[derive(Debug)]
+pub struct Root;
+
+impl NodeTest for Root {
+    fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
+        if context.node.kind() == NodeKind::Root {
             result.add(context.node);
         }
     }
@@ -153,9 +197,10 @@ impl ProcessingInstruction {
 
 impl NodeTest for ProcessingInstruction {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
-        if let nodeset::Node::ProcessingInstruction(pi) = context.node {
+        if context.node.kind() == NodeKind::ProcessingInstruction {
+            let target = context.node.expanded_name().map(|n| n.local_part());
             match self.target {
-                Some(ref name) if name == pi.target() => result.add(context.node),
+                Some(ref name) if Some(name.as_str()) == target => result.add(context.node),
                 Some(_) => {}
                 None => result.add(context.node),
             }
@@ -181,7 +226,7 @@ mod test {
     }
 
     impl<'d> Setup<'d> {
-        fn new(package: &'d Package) -> Setup<'_> {
+        fn new(package: &'d Package) -> Setup<'d> {
             Setup {
                 doc: package.as_document(),
                 context: Context::without_core_functions(),
@@ -415,6 +460,19 @@ mod test {
         assert_eq!(ordered_nodes![], result);
     }
 
+    #[test]
+    fn element_test_matches_any_namespace_when_prefix_is_wildcard() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        let (element, context) = setup.context_for_ns_element("prefix", "uri", "name");
+
+        let result = run_element(&context, Some("*"), "name");
+        assert_eq!(ordered_nodes![element], result);
+
+        let result = run_element(&context, Some("*"), "wrong-name");
+        assert_eq!(ordered_nodes![], result);
+    }
+
     #[test]
     fn element_test_ignores_namespace_when_wildcard_without_prefix() {
         let package = Package::new();
@@ -1,14 +1,23 @@
 //! Support for collections of nodes.
 
 use std::borrow::ToOwned;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::hash_set;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
 use std::iter::{FromIterator, IntoIterator};
-use std::usize;
+use std::rc::Rc;
 
 use sxd_document::dom;
 use sxd_document::QName;
 
+use crate::backend::{ExternalNode, ExternalTree};
+use crate::json;
+use crate::tree::{self, DocOrder, LazyDocOrder, NodeKind, TreeNode};
+use crate::xml;
+
 macro_rules! unpack(
     ($enum_name:ident, {
         $($name:ident, $wrapper:ident, dom::$inner:ident),*
@@ -75,20 +84,78 @@ pub enum Node<'d> {
     Comment(dom::Comment<'d>),
     Namespace(Namespace<'d>),
     ProcessingInstruction(dom::ProcessingInstruction<'d>),
+    /// A node of a tree provided by another [backend](../backend/index.html).
+    External(ExternalNode<'d>),
+}
+
+/// The tree that owns a [`Node`](enum.Node.html).
+#[derive(Copy, Clone)]
+pub enum Owner<'d> {
+    Document(dom::Document<'d>),
+    External(&'d dyn ExternalTree),
+}
+
+impl<'d> Owner<'d> {
+    /// The `sxd_document` document, if the tree is not an external one.
+    pub fn document(&self) -> Option<dom::Document<'d>> {
+        match *self {
+            Owner::Document(d) => Some(d),
+            Owner::External(_) => None,
+        }
+    }
+}
+
+impl<'d> tree::Document<'d> for Owner<'d> {
+    type Node = Node<'d>;
+
+    fn root(&self) -> Node<'d> {
+        match *self {
+            Owner::Document(d) => d.root().into(),
+            Owner::External(t) => ExternalNode::new(t, t.root()).into(),
+        }
+    }
 }
 
 impl<'d> Node<'d> {
     /// The document to which this node belongs.
+    ///
+    /// # Panics
+    ///
+    /// If the node comes from an external tree. Use
+    /// [`owner`](#method.owner) for a node of any tree.
     pub fn document(&self) -> dom::Document<'d> {
+        match self.owner() {
+            Owner::Document(d) => d,
+            Owner::External(_) => panic!("Node is from an external tree"),
+        }
+    }
+
+    /// The tree to which this node belongs.
+    pub fn owner(&self) -> Owner<'d> {
         use self::Node::*;
         match *self {
-            Root(n) => n.document(),
-            Element(n) => n.document(),
-            Attribute(n) => n.document(),
-            Text(n) => n.document(),
-            Comment(n) => n.document(),
-            ProcessingInstruction(n) => n.document(),
-            Namespace(n) => n.document(),
+            Root(n) => Owner::Document(n.document()),
+            Element(n) => Owner::Document(n.document()),
+            Attribute(n) => Owner::Document(n.document()),
+            Text(n) => Owner::Document(n.document()),
+            Comment(n) => Owner::Document(n.document()),
+            ProcessingInstruction(n) => Owner::Document(n.document()),
+            Namespace(n) => Owner::Document(n.document()),
+            External(n) => Owner::External(n.tree()),
+        }
+    }
+
+    /// The name of the node's type in the XPath data model, such as
+    /// `element` or `processing-instruction`.
+    pub fn kind_name(&self) -> &'static str {
+        match TreeNode::kind(self) {
+            NodeKind::Root => "root",
+            NodeKind::Element => "element",
+            NodeKind::Attribute => "attribute",
+            NodeKind::Text => "text",
+            NodeKind::Comment => "comment",
+            NodeKind::Namespace => "namespace",
+            NodeKind::ProcessingInstruction => "processing-instruction",
         }
     }
 
@@ -110,7 +177,7 @@ impl<'d> Node<'d> {
             } else {
                 name.local_part().to_owned()
             }
-        };
+        }
 
         match *self {
             Root(_) => None,
@@ -123,9 +190,33 @@ impl<'d> Node<'d> {
             Comment(_) => None,
             ProcessingInstruction(n) => Some(n.target().to_owned()),
             Namespace(n) => Some(n.prefix().to_owned()),
+            External(n) => n.prefixed_name(),
         }
     }
 
+    /// Writes the node as XML, with everything under it. See the
+    /// [`xml`](../xml/index.html) module.
+    pub fn to_xml(&self) -> String {
+        self.to_xml_with(&xml::Options::new())
+    }
+
+    pub fn to_xml_with(&self, options: &xml::Options) -> String {
+        let mut out = Vec::new();
+        self.write_xml_with(&mut out, options)
+            .expect("Writing to memory cannot fail");
+        String::from_utf8(out).expect("XML is written as UTF-8")
+    }
+
+    /// Writes the node as XML like [`to_xml`](#method.to_xml), without
+    /// building a string of it first.
+    pub fn write_xml<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_xml_with(out, &xml::Options::new())
+    }
+
+    pub fn write_xml_with<W: Write>(&self, out: &mut W, options: &xml::Options) -> io::Result<()> {
+        xml::write_node(out, *self, options)
+    }
+
     /// Returns the [expanded name][] of the node, if any.
     ///
     /// [expanded name]: https://www.w3.org/TR/xpath/#dt-expanded-name
@@ -139,6 +230,7 @@ impl<'d> Node<'d> {
             Comment(_) => None,
             ProcessingInstruction(n) => Some(QName::new(n.target())),
             Namespace(n) => Some(n.expanded_name()),
+            External(n) => n.expanded_name(),
         }
     }
 
@@ -153,6 +245,7 @@ impl<'d> Node<'d> {
             Comment(n) => n.parent().map(Into::into),
             ProcessingInstruction(n) => n.parent().map(Into::into),
             Namespace(n) => Some(n.parent().into()),
+            External(n) => n.parent().map(Into::into),
         }
     }
 
@@ -167,6 +260,7 @@ impl<'d> Node<'d> {
             Comment(_) => Vec::new(),
             ProcessingInstruction(_) => Vec::new(),
             Namespace(_) => Vec::new(),
+            External(n) => n.children().into_iter().map(Into::into).collect(),
         }
     }
 
@@ -201,6 +295,7 @@ impl<'d> Node<'d> {
                 .map(Into::into)
                 .collect(),
             Namespace(_) => Vec::new(),
+            External(n) => n.preceding_siblings().into_iter().map(Into::into).collect(),
         }
     }
 
@@ -217,7 +312,66 @@ impl<'d> Node<'d> {
                 n.following_siblings().into_iter().map(Into::into).collect()
             }
             Namespace(_) => Vec::new(),
+            External(n) => n.following_siblings().into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// An XPath that selects this node from the root of its document,
+    /// such as `/list/item[2]/@id`. A step has a position when its
+    /// parent has other children that the step would also select.
+    /// Names keep the prefixes of the document, which must be bound to
+    /// the same namespaces to evaluate the path.
+    pub fn path(&self) -> String {
+        let mut steps = Vec::new();
+        let mut node = *self;
+        while let Some(parent) = node.parent() {
+            steps.push(node.step());
+            node = parent;
         }
+        steps.reverse();
+        format!("/{}", steps.join("/"))
+    }
+
+    /// The last step of the node's path.
+    fn step(&self) -> String {
+        let name = || self.prefixed_name().unwrap_or_default();
+        let test = match self.kind() {
+            NodeKind::Root => return String::new(),
+            NodeKind::Attribute => return format!("@{}", name()),
+            NodeKind::Namespace => return format!("namespace::{}", name()),
+            NodeKind::Element => name(),
+            NodeKind::Text => "text()".to_owned(),
+            NodeKind::Comment => "comment()".to_owned(),
+            NodeKind::ProcessingInstruction => format!("processing-instruction('{}')", name()),
+        };
+
+        let selected =
+            |n: &Node<'d>| n.kind() == self.kind() && n.expanded_name() == self.expanded_name();
+        let position = self
+            .preceding_siblings()
+            .iter()
+            .filter(|n| selected(n))
+            .count()
+            + 1;
+        if position > 1 || self.following_siblings().iter().any(selected) {
+            format!("{}[{}]", test, position)
+        } else {
+            test
+        }
+    }
+
+    /// Compares the positions of this node and the other node in
+    /// [document order]. Nodes that are not attached to the tree are
+    /// ordered as described in the library-level docs.
+    ///
+    /// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
+    pub fn compare_document_order(&self, other: &Node<'d>) -> Ordering {
+        if self == other {
+            return Ordering::Equal;
+        }
+
+        let order = DocOrder::new(self.owner());
+        order.order_of(*self).cmp(&order.order_of(*other))
     }
 
     /// Returns the [string value] of this node.
@@ -226,33 +380,57 @@ impl<'d> Node<'d> {
     pub fn string_value(&self) -> String {
         use self::Node::*;
 
-        fn document_order_text_nodes(node: Node<'_>, result: &mut String) {
-            for child in node.children() {
-                match child {
-                    Node::Element(_) => document_order_text_nodes(child, result),
-                    Node::Text(n) => result.push_str(n.text()),
-                    _ => {}
-                }
-            }
-        };
-
-        fn text_descendants_string_value(node: Node<'_>) -> String {
-            let mut result = String::new();
-            document_order_text_nodes(node, &mut result);
-            result
-        }
-
         match *self {
-            Root(_) => text_descendants_string_value(*self),
-            Element(_) => text_descendants_string_value(*self),
+            Root(_) | Element(_) => {
+                let mut result = String::new();
+                self.each_text_descendant(&mut |text| {
+                    result.push_str(text);
+                    Ok(())
+                })
+                .expect("Collecting text cannot fail");
+                result
+            }
             Attribute(n) => n.value().to_owned(),
             ProcessingInstruction(n) => n.value().unwrap_or("").to_owned(),
             Comment(n) => n.text().to_owned(),
             Text(n) => n.text().to_owned(),
             Namespace(n) => n.uri().to_owned(),
+            External(n) => n.string_value(),
         }
     }
 
+    /// Writes the [string value](#method.string_value) of the node,
+    /// without building a string of it first. The text of an element
+    /// is written one text node at a time.
+    pub fn write_string_value<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        use self::Node::*;
+
+        match *self {
+            Root(_) | Element(_) => {
+                self.each_text_descendant(&mut |text| out.write_all(text.as_bytes()))
+            }
+            Attribute(n) => out.write_all(n.value().as_bytes()),
+            ProcessingInstruction(n) => out.write_all(n.value().unwrap_or("").as_bytes()),
+            Comment(n) => out.write_all(n.text().as_bytes()),
+            Text(n) => out.write_all(n.text().as_bytes()),
+            Namespace(n) => out.write_all(n.uri().as_bytes()),
+            External(n) => out.write_all(n.string_value().as_bytes()),
+        }
+    }
+
+    /// Calls `each` with the text of the text nodes below this node, in
+    /// document order.
+    fn each_text_descendant(&self, each: &mut dyn FnMut(&str) -> io::Result<()>) -> io::Result<()> {
+        for child in self.children() {
+            match child {
+                Node::Element(_) => child.each_text_descendant(each)?,
+                Node::Text(n) => each(n.text())?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     unpack!(Node, {
         root, Root, dom::Root,
         element, Element, dom::Element,
@@ -268,6 +446,13 @@ impl<'d> Node<'d> {
             _ => None,
         }
     }
+
+    pub fn external(self) -> Option<ExternalNode<'d>> {
+        match self {
+            Node::External(n) => Some(n),
+            _ => None,
+        }
+    }
 }
 
 conversion_trait!(Node, {
@@ -279,10 +464,10 @@ conversion_trait!(Node, {
     dom::ProcessingInstruction => Node::ProcessingInstruction
 });
 
-impl<'d> Into<Node<'d>> for dom::ChildOfRoot<'d> {
-    fn into(self) -> Node<'d> {
+impl<'d> From<dom::ChildOfRoot<'d>> for Node<'d> {
+    fn from(val: dom::ChildOfRoot<'d>) -> Self {
         use self::Node::*;
-        match self {
+        match val {
             dom::ChildOfRoot::Element(n) => Element(n),
             dom::ChildOfRoot::Comment(n) => Comment(n),
             dom::ChildOfRoot::ProcessingInstruction(n) => ProcessingInstruction(n),
@@ -290,10 +475,10 @@ impl<'d> Into<Node<'d>> for dom::ChildOfRoot<'d> {
     }
 }
 
-impl<'d> Into<Node<'d>> for dom::ChildOfElement<'d> {
-    fn into(self) -> Node<'d> {
+impl<'d> From<dom::ChildOfElement<'d>> for Node<'d> {
+    fn from(val: dom::ChildOfElement<'d>) -> Self {
         use self::Node::*;
-        match self {
+        match val {
             dom::ChildOfElement::Element(n) => Element(n),
             dom::ChildOfElement::Text(n) => Text(n),
             dom::ChildOfElement::Comment(n) => Comment(n),
@@ -302,10 +487,10 @@ impl<'d> Into<Node<'d>> for dom::ChildOfElement<'d> {
     }
 }
 
-impl<'d> Into<Node<'d>> for dom::ParentOfChild<'d> {
-    fn into(self) -> Node<'d> {
+impl<'d> From<dom::ParentOfChild<'d>> for Node<'d> {
+    fn from(val: dom::ParentOfChild<'d>) -> Self {
         use self::Node::*;
-        match self {
+        match val {
             dom::ParentOfChild::Root(n) => Root(n),
             dom::ParentOfChild::Element(n) => Element(n),
         }
@@ -313,9 +498,38 @@ impl<'d> Into<Node<'d>> for dom::ParentOfChild<'d> {
 }
 
 /// An unordered collection of unique nodes
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Default)]
 pub struct Nodeset<'d> {
     nodes: HashSet<Node<'d>>,
+    /// The positions of the nodes, as the set iterates over them, in
+    /// document order. It is kept until a node is added or the set
+    /// grows, as the set iterates in the same order until then. Positions, rather than
+    /// nodes, keep the nodeset covariant over `'d`.
+    ordered: RefCell<Option<Rc<[usize]>>>,
+}
+
+impl<'d> Clone for Nodeset<'d> {
+    fn clone(&self) -> Self {
+        // The clone may iterate in another order
+        Nodeset {
+            nodes: self.nodes.clone(),
+            ordered: RefCell::default(),
+        }
+    }
+}
+
+impl<'d> fmt::Debug for Nodeset<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nodeset")
+            .field("nodes", &self.nodes)
+            .finish()
+    }
+}
+
+impl<'d> PartialEq for Nodeset<'d> {
+    fn eq(&self, other: &Self) -> bool {
+        self.nodes == other.nodes
+    }
 }
 
 impl<'d> Nodeset<'d> {
@@ -323,6 +537,34 @@ impl<'d> Nodeset<'d> {
         Default::default()
     }
 
+    /// Creates an empty set with room for at least `capacity` nodes.
+    pub fn with_capacity(capacity: usize) -> Nodeset<'d> {
+        Nodeset {
+            nodes: HashSet::with_capacity(capacity),
+            ordered: RefCell::default(),
+        }
+    }
+
+    /// Makes room for at least `additional` more nodes, so that adding
+    /// them does not grow the set again.
+    pub fn reserve(&mut self, additional: usize) {
+        // Growing the set may change the order it iterates in
+        self.nodes.reserve(additional);
+        self.ordered.get_mut().take();
+    }
+
+    /// A set of nodes that are already in document order, without
+    /// duplicates. The order is kept, so it is not found again.
+    // Nodes are hashed by identity, not by their mutable contents
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn from_document_order(nodes: Vec<Node<'d>>) -> Nodeset<'d> {
+        let set: Nodeset<'d> = nodes.iter().copied().collect();
+        let index: HashMap<_, _> = set.iter().enumerate().map(|(i, n)| (n, i)).collect();
+        let positions: Rc<[usize]> = nodes.iter().map(|n| index[n]).collect();
+        *set.ordered.borrow_mut() = Some(positions);
+        set
+    }
+
     /// Checks if the node is present in the set
     pub fn contains<N>(&self, node: N) -> bool
     where
@@ -337,6 +579,42 @@ impl<'d> Nodeset<'d> {
         N: Into<Node<'d>>,
     {
         self.nodes.insert(node.into());
+        self.ordered.get_mut().take();
+    }
+
+    /// Adds every node to the set, growing it once for as many nodes
+    /// as the iterator says it holds.
+    pub fn insert_all<I, N>(&mut self, nodes: I)
+    where
+        I: IntoIterator<Item = N>,
+        N: Into<Node<'d>>,
+    {
+        let nodes = nodes.into_iter();
+        self.reserve(nodes.size_hint().0);
+        for node in nodes {
+            self.nodes.insert(node.into());
+        }
+        self.ordered.get_mut().take();
+    }
+
+    /// Keeps only the nodes for which the predicate returns true.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(Node<'d>) -> bool,
+    {
+        let size = self.nodes.len();
+        self.nodes.retain(|&n| f(n));
+        if self.nodes.len() != size {
+            self.ordered.get_mut().take();
+        }
+    }
+
+    /// A new set of the nodes for which the predicate returns true.
+    pub fn filtered<F>(&self, mut f: F) -> Nodeset<'d>
+    where
+        F: FnMut(Node<'d>) -> bool,
+    {
+        self.iter().filter(|&n| f(n)).collect()
     }
 
     pub fn iter<'a>(&'a self) -> Iter<'a, 'd> {
@@ -351,16 +629,20 @@ impl<'d> Nodeset<'d> {
     ///
     /// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
     pub fn document_order_first(&self) -> Option<Node<'d>> {
-        let node = match self.nodes.iter().next() {
-            Some(n) => n,
-            None => return None,
-        };
+        let node = self.nodes.iter().next()?;
 
         if self.nodes.len() == 1 {
             return Some(*node);
         }
 
-        let order = DocOrder::new(node.document());
+        // Comparing the nodes with each other does not number the
+        // rest of the document
+        if LazyDocOrder::can_order(self.iter()) {
+            let mut order = LazyDocOrder::new();
+            return self.iter().min_by(|&l, &r| order.compare(l, r));
+        }
+
+        let order = DocOrder::new(node.owner());
 
         self.nodes
             .iter()
@@ -368,20 +650,70 @@ impl<'d> Nodeset<'d> {
             .cloned()
     }
 
+    /// Iterates over the nodes in [document order]. The nodes are
+    /// sorted the first time and the order is kept until a node is
+    /// added, so iterating again does not sort them again.
+    ///
+    /// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
+    pub fn document_order_iter(&self) -> DocumentOrderIter<'d> {
+        DocumentOrderIter {
+            iter: self.document_order().into_iter(),
+        }
+    }
+
     pub fn document_order(&self) -> Vec<Node<'d>> {
-        let mut nodes: Vec<_> = self.iter().collect();
-        if nodes.len() == 1 {
+        let nodes: Vec<_> = self.iter().collect();
+        if nodes.len() <= 1 {
             return nodes;
         }
 
-        let doc = match nodes.first().map(Node::document) {
-            Some(doc) => doc,
-            None => return nodes,
-        };
+        let positions = self.ordered.borrow().clone();
+        let positions = positions.unwrap_or_else(|| {
+            let order = DocOrder::new(nodes[0].owner());
+            let mut positions: Vec<_> = (0..nodes.len()).collect();
+            positions.sort_by_key(|&i| order.order_of(nodes[i]));
+
+            let positions: Rc<[usize]> = positions.into();
+            *self.ordered.borrow_mut() = Some(Rc::clone(&positions));
+            positions
+        });
+        positions.iter().map(|&i| nodes[i]).collect()
+    }
+
+    /// Writes each node as XML, in document order, one after another.
+    /// See the [`xml`](../xml/index.html) module.
+    pub fn to_xml(&self) -> String {
+        self.to_xml_with(&xml::Options::new())
+    }
+
+    pub fn to_xml_with(&self, options: &xml::Options) -> String {
+        let mut out = Vec::new();
+        self.write_xml_with(&mut out, options)
+            .expect("Writing to memory cannot fail");
+        String::from_utf8(out).expect("XML is written as UTF-8")
+    }
+
+    /// Writes each node as XML like [`to_xml`](#method.to_xml), without
+    /// building a string of them first.
+    pub fn write_xml<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_xml_with(out, &xml::Options::new())
+    }
+
+    pub fn write_xml_with<W: Write>(&self, out: &mut W, options: &xml::Options) -> io::Result<()> {
+        for node in self.document_order() {
+            xml::write_node(out, node, options)?;
+        }
+        Ok(())
+    }
+
+    /// Maps the nodes onto plain JSON. See
+    /// [`json::render_nodes`](../json/fn.render_nodes.html).
+    pub fn to_json(&self) -> String {
+        self.to_json_with(&json::Mapping::new())
+    }
 
-        let order = DocOrder::new(doc);
-        nodes.sort_by_key(|&n| order.order_of(n));
-        nodes
+    pub fn to_json_with(&self, mapping: &json::Mapping) -> String {
+        json::render_nodes(self, mapping)
     }
 }
 
@@ -390,39 +722,18 @@ impl<'d> Extend<Node<'d>> for Nodeset<'d> {
     where
         I: IntoIterator<Item = Node<'d>>,
     {
-        self.nodes.extend(iter)
+        self.nodes.extend(iter);
+        self.ordered.get_mut().take();
     }
 }
 
-// Rebuilding this multiple times cannot possibly be performant,
-// but I want to see how widely used this is first before
-// picking an appropriate caching point.
-struct DocOrder<'d>(HashMap<Node<'d>, usize>);
-
-impl<'d> DocOrder<'d> {
-    fn new(doc: dom::Document<'d>) -> Self {
-        let mut idx = 0;
-        let mut stack: Vec<Node<'_>> = vec![doc.root().into()];
-        let mut order = HashMap::new();
-
-        while let Some(n) = stack.pop() {
-            order.insert(n, idx);
-            idx += 1;
-
-            stack.extend(n.children().into_iter().rev());
-
-            if let Node::Element(e) = n {
-                // TODO: namespaces
-                stack.extend(e.attributes().into_iter().map(Node::Attribute));
-            }
-        }
-
-        DocOrder(order)
-    }
-
-    fn order_of(&self, node: Node<'d>) -> usize {
-        // See the library-level docs for rationale on this MAX
-        self.0.get(&node).cloned().unwrap_or(usize::MAX)
+impl<'a, 'd> Extend<&'a Node<'d>> for Nodeset<'d> {
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = &'a Node<'d>>,
+    {
+        self.nodes.extend(iter.into_iter().copied());
+        self.ordered.get_mut().take();
     }
 }
 
@@ -454,6 +765,12 @@ impl<'d> From<OrderedNodes<'d>> for Nodeset<'d> {
     }
 }
 
+impl<'d> From<Vec<Node<'d>>> for Nodeset<'d> {
+    fn from(other: Vec<Node<'d>>) -> Self {
+        other.into_iter().collect()
+    }
+}
+
 impl<'d> FromIterator<Node<'d>> for Nodeset<'d> {
     fn from_iter<I>(iterator: I) -> Nodeset<'d>
     where
@@ -461,10 +778,38 @@ impl<'d> FromIterator<Node<'d>> for Nodeset<'d> {
     {
         Nodeset {
             nodes: iterator.into_iter().collect(),
+            ordered: RefCell::default(),
         }
     }
 }
 
+/// Iterates over the nodes of a nodeset in document order. See
+/// [`Nodeset::document_order_iter`](struct.Nodeset.html#method.document_order_iter).
+#[derive(Debug, Clone)]
+pub struct DocumentOrderIter<'d> {
+    iter: std::vec::IntoIter<Node<'d>>,
+}
+
+impl<'d> Iterator for DocumentOrderIter<'d> {
+    type Item = Node<'d>;
+
+    fn next(&mut self) -> Option<Node<'d>> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'d> DoubleEndedIterator for DocumentOrderIter<'d> {
+    fn next_back(&mut self) -> Option<Node<'d>> {
+        self.iter.next_back()
+    }
+}
+
+impl<'d> ExactSizeIterator for DocumentOrderIter<'d> {}
+
 pub struct Iter<'a, 'd> {
     iter: hash_set::Iter<'a, Node<'d>>,
 }
@@ -487,6 +832,130 @@ impl<'d> Iterator for IntoIter<'d> {
     }
 }
 
+/// Unique nodes, always kept in [document order].
+///
+/// A [`Nodeset`][] has no order of its own; this is a nodeset that has
+/// been sorted once, so it can be shown, indexed and iterated in the
+/// order the nodes appear in the document. Evaluate an XPath with
+/// [`XPath::evaluate_as`][] to get one directly:
+///
+/// ```
+/// use sxd_document::parser;
+/// use sxd_xpath::nodeset::OrderedNodeset;
+/// use sxd_xpath::{Context, Factory};
+///
+/// let package = parser::parse("<list><item>1</item><item>2</item></list>")
+///     .expect("failed to parse XML");
+/// let document = package.as_document();
+///
+/// let xpath = Factory::new().build("//item[2] | //item[1]").expect("Could not compile XPath");
+/// let items: OrderedNodeset<'_> = xpath
+///     .evaluate_as(&Context::new(), document.root())
+///     .expect("XPath evaluation failed");
+///
+/// let text: Vec<_> = items.iter().map(|n| n.string_value()).collect();
+/// assert_eq!(vec!["1", "2"], text);
+/// assert_eq!(Some("2".to_owned()), items.last().map(|n| n.string_value()));
+/// ```
+///
+/// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
+/// [`Nodeset`]: struct.Nodeset.html
+/// [`XPath::evaluate_as`]: ../struct.XPath.html#method.evaluate_as
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderedNodeset<'d>(Vec<Node<'d>>);
+
+impl<'d> OrderedNodeset<'d> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The node at the zero-based position, in document order.
+    pub fn get(&self, index: usize) -> Option<Node<'d>> {
+        self.0.get(index).copied()
+    }
+
+    pub fn first(&self) -> Option<Node<'d>> {
+        self.0.first().copied()
+    }
+
+    pub fn last(&self) -> Option<Node<'d>> {
+        self.0.last().copied()
+    }
+
+    pub fn contains<N>(&self, node: N) -> bool
+    where
+        N: Into<Node<'d>>,
+    {
+        self.0.contains(&node.into())
+    }
+
+    pub fn iter(&self) -> std::iter::Copied<std::slice::Iter<'_, Node<'d>>> {
+        self.0.iter().copied()
+    }
+
+    pub fn as_slice(&self) -> &[Node<'d>] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<Node<'d>> {
+        self.0
+    }
+}
+
+impl<'d> From<Nodeset<'d>> for OrderedNodeset<'d> {
+    fn from(other: Nodeset<'d>) -> Self {
+        OrderedNodeset(other.document_order())
+    }
+}
+
+impl<'a, 'd> From<&'a Nodeset<'d>> for OrderedNodeset<'d> {
+    fn from(other: &'a Nodeset<'d>) -> Self {
+        OrderedNodeset(other.document_order())
+    }
+}
+
+impl<'d> From<OrderedNodeset<'d>> for Nodeset<'d> {
+    fn from(other: OrderedNodeset<'d>) -> Self {
+        Nodeset::from_document_order(other.0)
+    }
+}
+
+/// Sorts the nodes into document order and drops any duplicates.
+impl<'d> FromIterator<Node<'d>> for OrderedNodeset<'d> {
+    fn from_iter<I>(iterator: I) -> Self
+    where
+        I: IntoIterator<Item = Node<'d>>,
+    {
+        iterator.into_iter().collect::<Nodeset<'_>>().into()
+    }
+}
+
+impl<'d> IntoIterator for OrderedNodeset<'d> {
+    type Item = Node<'d>;
+    type IntoIter = std::vec::IntoIter<Node<'d>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, 'd> IntoIterator for &'a OrderedNodeset<'d> {
+    type Item = Node<'d>;
+    type IntoIter = std::iter::Copied<std::slice::Iter<'a, Node<'d>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct OrderedNodes<'d>(Vec<Node<'d>>);
 
@@ -527,16 +996,186 @@ impl<'d> FromIterator<Node<'d>> for OrderedNodes<'d> {
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
+    use std::cmp::Ordering;
 
     use sxd_document::Package;
 
     use super::Node::*;
-    use super::{Node, Nodeset};
+    use super::{Node, Nodeset, OrderedNodeset};
 
     fn into_node<'d, T: Into<Node<'d>>>(n: T) -> Node<'d> {
         n.into()
     }
 
+    #[test]
+    fn nodeset_can_be_built_from_nodes() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = doc.create_element("element");
+        let t = doc.create_text("text");
+
+        let from_vec = Nodeset::from(vec![into_node(e), into_node(t), into_node(e)]);
+        let collected: Nodeset<'_> = vec![e, e].into_iter().map(into_node).collect();
+
+        assert_eq!(2, from_vec.size());
+        assert!(from_vec.contains(t));
+        assert_eq!(nodeset![e], collected);
+    }
+
+    #[test]
+    fn nodeset_can_add_many_nodes_at_once() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = doc.create_element("element");
+        let t = doc.create_text("text");
+        let c = doc.create_comment("comment");
+
+        let mut nodes = Nodeset::with_capacity(1);
+        nodes.insert_all(vec![e, e]);
+        nodes.insert_all(Some(t));
+        nodes.extend(&[into_node(c), into_node(e)]);
+
+        assert_eq!(nodeset![e, t, c], nodes);
+    }
+
+    #[test]
+    fn nodeset_reserves_room_for_more_nodes() {
+        let mut nodes = Nodeset::new();
+        nodes.reserve(100);
+        assert!(nodes.nodes.capacity() >= 100);
+        assert_eq!(0, nodes.size());
+    }
+
+    #[test]
+    fn nodeset_iterates_in_document_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let parent = doc.create_element("parent");
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        let c = doc.create_element("c");
+        doc.root().append_child(parent);
+        parent.append_child(a);
+        parent.append_child(b);
+        parent.append_child(c);
+
+        let mut nodes = nodeset![c, a];
+        let expected = vec![into_node(a), into_node(c)];
+        assert_eq!(expected, nodes.document_order_iter().collect::<Vec<_>>());
+        assert_eq!(expected, nodes.document_order_iter().collect::<Vec<_>>());
+        assert_eq!(
+            vec![into_node(c), into_node(a)],
+            nodes.document_order_iter().rev().collect::<Vec<_>>()
+        );
+
+        // Adding a node sorts the nodes again
+        nodes.add(b);
+        let expected = vec![into_node(a), into_node(b), into_node(c)];
+        assert_eq!(expected, nodes.document_order_iter().collect::<Vec<_>>());
+        assert_eq!(expected, nodes.document_order());
+    }
+
+    #[test]
+    fn nodeset_equality_ignores_the_sorted_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = doc.create_element("element");
+
+        let sorted = nodeset![e];
+        sorted.document_order_iter().for_each(drop);
+        assert_eq!(nodeset![e], sorted);
+    }
+
+    #[test]
+    fn nodeset_keeps_the_nodes_a_predicate_accepts() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let parent = doc.create_element("parent");
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        let t = doc.create_text("text");
+        doc.root().append_child(parent);
+        parent.append_child(a);
+        parent.append_child(t);
+        parent.append_child(b);
+
+        let mut nodes = nodeset![a, t, b];
+        assert_eq!(
+            vec![into_node(a), into_node(t), into_node(b)],
+            nodes.document_order()
+        );
+
+        let elements = nodes.filtered(|n| n.element().is_some());
+        assert_eq!(nodeset![a, b], elements);
+        assert_eq!(3, nodes.size());
+
+        nodes.retain(|n| n.element().is_some_and(|e| e.name().local_part() == "b"));
+        assert_eq!(nodeset![b], nodes);
+        assert_eq!(vec![into_node(b)], nodes.document_order());
+    }
+
+    #[test]
+    fn ordered_nodeset_is_in_document_order_without_duplicates() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let parent = doc.create_element("parent");
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        let c = doc.create_element("c");
+        doc.root().append_child(parent);
+        parent.append_child(a);
+        parent.append_child(b);
+        parent.append_child(c);
+
+        let ordered: OrderedNodeset<'_> = vec![c, a, c, b].into_iter().map(into_node).collect();
+        let expected = [into_node(a), into_node(b), into_node(c)];
+
+        assert_eq!(&expected[..], ordered.as_slice());
+        assert_eq!(Some(into_node(b)), ordered.get(1));
+        assert_eq!(Some(into_node(c)), ordered.last());
+        assert!(ordered.contains(a));
+        assert_eq!(ordered, OrderedNodeset::from(nodeset![b, c, a]));
+        assert_eq!(nodeset![a, b, c], Nodeset::from(ordered));
+    }
+
+    #[test]
+    fn nodeset_from_an_ordered_nodeset_keeps_its_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let parent = doc.create_element("parent");
+        let children: Vec<_> = (0..20).map(|_| doc.create_element("child")).collect();
+        doc.root().append_child(parent);
+        for &child in &children {
+            parent.append_child(child);
+        }
+
+        let ordered: OrderedNodeset<'_> = children.iter().rev().map(|&c| into_node(c)).collect();
+        let nodes = Nodeset::from(ordered.clone());
+        assert_eq!(ordered.into_vec(), nodes.document_order());
+    }
+
+    #[test]
+    fn reserving_room_keeps_the_document_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let parent = doc.create_element("parent");
+        let children: Vec<_> = (0..50).map(|_| doc.create_element("child")).collect();
+        doc.root().append_child(parent);
+        for &child in &children {
+            parent.append_child(child);
+        }
+        let expected: Vec<_> = children.iter().map(|&c| into_node(c)).collect();
+
+        let mut nodes: Nodeset<'_> = expected.iter().rev().copied().collect();
+        assert_eq!(expected, nodes.document_order());
+        nodes.reserve(100_000);
+        assert_eq!(expected, nodes.document_order());
+
+        let mut nodes = Nodeset::from_document_order(expected.clone());
+        nodes.reserve(100_000);
+        assert_eq!(expected, nodes.document_order());
+    }
+
     #[test]
     fn nodeset_can_include_all_node_types() {
         let package = Package::new();
@@ -696,6 +1335,30 @@ mod test {
         assert_eq!(Some("target".to_owned()), node.prefixed_name());
     }
 
+    #[test]
+    fn nodes_compare_in_document_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let parent = doc.create_element("parent");
+        let first = doc.create_element("first");
+        let second = doc.create_element("second");
+        doc.root().append_child(parent);
+        parent.append_child(first);
+        parent.append_child(second);
+
+        let attribute = parent.set_attribute_value("name", "value");
+
+        let first = into_node(first);
+        let second = into_node(second);
+        let attribute = into_node(attribute);
+
+        assert_eq!(Ordering::Less, first.compare_document_order(&second));
+        assert_eq!(Ordering::Greater, second.compare_document_order(&first));
+        assert_eq!(Ordering::Equal, first.compare_document_order(&first));
+        assert_eq!(Ordering::Less, attribute.compare_document_order(&first));
+    }
+
     #[test]
     fn string_value_of_element_node_is_concatenation_of_descendant_text_nodes() {
         let package = Package::new();
@@ -757,4 +1420,41 @@ mod test {
         let text: Node<'_> = doc.create_text("hello world").into();
         assert_eq!("hello world", text.string_value());
     }
+
+    #[test]
+    fn paths_select_the_node_they_were_made_from() {
+        let package = sxd_document::parser::parse(
+            "<a><b/><c x='1'>t<!--one--><?p v?><!--two--><b/></c><b>u</b></a>",
+        )
+        .expect("failed to parse XML");
+        let doc = package.as_document();
+
+        let all = match crate::evaluate_xpath(&doc, "//node() | //@*") {
+            Ok(crate::Value::Nodeset(nodes)) => nodes.document_order(),
+            other => panic!("expected nodes, got {:?}", other),
+        };
+        for node in all {
+            let path = node.path();
+            match crate::evaluate_xpath(&doc, &path) {
+                Ok(crate::Value::Nodeset(selected)) => {
+                    assert_eq!(vec![node], selected.document_order(), "{}", path)
+                }
+                other => panic!("{} gave {:?}", path, other),
+            }
+        }
+
+        let c = doc.root().children()[0].element().unwrap().children()[1];
+        let children: Vec<_> = into_node(c).children().iter().map(Node::path).collect();
+        assert_eq!(
+            vec![
+                "/a/c/text()",
+                "/a/c/comment()[1]",
+                "/a/c/processing-instruction('p')",
+                "/a/c/comment()[2]",
+                "/a/c/b",
+            ],
+            children
+        );
+        assert_eq!("/", into_node(doc.root()).path());
+    }
 }
@@ -0,0 +1,248 @@
+//! Rewrites syntax trees so that equivalent XPaths become equal.
+//!
+//! The parser already expands abbreviations such as `//` and `@`, so
+//! [`normalize`][] only has to fold operators whose operands are
+//! numeric literals and put the operands of operators into a fixed
+//! order where that order does not change the result:
+//!
+//! - `>`, `>=`, `gt`, `ge` and `>>` are written as their mirror image,
+//!   such as `b < a` for `a > b`.
+//! - The operands of `=`, `!=`, `eq`, `ne`, `is`, `+` and `*` are
+//!   sorted.
+//! - Chains of `and`, `or` and `|` are flattened and sorted. The order
+//!   in which the operands of `and` and `or` are evaluated is not
+//!   defined by XPath, so only XPaths where one operand fails might
+//!   behave differently.
+//! - Nested sequences are flattened.
+//!
+//! ```
+//! use sxd_xpath::Factory;
+//!
+//! let factory = Factory::new();
+//! let one = factory.build("//item[@price > 2 * 5 and @stock]").expect("Could not compile XPath");
+//! let other = factory
+//!     .build("/descendant-or-self::node()/item[@stock][10 < @price]")
+//!     .expect("Could not compile XPath");
+//! let same = factory
+//!     .build("//item[@stock and 10 < @price]")
+//!     .expect("Could not compile XPath");
+//!
+//! assert!(one.is_equivalent(&same));
+//! assert!(!one.is_equivalent(&other));
+//! ```
+//!
+//! The text of a normalized tree, as written by its `Display`
+//! implementation, can be used as a key to find duplicates among many
+//! XPaths.
+//!
+//! [`normalize`]: fn.normalize.html
+
+use crate::ast::{BinaryOperator, Expr};
+use crate::fold::{self, Fold};
+use crate::Value;
+
+/// Gives the normal form of the expression.
+pub fn normalize(expr: &Expr) -> Expr {
+    Normalizer.fold_expr(expr.clone())
+}
+
+/// Whether the expressions have the same normal form.
+pub fn equivalent(left: &Expr, right: &Expr) -> bool {
+    normalize(left) == normalize(right)
+}
+
+/// Normalizes the children of each expression before the expression.
+struct Normalizer;
+
+impl Fold for Normalizer {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        match fold::fold_expr(self, expr) {
+            Expr::Negation(expression) => match *expression {
+                Expr::Literal(Value::Number(n)) if n != 0.0 => Expr::Literal(Value::Number(-n)),
+                expression => Expr::Negation(Box::new(expression)),
+            },
+            Expr::Binary {
+                operator,
+                left,
+                right,
+            } => normalize_binary(operator, *left, *right),
+            Expr::Sequence(expressions) => {
+                let mut items = Vec::new();
+                for expr in expressions {
+                    match expr {
+                        Expr::Sequence(ref nested) if !nested.is_empty() => {
+                            items.extend(nested.iter().cloned())
+                        }
+                        expr => items.push(expr),
+                    }
+                }
+                Expr::Sequence(items)
+            }
+            expr => expr,
+        }
+    }
+}
+
+fn normalize_binary(operator: BinaryOperator, left: Expr, right: Expr) -> Expr {
+    use crate::ast::BinaryOperator::*;
+
+    if let (&Expr::Literal(Value::Number(l)), &Expr::Literal(Value::Number(r))) = (&left, &right) {
+        if let Some(value) = fold_numbers(operator, l, r) {
+            return Expr::Literal(value);
+        }
+    }
+
+    match operator {
+        GreaterThan => normalize_binary(LessThan, right, left),
+        GreaterThanOrEqual => normalize_binary(LessThanOrEqual, right, left),
+        ValueGreaterThan => normalize_binary(ValueLessThan, right, left),
+        ValueGreaterThanOrEqual => normalize_binary(ValueLessThanOrEqual, right, left),
+        Follows => normalize_binary(Precedes, right, left),
+        And | Or | Union => {
+            let mut operands = Vec::new();
+            flatten(operator, left, &mut operands);
+            flatten(operator, right, &mut operands);
+            sort(&mut operands);
+
+            let mut operands = operands.into_iter();
+            let first = operands.next().expect("Operators have operands");
+            operands.fold(first, |left, right| Expr::binary(operator, left, right))
+        }
+        Equal | NotEqual | ValueEqual | ValueNotEqual | Is | Addition | Multiplication => {
+            let mut operands = vec![left, right];
+            sort(&mut operands);
+            let right = operands.pop().expect("Operators have two operands");
+            let left = operands.pop().expect("Operators have two operands");
+            Expr::binary(operator, left, right)
+        }
+        _ => Expr::binary(operator, left, right),
+    }
+}
+
+/// Only results that a literal can hold are folded. Negative zero and
+/// values that are not finite cannot be written as a literal, so two
+/// of them could be equal while behaving differently.
+fn fold_numbers(operator: BinaryOperator, left: f64, right: f64) -> Option<Value<'static>> {
+    use crate::ast::BinaryOperator::*;
+
+    let number = match operator {
+        Addition => left + right,
+        Subtraction => left - right,
+        Multiplication => left * right,
+        Division => left / right,
+        Remainder => left % right,
+        Equal | ValueEqual => return Some(Value::Boolean(left == right)),
+        NotEqual | ValueNotEqual => return Some(Value::Boolean(left != right)),
+        LessThan | ValueLessThan => return Some(Value::Boolean(left < right)),
+        LessThanOrEqual | ValueLessThanOrEqual => return Some(Value::Boolean(left <= right)),
+        GreaterThan | ValueGreaterThan => return Some(Value::Boolean(left > right)),
+        GreaterThanOrEqual | ValueGreaterThanOrEqual => return Some(Value::Boolean(left >= right)),
+        _ => return None,
+    };
+
+    let writable = number.is_finite() && !(number == 0.0 && number.is_sign_negative());
+    if writable {
+        Some(Value::Number(number))
+    } else {
+        None
+    }
+}
+
+fn flatten(operator: BinaryOperator, expr: Expr, operands: &mut Vec<Expr>) {
+    match expr {
+        Expr::Binary {
+            operator: nested,
+            left,
+            right,
+        } if nested == operator => {
+            flatten(operator, *left, operands);
+            flatten(operator, *right, operands);
+        }
+        expr => operands.push(expr),
+    }
+}
+
+/// Orders expressions by their text, which is the same for equal
+/// expressions.
+fn sort(operands: &mut [Expr]) {
+    operands.sort_by_cached_key(|expr| expr.to_string());
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::context::{self, Context};
+    use crate::Factory;
+
+    fn normalized(xpath: &str) -> String {
+        let built = Factory::new().build(xpath).expect("Unable to build XPath");
+        normalize(built.ast()).to_string()
+    }
+
+    fn assert_equivalent(left: &str, right: &str) {
+        assert_eq!(normalized(left), normalized(right));
+    }
+
+    #[test]
+    fn numeric_literals_are_folded() {
+        assert_eq!("a[3]", normalized("a[1 + 2]").replace("child::", ""));
+        assert_eq!("-6", normalized("-(2 * 3)"));
+        assert_eq!("true()", normalized("2 > 1"));
+        assert_eq!("1 div 0", normalized("1 div 0"));
+        assert_eq!("0 - -1 * 0", normalized("0 - 0 * -1"));
+    }
+
+    #[test]
+    fn mirrored_comparisons_are_the_same() {
+        assert_equivalent("a > b", "b < a");
+        assert_equivalent("a ge 1", "1 le a");
+        assert_equivalent("a >> b", "b << a");
+    }
+
+    #[test]
+    fn operands_of_symmetric_operators_are_sorted() {
+        assert_equivalent("a = 'x'", "'x' = a");
+        assert_equivalent("$a * $b + 1", "1 + $b * $a");
+        assert_ne!(normalized("a - b"), normalized("b - a"));
+        assert_ne!(normalized("a div b"), normalized("b div a"));
+    }
+
+    #[test]
+    fn chains_of_associative_operators_are_flattened() {
+        assert_equivalent("a or (b or c)", "c or b or a");
+        assert_equivalent("a | b | c", "c | (a | b)");
+        assert_ne!(normalized("a and b or c"), normalized("a and (b or c)"));
+    }
+
+    #[test]
+    fn nested_sequences_are_flattened() {
+        assert_equivalent("(1, (2, 3), ())", "(1, 2, 3, ())");
+    }
+
+    #[test]
+    fn normalizing_keeps_the_value() {
+        let package = parser::parse("<a><b>1</b><b>2</b><c>3</c></a>").expect("Unable to parse");
+        let doc = package.as_document();
+        let context = Context::new();
+
+        let xpaths = [
+            "count(//b | //c) > 1 + 1",
+            "//b[. = 2 * 1]",
+            "(//c, (//b, 4 div 2)) = 3 and 1 < 2",
+            "sum(//b) - -(1 - 2)",
+        ];
+        for xpath in xpaths.iter() {
+            let built = Factory::new().build(xpath).expect("Unable to build XPath");
+            let expected = built
+                .evaluate(&context, doc.root())
+                .expect("Unable to evaluate");
+
+            let normal = normalize(built.ast()).compile();
+            let evaluation = context::Evaluation::new(&context, doc.root().into());
+            let actual = normal.evaluate(&evaluation).expect("Unable to evaluate");
+            assert_eq!(expected, actual, "{}", xpath);
+        }
+    }
+}
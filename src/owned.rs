@@ -0,0 +1,418 @@
+//! Values that do not borrow the document they came from.
+//!
+//! A [`Value`][] may hold nodes, so it cannot outlive its document.
+//! An [`OwnedValue`][] keeps an [`OwnedNode`][] for each node instead,
+//! a snapshot of its kind, its name, its attributes, its string value
+//! and its path. It can be stored, returned from anywhere and sent to
+//! other threads, after the document has been dropped:
+//!
+//! ```
+//! use std::thread;
+//!
+//! use sxd_document::parser;
+//! use sxd_xpath::evaluate_xpath;
+//! use sxd_xpath::owned::OwnedValue;
+//!
+//! let owned = {
+//!     let package = parser::parse("<stock><item>Apple</item><item>Pear</item></stock>")
+//!         .expect("failed to parse XML");
+//!     let document = package.as_document();
+//!     let value = evaluate_xpath(&document, "//item").expect("XPath evaluation failed");
+//!     OwnedValue::from(value)
+//! };
+//!
+//! let names = thread::spawn(move || match owned {
+//!     OwnedValue::Nodeset(nodes) => nodes.iter().map(|n| n.string_value().to_owned()).collect(),
+//!     _ => Vec::new(),
+//! });
+//! assert_eq!(vec!["Apple", "Pear"], names.join().expect("thread panicked"));
+//! ```
+//!
+//! [`Value`]: ../enum.Value.html
+//! [`OwnedValue`]: enum.OwnedValue.html
+//! [`OwnedNode`]: struct.OwnedNode.html
+
+use std::f64;
+
+use crate::datetime::{DateTime, Duration};
+use crate::nodeset::Node;
+use crate::tree::{NodeKind, TreeNode};
+use crate::{conv, Item, Value};
+
+/// A [`Value`](../enum.Value.html) with a snapshot of each of its
+/// nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    /// The nodes, in document order
+    Nodeset(Vec<OwnedNode>),
+    Sequence(Vec<OwnedItem>),
+}
+
+/// An [`Item`](../enum.Item.html) with a snapshot of its node, if it is
+/// one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedItem {
+    Node(OwnedNode),
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    DateTime(DateTime),
+    Duration(Duration),
+    Map(Vec<(OwnedItem, OwnedValue)>),
+    Array(Vec<OwnedValue>),
+    /// A function, which may refer to the document and so is only
+    /// described by the number of arguments it takes
+    Function {
+        arity: usize,
+    },
+}
+
+/// What a node was when it was copied out of its document.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OwnedNode {
+    kind: NodeKind,
+    namespace_uri: Option<String>,
+    local_name: Option<String>,
+    name: Option<String>,
+    attributes: Vec<(String, String)>,
+    value: String,
+    path: String,
+}
+
+impl OwnedNode {
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// The namespace of the node's name, if it has one.
+    pub fn namespace_uri(&self) -> Option<&str> {
+        self.namespace_uri.as_deref()
+    }
+
+    /// The node's name without its prefix, for the kinds of node that
+    /// have names.
+    pub fn local_name(&self) -> Option<&str> {
+        self.local_name.as_deref()
+    }
+
+    /// The node's name with the prefix that the document used for it.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The prefixed name and value of each attribute of an element.
+    pub fn attributes(&self) -> &[(String, String)] {
+        &self.attributes
+    }
+
+    /// The value of the attribute with the prefixed name, if the node
+    /// is an element that has it.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| &**value)
+    }
+
+    pub fn string_value(&self) -> &str {
+        &self.value
+    }
+
+    /// The XPath that selected the node from the root of its document.
+    /// See [`Node::path`](../nodeset/enum.Node.html#method.path).
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl<'d> From<Node<'d>> for OwnedNode {
+    fn from(node: Node<'d>) -> Self {
+        let name = node.expanded_name();
+        OwnedNode {
+            kind: node.kind(),
+            namespace_uri: name.and_then(|n| n.namespace_uri()).map(Into::into),
+            local_name: name.map(|n| n.local_part().into()),
+            name: node.prefixed_name(),
+            attributes: node
+                .attributes()
+                .into_iter()
+                .map(|a| (a.prefixed_name().unwrap_or_default(), a.string_value()))
+                .collect(),
+            value: node.string_value(),
+            path: node.path(),
+        }
+    }
+}
+
+impl OwnedValue {
+    /// Converts the value to a boolean, as `boolean()` would.
+    pub fn boolean(&self) -> bool {
+        match *self {
+            OwnedValue::Nodeset(ref nodes) => !nodes.is_empty(),
+            OwnedValue::Sequence(ref items) => match items[..] {
+                [] => false,
+                [ref item] => item.boolean(),
+                _ => true,
+            },
+            ref atomic => atomic.atomic().boolean(),
+        }
+    }
+
+    /// Converts the value to a number, as `number()` would.
+    pub fn number(&self) -> f64 {
+        match *self {
+            OwnedValue::Nodeset(..) => conv::string_to_number(&self.string()),
+            OwnedValue::Sequence(ref items) => items.first().map_or(f64::NAN, OwnedItem::number),
+            ref atomic => atomic.atomic().number(),
+        }
+    }
+
+    /// Converts the value to a string, as `string()` would.
+    pub fn string(&self) -> String {
+        match *self {
+            OwnedValue::Nodeset(ref nodes) => {
+                nodes.first().map(|n| n.value.clone()).unwrap_or_default()
+            }
+            OwnedValue::Sequence(ref items) => {
+                items.first().map(OwnedItem::string).unwrap_or_default()
+            }
+            ref atomic => atomic.atomic().string(),
+        }
+    }
+
+    /// The value as a `Value`, when it is a boolean, number or string.
+    fn atomic(&self) -> Value<'static> {
+        match *self {
+            OwnedValue::Boolean(b) => Value::Boolean(b),
+            OwnedValue::Number(n) => Value::Number(n),
+            OwnedValue::String(ref s) => Value::String(s.clone()),
+            _ => Value::Sequence(Vec::new()),
+        }
+    }
+}
+
+impl OwnedItem {
+    pub fn boolean(&self) -> bool {
+        match self.atomic() {
+            Some(item) => item.boolean(),
+            None => true,
+        }
+    }
+
+    pub fn number(&self) -> f64 {
+        match *self {
+            OwnedItem::Node(ref node) => conv::string_to_number(&node.value),
+            _ => self.atomic().map_or(f64::NAN, |item| item.number()),
+        }
+    }
+
+    pub fn string(&self) -> String {
+        match *self {
+            OwnedItem::Node(ref node) => node.value.clone(),
+            _ => self.atomic().map(|item| item.string()).unwrap_or_default(),
+        }
+    }
+
+    /// The item as an `Item`, when it is an atomic value.
+    fn atomic(&self) -> Option<Item<'static>> {
+        match *self {
+            OwnedItem::Boolean(b) => Some(Item::Boolean(b)),
+            OwnedItem::Number(n) => Some(Item::Number(n)),
+            OwnedItem::String(ref s) => Some(Item::String(s.clone())),
+            OwnedItem::DateTime(d) => Some(Item::DateTime(d)),
+            OwnedItem::Duration(d) => Some(Item::Duration(d)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, 'd> From<&'a Value<'d>> for OwnedValue {
+    fn from(value: &'a Value<'d>) -> Self {
+        match *value {
+            Value::Boolean(b) => OwnedValue::Boolean(b),
+            Value::Number(n) => OwnedValue::Number(n),
+            Value::String(ref s) => OwnedValue::String(s.clone()),
+            Value::Nodeset(ref nodes) => {
+                OwnedValue::Nodeset(nodes.document_order().into_iter().map(Into::into).collect())
+            }
+            Value::Sequence(ref items) => {
+                OwnedValue::Sequence(items.iter().map(Into::into).collect())
+            }
+        }
+    }
+}
+
+impl<'d> From<Value<'d>> for OwnedValue {
+    fn from(value: Value<'d>) -> Self {
+        match value {
+            Value::String(s) => OwnedValue::String(s),
+            value => OwnedValue::from(&value),
+        }
+    }
+}
+
+impl<'a, 'd> From<&'a Item<'d>> for OwnedItem {
+    fn from(item: &'a Item<'d>) -> Self {
+        match *item {
+            Item::Node(node) => OwnedItem::Node(node.into()),
+            Item::Boolean(b) => OwnedItem::Boolean(b),
+            Item::Number(n) => OwnedItem::Number(n),
+            Item::String(ref s) => OwnedItem::String(s.clone()),
+            Item::DateTime(d) => OwnedItem::DateTime(d),
+            Item::Duration(d) => OwnedItem::Duration(d),
+            Item::Map(ref map) => {
+                OwnedItem::Map(map.iter().map(|(k, v)| (k.into(), v.into())).collect())
+            }
+            Item::Array(ref members) => OwnedItem::Array(members.iter().map(Into::into).collect()),
+            Item::Function(ref f) => OwnedItem::Function { arity: f.arity() },
+        }
+    }
+}
+
+impl<'d> From<Item<'d>> for OwnedItem {
+    fn from(item: Item<'d>) -> Self {
+        OwnedItem::from(&item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::evaluate_xpath;
+
+    fn owned(xml: &str, xpath: &str) -> OwnedValue {
+        let package = parser::parse(xml).expect("failed to parse XML");
+        let document = package.as_document();
+        let value = evaluate_xpath(&document, xpath).expect("XPath evaluation failed");
+        OwnedValue::from(value)
+    }
+
+    #[test]
+    fn nodes_keep_their_kind_name_and_value() {
+        let value = owned(
+            "<a xmlns:p='urn:p' p:x='1'>t<p:b>u</p:b></a>",
+            "/a/* | /a/@* | /a/text()",
+        );
+        let nodes = match value {
+            OwnedValue::Nodeset(nodes) => nodes,
+            other => panic!("expected nodes, got {:?}", other),
+        };
+
+        let described: Vec<_> = nodes
+            .iter()
+            .map(|n| {
+                (
+                    n.kind(),
+                    n.namespace_uri(),
+                    n.local_name(),
+                    n.name(),
+                    n.string_value(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    NodeKind::Attribute,
+                    Some("urn:p"),
+                    Some("x"),
+                    Some("p:x"),
+                    "1"
+                ),
+                (NodeKind::Text, None, None, None, "t"),
+                (
+                    NodeKind::Element,
+                    Some("urn:p"),
+                    Some("b"),
+                    Some("p:b"),
+                    "u"
+                ),
+            ],
+            described
+        );
+    }
+
+    #[test]
+    fn structured_items_are_copied() {
+        let value = owned("<a>x</a>", "(map { 'k': [/a] }, function($v) { $v })");
+        let node = OwnedNode {
+            kind: NodeKind::Element,
+            namespace_uri: None,
+            local_name: Some("a".into()),
+            name: Some("a".into()),
+            attributes: Vec::new(),
+            value: "x".into(),
+            path: "/a".into(),
+        };
+
+        assert_eq!(
+            OwnedValue::Sequence(vec![
+                OwnedItem::Map(vec![(
+                    OwnedItem::String("k".into()),
+                    OwnedValue::Sequence(vec![OwnedItem::Array(vec![OwnedValue::Nodeset(vec![
+                        node
+                    ])])]),
+                )]),
+                OwnedItem::Function { arity: 1 },
+            ]),
+            value
+        );
+    }
+
+    #[test]
+    fn conversions_are_the_same_as_for_values() {
+        let xml = "<a><b>12</b><b>3</b></a>";
+        for xpath in &[
+            "//b",
+            "(//b, 1)",
+            "()",
+            "1 div 3",
+            "'x'",
+            "true()",
+            "xs:date('2020-01-02')",
+        ] {
+            let package = parser::parse(xml).expect("failed to parse XML");
+            let document = package.as_document();
+            let value = evaluate_xpath(&document, xpath).expect("XPath evaluation failed");
+            let owned = OwnedValue::from(&value);
+
+            assert_eq!(value.boolean(), owned.boolean(), "{}", xpath);
+            assert_eq!(value.string(), owned.string(), "{}", xpath);
+            let (number, expected) = (owned.number(), value.number());
+            assert!(
+                number == expected || number.is_nan() && expected.is_nan(),
+                "{}",
+                xpath
+            );
+        }
+    }
+
+    #[test]
+    fn owned_values_can_be_sent_between_threads() {
+        fn assert_send<T: Send + Sync + 'static>() {}
+        assert_send::<OwnedValue>();
+    }
+
+    #[test]
+    fn elements_keep_their_attributes_and_path() {
+        let value = owned(
+            "<list xmlns:p='urn:p'><item/><item p:id='2' n='x'>t<!--c--></item></list>",
+            "//item[2]",
+        );
+        let item = match value {
+            OwnedValue::Nodeset(ref nodes) => &nodes[0],
+            ref other => panic!("expected nodes, got {:?}", other),
+        };
+
+        assert_eq!("/list/item[2]", item.path());
+        assert_eq!(Some("2"), item.attribute("p:id"));
+        assert_eq!(Some("x"), item.attribute("n"));
+        assert_eq!(None, item.attribute("id"));
+        assert_eq!(2, item.attributes().len());
+    }
+}
@@ -1,16 +1,28 @@
 use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::fmt;
 use std::iter::Peekable;
-
-use crate::axis::{Axis, AxisLike, PrincipalNodeType};
-use crate::expression::{self, SubExpression};
-use crate::node_test::{self, SubNodeTest};
+use std::ops::Range;
+
+use crate::ast::{
+    self, BinaryOperator, Expr, ItemType, KindTest, LookupKey, Occurrence, Quantifier,
+    SequenceType, SingleType,
+};
+use crate::axis::Axis;
+use crate::expression;
+use crate::node_test;
 use crate::token::{AxisName, NodeTestName, Token};
 use crate::tokenizer::{self, TokenResult};
-use crate::Value;
+use crate::{OwnedPrefixedName, Value};
 
 #[allow(missing_copy_implementations)]
 pub struct Parser;
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
     pub fn new() -> Parser {
         Parser
@@ -32,28 +44,166 @@ pub enum Error {
     RightHandSideExpressionMissing,
     /// function argument is missing
     ArgumentMissing,
+    /// map entry is missing
+    MapEntryMissing,
+    /// array member is missing
+    ArrayMemberMissing,
     #[snafu(display("tokenizer error: {}", source))]
     Tokenizer { source: tokenizer::Error },
     /// trailing slash
     TrailingSlash,
     #[snafu(display("unexpected token: {:?}", token))]
     UnexpectedToken { token: Token },
+    #[snafu(display("{}", source))]
+    UnknownName { source: expression::Error },
 }
 
-pub type ParseResult<T = Option<SubExpression>> = Result<T, Error>;
-
-type BinaryExpressionBuilder = fn(SubExpression, SubExpression) -> SubExpression;
+pub type ParseResult<T = Option<Expr>> = Result<T, Error>;
 
 struct BinaryRule {
     token: Token,
-    builder: BinaryExpressionBuilder,
+    operator: BinaryOperator,
 }
 
 struct LeftAssociativeBinaryParser {
     rules: Vec<BinaryRule>,
 }
 
-type TokenSource<'a, I> = &'a mut Peekable<I>;
+/// A token that the parser could have used. For the tokens that
+/// carry a value, any token of that kind could have been used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    Token(Token),
+    Axis,
+    Function,
+    Literal,
+    NameTest,
+    NodeTest,
+    Number,
+    Variable,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Expected::Token(ref token) => match tokenizer::spelling(token) {
+                Some(spelling) => write!(f, "`{}`", spelling),
+                None => write!(f, "{:?}", token),
+            },
+            Expected::Axis => write!(f, "an axis"),
+            Expected::Function => write!(f, "a function call"),
+            Expected::Literal => write!(f, "a string"),
+            Expected::NameTest => write!(f, "a name"),
+            Expected::NodeTest => write!(f, "a node test"),
+            Expected::Number => write!(f, "a number"),
+            Expected::Variable => write!(f, "a variable"),
+        }
+    }
+}
+
+/// The tokens that a node of the syntax tree was parsed from, and the
+/// same for each of its parts. The parts are in the order that
+/// `spanned::children` gives them.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TokenTree {
+    pub(crate) tokens: Range<usize>,
+    pub(crate) children: Vec<TokenTree>,
+}
+
+/// Where the parser was when it started on a node, to find the tokens
+/// and the parts of the node once it has been parsed.
+#[derive(Debug, Copy, Clone)]
+struct Mark {
+    position: usize,
+    nodes: usize,
+}
+
+/// The tokens being parsed, along with the tokens that were looked
+/// for where the parser is.
+struct Source<I>
+where
+    I: Iterator,
+{
+    tokens: Peekable<I>,
+    expected: Vec<Expected>,
+    // The expected tokens were looked for before the last token was
+    // used, and are kept until others are looked for
+    used: bool,
+    // The number of tokens used
+    position: usize,
+    // The nodes that have been parsed but are not yet part of another
+    nodes: Vec<TokenTree>,
+}
+
+impl<I> Source<I>
+where
+    I: Iterator<Item = TokenResult>,
+{
+    fn new(tokens: I) -> Self {
+        Source {
+            tokens: tokens.peekable(),
+            expected: Vec::new(),
+            used: false,
+            position: 0,
+            nodes: Vec::new(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&TokenResult> {
+        self.tokens.peek()
+    }
+
+    fn next(&mut self) -> Option<TokenResult> {
+        self.used = true;
+        self.position += 1;
+        self.tokens.next()
+    }
+
+    fn mark(&self) -> Mark {
+        Mark {
+            position: self.position,
+            nodes: self.nodes.len(),
+        }
+    }
+
+    /// Records a node made of the tokens used since the mark, whose
+    /// parts are the nodes recorded since the mark. A node that was
+    /// not written, such as the start of a relative path, is recorded
+    /// right after marking and has no tokens.
+    fn close(&mut self, mark: Mark) {
+        let children = self.nodes.split_off(mark.nodes);
+        self.nodes.push(TokenTree {
+            tokens: mark.position..self.position,
+            children,
+        });
+    }
+
+    /// Forgets the nodes recorded since the mark, when they did not
+    /// become part of the syntax tree.
+    fn reset(&mut self, mark: Mark) {
+        self.nodes.truncate(mark.nodes);
+    }
+
+    /// Puts the function of a dynamic call written with `=>`, which
+    /// comes after its first argument, first.
+    fn swap_first_parts(&mut self) {
+        if let Some(node) = self.nodes.last_mut() {
+            node.children.swap(0, 1);
+        }
+    }
+
+    fn expect(&mut self, expected: Expected) {
+        if self.used {
+            self.expected.clear();
+            self.used = false;
+        }
+        if !self.expected.contains(&expected) {
+            self.expected.push(expected);
+        }
+    }
+}
+
+type TokenSource<'a, I> = &'a mut Source<I>;
 
 trait XCompat {
     fn has_more_tokens(&mut self) -> bool;
@@ -61,7 +211,7 @@ trait XCompat {
     fn consume(&mut self, token: &Token) -> Result<(), Error>;
 }
 
-impl<I> XCompat for Peekable<I>
+impl<I> XCompat for Source<I>
 where
     I: Iterator<Item = TokenResult>,
 {
@@ -70,13 +220,15 @@ where
     }
 
     fn next_token_is(&mut self, token: &Token) -> bool {
+        self.expect(Expected::Token(token.clone()));
         match self.peek() {
-            Some(&Ok(ref t)) => t == token,
+            Some(Ok(t)) => t == token,
             _ => false,
         }
     }
 
     fn consume(&mut self, token: &Token) -> Result<(), Error> {
+        self.expect(Expected::Token(token.clone()));
         let x = self.next().context(RanOutOfInput)?.context(Tokenizer)?;
         ensure!(&x == token, UnexpectedToken { token: x });
         Ok(())
@@ -87,6 +239,7 @@ where
 /// single value.
 macro_rules! consume_value(
     ($source:expr, Token::$token:ident) => ({
+        $source.expect(Expected::$token);
         let next = $source.next().context(RanOutOfInput)?.context(Tokenizer)?;
 
         match next {
@@ -99,12 +252,13 @@ macro_rules! consume_value(
 /// Similar to `next_token_is`, but can be used when the token carries
 /// a single value
 macro_rules! next_token_is(
-    ($source:expr, Token::$token:ident) => (
+    ($source:expr, Token::$token:ident) => ({
+        $source.expect(Expected::$token);
         match $source.peek() {
             Some(&Ok(Token::$token(_))) => true,
             _ => false,
         }
-    );
+    });
 );
 
 impl LeftAssociativeBinaryParser {
@@ -117,6 +271,7 @@ impl LeftAssociativeBinaryParser {
         F: Fn(TokenSource<'_, I>) -> ParseResult,
         I: Iterator<Item = TokenResult>,
     {
+        let mark = source.mark();
         let left = child_parse(source)?;
 
         let mut left = match left {
@@ -133,7 +288,8 @@ impl LeftAssociativeBinaryParser {
 
                     let right = child_parse(source)?.context(RightHandSideExpressionMissing)?;
 
-                    left = (rule.builder)(left, right);
+                    left = Expr::binary(rule.operator, left, right);
+                    source.close(mark);
 
                     found = true;
                     break;
@@ -192,7 +348,7 @@ impl Parser {
         }
     }
 
-    fn parse_node_test<I>(&self, source: TokenSource<'_, I>) -> Result<Option<SubNodeTest>, Error>
+    fn parse_node_test<I>(&self, source: TokenSource<'_, I>) -> Result<Option<ast::NodeTest>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
@@ -200,12 +356,12 @@ impl Parser {
             let name = consume_value!(source, Token::NodeTest);
 
             match name {
-                NodeTestName::Node => Ok(Some(Box::new(node_test::Node))),
-                NodeTestName::Text => Ok(Some(Box::new(node_test::Text))),
-                NodeTestName::Comment => Ok(Some(Box::new(node_test::Comment))),
-                NodeTestName::ProcessingInstruction(target) => Ok(Some(Box::new(
-                    node_test::ProcessingInstruction::new(target),
-                ))),
+                NodeTestName::Node => Ok(Some(ast::NodeTest::Node)),
+                NodeTestName::Text => Ok(Some(ast::NodeTest::Text)),
+                NodeTestName::Comment => Ok(Some(ast::NodeTest::Comment)),
+                NodeTestName::ProcessingInstruction(target) => {
+                    Ok(Some(ast::NodeTest::ProcessingInstruction(target)))
+                }
             }
         } else {
             Ok(None)
@@ -215,21 +371,13 @@ impl Parser {
     fn default_node_test<I>(
         &self,
         source: TokenSource<'_, I>,
-        axis: Axis,
-    ) -> Result<Option<SubNodeTest>, Error>
+    ) -> Result<Option<ast::NodeTest>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
         if next_token_is!(source, Token::NameTest) {
             let name = consume_value!(source, Token::NameTest);
-
-            let test: SubNodeTest = match axis.principal_node_type() {
-                PrincipalNodeType::Attribute => Box::new(node_test::Attribute::new(name)),
-                PrincipalNodeType::Element => Box::new(node_test::Element::new(name)),
-                PrincipalNodeType::Namespace => Box::new(node_test::Namespace::new(name)),
-            };
-
-            Ok(Some(test))
+            Ok(Some(ast::NodeTest::Name(name)))
         } else {
             Ok(None)
         }
@@ -240,10 +388,18 @@ impl Parser {
         I: Iterator<Item = TokenResult>,
     {
         if source.next_token_is(&Token::LeftParen) {
+            let mark = source.mark();
             source.consume(&Token::LeftParen)?;
-            let result = self.parse_expression(source)?;
+            let result = self.parse_sequence_expression(source)?;
             source.consume(&Token::RightParen)?;
-            Ok(result)
+
+            match result {
+                Some(result) => Ok(Some(result)),
+                None => {
+                    source.close(mark);
+                    Ok(Some(Expr::Sequence(Vec::new())))
+                }
+            }
         } else {
             Ok(None)
         }
@@ -254,8 +410,10 @@ impl Parser {
         I: Iterator<Item = TokenResult>,
     {
         if next_token_is!(source, Token::Variable) {
+            let mark = source.mark();
             let name = consume_value!(source, Token::Variable);
-            Ok(Some(Box::new(expression::Variable { name })))
+            source.close(mark);
+            Ok(Some(Expr::Variable(name)))
         } else {
             Ok(None)
         }
@@ -266,10 +424,10 @@ impl Parser {
         I: Iterator<Item = TokenResult>,
     {
         if next_token_is!(source, Token::Literal) {
+            let mark = source.mark();
             let value = consume_value!(source, Token::Literal);
-            Ok(Some(Box::new(expression::Literal::from(Value::String(
-                value,
-            )))))
+            source.close(mark);
+            Ok(Some(Expr::Literal(Value::String(value))))
         } else {
             Ok(None)
         }
@@ -280,10 +438,10 @@ impl Parser {
         I: Iterator<Item = TokenResult>,
     {
         if next_token_is!(source, Token::Number) {
+            let mark = source.mark();
             let value = consume_value!(source, Token::Number);
-            Ok(Some(Box::new(expression::Literal::from(Value::Number(
-                value,
-            )))))
+            source.close(mark);
+            Ok(Some(Expr::Literal(Value::Number(value))))
         } else {
             Ok(None)
         }
@@ -292,8 +450,8 @@ impl Parser {
     fn parse_function_args_tail<I>(
         &self,
         source: TokenSource<'_, I>,
-        mut arguments: Vec<SubExpression>,
-    ) -> Result<Vec<SubExpression>, Error>
+        mut arguments: Vec<Expr>,
+    ) -> Result<Vec<Expr>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
@@ -307,10 +465,7 @@ impl Parser {
         Ok(arguments)
     }
 
-    fn parse_function_args<I>(
-        &self,
-        source: TokenSource<'_, I>,
-    ) -> Result<Vec<SubExpression>, Error>
+    fn parse_function_args<I>(&self, source: TokenSource<'_, I>) -> Result<Vec<Expr>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
@@ -329,18 +484,186 @@ impl Parser {
         I: Iterator<Item = TokenResult>,
     {
         if next_token_is!(source, Token::Function) {
+            let mark = source.mark();
             let name = consume_value!(source, Token::Function);
 
             source.consume(&Token::LeftParen)?;
             let arguments = self.parse_function_args(source)?;
             source.consume(&Token::RightParen)?;
+            source.close(mark);
 
-            Ok(Some(Box::new(expression::Function { name, arguments })))
+            Ok(Some(Expr::Function { name, arguments }))
         } else {
             Ok(None)
         }
     }
 
+    fn parse_map_constructor<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::Map) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::Map)?;
+        source.consume(&Token::LeftBrace)?;
+
+        let mut entries = Vec::new();
+        if !source.next_token_is(&Token::RightBrace) {
+            loop {
+                let key = self.parse_expression(source)?.context(MapEntryMissing)?;
+                source.consume(&Token::Colon)?;
+                let value = self.parse_expression(source)?.context(MapEntryMissing)?;
+                entries.push((key, value));
+
+                if !source.next_token_is(&Token::Comma) {
+                    break;
+                }
+                source.consume(&Token::Comma)?;
+            }
+        }
+        source.consume(&Token::RightBrace)?;
+        source.close(mark);
+
+        Ok(Some(Expr::Map(entries)))
+    }
+
+    fn parse_square_array_constructor<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::LeftBracket) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::LeftBracket)?;
+
+        let mut members = Vec::new();
+        if !source.next_token_is(&Token::RightBracket) {
+            loop {
+                let member = self.parse_expression(source)?.context(ArrayMemberMissing)?;
+                members.push(member);
+
+                if !source.next_token_is(&Token::Comma) {
+                    break;
+                }
+                source.consume(&Token::Comma)?;
+            }
+        }
+        source.consume(&Token::RightBracket)?;
+        source.close(mark);
+
+        Ok(Some(Expr::SquareArray(members)))
+    }
+
+    fn parse_curly_array_constructor<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::Array) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::Array)?;
+        source.consume(&Token::LeftBrace)?;
+        let expr = self.parse_empty_or_sequence_expression(source)?;
+        source.consume(&Token::RightBrace)?;
+        source.close(mark);
+
+        Ok(Some(Expr::CurlyArray(Box::new(expr))))
+    }
+
+    fn parse_lookup<I>(&self, source: TokenSource<'_, I>) -> ParseResult<Option<LookupKey>>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::Lookup) {
+            return Ok(None);
+        }
+
+        source.consume(&Token::Lookup)?;
+
+        if let Some(expr) = self.parse_nested_expression(source)? {
+            return Ok(Some(LookupKey::Expression(Box::new(expr))));
+        }
+        if let Some(expr) = self.parse_numeric_literal(source)? {
+            return Ok(Some(LookupKey::Expression(Box::new(expr))));
+        }
+
+        let name = consume_value!(source, Token::NameTest);
+        match name.prefix {
+            None if name.local_part == "*" => Ok(Some(LookupKey::Wildcard)),
+            None => Ok(Some(LookupKey::Name(name.local_part))),
+            Some(_) => UnexpectedToken {
+                token: Token::NameTest(name),
+            }
+            .fail(),
+        }
+    }
+
+    fn parse_inline_function<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::InlineFunction) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::InlineFunction)?;
+        source.consume(&Token::LeftParen)?;
+
+        let mut parameters = Vec::new();
+        if !source.next_token_is(&Token::RightParen) {
+            loop {
+                parameters.push(consume_value!(source, Token::Variable));
+
+                if !source.next_token_is(&Token::Comma) {
+                    break;
+                }
+                source.consume(&Token::Comma)?;
+            }
+        }
+        source.consume(&Token::RightParen)?;
+
+        source.consume(&Token::LeftBrace)?;
+        let body = self.parse_empty_or_sequence_expression(source)?;
+        source.consume(&Token::RightBrace)?;
+        source.close(mark);
+
+        Ok(Some(Expr::InlineFunction {
+            parameters,
+            body: Box::new(body),
+        }))
+    }
+
+    /// A lookup without an expression applies to the context item
+    fn parse_unary_lookup<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mark = source.mark();
+        source.close(mark);
+
+        match self.parse_lookup(source)? {
+            Some(key) => {
+                source.close(mark);
+                Ok(Some(Expr::Lookup {
+                    expression: Box::new(Expr::ContextNode),
+                    key,
+                }))
+            }
+            None => {
+                source.reset(mark);
+                Ok(None)
+            }
+        }
+    }
+
     fn parse_primary_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
@@ -351,6 +674,11 @@ impl Parser {
             &|src: TokenSource<'_, I>| self.parse_string_literal(src),
             &|src: TokenSource<'_, I>| self.parse_numeric_literal(src),
             &|src: TokenSource<'_, I>| self.parse_function_call(src),
+            &|src: TokenSource<'_, I>| self.parse_map_constructor(src),
+            &|src: TokenSource<'_, I>| self.parse_square_array_constructor(src),
+            &|src: TokenSource<'_, I>| self.parse_curly_array_constructor(src),
+            &|src: TokenSource<'_, I>| self.parse_unary_lookup(src),
+            &|src: TokenSource<'_, I>| self.parse_inline_function(src),
         ];
 
         first_matching_rule(rules, source)
@@ -363,7 +691,9 @@ impl Parser {
         if source.next_token_is(&Token::LeftBracket) {
             source.consume(&Token::LeftBracket)?;
 
-            let predicate = self.parse_expression(source)?.context(EmptyPredicate)?;
+            let predicate = self
+                .parse_sequence_expression(source)?
+                .context(EmptyPredicate)?;
             source.consume(&Token::RightBracket)?;
             Ok(Some(predicate))
         } else {
@@ -371,7 +701,7 @@ impl Parser {
         }
     }
 
-    fn parse_predicates<I>(&self, source: TokenSource<'_, I>) -> Result<Vec<SubExpression>, Error>
+    fn parse_predicates<I>(&self, source: TokenSource<'_, I>) -> Result<Vec<Expr>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
@@ -384,15 +714,16 @@ impl Parser {
         Ok(predicates)
     }
 
-    fn parse_step<I>(&self, source: TokenSource<'_, I>) -> Result<Option<expression::Step>, Error>
+    fn parse_step<I>(&self, source: TokenSource<'_, I>) -> Result<Option<ast::Step>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
+        let mark = source.mark();
         let axis = self.parse_axis(source)?;
 
         let node_test = match self.parse_node_test(source)? {
             Some(test) => Some(test),
-            None => self.default_node_test(source, axis)?,
+            None => self.default_node_test(source)?,
         };
 
         let node_test = match node_test {
@@ -401,14 +732,21 @@ impl Parser {
         };
 
         let predicates = self.parse_predicates(source)?;
+        source.close(mark);
 
-        Ok(Some(expression::Step::new(axis, node_test, predicates)))
+        Ok(Some(ast::Step {
+            axis,
+            node_test,
+            predicates,
+        }))
     }
 
+    /// The start point was parsed after the mark.
     fn parse_relative_location_path_raw<I>(
         &self,
         source: TokenSource<'_, I>,
-        start_point: SubExpression,
+        mark: Mark,
+        start_point: Expr,
     ) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
@@ -423,8 +761,12 @@ impl Parser {
                     let next = self.parse_step(source)?.context(TrailingSlash)?;
                     steps.push(next);
                 }
+                source.close(mark);
 
-                Ok(Some(expression::Path::new(start_point, steps)))
+                Ok(Some(Expr::Path {
+                    start_point: Box::new(start_point),
+                    steps,
+                }))
             }
             None => Ok(None),
         }
@@ -434,8 +776,32 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
-        let start_point = Box::new(expression::ContextNode);
-        self.parse_relative_location_path_raw(source, start_point)
+        let mark = source.mark();
+        source.close(mark);
+
+        let path = self.parse_relative_location_path_raw(source, mark, Expr::ContextNode)?;
+        if path.is_none() {
+            source.reset(mark);
+        }
+
+        // A lone `.` is the context item, which may also be an atomic
+        // value of a sequence
+        if let Some(Expr::Path { ref steps, .. }) = path {
+            if let [ast::Step {
+                axis: Axis::SelfAxis,
+                node_test: ast::NodeTest::Node,
+                ref predicates,
+            }] = steps[..]
+            {
+                if predicates.is_empty() {
+                    source.reset(mark);
+                    source.close(mark);
+                    return Ok(Some(Expr::ContextNode));
+                }
+            }
+        }
+
+        Ok(path)
     }
 
     fn parse_absolute_location_path<I>(&self, source: TokenSource<'_, I>) -> ParseResult
@@ -443,12 +809,13 @@ impl Parser {
         I: Iterator<Item = TokenResult>,
     {
         if source.next_token_is(&Token::Slash) {
+            let mark = source.mark();
             source.consume(&Token::Slash)?;
+            source.close(mark);
 
-            let start_point = Box::new(expression::RootNode);
-            match self.parse_relative_location_path_raw(source, start_point)? {
+            match self.parse_relative_location_path_raw(source, mark, Expr::RootNode)? {
                 Some(expr) => Ok(Some(expr)),
-                None => Ok(Some(Box::new(expression::RootNode))),
+                None => Ok(Some(Expr::RootNode)),
             }
         } else {
             Ok(None)
@@ -471,15 +838,37 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
-        match self.parse_primary_expression(source)? {
-            Some(expr) => {
-                let predicates = self.parse_predicates(source)?;
+        let mark = source.mark();
+        let mut expr = match self.parse_primary_expression(source)? {
+            Some(expr) => expr,
+            None => return Ok(None),
+        };
 
-                Ok(Some(predicates.into_iter().fold(expr, |expr, pred| {
-                    expression::Filter::new(expr, pred)
-                })))
+        // Predicates, lookups and calls may be mixed, applying in order
+        loop {
+            if let Some(predicate) = self.parse_predicate_expression(source)? {
+                expr = Expr::Filter {
+                    expression: Box::new(expr),
+                    predicate: Box::new(predicate),
+                };
+            } else if let Some(key) = self.parse_lookup(source)? {
+                expr = Expr::Lookup {
+                    expression: Box::new(expr),
+                    key,
+                };
+            } else if source.next_token_is(&Token::LeftParen) {
+                source.consume(&Token::LeftParen)?;
+                let arguments = self.parse_function_args(source)?;
+                source.consume(&Token::RightParen)?;
+
+                expr = Expr::DynamicCall {
+                    function: Box::new(expr),
+                    arguments,
+                };
+            } else {
+                return Ok(Some(expr));
             }
-            None => Ok(None),
+            source.close(mark);
         }
     }
 
@@ -492,13 +881,14 @@ impl Parser {
             return Ok(expr);
         } // TODO: investigate if this is a pattern
 
+        let mark = source.mark();
         match self.parse_filter_expression(source)? {
             Some(expr) => {
                 if source.next_token_is(&Token::Slash) {
                     source.consume(&Token::Slash)?;
 
                     let expr = self
-                        .parse_relative_location_path_raw(source, expr)?
+                        .parse_relative_location_path_raw(source, mark, expr)?
                         .context(TrailingSlash)?;
                     Ok(Some(expr))
                 } else {
@@ -515,7 +905,7 @@ impl Parser {
     {
         let rules = vec![BinaryRule {
             token: Token::Pipe,
-            builder: expression::Union::new,
+            operator: BinaryOperator::Union,
         }];
 
         let parser = LeftAssociativeBinaryParser::new(rules);
@@ -532,13 +922,14 @@ impl Parser {
         }
 
         if source.next_token_is(&Token::MinusSign) {
+            let mark = source.mark();
             source.consume(&Token::MinusSign)?;
 
             let expression = self
                 .parse_unary_expression(source)?
                 .context(RightHandSideExpressionMissing)?;
-            let expression: SubExpression = Box::new(expression::Negation { expression });
-            Ok(Some(expression))
+            source.close(mark);
+            Ok(Some(Expr::Negation(Box::new(expression))))
         } else {
             Ok(None)
         }
@@ -551,162 +942,743 @@ impl Parser {
         let rules = vec![
             BinaryRule {
                 token: Token::Multiply,
-                builder: expression::Math::multiplication,
+                operator: BinaryOperator::Multiplication,
             },
             BinaryRule {
                 token: Token::Divide,
-                builder: expression::Math::division,
+                operator: BinaryOperator::Division,
             },
             BinaryRule {
                 token: Token::Remainder,
-                builder: expression::Math::remainder,
+                operator: BinaryOperator::Remainder,
             },
         ];
 
         let parser = LeftAssociativeBinaryParser::new(rules);
-        parser.parse(source, |source| self.parse_unary_expression(source))
+        parser.parse(source, |source| self.parse_instance_of_expression(source))
     }
 
-    fn parse_additive_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    fn parse_instance_of_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![
-            BinaryRule {
-                token: Token::PlusSign,
-                builder: expression::Math::addition,
-            },
-            BinaryRule {
-                token: Token::MinusSign,
-                builder: expression::Math::subtraction,
-            },
-        ];
+        let mark = source.mark();
+        let expression = match self.parse_treat_expression(source)? {
+            Some(expression) => expression,
+            None => return Ok(None),
+        };
 
-        let parser = LeftAssociativeBinaryParser::new(rules);
-        parser.parse(source, |source| {
-            self.parse_multiplicative_expression(source)
-        })
+        if !source.next_token_is(&Token::InstanceOf) {
+            return Ok(Some(expression));
+        }
+
+        source.consume(&Token::InstanceOf)?;
+        let sequence_type = self.parse_sequence_type(source)?;
+        source.close(mark);
+
+        Ok(Some(Expr::InstanceOf {
+            expression: Box::new(expression),
+            sequence_type,
+        }))
     }
 
-    fn parse_relational_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    fn parse_treat_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![
-            BinaryRule {
-                token: Token::LessThan,
-                builder: expression::Relational::less_than,
-            },
-            BinaryRule {
-                token: Token::LessThanOrEqual,
-                builder: expression::Relational::less_than_or_equal,
-            },
-            BinaryRule {
-                token: Token::GreaterThan,
-                builder: expression::Relational::greater_than,
-            },
-            BinaryRule {
-                token: Token::GreaterThanOrEqual,
-                builder: expression::Relational::greater_than_or_equal,
-            },
-        ];
+        let mark = source.mark();
+        let expression = match self.parse_castable_expression(source)? {
+            Some(expression) => expression,
+            None => return Ok(None),
+        };
 
-        let parser = LeftAssociativeBinaryParser::new(rules);
-        parser.parse(source, |source| self.parse_additive_expression(source))
+        if !source.next_token_is(&Token::TreatAs) {
+            return Ok(Some(expression));
+        }
+
+        source.consume(&Token::TreatAs)?;
+        let sequence_type = self.parse_sequence_type(source)?;
+        source.close(mark);
+
+        Ok(Some(Expr::TreatAs {
+            expression: Box::new(expression),
+            sequence_type,
+        }))
     }
 
-    fn parse_equality_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    fn parse_castable_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![
-            BinaryRule {
-                token: Token::Equal,
-                builder: expression::Equal::new,
-            },
-            BinaryRule {
-                token: Token::NotEqual,
-                builder: expression::NotEqual::new,
-            },
-        ];
+        let mark = source.mark();
+        let expression = match self.parse_cast_expression(source)? {
+            Some(expression) => expression,
+            None => return Ok(None),
+        };
 
-        let parser = LeftAssociativeBinaryParser::new(rules);
-        parser.parse(source, |source| self.parse_relational_expression(source))
+        if !source.next_token_is(&Token::CastableAs) {
+            return Ok(Some(expression));
+        }
+
+        source.consume(&Token::CastableAs)?;
+        let single_type = self.parse_single_type(source)?;
+        source.close(mark);
+
+        Ok(Some(Expr::Castable {
+            expression: Box::new(expression),
+            single_type,
+        }))
     }
 
-    fn parse_and_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    fn parse_cast_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![BinaryRule {
-            token: Token::And,
-            builder: expression::And::new,
-        }];
+        let mark = source.mark();
+        let expression = match self.parse_arrow_expression(source)? {
+            Some(expression) => expression,
+            None => return Ok(None),
+        };
 
-        let parser = LeftAssociativeBinaryParser::new(rules);
-        parser.parse(source, |source| self.parse_equality_expression(source))
+        if !source.next_token_is(&Token::CastAs) {
+            return Ok(Some(expression));
+        }
+
+        source.consume(&Token::CastAs)?;
+        let single_type = self.parse_single_type(source)?;
+        source.close(mark);
+
+        Ok(Some(Expr::Cast {
+            expression: Box::new(expression),
+            single_type,
+        }))
     }
 
-    fn parse_or_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    /// `value => f(args)` calls `f` with the value as its first
+    /// argument. The function may be named or be any expression that
+    /// evaluates to a function item.
+    fn parse_arrow_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![BinaryRule {
-            token: Token::Or,
-            builder: expression::Or::new,
-        }];
+        let mark = source.mark();
+        let mut expression = match self.parse_unary_expression(source)? {
+            Some(expression) => expression,
+            None => return Ok(None),
+        };
 
-        let parser = LeftAssociativeBinaryParser::new(rules);
-        parser.parse(source, |source| self.parse_and_expression(source))
+        while source.next_token_is(&Token::Arrow) {
+            source.consume(&Token::Arrow)?;
+
+            expression = if next_token_is!(source, Token::Function) {
+                let name = consume_value!(source, Token::Function);
+                let arguments = self.parse_arrow_arguments(source, expression)?;
+                source.close(mark);
+                Expr::Function { name, arguments }
+            } else {
+                let function = match self.parse_variable_reference(source)? {
+                    Some(function) => function,
+                    None => self
+                        .parse_nested_expression(source)?
+                        .context(RightHandSideExpressionMissing)?,
+                };
+                let arguments = self.parse_arrow_arguments(source, expression)?;
+                source.close(mark);
+                source.swap_first_parts();
+                Expr::DynamicCall {
+                    function: Box::new(function),
+                    arguments,
+                }
+            };
+        }
+
+        Ok(Some(expression))
     }
 
-    fn parse_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    fn parse_arrow_arguments<I>(
+        &self,
+        source: TokenSource<'_, I>,
+        first: Expr,
+    ) -> Result<Vec<Expr>, Error>
     where
         I: Iterator<Item = TokenResult>,
     {
-        self.parse_or_expression(source)
+        source.consume(&Token::LeftParen)?;
+        let mut arguments = vec![first];
+        arguments.extend(self.parse_function_args(source)?);
+        source.consume(&Token::RightParen)?;
+
+        Ok(arguments)
     }
 
-    pub fn parse<I>(&self, source: I) -> ParseResult<SubExpression>
+    fn parse_single_type<I>(&self, source: TokenSource<'_, I>) -> ParseResult<SingleType>
     where
         I: Iterator<Item = TokenResult>,
     {
-        let mut source = source.peekable();
-
-        let expr = self.parse_or_expression(&mut source)?;
-
-        ensure!(!source.has_more_tokens(), ExtraUnparsedTokens);
+        let name = match consume_value!(source, Token::NameTest) {
+            node_test::NameTest { local_part, .. } if local_part == "*" => {
+                return UnexpectedToken {
+                    token: Token::Multiply,
+                }
+                .fail()
+            }
+            node_test::NameTest { prefix, local_part } => OwnedPrefixedName { prefix, local_part },
+        };
 
-        let expr = expr.context(NoXPath)?;
+        let optional = source.next_token_is(&Token::QuestionMark);
+        if optional {
+            source.consume(&Token::QuestionMark)?;
+        }
 
-        Ok(expr)
+        Ok(SingleType { name, optional })
     }
-}
 
-#[cfg(test)]
-mod test {
-    use snafu::ResultExt;
-    use std::borrow::ToOwned;
-    use sxd_document::dom::{self, Document, Element, Root, Text};
-    use sxd_document::Package;
+    fn parse_sequence_type<I>(&self, source: TokenSource<'_, I>) -> ParseResult<SequenceType>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        source.expect(Expected::NodeTest);
+        source.expect(Expected::NameTest);
+        source.expect(Expected::Function);
+        let token = source.next().context(RanOutOfInput)?.context(Tokenizer)?;
+
+        let item_type = match token {
+            Token::NodeTest(name) => Some(ItemType::Kind(match name {
+                NodeTestName::Node => KindTest::Node,
+                NodeTestName::Text => KindTest::Text,
+                NodeTestName::Comment => KindTest::Comment,
+                NodeTestName::ProcessingInstruction(target) => {
+                    KindTest::ProcessingInstruction(target)
+                }
+            })),
+            Token::NameTest(name) if name.local_part != "*" => {
+                Some(ItemType::Atomic(OwnedPrefixedName {
+                    prefix: name.prefix,
+                    local_part: name.local_part,
+                }))
+            }
+            Token::Function(ref name) if name.prefix.is_none() => {
+                source.consume(&Token::LeftParen)?;
+                let item_type = match name.local_part.as_str() {
+                    "empty-sequence" => None,
+                    "item" => Some(ItemType::Item),
+                    "document-node" => Some(ItemType::Kind(KindTest::Document)),
+                    "element" => Some(ItemType::Kind(KindTest::Element(
+                        self.parse_kind_test_name(source)?,
+                    ))),
+                    "attribute" => Some(ItemType::Kind(KindTest::Attribute(
+                        self.parse_kind_test_name(source)?,
+                    ))),
+                    _ => return UnexpectedToken { token }.fail(),
+                };
+                source.consume(&Token::RightParen)?;
+                item_type
+            }
+            token => return UnexpectedToken { token }.fail(),
+        };
 
-    use crate::context::{self, Context};
-    use crate::expression::{Expression, SubExpression};
-    use crate::node_test;
-    use crate::nodeset::Node;
-    use crate::token::{AxisName, NodeTestName, Token};
-    use crate::tokenizer::{self, TokenResult};
-    use crate::Value;
-    use crate::Value::{Boolean, Number, String};
+        // `empty-sequence()` cannot have an occurrence indicator
+        let occurrence = match item_type {
+            Some(_) => self.parse_occurrence_indicator(source),
+            None => Occurrence::ExactlyOne,
+        };
 
-    use super::*;
+        Ok(SequenceType {
+            item_type,
+            occurrence,
+        })
+    }
+
+    fn parse_kind_test_name<I>(
+        &self,
+        source: TokenSource<'_, I>,
+    ) -> ParseResult<Option<node_test::NameTest>>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if next_token_is!(source, Token::NameTest) {
+            Ok(Some(consume_value!(source, Token::NameTest)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_occurrence_indicator<I>(&self, source: TokenSource<'_, I>) -> Occurrence
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let occurrence = if source.next_token_is(&Token::QuestionMark) {
+            Occurrence::ZeroOrOne
+        } else if source.next_token_is(&Token::Multiply) {
+            Occurrence::ZeroOrMore
+        } else if source.next_token_is(&Token::PlusSign) {
+            Occurrence::OneOrMore
+        } else {
+            return Occurrence::ExactlyOne;
+        };
+
+        source.next();
+        occurrence
+    }
+
+    fn parse_additive_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let rules = vec![
+            BinaryRule {
+                token: Token::PlusSign,
+                operator: BinaryOperator::Addition,
+            },
+            BinaryRule {
+                token: Token::MinusSign,
+                operator: BinaryOperator::Subtraction,
+            },
+        ];
+
+        let parser = LeftAssociativeBinaryParser::new(rules);
+        parser.parse(source, |source| {
+            self.parse_multiplicative_expression(source)
+        })
+    }
+
+    fn parse_relational_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let rules = vec![
+            BinaryRule {
+                token: Token::LessThan,
+                operator: BinaryOperator::LessThan,
+            },
+            BinaryRule {
+                token: Token::LessThanOrEqual,
+                operator: BinaryOperator::LessThanOrEqual,
+            },
+            BinaryRule {
+                token: Token::GreaterThan,
+                operator: BinaryOperator::GreaterThan,
+            },
+            BinaryRule {
+                token: Token::GreaterThanOrEqual,
+                operator: BinaryOperator::GreaterThanOrEqual,
+            },
+            BinaryRule {
+                token: Token::Is,
+                operator: BinaryOperator::Is,
+            },
+            BinaryRule {
+                token: Token::Precedes,
+                operator: BinaryOperator::Precedes,
+            },
+            BinaryRule {
+                token: Token::Follows,
+                operator: BinaryOperator::Follows,
+            },
+            BinaryRule {
+                token: Token::ValueLessThan,
+                operator: BinaryOperator::ValueLessThan,
+            },
+            BinaryRule {
+                token: Token::ValueLessThanOrEqual,
+                operator: BinaryOperator::ValueLessThanOrEqual,
+            },
+            BinaryRule {
+                token: Token::ValueGreaterThan,
+                operator: BinaryOperator::ValueGreaterThan,
+            },
+            BinaryRule {
+                token: Token::ValueGreaterThanOrEqual,
+                operator: BinaryOperator::ValueGreaterThanOrEqual,
+            },
+        ];
+
+        let parser = LeftAssociativeBinaryParser::new(rules);
+        parser.parse(source, |source| self.parse_range_expression(source))
+    }
+
+    fn parse_range_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mark = source.mark();
+        let left = match self.parse_additive_expression(source)? {
+            Some(left) => left,
+            None => return Ok(None),
+        };
+
+        // Ranges do not chain, so `1 to 2 to 3` leaves unparsed tokens
+        if !source.next_token_is(&Token::To) {
+            return Ok(Some(left));
+        }
+
+        source.consume(&Token::To)?;
+        let right = self
+            .parse_additive_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+        source.close(mark);
+
+        Ok(Some(Expr::binary(BinaryOperator::Range, left, right)))
+    }
+
+    fn parse_equality_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let rules = vec![
+            BinaryRule {
+                token: Token::Equal,
+                operator: BinaryOperator::Equal,
+            },
+            BinaryRule {
+                token: Token::NotEqual,
+                operator: BinaryOperator::NotEqual,
+            },
+            BinaryRule {
+                token: Token::ValueEqual,
+                operator: BinaryOperator::ValueEqual,
+            },
+            BinaryRule {
+                token: Token::ValueNotEqual,
+                operator: BinaryOperator::ValueNotEqual,
+            },
+        ];
+
+        let parser = LeftAssociativeBinaryParser::new(rules);
+        parser.parse(source, |source| self.parse_relational_expression(source))
+    }
+
+    fn parse_and_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let rules = vec![BinaryRule {
+            token: Token::And,
+            operator: BinaryOperator::And,
+        }];
+
+        let parser = LeftAssociativeBinaryParser::new(rules);
+        parser.parse(source, |source| self.parse_equality_expression(source))
+    }
+
+    fn parse_or_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let rules = vec![BinaryRule {
+            token: Token::Or,
+            operator: BinaryOperator::Or,
+        }];
+
+        let parser = LeftAssociativeBinaryParser::new(rules);
+        parser.parse(source, |source| self.parse_and_expression(source))
+    }
+
+    fn parse_if_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::If) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::If)?;
+        source.consume(&Token::LeftParen)?;
+        let condition = self
+            .parse_sequence_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+        source.consume(&Token::RightParen)?;
+
+        source.consume(&Token::Then)?;
+        let consequent = self
+            .parse_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+
+        source.consume(&Token::Else)?;
+        let alternative = self
+            .parse_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+        source.close(mark);
+
+        Ok(Some(Expr::If {
+            condition: Box::new(condition),
+            consequent: Box::new(consequent),
+            alternative: Box::new(alternative),
+        }))
+    }
+
+    /// Parses the comma-separated `$name <separator> expression`
+    /// clauses shared by `for`, `let`, and quantified expressions.
+    /// Each clause is marked where it starts, apart from the first,
+    /// which starts at the mark of the whole expression.
+    fn parse_bindings<I>(
+        &self,
+        source: TokenSource<'_, I>,
+        mark: Mark,
+        separator: &Token,
+    ) -> ParseResult<Vec<(Mark, OwnedPrefixedName, Expr)>>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mut bindings = Vec::new();
+        loop {
+            let mark = if bindings.is_empty() {
+                mark
+            } else {
+                source.mark()
+            };
+            let variable = consume_value!(source, Token::Variable);
+            source.consume(separator)?;
+            let expr = self
+                .parse_expression(source)?
+                .context(RightHandSideExpressionMissing)?;
+            bindings.push((mark, variable, expr));
+
+            if !source.next_token_is(&Token::Comma) {
+                return Ok(bindings);
+            }
+            source.consume(&Token::Comma)?;
+        }
+    }
+
+    fn parse_for_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::For) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::For)?;
+        let bindings = self.parse_bindings(source, mark, &Token::In)?;
+
+        source.consume(&Token::Return)?;
+        let body = self
+            .parse_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+
+        // Each additional binding is nested within the previous one
+        let expr = bindings
+            .into_iter()
+            .rev()
+            .fold(body, |body, (mark, variable, sequence)| {
+                source.close(mark);
+                Expr::For {
+                    variable,
+                    sequence: Box::new(sequence),
+                    body: Box::new(body),
+                }
+            });
+
+        Ok(Some(expr))
+    }
+
+    fn parse_let_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        if !source.next_token_is(&Token::Let) {
+            return Ok(None);
+        }
+
+        let mark = source.mark();
+        source.consume(&Token::Let)?;
+        let bindings = self.parse_bindings(source, mark, &Token::Assign)?;
+
+        source.consume(&Token::Return)?;
+        let body = self
+            .parse_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+
+        // Later bindings can refer to earlier ones
+        let expr = bindings
+            .into_iter()
+            .rev()
+            .fold(body, |body, (mark, variable, value)| {
+                source.close(mark);
+                Expr::Let {
+                    variable,
+                    value: Box::new(value),
+                    body: Box::new(body),
+                }
+            });
+
+        Ok(Some(expr))
+    }
+
+    fn parse_quantified_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mark = source.mark();
+        let quantifier = if source.next_token_is(&Token::Some) {
+            source.consume(&Token::Some)?;
+            Quantifier::Some
+        } else if source.next_token_is(&Token::Every) {
+            source.consume(&Token::Every)?;
+            Quantifier::Every
+        } else {
+            return Ok(None);
+        };
+
+        let bindings = self.parse_bindings(source, mark, &Token::In)?;
+
+        source.consume(&Token::Satisfies)?;
+        let test = self
+            .parse_expression(source)?
+            .context(RightHandSideExpressionMissing)?;
+
+        // Quantifying over several variables is the same as nesting
+        // the quantifiers
+        let expr = bindings
+            .into_iter()
+            .rev()
+            .fold(test, |test, (mark, variable, sequence)| {
+                source.close(mark);
+                Expr::Quantified {
+                    quantifier,
+                    variable,
+                    sequence: Box::new(sequence),
+                    test: Box::new(test),
+                }
+            });
+
+        Ok(Some(expr))
+    }
+
+    fn parse_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let rules: &[&Rule<'_, I>] = &[
+            &|src: TokenSource<'_, I>| self.parse_for_expression(src),
+            &|src: TokenSource<'_, I>| self.parse_let_expression(src),
+            &|src: TokenSource<'_, I>| self.parse_quantified_expression(src),
+            &|src: TokenSource<'_, I>| self.parse_if_expression(src),
+            &|src: TokenSource<'_, I>| self.parse_or_expression(src),
+        ];
+
+        first_matching_rule(rules, source)
+    }
+
+    fn parse_sequence_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mark = source.mark();
+        let first = match self.parse_expression(source)? {
+            Some(expr) => expr,
+            None => return Ok(None),
+        };
+
+        if !source.next_token_is(&Token::Comma) {
+            return Ok(Some(first));
+        }
+
+        let mut expressions = vec![first];
+        while source.next_token_is(&Token::Comma) {
+            source.consume(&Token::Comma)?;
+
+            let expr = self
+                .parse_expression(source)?
+                .context(RightHandSideExpressionMissing)?;
+            expressions.push(expr);
+        }
+        source.close(mark);
+
+        Ok(Some(Expr::Sequence(expressions)))
+    }
+
+    /// Parses a sequence expression, or gives the empty sequence when
+    /// there is none.
+    fn parse_empty_or_sequence_expression<I>(&self, source: TokenSource<'_, I>) -> ParseResult<Expr>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mark = source.mark();
+        match self.parse_sequence_expression(source)? {
+            Some(expr) => Ok(expr),
+            None => {
+                source.close(mark);
+                Ok(Expr::Sequence(Vec::new()))
+            }
+        }
+    }
+
+    pub fn parse<I>(&self, source: I) -> ParseResult<Expr>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        self.parse_expecting(source).map_err(|(error, _)| error)
+    }
+
+    /// Parses like [`parse`](#method.parse), but a failure also gives
+    /// the tokens that could have been used where the parser stopped.
+    pub fn parse_expecting<I>(&self, source: I) -> Result<Expr, (Error, Vec<Expected>)>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        self.parse_spanned(source).map(|(expr, _)| expr)
+    }
+
+    /// Parses like [`parse_expecting`](#method.parse_expecting), and
+    /// also gives the tokens of each node of the syntax tree.
+    pub(crate) fn parse_spanned<I>(
+        &self,
+        source: I,
+    ) -> Result<(Expr, TokenTree), (Error, Vec<Expected>)>
+    where
+        I: Iterator<Item = TokenResult>,
+    {
+        let mut source = Source::new(source);
+
+        let parsed = self
+            .parse_sequence_expression(&mut source)
+            .and_then(|expr| {
+                // A token that could not be read stops the parser
+                // wherever it is, so report it rather than where it stopped
+                if let Some(&Err(error)) = source.peek() {
+                    return Err(error).context(Tokenizer);
+                }
+                ensure!(!source.has_more_tokens(), ExtraUnparsedTokens);
+                expr.context(NoXPath)
+            });
+
+        match parsed {
+            Ok(expr) => {
+                let tokens = source.nodes.pop().expect("Parsed nodes are recorded");
+                debug_assert!(source.nodes.is_empty(), "Each node is part of another");
+                Ok((expr, tokens))
+            }
+            Err(error) => Err((error, source.expected)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use snafu::ResultExt;
+    use std::borrow::ToOwned;
+    use sxd_document::dom::{self, Document, Element, Root, Text};
+    use sxd_document::Package;
+
+    use crate::context::{self, Context};
+    use crate::expression::{Expression, SubExpression};
+    use crate::node_test;
+    use crate::nodeset::Node;
+    use crate::token::{AxisName, NodeTestName, Token};
+    use crate::tokenizer::{self, TokenResult};
+    use crate::Value::{Boolean, Number, String};
+    use crate::{Item, Value};
+
+    use super::*;
 
     macro_rules! tokens(
         ($($e:expr),*) => ({
-            // leading _ to allow empty construction without a warning.
-            let mut _temp: Vec<TokenResult> = ::std::vec::Vec::new();
-            $(_temp.push(Ok($e));)*
-            _temp
+            let temp: Vec<TokenResult> = vec![$(Ok($e)),*];
+            temp
         });
         ($($e:expr),+,) => (tokens!($($e),+))
     );
@@ -731,7 +1703,7 @@ mod test {
     impl<'d> ApproxEq for Value<'d> {
         fn is_approx_eq(&self, other: &Value<'d>) -> bool {
             match (self, other) {
-                (&Number(ref x), &Number(ref y)) => x.is_approx_eq(y),
+                (Number(x), Number(y)) => x.is_approx_eq(y),
                 _ => panic!("It's nonsensical to compare these quantities"),
             }
         }
@@ -749,12 +1721,12 @@ mod test {
 
     impl<'d> TestDoc<'d> {
         fn root(&'d self) -> Root<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
             doc.root()
         }
 
         fn top_node(&'d self) -> Element<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
 
             let kids = doc.root().children();
             match kids.len() {
@@ -773,7 +1745,7 @@ mod test {
         }
 
         fn add_child(&'d self, parent: Element<'d>, name: &str) -> Element<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
 
             let n = doc.create_element(name);
             parent.append_child(n);
@@ -781,7 +1753,7 @@ mod test {
         }
 
         fn add_text(&'d self, parent: Element<'d>, value: &str) -> Text<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
 
             let tn = doc.create_text(value);
             parent.append_child(tn);
@@ -822,7 +1794,9 @@ mod test {
         }
 
         fn parse_raw(&self, tokens: Vec<TokenResult>) -> ParseResult<SubExpression> {
-            self.parser.parse(tokens.into_iter())
+            self.parser
+                .parse(tokens.into_iter())
+                .map(|expr| expr.compile())
         }
 
         fn parse(&self, tokens: Vec<TokenResult>) -> SubExpression {
@@ -1449,8 +2423,17 @@ mod test {
     }
 
     #[test]
-    fn and_expression() {
-        let tokens = tokens![Token::Number(1.2), Token::And, Token::Number(0.0),];
+    fn if_expression() {
+        let tokens = tokens![
+            Token::If,
+            Token::LeftParen,
+            Token::Number(0.0),
+            Token::RightParen,
+            Token::Then,
+            Token::Literal("yes".to_owned()),
+            Token::Else,
+            Token::Literal("no".to_owned()),
+        ];
 
         let package = Package::new();
         let doc = TestDoc(package.as_document());
@@ -1458,12 +2441,45 @@ mod test {
         let ex = Exercise::new(&doc);
         let expr = ex.parse(tokens);
 
-        assert_eq!(Boolean(false), ex.evaluate(expr));
+        assert_eq!(String("no".to_owned()), ex.evaluate(expr));
     }
 
     #[test]
-    fn equality_expression() {
-        let tokens = tokens![Token::Number(1.2), Token::Equal, Token::Number(1.1),];
+    fn if_expression_without_else_is_reported_as_an_error() {
+        let tokens = tokens![
+            Token::If,
+            Token::LeftParen,
+            Token::Number(1.0),
+            Token::RightParen,
+            Token::Then,
+            Token::Number(2.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+
+        assert_eq!(Some(Error::RanOutOfInput), res.err());
+    }
+
+    #[test]
+    fn and_expression() {
+        let tokens = tokens![Token::Number(1.2), Token::And, Token::Number(0.0),];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Boolean(false), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn equality_expression() {
+        let tokens = tokens![Token::Number(1.2), Token::Equal, Token::Number(1.1),];
 
         let package = Package::new();
         let doc = TestDoc(package.as_document());
@@ -1560,6 +2576,255 @@ mod test {
         assert_approx_eq!(Number(1.1), ex.evaluate(expr));
     }
 
+    #[test]
+    fn sequence_expression() {
+        let tokens = tokens![
+            Token::LeftParen,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Literal("two".to_owned()),
+            Token::RightParen,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(
+            Value::Sequence(vec![Item::Number(1.0), Item::String("two".to_owned())]),
+            ex.evaluate(expr)
+        );
+    }
+
+    #[test]
+    fn for_expression() {
+        let tokens = tokens![
+            Token::For,
+            Token::Variable("a".into()),
+            Token::In,
+            Token::LeftParen,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Number(2.0),
+            Token::RightParen,
+            Token::Comma,
+            Token::Variable("b".into()),
+            Token::In,
+            Token::LeftParen,
+            Token::Number(10.0),
+            Token::Comma,
+            Token::Number(20.0),
+            Token::RightParen,
+            Token::Return,
+            Token::Variable("a".into()),
+            Token::Multiply,
+            Token::Variable("b".into()),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(
+            Value::Sequence(vec![
+                Item::Number(10.0),
+                Item::Number(20.0),
+                Item::Number(20.0),
+                Item::Number(40.0),
+            ]),
+            ex.evaluate(expr)
+        );
+    }
+
+    #[test]
+    fn for_expression_without_return_is_reported_as_an_error() {
+        let tokens = tokens![
+            Token::For,
+            Token::Variable("a".into()),
+            Token::In,
+            Token::Number(1.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+
+        assert_eq!(Some(Error::RanOutOfInput), res.err());
+    }
+
+    #[test]
+    fn let_expression() {
+        let tokens = tokens![
+            Token::Let,
+            Token::Variable("a".into()),
+            Token::Assign,
+            Token::Number(3.0),
+            Token::Comma,
+            Token::Variable("b".into()),
+            Token::Assign,
+            Token::Variable("a".into()),
+            Token::PlusSign,
+            Token::Number(1.0),
+            Token::Return,
+            Token::Variable("a".into()),
+            Token::Multiply,
+            Token::Variable("b".into()),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Number(12.0), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn quantified_expressions() {
+        let some = tokens![
+            Token::Some,
+            Token::Variable("a".into()),
+            Token::In,
+            Token::LeftParen,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Number(2.0),
+            Token::RightParen,
+            Token::Satisfies,
+            Token::Variable("a".into()),
+            Token::GreaterThan,
+            Token::Number(1.0),
+        ];
+        let every = tokens![
+            Token::Every,
+            Token::Variable("a".into()),
+            Token::In,
+            Token::LeftParen,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Number(2.0),
+            Token::RightParen,
+            Token::Satisfies,
+            Token::Variable("a".into()),
+            Token::GreaterThan,
+            Token::Number(1.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+
+        let expr = ex.parse(some);
+        assert_eq!(Boolean(true), ex.evaluate(expr));
+
+        let expr = ex.parse(every);
+        assert_eq!(Boolean(false), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn quantified_expression_without_satisfies_is_reported_as_an_error() {
+        let tokens = tokens![
+            Token::Every,
+            Token::Variable("a".into()),
+            Token::In,
+            Token::Number(1.0),
+            Token::Return,
+            Token::Number(1.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+
+        assert_eq!(
+            Some(Error::UnexpectedToken {
+                token: Token::Return
+            }),
+            res.err()
+        );
+    }
+
+    #[test]
+    fn range_expression() {
+        let tokens = tokens![
+            Token::Number(2.0),
+            Token::To,
+            Token::Number(1.0),
+            Token::PlusSign,
+            Token::Number(3.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(
+            Value::Sequence(vec![
+                Item::Number(2.0),
+                Item::Number(3.0),
+                Item::Number(4.0),
+            ]),
+            ex.evaluate(expr)
+        );
+    }
+
+    #[test]
+    fn descending_range_expression_is_empty() {
+        let tokens = tokens![Token::Number(3.0), Token::To, Token::Number(1.0)];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Value::Sequence(vec![]), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn chained_range_expression_is_reported_as_an_error() {
+        let tokens = tokens![
+            Token::Number(1.0),
+            Token::To,
+            Token::Number(2.0),
+            Token::To,
+            Token::Number(3.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+
+        assert_eq!(Some(Error::ExtraUnparsedTokens), res.err());
+    }
+
+    #[test]
+    fn empty_sequence_expression() {
+        let tokens = tokens![Token::LeftParen, Token::RightParen];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Value::Sequence(vec![]), ex.evaluate(expr));
+    }
+
     #[test]
     fn variable_reference() {
         let tokens = tokens![Token::Variable("variable-name".into())];
@@ -1658,6 +2923,293 @@ mod test {
         assert_eq!(nodeset![node1, node2], ex.evaluate(expr));
     }
 
+    #[test]
+    fn node_comparison_expression() {
+        let tokens = tokens![
+            Token::Variable("variable1".into()),
+            Token::Precedes,
+            Token::Variable("variable2".into()),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+        let node1 = doc.add_top_child("first-node");
+        let node2 = doc.add_top_child("second-node");
+
+        let mut ex = Exercise::new(&doc);
+        ex.context.set_variable("variable1", nodeset![node1]);
+        ex.context.set_variable("variable2", nodeset![node2]);
+
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Boolean(true), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn value_comparison_expression() {
+        let tokens = tokens![
+            Token::Literal("apple".to_owned()),
+            Token::ValueLessThan,
+            Token::Literal("banana".to_owned()),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Boolean(true), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn instance_of_expression() {
+        let tokens = tokens![
+            Token::Number(1.0),
+            Token::InstanceOf,
+            Token::NameTest(node_test::NameTest {
+                prefix: Some("xs".to_owned()),
+                local_part: "string".to_owned(),
+            }),
+            Token::QuestionMark,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Boolean(false), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn treat_as_expression() {
+        let tokens = tokens![
+            Token::LeftParen,
+            Token::RightParen,
+            Token::TreatAs,
+            Token::NodeTest(NodeTestName::Node),
+            Token::Multiply,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Value::Sequence(vec![]), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn cast_expression() {
+        let tokens = tokens![
+            Token::Literal("12".to_owned()),
+            Token::CastAs,
+            Token::NameTest(node_test::NameTest {
+                prefix: Some("xs".to_owned()),
+                local_part: "integer".to_owned(),
+            }),
+            Token::PlusSign,
+            Token::Number(1.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Number(13.0), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn castable_expression() {
+        let tokens = tokens![
+            Token::Literal("maybe".to_owned()),
+            Token::CastableAs,
+            Token::NameTest(node_test::NameTest {
+                prefix: Some("xs".to_owned()),
+                local_part: "boolean".to_owned(),
+            }),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Boolean(false), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn map_constructor_with_lookup() {
+        let tokens = tokens![
+            Token::Map,
+            Token::LeftBrace,
+            Token::Literal("a".to_owned()),
+            Token::Colon,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Literal("b".to_owned()),
+            Token::Colon,
+            Token::Number(2.0),
+            Token::RightBrace,
+            Token::Lookup,
+            name_test("b"),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Value::Sequence(vec![Item::Number(2.0)]), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn map_entry_without_value_is_reported_as_an_error() {
+        let tokens = tokens![
+            Token::Map,
+            Token::LeftBrace,
+            Token::Literal("a".to_owned()),
+            Token::Colon,
+            Token::RightBrace,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+        assert_eq!(Some(Error::MapEntryMissing), res.err());
+    }
+
+    #[test]
+    fn square_array_with_positional_lookup() {
+        let tokens = tokens![
+            Token::LeftBracket,
+            Token::Number(10.0),
+            Token::Comma,
+            Token::Number(20.0),
+            Token::RightBracket,
+            Token::Lookup,
+            Token::Number(2.0),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Value::Sequence(vec![Item::Number(20.0)]), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn curly_array_with_wildcard_lookup() {
+        let tokens = tokens![
+            Token::Array,
+            Token::LeftBrace,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Number(2.0),
+            Token::RightBrace,
+            Token::Lookup,
+            name_test("*"),
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(
+            Value::Sequence(vec![Item::Number(1.0), Item::Number(2.0)]),
+            ex.evaluate(expr)
+        );
+    }
+
+    #[test]
+    fn inline_function_called_dynamically() {
+        let tokens = tokens![
+            Token::LeftParen,
+            Token::InlineFunction,
+            Token::LeftParen,
+            Token::Variable("a".into()),
+            Token::Comma,
+            Token::Variable("b".into()),
+            Token::RightParen,
+            Token::LeftBrace,
+            Token::Variable("a".into()),
+            Token::MinusSign,
+            Token::Variable("b".into()),
+            Token::RightBrace,
+            Token::RightParen,
+            Token::LeftParen,
+            Token::Number(5.0),
+            Token::Comma,
+            Token::Number(3.0),
+            Token::RightParen,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Number(2.0), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn arrow_passes_the_value_as_the_first_argument() {
+        let tokens = tokens![
+            Token::Literal("a".to_owned()),
+            Token::Arrow,
+            Token::Function("concat".into()),
+            Token::LeftParen,
+            Token::Literal("b".to_owned()),
+            Token::RightParen,
+            Token::Arrow,
+            Token::Function("string-length".into()),
+            Token::LeftParen,
+            Token::RightParen,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Number(2.0), ex.evaluate(expr));
+    }
+
+    #[test]
+    fn arrow_binds_more_loosely_than_negation() {
+        let tokens = tokens![
+            Token::MinusSign,
+            Token::Number(2.5),
+            Token::Arrow,
+            Token::Function("floor".into()),
+            Token::LeftParen,
+            Token::RightParen,
+        ];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let expr = ex.parse(tokens);
+
+        assert_eq!(Number(-3.0), ex.evaluate(expr));
+    }
+
     #[test]
     fn absolute_path_expression() {
         let tokens = tokens![Token::Slash,];
@@ -1791,7 +3343,19 @@ mod test {
 
     #[test]
     fn having_extra_tokens_is_reported_as_an_error() {
-        let tokens = tokens![Token::LeftBracket];
+        let tokens = tokens![Token::RightBracket];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+        assert_eq!(Some(Error::ExtraUnparsedTokens), res.err());
+    }
+
+    #[test]
+    fn name_test_without_local_name_is_reported_as_an_error() {
+        let tokens = tokens![name_test("ns"), Token::Colon];
 
         let package = Package::new();
         let doc = TestDoc(package.as_document());
@@ -1821,4 +3385,59 @@ mod test {
             res.err()
         );
     }
+
+    #[test]
+    fn errors_give_the_tokens_that_could_have_been_used() {
+        let tokens = tokens![
+            Token::Function("func".into()),
+            Token::LeftParen,
+            Token::Number(1.0),
+            Token::RightBracket
+        ];
+
+        let (error, expected) = Parser::new()
+            .parse_expecting(tokens.into_iter())
+            .expect_err("Tokens should not parse");
+        assert_eq!(
+            Error::UnexpectedToken {
+                token: Token::RightBracket
+            },
+            error
+        );
+        assert!(expected.contains(&Expected::Token(Token::Comma)));
+        assert!(expected.contains(&Expected::Token(Token::RightParen)));
+        assert!(expected.contains(&Expected::Token(Token::PlusSign)));
+        assert!(!expected.contains(&Expected::Number));
+    }
+
+    #[test]
+    fn errors_at_the_start_give_the_tokens_that_begin_an_expression() {
+        let (error, expected) = Parser::new()
+            .parse_expecting(tokens![].into_iter())
+            .expect_err("Nothing should not parse");
+
+        assert_eq!(Error::NoXPath, error);
+        assert!(expected.contains(&Expected::Axis));
+        assert!(expected.contains(&Expected::Variable));
+        assert!(expected.contains(&Expected::Token(Token::Slash)));
+    }
+
+    #[test]
+    fn expected_tokens_are_described_as_written() {
+        let described: Vec<_> = [
+            Expected::Token(Token::DoubleSlash),
+            Expected::Token(Token::And),
+            Expected::Token(Token::InstanceOf),
+            Expected::Token(Token::Map),
+            Expected::Function,
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+        assert_eq!(
+            vec!["`//`", "`and`", "`instance of`", "`map`", "a function call"],
+            described
+        );
+    }
 }
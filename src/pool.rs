@@ -0,0 +1,141 @@
+//! Documents loaded by `document()` that are shared between contexts
+//! and evaluations.
+//!
+//! Each document is kept by its absolute URI and is only loaded once.
+//! Without a pool, the documents are kept with the result tree
+//! fragments given to [`Context::set_fragments`][]. A pool given to
+//! [`Context::set_document_pool`][] can instead outlive that storage
+//! and be given to any number of contexts:
+//!
+//! ```
+//! use std::cell::Cell;
+//! use std::rc::Rc;
+//!
+//! use sxd_document::parser;
+//! use sxd_xpath::pool::DocumentPool;
+//! use sxd_xpath::resolver::ResolveError;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let pool = DocumentPool::new();
+//! let loads = Rc::new(Cell::new(0));
+//! let xpath = Factory::new()
+//!     .build("string(document('urn:rates')/rates/@eur)")
+//!     .expect("Could not compile XPath");
+//!
+//! for order in &["<order/>", "<order/>"] {
+//!     let package = parser::parse(order).expect("failed to parse XML");
+//!     let document = package.as_document();
+//!
+//!     let mut context = Context::new();
+//!     context.set_document_pool(&pool);
+//!     context.set_document_resolver({
+//!         let loads = Rc::clone(&loads);
+//!         move |_: &str| -> Result<_, ResolveError> {
+//!             loads.set(loads.get() + 1);
+//!             Ok(parser::parse("<rates eur='0.9'/>")?)
+//!         }
+//!     });
+//!
+//!     let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//!     assert_eq!("0.9", value.string());
+//! }
+//!
+//! assert_eq!(1, loads.get());
+//! ```
+//!
+//! [`Context::set_fragments`]: ../context/struct.Context.html#method.set_fragments
+//! [`Context::set_document_pool`]: ../context/struct.Context.html#method.set_document_pool
+
+use elsa::FrozenMap;
+use sxd_document::{dom, Package};
+
+/// Owns documents by their absolute URI. They are freed when this is
+/// dropped.
+#[derive(Default)]
+pub struct DocumentPool {
+    loaded: FrozenMap<String, Box<Package>>,
+}
+
+impl DocumentPool {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a document that was loaded in advance. If a document was
+    /// already kept for the URI, that document is kept and returned
+    /// instead.
+    pub fn insert(&self, uri: &str, package: Package) -> dom::Document<'_> {
+        self.loaded
+            .insert(uri.to_owned(), Box::new(package))
+            .as_document()
+    }
+
+    /// The document kept for the URI, if any.
+    pub fn get(&self, uri: &str) -> Option<dom::Document<'_>> {
+        self.loaded.get(uri).map(Package::as_document)
+    }
+
+    /// The number of documents kept.
+    pub fn len(&self) -> usize {
+        self.loaded.len()
+    }
+
+    /// Whether no documents are kept.
+    pub fn is_empty(&self) -> bool {
+        self.loaded.is_empty()
+    }
+
+    /// Returns the document kept for the URI, loading it with `load`
+    /// the first time.
+    pub(crate) fn load<F, E>(&self, uri: &str, load: F) -> Result<dom::Document<'_>, E>
+    where
+        F: FnOnce() -> Result<Package, E>,
+    {
+        match self.get(uri) {
+            Some(doc) => Ok(doc),
+            None => Ok(self.insert(uri, load()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::convert::Infallible;
+
+    use sxd_document::parser;
+
+    use super::*;
+
+    fn parse(xml: &str) -> Package {
+        parser::parse(xml).expect("Unable to parse")
+    }
+
+    #[test]
+    fn documents_are_loaded_once() {
+        let pool = DocumentPool::new();
+        let loads = Cell::new(0);
+        let load = || {
+            loads.set(loads.get() + 1);
+            Ok::<_, Infallible>(parse("<a/>"))
+        };
+
+        let first = pool.load("urn:a", load);
+        let second = pool.load("urn:a", load);
+
+        assert_eq!(first, second);
+        assert_eq!(1, loads.get());
+        assert_eq!(1, pool.len());
+    }
+
+    #[test]
+    fn inserted_documents_are_not_replaced() {
+        let pool = DocumentPool::new();
+        let first = pool.insert("urn:a", parse("<a/>"));
+        let second = pool.insert("urn:a", parse("<b/>"));
+
+        assert_eq!(first, second);
+        assert_eq!(Some(first), pool.get("urn:a"));
+        assert_eq!(None, pool.get("urn:b"));
+    }
+}
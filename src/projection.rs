@@ -0,0 +1,837 @@
+//! Build documents that only contain the parts of the XML that a set
+//! of XPaths can reach.
+//!
+//! A [`Projection`][] is derived from one or more compiled XPaths.
+//! While the XML is parsed, elements that none of the paths can reach
+//! are discarded instead of being added to the document, which keeps
+//! the memory used by large inputs proportional to what is queried.
+//! Evaluating any of the XPaths against the projected document gives
+//! the same result as evaluating it against the complete document.
+//!
+//! Parts of an XPath that cannot be analyzed, such as the `parent` or
+//! `preceding` axes, fall back to keeping the nodes they could reach,
+//! up to and including the entire document.
+//!
+//! ```
+//! use sxd_xpath::projection::Projection;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let xml = r#"<feed>
+//!   <entry><title>One</title><content>A very long body</content></entry>
+//!   <entry><title>Two</title><content>Another long body</content></entry>
+//! </feed>"#;
+//!
+//! let factory = Factory::new();
+//! let context = Context::new();
+//! let titles = factory.build("/feed/entry/title").unwrap();
+//!
+//! let projection = Projection::new([&titles], &context).unwrap();
+//! let package = projection.parse(xml).unwrap();
+//! let document = package.as_document();
+//!
+//! let value = titles.evaluate(&context, document.root()).unwrap();
+//! assert_eq!(value.string(), "One");
+//!
+//! let contents = factory.build("count(//content)").unwrap();
+//! let value = contents.evaluate(&context, document.root()).unwrap();
+//! assert_eq!(value.number(), 0.0);
+//! ```
+//!
+//! [`Projection`]: struct.Projection.html
+
+use std::io::BufRead;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::PrefixDeclaration;
+use quick_xml::{escape, NsReader};
+use sxd_document::dom::{Document, Element};
+use sxd_document::{Package, QName};
+
+use crate::ast::{Expr, LookupKey, NodeTest, Step};
+use crate::axis::Axis;
+use crate::context::Context;
+use crate::stream::{self, Error, StreamPath, StreamStep};
+use crate::XPath;
+
+/// Functions that use the context node when called without arguments.
+const CONTEXT_FUNCTIONS: &[&str] = &["normalize-space", "number", "string", "string-length"];
+
+/// Core functions that look at nothing but their arguments, the context
+/// node's own value and the context position and size. Any other
+/// function may reach nodes that no XPath names.
+const ARGUMENT_FUNCTIONS: &[&str] = &[
+    "last",
+    "position",
+    "count",
+    "local-name",
+    "namespace-uri",
+    "name",
+    "string",
+    "concat",
+    "starts-with",
+    "contains",
+    "ends-with",
+    "substring-before",
+    "substring-after",
+    "substring",
+    "string-length",
+    "normalize-space",
+    "translate",
+    "lower-case",
+    "upper-case",
+    "string-join",
+    "compare",
+    "normalize-unicode",
+    "encode-for-uri",
+    "iri-to-uri",
+    "escape-html-uri",
+    "resolve-uri",
+    "document",
+    "boolean",
+    "not",
+    "true",
+    "false",
+    "number",
+    "sum",
+    "floor",
+    "ceiling",
+    "round",
+    "abs",
+    "round-half-to-even",
+    "min",
+    "max",
+    "avg",
+    "distinct-values",
+    "index-of",
+    "exists",
+    "empty",
+    "deep-equal",
+    "format-date",
+    "format-time",
+    "for-each",
+    "filter",
+    "fold-left",
+    "fold-right",
+    "parse-json",
+    "json-to-xml",
+    "matches",
+    "replace",
+    "tokenize",
+];
+
+/// The parts of a document that a set of XPaths can reach.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    paths: Vec<StreamPath>,
+    keep_all: bool,
+}
+
+impl Projection {
+    /// Analyzes the XPaths, resolving namespace prefixes with the
+    /// context. The XPaths are expected to be evaluated with the root
+    /// of the document as the context node.
+    pub fn new<'a, I>(xpaths: I, context: &Context<'_>) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = &'a XPath>,
+    {
+        let mut projection = Projection {
+            paths: Vec::new(),
+            keep_all: false,
+        };
+
+        for xpath in xpaths {
+            projection.add_expr(xpath.ast(), true, context)?;
+        }
+
+        Ok(projection)
+    }
+
+    /// Whether the XPaths may reach any node of the document.
+    pub fn keeps_everything(&self) -> bool {
+        self.keep_all
+    }
+
+    /// Parses the XML, keeping only the nodes that the XPaths can
+    /// reach.
+    pub fn parse(&self, xml: &str) -> Result<Package, Error> {
+        self.read(&mut NsReader::from_reader(xml.as_bytes()))
+    }
+
+    /// Reads the XML to the end, keeping only the nodes that the
+    /// XPaths can reach.
+    pub fn read<R>(&self, reader: &mut NsReader<R>) -> Result<Package, Error>
+    where
+        R: BufRead,
+    {
+        let package = Package::new();
+        {
+            let mut builder = Builder::new(self, package.as_document());
+            let mut buf = Vec::new();
+
+            loop {
+                match reader
+                    .read_event_into(&mut buf)
+                    .map_err(stream::to_xml_error)?
+                {
+                    Event::Start(ref e) => builder.start_element(reader, e)?,
+                    Event::Empty(ref e) => {
+                        builder.start_element(reader, e)?;
+                        builder.end_element();
+                    }
+                    Event::End(_) => builder.end_element(),
+                    Event::Text(ref t) => builder.text(&t.decode().map_err(stream::to_xml_error)?),
+                    Event::CData(ref t) => builder.text(&t.decode().map_err(stream::to_xml_error)?),
+                    Event::GeneralRef(ref r) => {
+                        if let Some(c) = r.resolve_char_ref().map_err(stream::to_xml_error)? {
+                            builder.text(c.encode_utf8(&mut [0; 4]));
+                        } else {
+                            let name = r.decode().map_err(stream::to_xml_error)?;
+                            builder.text(escape::resolve_predefined_entity(&name).unwrap_or(""));
+                        }
+                    }
+                    Event::Comment(ref t) => {
+                        let text = t.decode().map_err(stream::to_xml_error)?;
+                        builder.comment(&text);
+                    }
+                    Event::PI(ref pi) => {
+                        let target = String::from_utf8_lossy(pi.target());
+                        let value = String::from_utf8_lossy(pi.content());
+                        let value = Some(value.trim_start()).filter(|v| !v.is_empty());
+                        builder.processing_instruction(&target, value);
+                    }
+                    Event::Eof => break,
+                    _ => {}
+                }
+
+                buf.clear();
+            }
+        }
+
+        Ok(package)
+    }
+
+    fn add_expr(
+        &mut self,
+        expr: &Expr,
+        top_level: bool,
+        context: &Context<'_>,
+    ) -> Result<(), Error> {
+        match *expr {
+            // The context node is the root for the XPath itself, but
+            // unknown inside of predicates, which keep whole subtrees
+            Expr::ContextNode => self.keep_all |= top_level,
+            Expr::RootNode => self.keep_all = true,
+            Expr::Literal(_) | Expr::Variable(_) => {}
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => {
+                let uses_context = name.prefix.is_none()
+                    && arguments.is_empty()
+                    && CONTEXT_FUNCTIONS.contains(&&*name.local_part);
+                // Extension functions such as `dyn:evaluate` may look
+                // anywhere in the document
+                if name.prefix.is_some() || !ARGUMENT_FUNCTIONS.contains(&&*name.local_part) {
+                    self.keep_all = true;
+                }
+                self.keep_all |= uses_context && top_level;
+
+                for argument in arguments {
+                    self.add_expr(argument, top_level, context)?;
+                }
+            }
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } => {
+                for predicate in steps.iter().flat_map(|s| &s.predicates) {
+                    self.add_expr(predicate, false, context)?;
+                }
+
+                match **start_point {
+                    Expr::RootNode => self.add_path(steps, context)?,
+                    Expr::ContextNode if top_level => self.add_path(steps, context)?,
+                    // Predicates test nodes whose subtrees are kept
+                    Expr::ContextNode if steps.iter().all(|s| stays_within(s.axis)) => {}
+                    Expr::ContextNode => self.keep_all = true,
+                    ref start_point => {
+                        // The starting nodes keep their subtrees, so
+                        // only steps that leave them need attention
+                        self.add_expr(start_point, top_level, context)?;
+                        if !steps.iter().all(|s| is_downward(s.axis)) {
+                            self.keep_all = true;
+                        }
+                    }
+                }
+            }
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => {
+                self.add_expr(expression, top_level, context)?;
+                self.add_expr(predicate, false, context)?;
+            }
+            Expr::Negation(ref expr)
+            | Expr::InstanceOf {
+                expression: ref expr,
+                ..
+            }
+            | Expr::TreatAs {
+                expression: ref expr,
+                ..
+            }
+            | Expr::Cast {
+                expression: ref expr,
+                ..
+            }
+            | Expr::Castable {
+                expression: ref expr,
+                ..
+            }
+            | Expr::CurlyArray(ref expr) => self.add_expr(expr, top_level, context)?,
+            Expr::If {
+                ref condition,
+                ref consequent,
+                ref alternative,
+            } => {
+                self.add_expr(condition, top_level, context)?;
+                self.add_expr(consequent, top_level, context)?;
+                self.add_expr(alternative, top_level, context)?;
+            }
+            // Bound variables hold items of the sequence, whose
+            // subtrees are already kept
+            Expr::For {
+                sequence: ref bound,
+                ref body,
+                ..
+            }
+            | Expr::Let {
+                value: ref bound,
+                ref body,
+                ..
+            }
+            | Expr::Quantified {
+                sequence: ref bound,
+                test: ref body,
+                ..
+            } => {
+                self.add_expr(bound, top_level, context)?;
+                self.add_expr(body, top_level, context)?;
+            }
+            Expr::Sequence(ref expressions) | Expr::SquareArray(ref expressions) => {
+                for expr in expressions {
+                    self.add_expr(expr, top_level, context)?;
+                }
+            }
+            Expr::InlineFunction { ref body, .. } => self.add_expr(body, top_level, context)?,
+            Expr::DynamicCall {
+                ref function,
+                ref arguments,
+            } => {
+                self.add_expr(function, top_level, context)?;
+                for argument in arguments {
+                    self.add_expr(argument, top_level, context)?;
+                }
+            }
+            Expr::Map(ref entries) => {
+                for (key, value) in entries {
+                    self.add_expr(key, top_level, context)?;
+                    self.add_expr(value, top_level, context)?;
+                }
+            }
+            Expr::Lookup {
+                ref expression,
+                ref key,
+            } => {
+                self.add_expr(expression, top_level, context)?;
+                if let LookupKey::Expression(ref key) = *key {
+                    self.add_expr(key, top_level, context)?;
+                }
+            }
+            Expr::Binary {
+                ref left,
+                ref right,
+                ..
+            } => {
+                self.add_expr(left, top_level, context)?;
+                self.add_expr(right, top_level, context)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the longest prefix of the steps that can be followed while
+    /// parsing. The nodes selected by that prefix keep their subtrees,
+    /// which contain everything the remaining steps can reach.
+    fn add_path(&mut self, steps: &[Step], context: &Context<'_>) -> Result<(), Error> {
+        let mut stream_steps = Vec::new();
+
+        for (i, step) in steps.iter().enumerate() {
+            let is_last = i + 1 == steps.len();
+
+            if !is_downward(step.axis) {
+                if step.axis == Axis::Namespace && is_last {
+                    break;
+                }
+                self.keep_all = true;
+                return Ok(());
+            }
+
+            // Text nodes are only kept in subtrees, as are the
+            // siblings that positional predicates may count
+            let counts_text = step.node_test == NodeTest::Node || step.node_test == NodeTest::Text;
+            if counts_text && (is_last || !step.predicates.is_empty()) {
+                break;
+            }
+
+            match StreamStep::new(step, is_last, context) {
+                Ok(stream_step) => stream_steps.push(stream_step),
+                Err(Error::Unsupported { .. }) => {
+                    let bare = Step {
+                        predicates: Vec::new(),
+                        ..step.clone()
+                    };
+                    match StreamStep::new(&bare, is_last, context) {
+                        // The predicates are checked against the
+                        // subtree of the selected node
+                        Ok(stream_step) if !stream_step.is_attribute() => {
+                            stream_steps.push(stream_step)
+                        }
+                        Ok(_) | Err(Error::Unsupported { .. }) => {}
+                        Err(e) => return Err(e),
+                    }
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        while stream_steps.last().is_some_and(StreamStep::is_any_depth) {
+            stream_steps.pop();
+        }
+
+        if stream_steps.is_empty() {
+            self.keep_all = true;
+        } else {
+            self.paths.push(StreamPath::from_steps(stream_steps));
+        }
+
+        Ok(())
+    }
+}
+
+fn is_downward(axis: Axis) -> bool {
+    matches!(
+        axis,
+        Axis::Child | Axis::Descendant | Axis::DescendantOrSelf | Axis::Attribute
+    )
+}
+
+/// Whether the axis only reaches the node itself and its subtree.
+fn stays_within(axis: Axis) -> bool {
+    axis == Axis::SelfAxis || is_downward(axis)
+}
+
+/// How an open element is being copied.
+enum Level<'d> {
+    /// The element is kept along with all of its descendants
+    Subtree(Element<'d>),
+    /// The element is kept if it contains reachable descendants or
+    /// its attributes are selected
+    Projected {
+        element: Element<'d>,
+        frames: Vec<Vec<usize>>,
+        keep: bool,
+    },
+}
+
+struct Builder<'p, 'd> {
+    projection: &'p Projection,
+    document: Document<'d>,
+    levels: Vec<Level<'d>>,
+    /// The depth within an element that has been discarded
+    skipped: usize,
+    /// Adjacent text is added as a single text node
+    pending_text: String,
+}
+
+impl<'p, 'd> Builder<'p, 'd> {
+    fn new(projection: &'p Projection, document: Document<'d>) -> Self {
+        Builder {
+            projection,
+            document,
+            levels: Vec::new(),
+            skipped: 0,
+            pending_text: String::new(),
+        }
+    }
+
+    fn start_element<R>(&mut self, reader: &NsReader<R>, e: &BytesStart<'_>) -> Result<(), Error> {
+        if self.skipped > 0 {
+            self.skipped += 1;
+            return Ok(());
+        }
+
+        self.flush_text();
+
+        let (name, attributes) = stream::resolve(reader, e)?;
+
+        let level = match self.levels.last() {
+            Some(Level::Subtree(_)) => Some(None),
+            _ if self.projection.keep_all => Some(None),
+            parent => {
+                let initial;
+                let parent_frames = match parent {
+                    Some(Level::Projected { frames, .. }) => frames,
+                    _ => {
+                        initial = self
+                            .projection
+                            .paths
+                            .iter()
+                            .map(StreamPath::initial_frame)
+                            .collect();
+                        &initial
+                    }
+                };
+
+                let mut frames = Vec::new();
+                let mut selected = false;
+                let mut keep = false;
+                for (path, parent_frame) in self.projection.paths.iter().zip(parent_frames) {
+                    let (frame, s) = path.advance(parent_frame, &name, &attributes);
+                    selected |= s;
+                    keep |= frame.iter().any(|&i| path.steps()[i].is_attribute());
+                    frames.push(frame);
+                }
+
+                if selected {
+                    Some(None)
+                } else if frames.iter().all(Vec::is_empty) {
+                    None
+                } else {
+                    Some(Some((frames, keep)))
+                }
+            }
+        };
+
+        let level = match level {
+            Some(level) => level,
+            None => {
+                self.skipped = 1;
+                return Ok(());
+            }
+        };
+
+        let element = self.create_element(reader, e, &name, &attributes)?;
+        match self.levels.last() {
+            Some(Level::Subtree(parent))
+            | Some(Level::Projected {
+                element: parent, ..
+            }) => parent.append_child(element),
+            None => self.document.root().append_child(element),
+        }
+
+        self.levels.push(match level {
+            None => Level::Subtree(element),
+            Some((frames, keep)) => Level::Projected {
+                element,
+                frames,
+                keep,
+            },
+        });
+
+        Ok(())
+    }
+
+    fn create_element<R>(
+        &self,
+        reader: &NsReader<R>,
+        e: &BytesStart<'_>,
+        name: &stream::ElementName,
+        attributes: &[stream::Attribute],
+    ) -> Result<Element<'d>, Error> {
+        let element = self.document.create_element(QName::with_namespace_uri(
+            name.namespace_uri.as_deref(),
+            &name.local_part,
+        ));
+        element.set_preferred_prefix(prefix_of(e.name().prefix()).as_deref());
+
+        let mut attributes = attributes.iter();
+        for attribute in e.attributes() {
+            let attribute = attribute.map_err(stream::to_xml_error)?;
+            let value = attribute
+                .decode_and_unescape_value(reader.decoder())
+                .map_err(stream::to_xml_error)?;
+
+            match attribute.key.as_namespace_binding() {
+                Some(PrefixDeclaration::Default) => {
+                    element.set_default_namespace_uri(Some(&*value).filter(|v| !v.is_empty()))
+                }
+                Some(PrefixDeclaration::Named(prefix)) => {
+                    element.register_prefix(&String::from_utf8_lossy(prefix), &value)
+                }
+                None => {
+                    // Attributes are resolved in the same order,
+                    // skipping namespace declarations
+                    if let Some(a) = attributes.next() {
+                        let name =
+                            QName::with_namespace_uri(a.namespace_uri.as_deref(), &a.local_part);
+                        let attr = element.set_attribute_value(name, &a.value);
+                        attr.set_preferred_prefix(prefix_of(attribute.key.prefix()).as_deref());
+                    }
+                }
+            }
+        }
+
+        Ok(element)
+    }
+
+    fn end_element(&mut self) {
+        if self.skipped > 0 {
+            self.skipped -= 1;
+            return;
+        }
+
+        self.flush_text();
+
+        if let Some(Level::Projected {
+            element,
+            keep: false,
+            ..
+        }) = self.levels.pop()
+        {
+            if element.children().is_empty() {
+                element.remove_from_parent();
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.skipped == 0 {
+            self.pending_text.push_str(text);
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if let Some(&Level::Subtree(element)) = self.levels.last() {
+            if !self.pending_text.is_empty() {
+                element.append_child(self.document.create_text(&self.pending_text));
+            }
+        }
+        self.pending_text.clear();
+    }
+
+    fn comment(&mut self, text: &str) {
+        if self.skipped > 0 {
+            return;
+        }
+
+        self.flush_text();
+        let comment = self.document.create_comment(text);
+        match self.levels.last() {
+            Some(Level::Subtree(element)) => element.append_child(comment),
+            None if self.projection.keep_all => self.document.root().append_child(comment),
+            _ => {}
+        }
+    }
+
+    fn processing_instruction(&mut self, target: &str, value: Option<&str>) {
+        if self.skipped > 0 {
+            return;
+        }
+
+        self.flush_text();
+        let pi = self.document.create_processing_instruction(target, value);
+        match self.levels.last() {
+            Some(Level::Subtree(element)) => element.append_child(pi),
+            None if self.projection.keep_all => self.document.root().append_child(pi),
+            _ => {}
+        }
+    }
+}
+
+fn prefix_of(prefix: Option<quick_xml::name::Prefix<'_>>) -> Option<String> {
+    prefix.map(|p| String::from_utf8_lossy(p.as_ref()).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::writer::format_document;
+
+    use crate::{Factory, Value};
+
+    use super::*;
+
+    fn project(xpaths: &[&str], xml: &str) -> String {
+        let mut context = Context::new();
+        context.set_namespace("a", "urn:atom");
+
+        let factory = Factory::new();
+        let xpaths: Vec<_> = xpaths
+            .iter()
+            .map(|x| factory.build(x).expect("Unable to build XPath"))
+            .collect();
+
+        let projection = Projection::new(&xpaths, &context).expect("Unable to project");
+        let package = projection.parse(xml).expect("Unable to parse XML");
+
+        let mut output = Vec::new();
+        format_document(&package.as_document(), &mut output).expect("Unable to write XML");
+        let output = String::from_utf8(output).expect("Output was not UTF-8");
+        output
+            .trim_start_matches("<?xml version='1.0'?>")
+            .to_owned()
+    }
+
+    const FEED: &str = r#"<feed><!-- feed -->
+        <entry kind="post"><id>1</id><title>One</title></entry>
+        <entry><id>2</id><x:id xmlns:x="urn:atom">ns</x:id></entry>
+        <other><entry><id>3</id></entry></other>
+    </feed>"#;
+
+    #[test]
+    fn keeps_selected_subtrees_and_their_ancestors() {
+        assert_eq!(
+            project(&["/feed/entry/title"], FEED),
+            "<feed><entry kind='post'><title>One</title></entry></feed>"
+        );
+    }
+
+    #[test]
+    fn keeps_elements_whose_attributes_are_selected() {
+        assert_eq!(
+            project(&["/feed/entry/@kind"], FEED),
+            "<feed><entry kind='post'/><entry/></feed>"
+        );
+    }
+
+    #[test]
+    fn prunes_elements_that_lead_nowhere() {
+        assert_eq!(
+            project(&["//a:id"], FEED),
+            "<feed><entry><x:id xmlns:x='urn:atom'>ns</x:id></entry></feed>"
+        );
+    }
+
+    #[test]
+    fn combines_every_xpath() {
+        assert_eq!(
+            project(&["/feed/other", "count(/feed/entry/title)"], FEED),
+            "<feed><entry kind='post'><title>One</title></entry>\
+             <other><entry><id>3</id></entry></other></feed>"
+        );
+    }
+
+    #[test]
+    fn predicates_keep_the_subtree_they_inspect() {
+        assert_eq!(
+            project(&["/feed/entry[id = 2]/@kind"], FEED),
+            "<feed><entry kind='post'><id>1</id><title>One</title></entry>\
+             <entry><id>2</id><x:id xmlns:x='urn:atom'>ns</x:id></entry></feed>"
+        );
+    }
+
+    #[test]
+    fn text_steps_keep_their_parent() {
+        assert_eq!(
+            project(&["/feed/entry/title/text()"], FEED),
+            "<feed><entry kind='post'><title>One</title></entry></feed>"
+        );
+    }
+
+    #[test]
+    fn unanalyzable_xpaths_keep_everything() {
+        let all = project(&["/"], FEED);
+        assert!(all.contains("<!-- feed -->"));
+        assert!(all.contains("<other>"));
+
+        assert_eq!(project(&["//title/.."], FEED), all);
+        assert_eq!(project(&["string()"], FEED), all);
+        assert_eq!(project(&["key('k', '1')"], FEED), all);
+        assert_eq!(project(&["//title[. = current()/x]"], FEED), all);
+    }
+
+    #[test]
+    fn predicates_that_leave_the_node_keep_everything() {
+        let all = project(&["/"], FEED);
+
+        assert_eq!(project(&["/feed/entry/title[../id]"], FEED), all);
+        assert_eq!(
+            project(&["/feed/entry/id[following-sibling::title]"], FEED),
+            all
+        );
+        assert_eq!(project(&["//id[ancestor::other]"], FEED), all);
+    }
+
+    #[test]
+    fn unknown_and_extension_functions_keep_everything() {
+        let all = project(&["/"], FEED);
+
+        assert_eq!(project(&["id('1')"], FEED), all);
+        assert_eq!(project(&["base-uri(/feed/entry)"], FEED), all);
+        assert_eq!(project(&["a:anything(/feed/other)"], FEED), all);
+        assert_eq!(project(&["no-such-function(/feed/other)"], FEED), all);
+    }
+
+    #[cfg(feature = "exslt")]
+    #[test]
+    fn dynamic_evaluation_sees_the_complete_document() {
+        let mut context = Context::new();
+        crate::exslt::register_dynamic_functions(&mut context);
+
+        let xpath = Factory::new()
+            .build("dyn:evaluate('count(//entry)')")
+            .expect("Unable to build XPath");
+        let projection = Projection::new([&xpath], &context).expect("Unable to project");
+        assert!(projection.keeps_everything());
+
+        let projected = projection.parse(FEED).expect("Unable to parse XML");
+        let value = xpath
+            .evaluate(&context, projected.as_document().root())
+            .expect("Unable to evaluate XPath");
+        assert_eq!(value.string(), "3");
+    }
+
+    #[test]
+    fn results_match_the_complete_document() {
+        let xpaths = [
+            "count(//entry)",
+            "string(/feed/entry[2]/id)",
+            "//entry[@kind = 'post']/title",
+            "sum(/feed/other//id)",
+            "string(/feed/entry/title[../id])",
+            "string(/feed/entry/id[following-sibling::title])",
+            "count(//id[ancestor::other])",
+            "count(//id[preceding::title])",
+            "string(/feed/entry/id[self::id][title])",
+        ];
+
+        let factory = Factory::new();
+        let context = Context::new();
+        let full = sxd_document::parser::parse(FEED).expect("Unable to parse XML");
+
+        for xpath in &xpaths {
+            let xpath = factory.build(xpath).expect("Unable to build XPath");
+            let projection = Projection::new([&xpath], &context).expect("Unable to project");
+            let projected = projection.parse(FEED).expect("Unable to parse XML");
+
+            let expected = xpath
+                .evaluate(&context, full.as_document().root())
+                .map(|v| v.string());
+            let actual = xpath
+                .evaluate(&context, projected.as_document().root())
+                .map(|v| v.string());
+            assert_eq!(actual, expected);
+        }
+
+        let xpath = factory.build("/feed/other").expect("Unable to build XPath");
+        let projection = Projection::new([&xpath], &context).expect("Unable to project");
+        assert!(!projection.keeps_everything());
+        let projected = projection.parse(FEED).expect("Unable to parse XML");
+        let value = xpath
+            .evaluate(&context, projected.as_document().root())
+            .expect("Unable to evaluate XPath");
+        assert_eq!(value.string(), Value::from("3").string());
+    }
+}
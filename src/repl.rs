@@ -0,0 +1,270 @@
+//! Evaluates XPaths typed in one at a time.
+//!
+//! A [`Session`][] holds a document and the context that XPaths are
+//! evaluated with. Each line given to it is either a command, which
+//! starts with `:`, or an XPath to evaluate against the root of the
+//! document. Namespaces and variables set by commands are kept for the
+//! lines that follow. The `sxd-xpath-repl` program reads the lines from
+//! the terminal.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::repl::{Outcome, Session};
+//!
+//! let package = parser::parse("<shop><item>apple</item><item>pear</item></shop>")
+//!     .expect("failed to parse XML");
+//! let mut session = Session::new(package.as_document());
+//!
+//! session.execute(":let first /shop/item[1]");
+//! assert_eq!(
+//!     Outcome::Output("1: <item> \"pear\"".into()),
+//!     session.execute("$first/following-sibling::item")
+//! );
+//! ```
+//!
+//! [`Session`]: struct.Session.html
+
+use std::fmt::Write;
+
+use sxd_document::dom;
+
+use crate::nodeset::Node;
+use crate::{Context, Factory, Item, Value};
+
+const HELP: &str = "\
+:ns PREFIX URI   binds the prefix to the namespace URI
+:let NAME XPATH  sets the variable to the value of the XPath
+:help            shows this help
+:quit            ends the session
+Any other line is evaluated as an XPath.";
+
+/// What came of executing a line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Text to show, which may be empty
+    Output(String),
+    /// The session has been asked to end
+    Quit,
+}
+
+/// A document and the context that XPaths are evaluated against it
+/// with.
+pub struct Session<'d> {
+    document: dom::Document<'d>,
+    context: Context<'d>,
+    factory: Factory,
+}
+
+impl<'d> Session<'d> {
+    pub fn new(document: dom::Document<'d>) -> Self {
+        Session {
+            document,
+            context: Context::new(),
+            factory: Factory::new(),
+        }
+    }
+
+    /// The context that XPaths are evaluated with, as changed by the
+    /// commands so far.
+    pub fn context(&self) -> &Context<'d> {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context<'d> {
+        &mut self.context
+    }
+
+    /// Runs the command or evaluates the XPath on the line. Failures
+    /// are reported as output, so that the session can go on.
+    pub fn execute(&mut self, line: &str) -> Outcome {
+        let line = line.trim();
+        let output = match line.strip_prefix(':') {
+            Some(command) => match self.command(command) {
+                Some(output) => output,
+                None => return Outcome::Quit,
+            },
+            None if line.is_empty() => String::new(),
+            None => self.evaluate(line).unwrap_or_else(|error| error),
+        };
+        Outcome::Output(output)
+    }
+
+    /// The output of the command, or nothing when the session should
+    /// end.
+    fn command(&mut self, command: &str) -> Option<String> {
+        let (name, arguments) = match command.find(char::is_whitespace) {
+            Some(end) => (&command[..end], command[end..].trim_start()),
+            None => (command, ""),
+        };
+
+        let output = match name {
+            "ns" => match arguments.split_whitespace().collect::<Vec<_>>()[..] {
+                [prefix, uri] => {
+                    self.context.set_namespace(prefix, uri);
+                    String::new()
+                }
+                _ => String::from("usage: :ns PREFIX URI"),
+            },
+            "let" => match arguments.find(char::is_whitespace) {
+                Some(end) => {
+                    let (variable, xpath) = (&arguments[..end], arguments[end..].trim_start());
+                    match self.value_of(xpath) {
+                        Ok(value) => {
+                            self.context.set_variable(variable, value);
+                            String::new()
+                        }
+                        Err(error) => error,
+                    }
+                }
+                None => String::from("usage: :let NAME XPATH"),
+            },
+            "help" => String::from(HELP),
+            "quit" | "q" => return None,
+            _ => format!("unknown command :{}, try :help", name),
+        };
+        Some(output)
+    }
+
+    fn evaluate(&self, xpath: &str) -> Result<String, String> {
+        self.value_of(xpath).map(|value| pretty(&value))
+    }
+
+    fn value_of(&self, xpath: &str) -> Result<Value<'d>, String> {
+        let compiled = self
+            .factory
+            .build(xpath)
+            .map_err(|error| format!("error: {}", error.render(xpath)))?;
+        compiled
+            .evaluate(&self.context, self.document.root())
+            .map_err(|error| format!("error: {}", error))
+    }
+}
+
+/// Writes a nodeset or sequence with one numbered line for each member,
+/// in order. Other values are written on a single line.
+pub fn pretty(value: &Value<'_>) -> String {
+    let lines: Vec<String> = match *value {
+        Value::Nodeset(ref nodes) if nodes.size() == 0 => return String::from("(empty)"),
+        Value::Nodeset(ref nodes) => nodes.document_order().into_iter().map(node).collect(),
+        Value::Sequence(ref items) if items.is_empty() => return String::from("()"),
+        Value::Sequence(ref items) => items.iter().map(item).collect(),
+        Value::String(ref s) => return quoted(s),
+        ref other => return other.string(),
+    };
+
+    let mut output = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            output.push('\n');
+        }
+        let _ = write!(output, "{}: {}", i + 1, line);
+    }
+    output
+}
+
+fn node(node: Node<'_>) -> String {
+    match node {
+        Node::Root(_) => String::from("/"),
+        Node::Element(_) => {
+            let name = node.prefixed_name().unwrap_or_default();
+            format!("<{}> {}", name, quoted(&node.string_value()))
+        }
+        Node::Attribute(_) => {
+            let name = node.prefixed_name().unwrap_or_default();
+            format!("@{}={}", name, quoted(&node.string_value()))
+        }
+        Node::Text(_) => format!("text {}", quoted(&node.string_value())),
+        Node::Comment(c) => format!("<!--{}-->", c.text()),
+        Node::Namespace(ns) => format!("xmlns:{}={}", ns.prefix, quoted(ns.uri)),
+        Node::ProcessingInstruction(pi) => match pi.value() {
+            Some(value) => format!("<?{} {}?>", pi.target(), value),
+            None => format!("<?{}?>", pi.target()),
+        },
+        Node::External(_) => match node.prefixed_name() {
+            Some(name) => format!("<{}> {}", name, quoted(&node.string_value())),
+            None => quoted(&node.string_value()),
+        },
+    }
+}
+
+fn item(item: &Item<'_>) -> String {
+    match *item {
+        Item::Node(n) => node(n),
+        Item::String(ref s) => quoted(s),
+        Item::Map(ref map) => format!("map with {} entries", map.len()),
+        Item::Array(ref members) => format!("array with {} members", members.len()),
+        Item::Function(ref function) => format!("function with arity {}", function.arity()),
+        ref atomic => atomic.string(),
+    }
+}
+
+fn quoted(s: &str) -> String {
+    format!("{:?}", s)
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+
+    fn output(session: &mut Session<'_>, line: &str) -> String {
+        match session.execute(line) {
+            Outcome::Output(output) => output,
+            Outcome::Quit => panic!("session ended on {:?}", line),
+        }
+    }
+
+    #[test]
+    fn nodesets_are_numbered_in_document_order() {
+        let package = parser::parse("<a x='1'><b>one</b><!--c--><b>two</b></a>")
+            .expect("failed to parse XML");
+        let mut session = Session::new(package.as_document());
+
+        assert_eq!(
+            "1: @x=\"1\"\n2: <b> \"one\"\n3: <!--c-->\n4: <b> \"two\"",
+            output(&mut session, "/a/b[2] | /a/comment() | /a/@x | /a/b[1]")
+        );
+        assert_eq!("(empty)", output(&mut session, "/a/missing"));
+    }
+
+    #[test]
+    fn other_values_are_written_on_one_line() {
+        let package = parser::parse("<a/>").expect("failed to parse XML");
+        let mut session = Session::new(package.as_document());
+
+        assert_eq!("3", output(&mut session, "1 + 2"));
+        assert_eq!("\"a\\\"b\"", output(&mut session, "concat('a', '\"b')"));
+        assert_eq!("1: 1\n2: \"x\"", output(&mut session, "(1, 'x')"));
+        assert_eq!("", output(&mut session, "   "));
+    }
+
+    #[test]
+    fn namespaces_and_variables_are_kept_between_lines() {
+        let package = parser::parse("<a xmlns='urn:x'><b/></a>").expect("failed to parse XML");
+        let mut session = Session::new(package.as_document());
+
+        assert_eq!("", output(&mut session, ":ns x urn:x"));
+        assert_eq!("", output(&mut session, ":let b /x:a/x:b"));
+        assert_eq!("1: <b> \"\"", output(&mut session, "$b"));
+        assert_eq!("1", output(&mut session, "count($b)"));
+    }
+
+    #[test]
+    fn failures_are_reported_without_ending_the_session() {
+        let package = parser::parse("<a/>").expect("failed to parse XML");
+        let mut session = Session::new(package.as_document());
+
+        assert_eq!(
+            "error: right hand side of expression is missing at line 1, column 4\n1 +\n   ^",
+            output(&mut session, "1 +")
+        );
+        assert!(output(&mut session, "$missing").starts_with("error: "));
+        assert_eq!("usage: :ns PREFIX URI", output(&mut session, ":ns x"));
+        assert_eq!(
+            "unknown command :nope, try :help",
+            output(&mut session, ":nope")
+        );
+        assert_eq!(Outcome::Quit, session.execute(":quit"));
+    }
+}
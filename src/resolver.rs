@@ -0,0 +1,104 @@
+//! Loading the documents that the `document()` function refers to.
+//!
+//! A [`DocumentResolver`][] registered on the [`Context`][] turns an
+//! absolute URI into a parsed document. The documents are kept in the
+//! storage given to [`Context::set_fragments`][], and each is loaded
+//! only once, so nodes that are reached through the same URI are the
+//! same nodes.
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::fragment::Fragments;
+//! use sxd_xpath::resolver::ResolveError;
+//! use sxd_xpath::{Context, Factory};
+//!
+//! let fragments = Fragments::new();
+//! let package = parser::parse("<map><topicref href='intro.xml'/></map>")
+//!     .expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let mut context = Context::new();
+//! context.set_fragments(&fragments);
+//! context.set_base_uri("file:///maps/");
+//! context.set_document_resolver(|uri: &str| -> Result<_, ResolveError> {
+//!     assert_eq!("file:///maps/intro.xml", uri);
+//!     Ok(parser::parse("<topic><title>Introduction</title></topic>")?)
+//! });
+//!
+//! let xpath = Factory::new()
+//!     .build("string(document(//topicref/@href)/topic/title)")
+//!     .expect("Could not compile XPath");
+//! let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+//!
+//! assert_eq!("Introduction", value.string());
+//! ```
+//!
+//! Documents can also be loaded without blocking by registering an
+//! [`AsyncDocumentResolver`][] and evaluating with
+//! [`XPath::evaluate_async`][].
+//!
+//! [`DocumentResolver`]: trait.DocumentResolver.html
+//! [`AsyncDocumentResolver`]: trait.AsyncDocumentResolver.html
+//! [`XPath::evaluate_async`]: ../struct.XPath.html#method.evaluate_async
+//! [`Context`]: ../context/struct.Context.html
+//! [`Context::set_fragments`]: ../context/struct.Context.html#method.set_fragments
+
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+
+use sxd_document::Package;
+
+#[cfg(feature = "http-resolver")]
+pub mod http;
+
+/// The reason a document could not be loaded.
+pub type ResolveError = Box<dyn Error + Send + Sync>;
+
+/// Loads documents by URI.
+pub trait DocumentResolver {
+    /// Loads and parses the document at the URI. The URI is absolute
+    /// when a base URI is known, and has no fragment identifier.
+    fn resolve(&self, uri: &str) -> Result<Package, ResolveError>;
+}
+
+impl<F> DocumentResolver for F
+where
+    F: Fn(&str) -> Result<Package, ResolveError>,
+{
+    fn resolve(&self, uri: &str) -> Result<Package, ResolveError> {
+        self(uri)
+    }
+}
+
+/// The future of a document being loaded by an
+/// [`AsyncDocumentResolver`](trait.AsyncDocumentResolver.html).
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Result<Package, ResolveError>> + 'a>>;
+
+/// Loads documents by URI without blocking, such as over the network.
+/// It is only used by [`XPath::evaluate_async`][].
+///
+/// ```
+/// use sxd_document::parser;
+/// use sxd_xpath::resolver::{AsyncDocumentResolver, ResolveError, ResolveFuture};
+///
+/// struct Remote;
+///
+/// async fn fetch(uri: &str) -> Result<String, ResolveError> {
+///     // Await the network here
+///     Ok(format!("<fetched>{}</fetched>", uri))
+/// }
+///
+/// impl AsyncDocumentResolver for Remote {
+///     fn resolve<'a>(&'a self, uri: &'a str) -> ResolveFuture<'a> {
+///         Box::pin(async move { Ok(parser::parse(&fetch(uri).await?)?) })
+///     }
+/// }
+/// ```
+///
+/// [`XPath::evaluate_async`]: ../struct.XPath.html#method.evaluate_async
+pub trait AsyncDocumentResolver {
+    /// Loads and parses the document at the URI, like
+    /// [`DocumentResolver::resolve`](trait.DocumentResolver.html#tymethod.resolve).
+    fn resolve<'a>(&'a self, uri: &'a str) -> ResolveFuture<'a>;
+}
@@ -0,0 +1,190 @@
+//! Load documents over HTTP with [`ureq`](https://docs.rs/ureq/).
+//!
+//! Only hosts that were explicitly allowed are contacted, and
+//! redirects are not followed, as they could lead to any other host.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use sxd_xpath::fragment::Fragments;
+//! use sxd_xpath::resolver::http::HttpResolver;
+//! use sxd_xpath::Context;
+//!
+//! let mut resolver = HttpResolver::new();
+//! resolver.allow_host("data.example.com");
+//! resolver.set_timeout(Duration::from_secs(10));
+//!
+//! let fragments = Fragments::new();
+//! let mut context = Context::new();
+//! context.set_fragments(&fragments);
+//! context.set_document_resolver(resolver);
+//! ```
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use sxd_document::{parser, Package};
+use url::Url;
+
+use super::{DocumentResolver, ResolveError};
+
+/// Fetches `http` and `https` documents from an allowlist of hosts.
+#[derive(Debug, Clone)]
+pub struct HttpResolver {
+    allowed_hosts: HashSet<String>,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl HttpResolver {
+    /// No hosts are allowed. Connecting times out after 10 seconds and
+    /// the whole request after 30.
+    pub fn new() -> Self {
+        HttpResolver {
+            allowed_hosts: HashSet::new(),
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Allows documents to be fetched from the host. Hosts are
+    /// compared ASCII case-insensitively, and an IPv6 address is
+    /// written in brackets.
+    pub fn allow_host(&mut self, host: &str) {
+        self.allowed_hosts.insert(host.to_ascii_lowercase());
+    }
+
+    /// Sets how long to wait for a connection to be established.
+    pub fn set_connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Sets how long the whole request may take, including reading
+    /// the document.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Parses the URI as the request will, so the host that is checked
+    /// is the host that is contacted.
+    fn check_allowed(&self, uri: &str) -> Result<Url, ResolveError> {
+        let url = Url::parse(uri).map_err(|e| format!("{:?} is not a valid URI: {}", uri, e))?;
+        match url.scheme() {
+            "http" | "https" => {}
+            _ => return Err(format!("{:?} is not an HTTP URI", uri).into()),
+        }
+        match url.host_str() {
+            Some(host) if self.allowed_hosts.contains(&host.to_ascii_lowercase()) => Ok(url),
+            Some(host) => Err(format!("the host {:?} is not allowed", host).into()),
+            None => Err(format!("{:?} has no host", uri).into()),
+        }
+    }
+}
+
+impl Default for HttpResolver {
+    fn default() -> Self {
+        HttpResolver::new()
+    }
+}
+
+impl DocumentResolver for HttpResolver {
+    fn resolve(&self, uri: &str) -> Result<Package, ResolveError> {
+        let url = self.check_allowed(uri)?;
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(self.connect_timeout)
+            .timeout(self.timeout)
+            .redirects(0)
+            .build();
+        let response = agent.request_url("GET", &url).call().map_err(Box::new)?;
+        if response.status() != 200 {
+            return Err(format!("the server responded with {}", response.status()).into());
+        }
+        let xml = response.into_string()?;
+        Ok(parser::parse(&xml)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Serves a single request with the response, returning the URI of
+    /// the server.
+    fn serve_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to listen");
+        let address = listener.local_addr().expect("No local address");
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("No connection");
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            stream
+                .write_all(response.as_bytes())
+                .expect("Unable to respond");
+        });
+        format!("http://{}/data.xml", address)
+    }
+
+    fn error(result: Result<Package, ResolveError>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn documents_are_fetched_from_allowed_hosts() {
+        let uri = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Length: 14\r\nConnection: close\r\n\r\n<data>1</data>",
+        );
+        let mut resolver = HttpResolver::new();
+        resolver.allow_host("127.0.0.1");
+
+        let package = resolver.resolve(&uri).expect("Unable to fetch");
+        let doc = package.as_document();
+        let data = doc.root().children()[0].element().expect("No element");
+        assert_eq!("data", data.name().local_part());
+    }
+
+    #[test]
+    fn other_hosts_are_not_contacted() {
+        let resolver = HttpResolver::new();
+        let e = error(resolver.resolve("https://example.com/data.xml"));
+        assert_eq!("the host \"example.com\" is not allowed", e);
+    }
+
+    #[test]
+    fn hosts_are_found_as_the_request_finds_them() {
+        let mut resolver = HttpResolver::new();
+        resolver.allow_host("allowed.example");
+
+        let e = error(resolver.resolve("http://evil.example\\@allowed.example/x.xml"));
+        assert_eq!("the host \"evil.example\" is not allowed", e);
+        let e = error(resolver.resolve("http://evil.example#@allowed.example/x.xml"));
+        assert_eq!("the host \"evil.example\" is not allowed", e);
+    }
+
+    #[test]
+    fn other_schemes_are_rejected() {
+        let mut resolver = HttpResolver::new();
+        resolver.allow_host("example.com");
+        let e = error(resolver.resolve("ftp://example.com/data.xml"));
+        assert_eq!("\"ftp://example.com/data.xml\" is not an HTTP URI", e);
+    }
+
+    #[test]
+    fn redirects_are_not_followed() {
+        let uri = serve_once(
+            "HTTP/1.1 302 Found\r\nLocation: http://example.com/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+        let mut resolver = HttpResolver::new();
+        resolver.allow_host("127.0.0.1");
+
+        let e = error(resolver.resolve(&uri));
+        assert_eq!("the server responded with 302", e);
+    }
+}
@@ -0,0 +1,823 @@
+//! `serde` support for values and compiled XPaths, with the `serde`
+//! feature.
+//!
+//! Values are serialized in the shape that
+//! [`json::render_value`](../json/fn.render_value.html) writes them:
+//! booleans, numbers and strings as themselves, nodesets and sequences
+//! as sequences, and each node as a struct with its `type`, its `name`
+//! when it has one and its string `value`.
+//!
+//! An [`XPath`](../struct.XPath.html) is serialized as its syntax tree
+//! and compiled again when it is deserialized, which skips parsing the
+//! text. The positions of the parts of the text are not kept.
+
+use std::borrow::Cow;
+
+use serde::ser::{SerializeMap, SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::ast::Expr;
+use crate::nodeset::{Node, Nodeset};
+use crate::{Item, Value, XPath};
+
+impl<'d> Serialize for Value<'d> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Value::Boolean(b) => serializer.serialize_bool(b),
+            Value::Number(n) => serializer.serialize_f64(n),
+            Value::String(ref s) => serializer.serialize_str(s),
+            Value::Nodeset(ref nodes) => nodes.serialize(serializer),
+            Value::Sequence(ref items) => items.serialize(serializer),
+        }
+    }
+}
+
+/// In document order.
+impl<'d> Serialize for Nodeset<'d> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let nodes = self.document_order();
+        let mut seq = serializer.serialize_seq(Some(nodes.len()))?;
+        for node in nodes {
+            seq.serialize_element(&node)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'d> Serialize for Node<'d> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = self.prefixed_name();
+
+        let fields = if name.is_some() { 3 } else { 2 };
+        let mut node = serializer.serialize_struct("Node", fields)?;
+        node.serialize_field("type", self.kind_name())?;
+        match name {
+            Some(name) => node.serialize_field("name", &name)?,
+            None => node.skip_field("name")?,
+        }
+        node.serialize_field("value", &self.string_value())?;
+        node.end()
+    }
+}
+
+/// Maps are keyed by the string value of each key, and functions are
+/// unit.
+impl<'d> Serialize for Item<'d> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Item::Node(ref node) => node.serialize(serializer),
+            Item::Boolean(b) => serializer.serialize_bool(b),
+            Item::Number(n) => serializer.serialize_f64(n),
+            Item::String(ref s) => serializer.serialize_str(s),
+            Item::DateTime(_) | Item::Duration(_) => serializer.serialize_str(&self.string()),
+            Item::Map(ref entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries.iter() {
+                    map.serialize_entry(&key.string(), value)?;
+                }
+                map.end()
+            }
+            Item::Array(ref members) => members.serialize(serializer),
+            Item::Function(_) => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl Serialize for XPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.ast().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for XPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Expr::deserialize(deserializer).map(XPath::from)
+    }
+}
+
+/// The literals of syntax trees. A literal that folding made from
+/// another kind of value is written as its string, as the
+/// [`Formatter`](../format/struct.Formatter.html) writes it.
+pub(crate) mod literal {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    enum Literal<'a> {
+        Boolean(bool),
+        Number(f64),
+        String(Cow<'a, str>),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Value<'_>, serializer: S) -> Result<S::Ok, S::Error> {
+        let literal = match *value {
+            Value::Boolean(b) => Literal::Boolean(b),
+            Value::Number(n) => Literal::Number(n),
+            Value::String(ref s) => Literal::String(Cow::Borrowed(s)),
+            ref value => Literal::String(Cow::Owned(value.string())),
+        };
+        literal.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Value<'static>, D::Error> {
+        Ok(match Literal::deserialize(deserializer)? {
+            Literal::Boolean(b) => Value::Boolean(b),
+            Literal::Number(n) => Value::Number(n),
+            Literal::String(s) => Value::String(s.into_owned()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::{self, Write};
+
+    use serde::de::value::{MapDeserializer, SeqDeserializer};
+    use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+    use serde::ser::{
+        self, Impossible, SerializeStructVariant, SerializeTuple, SerializeTupleStruct,
+        SerializeTupleVariant,
+    };
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::{evaluate_xpath, json, Context, Factory};
+
+    /// Writes JSON, for the values that this module serializes.
+    struct Json<'a>(&'a mut String);
+
+    #[derive(Debug)]
+    struct Error;
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("unsupported")
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    impl ser::Error for Error {
+        fn custom<T: fmt::Display>(_: T) -> Self {
+            Error
+        }
+    }
+
+    impl de::Error for Error {
+        fn custom<T: fmt::Display>(_: T) -> Self {
+            Error
+        }
+    }
+
+    /// Separates the members of a sequence, map or struct.
+    struct Compound<'a> {
+        out: &'a mut String,
+        first: bool,
+        close: char,
+    }
+
+    impl<'a> Compound<'a> {
+        fn open(out: &'a mut String, open: char, close: char) -> Self {
+            out.push(open);
+            Compound {
+                out,
+                first: true,
+                close,
+            }
+        }
+
+        fn separate(&mut self) {
+            if !self.first {
+                self.out.push(',');
+            }
+            self.first = false;
+        }
+
+        fn entry<V: Serialize + ?Sized>(&mut self, key: &str, value: &V) -> Result<(), Error> {
+            self.separate();
+            let _ = write!(self.out, "{:?}:", key);
+            value.serialize(Json(self.out))
+        }
+    }
+
+    impl<'a> SerializeSeq for Compound<'a> {
+        type Ok = ();
+        type Error = Error;
+
+        fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+            self.separate();
+            value.serialize(Json(self.out))
+        }
+
+        fn end(self) -> Result<(), Error> {
+            self.out.push(self.close);
+            Ok(())
+        }
+    }
+
+    impl<'a> SerializeMap for Compound<'a> {
+        type Ok = ();
+        type Error = Error;
+
+        fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+            self.separate();
+            key.serialize(Json(self.out))?;
+            self.out.push(':');
+            Ok(())
+        }
+
+        fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+            value.serialize(Json(self.out))
+        }
+
+        fn end(self) -> Result<(), Error> {
+            self.out.push(self.close);
+            Ok(())
+        }
+    }
+
+    impl<'a> SerializeStruct for Compound<'a> {
+        type Ok = ();
+        type Error = Error;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), Error> {
+            self.entry(key, value)
+        }
+
+        fn end(self) -> Result<(), Error> {
+            self.out.push(self.close);
+            Ok(())
+        }
+    }
+
+    impl<'a> Serializer for Json<'a> {
+        type Ok = ();
+        type Error = Error;
+        type SerializeSeq = Compound<'a>;
+        type SerializeTuple = Impossible<(), Error>;
+        type SerializeTupleStruct = Impossible<(), Error>;
+        type SerializeTupleVariant = Impossible<(), Error>;
+        type SerializeMap = Compound<'a>;
+        type SerializeStruct = Compound<'a>;
+        type SerializeStructVariant = Impossible<(), Error>;
+
+        fn serialize_bool(self, v: bool) -> Result<(), Error> {
+            self.0.push_str(if v { "true" } else { "false" });
+            Ok(())
+        }
+
+        fn serialize_f64(self, v: f64) -> Result<(), Error> {
+            let _ = write!(self.0, "{}", v);
+            Ok(())
+        }
+
+        fn serialize_str(self, v: &str) -> Result<(), Error> {
+            let _ = write!(self.0, "{:?}", v);
+            Ok(())
+        }
+
+        fn serialize_unit(self) -> Result<(), Error> {
+            self.0.push_str("null");
+            Ok(())
+        }
+
+        fn serialize_seq(self, _: Option<usize>) -> Result<Compound<'a>, Error> {
+            Ok(Compound::open(self.0, '[', ']'))
+        }
+
+        fn serialize_map(self, _: Option<usize>) -> Result<Compound<'a>, Error> {
+            Ok(Compound::open(self.0, '{', '}'))
+        }
+
+        fn serialize_struct(self, _: &'static str, _: usize) -> Result<Compound<'a>, Error> {
+            Ok(Compound::open(self.0, '{', '}'))
+        }
+
+        fn serialize_i8(self, _: i8) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_i16(self, _: i16) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_i32(self, _: i32) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_i64(self, _: i64) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_u8(self, _: u8) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_u16(self, _: u16) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_u32(self, _: u32) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_u64(self, _: u64) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_f32(self, _: f32) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_char(self, _: char) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_bytes(self, _: &[u8]) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_none(self) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_unit_struct(self, _: &'static str) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_unit_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+        ) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_newtype_struct<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            _: &T,
+        ) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: &T,
+        ) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+            Err(Error)
+        }
+        fn serialize_tuple_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleStruct, Error> {
+            Err(Error)
+        }
+        fn serialize_tuple_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleVariant, Error> {
+            Err(Error)
+        }
+        fn serialize_struct_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStructVariant, Error> {
+            Err(Error)
+        }
+    }
+
+    fn serialized(value: &Value<'_>) -> String {
+        let mut out = String::new();
+        value
+            .serialize(Json(&mut out))
+            .expect("Unable to serialize");
+        out
+    }
+
+    #[test]
+    fn nodes_are_described_in_document_order() {
+        let package = parser::parse("<a id='1'>x<!--c--></a>").expect("failed to parse XML");
+        let document = package.as_document();
+        let value = evaluate_xpath(&document, "/a/comment() | /a/@id | /a | /a/text()")
+            .expect("XPath evaluation failed");
+
+        assert_eq!(
+            concat!(
+                r#"[{"type":"element","name":"a","value":"x"},"#,
+                r#"{"type":"attribute","name":"id","value":"1"},"#,
+                r#"{"type":"text","value":"x"},"#,
+                r#"{"type":"comment","value":"c"}]"#
+            ),
+            serialized(&value)
+        );
+    }
+
+    #[test]
+    fn the_shape_is_the_same_as_rendered_json() {
+        let package = parser::parse("<a>x</a>").expect("failed to parse XML");
+        let document = package.as_document();
+
+        for xpath in &["true()", "'s'", "(1, /a, 'b')", "map { 'k': [1, 2] }"] {
+            let value = evaluate_xpath(&document, xpath).expect("XPath evaluation failed");
+            assert_eq!(json::render_value(&value), serialized(&value), "{}", xpath);
+        }
+    }
+
+    /// Holds serialized data in memory, so that it can be read back.
+    #[derive(Debug)]
+    enum Tree {
+        Unit,
+        Bool(bool),
+        U64(u64),
+        I64(i64),
+        F64(f64),
+        Str(String),
+        None,
+        Some(Box<Tree>),
+        Seq(Vec<Tree>),
+        Map(Vec<(Tree, Tree)>),
+        Variant(&'static str, Box<Tree>),
+    }
+
+    struct ToTree;
+
+    /// Collects the members of a sequence, tuple or tuple variant.
+    struct Members(Vec<Tree>, Option<&'static str>);
+
+    /// Collects the entries of a map, struct or struct variant.
+    struct Entries(Vec<(Tree, Tree)>, Option<Tree>, Option<&'static str>);
+
+    fn wrap(tree: Tree, variant: Option<&'static str>) -> Tree {
+        match variant {
+            Some(name) => Tree::Variant(name, Box::new(tree)),
+            None => tree,
+        }
+    }
+
+    impl Members {
+        fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+            self.0.push(value.serialize(ToTree)?);
+            Ok(())
+        }
+
+        fn finish(self) -> Result<Tree, Error> {
+            Ok(wrap(Tree::Seq(self.0), self.1))
+        }
+    }
+
+    impl Entries {
+        fn push<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+            self.0
+                .push((Tree::Str(key.into()), value.serialize(ToTree)?));
+            Ok(())
+        }
+
+        fn finish(self) -> Result<Tree, Error> {
+            Ok(wrap(Tree::Map(self.0), self.2))
+        }
+    }
+
+    macro_rules! members {
+        ($($trait:ident :: $method:ident),*) => {$(
+            impl $trait for Members {
+                type Ok = Tree;
+                type Error = Error;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+                    self.push(value)
+                }
+
+                fn end(self) -> Result<Tree, Error> {
+                    self.finish()
+                }
+            }
+        )*};
+    }
+
+    members!(
+        SerializeSeq::serialize_element,
+        SerializeTuple::serialize_element,
+        SerializeTupleStruct::serialize_field,
+        SerializeTupleVariant::serialize_field
+    );
+
+    impl SerializeMap for Entries {
+        type Ok = Tree;
+        type Error = Error;
+
+        fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+            self.1 = Some(key.serialize(ToTree)?);
+            Ok(())
+        }
+
+        fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+            let key = self.1.take().ok_or(Error)?;
+            self.0.push((key, value.serialize(ToTree)?));
+            Ok(())
+        }
+
+        fn end(self) -> Result<Tree, Error> {
+            self.finish()
+        }
+    }
+
+    impl SerializeStruct for Entries {
+        type Ok = Tree;
+        type Error = Error;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), Error> {
+            self.push(key, value)
+        }
+
+        fn end(self) -> Result<Tree, Error> {
+            self.finish()
+        }
+    }
+
+    impl SerializeStructVariant for Entries {
+        type Ok = Tree;
+        type Error = Error;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), Error> {
+            self.push(key, value)
+        }
+
+        fn end(self) -> Result<Tree, Error> {
+            self.finish()
+        }
+    }
+
+    impl Serializer for ToTree {
+        type Ok = Tree;
+        type Error = Error;
+        type SerializeSeq = Members;
+        type SerializeTuple = Members;
+        type SerializeTupleStruct = Members;
+        type SerializeTupleVariant = Members;
+        type SerializeMap = Entries;
+        type SerializeStruct = Entries;
+        type SerializeStructVariant = Entries;
+
+        fn serialize_bool(self, v: bool) -> Result<Tree, Error> {
+            Ok(Tree::Bool(v))
+        }
+        fn serialize_i8(self, v: i8) -> Result<Tree, Error> {
+            Ok(Tree::I64(v.into()))
+        }
+        fn serialize_i16(self, v: i16) -> Result<Tree, Error> {
+            Ok(Tree::I64(v.into()))
+        }
+        fn serialize_i32(self, v: i32) -> Result<Tree, Error> {
+            Ok(Tree::I64(v.into()))
+        }
+        fn serialize_i64(self, v: i64) -> Result<Tree, Error> {
+            Ok(Tree::I64(v))
+        }
+        fn serialize_u8(self, v: u8) -> Result<Tree, Error> {
+            Ok(Tree::U64(v.into()))
+        }
+        fn serialize_u16(self, v: u16) -> Result<Tree, Error> {
+            Ok(Tree::U64(v.into()))
+        }
+        fn serialize_u32(self, v: u32) -> Result<Tree, Error> {
+            Ok(Tree::U64(v.into()))
+        }
+        fn serialize_u64(self, v: u64) -> Result<Tree, Error> {
+            Ok(Tree::U64(v))
+        }
+        fn serialize_f32(self, v: f32) -> Result<Tree, Error> {
+            Ok(Tree::F64(v.into()))
+        }
+        fn serialize_f64(self, v: f64) -> Result<Tree, Error> {
+            Ok(Tree::F64(v))
+        }
+        fn serialize_char(self, v: char) -> Result<Tree, Error> {
+            Ok(Tree::Str(v.into()))
+        }
+        fn serialize_str(self, v: &str) -> Result<Tree, Error> {
+            Ok(Tree::Str(v.into()))
+        }
+        fn serialize_bytes(self, _: &[u8]) -> Result<Tree, Error> {
+            Err(Error)
+        }
+        fn serialize_none(self) -> Result<Tree, Error> {
+            Ok(Tree::None)
+        }
+        fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Tree, Error> {
+            Ok(Tree::Some(Box::new(value.serialize(ToTree)?)))
+        }
+        fn serialize_unit(self) -> Result<Tree, Error> {
+            Ok(Tree::Unit)
+        }
+        fn serialize_unit_struct(self, _: &'static str) -> Result<Tree, Error> {
+            Ok(Tree::Unit)
+        }
+        fn serialize_unit_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            variant: &'static str,
+        ) -> Result<Tree, Error> {
+            Ok(Tree::Variant(variant, Box::new(Tree::Unit)))
+        }
+        fn serialize_newtype_struct<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            value: &T,
+        ) -> Result<Tree, Error> {
+            value.serialize(ToTree)
+        }
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            _: u32,
+            variant: &'static str,
+            value: &T,
+        ) -> Result<Tree, Error> {
+            Ok(Tree::Variant(variant, Box::new(value.serialize(ToTree)?)))
+        }
+        fn serialize_seq(self, _: Option<usize>) -> Result<Members, Error> {
+            Ok(Members(Vec::new(), None))
+        }
+        fn serialize_tuple(self, _: usize) -> Result<Members, Error> {
+            Ok(Members(Vec::new(), None))
+        }
+        fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Members, Error> {
+            Ok(Members(Vec::new(), None))
+        }
+        fn serialize_tuple_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            variant: &'static str,
+            _: usize,
+        ) -> Result<Members, Error> {
+            Ok(Members(Vec::new(), Some(variant)))
+        }
+        fn serialize_map(self, _: Option<usize>) -> Result<Entries, Error> {
+            Ok(Entries(Vec::new(), None, None))
+        }
+        fn serialize_struct(self, _: &'static str, _: usize) -> Result<Entries, Error> {
+            Ok(Entries(Vec::new(), None, None))
+        }
+        fn serialize_struct_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            variant: &'static str,
+            _: usize,
+        ) -> Result<Entries, Error> {
+            Ok(Entries(Vec::new(), None, Some(variant)))
+        }
+    }
+
+    impl<'de> IntoDeserializer<'de, Error> for Tree {
+        type Deserializer = Tree;
+
+        fn into_deserializer(self) -> Tree {
+            self
+        }
+    }
+
+    impl<'de> de::Deserializer<'de> for Tree {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self {
+                Tree::Unit => visitor.visit_unit(),
+                Tree::Bool(v) => visitor.visit_bool(v),
+                Tree::U64(v) => visitor.visit_u64(v),
+                Tree::I64(v) => visitor.visit_i64(v),
+                Tree::F64(v) => visitor.visit_f64(v),
+                Tree::Str(v) => visitor.visit_string(v),
+                Tree::None => visitor.visit_none(),
+                Tree::Some(v) => visitor.visit_some(*v),
+                Tree::Seq(v) => visitor.visit_seq(SeqDeserializer::new(v.into_iter())),
+                Tree::Map(v) => visitor.visit_map(MapDeserializer::new(v.into_iter())),
+                Tree::Variant(..) => visitor.visit_enum(self),
+            }
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self {
+                Tree::None => visitor.visit_none(),
+                Tree::Some(v) => visitor.visit_some(*v),
+                other => visitor.visit_some(other),
+            }
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            _: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, Error> {
+            visitor.visit_enum(self)
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct identifier ignored_any
+        }
+    }
+
+    impl<'de> de::EnumAccess<'de> for Tree {
+        type Error = Error;
+        type Variant = Tree;
+
+        fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Tree), Error> {
+            match self {
+                Tree::Variant(name, value) => {
+                    let name = seed.deserialize(name.into_deserializer())?;
+                    Ok((name, *value))
+                }
+                _ => Err(Error),
+            }
+        }
+    }
+
+    impl<'de> de::VariantAccess<'de> for Tree {
+        type Error = Error;
+
+        fn unit_variant(self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+            seed.deserialize(self)
+        }
+
+        fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Error> {
+            de::Deserializer::deserialize_any(self, visitor)
+        }
+
+        fn struct_variant<V: Visitor<'de>>(
+            self,
+            _: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, Error> {
+            de::Deserializer::deserialize_any(self, visitor)
+        }
+    }
+
+    fn round_trip(xpath: &XPath) -> XPath {
+        let tree = xpath.serialize(ToTree).expect("Unable to serialize");
+        XPath::deserialize(tree).expect("Unable to deserialize")
+    }
+
+    #[test]
+    fn xpaths_are_the_same_after_a_round_trip() {
+        let package = parser::parse("<a><b id='1'>x</b><b>y</b></a>").expect("failed to parse XML");
+        let document = package.as_document();
+        let context = Context::new();
+
+        let xpaths = [
+            "//b[@id = 1 or position() = last()]/text()",
+            "for $i in (1, 2) return let $j := -$i return $i * $j",
+            "some $b in /a/b satisfies $b instance of element(b)+",
+            "map { 'k': [1, 'two'] }?k?2, '3' castable as xs:integer?",
+            "function($f) { $f(/a/processing-instruction('p')) }(function($n) { count($n) })",
+            "if (ancestor-or-self::node()) then 'yes' else array { 1 to 3 }?*",
+        ];
+        for text in &xpaths {
+            let xpath = Factory::new().build(text).expect("Unable to build XPath");
+            let copy = round_trip(&xpath);
+
+            assert_eq!(xpath.ast(), copy.ast(), "{}", text);
+            assert_eq!(
+                xpath.evaluate(&context, document.root()).ok(),
+                copy.evaluate(&context, document.root()).ok(),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn literals_keep_their_type() {
+        for value in [Value::Boolean(true), Value::Number(1.5), Value::from("s")] {
+            let xpath = XPath::from(Expr::Literal(value.clone()));
+            assert_eq!(&Expr::Literal(value), round_trip(&xpath).ast());
+        }
+
+        let xpath = XPath::from(Expr::Literal(Value::Sequence(vec![Item::Number(2.0)])));
+        assert_eq!(&Expr::Literal(Value::from("2")), round_trip(&xpath).ast());
+    }
+}
@@ -0,0 +1,338 @@
+//! Where each part of a parsed XPath was written.
+//!
+//! [`XPath::spanned`][] gives the syntax tree of an XPath with the
+//! [`Span`][] of each of its nodes, so that tools such as editors can
+//! find the node under the cursor or mark the text of a node.
+//!
+//! ```
+//! use sxd_xpath::spanned::Node;
+//! use sxd_xpath::Factory;
+//!
+//! let xpath = "count(//item[@price > 10])";
+//! let built = Factory::new().build(xpath).expect("Could not compile XPath");
+//! let spanned = built.spanned().expect("Parsed XPaths have spans");
+//!
+//! let under_cursor = spanned.path_to(xpath.find("price").expect("price is written"));
+//! let innermost = under_cursor.last().expect("The cursor is in the XPath");
+//! assert_eq!("@price", &xpath[innermost.span.range()]);
+//! assert!(matches!(innermost.node, Node::Step(_)));
+//! ```
+//!
+//! Nodes that are implied rather than written, such as the context
+//! node that a relative path starts at, have an empty span where they
+//! are implied. The parts of an abbreviation, such as the steps that
+//! `//` stands for, have the span of the abbreviation.
+//!
+//! [`XPath::spanned`]: ../struct.XPath.html#method.spanned
+//! [`Span`]: ../tokenizer/struct.Span.html
+
+use crate::ast::{Expr, LookupKey, Step};
+use crate::parser::TokenTree;
+use crate::tokenizer::Span;
+
+/// A node of the syntax tree.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Node<'a> {
+    Expr(&'a Expr),
+    Step(&'a Step),
+}
+
+/// A node of the syntax tree with where it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<'a> {
+    pub node: Node<'a>,
+    pub span: Span,
+    /// The parts of the node, in the order they are written
+    pub children: Vec<Spanned<'a>>,
+}
+
+impl<'a> Spanned<'a> {
+    /// The nodes that were written at the offset, starting with this
+    /// one and ending with the innermost. Empty when this node was not
+    /// written there.
+    pub fn path_to(&self, offset: usize) -> Vec<&Spanned<'a>> {
+        let mut path = Vec::new();
+        let mut node = self;
+        while node.span.range().contains(&offset) {
+            path.push(node);
+            match node
+                .children
+                .iter()
+                .find(|child| child.span.range().contains(&offset))
+            {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        path
+    }
+}
+
+/// The spans of a node of the syntax tree and its parts, which are
+/// kept apart from the tree so it can be compared and rewritten.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SpanTree {
+    span: Span,
+    children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    /// Finds the spans of the nodes from the spans of the tokens they
+    /// were parsed from. Nodes without tokens are placed before the
+    /// next token, or at the end of the XPath.
+    pub(crate) fn new(tokens: &TokenTree, spans: &[Span], end: usize) -> SpanTree {
+        let range = &tokens.tokens;
+        let span = if range.start < range.end {
+            let offset = spans[range.start].offset;
+            Span {
+                offset,
+                length: spans[range.end - 1].range().end - offset,
+            }
+        } else {
+            Span {
+                offset: spans.get(range.start).map_or(end, |span| span.offset),
+                length: 0,
+            }
+        };
+
+        SpanTree {
+            span,
+            children: tokens
+                .children
+                .iter()
+                .map(|child| SpanTree::new(child, spans, end))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn spanned<'a>(&self, expr: &'a Expr) -> Spanned<'a> {
+        self.spanned_node(Node::Expr(expr))
+    }
+
+    fn spanned_node<'a>(&self, node: Node<'a>) -> Spanned<'a> {
+        let parts = children(node);
+        debug_assert_eq!(parts.len(), self.children.len(), "{:?}", node);
+
+        Spanned {
+            node,
+            span: self.span,
+            children: parts
+                .into_iter()
+                .zip(&self.children)
+                .map(|(part, spans)| spans.spanned_node(part))
+                .collect(),
+        }
+    }
+}
+
+/// The parts of the node, in the order the parser records them.
+fn children(node: Node<'_>) -> Vec<Node<'_>> {
+    let expr = match node {
+        Node::Expr(expr) => expr,
+        Node::Step(step) => return step.predicates.iter().map(Node::Expr).collect(),
+    };
+
+    match *expr {
+        Expr::ContextNode | Expr::RootNode | Expr::Literal(_) | Expr::Variable(_) => Vec::new(),
+        Expr::Function { ref arguments, .. }
+        | Expr::Sequence(ref arguments)
+        | Expr::SquareArray(ref arguments) => arguments.iter().map(Node::Expr).collect(),
+        Expr::Path {
+            ref start_point,
+            ref steps,
+        } => {
+            let mut parts = vec![Node::Expr(start_point)];
+            parts.extend(steps.iter().map(Node::Step));
+            parts
+        }
+        Expr::Filter {
+            ref expression,
+            ref predicate,
+        } => vec![Node::Expr(expression), Node::Expr(predicate)],
+        Expr::Negation(ref expr)
+        | Expr::InstanceOf {
+            expression: ref expr,
+            ..
+        }
+        | Expr::TreatAs {
+            expression: ref expr,
+            ..
+        }
+        | Expr::Cast {
+            expression: ref expr,
+            ..
+        }
+        | Expr::Castable {
+            expression: ref expr,
+            ..
+        }
+        | Expr::CurlyArray(ref expr)
+        | Expr::InlineFunction { body: ref expr, .. } => vec![Node::Expr(expr)],
+        Expr::Binary {
+            ref left,
+            ref right,
+            ..
+        } => vec![Node::Expr(left), Node::Expr(right)],
+        Expr::If {
+            ref condition,
+            ref consequent,
+            ref alternative,
+        } => vec![
+            Node::Expr(condition),
+            Node::Expr(consequent),
+            Node::Expr(alternative),
+        ],
+        Expr::For {
+            sequence: ref bound,
+            ref body,
+            ..
+        }
+        | Expr::Let {
+            value: ref bound,
+            ref body,
+            ..
+        }
+        | Expr::Quantified {
+            sequence: ref bound,
+            test: ref body,
+            ..
+        } => vec![Node::Expr(bound), Node::Expr(body)],
+        Expr::Map(ref entries) => entries
+            .iter()
+            .flat_map(|(key, value)| vec![Node::Expr(key), Node::Expr(value)])
+            .collect(),
+        Expr::Lookup {
+            ref expression,
+            ref key,
+        } => {
+            let mut parts = vec![Node::Expr(expression)];
+            if let LookupKey::Expression(ref key) = *key {
+                parts.push(Node::Expr(key));
+            }
+            parts
+        }
+        Expr::DynamicCall {
+            ref function,
+            ref arguments,
+        } => {
+            let mut parts = vec![Node::Expr(function)];
+            parts.extend(arguments.iter().map(Node::Expr));
+            parts
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Factory, XPath};
+
+    fn build(xpath: &str) -> XPath {
+        Factory::new().build(xpath).expect("Unable to build XPath")
+    }
+
+    /// The text of each node, with its parts after it.
+    fn texts(xpath: &str) -> Vec<&str> {
+        fn walk<'x>(xpath: &'x str, spanned: &Spanned<'_>, texts: &mut Vec<&'x str>) {
+            texts.push(&xpath[spanned.span.range()]);
+            for child in &spanned.children {
+                walk(xpath, child, texts);
+            }
+        }
+
+        let built = build(xpath);
+        let spanned = built.spanned().expect("Parsed XPaths have spans");
+        let mut texts = Vec::new();
+        walk(xpath, &spanned, &mut texts);
+        texts
+    }
+
+    #[test]
+    fn each_node_has_the_span_it_was_written_at() {
+        assert_eq!(
+            vec!["1 + f($x, 'y')", "1", "f($x, 'y')", "$x", "'y'"],
+            texts("1 + f($x, 'y')")
+        );
+        assert_eq!(vec!["(a)[2]", "a", "", "a", "2"], texts("(a)[2]"));
+        assert_eq!(
+            vec!["if (.) then () else -1", ".", "()", "-1", "1"],
+            texts("if (.) then () else -1")
+        );
+    }
+
+    #[test]
+    fn steps_are_nodes_of_paths() {
+        assert_eq!(vec!["/a/@b[1]", "/", "a", "@b[1]", "1"], texts("/a/@b[1]"));
+        assert_eq!(vec!["a//..", "", "a", "//", ".."], texts("a//.."));
+    }
+
+    #[test]
+    fn nodes_are_in_the_order_of_the_syntax_tree() {
+        assert_eq!(vec!["$x => $f(1)", "$f", "$x", "1"], texts("$x => $f(1)"));
+        assert_eq!(
+            vec![
+                "for $a in 1, $b in 2 return $b",
+                "1",
+                "$b in 2 return $b",
+                "2",
+                "$b"
+            ],
+            texts("for $a in 1, $b in 2 return $b")
+        );
+        assert_eq!(
+            vec!["map{1:2}?(1)", "map{1:2}", "1", "2", "1", "?k", ""],
+            texts("map{1:2}?(1) , ?k")[1..]
+        );
+    }
+
+    #[test]
+    fn every_xpath_has_spans_for_its_whole_tree() {
+        let xpaths = [
+            "//a[@b = $c]/text() | f:g(1, 'x')",
+            "let $j := 2, $k := $j return ($j, $k) instance of xs:integer+",
+            "every $x in a satisfies $x castable as xs:double?",
+            "map { 'k' : [1, 2] }?k?1",
+            "array { } => $f(), function($p) { }(.)",
+            "(a treat as element(p:b)*) | a/attribute::p:*",
+            "-a => upper-case() cast as xs:string to 3",
+            "(a[. = 1 or . < 2 and . eq 3])?*",
+        ];
+        for xpath in xpaths.iter() {
+            let built = build(xpath);
+            let spanned = built.spanned().expect("Parsed XPaths have spans");
+            assert_eq!(*xpath, &xpath[spanned.span.range()]);
+        }
+    }
+
+    #[test]
+    fn the_nodes_at_an_offset_end_with_the_innermost() {
+        let xpath = "count(a/b[@c])";
+        let built = build(xpath);
+        let spanned = built.spanned().expect("Parsed XPaths have spans");
+
+        let path: Vec<_> = spanned
+            .path_to(xpath.rfind('c').expect("c is written"))
+            .iter()
+            .map(|node| &xpath[node.span.range()])
+            .collect();
+        assert_eq!(vec!["count(a/b[@c])", "a/b[@c]", "b[@c]", "@c", "@c"], path);
+        assert!(spanned.path_to(xpath.len()).is_empty());
+    }
+
+    #[test]
+    fn recovered_xpaths_have_spans_but_rewritten_ones_do_not() {
+        let (built, _) = Factory::new().build_recovering("count(a[");
+        let built = built.expect("Unable to recover XPath");
+        assert_eq!(
+            0..8,
+            built
+                .spanned()
+                .expect("Parsed XPaths have spans")
+                .span
+                .range()
+        );
+
+        let rewritten = XPath::from(built.ast().clone());
+        assert!(rewritten.spanned().is_none());
+    }
+}
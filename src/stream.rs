@@ -0,0 +1,950 @@
+//! Evaluate XPaths over a stream of XML events, without building a
+//! document.
+//!
+//! Only XPaths that can be answered in a single forward pass are
+//! supported:
+//!
+//! - absolute location paths, such as `/feed/entry/id` or `//item`,
+//! - using the `child`, `descendant`, and `descendant-or-self` axes,
+//!   with a final `attribute` or `text()` step allowed,
+//! - with predicates that only test for the presence or value of an
+//!   attribute, such as `[@type]` or `[@type = 'book']`.
+//!
+//! The result is the [string value][] of each selected node, in
+//! document order.
+//!
+//! Events can be pushed by hand to a [`PushEvaluator`][], which suits
+//! XML that arrives incrementally. With the `stream` feature enabled,
+//! a [`StreamPath`][] can also pull events from a `quick_xml` reader.
+//!
+//! [string value]: https://www.w3.org/TR/xpath/#dt-string-value
+//! [`PushEvaluator`]: struct.PushEvaluator.html
+//! [`StreamPath`]: struct.StreamPath.html
+
+use std::collections::VecDeque;
+#[cfg(feature = "stream")]
+use std::io::BufRead;
+
+#[cfg(feature = "stream")]
+use quick_xml::escape;
+#[cfg(feature = "stream")]
+use quick_xml::events::{BytesStart, Event};
+#[cfg(feature = "stream")]
+use quick_xml::name::ResolveResult;
+#[cfg(feature = "stream")]
+use quick_xml::NsReader;
+#[cfg(feature = "stream")]
+use snafu::ResultExt;
+use snafu::Snafu;
+use sxd_document::QName;
+
+use crate::ast::{BinaryOperator, Expr, NodeTest, Step};
+use crate::axis::Axis;
+use crate::context::Context;
+use crate::node_test::NameTest;
+use crate::{Value, XPath};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("XPath cannot be streamed: {}", reason))]
+    Unsupported { reason: &'static str },
+    #[snafu(display("unknown namespace prefix {}", prefix))]
+    UnknownNamespace { prefix: String },
+    #[cfg(feature = "stream")]
+    #[snafu(display("unable to read XML: {}", source))]
+    Xml { source: quick_xml::Error },
+}
+
+fn unsupported<T>(reason: &'static str) -> Result<T, Error> {
+    Unsupported { reason }.fail()
+}
+
+/// A name test with its prefix resolved. `None` for the local part
+/// matches any name.
+#[derive(Debug, Clone, PartialEq)]
+struct Name {
+    any_namespace: bool,
+    namespace_uri: Option<String>,
+    local_part: Option<String>,
+}
+
+impl Name {
+    fn new(name_test: &NameTest, context: &Context<'_>) -> Result<Self, Error> {
+        let any_namespace = name_test.prefix.as_deref() == Some("*");
+        let namespace_uri = match name_test.prefix {
+            _ if any_namespace => None,
+            Some(ref prefix) => match context.namespace_for(prefix) {
+                Some(uri) => Some(uri.to_owned()),
+                None => {
+                    return UnknownNamespace {
+                        prefix: prefix.clone(),
+                    }
+                    .fail()
+                }
+            },
+            None => None,
+        };
+
+        let local_part = Some(name_test.local_part.clone()).filter(|l| l != "*");
+
+        Ok(Name {
+            any_namespace,
+            namespace_uri,
+            local_part,
+        })
+    }
+
+    fn matches(&self, namespace_uri: Option<&str>, local_part: &str) -> bool {
+        let namespace_matches = match (self.namespace_uri.as_deref(), &self.local_part) {
+            _ if self.any_namespace => true,
+            // A bare `*` matches any namespace
+            (None, None) => true,
+            (uri, _) => uri == namespace_uri,
+        };
+
+        namespace_matches && self.local_part.as_ref().is_none_or(|l| l == local_part)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Predicate {
+    Exists(Name),
+    Equals(Name, Value<'static>),
+}
+
+impl Predicate {
+    fn new(expr: &Expr, context: &Context<'_>) -> Result<Self, Error> {
+        fn attribute_name(expr: &Expr, context: &Context<'_>) -> Result<Option<Name>, Error> {
+            if let Expr::Path { start_point, steps } = expr {
+                if let (Expr::ContextNode, [step]) = (&**start_point, &steps[..]) {
+                    if let (Axis::Attribute, NodeTest::Name(name), true) =
+                        (step.axis, &step.node_test, step.predicates.is_empty())
+                    {
+                        return Name::new(name, context).map(Some);
+                    }
+                }
+            }
+            Ok(None)
+        }
+
+        if let Some(name) = attribute_name(expr, context)? {
+            return Ok(Predicate::Exists(name));
+        }
+
+        if let Expr::Binary {
+            operator: BinaryOperator::Equal,
+            left,
+            right,
+        } = expr
+        {
+            let operands = match (&**left, &**right) {
+                (attr, Expr::Literal(value)) | (Expr::Literal(value), attr) => {
+                    attribute_name(attr, context)?.map(|name| (name, value))
+                }
+                _ => None,
+            };
+
+            if let Some((name, value)) = operands {
+                return Ok(Predicate::Equals(name, value.clone()));
+            }
+        }
+
+        unsupported("only predicates testing attributes can be streamed")
+    }
+
+    fn matches(&self, attributes: &[Attribute]) -> bool {
+        match *self {
+            Predicate::Exists(ref name) => attributes.iter().any(|a| a.is(name)),
+            Predicate::Equals(ref name, ref value) => {
+                attributes
+                    .iter()
+                    .filter(|a| a.is(name))
+                    .any(|a| match *value {
+                        Value::Number(n) => Value::String(a.value.clone()).number() == n,
+                        _ => a.value == value.string(),
+                    })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum StepAxis {
+    Child,
+    Descendant,
+    /// `descendant-or-self::node()`, the expansion of `//`
+    AnyDepth,
+    Attribute,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StepTest {
+    Name(Name),
+    Node,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StreamStep {
+    axis: StepAxis,
+    test: StepTest,
+    predicates: Vec<Predicate>,
+}
+
+impl StreamStep {
+    pub(crate) fn new(step: &Step, is_last: bool, context: &Context<'_>) -> Result<Self, Error> {
+        let test = match step.node_test {
+            NodeTest::Name(ref name) => StepTest::Name(Name::new(name, context)?),
+            NodeTest::Node => StepTest::Node,
+            NodeTest::Text if is_last => StepTest::Text,
+            NodeTest::Text => return unsupported("text() must be the last step"),
+            NodeTest::Comment | NodeTest::ProcessingInstruction(_) => {
+                return unsupported("comments and processing instructions are not streamed")
+            }
+        };
+
+        let predicates = step
+            .predicates
+            .iter()
+            .map(|p| Predicate::new(p, context))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let axis = match step.axis {
+            Axis::Child => StepAxis::Child,
+            Axis::Descendant => StepAxis::Descendant,
+            Axis::DescendantOrSelf if test == StepTest::Node && predicates.is_empty() => {
+                if is_last {
+                    return unsupported("a path may not end with descendant-or-self::node()");
+                }
+                StepAxis::AnyDepth
+            }
+            Axis::Attribute if is_last => StepAxis::Attribute,
+            Axis::Attribute => return unsupported("the attribute axis must be the last step"),
+            _ => return unsupported("only forward axes can be streamed"),
+        };
+
+        if axis == StepAxis::Attribute && !predicates.is_empty() {
+            return unsupported("predicates on attributes cannot be streamed");
+        }
+
+        Ok(StreamStep {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    #[cfg(feature = "stream")]
+    /// Whether this is the expansion of `//`, which selects nothing
+    /// on its own.
+    pub(crate) fn is_any_depth(&self) -> bool {
+        self.axis == StepAxis::AnyDepth
+    }
+
+    #[cfg(feature = "stream")]
+    pub(crate) fn is_attribute(&self) -> bool {
+        self.axis == StepAxis::Attribute
+    }
+
+    fn matches_element(&self, name: &ElementName, attributes: &[Attribute]) -> bool {
+        let test_matches = match self.test {
+            StepTest::Name(ref n) => n.matches(name.namespace_uri.as_deref(), &name.local_part),
+            StepTest::Node => true,
+            StepTest::Text => false,
+        };
+
+        test_matches && self.predicates.iter().all(|p| p.matches(attributes))
+    }
+}
+
+/// The namespace-resolved name of an element.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ElementName {
+    pub(crate) namespace_uri: Option<String>,
+    pub(crate) local_part: String,
+}
+
+/// A namespace-resolved attribute of an element.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Attribute {
+    pub(crate) namespace_uri: Option<String>,
+    pub(crate) local_part: String,
+    pub(crate) value: String,
+}
+
+impl Attribute {
+    fn is(&self, name: &Name) -> bool {
+        name.matches(self.namespace_uri.as_deref(), &self.local_part)
+    }
+}
+
+/// A compiled XPath that can be evaluated over a stream of events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamPath {
+    steps: Vec<StreamStep>,
+}
+
+impl StreamPath {
+    /// Checks that the XPath can be streamed, resolving namespace
+    /// prefixes with the context.
+    pub fn new(xpath: &XPath, context: &Context<'_>) -> Result<Self, Error> {
+        let steps = match *xpath.ast() {
+            Expr::Path {
+                ref start_point,
+                ref steps,
+            } if **start_point == Expr::RootNode => steps,
+            _ => return unsupported("only absolute location paths can be streamed"),
+        };
+
+        let last = match steps.len().checked_sub(1) {
+            Some(last) => last,
+            None => return unsupported("only location paths with steps can be streamed"),
+        };
+        let steps = steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| StreamStep::new(step, i == last, context))
+            .collect::<Result<_, _>>()?;
+
+        Ok(StreamPath { steps })
+    }
+
+    #[cfg(feature = "stream")]
+    pub(crate) fn from_steps(steps: Vec<StreamStep>) -> Self {
+        StreamPath { steps }
+    }
+
+    #[cfg(feature = "stream")]
+    pub(crate) fn steps(&self) -> &[StreamStep] {
+        &self.steps
+    }
+
+    fn is_last(&self, step: usize) -> bool {
+        step + 1 == self.steps.len()
+    }
+
+    /// The steps that children of the root may match.
+    pub(crate) fn initial_frame(&self) -> Vec<usize> {
+        expand(self, vec![0])
+    }
+
+    /// Matches an element against the steps its parent allows,
+    /// returning the steps its own children may match and whether the
+    /// element is selected by the final step.
+    pub(crate) fn advance(
+        &self,
+        parent: &[usize],
+        name: &ElementName,
+        attributes: &[Attribute],
+    ) -> (Vec<usize>, bool) {
+        let mut frame = Vec::new();
+        let mut selected = false;
+
+        for &i in parent {
+            let step = &self.steps[i];
+
+            match step.axis {
+                StepAxis::AnyDepth | StepAxis::Descendant => frame.push(i),
+                StepAxis::Child | StepAxis::Attribute => {}
+            }
+
+            if step.axis != StepAxis::AnyDepth
+                && step.axis != StepAxis::Attribute
+                && step.matches_element(name, attributes)
+            {
+                if self.is_last(i) {
+                    selected = true;
+                } else {
+                    frame.push(i + 1);
+                }
+            }
+        }
+
+        (expand(self, frame), selected)
+    }
+
+    /// Reads the XML to the end, collecting the string value of every
+    /// selected node.
+    ///
+    /// ```
+    /// use quick_xml::NsReader;
+    /// use sxd_xpath::stream::StreamPath;
+    /// use sxd_xpath::{Context, Factory};
+    ///
+    /// let xml = r#"<feed><entry><id>1</id></entry><entry><id>2</id></entry></feed>"#;
+    ///
+    /// let xpath = Factory::new().build("/feed/entry/id").unwrap();
+    /// let path = StreamPath::new(&xpath, &Context::new()).unwrap();
+    ///
+    /// let mut reader = NsReader::from_reader(xml.as_bytes());
+    /// assert_eq!(path.evaluate(&mut reader).unwrap(), ["1", "2"]);
+    /// ```
+    #[cfg(feature = "stream")]
+    pub fn evaluate<R>(&self, reader: &mut NsReader<R>) -> Result<Vec<String>, Error>
+    where
+        R: BufRead,
+    {
+        let mut results = Vec::new();
+        self.for_each(reader, |value| results.push(value))?;
+        Ok(results)
+    }
+
+    /// Reads the XML to the end, calling the function with the string
+    /// value of every selected node as soon as it is known.
+    #[cfg(feature = "stream")]
+    pub fn for_each<R, F>(&self, reader: &mut NsReader<R>, f: F) -> Result<(), Error>
+    where
+        R: BufRead,
+        F: FnMut(String),
+    {
+        let mut evaluator = PushEvaluator::new();
+        evaluator.register(self.clone(), f);
+        let mut buf = Vec::new();
+
+        loop {
+            match reader.read_event_into(&mut buf).context(Xml)? {
+                Event::Start(ref e) => {
+                    let (name, attributes) = resolve(reader, e)?;
+                    evaluator.start_resolved_element(&name, &attributes);
+                }
+                Event::Empty(ref e) => {
+                    let (name, attributes) = resolve(reader, e)?;
+                    evaluator.start_resolved_element(&name, &attributes);
+                    evaluator.end_element();
+                }
+                Event::End(_) => evaluator.end_element(),
+                Event::Text(ref t) => evaluator.text(&t.decode().map_err(to_xml_error)?),
+                Event::CData(ref t) => evaluator.text(&t.decode().map_err(to_xml_error)?),
+                Event::GeneralRef(ref r) => {
+                    if let Some(c) = r.resolve_char_ref().context(Xml)? {
+                        evaluator.text(c.encode_utf8(&mut [0; 4]));
+                    } else {
+                        let name = r.decode().map_err(to_xml_error)?;
+                        evaluator.text(escape::resolve_predefined_entity(&name).unwrap_or(""));
+                    }
+                }
+                Event::Comment(_) => evaluator.comment(),
+                Event::PI(_) => evaluator.processing_instruction(),
+                Event::Eof => break,
+                _ => {}
+            }
+
+            buf.clear();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "stream")]
+pub(crate) fn to_xml_error<E: Into<quick_xml::Error>>(e: E) -> Error {
+    Error::Xml { source: e.into() }
+}
+
+#[cfg(feature = "stream")]
+fn resolve_namespace(result: ResolveResult<'_>) -> Result<Option<String>, Error> {
+    match result {
+        ResolveResult::Bound(ns) => Ok(Some(String::from_utf8_lossy(ns.as_ref()).into_owned())),
+        ResolveResult::Unbound => Ok(None),
+        ResolveResult::Unknown(prefix) => UnknownNamespace {
+            prefix: String::from_utf8_lossy(&prefix).into_owned(),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(feature = "stream")]
+pub(crate) fn resolve<R>(
+    reader: &NsReader<R>,
+    element: &BytesStart<'_>,
+) -> Result<(ElementName, Vec<Attribute>), Error> {
+    let (namespace, local_part) = reader.resolve_element(element.name());
+    let name = ElementName {
+        namespace_uri: resolve_namespace(namespace)?,
+        local_part: String::from_utf8_lossy(local_part.as_ref()).into_owned(),
+    };
+
+    let mut attributes = Vec::new();
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(to_xml_error)?;
+        let key = attribute.key.as_ref();
+        if key == b"xmlns" || key.starts_with(b"xmlns:") {
+            continue;
+        }
+
+        let (namespace, local_part) = reader.resolve_attribute(attribute.key);
+        attributes.push(Attribute {
+            namespace_uri: resolve_namespace(namespace)?,
+            local_part: String::from_utf8_lossy(local_part.as_ref()).into_owned(),
+            value: attribute
+                .decode_and_unescape_value(reader.decoder())
+                .context(Xml)?
+                .into_owned(),
+        });
+    }
+
+    Ok((name, attributes))
+}
+
+/// Evaluates [`StreamPath`][]s against events that are pushed to it,
+/// calling back as nodes are selected.
+///
+/// Register every path before pushing the first event. Each selected
+/// node is reported once its string value is complete; results of a
+/// single path are reported in document order.
+///
+/// ```
+/// use std::cell::RefCell;
+/// use sxd_document::QName;
+/// use sxd_xpath::stream::{PushEvaluator, StreamPath};
+/// use sxd_xpath::{Context, Factory};
+///
+/// let factory = Factory::new();
+/// let context = Context::new();
+/// let ids = factory.build("//entry/@id").unwrap();
+/// let titles = factory.build("//entry/title").unwrap();
+///
+/// let seen = RefCell::new(Vec::new());
+/// let mut evaluator = PushEvaluator::new();
+/// evaluator.register(StreamPath::new(&ids, &context).unwrap(), |id| {
+///     seen.borrow_mut().push(format!("id {}", id))
+/// });
+/// evaluator.register(StreamPath::new(&titles, &context).unwrap(), |title| {
+///     seen.borrow_mut().push(format!("title {}", title))
+/// });
+///
+/// evaluator.start_element(QName::new("entry"), &[(QName::new("id"), "7")]);
+/// evaluator.start_element(QName::new("title"), &[]);
+/// evaluator.text("Hello, ");
+/// evaluator.text("world");
+/// evaluator.end_element();
+/// evaluator.end_element();
+/// drop(evaluator);
+///
+/// assert_eq!(seen.into_inner(), ["id 7", "title Hello, world"]);
+/// ```
+///
+/// [`StreamPath`]: struct.StreamPath.html
+pub struct PushEvaluator<'a> {
+    registrations: Vec<Registration<'a>>,
+    /// Adjacent text is reported as a single text node
+    pending_text: String,
+}
+
+struct Registration<'a> {
+    path: StreamPath,
+    matcher: Matcher,
+    callback: Box<dyn FnMut(String) + 'a>,
+}
+
+impl<'a> PushEvaluator<'a> {
+    pub fn new() -> Self {
+        PushEvaluator {
+            registrations: Vec::new(),
+            pending_text: String::new(),
+        }
+    }
+
+    /// Calls the function with the string value of each node that the
+    /// path selects.
+    pub fn register<F>(&mut self, path: StreamPath, callback: F)
+    where
+        F: FnMut(String) + 'a,
+    {
+        let matcher = Matcher::new(&path);
+        self.registrations.push(Registration {
+            path,
+            matcher,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// An element has been opened. Attribute values should already
+    /// have entities replaced.
+    pub fn start_element(&mut self, name: QName<'_>, attributes: &[(QName<'_>, &str)]) {
+        let name = ElementName {
+            namespace_uri: name.namespace_uri().map(Into::into),
+            local_part: name.local_part().into(),
+        };
+        let attributes: Vec<_> = attributes
+            .iter()
+            .map(|&(name, value)| Attribute {
+                namespace_uri: name.namespace_uri().map(Into::into),
+                local_part: name.local_part().into(),
+                value: value.into(),
+            })
+            .collect();
+
+        self.start_resolved_element(&name, &attributes);
+    }
+
+    fn start_resolved_element(&mut self, name: &ElementName, attributes: &[Attribute]) {
+        self.flush_text();
+        for r in &mut self.registrations {
+            r.matcher.start_element(&r.path, name, attributes);
+            r.matcher.emit(&mut r.callback);
+        }
+    }
+
+    /// Character data has been read. Text may be split across any
+    /// number of calls.
+    pub fn text(&mut self, text: &str) {
+        self.pending_text.push_str(text);
+    }
+
+    /// A comment has been read. The text on either side of it belongs
+    /// to separate text nodes.
+    pub fn comment(&mut self) {
+        self.flush_text();
+    }
+
+    /// A processing instruction has been read. The text on either side
+    /// of it belongs to separate text nodes.
+    pub fn processing_instruction(&mut self) {
+        self.flush_text();
+    }
+
+    /// The most recently opened element has been closed.
+    pub fn end_element(&mut self) {
+        self.flush_text();
+        for r in &mut self.registrations {
+            r.matcher.end_element();
+            r.matcher.emit(&mut r.callback);
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if self.pending_text.is_empty() {
+            return;
+        }
+
+        for r in &mut self.registrations {
+            r.matcher.text(&r.path, &self.pending_text);
+            r.matcher.emit(&mut r.callback);
+        }
+        self.pending_text.clear();
+    }
+}
+
+impl<'a> Default for PushEvaluator<'a> {
+    fn default() -> Self {
+        PushEvaluator::new()
+    }
+}
+
+/// Tracks the progress of a path through the open elements.
+///
+/// Each open element has a frame listing the indices of the steps
+/// that its children may match next.
+struct Matcher {
+    frames: Vec<Vec<usize>>,
+    /// The depth and result slot of selected elements that are still open
+    captures: Vec<(usize, usize)>,
+    /// Results that have not been reported, starting at slot `emitted`
+    results: VecDeque<Option<String>>,
+    emitted: usize,
+}
+
+impl Matcher {
+    fn new(path: &StreamPath) -> Self {
+        Matcher {
+            frames: vec![path.initial_frame()],
+            captures: Vec::new(),
+            results: VecDeque::new(),
+            emitted: 0,
+        }
+    }
+
+    fn push_result(&mut self, value: Option<String>) -> usize {
+        self.results.push_back(value);
+        self.emitted + self.results.len() - 1
+    }
+
+    fn start_element(&mut self, path: &StreamPath, name: &ElementName, attributes: &[Attribute]) {
+        let parent = self.frames.last().map(Vec::as_slice).unwrap_or_default();
+        let (frame, selected) = path.advance(parent, name, attributes);
+
+        if selected {
+            let slot = self.push_result(None);
+            self.captures.push((self.frames.len(), slot));
+        }
+
+        for &i in &frame {
+            let step = &path.steps[i];
+            if step.axis != StepAxis::Attribute {
+                continue;
+            }
+
+            for attribute in attributes {
+                let matches = match step.test {
+                    StepTest::Name(ref n) => attribute.is(n),
+                    StepTest::Node => true,
+                    StepTest::Text => false,
+                };
+                if matches {
+                    self.push_result(Some(attribute.value.clone()));
+                }
+            }
+        }
+
+        self.frames.push(frame);
+    }
+
+    fn text(&mut self, path: &StreamPath, text: &str) {
+        for &(_, slot) in &self.captures {
+            self.results[slot - self.emitted]
+                .get_or_insert_with(String::new)
+                .push_str(text);
+        }
+
+        let frame = match self.frames.last() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let selected = frame.iter().any(|&i| {
+            let step = &path.steps[i];
+            let axis_matches = step.axis == StepAxis::Child || step.axis == StepAxis::Descendant;
+            let test_matches = step.test == StepTest::Text || step.test == StepTest::Node;
+            axis_matches && test_matches && step.predicates.is_empty() && path.is_last(i)
+        });
+
+        if selected {
+            self.push_result(Some(text.to_owned()));
+        }
+    }
+
+    fn end_element(&mut self) {
+        self.frames.pop();
+        let depth = self.frames.len();
+
+        while let Some(&(d, slot)) = self.captures.last() {
+            if d != depth {
+                break;
+            }
+            self.captures.pop();
+            self.results[slot - self.emitted].get_or_insert_with(String::new);
+        }
+    }
+
+    /// Passes along the results that are complete, in document order.
+    fn emit(&mut self, f: &mut dyn FnMut(String)) {
+        // Captures are nested, so the outermost open one is first
+        let open = self.captures.first().map(|&(_, slot)| slot);
+
+        while open != Some(self.emitted) {
+            match self.results.front() {
+                Some(Some(_)) => {}
+                _ => break,
+            }
+            if let Some(Some(value)) = self.results.pop_front() {
+                f(value);
+            }
+            self.emitted += 1;
+        }
+    }
+}
+
+/// Adds the steps that `descendant-or-self::node()` steps also allow
+/// to match at the same level.
+fn expand(path: &StreamPath, mut frame: Vec<usize>) -> Vec<usize> {
+    let mut i = 0;
+    while i < frame.len() {
+        let step = frame[i];
+        if path.steps[step].axis == StepAxis::AnyDepth && !frame.contains(&(step + 1)) {
+            frame.push(step + 1);
+        }
+        i += 1;
+    }
+    frame.sort_unstable();
+    frame.dedup();
+    frame
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Factory;
+
+    use super::*;
+
+    fn path(xpath: &str) -> Result<StreamPath, Error> {
+        let mut context = Context::new();
+        context.set_namespace("a", "urn:atom");
+
+        let xpath = Factory::new().build(xpath).expect("Unable to build XPath");
+        StreamPath::new(&xpath, &context)
+    }
+
+    #[cfg(feature = "stream")]
+    fn stream(xpath: &str, xml: &str) -> Result<Vec<String>, Error> {
+        path(xpath)?.evaluate(&mut NsReader::from_reader(xml.as_bytes()))
+    }
+
+    #[cfg(feature = "stream")]
+    const FEED: &str = r#"<feed xmlns:x="urn:atom">
+        <entry kind="post"><id>1</id><title>One &amp; only</title></entry>
+        <entry><id>2</id><x:id>ns</x:id></entry>
+        <other><entry><id>3</id></entry></other>
+    </feed>"#;
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn selects_child_elements() {
+        assert_eq!(stream("/feed/entry/id", FEED).unwrap(), ["1", "2"]);
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn selects_descendant_elements() {
+        assert_eq!(stream("//entry/id", FEED).unwrap(), ["1", "2", "3"]);
+        assert_eq!(
+            stream("/feed/descendant::id", FEED).unwrap(),
+            ["1", "2", "3"]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn selects_namespaced_elements() {
+        assert_eq!(stream("//a:id", FEED).unwrap(), ["ns"]);
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn selects_elements_in_any_namespace() {
+        assert_eq!(stream("/feed/entry/*:id", FEED).unwrap(), ["1", "2", "ns"]);
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn selects_attributes_and_text() {
+        assert_eq!(stream("//entry/@kind", FEED).unwrap(), ["post"]);
+        assert_eq!(stream("//title/text()", FEED).unwrap(), ["One & only"]);
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn comments_and_processing_instructions_separate_text() {
+        let xml = "<t>a<!--x-->b<?p?>c</t>";
+        assert_eq!(stream("//t/text()", xml).unwrap(), ["a", "b", "c"]);
+        assert_eq!(stream("/t", xml).unwrap(), ["abc"]);
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn selected_elements_have_their_string_value() {
+        assert_eq!(stream("/feed/entry[@kind]", FEED).unwrap(), ["1One & only"]);
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn predicates_compare_attributes() {
+        assert_eq!(stream("//entry[@kind = 'post']/id", FEED).unwrap(), ["1"]);
+        assert_eq!(
+            stream("//entry[@kind = 'page']/id", FEED).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn nested_selections_are_in_document_order() {
+        let xml = "<a><b>1<b>2</b></b><b>3</b></a>";
+        assert_eq!(stream("//b", xml).unwrap(), ["12", "2", "3"]);
+    }
+
+    #[test]
+    fn rejects_xpaths_that_need_lookbehind() {
+        assert!(path("//id/..").is_err());
+        assert!(path("//entry[1]").is_err());
+        assert!(path("count(//entry)").is_err());
+        assert!(path("entry").is_err());
+    }
+
+    #[test]
+    fn rejects_paths_without_steps() {
+        let xpath = XPath::from(Expr::Path {
+            start_point: Box::new(Expr::RootNode),
+            steps: Vec::new(),
+        });
+        assert!(StreamPath::new(&xpath, &Context::new()).is_err());
+    }
+
+    fn push<F>(xpath: &str, events: F) -> Vec<String>
+    where
+        F: FnOnce(&mut PushEvaluator<'_>),
+    {
+        let mut results = Vec::new();
+        {
+            let mut evaluator = PushEvaluator::new();
+            evaluator.register(path(xpath).unwrap(), |v| results.push(v));
+            events(&mut evaluator);
+        }
+        results
+    }
+
+    #[test]
+    fn pushed_text_is_coalesced() {
+        let results = push("//id/text()", |e| {
+            e.start_element(QName::new("id"), &[]);
+            e.text("12");
+            e.text("34");
+            e.end_element();
+        });
+        assert_eq!(results, ["1234"]);
+    }
+
+    #[test]
+    fn pushed_text_is_split_by_comments_and_processing_instructions() {
+        let results = push("//id/text()", |e| {
+            e.start_element(QName::new("id"), &[]);
+            e.text("1");
+            e.comment();
+            e.text("2");
+            e.processing_instruction();
+            e.text("3");
+            e.end_element();
+        });
+        assert_eq!(results, ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn pushed_results_are_reported_when_complete() {
+        let results = std::cell::RefCell::new(Vec::new());
+        let mut evaluator = PushEvaluator::new();
+        evaluator.register(path("/a/b").unwrap(), |v| results.borrow_mut().push(v));
+
+        evaluator.start_element(QName::new("a"), &[]);
+        evaluator.start_element(QName::new("b"), &[]);
+        evaluator.text("x");
+        assert!(results.borrow().is_empty());
+
+        evaluator.end_element();
+        assert_eq!(*results.borrow(), ["x"]);
+    }
+
+    #[test]
+    fn pushed_events_fire_every_registration() {
+        let ids = std::cell::RefCell::new(Vec::new());
+        let names = std::cell::RefCell::new(Vec::new());
+        {
+            let mut evaluator = PushEvaluator::new();
+            evaluator.register(path("//a:entry/@id").unwrap(), |v| ids.borrow_mut().push(v));
+            evaluator.register(path("//a:entry/name").unwrap(), |v| {
+                names.borrow_mut().push(v)
+            });
+
+            let entry = QName::with_namespace_uri(Some("urn:atom"), "entry");
+            for (id, name) in &[("1", "one"), ("2", "two")] {
+                evaluator.start_element(entry, &[(QName::new("id"), id)]);
+                evaluator.start_element(QName::new("name"), &[]);
+                evaluator.text(name);
+                evaluator.end_element();
+                evaluator.end_element();
+            }
+        }
+
+        assert_eq!(ids.into_inner(), ["1", "2"]);
+        assert_eq!(names.into_inner(), ["one", "two"]);
+    }
+}
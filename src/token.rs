@@ -31,19 +31,38 @@ pub enum NodeTestName {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     And,
+    Array,
+    Arrow,
+    Assign,
     AtSign,
+    CastAs,
+    CastableAs,
+    Colon,
     Comma,
     CurrentNode,
     Divide,
     DoubleSlash,
+    Else,
     Equal,
+    Every,
+    Follows,
+    For,
     GreaterThan,
     GreaterThanOrEqual,
+    If,
+    In,
+    InlineFunction,
+    InstanceOf,
+    Is,
+    LeftBrace,
     LeftBracket,
     LeftParen,
     LessThan,
     LessThanOrEqual,
+    Let,
     Literal(string::String),
+    Lookup,
+    Map,
     MinusSign,
     Multiply,
     NotEqual,
@@ -52,10 +71,25 @@ pub enum Token {
     ParentNode,
     Pipe,
     PlusSign,
+    Precedes,
+    QuestionMark,
     Remainder,
+    Return,
+    RightBrace,
     RightBracket,
     RightParen,
+    Satisfies,
     Slash,
+    Some,
+    Then,
+    To,
+    TreatAs,
+    ValueEqual,
+    ValueGreaterThan,
+    ValueGreaterThanOrEqual,
+    ValueLessThan,
+    ValueLessThanOrEqual,
+    ValueNotEqual,
 
     // Specializations
     Axis(AxisName),
@@ -67,38 +101,60 @@ pub enum Token {
 
 impl Token {
     pub fn precedes_node_test(&self) -> bool {
-        match *self {
-            Token::AtSign | Token::Axis(..) => true,
-            _ => false,
-        }
+        matches!(*self, Token::AtSign | Token::Axis(..) | Token::Lookup)
     }
 
     pub fn precedes_expression(&self) -> bool {
-        match *self {
-            Token::LeftParen | Token::LeftBracket => true,
-            _ => false,
-        }
+        matches!(
+            *self,
+            Token::LeftParen
+                | Token::LeftBracket
+                | Token::Comma
+                | Token::Then
+                | Token::Else
+                | Token::In
+                | Token::Return
+                | Token::Assign
+                | Token::Satisfies
+                | Token::LeftBrace
+                | Token::Colon
+        )
     }
 
     pub fn is_operator(&self) -> bool {
-        match *self {
+        matches!(
+            *self,
             Token::Slash
-            | Token::DoubleSlash
-            | Token::PlusSign
-            | Token::MinusSign
-            | Token::Pipe
-            | Token::Equal
-            | Token::NotEqual
-            | Token::LessThan
-            | Token::LessThanOrEqual
-            | Token::GreaterThan
-            | Token::GreaterThanOrEqual
-            | Token::And
-            | Token::Or
-            | Token::Remainder
-            | Token::Divide
-            | Token::Multiply => true,
-            _ => false,
-        }
+                | Token::DoubleSlash
+                | Token::PlusSign
+                | Token::MinusSign
+                | Token::Pipe
+                | Token::Equal
+                | Token::NotEqual
+                | Token::LessThan
+                | Token::LessThanOrEqual
+                | Token::GreaterThan
+                | Token::GreaterThanOrEqual
+                | Token::And
+                | Token::Or
+                | Token::Remainder
+                | Token::Divide
+                | Token::Multiply
+                | Token::To
+                | Token::Is
+                | Token::Precedes
+                | Token::Follows
+                | Token::ValueEqual
+                | Token::ValueNotEqual
+                | Token::ValueLessThan
+                | Token::ValueLessThanOrEqual
+                | Token::ValueGreaterThan
+                | Token::ValueGreaterThanOrEqual
+                | Token::InstanceOf
+                | Token::TreatAs
+                | Token::CastAs
+                | Token::CastableAs
+                | Token::Arrow
+        )
     }
 }
@@ -2,18 +2,57 @@ use peresil::{self, try_parse, Identifier, ParseMaster, Recoverable, StringPoint
 use snafu::Snafu;
 use std::borrow::ToOwned;
 use std::collections::VecDeque;
+use std::ops::Range;
 use std::string;
 use sxd_document::parser::XmlParseExt;
+use sxd_document::XmlChar;
 
 use crate::node_test;
 use crate::token::{AxisName, NodeTestName, Token};
 
+/// Splits an XPath into tokens.
+///
+/// The tokens are produced as they are written, without expanding
+/// abbreviations such as `//` or `@`. Tokens that cannot be made from
+/// the text are errors, and the tokenizer continues after them, so an
+/// XPath that is being edited can still be highlighted.
+///
+/// ```
+/// use sxd_xpath::token::Token;
+/// use sxd_xpath::tokenizer::Tokenizer;
+///
+/// let xpath = "count($items) > 'x'";
+/// let strings: Vec<_> = Tokenizer::new(xpath)
+///     .with_spans()
+///     .filter(|(token, _)| matches!(token, Ok(Token::Literal(_))))
+///     .map(|(_, span)| &xpath[span.range()])
+///     .collect();
+///
+/// assert_eq!(vec!["'x'"], strings);
+/// ```
 pub struct Tokenizer {
     xpath: string::String,
     start: usize,
+    span: Span,
     prefer_recognition_of_operator_names: bool,
 }
 
+/// A part of an XPath, in bytes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The offset of the first byte
+    pub offset: usize,
+    /// The number of bytes
+    pub length: usize,
+}
+
+impl Span {
+    /// The range of the XPath's bytes, to slice the XPath with.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.length
+    }
+}
+
 type XPathMaster<'a> = ParseMaster<StringPoint<'a>, Error>;
 type XPathProgress<'a, T, E> = peresil::Progress<StringPoint<'a>, T, E>;
 
@@ -28,6 +67,8 @@ pub enum Error {
     ExpectedNumber,
     /// Expected the current node token
     ExpectedCurrentNode,
+    /// expected a keyword
+    ExpectedKeyword,
     /// expected a named operator
     ExpectedNamedOperator,
     /// expected an axis name
@@ -46,10 +87,14 @@ pub enum Error {
     ExpectedToken,
     /// expected a left parenthesis
     ExpectedLeftParenthesis,
+    /// expected a lookup
+    ExpectedLookup,
     /// internal error
     NotTokenizingNamedOperators,
     /// mismatched quote character
     MismatchedQuoteCharacters,
+    /// comment was not closed
+    UnterminatedComment,
     /// unable to create token
     UnableToCreateToken,
 }
@@ -57,10 +102,7 @@ pub enum Error {
 impl Recoverable for Error {
     fn recoverable(&self) -> bool {
         use self::Error::*;
-        match *self {
-            MismatchedQuoteCharacters | UnableToCreateToken => false,
-            _ => true,
-        }
+        !matches!(*self, MismatchedQuoteCharacters | UnableToCreateToken)
     }
 }
 
@@ -87,12 +129,12 @@ trait XPathParseExt<'a> {
 
 impl<'a> XPathParseExt<'a> for StringPoint<'a> {
     fn consume_quoted_string(&self, quote: &str) -> XPathProgress<'a, &'a str, ()> {
-        let end_of_str = self.s.find(quote).or_else(|| Some(self.s.len()));
+        let end_of_str = self.s.find(quote).or(Some(self.s.len()));
         self.consume_to(end_of_str)
     }
 }
 
-static SINGLE_CHAR_TOKENS: [Identifier<'static, Token>; 13] = [
+static SINGLE_CHAR_TOKENS: [Identifier<'static, Token>; 17] = [
     ("/", Token::Slash),
     ("(", Token::LeftParen),
     (")", Token::RightParen),
@@ -106,24 +148,90 @@ static SINGLE_CHAR_TOKENS: [Identifier<'static, Token>; 13] = [
     ("<", Token::LessThan),
     (">", Token::GreaterThan),
     (",", Token::Comma),
+    ("?", Token::QuestionMark),
+    ("{", Token::LeftBrace),
+    ("}", Token::RightBrace),
+    (":", Token::Colon),
 ];
 
-static TWO_CHAR_TOKENS: [Identifier<'static, Token>; 5] = [
+static TWO_CHAR_TOKENS: [Identifier<'static, Token>; 9] = [
     ("<=", Token::LessThanOrEqual),
     (">=", Token::GreaterThanOrEqual),
     ("!=", Token::NotEqual),
     ("//", Token::DoubleSlash),
     ("..", Token::ParentNode),
+    (":=", Token::Assign),
+    ("<<", Token::Precedes),
+    (">>", Token::Follows),
+    ("=>", Token::Arrow),
 ];
 
-static NAMED_OPERATORS: [Identifier<'static, Token>; 5] = [
+static NAMED_OPERATORS: [Identifier<'static, Token>; 18] = [
     ("and", Token::And),
     ("or", Token::Or),
     ("mod", Token::Remainder),
     ("div", Token::Divide),
     ("*", Token::Multiply),
+    ("then", Token::Then),
+    ("else", Token::Else),
+    ("in", Token::In),
+    ("return", Token::Return),
+    ("satisfies", Token::Satisfies),
+    ("to", Token::To),
+    ("is", Token::Is),
+    ("eq", Token::ValueEqual),
+    ("ne", Token::ValueNotEqual),
+    ("lt", Token::ValueLessThan),
+    ("le", Token::ValueLessThanOrEqual),
+    ("gt", Token::ValueGreaterThan),
+    ("ge", Token::ValueGreaterThanOrEqual),
+];
+
+// Operators spelled as two words, which may be separated by any
+// amount of whitespace.
+static COMPOUND_OPERATORS: [(&str, &str, Token); 4] = [
+    ("instance", "of", Token::InstanceOf),
+    ("treat", "as", Token::TreatAs),
+    ("castable", "as", Token::CastableAs),
+    ("cast", "as", Token::CastAs),
+];
+
+// Keywords that begin an expression, along with the text that must
+// follow them to distinguish them from a name.
+static EXPRESSION_KEYWORDS: [(&str, &str, Token); 8] = [
+    ("if", "(", Token::If),
+    ("for", "$", Token::For),
+    ("let", "$", Token::Let),
+    ("some", "$", Token::Some),
+    ("every", "$", Token::Every),
+    ("map", "{", Token::Map),
+    ("array", "{", Token::Array),
+    ("function", "(", Token::InlineFunction),
 ];
 
+/// How the token is written, for tokens that do not carry a value.
+pub(crate) fn spelling(token: &Token) -> Option<String> {
+    let identifiers = SINGLE_CHAR_TOKENS
+        .iter()
+        .chain(&TWO_CHAR_TOKENS)
+        .chain(&NAMED_OPERATORS);
+    let words = COMPOUND_OPERATORS.iter().chain(&EXPRESSION_KEYWORDS);
+
+    match *token {
+        Token::CurrentNode => Some(".".to_owned()),
+        Token::Lookup => Some("?".to_owned()),
+        Token::InstanceOf | Token::TreatAs | Token::CastableAs | Token::CastAs => words
+            .filter(|(_, _, t)| t == token)
+            .map(|(first, second, _)| format!("{} {}", first, second))
+            .next(),
+        _ => identifiers
+            .map(|(text, t)| (*text, t))
+            .chain(words.map(|(text, _, t)| (*text, t)))
+            .find(|(_, t)| *t == token)
+            .map(|(text, _)| text.to_owned()),
+    }
+}
+
 // These will be matched in order, so substrings should come later.
 static AXES: [Identifier<'static, AxisName>; 13] = [
     ("ancestor-or-self", AxisName::AncestorOrSelf),
@@ -141,6 +249,11 @@ static AXES: [Identifier<'static, AxisName>; 13] = [
     ("self", AxisName::SelfAxis),
 ];
 
+/// The names of the axes, without the `::` that follows them.
+pub(crate) fn axis_names() -> impl Iterator<Item = &'static str> {
+    AXES.iter().map(|&(name, _)| name)
+}
+
 static NODE_TESTS: [Identifier<'static, NodeTestName>; 4] = [
     ("comment", NodeTestName::Comment),
     ("text", NodeTestName::Text),
@@ -201,6 +314,22 @@ fn parse_number<'a>(
         peresil::Progress::success(p, ())
     }
 
+    fn exponent(p: StringPoint<'_>) -> XPathProgress<'_, (), ()> {
+        let marker = match p.s.chars().next() {
+            Some('e') | Some('E') => Some(1),
+            _ => None,
+        };
+        let (p, _) = try_parse!(p.consume_to(marker));
+        let sign = match p.s.chars().next() {
+            Some('+') | Some('-') => Some(1),
+            _ => Some(0),
+        };
+        let (p, _) = try_parse!(p.consume_to(sign));
+        let (p, _) = try_parse!(p.consume_decimal_chars());
+
+        peresil::Progress::success(p, ())
+    }
+
     let before_p = p;
 
     let (p, _) = try_parse!({
@@ -209,6 +338,7 @@ fn parse_number<'a>(
             .one(|_| without_integer(p).context(ExpectedNumber))
             .finish()
     });
+    let (p, _) = exponent(p).optional(p);
 
     let num = before_p.to(p);
     // Should always be able to parse as we've pre-validated with our own parser
@@ -217,6 +347,19 @@ fn parse_number<'a>(
     peresil::Progress::success(p, Token::Number(num))
 }
 
+fn parse_lookup(p: StringPoint<'_>) -> XPathProgress<'_, Token, Error> {
+    let (q, _) = try_parse!(p.consume_literal("?").context(ExpectedLookup));
+
+    // The key must follow immediately, otherwise this is the `?`
+    // occurrence indicator of a sequence type
+    match q.s.chars().next() {
+        Some(c) if c.is_ncname_start_char() || c.is_ascii_digit() || c == '*' || c == '(' => {
+            peresil::Progress::success(q, Token::Lookup)
+        }
+        _ => peresil::Progress::failure(p, Error::ExpectedLookup),
+    }
+}
+
 fn parse_current_node(p: StringPoint<'_>) -> XPathProgress<'_, Token, Error> {
     let (p, _) = try_parse!(p.consume_literal(".").context(ExpectedCurrentNode));
 
@@ -227,7 +370,22 @@ fn parse_named_operators(
     p: StringPoint<'_>,
     prefer_named_ops: bool,
 ) -> XPathProgress<'_, Token, Error> {
+    fn compound<'a>(p: StringPoint<'a>, first: &str, second: &str) -> XPathProgress<'a, (), ()> {
+        let (p, _) = try_parse!(p.consume_literal(first));
+        let (p, _) = try_parse!(p.consume_space());
+        let (p, _) = try_parse!(p.consume_literal(second));
+
+        peresil::Progress::success(p, ())
+    }
+
     if prefer_named_ops {
+        // Compound operators come first, as `in` is a prefix of `instance`
+        for (first, second, token) in &COMPOUND_OPERATORS {
+            if let (q, Some(())) = compound(p, first, second).optional(p) {
+                return peresil::Progress::success(q, token.clone());
+            }
+        }
+
         p.consume_identifier(&NAMED_OPERATORS)
             .context(ExpectedNamedOperator)
     } else {
@@ -236,6 +394,26 @@ fn parse_named_operators(
     }
 }
 
+fn parse_expression_keyword(p: StringPoint<'_>) -> XPathProgress<'_, Token, Error> {
+    fn keyword<'a>(p: StringPoint<'a>, keyword: &str, follower: &str) -> XPathProgress<'a, (), ()> {
+        let (p, _) = try_parse!(p.consume_literal(keyword));
+        // Do not advance the point past the follower, which is a
+        // token of its own
+        let (q, _) = p.consume_space().optional(p);
+        try_parse!(q.consume_literal(follower));
+
+        peresil::Progress::success(p, ())
+    }
+
+    for (name, follower, token) in &EXPRESSION_KEYWORDS {
+        if let (q, Some(())) = keyword(p, name, follower).optional(p) {
+            return peresil::Progress::success(q, token.clone());
+        }
+    }
+
+    peresil::Progress::failure(p, Error::ExpectedKeyword)
+}
+
 fn parse_axis_specifier(p: StringPoint<'_>) -> XPathProgress<'_, Token, Error> {
     // Ideally, we would check for the pair of the name and the ::,
     // then loop. This would prevent us from having to order AXES.
@@ -306,6 +484,18 @@ fn parse_name_test<'a>(
         peresil::Progress::success(p, Token::NameTest(name))
     }
 
+    fn any_namespace(p: StringPoint<'_>) -> XPathProgress<'_, Token, ()> {
+        let (p, wc) = try_parse!(p.consume_literal("*"));
+        let (p, _) = try_parse!(p.consume_literal(":"));
+        let (p, local_part) = try_parse!(p.consume_ncname());
+
+        let name = node_test::NameTest {
+            prefix: Some(wc.to_owned()),
+            local_part: local_part.to_owned(),
+        };
+        peresil::Progress::success(p, Token::NameTest(name))
+    }
+
     fn prefixed_name(p: StringPoint<'_>) -> XPathProgress<'_, Token, ()> {
         p.consume_prefixed_name().map(|name| {
             Token::NameTest(node_test::NameTest {
@@ -316,6 +506,7 @@ fn parse_name_test<'a>(
     }
 
     pm.alternate()
+        .one(|_| any_namespace(p).context(ExpectedNameTest))
         .one(|_| wildcard(p).context(ExpectedNameTest))
         .one(|_| prefixed_wildcard(p).context(ExpectedNameTest))
         .one(|_| prefixed_name(p).context(ExpectedNameTest))
@@ -334,14 +525,29 @@ impl Tokenizer {
         Tokenizer {
             xpath: xpath.to_owned(),
             start: 0,
+            span: Span::default(),
             prefer_recognition_of_operator_names: false,
         }
     }
 
+    /// The part of the XPath of the last token, or of the text that
+    /// could not be made into a token, which is skipped when more tokens
+    /// are asked for. Once all tokens have been produced, this is the
+    /// empty span at the end of the XPath.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
     pub fn has_more_tokens(&self) -> bool {
         self.xpath.len() > self.start
     }
 
+    /// Produces each token along with its span. The tokenizer can still
+    /// be used afterwards, such as to find where it stopped.
+    pub fn with_spans(&mut self) -> WithSpans<'_> {
+        WithSpans { tokenizer: self }
+    }
+
     fn parse_token<'a>(
         &self,
         pm: &mut XPathMaster<'a>,
@@ -355,6 +561,7 @@ impl Tokenizer {
                     p.consume_identifier(&TWO_CHAR_TOKENS)
                         .context(ExpectedToken)
                 })
+                .one(|_| parse_lookup(p))
                 .one(|_| {
                     p.consume_identifier(&SINGLE_CHAR_TOKENS)
                         .context(ExpectedToken)
@@ -363,6 +570,7 @@ impl Tokenizer {
                 .one(|pm| parse_number(pm, p))
                 .one(|_| parse_current_node(p))
                 .one(|_| parse_named_operators(p, self.prefer_recognition_of_operator_names))
+                .one(|_| parse_expression_keyword(p))
                 .one(|_| parse_axis_specifier(p))
                 .one(|pm| parse_node_type(pm, p))
                 .one(|_| parse_function_call(p))
@@ -376,7 +584,49 @@ impl Tokenizer {
         peresil::Progress::success(p, tok)
     }
 
+    /// Advances past whitespace and `(: comments :)`, which may be
+    /// nested.
+    fn skip_ignorable(&mut self) -> Result<(), Error> {
+        loop {
+            let rest = &self.xpath[self.start..];
+            let trimmed = rest.trim_start_matches([' ', '\t', '\r', '\n']);
+            self.start += rest.len() - trimmed.len();
+
+            if !trimmed.starts_with("(:") {
+                return Ok(());
+            }
+
+            let mut depth = 0;
+            let mut length = 0;
+            loop {
+                let rest = &trimmed[length..];
+                if rest.starts_with("(:") {
+                    depth += 1;
+                    length += 2;
+                } else if rest.starts_with(":)") {
+                    depth -= 1;
+                    length += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else if let Some(c) = rest.chars().next() {
+                    length += c.len_utf8();
+                } else {
+                    self.span = Span {
+                        offset: self.start,
+                        length: trimmed.len(),
+                    };
+                    self.start = self.xpath.len();
+                    return UnterminatedComment.fail();
+                }
+            }
+            self.start += length;
+        }
+    }
+
     fn raw_next_token(&mut self) -> TokenResult {
+        self.skip_ignorable()?;
+
         let mut pm = ParseMaster::new();
         let p = StringPoint {
             s: &self.xpath[self.start..],
@@ -389,19 +639,43 @@ impl Tokenizer {
                 status: peresil::Status::Success(data),
                 point,
             } => {
+                let text = &self.xpath[self.start..point.offset];
+                self.span = Span {
+                    offset: self.start,
+                    length: text.trim_end().len(),
+                };
                 self.start = point.offset;
+                // Trailing comments should not look like another token.
+                // One that is never closed is reported in place of the
+                // next token, rather than losing this one.
+                let (start, span) = (self.start, self.span);
+                if self.skip_ignorable().is_err() {
+                    self.start = start;
+                    self.span = span;
+                }
                 Ok(data)
             }
             peresil::Progress {
                 status: peresil::Status::Failure(mut e),
                 point,
             } => {
-                if point.offset == self.start {
-                    UnableToCreateToken.fail()
+                let rest = &self.xpath[self.start..];
+                let (error, length) = if point.offset == self.start {
+                    let length = rest.chars().next().map_or(0, char::len_utf8);
+                    (Error::UnableToCreateToken, length)
                 } else {
                     // Should always have one error, otherwise we wouldn't be here!
-                    Err(e.pop().expect("Unknown error while parsing"))
-                }
+                    let error = e.pop().expect("Unknown error while parsing");
+                    (error, point.offset - self.start)
+                };
+
+                // Continue after the text, for those that want more tokens
+                self.span = Span {
+                    offset: self.start,
+                    length,
+                };
+                self.start += length;
+                Err(error)
             }
         }
     }
@@ -424,57 +698,88 @@ impl Iterator for Tokenizer {
     type Item = TokenResult;
 
     fn next(&mut self) -> Option<TokenResult> {
+        if let Err(e) = self.skip_ignorable() {
+            return Some(Err(e));
+        }
+
         if self.has_more_tokens() {
             Some(self.next_token())
         } else {
+            self.span = Span {
+                offset: self.xpath.len(),
+                length: 0,
+            };
             None
         }
     }
 }
 
-pub struct TokenDeabbreviator<I> {
-    source: I,
-    buffer: VecDeque<Token>,
+/// The tokens of a [`Tokenizer`](struct.Tokenizer.html) along with
+/// their spans.
+pub struct WithSpans<'a> {
+    tokenizer: &'a mut Tokenizer,
 }
 
-// Avoid adding the first element to the buffer, but keep the
-// expansion values nicely co-located.
-macro_rules! deabbrev {
-    ($this:expr, $head:expr $(, $tail:expr)*) => {{
-        $this.buffer.extend([$( $tail, )*].iter().cloned());
-        $head
-    }}
-}
+impl Iterator for WithSpans<'_> {
+    type Item = (TokenResult, Span);
 
-impl<I> TokenDeabbreviator<I> {
-    pub fn new(source: I) -> TokenDeabbreviator<I> {
-        TokenDeabbreviator {
-            source,
-            buffer: Default::default(),
-        }
+    fn next(&mut self) -> Option<(TokenResult, Span)> {
+        let token = self.tokenizer.next()?;
+        Some((token, self.tokenizer.span()))
     }
+}
+
+/// Rewrites the tokens of an XPath between tokenizing and parsing, such
+/// as to expand an abbreviation into the tokens it stands for.
+///
+/// Each token is given to the transformer in the order it is written,
+/// and is replaced by the tokens returned, which are given the span of
+/// the token they replace.
+pub trait TokenTransformer {
+    fn transform(&mut self, token: Token) -> Vec<Token>;
+}
+
+/// Expands the abbreviations of XPath, such as `@` and `//`, into the
+/// axes and node tests they stand for. The parser only understands the
+/// expanded tokens.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Deabbreviator;
 
-    fn expand_token(&mut self, token: Token) -> Token {
+impl TokenTransformer for Deabbreviator {
+    fn transform(&mut self, token: Token) -> Vec<Token> {
         match token {
-            Token::AtSign => deabbrev!(self, Token::Axis(AxisName::Attribute)),
-            Token::DoubleSlash => deabbrev!(
-                self,
+            Token::AtSign => vec![Token::Axis(AxisName::Attribute)],
+            Token::DoubleSlash => vec![
                 Token::Slash,
                 Token::Axis(AxisName::DescendantOrSelf),
                 Token::NodeTest(NodeTestName::Node),
-                Token::Slash
-            ),
-            Token::CurrentNode => deabbrev!(
-                self,
+                Token::Slash,
+            ],
+            Token::CurrentNode => vec![
                 Token::Axis(AxisName::SelfAxis),
-                Token::NodeTest(NodeTestName::Node)
-            ),
-            Token::ParentNode => deabbrev!(
-                self,
+                Token::NodeTest(NodeTestName::Node),
+            ],
+            Token::ParentNode => vec![
                 Token::Axis(AxisName::Parent),
-                Token::NodeTest(NodeTestName::Node)
-            ),
-            _ => token,
+                Token::NodeTest(NodeTestName::Node),
+            ],
+            _ => vec![token],
+        }
+    }
+}
+
+/// The tokens of the source with their abbreviations expanded by the
+/// [`Deabbreviator`](struct.Deabbreviator.html).
+pub struct TokenDeabbreviator<I> {
+    source: I,
+    buffer: VecDeque<Token>,
+}
+
+impl<I> TokenDeabbreviator<I> {
+    pub fn new(source: I) -> TokenDeabbreviator<I> {
+        TokenDeabbreviator {
+            source,
+            buffer: Default::default(),
         }
     }
 }
@@ -486,16 +791,13 @@ where
     type Item = TokenResult;
 
     fn next(&mut self) -> Option<TokenResult> {
-        if let Some(tok) = self.buffer.pop_front() {
-            return Some(Ok(tok));
-        }
-
-        let token = self.source.next();
-
-        match token {
-            None | Some(Err(_)) => token,
-            Some(Ok(token)) => Some(Ok(self.expand_token(token))),
+        while self.buffer.is_empty() {
+            match self.source.next()? {
+                Ok(token) => self.buffer.extend(Deabbreviator.transform(token)),
+                Err(e) => return Some(Err(e)),
+            }
         }
+        self.buffer.pop_front().map(Ok)
     }
 }
 
@@ -507,7 +809,7 @@ mod test {
     use crate::token::{AxisName, NodeTestName, Token};
 
     use super::Error::*;
-    use super::{Error, TokenDeabbreviator, TokenResult, Tokenizer};
+    use super::{Error, Span, TokenDeabbreviator, TokenResult, Tokenizer};
 
     fn is_finished(tokenizer: &Tokenizer) -> bool {
         !tokenizer.has_more_tokens()
@@ -597,6 +899,44 @@ mod test {
         );
     }
 
+    #[test]
+    fn ignores_comments_between_tokens() {
+        let tokenizer = Tokenizer::new("(: leading :)@(: a (: nested :) comment :) @ (:trailing:)");
+
+        assert_eq!(all_tokens(tokenizer), vec![Token::AtSign, Token::AtSign]);
+    }
+
+    #[test]
+    fn comments_are_not_recognized_inside_literals() {
+        let tokenizer = Tokenizer::new("'(: text :)'");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::Literal("(: text :)".to_owned())]
+        );
+    }
+
+    #[test]
+    fn exception_thrown_when_comment_is_not_closed() {
+        let tokenizer = Tokenizer::new("@ (: outer (: inner :)");
+        let res = all_tokens_raw(tokenizer);
+
+        assert_eq!(Err(UnterminatedComment), res);
+    }
+
+    #[test]
+    fn tokenizes_any_namespace_name_test() {
+        let tokenizer = Tokenizer::new("*:world");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::NameTest(node_test::NameTest {
+                prefix: Some("*".to_owned()),
+                local_part: "world".to_owned(),
+            })]
+        );
+    }
+
     #[test]
     fn tokenizes_wildcard_name_test() {
         let tokenizer = Tokenizer::new("*");
@@ -614,6 +954,283 @@ mod test {
         );
     }
 
+    #[test]
+    fn tokenizes_conditional_keywords() {
+        let tokenizer = Tokenizer::new("if ($a) then b else c");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::If,
+                Token::LeftParen,
+                Token::Variable("a".into()),
+                Token::RightParen,
+                Token::Then,
+                name_test("b"),
+                Token::Else,
+                name_test("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_for_keywords() {
+        let tokenizer = Tokenizer::new("for $x in a return $x");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::For,
+                Token::Variable("x".into()),
+                Token::In,
+                name_test("a"),
+                Token::Return,
+                Token::Variable("x".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_let_keywords() {
+        let tokenizer = Tokenizer::new("let $x := a return $x");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Let,
+                Token::Variable("x".into()),
+                Token::Assign,
+                name_test("a"),
+                Token::Return,
+                Token::Variable("x".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_quantifier_keywords() {
+        let tokenizer = Tokenizer::new("every $x in a satisfies some $y in b satisfies $y");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Every,
+                Token::Variable("x".into()),
+                Token::In,
+                name_test("a"),
+                Token::Satisfies,
+                Token::Some,
+                Token::Variable("y".into()),
+                Token::In,
+                name_test("b"),
+                Token::Satisfies,
+                Token::Variable("y".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_range_operator() {
+        let tokenizer = Tokenizer::new("1 to count(to)");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Number(1.0),
+                Token::To,
+                Token::Function("count".into()),
+                Token::LeftParen,
+                name_test("to"),
+                Token::RightParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_node_comparison_operators() {
+        let tokenizer = Tokenizer::new("a is b << c >> is");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                name_test("a"),
+                Token::Is,
+                name_test("b"),
+                Token::Precedes,
+                name_test("c"),
+                Token::Follows,
+                name_test("is"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_value_comparison_operators() {
+        let tokenizer = Tokenizer::new("1 eq 2 ne 3 lt 4 le 5 gt 6 ge lt");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Number(1.0),
+                Token::ValueEqual,
+                Token::Number(2.0),
+                Token::ValueNotEqual,
+                Token::Number(3.0),
+                Token::ValueLessThan,
+                Token::Number(4.0),
+                Token::ValueLessThanOrEqual,
+                Token::Number(5.0),
+                Token::ValueGreaterThan,
+                Token::Number(6.0),
+                Token::ValueGreaterThanOrEqual,
+                name_test("lt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_sequence_type_operators() {
+        let tokenizer = Tokenizer::new("a instance  of xs:string? treat as element(b)*");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                name_test("a"),
+                Token::InstanceOf,
+                Token::NameTest(node_test::NameTest {
+                    prefix: Some("xs".to_owned()),
+                    local_part: "string".to_owned(),
+                }),
+                Token::QuestionMark,
+                Token::TreatAs,
+                Token::Function("element".into()),
+                Token::LeftParen,
+                name_test("b"),
+                Token::RightParen,
+                Token::Multiply,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_map_and_array_constructors() {
+        let tokenizer = Tokenizer::new("map { 'a' : [1], \"b\":array{} }");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Map,
+                Token::LeftBrace,
+                Token::Literal("a".to_owned()),
+                Token::Colon,
+                Token::LeftBracket,
+                Token::Number(1.0),
+                Token::RightBracket,
+                Token::Comma,
+                Token::Literal("b".to_owned()),
+                Token::Colon,
+                Token::Array,
+                Token::LeftBrace,
+                Token::RightBrace,
+                Token::RightBrace,
+            ]
+        );
+    }
+
+    #[test]
+    fn map_and_array_are_names_without_a_brace() {
+        let tokenizer = Tokenizer::new("map/array");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![name_test("map"), Token::Slash, name_test("array")]
+        );
+    }
+
+    #[test]
+    fn tokenizes_lookups() {
+        let tokenizer = Tokenizer::new("$m?order?1?*?($k)");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Variable("m".into()),
+                Token::Lookup,
+                name_test("order"),
+                Token::Lookup,
+                Token::Number(1.0),
+                Token::Lookup,
+                name_test("*"),
+                Token::Lookup,
+                Token::LeftParen,
+                Token::Variable("k".into()),
+                Token::RightParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_inline_function_and_arrow() {
+        let tokenizer = Tokenizer::new("function ($a) { $a } => f()");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::InlineFunction,
+                Token::LeftParen,
+                Token::Variable("a".into()),
+                Token::RightParen,
+                Token::LeftBrace,
+                Token::Variable("a".into()),
+                Token::RightBrace,
+                Token::Arrow,
+                Token::Function("f".into()),
+                Token::LeftParen,
+                Token::RightParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn function_prefixed_names_are_not_inline_functions() {
+        let tokenizer = Tokenizer::new("function-available()");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Function("function-available".into()),
+                Token::LeftParen,
+                Token::RightParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_cast_operators() {
+        let tokenizer = Tokenizer::new("a cast as b castable as c");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                name_test("a"),
+                Token::CastAs,
+                name_test("b"),
+                Token::CastableAs,
+                name_test("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords_are_names_unless_followed_by_their_syntax() {
+        let tokenizer = Tokenizer::new("if/then");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![name_test("if"), Token::Slash, name_test("then")]
+        );
+    }
+
     #[test]
     fn tokenizes_axis_selector_that_contains_another_axis() {
         let tokenizer = Tokenizer::new("ancestor-or-self::world");
@@ -704,6 +1321,30 @@ mod test {
         assert_eq!(all_tokens(tokenizer), vec![Token::Number(0.40)]);
     }
 
+    #[test]
+    fn tokenizes_number_with_exponent() {
+        let tokenizer = Tokenizer::new("1.5e3 2E-2 .5e+1");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Number(1500.0),
+                Token::Number(0.02),
+                Token::Number(5.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn number_followed_by_a_name_is_not_an_exponent() {
+        let tokenizer = Tokenizer::new("1 eq 1");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::Number(1.0), Token::ValueEqual, Token::Number(1.0)]
+        );
+    }
+
     #[test]
     fn tokenizes_left_bracket() {
         let tokenizer = Tokenizer::new("[");
@@ -969,11 +1610,10 @@ mod test {
     }
 
     #[test]
-    fn exception_thrown_when_name_test_has_no_local_name() {
+    fn name_test_without_local_name_is_followed_by_a_colon() {
         let tokenizer = Tokenizer::new("ns:");
-        let res = all_tokens_raw(tokenizer);
 
-        assert_eq!(Err(UnableToCreateToken), res);
+        assert_eq!(all_tokens(tokenizer), vec![name_test("ns"), Token::Colon]);
     }
 
     #[test]
@@ -984,6 +1624,62 @@ mod test {
         assert_eq!(Err(MismatchedQuoteCharacters), res);
     }
 
+    #[test]
+    fn spans_cover_each_token_without_whitespace() {
+        let mut tokenizer = Tokenizer::new(" a  (: x :) != 'b' ");
+        let mut spans = Vec::new();
+        while let Some(token) = tokenizer.next() {
+            assert!(token.is_ok());
+            spans.push(tokenizer.span().range());
+        }
+
+        assert_eq!(vec![1..2, 12..14, 15..18], spans);
+        assert_eq!(
+            Span {
+                offset: 19,
+                length: 0
+            },
+            tokenizer.span()
+        );
+    }
+
+    #[test]
+    fn spans_cover_text_that_could_not_be_tokenized() {
+        let mut tokenizer = Tokenizer::new("a + \u{ac}@");
+        tokenizer.next();
+        tokenizer.next();
+
+        assert_eq!(Some(Err(UnableToCreateToken)), tokenizer.next());
+        assert_eq!(4..6, tokenizer.span().range());
+
+        assert_eq!(Some(Ok(Token::AtSign)), tokenizer.next());
+
+        let mut tokenizer = Tokenizer::new("a (: open");
+        assert!(matches!(tokenizer.next(), Some(Ok(Token::NameTest(_)))));
+        assert_eq!(0..1, tokenizer.span().range());
+        assert_eq!(Some(Err(UnterminatedComment)), tokenizer.next());
+        assert_eq!(2..9, tokenizer.span().range());
+    }
+
+    #[test]
+    fn tokens_are_produced_with_their_spans() {
+        let mut tokenizer = Tokenizer::new("$a + \u{ac}");
+        let tokens: Vec<_> = tokenizer
+            .with_spans()
+            .map(|(token, span)| (token, span.range()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (Ok(Token::Variable("a".into())), 0..2),
+                (Ok(Token::PlusSign), 3..4),
+                (Err(UnableToCreateToken), 5..7),
+            ],
+            tokens
+        );
+        assert_eq!(7..7, tokenizer.span().range());
+    }
+
     #[test]
     fn converts_at_sign_to_attribute_axis() {
         let input_tokens: Vec<TokenResult> = vec![Ok(Token::AtSign)];
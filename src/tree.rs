@@ -0,0 +1,649 @@
+//! Abstractions over the tree of nodes that XPath navigates.
+//!
+//! Axis traversal, node tests, and document ordering are written
+//! against the [`Document`][] and [`TreeNode`][] traits instead of
+//! the concrete `sxd_document` types. The `sxd_document` DOM, as well
+//! as any [external tree][backend], is supported out of the box
+//! through [`nodeset::Node`][].
+//!
+//! [backend]: ../backend/index.html
+//!
+//! [`Document`]: trait.Document.html
+//! [`TreeNode`]: trait.TreeNode.html
+//! [`nodeset::Node`]: ../nodeset/enum.Node.html
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use sxd_document::QName;
+
+use crate::nodeset::{self, Node};
+
+/// The seven types of nodes in the [XPath data model][model].
+///
+/// [model]: https://www.w3.org/TR/xpath/#data-model
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Root,
+    Element,
+    Attribute,
+    Text,
+    Comment,
+    Namespace,
+    ProcessingInstruction,
+}
+
+/// A tree of nodes that XPath expressions can be evaluated against.
+pub trait Document<'d>: Copy {
+    /// The type of the nodes contained in the document.
+    type Node: TreeNode<'d, Document = Self>;
+
+    /// The root node of the document.
+    fn root(&self) -> Self::Node;
+}
+
+/// A single node of a [`Document`][].
+///
+/// Nodes are expected to be cheap handles into the tree; equality and
+/// hashing should be based on the identity of the node, not its
+/// contents.
+///
+/// [`Document`]: trait.Document.html
+pub trait TreeNode<'d>: Copy + Eq + Hash + fmt::Debug {
+    /// The type of the document that owns the node.
+    type Document: Document<'d, Node = Self>;
+
+    /// The document to which this node belongs.
+    fn document(&self) -> Self::Document;
+
+    /// The type of this node.
+    fn kind(&self) -> NodeKind;
+
+    /// Returns the [expanded name][] of the node, if any.
+    ///
+    /// [expanded name]: https://www.w3.org/TR/xpath/#dt-expanded-name
+    fn expanded_name(&self) -> Option<QName<'d>>;
+
+    /// Returns the parent of the node, if any.
+    fn parent(&self) -> Option<Self>;
+
+    /// Returns the children of the node in document order.
+    fn children(&self) -> Vec<Self>;
+
+    /// Returns the attributes of the node, if it is an element.
+    fn attributes(&self) -> Vec<Self>;
+
+    /// Returns the namespaces in scope for the node, if it is an
+    /// element.
+    fn namespaces(&self) -> Vec<Self>;
+
+    /// Returns the nodes with the same parent that occur before this
+    /// node, nearest first.
+    fn preceding_siblings(&self) -> Vec<Self>;
+
+    /// Returns the nodes with the same parent that occur after this
+    /// node, nearest first.
+    fn following_siblings(&self) -> Vec<Self>;
+
+    /// Returns the [string value] of this node.
+    ///
+    /// [string value]: https://www.w3.org/TR/xpath/#dt-string-value
+    fn string_value(&self) -> String;
+
+    /// Whether name tests against this node follow the [HTML rules][]:
+    /// unprefixed names select elements in the HTML namespace and
+    /// names are compared ASCII case-insensitively.
+    ///
+    /// [HTML rules]: https://html.spec.whatwg.org/multipage/infrastructure.html#interactions-with-xpath-and-xslt
+    fn is_html(&self) -> bool {
+        false
+    }
+}
+
+impl<'d> TreeNode<'d> for Node<'d> {
+    type Document = nodeset::Owner<'d>;
+
+    fn document(&self) -> nodeset::Owner<'d> {
+        Node::owner(self)
+    }
+
+    fn kind(&self) -> NodeKind {
+        match *self {
+            Node::Root(_) => NodeKind::Root,
+            Node::Element(_) => NodeKind::Element,
+            Node::Attribute(_) => NodeKind::Attribute,
+            Node::Text(_) => NodeKind::Text,
+            Node::Comment(_) => NodeKind::Comment,
+            Node::Namespace(_) => NodeKind::Namespace,
+            Node::ProcessingInstruction(_) => NodeKind::ProcessingInstruction,
+            Node::External(n) => n.kind(),
+        }
+    }
+
+    fn expanded_name(&self) -> Option<QName<'d>> {
+        Node::expanded_name(self)
+    }
+
+    fn parent(&self) -> Option<Self> {
+        Node::parent(self)
+    }
+
+    fn children(&self) -> Vec<Self> {
+        Node::children(self)
+    }
+
+    fn attributes(&self) -> Vec<Self> {
+        match *self {
+            Node::Element(e) => e.attributes().into_iter().map(Node::Attribute).collect(),
+            Node::External(n) => n.attributes().into_iter().map(Node::External).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn namespaces(&self) -> Vec<Self> {
+        match *self {
+            Node::Element(e) => e
+                .namespaces_in_scope()
+                .into_iter()
+                .map(|ns| {
+                    Node::Namespace(nodeset::Namespace {
+                        parent: e,
+                        prefix: ns.prefix(),
+                        uri: ns.uri(),
+                    })
+                })
+                .collect(),
+            Node::External(n) => n.namespaces().into_iter().map(Node::External).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn preceding_siblings(&self) -> Vec<Self> {
+        Node::preceding_siblings(self)
+    }
+
+    fn following_siblings(&self) -> Vec<Self> {
+        Node::following_siblings(self)
+    }
+
+    fn string_value(&self) -> String {
+        Node::string_value(self)
+    }
+
+    fn is_html(&self) -> bool {
+        match *self {
+            Node::External(n) => n.is_html(),
+            _ => false,
+        }
+    }
+}
+
+/// Visits the node and all of its descendants in document order.
+pub fn preorder_left_to_right<'d, N, F>(node: N, mut f: F)
+where
+    N: TreeNode<'d>,
+    F: FnMut(N),
+{
+    preorder_left_to_right_while(node, |n| {
+        f(n);
+        true
+    });
+}
+
+/// Visits the node and its descendants in document order until `f`
+/// returns false. Returns whether every node was visited.
+pub fn preorder_left_to_right_while<'d, N, F>(node: N, mut f: F) -> bool
+where
+    N: TreeNode<'d>,
+    F: FnMut(N) -> bool,
+{
+    let mut stack = vec![node];
+
+    while let Some(current) = stack.pop() {
+        if !f(current) {
+            return false;
+        }
+
+        for child in current.children().into_iter().rev() {
+            stack.push(child);
+        }
+    }
+
+    true
+}
+
+/// Visits the node and all of its descendants in reverse document
+/// order.
+// There's other implementations that only require a single stack; are
+// those applicable? Are they better?
+pub fn postorder_right_to_left<'d, N, F>(node: N, mut f: F)
+where
+    N: TreeNode<'d>,
+    F: FnMut(N),
+{
+    postorder_right_to_left_while(node, |n| {
+        f(n);
+        true
+    });
+}
+
+/// Visits the node and its descendants in reverse document order
+/// until `f` returns false. Returns whether every node was visited.
+pub fn postorder_right_to_left_while<'d, N, F>(node: N, f: F) -> bool
+where
+    N: TreeNode<'d>,
+    F: FnMut(N) -> bool,
+{
+    let mut stack = vec![node];
+    let mut stack2 = vec![];
+
+    while let Some(current) = stack.pop() {
+        for child in current.children().into_iter().rev() {
+            stack.push(child);
+        }
+        stack2.push(current);
+    }
+
+    stack2.into_iter().rev().all(f)
+}
+
+/// Visits the node and then each of its ancestors, nearest first.
+pub fn node_and_each_parent<'d, N, F>(node: N, mut f: F)
+where
+    N: TreeNode<'d>,
+    F: FnMut(N),
+{
+    f(node);
+    each_parent(node, f);
+}
+
+/// Visits the node and then each of its ancestors, nearest first,
+/// until `f` returns false. Returns whether every node was visited.
+pub fn node_and_each_parent_while<'d, N, F>(node: N, mut f: F) -> bool
+where
+    N: TreeNode<'d>,
+    F: FnMut(N) -> bool,
+{
+    f(node) && each_parent_while(node, f)
+}
+
+/// Visits each of the ancestors of the node, nearest first.
+pub fn each_parent<'d, N, F>(node: N, mut f: F)
+where
+    N: TreeNode<'d>,
+    F: FnMut(N),
+{
+    each_parent_while(node, |n| {
+        f(n);
+        true
+    });
+}
+
+/// Visits each of the ancestors of the node, nearest first, until `f`
+/// returns false. Returns whether every ancestor was visited.
+pub fn each_parent_while<'d, N, F>(mut node: N, mut f: F) -> bool
+where
+    N: TreeNode<'d>,
+    F: FnMut(N) -> bool,
+{
+    while let Some(parent) = node.parent() {
+        if !f(parent) {
+            return false;
+        }
+        node = parent;
+    }
+    true
+}
+
+/// The position of every node of a document in [document order].
+///
+/// Nodes that are not attached to the document are ordered after all
+/// attached nodes; see the library-level docs for details.
+///
+/// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
+// Rebuilding this multiple times cannot possibly be performant,
+// but I want to see how widely used this is first before
+// picking an appropriate caching point.
+pub struct DocOrder<N>(HashMap<N, usize>);
+
+impl<'d, N> DocOrder<N>
+where
+    N: TreeNode<'d>,
+{
+    // Nodes are hashed by identity, not by their mutable contents
+    #[allow(clippy::mutable_key_type)]
+    pub fn new(doc: N::Document) -> Self {
+        let mut idx = 0;
+        let mut stack = vec![doc.root()];
+        let mut order = HashMap::new();
+
+        while let Some(n) = stack.pop() {
+            order.insert(n, idx);
+            idx += 1;
+
+            stack.extend(n.children().into_iter().rev());
+            // TODO: namespaces
+            stack.extend(n.attributes().into_iter().rev());
+        }
+
+        DocOrder(order)
+    }
+
+    pub fn order_of(&self, node: N) -> usize {
+        // See the library-level docs for rationale on this MAX
+        self.0.get(&node).cloned().unwrap_or(usize::MAX)
+    }
+}
+
+/// Compares nodes of one tree in [document order] without numbering
+/// the whole document, as [`DocOrder`][] does. The siblings of the
+/// nodes that are compared, and of their ancestors, are only numbered
+/// as far as those nodes, so comparing nodes near the start of a
+/// large document stays cheap.
+///
+/// Namespace nodes are ordered after the other children of their
+/// element, and nodes of different trees are not ordered.
+///
+/// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
+/// [`DocOrder`]: struct.DocOrder.html
+pub struct LazyDocOrder<N> {
+    positions: HashMap<N, usize>,
+    /// The attributes and then the children of each parent, and how
+    /// many of them have been numbered
+    siblings: HashMap<N, (Vec<N>, usize)>,
+}
+
+impl<'d, N> LazyDocOrder<N>
+where
+    N: TreeNode<'d>,
+{
+    pub fn new() -> Self {
+        LazyDocOrder {
+            positions: HashMap::new(),
+            siblings: HashMap::new(),
+        }
+    }
+
+    /// Whether the nodes can be compared with each other: they are in
+    /// one tree, and none of them is a namespace node.
+    pub fn can_order<I>(nodes: I) -> bool
+    where
+        I: IntoIterator<Item = N>,
+    {
+        let root = |node: N| {
+            let mut root = node;
+            each_parent(node, |parent| root = parent);
+            root
+        };
+
+        let mut first_root = None;
+        nodes.into_iter().all(|node| {
+            let root = root(node);
+            node.kind() != NodeKind::Namespace && *first_root.get_or_insert(root) == root
+        })
+    }
+
+    pub fn compare(&mut self, left: N, right: N) -> Ordering {
+        if left == right {
+            return Ordering::Equal;
+        }
+
+        let ancestry = |node: N| {
+            let mut nodes = vec![node];
+            each_parent(node, |parent| nodes.push(parent));
+            nodes.reverse();
+            nodes
+        };
+        let (left, right) = (ancestry(left), ancestry(right));
+        let shared = left.iter().zip(&right).take_while(|(l, r)| l == r).count();
+
+        match (left.get(shared), right.get(shared)) {
+            // An ancestor comes before its descendants
+            (None, _) => Ordering::Less,
+            (_, None) => Ordering::Greater,
+            (Some(_), Some(_)) if shared == 0 => Ordering::Equal,
+            (Some(&l), Some(&r)) => self.position(l).cmp(&self.position(r)),
+        }
+    }
+
+    /// Where the node comes among the nodes with the same parent:
+    /// attributes first, then children.
+    fn position(&mut self, node: N) -> usize {
+        if let Some(&position) = self.positions.get(&node) {
+            return position;
+        }
+
+        if let Some(parent) = node.parent() {
+            let (siblings, numbered) = self.siblings.entry(parent).or_insert_with(|| {
+                let mut siblings = parent.attributes();
+                siblings.extend(parent.children());
+                (siblings, 0)
+            });
+
+            while let Some(&sibling) = siblings.get(*numbered) {
+                let position = *numbered;
+                self.positions.insert(sibling, position);
+                *numbered += 1;
+                if sibling == node {
+                    return position;
+                }
+            }
+        }
+
+        // Namespace nodes are neither attributes nor children
+        *self.positions.entry(node).or_insert(usize::MAX)
+    }
+}
+
+impl<'d, N> Default for LazyDocOrder<N>
+where
+    N: TreeNode<'d>,
+{
+    fn default() -> Self {
+        LazyDocOrder::new()
+    }
+}
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use sxd_document::QName;
+
+    use crate::axis::Axis;
+
+    use super::*;
+
+    // A minimal tree that is not backed by `sxd_document`, to ensure
+    // that traversal only relies on the traits.
+    #[derive(Debug, Default)]
+    struct Arena {
+        names: RefCell<Vec<&'static str>>,
+        parents: RefCell<Vec<Option<usize>>>,
+    }
+
+    impl Arena {
+        fn add(&self, name: &'static str, parent: Option<usize>) -> usize {
+            self.names.borrow_mut().push(name);
+            self.parents.borrow_mut().push(parent);
+            self.names.borrow().len() - 1
+        }
+    }
+
+    #[derive(Debug, Copy, Clone)]
+    struct ArenaDocument<'d>(&'d Arena);
+
+    #[derive(Debug, Copy, Clone)]
+    struct ArenaNode<'d>(&'d Arena, usize);
+
+    impl<'d> PartialEq for ArenaNode<'d> {
+        fn eq(&self, other: &Self) -> bool {
+            std::ptr::eq(self.0, other.0) && self.1 == other.1
+        }
+    }
+
+    impl<'d> Eq for ArenaNode<'d> {}
+
+    impl<'d> Hash for ArenaNode<'d> {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.1.hash(state)
+        }
+    }
+
+    impl<'d> Document<'d> for ArenaDocument<'d> {
+        type Node = ArenaNode<'d>;
+
+        fn root(&self) -> ArenaNode<'d> {
+            ArenaNode(self.0, 0)
+        }
+    }
+
+    impl<'d> ArenaNode<'d> {
+        fn siblings(&self) -> Vec<Self> {
+            self.parent().map(|p| p.children()).unwrap_or_default()
+        }
+    }
+
+    impl<'d> TreeNode<'d> for ArenaNode<'d> {
+        type Document = ArenaDocument<'d>;
+
+        fn document(&self) -> ArenaDocument<'d> {
+            ArenaDocument(self.0)
+        }
+
+        fn kind(&self) -> NodeKind {
+            if self.1 == 0 {
+                NodeKind::Root
+            } else {
+                NodeKind::Element
+            }
+        }
+
+        fn expanded_name(&self) -> Option<QName<'d>> {
+            let name = self.0.names.borrow()[self.1];
+            Some(QName::new(name))
+        }
+
+        fn parent(&self) -> Option<Self> {
+            self.0.parents.borrow()[self.1].map(|p| ArenaNode(self.0, p))
+        }
+
+        fn children(&self) -> Vec<Self> {
+            let parents = self.0.parents.borrow();
+            (0..parents.len())
+                .filter(|&i| parents[i] == Some(self.1))
+                .map(|i| ArenaNode(self.0, i))
+                .collect()
+        }
+
+        fn attributes(&self) -> Vec<Self> {
+            Vec::new()
+        }
+
+        fn namespaces(&self) -> Vec<Self> {
+            Vec::new()
+        }
+
+        fn preceding_siblings(&self) -> Vec<Self> {
+            let mut s: Vec<_> = self
+                .siblings()
+                .into_iter()
+                .filter(|s| s.1 < self.1)
+                .collect();
+            s.reverse();
+            s
+        }
+
+        fn following_siblings(&self) -> Vec<Self> {
+            self.siblings()
+                .into_iter()
+                .filter(|s| s.1 > self.1)
+                .collect()
+        }
+
+        fn string_value(&self) -> String {
+            String::new()
+        }
+    }
+
+    fn names(nodes: Vec<ArenaNode<'_>>) -> Vec<&'static str> {
+        nodes.iter().map(|n| n.0.names.borrow()[n.1]).collect()
+    }
+
+    fn build(arena: &Arena) -> [ArenaNode<'_>; 5] {
+        let root = arena.add("", None);
+        let a = arena.add("a", Some(root));
+        let b = arena.add("b", Some(a));
+        let c = arena.add("c", Some(a));
+        let d = arena.add("d", Some(c));
+        [root, a, b, c, d].map(|i| ArenaNode(arena, i))
+    }
+
+    #[test]
+    fn axes_traverse_other_trees() {
+        let arena = Arena::default();
+        let [_, a, b, c, d] = build(&arena);
+
+        let mut seen = Vec::new();
+        Axis::Descendant.traverse(a, |n| seen.push(n));
+        assert_eq!(names(seen), ["b", "c", "d"]);
+
+        let mut seen = Vec::new();
+        Axis::Preceding.traverse(d, |n| seen.push(n));
+        assert_eq!(names(seen), ["b"]);
+
+        let mut seen = Vec::new();
+        Axis::AncestorOrSelf.traverse(c, |n| seen.push(n));
+        assert_eq!(names(seen), ["c", "a", ""]);
+
+        let mut seen = Vec::new();
+        Axis::FollowingSibling.traverse(b, |n| seen.push(n));
+        assert_eq!(names(seen), ["c"]);
+    }
+
+    #[test]
+    fn document_order_of_other_trees() {
+        let arena = Arena::default();
+        let [root, a, b, _, d] = build(&arena);
+
+        let order = DocOrder::new(root.document());
+        assert!(order.order_of(root) < order.order_of(a));
+        assert!(order.order_of(b) < order.order_of(d));
+    }
+
+    #[test]
+    fn lazy_document_order_agrees_with_document_order() {
+        let arena = Arena::default();
+        let nodes = build(&arena);
+
+        let order = DocOrder::new(nodes[0].document());
+        let mut lazy = LazyDocOrder::new();
+        for &left in &nodes {
+            for &right in &nodes {
+                let expected = order.order_of(left).cmp(&order.order_of(right));
+                assert_eq!(
+                    expected,
+                    lazy.compare(left, right),
+                    "{:?} {:?}",
+                    left,
+                    right
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn traversal_stops_when_asked() {
+        let arena = Arena::default();
+        let [root, _, _, c, _] = build(&arena);
+
+        let mut seen = Vec::new();
+        let finished = Axis::Descendant.traverse_while(root, |n| {
+            seen.push(n);
+            n != c
+        });
+        assert!(!finished);
+        assert_eq!(names(seen), ["a", "b", "c"]);
+    }
+}
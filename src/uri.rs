@@ -0,0 +1,245 @@
+//! Resolution of relative URI references, as described by [RFC 3986].
+//!
+//! This is used by `resolve-uri` and `base-uri`, which resolve
+//! `xml:base` attributes against the base URI of their parent.
+//!
+//! [RFC 3986]: https://tools.ietf.org/html/rfc3986#section-5.2
+
+/// The five components of a URI reference.
+#[derive(Debug, Default, PartialEq)]
+struct Reference<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Reference<'a> {
+    fn parse(mut s: &'a str) -> Reference<'a> {
+        let mut reference = Reference::default();
+
+        if let Some(i) = s.find('#') {
+            reference.fragment = Some(&s[i + 1..]);
+            s = &s[..i];
+        }
+        if let Some(i) = s.find('?') {
+            reference.query = Some(&s[i + 1..]);
+            s = &s[..i];
+        }
+        if let Some(i) = s.find(':') {
+            let scheme = &s[..i];
+            if is_scheme(scheme) {
+                reference.scheme = Some(scheme);
+                s = &s[i + 1..];
+            }
+        }
+        if let Some(rest) = s.strip_prefix("//") {
+            let end = rest.find('/').unwrap_or(rest.len());
+            reference.authority = Some(&rest[..end]);
+            s = &rest[end..];
+        }
+        reference.path = s;
+
+        reference
+    }
+}
+
+impl<'a> std::fmt::Display for Reference<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = self.scheme {
+            write!(f, "{}:", scheme)?;
+        }
+        if let Some(authority) = self.authority {
+            write!(f, "//{}", authority)?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+/// Whether the URI has a scheme, and so can be used as a base URI.
+pub fn is_absolute(uri: &str) -> bool {
+    Reference::parse(uri).scheme.is_some()
+}
+
+/// The scheme of the URI, such as `http`, if it has one.
+pub fn scheme(uri: &str) -> Option<&str> {
+    Reference::parse(uri).scheme
+}
+
+/// Removes the `.` and `..` segments of a path.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let mut input = path;
+
+    while !input.is_empty() {
+        if let Some(rest) = input
+            .strip_prefix("../")
+            .or_else(|| input.strip_prefix("./"))
+        {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            // Move the first segment, with its leading slash, to the output
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push(&input[..end]);
+            input = &input[end..];
+        }
+    }
+
+    output.concat()
+}
+
+fn merge(base: &Reference<'_>, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        format!("/{}", path)
+    } else {
+        match base.path.rfind('/') {
+            Some(i) => format!("{}{}", &base.path[..=i], path),
+            None => path.to_owned(),
+        }
+    }
+}
+
+/// Resolves a URI reference against an absolute base URI. Returns
+/// `None` if the base URI is not absolute.
+///
+/// ```
+/// use sxd_xpath::uri;
+///
+/// let resolved = uri::resolve("http://example.com/a/b/c", "../d?q");
+/// assert_eq!(Some("http://example.com/a/d?q".to_owned()), resolved);
+/// ```
+pub fn resolve(base: &str, reference: &str) -> Option<String> {
+    let base = Reference::parse(base);
+    base.scheme?;
+    let reference = Reference::parse(reference);
+
+    let path;
+    let target = if reference.scheme.is_some() {
+        path = remove_dot_segments(reference.path);
+        Reference {
+            path: &path,
+            ..reference
+        }
+    } else if reference.authority.is_some() {
+        path = remove_dot_segments(reference.path);
+        Reference {
+            scheme: base.scheme,
+            path: &path,
+            ..reference
+        }
+    } else if reference.path.is_empty() {
+        Reference {
+            query: reference.query.or(base.query),
+            fragment: reference.fragment,
+            ..base
+        }
+    } else {
+        path = if reference.path.starts_with('/') {
+            remove_dot_segments(reference.path)
+        } else {
+            remove_dot_segments(&merge(&base, reference.path))
+        };
+        Reference {
+            scheme: base.scheme,
+            authority: base.authority,
+            path: &path,
+            query: reference.query,
+            fragment: reference.fragment,
+        }
+    };
+
+    Some(target.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BASE: &str = "http://a/b/c/d;p?q";
+
+    fn resolved(reference: &str) -> String {
+        resolve(BASE, reference).expect("base is absolute")
+    }
+
+    #[test]
+    fn normal_examples_of_rfc_3986() {
+        let examples = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            (";x", "http://a/b/c/;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+        ];
+        for &(reference, expected) in &examples {
+            assert_eq!(expected, resolved(reference), "resolving {:?}", reference);
+        }
+    }
+
+    #[test]
+    fn abnormal_examples_of_rfc_3986() {
+        let examples = [
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+        ];
+        for &(reference, expected) in &examples {
+            assert_eq!(expected, resolved(reference), "resolving {:?}", reference);
+        }
+    }
+
+    #[test]
+    fn schemes_are_taken_from_the_start() {
+        assert_eq!(Some("https"), scheme("https://example.com"));
+        assert_eq!(Some("urn"), scheme("urn:isbn:0451450523"));
+        assert_eq!(None, scheme("a/b:c"));
+    }
+
+    #[test]
+    fn relative_bases_cannot_be_resolved_against() {
+        assert_eq!(None, resolve("a/b", "c"));
+        assert!(!is_absolute("a/b"));
+        assert!(is_absolute("urn:isbn:0451450523"));
+    }
+}
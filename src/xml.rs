@@ -0,0 +1,348 @@
+//! Writes nodes back out as XML.
+//!
+//! [`Node::to_xml`][] writes a node with everything under it, and
+//! [`Nodeset::to_xml`][] writes each node of a nodeset in document
+//! order. This gives the text of the parts of a document that an XPath
+//! selects:
+//!
+//! ```
+//! use sxd_document::parser;
+//! use sxd_xpath::{evaluate_xpath, Value};
+//!
+//! let package = parser::parse("<feed xmlns:a='urn:a'><a:entry id='1'>One</a:entry></feed>")
+//!     .expect("failed to parse XML");
+//! let document = package.as_document();
+//!
+//! let value = evaluate_xpath(&document, "/feed/*").expect("XPath evaluation failed");
+//! if let Value::Nodeset(nodes) = value {
+//!     assert_eq!(r#"<a:entry xmlns:a="urn:a" id="1">One</a:entry>"#, nodes.to_xml());
+//! }
+//! ```
+//!
+//! An element may use namespaces that were declared on an element
+//! above it. [`Namespaces`][] chooses which declarations are written.
+//!
+//! [`Node::to_xml`]: ../nodeset/enum.Node.html#method.to_xml
+//! [`Nodeset::to_xml`]: ../nodeset/struct.Nodeset.html#method.to_xml
+//! [`Namespaces`]: enum.Namespaces.html
+
+use std::io::{self, Write};
+
+use sxd_document::dom;
+use sxd_document::QName;
+
+use crate::nodeset::Node;
+
+const XML_PREFIX: &str = "xml";
+
+/// Which namespace declarations are written on elements.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Namespaces {
+    /// The outermost element declares every namespace in scope, as if it
+    /// were the root of its own document. Elements below it declare the
+    /// namespaces that change.
+    #[default]
+    InScope,
+    /// Only the namespaces that names are written with are declared, on
+    /// the outermost element that uses each one.
+    Used,
+    /// No namespaces are declared. Names keep their prefixes, so the XML
+    /// may need the declarations of the document it came from.
+    Omitted,
+}
+
+/// How nodes are written as XML.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    namespaces: Namespaces,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options::default()
+    }
+
+    /// Chooses the namespace declarations. The default is
+    /// [`Namespaces::InScope`](enum.Namespaces.html#variant.InScope).
+    pub fn set_namespaces(&mut self, namespaces: Namespaces) {
+        self.namespaces = namespaces;
+    }
+}
+
+pub(crate) fn write_node(out: &mut dyn Write, node: Node<'_>, options: &Options) -> io::Result<()> {
+    match node {
+        Node::Root(root) => {
+            for child in root.children() {
+                match child {
+                    dom::ChildOfRoot::Element(e) => Writer::new(out, options).element(e)?,
+                    dom::ChildOfRoot::Comment(c) => comment(out, c)?,
+                    dom::ChildOfRoot::ProcessingInstruction(pi) => processing_instruction(out, pi)?,
+                }
+            }
+            Ok(())
+        }
+        Node::Element(element) => Writer::new(out, options).element(element),
+        Node::Attribute(attribute) => {
+            let name = node.prefixed_name().unwrap_or_default();
+            write!(out, "{}=\"", name)?;
+            escape(out, attribute.value(), true)?;
+            out.write_all(b"\"")
+        }
+        Node::Text(text) => escape(out, text.text(), false),
+        Node::Comment(c) => comment(out, c),
+        Node::Namespace(namespace) => {
+            write!(out, "xmlns:{}=\"", namespace.prefix)?;
+            escape(out, namespace.uri, true)?;
+            out.write_all(b"\"")
+        }
+        Node::ProcessingInstruction(pi) => processing_instruction(out, pi),
+        Node::External(_) => escape(out, &node.string_value(), false),
+    }
+}
+
+/// Writes an element and everything under it, keeping track of the
+/// namespaces declared so far.
+struct Writer<'o, 'd> {
+    out: &'o mut dyn Write,
+    namespaces: Namespaces,
+    /// The prefixes declared by the elements being written, with the
+    /// empty prefix for the default namespace
+    declared: Vec<(&'d str, &'d str)>,
+}
+
+impl<'o, 'd> Writer<'o, 'd> {
+    fn new(out: &'o mut dyn Write, options: &Options) -> Self {
+        Writer {
+            out,
+            namespaces: options.namespaces,
+            declared: Vec::new(),
+        }
+    }
+
+    fn element(&mut self, element: dom::Element<'d>) -> io::Result<()> {
+        let scope = self.declared.len();
+
+        let (prefix, name) = prefixed(element, element.name(), element.preferred_prefix());
+        let attributes: Vec<_> = element
+            .attributes()
+            .into_iter()
+            .map(|a| (prefixed(element, a.name(), a.preferred_prefix()), a.value()))
+            .collect();
+
+        let mut wanted = Vec::new();
+        match self.namespaces {
+            Namespaces::InScope => {
+                for namespace in element.namespaces_in_scope() {
+                    wanted.push((namespace.prefix(), namespace.uri()));
+                }
+                // The document keeps prefixes in no particular order
+                wanted.sort_unstable();
+                wanted.push(("", element.recursive_default_namespace_uri().unwrap_or("")));
+            }
+            Namespaces::Used => {
+                let uri = element.name().namespace_uri().unwrap_or("");
+                wanted.push((prefix.unwrap_or(""), uri));
+                for &((prefix, _), _) in &attributes {
+                    if let Some(prefix) = prefix {
+                        let uri = element.namespace_uri_for_prefix(prefix).unwrap_or("");
+                        wanted.push((prefix, uri));
+                    }
+                }
+            }
+            Namespaces::Omitted => {}
+        }
+
+        write!(self.out, "<{}", name)?;
+        for (prefix, uri) in wanted {
+            if prefix == XML_PREFIX || self.in_scope(prefix) == uri {
+                continue;
+            }
+            self.declared.push((prefix, uri));
+            if prefix.is_empty() {
+                self.out.write_all(b" xmlns=\"")?;
+            } else {
+                write!(self.out, " xmlns:{}=\"", prefix)?;
+            }
+            escape(self.out, uri, true)?;
+            self.out.write_all(b"\"")?;
+        }
+        for ((_, name), value) in attributes {
+            write!(self.out, " {}=\"", name)?;
+            escape(self.out, value, true)?;
+            self.out.write_all(b"\"")?;
+        }
+
+        let children = element.children();
+        if children.is_empty() {
+            self.out.write_all(b"/>")?;
+        } else {
+            self.out.write_all(b">")?;
+            for child in children {
+                match child {
+                    dom::ChildOfElement::Element(e) => self.element(e)?,
+                    dom::ChildOfElement::Text(t) => escape(self.out, t.text(), false)?,
+                    dom::ChildOfElement::Comment(c) => comment(self.out, c)?,
+                    dom::ChildOfElement::ProcessingInstruction(pi) => {
+                        processing_instruction(self.out, pi)?
+                    }
+                }
+            }
+            write!(self.out, "</{}>", name)?;
+        }
+
+        self.declared.truncate(scope);
+        Ok(())
+    }
+
+    /// The URI that the prefix has been declared with, where no
+    /// declaration of the default namespace leaves it empty.
+    fn in_scope(&self, prefix: &str) -> &'d str {
+        self.declared
+            .iter()
+            .rev()
+            .find(|&&(p, _)| p == prefix)
+            .map_or("", |&(_, uri)| uri)
+    }
+}
+
+/// The prefix that the name is written with, if any, and the name as
+/// it is written.
+fn prefixed<'d>(
+    element: dom::Element<'d>,
+    name: QName<'_>,
+    preferred: Option<&str>,
+) -> (Option<&'d str>, String) {
+    let prefix = name
+        .namespace_uri()
+        .and_then(|uri| element.prefix_for_namespace_uri(uri, preferred));
+    match prefix {
+        Some(prefix) => (Some(prefix), format!("{}:{}", prefix, name.local_part())),
+        None => (None, name.local_part().to_owned()),
+    }
+}
+
+fn comment(out: &mut dyn Write, comment: dom::Comment<'_>) -> io::Result<()> {
+    write!(out, "<!--{}-->", comment.text())
+}
+
+fn processing_instruction(
+    out: &mut dyn Write,
+    pi: dom::ProcessingInstruction<'_>,
+) -> io::Result<()> {
+    match pi.value() {
+        Some(value) => write!(out, "<?{} {}?>", pi.target(), value),
+        None => write!(out, "<?{}?>", pi.target()),
+    }
+}
+
+/// Writes the text with the characters that would be read as markup
+/// replaced, copying the runs between them as they are.
+fn escape(out: &mut dyn Write, text: &str, in_attribute: bool) -> io::Result<()> {
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        let replacement = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            '"' if in_attribute => "&quot;",
+            _ => continue,
+        };
+        out.write_all(&text.as_bytes()[start..i])?;
+        out.write_all(replacement.as_bytes())?;
+        start = i + 1;
+    }
+    out.write_all(&text.as_bytes()[start..])
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::parser;
+
+    use super::*;
+    use crate::{evaluate_xpath, Value};
+
+    fn to_xml(xml: &str, xpath: &str, namespaces: Namespaces) -> String {
+        let package = parser::parse(xml).expect("failed to parse XML");
+        let document = package.as_document();
+        let mut options = Options::new();
+        options.set_namespaces(namespaces);
+
+        match evaluate_xpath(&document, xpath).expect("XPath evaluation failed") {
+            Value::Nodeset(nodes) => nodes.to_xml_with(&options),
+            other => panic!("expected a nodeset, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn subtrees_are_written_with_their_text_escaped() {
+        assert_eq!(
+            r#"<b x="&quot;1&quot;">2 &lt; 3<!--c--><?p v?><c/></b>"#,
+            to_xml(
+                "<a><b x='\"1\"'>2 &lt; 3<!--c--><?p v?><c/></b></a>",
+                "/a/b",
+                Namespaces::InScope
+            )
+        );
+    }
+
+    #[test]
+    fn nodes_are_written_one_after_another_in_document_order() {
+        assert_eq!(
+            r#"x="1"<b>2</b>3"#,
+            to_xml(
+                "<a x='1'><b>2</b>3</a>",
+                "/a/text() | /a/b | /a/@x",
+                Namespaces::InScope
+            )
+        );
+    }
+
+    #[test]
+    fn namespaces_in_scope_are_declared_on_the_outermost_element() {
+        let xml = "<a xmlns='urn:d' xmlns:p='urn:p' xmlns:q='urn:q'><b><p:c/></b></a>";
+        assert_eq!(
+            r#"<b xmlns:p="urn:p" xmlns:q="urn:q" xmlns="urn:d"><p:c/></b>"#,
+            to_xml(xml, "/*/*", Namespaces::InScope)
+        );
+    }
+
+    #[test]
+    fn used_namespaces_are_declared_where_they_are_first_used() {
+        let xml =
+            "<a xmlns='urn:d' xmlns:p='urn:p' xmlns:q='urn:q'><b><p:c p:x='1'/><p:c/></b></a>";
+        assert_eq!(
+            r#"<b xmlns="urn:d"><p:c xmlns:p="urn:p" p:x="1"/><p:c xmlns:p="urn:p"/></b>"#,
+            to_xml(xml, "/*/*", Namespaces::Used)
+        );
+    }
+
+    #[test]
+    fn an_element_without_a_namespace_undeclares_the_default() {
+        let xml = "<a xmlns='urn:d'><b xmlns=''/></a>";
+        assert_eq!(
+            r#"<a xmlns="urn:d"><b xmlns=""/></a>"#,
+            to_xml(xml, "/*", Namespaces::Used)
+        );
+    }
+
+    #[test]
+    fn declarations_may_be_left_out() {
+        let xml = "<a xmlns:p='urn:p'><p:b/></a>";
+        assert_eq!("<p:b/>", to_xml(xml, "/a/*", Namespaces::Omitted));
+    }
+
+    #[test]
+    fn nodes_are_written_to_a_writer_as_they_are_to_a_string() {
+        let package =
+            parser::parse("<a><b x='1'>2 &amp; 3</b><c/></a>").expect("failed to parse XML");
+        let document = package.as_document();
+
+        if let Value::Nodeset(nodes) =
+            evaluate_xpath(&document, "/a/*").expect("XPath evaluation failed")
+        {
+            let mut written = Vec::new();
+            nodes.write_xml(&mut written).expect("Unable to write");
+            assert_eq!(nodes.to_xml().into_bytes(), written);
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use std::borrow::ToOwned;
-use sxd_document::{dom, parser};
-use sxd_xpath::{context, function, nodeset};
-use sxd_xpath::{evaluate_xpath, Context, Factory, Value};
+use std::cell::Cell;
+use std::rc::Rc;
+use sxd_document::{dom, parser, QName};
+use sxd_xpath::backend::{ExternalNode, ExternalTree};
+use sxd_xpath::tree::NodeKind;
+use sxd_xpath::{atomic, context, function, nodeset};
+use sxd_xpath::{evaluate_xpath, Context, EvaluationOptions, Factory, Item, Value};
 
 #[test]
 fn functions_accept_arguments() {
@@ -47,6 +51,864 @@ fn position_function_in_predicate() {
     });
 }
 
+#[test]
+fn sequences_keep_order_and_duplicates() {
+    with_document("<a><b>1</b><b>2</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "count((//b, 'x', //b))");
+        assert_eq!(Ok(Value::Number(5.0)), result);
+
+        let result = evaluate_xpath(&doc, "string((//b[2], //b[1])[1])");
+        assert_eq!(Ok(Value::String("2".to_owned())), result);
+    });
+}
+
+#[test]
+fn sequences_can_be_filtered_by_the_context_item() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "sum((1, 2, 3, 4)[. > 2])");
+        assert_eq!(Ok(Value::Number(7.0)), result);
+
+        let result = evaluate_xpath(&doc, "(1, 2, 3)[last()] = 3");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn sequences_of_nodes_can_start_a_path() {
+    with_document("<a><b><c/></b><d><c/><c/></d></a>", |doc| {
+        let result = evaluate_xpath(&doc, "count((//d, //b)/c)");
+        assert_eq!(Ok(Value::Number(3.0)), result);
+    });
+}
+
+#[test]
+fn sequences_compare_every_item() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "(1, 2, 3) = (3, 4)");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "(1, 2) > (5, 6)");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+    });
+}
+
+#[test]
+fn conditional_expressions_choose_a_branch() {
+    with_document("<a><b>5</b><b>50</b></a>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "concat(if (//b[1] > 10) then 'big' else 'small', '-', if (//b[2] > 10) then 'big' else 'small')",
+        );
+        assert_eq!(Ok(Value::String("small-big".to_owned())), result);
+
+        let result = evaluate_xpath(&doc, "count(//b[if (. > 10) then true() else false()])");
+        assert_eq!(Ok(Value::Number(1.0)), result);
+    });
+}
+
+#[test]
+fn for_expressions_compute_values_per_node() {
+    with_document(
+        "<order><line qty='2' price='3'/><line qty='4' price='5'/></order>",
+        |doc| {
+            let result = evaluate_xpath(&doc, "sum(for $l in //line return $l/@qty * $l/@price)");
+            assert_eq!(Ok(Value::Number(26.0)), result);
+        },
+    );
+}
+
+#[test]
+fn for_expressions_shadow_outer_variables() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_variable("x", 100.0);
+
+        let result = setup.evaluate(&doc, "sum((for $x in (1, 2) return $x, $x))");
+        assert_eq!(Value::Number(103.0), result);
+    });
+}
+
+#[test]
+fn let_expressions_name_repeated_subexpressions() {
+    with_document("<a><b>1</b><b>2</b><b>3</b></a>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "let $b := /a/b return concat(count($b), ':', sum($b), ':', $b[last()])",
+        );
+        assert_eq!(Ok(Value::String("3:6:3".to_owned())), result);
+    });
+}
+
+#[test]
+fn quantified_expressions_validate_every_node() {
+    with_document(
+        "<order><line qty='2'/><line qty='0'/><line qty='4'/></order>",
+        |doc| {
+            let result = evaluate_xpath(&doc, "every $l in //line satisfies $l/@qty > 0");
+            assert_eq!(Ok(Value::Boolean(false)), result);
+
+            let result = evaluate_xpath(&doc, "some $l in //line satisfies $l/@qty > 3");
+            assert_eq!(Ok(Value::Boolean(true)), result);
+
+            let result = evaluate_xpath(&doc, "every $l in //missing satisfies false()");
+            assert_eq!(Ok(Value::Boolean(true)), result);
+        },
+    );
+}
+
+#[test]
+fn ranges_generate_positions() {
+    with_document("<a><b>1</b><b>2</b><b>3</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "sum(for $i in 1 to count(//b) return $i * //b[$i])");
+        assert_eq!(Ok(Value::Number(14.0)), result);
+
+        let result = evaluate_xpath(&doc, "count(1 to 0)");
+        assert_eq!(Ok(Value::Number(0.0)), result);
+    });
+}
+
+#[test]
+fn node_comparisons_use_identity_and_document_order() {
+    with_document("<a><b/><c/></a>", |doc| {
+        let result = evaluate_xpath(&doc, "/a/b is //b");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "/a/b is /a/c");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+
+        let result = evaluate_xpath(&doc, "/a/b << /a/c");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "/a/b >> /a/c");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+
+        let result = evaluate_xpath(&doc, "count(/a/* [. >> /a/b])");
+        assert_eq!(Ok(Value::Number(1.0)), result);
+    });
+}
+
+#[test]
+fn value_comparisons_compare_single_items() {
+    with_document("<a><b>x</b><b>y</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "/a/b[1] eq 'x'");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "2 ge 10");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+
+        let result = evaluate_xpath(&doc, "/a/b eq 'x'");
+        assert!(result.is_err());
+
+        let result = evaluate_xpath(&doc, "1 eq '1'");
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn instance_of_tests_sequence_types() {
+    with_document("<a b='1'><c/>text</a>", |doc| {
+        let result = evaluate_xpath(&doc, "/a instance of element(a)");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "/a/node() instance of element()+");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+
+        let result = evaluate_xpath(&doc, "/a/@b instance of attribute(b)?");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "(1, 'x') instance of xs:anyAtomicType*");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "(/) instance of document-node()");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "/a/missing instance of empty-sequence()");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn treat_as_asserts_sequence_types() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "('x' treat as xs:string) = 'x'");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "/a treat as xs:string");
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn casts_convert_atomic_values() {
+    with_document("<a n=' 7 '/>", |doc| {
+        let result = evaluate_xpath(&doc, "/a/@n cast as xs:integer");
+        assert_eq!(Ok(Value::Number(7.0)), result);
+
+        let result = evaluate_xpath(&doc, "3.9 cast as xs:integer");
+        assert_eq!(Ok(Value::Number(3.0)), result);
+
+        let result = evaluate_xpath(&doc, "/a/@missing castable as xs:integer?");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "'seven' cast as xs:integer");
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn atomic_types_can_be_registered() {
+    struct Even;
+
+    impl atomic::Type for Even {
+        fn matches(&self, item: &Item<'_>) -> bool {
+            matches!(*item, Item::Number(n) if n % 2.0 == 0.0)
+        }
+
+        fn cast<'d>(&self, item: Item<'d>) -> Result<Item<'d>, atomic::Error> {
+            Ok(Item::Number(item.number() * 2.0))
+        }
+    }
+
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_atomic_type(("urn:example", "even"), Even);
+        setup.context.set_namespace("ex", "urn:example");
+
+        let result = setup.evaluate(&doc, "(3 cast as ex:even) instance of ex:even");
+        assert_eq!(Value::Boolean(true), result);
+    });
+}
+
+#[test]
+fn comments_are_ignored() {
+    with_document("<a><b/><b/></a>", |doc| {
+        let result = evaluate_xpath(&doc, "count(//b (: every b :)) (: done :)");
+        assert_eq!(Ok(Value::Number(2.0)), result);
+    });
+}
+
+#[test]
+fn name_tests_can_match_any_namespace() {
+    with_document(
+        "<a xmlns:v1='urn:v1' xmlns:v2='urn:v2'><v1:b/><v2:b/><b/><c/></a>",
+        |doc| {
+            let result = evaluate_xpath(&doc, "count(/a/*:b)");
+            assert_eq!(Ok(Value::Number(3.0)), result);
+        },
+    );
+}
+
+#[test]
+fn numbers_can_use_scientific_notation() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "1.5e3 + 2E-1");
+        assert_eq!(Ok(Value::Number(1500.2)), result);
+    });
+}
+
+#[test]
+fn maps_build_structured_results() {
+    with_document("<a><b id='x'>1</b><b id='y'>2</b></a>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "let $m := map { 'count': count(//b), 'ids': array { for $b in //b return string($b/@id) } }
+             return ($m?count, $m?ids?2, map:size($m), map:get($m, 'missing'))",
+        );
+        assert_eq!(
+            Ok(Value::Sequence(vec![
+                Item::Number(2.0),
+                Item::String("y".to_owned()),
+                Item::Number(2.0),
+            ])),
+            result
+        );
+    });
+}
+
+#[test]
+fn json_embedded_in_xml_can_be_queried() {
+    with_document(
+        r#"<order><payload>{"id": 7, "lines": [{"sku": "a"}, {"sku": "b"}], "gift": null}</payload></order>"#,
+        |doc| {
+            let result = evaluate_xpath(
+                &doc,
+                "let $o := parse-json(/order/payload) \
+                 return ($o?id, $o?lines?2?sku, array:size($o?lines), $o?gift)",
+            );
+            assert_eq!(
+                Ok(Value::Sequence(vec![
+                    Item::Number(7.0),
+                    Item::String("b".to_owned()),
+                    Item::Number(2.0),
+                ])),
+                result
+            );
+        },
+    );
+}
+
+#[test]
+fn json_can_be_queried_as_xml() {
+    let fragments = sxd_xpath::fragment::Fragments::new();
+    with_document(
+        "<payload>{\"tags\": [\"x\", \"y\"], \"n\": 1.50}</payload>",
+        |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_fragments(&fragments);
+            setup
+                .context
+                .set_namespace("fn", "http://www.w3.org/2005/xpath-functions");
+
+            let result = setup.evaluate(
+                &doc,
+                "string(json-to-xml(/payload)/fn:array[@key = 'tags']/fn:string[2])",
+            );
+            assert_eq!(Value::String("y".to_owned()), result);
+
+            let result = setup.evaluate(&doc, "string(json-to-xml(/payload)/fn:number/@key)");
+            assert_eq!(Value::String("n".to_owned()), result);
+
+            let result = setup.evaluate(&doc, "string(json-to-xml(/payload)/fn:number)");
+            assert_eq!(Value::String("1.50".to_owned()), result);
+        },
+    );
+}
+
+#[test]
+fn lookups_apply_to_each_map_of_a_sequence() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "(map { 'n': 1 }, map { 'n': 2 }, map { 'm': 3 })[?n > 1]?n",
+        );
+        assert_eq!(Ok(Value::Sequence(vec![Item::Number(2.0)])), result);
+
+        let result = evaluate_xpath(&doc, "[1, (2, 3)]?2");
+        assert_eq!(
+            Ok(Value::Sequence(vec![Item::Number(2.0), Item::Number(3.0)])),
+            result
+        );
+    });
+}
+
+#[test]
+fn invalid_map_and_array_accesses_are_errors() {
+    with_document("<a/>", |doc| {
+        assert!(evaluate_xpath(&doc, "map { 'a': 1, 'a': 2 }").is_err());
+        assert!(evaluate_xpath(&doc, "[1, 2]?3").is_err());
+        assert!(evaluate_xpath(&doc, "'text'?a").is_err());
+        assert!(evaluate_xpath(&doc, "array:get([1], 0)").is_err());
+    });
+}
+
+#[test]
+fn inline_functions_capture_bound_variables() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "let $add := function($n) { function($m) { $n + $m } }
+             return ($add(1)(2), 10 => ($add(5))())",
+        );
+        assert_eq!(
+            Ok(Value::Sequence(vec![Item::Number(3.0), Item::Number(15.0)])),
+            result
+        );
+    });
+}
+
+#[test]
+fn arrows_chain_function_calls() {
+    with_document("<a><b>x</b><b>y</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "//b => count() => string()");
+        assert_eq!(Ok(Value::String("2".to_owned())), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "let $twice := function($s) { concat($s, $s) } return 'ab' => $twice()",
+        );
+        assert_eq!(Ok(Value::String("abab".to_owned())), result);
+    });
+}
+
+#[test]
+fn invalid_dynamic_calls_are_errors() {
+    with_document("<a/>", |doc| {
+        assert!(evaluate_xpath(&doc, "let $f := 1 return $f(2)").is_err());
+        assert!(evaluate_xpath(&doc, "function($a) { $a }(1, 2)").is_err());
+    });
+}
+
+#[test]
+fn higher_order_functions_process_sequences() {
+    with_document("<a><b>3</b><b>1</b><b>4</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "for-each(//b, function($b) { $b * 10 })");
+        assert_eq!(
+            Ok(Value::Sequence(vec![
+                Item::Number(30.0),
+                Item::Number(10.0),
+                Item::Number(40.0),
+            ])),
+            result
+        );
+
+        let result = evaluate_xpath(&doc, "count(filter(//b, function($b) { $b > 2 }))");
+        assert_eq!(Ok(Value::Number(2.0)), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "fold-left(//b, '', function($acc, $b) { concat($acc, $b) })",
+        );
+        assert_eq!(Ok(Value::String("314".to_owned())), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "fold-right(//b, '', function($b, $acc) { concat($acc, $b) })",
+        );
+        assert_eq!(Ok(Value::String("413".to_owned())), result);
+    });
+}
+
+#[test]
+fn higher_order_functions_check_the_arity() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "for-each(1, function($a, $b) { $a })");
+        assert!(result.is_err());
+    });
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn regular_expressions_match_attribute_values() {
+    with_document(
+        r#"<a><b id="x-12"/><b id="y-3"/><b id="X-4"/></a>"#,
+        |doc| {
+            let result = evaluate_xpath(&doc, r"count(//b[matches(@id, '^x-\d+$')])");
+            assert_eq!(Ok(Value::Number(1.0)), result);
+
+            let result = evaluate_xpath(&doc, r"count(//b[matches(@id, '^x-\d+$', 'i')])");
+            assert_eq!(Ok(Value::Number(2.0)), result);
+        },
+    );
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn regular_expressions_replace_and_tokenize() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            r"replace('2024-01-31', '(\d+)-(\d+)-(\d+)', '$3/$2/$1')",
+        );
+        assert_eq!(Ok(Value::String("31/01/2024".to_owned())), result);
+
+        let result = evaluate_xpath(&doc, "tokenize('a, b,c', ',\\s*')");
+        assert_eq!(
+            Ok(Value::Sequence(vec![
+                Item::String("a".to_owned()),
+                Item::String("b".to_owned()),
+                Item::String("c".to_owned()),
+            ])),
+            result
+        );
+
+        let result = evaluate_xpath(&doc, "count(tokenize('  one two  '))");
+        assert_eq!(Ok(Value::Number(2.0)), result);
+    });
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn invalid_regular_expressions_are_errors() {
+    with_document("<a/>", |doc| {
+        assert!(evaluate_xpath(&doc, "matches('a', '(')").is_err());
+        assert!(evaluate_xpath(&doc, "matches('a', 'a', 'g')").is_err());
+        assert!(evaluate_xpath(&doc, "tokenize('abc', 'x*')").is_err());
+        assert!(evaluate_xpath(&doc, "replace('abc', 'b', '$')").is_err());
+    });
+}
+
+#[test]
+fn string_functions_from_xpath_2() {
+    with_document("<a><b>Report.PDF</b><b>notes.txt</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "count(//b[ends-with(lower-case(.), '.pdf')])");
+        assert_eq!(Ok(Value::Number(1.0)), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "string-join(for $b in //b return upper-case($b), '; ')",
+        );
+        assert_eq!(
+            Ok(Value::String("REPORT.PDF; NOTES.TXT".to_owned())),
+            result
+        );
+    });
+}
+
+#[test]
+fn sequence_functions_from_xpath_2() {
+    with_document("<a><b k='x'/><b k='y'/><b k='x'/><c/></a>", |doc| {
+        let result = evaluate_xpath(&doc, "string-join(distinct-values(//b/@k), ',')");
+        assert_eq!(Ok(Value::String("x,y".to_owned())), result);
+
+        let result = evaluate_xpath(&doc, "index-of(//b/@k, 'x')");
+        assert_eq!(
+            Ok(Value::Sequence(vec![Item::Number(1.0), Item::Number(3.0)])),
+            result
+        );
+
+        let result = evaluate_xpath(&doc, "count(/a/*[empty(@k)])");
+        assert_eq!(Ok(Value::Number(1.0)), result);
+
+        let result = evaluate_xpath(&doc, "exists(//d)");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+    });
+}
+
+#[test]
+fn numeric_aggregates_use_node_values_as_numbers() {
+    with_document("<a><b>9</b><b>10</b><b>-2</b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "max(//b)");
+        assert_eq!(Ok(Value::Number(10.0)), result);
+
+        let result = evaluate_xpath(&doc, "min(//b)");
+        assert_eq!(Ok(Value::Number(-2.0)), result);
+
+        let result = evaluate_xpath(&doc, "round-half-to-even(avg(//b), 1)");
+        assert_eq!(Ok(Value::Number(5.7)), result);
+
+        let result = evaluate_xpath(&doc, "abs(//b[3])");
+        assert_eq!(Ok(Value::Number(2.0)), result);
+
+        let result = evaluate_xpath(&doc, "empty(max(//c))");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn deep_equal_compares_subtrees() {
+    with_document(
+        "<r><a x='1' y='2'><b>t</b><!-- note --></a><a y='2' x='1'><b>t</b></a><a x='1'><b>t</b></a></r>",
+        |doc| {
+            let result = evaluate_xpath(&doc, "deep-equal(/r/a[1], /r/a[2])");
+            assert_eq!(Ok(Value::Boolean(true)), result);
+
+            let result = evaluate_xpath(&doc, "deep-equal(/r/a[1], /r/a[3])");
+            assert_eq!(Ok(Value::Boolean(false)), result);
+
+            let result = evaluate_xpath(&doc, "deep-equal((/r/a[1], 1), (/r/a[2], 1))");
+            assert_eq!(Ok(Value::Boolean(true)), result);
+        },
+    );
+}
+
+#[test]
+fn deep_equal_cannot_compare_functions() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "let $f := function() { 1 } return deep-equal($f, $f)");
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn dates_are_compared_as_points_in_time() {
+    with_document(
+        "<log><e at='2024-03-01T09:00:00+01:00'/><e at='2024-03-01T09:30:00Z'/></log>",
+        |doc| {
+            let result = evaluate_xpath(
+                &doc,
+                "count(//e[xs:dateTime(@at) > xs:dateTime('2024-03-01T09:00:00Z')])",
+            );
+            assert_eq!(Ok(Value::Number(1.0)), result);
+
+            let result = evaluate_xpath(&doc, "xs:date('2024-03-01') = '2024-03-01'");
+            assert_eq!(Ok(Value::Boolean(true)), result);
+
+            let result = evaluate_xpath(&doc, "xs:time('10:00:00') lt xs:time('09:00:00')");
+            assert_eq!(Ok(Value::Boolean(false)), result);
+        },
+    );
+}
+
+#[test]
+fn durations_can_be_added_to_dates() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "string(xs:date('2024-01-31') + xs:yearMonthDuration('P1M'))",
+        );
+        assert_eq!(Ok(Value::String("2024-02-29".to_owned())), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "string(xs:dateTime('2024-03-01T00:00:00Z') - xs:dateTime('2024-02-28T12:00:00Z'))",
+        );
+        assert_eq!(Ok(Value::String("P1DT12H".to_owned())), result);
+
+        let result = evaluate_xpath(&doc, "string(-xs:dayTimeDuration('PT90M') * 2)");
+        assert_eq!(Ok(Value::String("-PT3H".to_owned())), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "xs:dayTimeDuration('P1D') div xs:dayTimeDuration('PT6H')",
+        );
+        assert_eq!(Ok(Value::Number(4.0)), result);
+    });
+}
+
+#[test]
+fn temporal_types_are_checked() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "xs:date('2024-01-31') instance of xs:date");
+        assert_eq!(Ok(Value::Boolean(true)), result);
+
+        let result = evaluate_xpath(&doc, "'2024-02-30' castable as xs:date");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+
+        assert!(evaluate_xpath(&doc, "xs:date('2024-01-31') + 1").is_err());
+        assert!(evaluate_xpath(&doc, "xs:date('yesterday')").is_err());
+    });
+}
+
+#[cfg(feature = "unicode-normalization")]
+#[test]
+fn normalized_text_compares_equal() {
+    with_document("<a><n>Cafe\u{301}</n><n>Caf\u{e9}</n></a>", |doc| {
+        let result = evaluate_xpath(&doc, "/a/n[1] = /a/n[2]");
+        assert_eq!(Ok(Value::Boolean(false)), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "normalize-unicode(/a/n[1]) = normalize-unicode(/a/n[2])",
+        );
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn strings_are_compared_with_a_collation() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "compare('abc', 'abd')");
+        assert_eq!(Ok(Value::Number(-1.0)), result);
+
+        let result = evaluate_xpath(
+            &doc,
+            "compare('abc', 'abc', 'http://www.w3.org/2005/xpath-functions/collation/codepoint')",
+        );
+        assert_eq!(Ok(Value::Number(0.0)), result);
+
+        assert!(evaluate_xpath(&doc, "compare('a', 'b', 'urn:unknown')").is_err());
+    });
+}
+
+#[test]
+fn case_insensitive_collation_matches_without_changing_documents() {
+    with_document("<a><b>Apple</b><b>banana</b></a>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_default_collation(
+            "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive",
+        );
+
+        let result = setup.evaluate(&doc, "count(//b[. = 'APPLE'])");
+        assert_eq!(Value::Number(1.0), result);
+
+        let result = setup.evaluate(&doc, "count(//b[contains(., 'NAN')])");
+        assert_eq!(Value::Number(1.0), result);
+
+        let result = setup.evaluate(&doc, "'apple' lt 'Banana'");
+        assert_eq!(Value::Boolean(true), result);
+    });
+}
+
+#[cfg(feature = "exslt")]
+#[test]
+fn exslt_date_functions_are_available_with_their_prefix() {
+    with_document("<log><e at='2024-03-09T18:30:00Z'/></log>", |doc| {
+        let mut setup = Setup::new();
+        sxd_xpath::exslt::register_exslt_functions(&mut setup.context);
+
+        let result = setup.evaluate(&doc, "date:month-name(//e/@at)");
+        assert_eq!(Value::String("March".to_owned()), result);
+
+        let result = setup.evaluate(&doc, "date:add(date:date(//e/@at), 'P1D')");
+        assert_eq!(Value::String("2024-03-10Z".to_owned()), result);
+
+        let result = setup.evaluate(&doc, "string-length(date:date-time()) > 0");
+        assert_eq!(Value::Boolean(true), result);
+    });
+}
+
+#[cfg(feature = "exslt")]
+#[test]
+fn exslt_math_functions_find_the_extremes_of_nodes() {
+    with_document("<scores><s>12</s><s>40</s><s>7</s></scores>", |doc| {
+        let mut setup = Setup::new();
+        sxd_xpath::exslt::register_math_functions(&mut setup.context);
+
+        let result = setup.evaluate(&doc, "math:max(//s) - math:min(//s)");
+        assert_eq!(Value::Number(33.0), result);
+
+        let result = setup.evaluate(&doc, "count(math:highest(//s)/preceding-sibling::s)");
+        assert_eq!(Value::Number(1.0), result);
+
+        let result = setup.evaluate(&doc, "math:power(2, math:abs(-3))");
+        assert_eq!(Value::Number(8.0), result);
+    });
+}
+
+#[cfg(feature = "exslt")]
+#[test]
+fn exslt_strings_functions_create_tokens() {
+    let fragments = sxd_xpath::fragment::Fragments::new();
+    with_document("<tags>rust, xml,xpath</tags>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        sxd_xpath::exslt::register_strings_functions(&mut setup.context);
+
+        let result = setup.evaluate(&doc, "count(str:tokenize(/tags, ', '))");
+        assert_eq!(Value::Number(3.0), result);
+
+        let result = setup.evaluate(&doc, "string(str:split(/tags, ',')[2])");
+        assert_eq!(Value::String(" xml".to_owned()), result);
+
+        let result = setup.evaluate(&doc, "str:align(/tags, str:padding(4, '.'), 'right')");
+        assert_eq!(Value::String("rust".to_owned()), result);
+    });
+}
+
+#[cfg(all(feature = "exslt", feature = "regex"))]
+#[test]
+fn exslt_regexp_functions_are_available_with_their_prefix() {
+    let fragments = sxd_xpath::fragment::Fragments::new();
+    with_document("<phone>+44 20 7946 0018</phone>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        sxd_xpath::exslt::register_exslt_functions(&mut setup.context);
+
+        let result = setup.evaluate(&doc, r"regexp:test(/phone, '^\+\d+')");
+        assert_eq!(Value::Boolean(true), result);
+
+        let result = setup.evaluate(&doc, r"string(regexp:match(/phone, '^\+(\d+)')[2])");
+        assert_eq!(Value::String("44".to_owned()), result);
+
+        let result = setup.evaluate(&doc, "regexp:replace(/phone, ' ', 'g', '')");
+        assert_eq!(Value::String("+442079460018".to_owned()), result);
+    });
+}
+
+#[cfg(feature = "exslt")]
+#[test]
+fn exslt_node_set_converts_values_to_nodes() {
+    let fragments = sxd_xpath::fragment::Fragments::new();
+    with_document("<a>x</a>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        sxd_xpath::exslt::register_common_functions(&mut setup.context);
+
+        let result = setup.evaluate(&doc, "exsl:node-set('hello')/self::text()");
+        assert_eq!("hello", result.string());
+
+        let result = setup.evaluate(&doc, "count(exsl:node-set(/a))");
+        assert_eq!(Value::Number(1.0), result);
+    });
+}
+
+#[cfg(feature = "exslt")]
+#[test]
+fn exslt_dynamic_evaluate_follows_embedded_expressions() {
+    with_document(
+        "<doc><link to='//section[@id = \"b\"]/title'/><section id='a'><title>A</title></section><section id='b'><title>B</title></section></doc>",
+        |doc| {
+            let mut setup = Setup::new();
+            sxd_xpath::exslt::register_dynamic_functions(&mut setup.context);
+
+            let result = setup.evaluate(&doc, "string(dyn:evaluate(/doc/link/@to))");
+            assert_eq!(Value::String("B".to_owned()), result);
+        },
+    );
+}
+
+#[cfg(feature = "exslt")]
+#[test]
+fn exslt_sets_functions_combine_nodesets() {
+    with_document(
+        "<menu><i veg='y'>soup</i><i>fish</i><i veg='y'>salad</i><i>soup</i></menu>",
+        |doc| {
+            let mut setup = Setup::new();
+            sxd_xpath::exslt::register_sets_functions(&mut setup.context);
+
+            let result = setup.evaluate(&doc, "count(set:difference(//i, //i[@veg]))");
+            assert_eq!(Value::Number(2.0), result);
+
+            let result = setup.evaluate(&doc, "count(set:distinct(//i))");
+            assert_eq!(Value::Number(3.0), result);
+
+            let result = setup.evaluate(&doc, "string(set:trailing(//i, //i[2]))");
+            assert_eq!(Value::String("salad".to_owned()), result);
+
+            let result = setup.evaluate(&doc, "set:has-same-node(//i[1], //i[@veg])");
+            assert_eq!(Value::Boolean(true), result);
+        },
+    );
+}
+
+#[test]
+fn uris_are_built_from_document_content() {
+    with_document("<q term='fish &amp; chips'/>", |doc| {
+        let result = evaluate_xpath(
+            &doc,
+            "concat('http://example.com/search?q=', encode-for-uri(/q/@term))",
+        );
+        assert_eq!(
+            Ok(Value::String(
+                "http://example.com/search?q=fish%20%26%20chips".to_owned()
+            )),
+            result
+        );
+
+        let result = evaluate_xpath(&doc, "iri-to-uri('http://example.com/a b')");
+        assert_eq!(
+            Ok(Value::String("http://example.com/a%20b".to_owned())),
+            result
+        );
+    });
+}
+
+#[test]
+fn links_are_resolved_against_xml_base() {
+    with_document(
+        "<site xml:base='http://example.com/docs/'><a href='intro.html'/></site>",
+        |doc| {
+            let result = evaluate_xpath(&doc, "resolve-uri(//a/@href, base-uri(//a))");
+            assert_eq!(
+                Ok(Value::String(
+                    "http://example.com/docs/intro.html".to_owned()
+                )),
+                result
+            );
+        },
+    );
+}
+
+#[test]
+fn dates_are_formatted_with_pictures() {
+    with_document("<e on='2024-03-09'/>", |doc| {
+        let result = evaluate_xpath(&doc, "format-date(/e/@on, '[FNn], [D1o] [MNn] [Y]')");
+        assert_eq!(
+            Ok(Value::String("Saturday, 9th March 2024".to_owned())),
+            result
+        );
+
+        let result = evaluate_xpath(
+            &doc,
+            "format-dateTime(xs:dateTime('2024-03-09T18:30:00Z'), '[h].[m01] [PN] [Z]')",
+        );
+        assert_eq!(Ok(Value::String("6.30 PM +00:00".to_owned())), result);
+
+        assert!(evaluate_xpath(&doc, "format-time(xs:date('2024-03-09'), '[H]')").is_err());
+        assert!(evaluate_xpath(&doc, "format-date(xs:date('2024-03-09'), '[H]')").is_err());
+    });
+}
+
 #[test]
 fn variables_with_qualified_names() {
     with_document("<a/>", |doc| {
@@ -75,6 +937,439 @@ fn functions_with_qualified_names() {
     });
 }
 
+#[test]
+fn functions_can_return_new_nodes() {
+    let fragments = sxd_xpath::fragment::Fragments::new();
+    with_document("<list><item>a</item><item>b</item></list>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_fragments(&fragments);
+        setup.context.set_function("wrap", WrapFunction);
+
+        let result = setup.evaluate(&doc, "count(wrap(//item)/item)");
+        assert_eq!(Value::Number(2.0), result);
+
+        let result = setup.evaluate(&doc, "name(wrap(//item))");
+        assert_eq!(Value::String("wrapper".to_owned()), result);
+    });
+}
+
+#[test]
+fn new_nodes_need_storage_for_fragments() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_function("wrap", WrapFunction);
+
+        let xpath = setup
+            .factory
+            .build("wrap(/a)")
+            .expect("Unable to build XPath");
+        let result = xpath.evaluate(&setup.context, doc.root());
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn document_follows_links_between_files() {
+    let fragments = sxd_xpath::fragment::Fragments::new();
+    with_document(
+        "<map><topicref href='a.xml'/><topicref href='b.xml'/></map>",
+        |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_fragments(&fragments);
+            setup.context.set_base_uri("file:///book/map.ditamap");
+            setup.context.set_document_resolver(
+                |uri: &str| -> Result<_, sxd_xpath::resolver::ResolveError> {
+                    let title = uri.trim_start_matches("file:///book/");
+                    Ok(parser::parse(&format!(
+                        "<topic><title>{}</title></topic>",
+                        title
+                    ))?)
+                },
+            );
+
+            let result = setup.evaluate(&doc, "count(document(//topicref/@href)/topic)");
+            assert_eq!(Value::Number(2.0), result);
+
+            let result = setup.evaluate(&doc, "document('a.xml') is document(//topicref[1]/@href)");
+            assert_eq!(Value::Boolean(true), result);
+        },
+    );
+}
+
+#[test]
+fn document_pools_are_shared_between_contexts() {
+    let pool = sxd_xpath::pool::DocumentPool::new();
+    let loads = Rc::new(Cell::new(0));
+    for &(order, rate) in &[
+        ("<order currency='EUR'/>", 1.0),
+        ("<order currency='USD'/>", 2.0),
+    ] {
+        with_document(order, |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_document_pool(&pool);
+            setup.context.set_document_resolver({
+                let loads = Rc::clone(&loads);
+                move |_: &str| -> Result<_, sxd_xpath::resolver::ResolveError> {
+                    loads.set(loads.get() + 1);
+                    Ok(parser::parse(
+                        "<rates><rate of='EUR'>1</rate><rate of='USD'>2</rate></rates>",
+                    )?)
+                }
+            });
+            let currency = doc.root().children()[0]
+                .element()
+                .and_then(|e| e.attribute_value("currency"));
+            setup
+                .context
+                .set_variable("currency", currency.unwrap_or_default());
+
+            let result =
+                setup.evaluate(&doc, "number(document('urn:rates')//rate[@of = $currency])");
+            assert_eq!(Value::Number(rate), result);
+        });
+    }
+    assert_eq!(1, loads.get());
+    assert_eq!(1, pool.len());
+}
+
+#[test]
+fn keys_join_nodes_by_value() {
+    with_document(
+        "<shop>
+           <customer id='c1' name='Ada'/>
+           <customer id='c2' name='Grace'/>
+           <order id='o1' customer='c2'/>
+           <order id='o2' customer='c1'/>
+           <order id='o3' customer='c2'/>
+         </shop>",
+        |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_key(
+                "orders",
+                &setup.factory.build("order").expect("Invalid pattern"),
+                &setup.factory.build("@customer").expect("Invalid XPath"),
+            );
+
+            let result = setup.evaluate(
+                &doc,
+                "count(key('orders', //customer[@name = 'Grace']/@id))",
+            );
+            assert_eq!(Value::Number(2.0), result);
+
+            let result = setup.evaluate(
+                &doc,
+                "//customer[count(key('orders', @id)) = 1]/@name = 'Ada'",
+            );
+            assert_eq!(Value::Boolean(true), result);
+        },
+    );
+}
+
+#[test]
+fn attached_indexes_answer_equality_predicates() {
+    with_document(
+        "<shop><item id='a'><name>Apple</name></item><item id='b'><name>Pear</name></item></shop>",
+        |doc| {
+            let mut setup = Setup::new();
+            let index = sxd_xpath::index::Index::build(
+                &setup.context,
+                doc.root(),
+                &setup.factory.build("item").expect("Invalid pattern"),
+                &setup.factory.build("@id").expect("Invalid XPath"),
+            )
+            .expect("Unable to build index");
+
+            // Nodes added afterwards are only found without the index
+            let shop = doc.root().children()[0].element().expect("No element");
+            let late = doc.create_element("item");
+            late.set_attribute_value("id", "b");
+            shop.append_child(late);
+
+            let result = setup.evaluate(&doc, "count(//item[@id = 'b'])");
+            assert_eq!(Value::Number(2.0), result);
+
+            setup.context.add_index(&index);
+            setup.context.set_variable("id", "b");
+
+            let result = setup.evaluate(&doc, "count(//item[@id = 'b'])");
+            assert_eq!(Value::Number(1.0), result);
+            let result = setup.evaluate(&doc, "string(//item[$id = @id]/name)");
+            assert_eq!(Value::String("Pear".to_owned()), result);
+            let result = setup.evaluate(&doc, "count(/shop/item[@id = $id])");
+            assert_eq!(Value::Number(2.0), result);
+        },
+    );
+}
+
+#[test]
+fn id_follows_references_between_elements() {
+    with_document(
+        "<doc><section xml:id='intro'>Hello</section><link to='intro'/><fig label='f1'/></doc>",
+        |doc| {
+            let mut setup = Setup::new();
+            let result = setup.evaluate(&doc, "string(id(//link/@to))");
+            assert_eq!(Value::String("Hello".to_owned()), result);
+
+            let result = setup.evaluate(&doc, "count(id('f1'))");
+            assert_eq!(Value::Number(0.0), result);
+            setup.context.add_id_attribute("label");
+            let result = setup.evaluate(&doc, "count(id('f1 intro'))");
+            assert_eq!(Value::Number(2.0), result);
+        },
+    );
+}
+
+#[test]
+fn current_refers_to_the_node_the_xpath_is_evaluated_for() {
+    with_document(
+        "<doc><item id='1' ref='2'>a</item><item id='2' ref='1'>b</item></doc>",
+        |doc| {
+            let setup = Setup::new();
+            let first = doc.root().children()[0].element().unwrap().children()[0];
+            let xpath = setup
+                .factory
+                .build("string(//item[@ref = current()/@id])")
+                .expect("Unable to build XPath");
+            let result = xpath
+                .evaluate(&setup.context, first)
+                .expect("Unable to evaluate XPath");
+
+            assert_eq!(Value::String("b".to_owned()), result);
+        },
+    );
+}
+
+#[test]
+fn results_can_be_returned_a_page_at_a_time() {
+    with_document(
+        "<r><g><i>1</i><i>2</i></g><g><i>3</i><i>4</i></g><g><i>5</i></g></r>",
+        |doc| {
+            let setup = Setup::new();
+            let xpath = setup.factory.build("//i").expect("Unable to build XPath");
+            let page = |skip, max_results| {
+                let mut options = EvaluationOptions::new();
+                options.set_skip(skip);
+                options.set_max_results(max_results);
+                let value = xpath
+                    .evaluate_with_options(&setup.context, doc.root(), &options)
+                    .expect("Unable to evaluate XPath");
+                value.iter().map(|n| n.string_value()).collect::<Vec<_>>()
+            };
+
+            assert_eq!(vec!["1", "2"], page(0, Some(2)));
+            assert_eq!(vec!["3", "4"], page(2, Some(2)));
+            assert_eq!(vec!["5"], page(4, Some(2)));
+            assert_eq!(Vec::<String>::new(), page(6, Some(2)));
+            assert_eq!(vec!["4", "5"], page(3, None));
+        },
+    );
+}
+
+#[test]
+fn limited_results_skip_the_remaining_nodes() {
+    with_document(
+        "<r><g><i/><i/></g><g><i/><i/></g><g><i/><i/></g></r>",
+        |doc| {
+            let calls = Rc::new(Cell::new(0));
+            let mut setup = Setup::new();
+            setup
+                .context
+                .set_function("counted", CountingFunction(calls.clone()));
+
+            let xpath = setup
+                .factory
+                .build("/r/g/i[counted()]")
+                .expect("Unable to build XPath");
+            let mut options = EvaluationOptions::new();
+            options.set_max_results(Some(2));
+            let value = xpath
+                .evaluate_with_options(&setup.context, doc.root(), &options)
+                .expect("Unable to evaluate XPath");
+
+            assert_eq!(2, value.iter().count());
+            assert_eq!(2, calls.get());
+        },
+    );
+}
+
+#[test]
+fn limited_results_stop_every_step() {
+    with_document(
+        "<r><s><g><i/></g></s><s><g><i/></g></s><s><g><i/></g></s></r>",
+        |doc| {
+            let calls = Rc::new(Cell::new(0));
+            let mut setup = Setup::new();
+            setup
+                .context
+                .set_function("counted", CountingFunction(calls.clone()));
+
+            let xpath = setup
+                .factory
+                .build("//g[counted()]/i")
+                .expect("Unable to build XPath");
+            let mut options = EvaluationOptions::new();
+            options.set_max_results(Some(1));
+            let value = xpath
+                .evaluate_with_options(&setup.context, doc.root(), &options)
+                .expect("Unable to evaluate XPath");
+
+            assert_eq!(1, value.iter().count());
+            assert_eq!(1, calls.get());
+        },
+    );
+}
+
+#[test]
+fn limited_results_are_the_first_nodes_of_the_whole_result() {
+    with_document(
+        "<r a='1'><g b='2'><i>1<j/></i><g><i>2</i><i>3</i></g><i>4</i></g><i>5</i><g><i>6</i></g></r>",
+        |doc| {
+            let setup = Setup::new();
+            let xpaths = [
+                "//i",
+                "//g//i",
+                "//g/i",
+                "//i/..",
+                "//j/ancestor::*",
+                "//i[2]",
+                "//i[last()]/preceding::i",
+                "//i/following-sibling::*",
+                "//i/following::i",
+                "//@*",
+                "//g/descendant-or-self::node()",
+                "/r/g/g/i | /r/i",
+                "(//g)[2]//i",
+            ];
+
+            for xpath in &xpaths {
+                let compiled = setup.factory.build(xpath).expect("Unable to build XPath");
+                let all: Vec<_> = setup.evaluate(&doc, xpath).iter().collect();
+                for max_results in 0..=all.len() {
+                    let mut options = EvaluationOptions::new();
+                    options.set_max_results(Some(max_results));
+                    let page: Vec<_> = compiled
+                        .evaluate_with_options(&setup.context, doc.root(), &options)
+                        .expect("Unable to evaluate XPath")
+                        .iter()
+                        .collect();
+                    assert_eq!(&all[..max_results], &page[..], "{} {}", xpath, max_results);
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn the_first_node_is_found_without_evaluating_the_rest() {
+    with_document(
+        "<r><g><i>1</i><i>2</i></g><g><i>3</i></g><h>4</h></r>",
+        |doc| {
+            let calls = Rc::new(Cell::new(0));
+            let mut setup = Setup::new();
+            setup
+                .context
+                .set_function("counted", CountingFunction(calls.clone()));
+
+            let first = |xpath: &str| {
+                let xpath = setup.factory.build(xpath).expect("Unable to build XPath");
+                xpath
+                    .evaluate_first(&setup.context, doc.root())
+                    .expect("Unable to evaluate XPath")
+                    .map(|n| n.string_value())
+            };
+
+            assert_eq!(Some("1".to_owned()), first("/r/g/i[counted()]"));
+            assert_eq!(2, calls.get());
+
+            assert_eq!(Some("1".to_owned()), first("/r/h | //i[counted()]"));
+            assert_eq!(Some("3".to_owned()), first("/r/g/i[. > 2]"));
+            assert_eq!(None, first("/r/missing"));
+            assert_eq!(None, first("count(//i)"));
+        },
+    );
+}
+
+#[test]
+fn the_first_node_stops_every_step() {
+    with_document(
+        "<r><s><g><i>1</i></g></s><s><g><i>2</i></g></s><s><g><i>3</i></g></s></r>",
+        |doc| {
+            let calls = Rc::new(Cell::new(0));
+            let mut setup = Setup::new();
+            setup
+                .context
+                .set_function("counted", CountingFunction(calls.clone()));
+
+            let first = |xpath: &str| {
+                let xpath = setup.factory.build(xpath).expect("Unable to build XPath");
+                xpath
+                    .evaluate_first(&setup.context, doc.root())
+                    .expect("Unable to evaluate XPath")
+                    .map(|n| n.string_value())
+            };
+
+            assert_eq!(Some("1".to_owned()), first("//g[counted()]/i"));
+            assert_eq!(1, calls.get());
+
+            assert_eq!(Some("1".to_owned()), first("//s[3]/g/i | //g[counted()]/i"));
+            assert_eq!(2, calls.get());
+        },
+    );
+}
+
+#[test]
+fn boolean_values_stop_at_the_first_node() {
+    with_document(
+        "<log><g><error/></g><g><error fatal='yes'/></g><g><error fatal='no'/></g></log>",
+        |doc| {
+            let calls = Rc::new(Cell::new(0));
+            let mut setup = Setup::new();
+            setup
+                .context
+                .set_function("counted", CountingFunction(calls.clone()));
+
+            let boolean = |xpath: &str| {
+                let xpath = setup.factory.build(xpath).expect("Unable to build XPath");
+                xpath
+                    .evaluate_boolean(&setup.context, doc.root())
+                    .expect("Unable to evaluate XPath")
+            };
+
+            assert!(boolean("//error[@fatal][counted()]"));
+            assert_eq!(1, calls.get());
+
+            assert!(boolean("/log or //error[counted()]"));
+            assert!(!boolean("//warning and //error[counted()]"));
+            assert!(boolean("//warning | //error[counted()]"));
+            assert_eq!(2, calls.get());
+
+            assert!(!boolean("//error[@fatal = 'maybe']"));
+            assert!(!boolean("count(//error) = 0"));
+            assert!(boolean("string(/log/g[2]/error/@fatal)"));
+        },
+    );
+}
+
+#[test]
+fn predicates_look_at_each_node_once() {
+    let items = 200;
+    let tree = FlatTree::new(items);
+    let setup = Setup::new();
+
+    let xpath = setup
+        .factory
+        .build("count(/root/item[@a and @b])")
+        .expect("Unable to build XPath");
+    let value = xpath
+        .evaluate(&setup.context, ExternalNode::new(&tree, 0))
+        .expect("Unable to evaluate XPath");
+
+    assert_eq!(Value::Number(items as f64), value);
+    // Numbering the whole document for each item would look at the
+    // children of every node again for each of them
+    assert!(tree.children_calls.get() < 4 * items);
+}
+
 #[test]
 fn nodesets_are_unique() {
     with_document("<a/>", |doc| {
@@ -111,6 +1406,121 @@ impl<'d> Setup<'d> {
     }
 }
 
+/// `<root><item a="" b=""/>...</root>`, counting how often the
+/// children of a node are asked for. The root is 0 and the `root`
+/// element is 1; item `i` is `2 + 3 * i`, followed by its attributes.
+struct FlatTree {
+    items: usize,
+    children_calls: Cell<usize>,
+}
+
+impl FlatTree {
+    fn new(items: usize) -> Self {
+        FlatTree {
+            items,
+            children_calls: Cell::new(0),
+        }
+    }
+}
+
+impl ExternalTree for FlatTree {
+    fn root(&self) -> usize {
+        0
+    }
+
+    fn kind(&self, id: usize) -> NodeKind {
+        match (id, (id + 1) % 3) {
+            (0, _) => NodeKind::Root,
+            (1, _) | (_, 0) => NodeKind::Element,
+            _ => NodeKind::Attribute,
+        }
+    }
+
+    fn expanded_name(&self, id: usize) -> Option<QName<'_>> {
+        let name = match id {
+            0 => return None,
+            1 => "root",
+            _ => ["item", "a", "b"][(id - 2) % 3],
+        };
+        Some(QName::new(name))
+    }
+
+    fn parent(&self, id: usize) -> Option<usize> {
+        match (id, (id + 1) % 3) {
+            (0, _) => None,
+            (1, _) => Some(0),
+            (_, 0) => Some(1),
+            (_, attribute) => Some(id - attribute),
+        }
+    }
+
+    fn children(&self, id: usize) -> Vec<usize> {
+        self.children_calls.set(self.children_calls.get() + 1);
+        match id {
+            0 => vec![1],
+            1 => (0..self.items).map(|i| 2 + 3 * i).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn attributes(&self, id: usize) -> Vec<usize> {
+        match self.kind(id) {
+            NodeKind::Element if id > 1 => vec![id + 1, id + 2],
+            _ => Vec::new(),
+        }
+    }
+
+    fn value(&self, id: usize) -> Option<&str> {
+        match self.kind(id) {
+            NodeKind::Attribute => Some(""),
+            _ => None,
+        }
+    }
+}
+
+/// Copies the elements of its argument into a new `wrapper` element.
+struct WrapFunction;
+
+impl function::Function for WrapFunction {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, function::Error> {
+        let mut args = function::Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+
+        let fragment = context.new_fragment().ok_or(function::Error::NoFragments)?;
+        let doc = fragment.document();
+        let wrapper = doc.create_element("wrapper");
+        for node in nodes.document_order() {
+            if let Some(name) = node.element().map(|e| e.name()) {
+                let copy = doc.create_element(name);
+                copy.append_child(doc.create_text(&node.string_value()));
+                wrapper.append_child(copy);
+            }
+        }
+        fragment.append_child(wrapper);
+
+        Ok(Value::Nodeset(nodeset![wrapper]))
+    }
+}
+
+/// Counts how many times it is called, and is always true.
+struct CountingFunction(Rc<Cell<usize>>);
+
+impl function::Function for CountingFunction {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        _args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, function::Error> {
+        self.0.set(self.0.get() + 1);
+        Ok(Value::Boolean(true))
+    }
+}
+
 struct ConstantValueFunction(f64);
 
 impl function::Function for ConstantValueFunction {
@@ -122,3 +1532,18 @@ impl function::Function for ConstantValueFunction {
         Ok(Value::Number(self.0))
     }
 }
+
+#[test]
+fn unterminated_comments_are_reported_where_they_start() {
+    let unterminated = sxd_xpath::parser::Error::Tokenizer {
+        source: sxd_xpath::tokenizer::Error::UnterminatedComment,
+    };
+
+    for xpath in &["1 (: x", "(: x", "/ (: x", "a (: x (: y :)"] {
+        let error = Factory::new()
+            .build(xpath)
+            .expect_err("XPath should have an unterminated comment");
+        assert_eq!(&unterminated, error.kind(), "{}", xpath);
+        assert_eq!(xpath.find("(:"), Some(error.span().offset), "{}", xpath);
+    }
+}